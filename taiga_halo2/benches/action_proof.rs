@@ -10,7 +10,7 @@ use rand::Rng;
 use taiga_halo2::{
     action::ActionInfo,
     constant::{
-        ACTION_CIRCUIT_PARAMS_SIZE, ACTION_PROVING_KEY, ACTION_VERIFYING_KEY, SETUP_PARAMS_MAP,
+        get_params, ACTION_CIRCUIT_PARAMS_SIZE, ACTION_PROVING_KEY, ACTION_VERIFYING_KEY,
         TAIGA_COMMITMENT_TREE_DEPTH,
     },
     merkle_tree::MerklePath,
@@ -71,7 +71,7 @@ fn bench_action_proof(name: &str, c: &mut Criterion) {
         ActionInfo::new(input_note, input_merkle_path, anchor, output_note, rseed)
     };
     let (action, action_circuit) = action_info.build();
-    let params = SETUP_PARAMS_MAP.get(&ACTION_CIRCUIT_PARAMS_SIZE).unwrap();
+    let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
 
     // Prover bench
     let prover_name = name.to_string() + "-prover";
@@ -82,7 +82,7 @@ fn bench_action_proof(name: &str, c: &mut Criterion) {
                 params,
                 &ACTION_PROVING_KEY,
                 &[action_circuit.clone()],
-                &[&[&action.to_instance()]],
+                &[&[&action.to_vec()]],
                 &mut rng,
                 &mut transcript,
             )
@@ -99,7 +99,7 @@ fn bench_action_proof(name: &str, c: &mut Criterion) {
             params,
             &ACTION_PROVING_KEY,
             &[action_circuit],
-            &[&[&action.to_instance()]],
+            &[&[&action.to_vec()]],
             &mut rng,
             &mut transcript,
         )
@@ -116,7 +116,7 @@ fn bench_action_proof(name: &str, c: &mut Criterion) {
                 params,
                 &ACTION_VERIFYING_KEY,
                 strategy,
-                &[&[&action.to_instance()]],
+                &[&[&action.to_vec()]],
                 &mut transcript
             )
             .is_ok());
@@ -14,7 +14,7 @@ use taiga_halo2::{
             ValidityPredicateVerifyingInfo,
         },
     },
-    constant::{NUM_NOTE, SETUP_PARAMS_MAP},
+    constant::{NUM_NOTE},
     note::{Note, RandomSeed},
     proof::Proof,
     vp_circuit_impl,
@@ -23,7 +23,7 @@ use taiga_halo2::{
             ValidityPredicateInfo, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
         },
     },
-    constant::{NUM_NOTE, SETUP_PARAMS_MAP},
+    constant::{NUM_NOTE},
     note::{Note, RandomSeed},
     proof::Proof,
     utils::poseidon_hash,
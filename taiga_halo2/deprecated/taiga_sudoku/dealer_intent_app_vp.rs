@@ -27,7 +27,7 @@ use taiga_halo2::{
             ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
         },
     },
-    constant::{NUM_NOTE, SETUP_PARAMS_MAP},
+    constant::{NUM_NOTE, get_params},
     note::{Note, RandomSeed},
     proof::Proof,
     utils::poseidon_hash,
@@ -389,7 +389,7 @@ fn test_halo2_dealer_intent_vp_circuit() {
             .unwrap();
     assert_eq!(prover.verify(), Ok(()));
 
-    let params = SETUP_PARAMS_MAP.get(&12).unwrap();
+    let params = &get_params(12);
     let vk = keygen_vk(params, &circuit).expect("keygen_vk should not fail");
     let pk = keygen_pk(params, vk.clone(), &circuit).expect("keygen_pk should not fail");
     let proof = Proof::create(&pk, params, circuit, &[public_inputs.inner()], &mut rng).unwrap();
@@ -0,0 +1,744 @@
+/// A node with a mempool and a block builder, three users and a solver over five blocks.
+/// Genesis: the issuer mints 5 "btc" to Alice, 10 "eth" to Bob and 3 "btc" to Carol.
+/// Block 1: Alice puts her 5 btc in an intent for 10 eth or 20 usdc, Carol sends 2 btc to Bob.
+/// Block 2: Bob sends 2 btc to Alice and tries to send the same note to Carol.
+/// Block 3: Bob offers 10 eth for 5 btc, the solver matches it with Alice's intent.
+/// Block 4: Alice sends 4 eth to Carol.
+/// Block 5: Bob replays his double spend, the block builder drops it.
+/// The users only learn their notes by scanning the blocks. An indexer registered on the node
+/// builds the same balances from the state events, trial-decrypting the ciphertexts as the blocks
+/// are applied.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use std::collections::HashMap;
+use std::sync::Arc;
+use taiga_halo2::{
+    block::{Block, BlockBuilder},
+    circuit::vp_examples::{
+        or_relation_intent::{create_intent_note, OrRelationIntentValidityPredicateCircuit},
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName, TokenNote},
+    },
+    constant::{NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH},
+    keys::{FullViewingKey, SigningKey, SpendingKey},
+    mempool::Mempool,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, NoteCommitment, OutputNoteProvingInfo, RandomSeed},
+    note_encryption::EncryptedNote,
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    solver::match_ptxs,
+    state::{CheckpointedState, StateObserver},
+    transaction::{
+        ShieldedPartialTxBundle, Transaction, TransactionId, TransparentPartialTxBundle,
+    },
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Address {
+    auth_pk: pallas::Point,
+    rcv_pk: pallas::Point,
+    nk_com: pallas::Base,
+}
+
+impl Address {
+    fn auth(&self) -> TokenAuthorization {
+        TokenAuthorization::new(self.auth_pk, *COMPRESSED_TOKEN_AUTH_VK)
+            .unwrap()
+            .with_rcv_pk(self.rcv_pk)
+            .unwrap()
+    }
+
+    fn nk_container(&self) -> NullifierKeyContainer {
+        NullifierKeyContainer::from_commitment(self.nk_com)
+    }
+}
+
+// A user with the notes found in the blocks. The wallet keeps its own commitment tree, in sync
+// with the node, to witness its notes.
+struct Wallet {
+    name: &'static str,
+    sk: SpendingKey,
+    tree: CheckpointedState,
+    // The owned notes and their positions in the tree
+    notes: Vec<(u64, Note)>,
+}
+
+impl Wallet {
+    fn new<R: RngCore>(mut rng: R, name: &'static str) -> Self {
+        let mut root = [0u8; 32];
+        rng.fill_bytes(&mut root);
+        Self {
+            name,
+            sk: SpendingKey::from_root(&root),
+            tree: CheckpointedState::default(),
+            notes: vec![],
+        }
+    }
+
+    fn address(&self) -> Address {
+        let ivk = self.sk.to_incoming_viewing_key();
+        Address {
+            auth_pk: ivk.get_auth_pk(),
+            rcv_pk: ivk.get_rcv_pk(),
+            nk_com: ivk.get_nk_commitment(),
+        }
+    }
+
+    fn root(&self) -> Anchor {
+        self.tree.root()
+    }
+
+    // Trial-decrypts the ciphertexts of the block and drops the spent notes
+    fn scan(&mut self, block: &Block) {
+        let fvk = self.sk.to_full_viewing_key();
+        let ivk = fvk.to_incoming_viewing_key();
+        for diff in block.diffs.iter() {
+            for cm in diff.new_commitments.iter() {
+                let received = diff
+                    .ciphertexts
+                    .iter()
+                    .filter(|(ciphertext_cm, _)| ciphertext_cm == cm)
+                    .find_map(|(_, ciphertext)| ivk.decrypt(ciphertext).ok())
+                    .map(|decrypted| decrypted.note)
+                    .filter(|note| note.commitment() == *cm);
+                match received {
+                    Some(note) => {
+                        let position = self.tree.append_marked(*cm).unwrap();
+                        self.notes.push((position, note));
+                    }
+                    None => {
+                        self.tree.append(*cm).unwrap();
+                    }
+                }
+            }
+            self.notes
+                .retain(|(_, note)| !fvk.is_spent(note, &diff.spent_nullifiers));
+        }
+        assert_eq!(self.root(), block.root);
+    }
+
+    fn balance(&self, token: &Token) -> u64 {
+        self.notes
+            .iter()
+            .filter(|(_, note)| note.note_type == token.note_type())
+            .map(|(_, note)| note.value)
+            .sum()
+    }
+
+    // A note of the token worth at least the value, with the nk to spend it
+    fn find_note(&self, token: &Token, value: u64) -> (TokenNote, MerklePath) {
+        let (position, note) = self
+            .notes
+            .iter()
+            .find(|(_, note)| note.note_type == token.note_type() && note.value >= value)
+            .unwrap_or_else(|| panic!("{} has no {} note", self.name, token.name().inner()));
+        let token_note = TokenNote {
+            token_name: token.name().clone(),
+            note: Note {
+                nk_container: self.sk.get_nk(),
+                ..*note
+            },
+        };
+        (token_note, self.tree.witness(*position).unwrap())
+    }
+
+    fn auth(&self) -> TokenAuthorization {
+        self.address().auth()
+    }
+}
+
+// An indexer watching the addresses with their full viewing keys. It's called by the node while
+// the blocks are applied, the received notes are trial-decrypted and the spent ones dropped.
+#[derive(Default)]
+struct BalanceIndex {
+    keys: Vec<(&'static str, FullViewingKey)>,
+    // The unspent notes of each watched address
+    notes: HashMap<&'static str, Vec<Note>>,
+    // The height of the last anchor
+    height: Option<u64>,
+}
+
+impl BalanceIndex {
+    fn watch(&mut self, wallet: &Wallet) {
+        self.keys
+            .push((wallet.name, wallet.sk.to_full_viewing_key()));
+    }
+
+    fn balance(&self, name: &str, token: &Token) -> u64 {
+        self.notes
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|note| note.note_type == token.note_type())
+            .map(|note| note.value)
+            .sum()
+    }
+}
+
+impl StateObserver for BalanceIndex {
+    fn on_note_created(
+        &mut self,
+        cm: NoteCommitment,
+        _position: u64,
+        ciphertext: Option<&EncryptedNote>,
+    ) {
+        if let Some(ciphertext) = ciphertext {
+            for (name, fvk) in self.keys.iter() {
+                let received = fvk
+                    .to_incoming_viewing_key()
+                    .decrypt(ciphertext)
+                    .map(|decrypted| decrypted.note)
+                    .ok()
+                    .filter(|note| note.commitment() == cm);
+                if let Some(note) = received {
+                    self.notes.entry(*name).or_default().push(note);
+                }
+            }
+        }
+    }
+
+    fn on_nullifier_spent(&mut self, nf: Nullifier, _tx_id: Option<TransactionId>) {
+        for (name, fvk) in self.keys.iter() {
+            if let Some(notes) = self.notes.get_mut(name) {
+                notes.retain(|note| !fvk.is_spent(note, &[nf]));
+            }
+        }
+    }
+
+    fn on_anchor(&mut self, _anchor: Anchor, height: Option<u64>) {
+        self.height = height;
+    }
+}
+
+// The node applies the blocks to the state and keeps the pending ptxs and transactions
+struct Node {
+    state: CheckpointedState,
+    mempool: Mempool,
+    builder: BlockBuilder,
+    index: BalanceIndex,
+}
+
+impl Node {
+    fn new() -> Self {
+        Self {
+            state: CheckpointedState::default(),
+            mempool: Mempool::new(),
+            builder: BlockBuilder::new(10),
+            index: BalanceIndex::default(),
+        }
+    }
+
+    fn produce_block(&mut self) -> Block {
+        let block = self
+            .builder
+            .build_block_with_observer(&mut self.mempool, &mut self.state, &mut self.index)
+            .unwrap();
+        // The roots of the past blocks stay known, the pending ptxs may be built on them
+        self.state.checkpoint(block.height).unwrap();
+        self.mempool.prune(&self.state);
+        block
+    }
+}
+
+// What the solver needs to consume the intent, shared by the intent creator
+struct IntentOffer {
+    token_1: Token,
+    token_2: Token,
+    receiver_nk_com: pallas::Base,
+    receiver_app_data_dynamic: pallas::Base,
+    rho: Nullifier,
+    nk: NullifierKeyContainer,
+}
+
+impl IntentOffer {
+    fn note<R: RngCore>(&self, rng: R) -> Note {
+        create_intent_note(
+            rng,
+            &self.token_1,
+            &self.token_2,
+            self.receiver_nk_com,
+            self.receiver_app_data_dynamic,
+            self.rho,
+            self.nk,
+        )
+    }
+
+    fn vp(
+        &self,
+        owned_note_pub_id: pallas::Base,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> OrRelationIntentValidityPredicateCircuit {
+        OrRelationIntentValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            token_1: self.token_1.clone(),
+            token_2: self.token_2.clone(),
+            receiver_nk_com: self.receiver_nk_com,
+            receiver_app_data_dynamic: self.receiver_app_data_dynamic,
+        }
+    }
+}
+
+fn build_tx<R: RngCore + CryptoRng>(rng: R, ptxs: Vec<ShieldedPartialTransaction>) -> Transaction {
+    Transaction::build(
+        rng,
+        ShieldedPartialTxBundle::new(ptxs),
+        TransparentPartialTxBundle::default(),
+    )
+    .unwrap()
+}
+
+// Mint the token to the receiver with an ephemeral issuance note
+fn create_mint_ptx<R: RngCore>(
+    mut rng: R,
+    token: &Token,
+    issuer_sk: pallas::Scalar,
+    receiver: &Address,
+    root: Anchor,
+) -> ShieldedPartialTransaction {
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let issuance_nk = NullifierKeyContainer::random_key(&mut rng);
+    let issuance_note = token.create_issuance_note(&mut rng, rho, issuance_nk);
+    let output_note = token.create_random_token_note(
+        &mut rng,
+        issuance_note.get_nf().unwrap(),
+        receiver.nk_container(),
+        &receiver.auth(),
+    );
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*issuance_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // The ephemeral notes are not checked in the tree, their anchors still have to be known
+    let mut issuance_note_proving_info = issuance_note.generate_input_issuance_proving_info(
+        &mut rng,
+        &SigningKey::new(issuer_sk),
+        merkle_path.clone(),
+        [root, root],
+        input_notes,
+        output_notes,
+    );
+    issuance_note_proving_info.anchor = root;
+    let output_note_proving_info = output_note.generate_output_token_note_proving_info(
+        &mut rng,
+        receiver.auth(),
+        input_notes,
+        output_notes,
+    );
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        root,
+        input_notes,
+        output_notes,
+    );
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    ShieldedPartialTransaction::build(
+        [issuance_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+// Spend the note to create the output for the receiver, the change goes back to the spender.
+// The output token may differ from the spent one, a solver then balances the ptx.
+fn create_transfer_ptx<R: RngCore>(
+    mut rng: R,
+    spender: &Wallet,
+    (input_note, merkle_path): (TokenNote, MerklePath),
+    output: &Token,
+    receiver: &Address,
+    change: u64,
+) -> ShieldedPartialTransaction {
+    let input_auth = spender.auth();
+    let output_note = output.create_random_token_note(
+        &mut rng,
+        input_note.get_nf().unwrap(),
+        receiver.nk_container(),
+        &receiver.auth(),
+    );
+
+    // the change note takes the place of the padding output note
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let change_note = (change > 0).then(|| TokenNote {
+        token_name: input_note.token_name().clone(),
+        note: Note::new(
+            input_note.note_type.app_vk,
+            input_note.note_type.app_data_static,
+            input_auth.to_app_data_dynamic(),
+            change,
+            input_note.nk_container.to_commitment(),
+            padding_input_note_nf,
+            true,
+            RandomSeed::random(&mut rng),
+        ),
+    });
+    let second_output_note = match &change_note {
+        Some(change_note) => *change_note.note(),
+        None => Note::random_padding_output_note(&mut rng, padding_input_note_nf),
+    };
+
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), second_output_note];
+    let anchor = input_note.calculate_root(&merkle_path);
+
+    let input_note_proving_info = input_note.generate_input_token_note_proving_info(
+        &mut rng,
+        input_auth,
+        &spender.sk.get_auth_sk(),
+        merkle_path.clone(),
+        [anchor, anchor],
+        input_notes,
+        output_notes,
+    );
+    let output_note_proving_info = output_note.generate_output_token_note_proving_info(
+        &mut rng,
+        receiver.auth(),
+        input_notes,
+        output_notes,
+    );
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+    let second_output_note_proving_info = match change_note {
+        Some(change_note) => change_note.generate_output_token_note_proving_info(
+            &mut rng,
+            input_auth,
+            input_notes,
+            output_notes,
+        ),
+        None => OutputNoteProvingInfo::create_padding_note_proving_info(
+            second_output_note,
+            input_notes,
+            output_notes,
+        ),
+    };
+
+    ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, second_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+// Spend the note into an intent for token_1 or token_2, paid to the spender
+fn create_intent_ptx<R: RngCore>(
+    mut rng: R,
+    spender: &Wallet,
+    (input_note, merkle_path): (TokenNote, MerklePath),
+    token_1: Token,
+    token_2: Token,
+) -> (ShieldedPartialTransaction, IntentOffer) {
+    let input_auth = spender.auth();
+    // The intent note is ephemeral, its nk is shared with the solver
+    let offer = IntentOffer {
+        token_1,
+        token_2,
+        receiver_nk_com: input_note.get_nk_commitment(),
+        receiver_app_data_dynamic: input_note.app_data_dynamic,
+        rho: input_note.get_nf().unwrap(),
+        nk: NullifierKeyContainer::random_key(&mut rng),
+    };
+    let intent_note = offer.note(&mut rng);
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [intent_note, padding_output_note];
+    let anchor = input_note.calculate_root(&merkle_path);
+
+    let input_note_proving_info = input_note.generate_input_token_note_proving_info(
+        &mut rng,
+        input_auth,
+        &spender.sk.get_auth_sk(),
+        merkle_path.clone(),
+        [anchor, anchor],
+        input_notes,
+        output_notes,
+    );
+    let intent_vp = offer.vp(intent_note.commitment().inner(), input_notes, output_notes);
+    let intent_note_proving_info =
+        OutputNoteProvingInfo::new(intent_note, Box::new(intent_vp), vec![]);
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    let ptx = ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [intent_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+    (ptx, offer)
+}
+
+// The solver consumes the intent and pays token_1 to the intent creator
+fn consume_intent_ptx<R: RngCore>(
+    mut rng: R,
+    offer: &IntentOffer,
+    receiver: Address,
+    root: Anchor,
+) -> ShieldedPartialTransaction {
+    let intent_note = offer.note(&mut rng);
+    let intent_note_nf = intent_note.get_nf().unwrap();
+    let receiver = Address {
+        nk_com: offer.receiver_nk_com,
+        ..receiver
+    };
+    let output_note = offer.token_1.create_random_token_note(
+        &mut rng,
+        intent_note_nf,
+        receiver.nk_container(),
+        &receiver.auth(),
+    );
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [intent_note, padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    let intent_vp = offer.vp(intent_note_nf.inner(), input_notes, output_notes);
+    let intent_note_proving_info = InputNoteProvingInfo::new(
+        intent_note,
+        merkle_path.clone(),
+        Some(root),
+        Box::new(intent_vp),
+        vec![],
+    );
+    let output_note_proving_info = output_note.generate_output_token_note_proving_info(
+        &mut rng,
+        receiver.auth(),
+        input_notes,
+        output_notes,
+    );
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        root,
+        input_notes,
+        output_notes,
+    );
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    ShieldedPartialTransaction::build(
+        [intent_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+// Produce the next block, every user scans it
+fn produce_block(node: &mut Node, wallets: [&mut Wallet; 3]) -> Block {
+    let block = node.produce_block();
+    for wallet in wallets {
+        wallet.scan(&block);
+    }
+    println!(
+        "block {}: {} transactions, {} pending ptxs",
+        block.height,
+        block.txs.len(),
+        node.mempool.ptxs().len()
+    );
+    block
+}
+
+pub fn simulate<R: RngCore + CryptoRng>(mut rng: R) {
+    let issuer_sk = pallas::Scalar::random(&mut rng);
+    let issuer_pk = SigningKey::new(issuer_sk).public_key();
+    let btc = |value| Token::new(TokenName::new_issuable("btc", issuer_pk).unwrap(), value);
+    let eth = |value| Token::new(TokenName::new_issuable("eth", issuer_pk).unwrap(), value);
+    let usdc = |value| Token::new(TokenName::new_issuable("usdc", issuer_pk).unwrap(), value);
+
+    let mut node = Node::new();
+    let mut alice = Wallet::new(&mut rng, "alice");
+    let mut bob = Wallet::new(&mut rng, "bob");
+    let mut carol = Wallet::new(&mut rng, "carol");
+    for wallet in [&alice, &bob, &carol] {
+        node.index.watch(wallet);
+    }
+
+    // Genesis
+    let root = node.state.root();
+    for (token, receiver) in [
+        (btc(5), alice.address()),
+        (eth(10), bob.address()),
+        (btc(3), carol.address()),
+    ] {
+        let ptx = create_mint_ptx(&mut rng, &token, issuer_sk, &receiver, root);
+        let tx = build_tx(&mut rng, vec![ptx]);
+        node.mempool.submit_tx(tx).unwrap();
+    }
+    let block = produce_block(&mut node, [&mut alice, &mut bob, &mut carol]);
+    assert_eq!(block.txs.len(), 3);
+
+    // Block 1
+    let (intent_ptx, offer) = create_intent_ptx(
+        &mut rng,
+        &alice,
+        alice.find_note(&btc(5), 5),
+        eth(10),
+        usdc(20),
+    );
+    node.mempool.submit_ptx(intent_ptx).unwrap();
+    let ptx = create_transfer_ptx(
+        &mut rng,
+        &carol,
+        carol.find_note(&btc(2), 2),
+        &btc(2),
+        &bob.address(),
+        1,
+    );
+    node.mempool
+        .submit_tx(build_tx(&mut rng, vec![ptx]))
+        .unwrap();
+    let block = produce_block(&mut node, [&mut alice, &mut bob, &mut carol]);
+    assert_eq!(block.txs.len(), 1);
+    assert_eq!(node.mempool.ptxs().len(), 1);
+
+    // Block 2
+    let bob_btc = bob.find_note(&btc(2), 2);
+    let ptx = create_transfer_ptx(
+        &mut rng,
+        &bob,
+        bob_btc.clone(),
+        &btc(2),
+        &alice.address(),
+        0,
+    );
+    node.mempool
+        .submit_tx(build_tx(&mut rng, vec![ptx]))
+        .unwrap();
+    let ptx = create_transfer_ptx(&mut rng, &bob, bob_btc, &btc(2), &carol.address(), 0);
+    let double_spend = build_tx(&mut rng, vec![ptx]);
+    node.mempool.submit_tx(double_spend.clone()).unwrap();
+    let block = produce_block(&mut node, [&mut alice, &mut bob, &mut carol]);
+    assert_eq!(block.txs.len(), 1);
+    // The double spend was deferred, then pruned
+    assert!(node.mempool.txs().is_empty());
+
+    // Block 3
+    let ptx = create_transfer_ptx(
+        &mut rng,
+        &bob,
+        bob.find_note(&eth(10), 10),
+        &btc(5),
+        &bob.address(),
+        0,
+    );
+    node.mempool.submit_ptx(ptx).unwrap();
+    let solver_ptx = consume_intent_ptx(&mut rng, &offer, alice.address(), node.state.root());
+    let mut ptxs = node.mempool.ptxs().to_vec();
+    ptxs.push(solver_ptx);
+    assert!(match_ptxs(&ptxs));
+    node.mempool.submit_tx(build_tx(&mut rng, ptxs)).unwrap();
+    assert!(node.mempool.ptxs().is_empty());
+    let block = produce_block(&mut node, [&mut alice, &mut bob, &mut carol]);
+    assert_eq!(block.txs.len(), 1);
+
+    // Block 4
+    let ptx = create_transfer_ptx(
+        &mut rng,
+        &alice,
+        alice.find_note(&eth(4), 4),
+        &eth(4),
+        &carol.address(),
+        6,
+    );
+    node.mempool
+        .submit_tx(build_tx(&mut rng, vec![ptx]))
+        .unwrap();
+    let block = produce_block(&mut node, [&mut alice, &mut bob, &mut carol]);
+    assert_eq!(block.txs.len(), 1);
+
+    // Block 5, the proofs of the replayed double spend are valid but its note is spent
+    node.mempool.submit_tx(double_spend).unwrap();
+    let block = produce_block(&mut node, [&mut alice, &mut bob, &mut carol]);
+    assert!(block.txs.is_empty());
+    assert!(node.mempool.txs().is_empty());
+
+    for (wallet, balances) in [(&alice, [2, 6]), (&bob, [5, 0]), (&carol, [1, 4])] {
+        assert_eq!(
+            [wallet.balance(&btc(0)), wallet.balance(&eth(0))],
+            balances,
+            "{} balances",
+            wallet.name
+        );
+        println!("{}: {} btc, {} eth", wallet.name, balances[0], balances[1]);
+        // The indexer agrees with the wallet
+        assert_eq!(
+            [
+                node.index.balance(wallet.name, &btc(0)),
+                node.index.balance(wallet.name, &eth(0))
+            ],
+            balances,
+            "{} indexed balances",
+            wallet.name
+        );
+    }
+    // Block 5 is empty, the last anchor is the one of block 4
+    assert_eq!(node.index.height, Some(4));
+}
+
+fn main() {
+    use rand::rngs::OsRng;
+    simulate(OsRng);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_node_simulation() {
+        simulate(OsRng);
+    }
+}
@@ -0,0 +1,220 @@
+/// Rotate the auth key of a token note, e.g. after the old key is suspected to be compromised.
+/// The note authorized under the old key is spent and the same note authorized under the new key
+/// is created in one ptx. The value, the token and the nk commitment are kept, so the rotation
+/// looks like any other transfer. The old key signs the commitment of the rotated note.
+///
+use group::Group;
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    circuit::vp_examples::{
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName, TokenNote},
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+// Spend the input note with the auth_sk and create the output note
+fn create_token_transfer_ptx<R: RngCore>(
+    mut rng: R,
+    input_note: &TokenNote,
+    input_auth: TokenAuthorization,
+    input_auth_sk: pallas::Scalar,
+    output_note: &TokenNote,
+    output_auth: TokenAuthorization,
+) -> ShieldedPartialTransaction {
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the input note proving info
+    let input_note_proving_info = input_note.generate_input_token_note_proving_info(
+        &mut rng,
+        input_auth,
+        &SigningKey::new(input_auth_sk),
+        merkle_path.clone(),
+        [input_note.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the output note proving info
+    let output_note_proving_info = output_note.generate_output_token_note_proving_info(
+        &mut rng,
+        output_auth,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+// Returns the ptx and the rotated note. The token note must carry the nk, the rotated note
+// keeps it so it can be spent with the new key.
+pub fn create_auth_rotation_ptx<R: RngCore>(
+    mut rng: R,
+    token_note: &TokenNote,
+    old_auth_sk: pallas::Scalar,
+    new_auth_pk: pallas::Point,
+) -> (ShieldedPartialTransaction, TokenNote) {
+    let old_auth = TokenAuthorization::from_sk_vk(&old_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+    let new_auth = TokenAuthorization::new(new_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let rotated_note = token_note.rotate_auth(&mut rng, &new_auth);
+    let ptx = create_token_transfer_ptx(
+        &mut rng,
+        token_note,
+        old_auth,
+        old_auth_sk,
+        &rotated_note,
+        new_auth,
+    );
+    (ptx, rotated_note)
+}
+
+// Spend the rotated note to the receiver
+pub fn create_rotated_note_spend_ptx<R: RngCore>(
+    mut rng: R,
+    rotated_note: &TokenNote,
+    auth_sk: pallas::Scalar,
+    receiver_auth_pk: pallas::Point,
+    receiver_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> ShieldedPartialTransaction {
+    let auth = TokenAuthorization::from_sk_vk(&auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+    let receiver_auth =
+        TokenAuthorization::new(receiver_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let output_note = TokenNote {
+        token_name: rotated_note.token_name().clone(),
+        note: Note::new(
+            rotated_note.get_app_vk(),
+            rotated_note.get_app_data_static(),
+            receiver_auth.to_app_data_dynamic(),
+            rotated_note.value,
+            receiver_nk_com,
+            rotated_note.get_nf().unwrap(),
+            true,
+            RandomSeed::random(&mut rng),
+        ),
+    };
+    create_token_transfer_ptx(
+        &mut rng,
+        rotated_note,
+        auth,
+        auth_sk,
+        &output_note,
+        receiver_auth,
+    )
+}
+
+fn build_transaction<R: RngCore>(rng: R, ptx: ShieldedPartialTransaction) -> Transaction {
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+// Rotate the auth key of a note, then spend the rotated note with the new key
+pub fn create_auth_rotation_transactions<R: RngCore + CryptoRng>(mut rng: R) -> Vec<Transaction> {
+    let generator = pallas::Point::generator();
+    let old_auth_sk = pallas::Scalar::random(&mut rng);
+    let old_auth = TokenAuthorization::from_sk_vk(&old_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+    let token = Token::new(TokenName::new("xan").unwrap(), 10);
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+    let token_note = token.create_random_token_note(&mut rng, rho, nk, &old_auth);
+
+    let new_auth_sk = pallas::Scalar::random(&mut rng);
+    let (rotation_ptx, rotated_note) =
+        create_auth_rotation_ptx(&mut rng, &token_note, old_auth_sk, generator * new_auth_sk);
+    let rotation_tx = build_transaction(&mut rng, rotation_ptx);
+
+    let receiver_nk = NullifierKeyContainer::random_key(&mut rng);
+    let spend_ptx = create_rotated_note_spend_ptx(
+        &mut rng,
+        &rotated_note,
+        new_auth_sk,
+        pallas::Point::random(&mut rng),
+        receiver_nk.to_commitment(),
+    );
+    let spend_tx = build_transaction(&mut rng, spend_ptx);
+    vec![rotation_tx, spend_tx]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_auth_rotation_tx() {
+        for tx in create_auth_rotation_transactions(OsRng) {
+            tx.execute().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rotated_note_spend_with_old_key() {
+        let mut rng = OsRng;
+        let generator = pallas::Point::generator();
+        let old_auth_sk = pallas::Scalar::random(&mut rng);
+        let old_auth = TokenAuthorization::from_sk_vk(&old_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+        let token = Token::new(TokenName::new("xan").unwrap(), 10);
+        let rho = Nullifier::from(pallas::Base::random(&mut rng));
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let token_note = token.create_random_token_note(&mut rng, rho, nk, &old_auth);
+
+        let new_auth_sk = pallas::Scalar::random(&mut rng);
+        let (rotation_ptx, rotated_note) =
+            create_auth_rotation_ptx(&mut rng, &token_note, old_auth_sk, generator * new_auth_sk);
+        build_transaction(&mut rng, rotation_ptx).execute().unwrap();
+
+        // The old key doesn't authorize the rotated note anymore
+        let receiver_nk = NullifierKeyContainer::random_key(&mut rng);
+        let spend_ptx = create_rotated_note_spend_ptx(
+            &mut rng,
+            &rotated_note,
+            old_auth_sk,
+            pallas::Point::random(&mut rng),
+            receiver_nk.to_commitment(),
+        );
+        assert!(build_transaction(&mut rng, spend_ptx).execute().is_err());
+    }
+}
@@ -0,0 +1,240 @@
+/// Wrap a transparent token resource into a shielded note and unwrap it back.
+/// Wrap: spend an ephemeral note standing for the transparent input, create the wrapped note.
+/// Unwrap: spend the wrapped note, create an ephemeral note standing for the transparent output.
+/// The bridge vp publishes the commitment of the transparent resource. The transparent ptx
+/// consuming or creating the resource is not added: transparent ptxs can't be executed yet.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    circuit::vp_examples::{
+        bridge_vp::TransparentTokenResource,
+        token::{Token, TokenName},
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+// Build the ptx from the bridge note proving infos and the padding notes
+fn create_bridge_ptx<R: RngCore>(
+    mut rng: R,
+    input_note: Note,
+    output_note: Note,
+    input_resource: &TransparentTokenResource,
+    output_resource: &TransparentTokenResource,
+) -> ShieldedPartialTransaction {
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [input_note, padding_input_note];
+    let output_notes = [output_note, padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    let input_note_proving_info = input_resource.generate_input_proving_info(
+        input_note,
+        merkle_path.clone(),
+        None,
+        input_notes,
+        output_notes,
+    );
+    let output_note_proving_info =
+        output_resource.generate_output_proving_info(output_note, input_notes, output_notes);
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+fn build_transaction<R: RngCore>(rng: R, ptx: ShieldedPartialTransaction) -> Transaction {
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+// Wrap the transparent input into a note of `token` for the receiver
+pub fn wrap_tokens<R: RngCore>(
+    mut rng: R,
+    transparent_input: &TransparentTokenResource,
+    token: Token,
+    receiver_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> Transaction {
+    let wrapped = TransparentTokenResource::new(token, transparent_input.data_hash);
+
+    // The ephemeral note of the transparent input
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let bridge_nk = NullifierKeyContainer::random_key(&mut rng);
+    let input_note = wrapped.create_wrapped_note(&mut rng, rho, bridge_nk, true);
+
+    // The wrapped note to the receiver
+    let output_note = wrapped.create_wrapped_note(
+        &mut rng,
+        input_note.get_nf().unwrap(),
+        receiver_nk_com,
+        false,
+    );
+
+    // The bridge vps of both notes check the transparent input
+    let ptx = create_bridge_ptx(
+        &mut rng,
+        input_note,
+        output_note,
+        transparent_input,
+        transparent_input,
+    );
+    build_transaction(rng, ptx)
+}
+
+// Unwrap the wrapped note of the holder into the transparent output
+pub fn unwrap_tokens<R: RngCore>(
+    mut rng: R,
+    wrapped_note: Note,
+    transparent_output: &TransparentTokenResource,
+) -> Transaction {
+    // The ephemeral note of the transparent output, it's of the wrapped note type
+    let bridge_nk = NullifierKeyContainer::random_key(&mut rng);
+    let output_note = Note::new(
+        wrapped_note.note_type.app_vk,
+        wrapped_note.note_type.app_data_static,
+        wrapped_note.app_data_dynamic,
+        wrapped_note.value,
+        bridge_nk.to_commitment(),
+        wrapped_note.get_nf().unwrap(),
+        false,
+        RandomSeed::random(&mut rng),
+    );
+
+    // The bridge vps of both notes check the transparent output
+    let ptx = create_bridge_ptx(
+        &mut rng,
+        wrapped_note,
+        output_note,
+        transparent_output,
+        transparent_output,
+    );
+    build_transaction(rng, ptx)
+}
+
+pub fn create_wrap_and_unwrap_transactions<R: RngCore + CryptoRng>(mut rng: R) -> Vec<Transaction> {
+    let data_hash = pallas::Base::random(&mut rng);
+    let resource =
+        TransparentTokenResource::new(Token::new(TokenName::new("xan").unwrap(), 10), data_hash);
+    let holder_nk = NullifierKeyContainer::random_key(&mut rng);
+    let wrap_tx = wrap_tokens(
+        &mut rng,
+        &resource,
+        resource.token.clone(),
+        holder_nk.to_commitment(),
+    );
+
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let wrapped_note = resource.create_wrapped_note(&mut rng, rho, holder_nk, false);
+    let unwrap_tx = unwrap_tokens(&mut rng, wrapped_note, &resource);
+    vec![wrap_tx, unwrap_tx]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    fn resource(name: &str, value: u64, data_hash: pallas::Base) -> TransparentTokenResource {
+        TransparentTokenResource::new(Token::new(TokenName::new(name).unwrap(), value), data_hash)
+    }
+
+    fn wrap(transparent_input: &TransparentTokenResource, token: Token) -> Transaction {
+        let receiver_nk = NullifierKeyContainer::random_key(OsRng);
+        wrap_tokens(OsRng, transparent_input, token, receiver_nk.to_commitment())
+    }
+
+    fn unwrap(
+        wrapped: &TransparentTokenResource,
+        transparent_output: &TransparentTokenResource,
+    ) -> Transaction {
+        let rho = Nullifier::from(pallas::Base::random(OsRng));
+        let holder_nk = NullifierKeyContainer::random_key(OsRng);
+        let wrapped_note = wrapped.create_wrapped_note(OsRng, rho, holder_nk, false);
+        unwrap_tokens(OsRng, wrapped_note, transparent_output)
+    }
+
+    #[test]
+    fn test_wrap_and_unwrap_tx() {
+        for tx in create_wrap_and_unwrap_transactions(OsRng) {
+            tx.execute().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_wrap_tx_with_wrong_amount() {
+        let data_hash = pallas::Base::random(OsRng);
+        let transparent_input = resource("xan", 10, data_hash);
+        assert!(wrap(
+            &transparent_input,
+            Token::new(TokenName::new("xan").unwrap(), 11)
+        )
+        .execute()
+        .is_err());
+    }
+
+    #[test]
+    fn test_wrap_tx_with_wrong_token() {
+        let data_hash = pallas::Base::random(OsRng);
+        let transparent_input = resource("xan", 10, data_hash);
+        assert!(wrap(
+            &transparent_input,
+            Token::new(TokenName::new("btc").unwrap(), 10)
+        )
+        .execute()
+        .is_err());
+    }
+
+    #[test]
+    fn test_unwrap_tx_with_wrong_amount() {
+        let data_hash = pallas::Base::random(OsRng);
+        let wrapped = resource("xan", 10, data_hash);
+        assert!(unwrap(&wrapped, &resource("xan", 9, data_hash))
+            .execute()
+            .is_err());
+    }
+
+    #[test]
+    fn test_unwrap_tx_with_wrong_token() {
+        let data_hash = pallas::Base::random(OsRng);
+        let wrapped = resource("xan", 10, data_hash);
+        assert!(unwrap(&wrapped, &resource("btc", 10, data_hash))
+            .execute()
+            .is_err());
+    }
+}
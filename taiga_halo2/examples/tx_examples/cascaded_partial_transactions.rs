@@ -4,13 +4,15 @@
 use halo2_proofs::arithmetic::Field;
 use pasta_curves::pallas;
 use rand::{CryptoRng, RngCore};
+use std::sync::Arc;
 use taiga_halo2::{
     circuit::vp_examples::{
         cascade_intent::{create_intent_note, CascadeIntentValidityPredicateCircuit},
         signature_verification::COMPRESSED_TOKEN_AUTH_VK,
-        token::{Token, TokenAuthorization},
+        token::{Token, TokenAuthorization, TokenName},
     },
     constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    keys::SigningKey,
     merkle_tree::{Anchor, MerklePath},
     note::{InputNoteProvingInfo, OutputNoteProvingInfo},
     nullifier::{Nullifier, NullifierKeyContainer},
@@ -27,19 +29,19 @@ pub fn create_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
     let bob_nk_com = NullifierKeyContainer::random_commitment(&mut rng);
 
     let rho = Nullifier::from(pallas::Base::random(&mut rng));
-    let input_token_1 = Token::new("btc".to_string(), 1u64);
+    let input_token_1 = Token::new(TokenName::new("btc").unwrap(), 1u64);
     let input_note_1 = input_token_1.create_random_token_note(&mut rng, rho, alice_nk, &alice_auth);
-    let output_token_1 = Token::new("btc".to_string(), 1u64);
+    let output_token_1 = Token::new(TokenName::new("btc").unwrap(), 1u64);
     let output_note_1 = output_token_1.create_random_token_note(
         &mut rng,
         input_note_1.get_nf().unwrap(),
         bob_nk_com,
         &bob_auth,
     );
-    let input_token_2 = Token::new("eth".to_string(), 2u64);
+    let input_token_2 = Token::new(TokenName::new("eth").unwrap(), 2u64);
     let input_note_2 = input_token_2.create_random_token_note(&mut rng, rho, alice_nk, &alice_auth);
 
-    let input_token_3 = Token::new("xan".to_string(), 3u64);
+    let input_token_3 = Token::new(TokenName::new("xan").unwrap(), 3u64);
     let input_note_3 = input_token_3.create_random_token_note(&mut rng, rho, alice_nk, &alice_auth);
     let cascade_intent_note = create_intent_note(
         &mut rng,
@@ -47,14 +49,14 @@ pub fn create_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
         input_note_2.get_nf().unwrap(),
         alice_nk,
     );
-    let output_token_2 = Token::new("eth".to_string(), 2u64);
+    let output_token_2 = Token::new(TokenName::new("eth").unwrap(), 2u64);
     let output_note_2 = output_token_2.create_random_token_note(
         &mut rng,
         cascade_intent_note.get_nf().unwrap(),
         bob_nk_com,
         &bob_auth,
     );
-    let output_token_3 = Token::new("xan".to_string(), 3u64);
+    let output_token_3 = Token::new(TokenName::new("xan").unwrap(), 3u64);
     let output_note_3 = output_token_3.create_random_token_note(
         &mut rng,
         input_note_3.get_nf().unwrap(),
@@ -73,20 +75,26 @@ pub fn create_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
     let ptx_1 = {
         let input_notes = [*input_note_1.note(), *input_note_2.note()];
         let output_notes = [*output_note_1.note(), cascade_intent_note];
+        let anchors = [
+            input_note_1.calculate_root(&merkle_path),
+            input_note_2.calculate_root(&merkle_path),
+        ];
         // Create the input note proving info
         let input_note_1_proving_info = input_note_1.generate_input_token_note_proving_info(
             &mut rng,
             alice_auth,
-            alice_auth_sk,
+            &SigningKey::new(alice_auth_sk),
             merkle_path.clone(),
+            anchors,
             input_notes,
             output_notes,
         );
         let input_note_2_proving_info = input_note_2.generate_input_token_note_proving_info(
             &mut rng,
             alice_auth,
-            alice_auth_sk,
+            &SigningKey::new(alice_auth_sk),
             merkle_path.clone(),
+            anchors,
             input_notes,
             output_notes,
         );
@@ -102,8 +110,8 @@ pub fn create_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
         let intent_note_proving_info = {
             let intent_vp = CascadeIntentValidityPredicateCircuit {
                 owned_note_pub_id: cascade_intent_note.commitment().inner(),
-                input_notes,
-                output_notes,
+                input_notes: Arc::new(input_notes),
+                output_notes: Arc::new(output_notes),
                 cascade_note_cm: cascade_intent_note.get_app_data_static(),
             };
 
@@ -117,6 +125,7 @@ pub fn create_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
             vec![],
             &mut rng,
         )
+        .unwrap()
     };
 
     // The second partial transaction:
@@ -125,12 +134,13 @@ pub fn create_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
     let ptx_2 = {
         let input_notes = [cascade_intent_note, *input_note_3.note()];
         let output_notes = [*output_note_2.note(), *output_note_3.note()];
+        let anchors = [anchor, input_note_3.calculate_root(&merkle_path)];
         // Create the input note proving info
         let intent_note_proving_info = {
             let intent_vp = CascadeIntentValidityPredicateCircuit {
                 owned_note_pub_id: cascade_intent_note.get_nf().unwrap().inner(),
-                input_notes,
-                output_notes,
+                input_notes: Arc::new(input_notes),
+                output_notes: Arc::new(output_notes),
                 cascade_note_cm: cascade_intent_note.get_app_data_static(),
             };
 
@@ -145,8 +155,9 @@ pub fn create_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
         let input_note_3_proving_info = input_note_3.generate_input_token_note_proving_info(
             &mut rng,
             alice_auth,
-            alice_auth_sk,
+            &SigningKey::new(alice_auth_sk),
             merkle_path,
+            anchors,
             input_notes,
             output_notes,
         );
@@ -171,12 +182,13 @@ pub fn create_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
             vec![],
             &mut rng,
         )
+        .unwrap()
     };
 
     // Create the final transaction
     let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx_1, ptx_2]);
     let transparent_ptx_bundle = TransparentPartialTxBundle::default();
-    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle)
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
 }
 
 #[test]
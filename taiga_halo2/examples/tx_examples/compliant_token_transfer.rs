@@ -0,0 +1,155 @@
+/// Compliant token transfer
+/// The "USDC" notes are screened by the compliance dynamic VP, only the keys in the allowlist can receive them
+/// Alice sends 5 "USDC" to Bob, both are in the allowlist
+///
+use group::{Curve, Group};
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    circuit::vp_examples::{
+        compliance_vp::ComplianceAllowlist,
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName},
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+// Returns the ptx and the opening of the dynamic vp commitment of the output note, the sender
+// hands the opening to the issuer to show the compliance vp is attached.
+pub fn create_compliant_token_transfer_ptx<R: RngCore>(
+    mut rng: R,
+    allowlist: &ComplianceAllowlist,
+    token: Token,
+    input_auth_sk: pallas::Scalar,
+    input_nk: NullifierKeyContainer, // NullifierKeyContainer::Key
+    output_auth_pk: pallas::Point,
+    output_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> (ShieldedPartialTransaction, pallas::Base) {
+    let input_auth = TokenAuthorization::from_sk_vk(&input_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+
+    // input note
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let input_note =
+        allowlist.create_compliant_token_note(&mut rng, &token, rho, input_nk, &input_auth);
+
+    // output note
+    let input_note_nf = input_note.get_nf().unwrap();
+    let output_auth = TokenAuthorization::new(output_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let output_note = allowlist.create_compliant_token_note(
+        &mut rng,
+        &token,
+        input_note_nf,
+        output_nk_com,
+        &output_auth,
+    );
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the input note proving info
+    let input_note_proving_info = allowlist.generate_input_token_note_proving_info(
+        &mut rng,
+        &input_note,
+        input_auth,
+        &SigningKey::new(input_auth_sk),
+        merkle_path.clone(),
+        [input_note.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the output note proving info, the receiver must be in the allowlist
+    let (output_note_proving_info, vp_cm_r) = allowlist
+        .generate_output_token_note_proving_info(
+            &mut rng,
+            &output_note,
+            output_auth,
+            input_notes,
+            output_notes,
+        )
+        .expect("the receiver is not in the allowlist");
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    let ptx = ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+    (ptx, vp_cm_r)
+}
+
+pub fn create_compliant_token_transfer_transaction<R: RngCore + CryptoRng>(
+    mut rng: R,
+) -> Transaction {
+    let generator = pallas::Point::generator().to_affine();
+    let alice_auth_sk = pallas::Scalar::random(&mut rng);
+    let alice_nk = NullifierKeyContainer::random_key(&mut rng);
+    let bob_auth_sk = pallas::Scalar::random(&mut rng);
+    let bob_auth_pk = generator * bob_auth_sk;
+    let bob_nk = NullifierKeyContainer::random_key(&mut rng);
+
+    // The issuer publishes the allowlist, the compliance vp vk pins it
+    let allowlist = ComplianceAllowlist::new(vec![generator * alice_auth_sk, bob_auth_pk]);
+
+    let (ptx, vp_cm_r) = create_compliant_token_transfer_ptx(
+        &mut rng,
+        &allowlist,
+        Token::new(TokenName::new("usdc").unwrap(), 5),
+        alice_auth_sk,
+        alice_nk,
+        bob_auth_pk,
+        bob_nk.to_commitment(),
+    );
+
+    // The issuer checks the compliance vp of Bob's note is attached
+    ptx.check_output_dynamic_vp(0, allowlist.vp_vk(), vp_cm_r)
+        .unwrap();
+
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+#[test]
+fn test_compliant_token_transfer_tx() {
+    use rand::rngs::OsRng;
+
+    let tx = create_compliant_token_transfer_transaction(OsRng);
+    tx.execute().unwrap();
+}
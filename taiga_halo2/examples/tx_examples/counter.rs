@@ -0,0 +1,150 @@
+/// The counter application flow
+/// Create a counter from the genesis note, then increment it three times in three transactions.
+/// Each transaction spends the counter note created in the previous transaction.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    circuit::vp_examples::counter::{
+        create_counter_note, create_genesis_counter_note, CounterState,
+        CounterValidityPredicateCircuit,
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+// Spend the counter note and create the next counter note.
+// Returns the ptx and the new counter note.
+pub fn create_counter_increment_ptx<R: RngCore>(
+    mut rng: R,
+    counter_id: pallas::Base,
+    input_note: Note,
+    input_state: CounterState,
+    nk: NullifierKeyContainer, // NullifierKeyContainer::Key
+) -> (ShieldedPartialTransaction, Note) {
+    // The counter note with the next state
+    let output_state = input_state.next();
+    let output_note = create_counter_note(
+        &mut rng,
+        counter_id,
+        output_state,
+        input_note.get_nf().unwrap(),
+        nk,
+    );
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [input_note, padding_input_note];
+    let output_notes = [output_note, padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the input note proving info
+    let input_note_proving_info = {
+        let counter_vp = CounterValidityPredicateCircuit::new(
+            input_note.get_nf().unwrap().inner(),
+            input_notes,
+            output_notes,
+            input_state,
+        );
+        InputNoteProvingInfo::new(
+            input_note,
+            merkle_path.clone(),
+            None,
+            Box::new(counter_vp),
+            vec![],
+        )
+    };
+
+    // Create the output note proving info
+    let output_note_proving_info = {
+        let counter_vp = CounterValidityPredicateCircuit::new(
+            output_note.commitment().inner(),
+            input_notes,
+            output_notes,
+            input_state,
+        );
+        OutputNoteProvingInfo::new(output_note, Box::new(counter_vp), vec![])
+    };
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    let ptx = ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+
+    (ptx, output_note)
+}
+
+fn create_transaction<R: RngCore + CryptoRng>(
+    mut rng: R,
+    ptx: ShieldedPartialTransaction,
+) -> Transaction {
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+// Returns the genesis transaction and the three increment transactions
+pub fn create_counter_transactions<R: RngCore + CryptoRng>(mut rng: R) -> Vec<Transaction> {
+    let counter_id = pallas::Base::random(&mut rng);
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+
+    // Create the counter from the genesis note
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let mut note = create_genesis_counter_note(&mut rng, counter_id, rho, nk);
+    let mut state = CounterState::genesis();
+
+    let mut txs = vec![];
+    for _ in 0..4 {
+        let (ptx, output_note) =
+            create_counter_increment_ptx(&mut rng, counter_id, note, state, nk);
+        txs.push(create_transaction(&mut rng, ptx));
+        note = output_note;
+        state = state.next();
+    }
+    assert_eq!(state, CounterState::new(3));
+
+    txs
+}
+
+#[test]
+fn test_counter_txs() {
+    use rand::rngs::OsRng;
+
+    let txs = create_counter_transactions(OsRng);
+    for tx in txs.iter() {
+        tx.execute().unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+/// The cyclic token swap
+/// Alice has 5 "apple" and wants 5 "banana", Bob has 5 "banana" and wants 5 "cherry",
+/// Carol has 5 "cherry" and wants 5 "apple". No two of them can swap directly.
+/// Each of them creates an intent, and the solver fully fills all the intents in one transaction.
+///
+use crate::partial_fulfillment_token_swap::{consume_token_intent_ptx, create_token_intent_ptx};
+use group::Group;
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::{group::Curve, pallas};
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    circuit::vp_examples::token::{Token, TokenName},
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+// Create the intent ptxs and the solver ptxs. The solver fills the i-th intent with `fills[i]`.
+fn create_cycle_swap_transaction_with_fills<R: RngCore + CryptoRng>(
+    mut rng: R,
+    participants: Vec<(Token, Token, pallas::Scalar)>,
+    fills: Vec<Token>,
+) -> Transaction {
+    assert_eq!(participants.len(), fills.len());
+    let generator = pallas::Point::generator().to_affine();
+
+    let mut ptxs = vec![];
+    let mut swaps = vec![];
+    // Each participant creates the intent ptx selling what they have for what they want
+    for (have, want, auth_sk) in participants.iter() {
+        let (ptx, swap, intent_note) =
+            create_token_intent_ptx(&mut rng, have.clone(), want.clone(), *auth_sk).unwrap();
+        ptxs.push(ptx);
+        swaps.push((swap, intent_note, generator * *auth_sk));
+    }
+
+    // The solver consumes the intents and routes the assets. The token sold by one participant
+    // is the token bought by the previous one in the cycle, so the tx is balanced.
+    for ((swap, intent_note, auth_pk), fill) in swaps.into_iter().zip(fills) {
+        ptxs.push(consume_token_intent_ptx(
+            &mut rng,
+            swap,
+            intent_note,
+            fill,
+            auth_pk,
+        ));
+    }
+
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(ptxs);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+// The participants are (have, want, auth_sk). Every intent is fully filled.
+pub fn create_cycle_swap_transaction<R: RngCore + CryptoRng>(
+    rng: R,
+    participants: Vec<(Token, Token, pallas::Scalar)>,
+) -> Transaction {
+    let fills = participants
+        .iter()
+        .map(|(_, want, _)| want.clone())
+        .collect();
+    create_cycle_swap_transaction_with_fills(rng, participants, fills)
+}
+
+// Alice, Bob and Carol swap apples, bananas and cherries
+pub fn create_three_party_cycle_swap_transaction<R: RngCore + CryptoRng>(
+    mut rng: R,
+) -> Transaction {
+    let participants = three_party_cycle(&mut rng);
+    create_cycle_swap_transaction(rng, participants)
+}
+
+fn three_party_cycle<R: RngCore>(mut rng: R) -> Vec<(Token, Token, pallas::Scalar)> {
+    let apple = Token::new(TokenName::new("apple").unwrap(), 5u64);
+    let banana = Token::new(TokenName::new("banana").unwrap(), 5u64);
+    let cherry = Token::new(TokenName::new("cherry").unwrap(), 5u64);
+    vec![
+        (
+            apple.clone(),
+            banana.clone(),
+            pallas::Scalar::random(&mut rng),
+        ),
+        (banana, cherry.clone(), pallas::Scalar::random(&mut rng)),
+        (cherry, apple, pallas::Scalar::random(&mut rng)),
+    ]
+}
+
+#[test]
+fn test_cyclic_swap_tx() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let tx = create_three_party_cycle_swap_transaction(&mut rng);
+    tx.execute().unwrap();
+
+    // The solver shorts Bob: Bob gets 4 cherry and 1 banana back, so the cycle is unbalanced.
+    let participants = three_party_cycle(&mut rng);
+    let mut fills: Vec<Token> = participants
+        .iter()
+        .map(|(_, want, _)| want.clone())
+        .collect();
+    fills[1] = Token::new(TokenName::new("cherry").unwrap(), 4u64);
+    let tx = create_cycle_swap_transaction_with_fills(&mut rng, participants, fills);
+    assert!(tx.execute().is_err());
+}
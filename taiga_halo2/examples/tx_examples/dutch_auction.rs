@@ -0,0 +1,269 @@
+/// The dutch auction
+/// Alice sells 1 "monalisa" starting at 100 "ETH", the price decreases by 5 "ETH" per time unit.
+/// Bob has 50 "ETH" and wants 1 "monalisa".
+/// At time 10, the floor price is 50 "ETH", so the Solver/Bob can fill the auction.
+/// The executor supplies the timestamp of the transaction in the execution context, it must be the
+/// time published by the auction vps.
+///
+use crate::token::create_token_swap_ptx;
+use group::Group;
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::{group::Curve, pallas};
+use rand::{CryptoRng, RngCore};
+use std::sync::Arc;
+use taiga_halo2::{
+    circuit::vp_examples::{
+        dutch_auction_intent::{
+            DutchAuction, DutchAuctionIntentValidityPredicateCircuit,
+            COMPRESSED_DUTCH_AUCTION_INTENT_VK, DUTCH_AUCTION_TIME_PUBLIC_INPUT_IDX,
+        },
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName, TokenNote},
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    error::TransactionError,
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    nullifier::NullifierKeyContainer,
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{
+        ExecutionContext, ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle,
+    },
+};
+
+// The context of the executor at the time
+pub fn auction_context(timestamp: u64) -> ExecutionContext {
+    ExecutionContext::default()
+        .with_timestamp(timestamp)
+        .bind_timestamp(
+            *COMPRESSED_DUTCH_AUCTION_INTENT_VK,
+            DUTCH_AUCTION_TIME_PUBLIC_INPUT_IDX,
+        )
+}
+
+pub fn create_auction_ptx<R: RngCore>(
+    mut rng: R,
+    sell: Token,
+    payment: TokenName,
+    start_price: u64,
+    decay_rate: u64,
+    start_time: u64,
+    current_time: u64,
+    input_auth_sk: pallas::Scalar,
+) -> (ShieldedPartialTransaction, DutchAuction, Note) {
+    let input_auth = TokenAuthorization::from_sk_vk(&input_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+    let auction = DutchAuction::random(
+        &mut rng,
+        sell,
+        payment,
+        start_price,
+        decay_rate,
+        start_time,
+        input_auth,
+    );
+    let intent_note = auction.create_intent_note(&mut rng);
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*auction.sell.note(), padding_input_note];
+    let output_notes = [intent_note, padding_output_note];
+
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for dummy notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the input note proving info
+    let input_note_proving_info = auction.sell.generate_input_token_note_proving_info(
+        &mut rng,
+        input_auth,
+        &SigningKey::new(input_auth_sk),
+        merkle_path.clone(),
+        [auction.sell.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the intent note proving info, the price is not checked when creating the auction but
+    // the time is still the one of the transaction
+    let intent_note_proving_info = {
+        let intent_vp = DutchAuctionIntentValidityPredicateCircuit {
+            owned_note_pub_id: intent_note.commitment().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            auction: auction.clone(),
+            current_time,
+        };
+
+        OutputNoteProvingInfo::new(intent_note, Box::new(intent_vp), vec![])
+    };
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    let ptx = ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [intent_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+
+    (ptx, auction, intent_note)
+}
+
+pub fn fill_auction_ptx<R: RngCore>(
+    mut rng: R,
+    auction: DutchAuction,
+    intent_note: Note,
+    payment_value: u64,
+    current_time: u64,
+) -> ShieldedPartialTransaction {
+    let (input_notes, output_notes) = auction.fill(&mut rng, intent_note, payment_value);
+    let [intent_note, padding_input_note] = input_notes;
+    let [payment_note, padding_output_note] = output_notes;
+
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for dummy notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the intent note proving info
+    let intent_note_proving_info = {
+        let intent_vp = DutchAuctionIntentValidityPredicateCircuit {
+            owned_note_pub_id: intent_note.get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            auction: auction.clone(),
+            current_time,
+        };
+
+        InputNoteProvingInfo::new(
+            intent_note,
+            merkle_path.clone(),
+            Some(anchor),
+            Box::new(intent_vp),
+            vec![],
+        )
+    };
+
+    // Create the payment note proving info
+    let payment_note_proving_info = TokenNote {
+        token_name: auction.payment.clone(),
+        note: payment_note,
+    }
+    .generate_output_token_note_proving_info(&mut rng, auction.auth, input_notes, output_notes);
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    ShieldedPartialTransaction::build(
+        [intent_note_proving_info, padding_input_note_proving_info],
+        [payment_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+// The auction is created and filled in the same transaction at the time
+pub fn create_dutch_auction_transaction<R: RngCore + CryptoRng>(
+    mut rng: R,
+    current_time: u64,
+) -> Transaction {
+    let generator = pallas::Point::generator().to_affine();
+
+    // Alice creates the auction starting at time 0
+    let alice_auth_sk = pallas::Scalar::random(&mut rng);
+    let sell = Token::new(TokenName::new("monalisa").unwrap(), 1u64);
+    let payment = TokenName::new("eth").unwrap();
+    let (alice_ptx, auction, intent_note) = create_auction_ptx(
+        &mut rng,
+        sell,
+        payment,
+        100,
+        5,
+        0,
+        current_time,
+        alice_auth_sk,
+    );
+
+    // Bob creates the partial transaction with 50 ETH input and 1 monalisa output
+    let bob_auth_sk = pallas::Scalar::random(&mut rng);
+    let bob_auth_pk = generator * bob_auth_sk;
+    let bob_nk = NullifierKeyContainer::random_key(&mut rng);
+    let offer = Token::new(TokenName::new("eth").unwrap(), 50u64);
+    let bought = Token::new(TokenName::new("monalisa").unwrap(), 1u64);
+    let bob_ptx = create_token_swap_ptx(
+        &mut rng,
+        offer,
+        bob_auth_sk,
+        bob_nk,
+        bought,
+        bob_auth_pk,
+        bob_nk.to_commitment(),
+    );
+
+    // Solver/Bob fills the auction, paying the floor price to Alice
+    let payment_value = auction.floor_price(current_time).unwrap();
+    let solver_ptx = fill_auction_ptx(&mut rng, auction, intent_note, payment_value, current_time);
+
+    // Solver creates the final transaction
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![alice_ptx, bob_ptx, solver_ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+#[test]
+fn test_dutch_auction_tx() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let tx = create_dutch_auction_transaction(&mut rng, 10);
+    tx.execute_with_context(&auction_context(10)).unwrap();
+
+    // The vps are proved at time 10, not at the timestamp of the executor
+    assert!(matches!(
+        tx.execute_with_context(&auction_context(20)),
+        Err(TransactionError::TimestampMismatch)
+    ));
+    assert!(matches!(
+        tx.execute_with_context(&ExecutionContext::default().bind_timestamp(
+            *COMPRESSED_DUTCH_AUCTION_INTENT_VK,
+            DUTCH_AUCTION_TIME_PUBLIC_INPUT_IDX,
+        )),
+        Err(TransactionError::MissingTimestamp)
+    ));
+}
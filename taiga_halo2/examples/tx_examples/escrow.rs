@@ -0,0 +1,425 @@
+/// The escrow flow
+/// Alice locks 10 "btc" for Bob with an arbiter, the arbiter can act after height 100.
+/// - Alice releases the token to Bob at height 60.
+/// - Bob refunds the token to Alice at height 60.
+/// - The arbiter pays the token to Bob at height 150.
+/// Bob can't release the token to himself and the arbiter can't act before the timeout.
+/// The executor supplies the height in the execution context, it must be the height published by
+/// the escrow vps.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use std::sync::Arc;
+use taiga_halo2::{
+    circuit::vp_examples::{
+        escrow::{
+            EscrowAction, EscrowTerms, EscrowValidityPredicateCircuit, COMPRESSED_ESCROW_VK,
+            ESCROW_HEIGHT_PUBLIC_INPUT_IDX,
+        },
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName, TokenNote, TokenValidityPredicateCircuit},
+    },
+    constant::{NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH},
+    error::TransactionError,
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{
+        ExecutionContext, ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle,
+    },
+};
+
+const TIMEOUT_HEIGHT: u64 = 100;
+const AMOUNT: u64 = 10;
+const LOCK_HEIGHT: u64 = 50;
+
+// The context of the executor at the height
+pub fn escrow_context(height: u64) -> ExecutionContext {
+    ExecutionContext::new(height).bind_height(*COMPRESSED_ESCROW_VK, ESCROW_HEIGHT_PUBLIC_INPUT_IDX)
+}
+
+// The token vp of the locked token, the escrow vp is the dynamic vp
+fn locked_token_vp<R: RngCore>(
+    mut rng: R,
+    owned_note_pub_id: pallas::Base,
+    terms: &EscrowTerms,
+    locked_token: &TokenNote,
+    input_notes: [Note; NUM_NOTE],
+    output_notes: [Note; NUM_NOTE],
+) -> TokenValidityPredicateCircuit {
+    TokenValidityPredicateCircuit {
+        owned_note_pub_id,
+        input_notes: Arc::new(input_notes),
+        output_notes: Arc::new(output_notes),
+        token_name: locked_token.token_name().clone(),
+        auth: terms.locked_token_authorization(),
+        receiver_vp_vk: *COMPRESSED_ESCROW_VK,
+        rseed: RandomSeed::random(&mut rng),
+    }
+}
+
+// Alice locks her token note. Returns the ptx, the locked token note and the escrow note.
+pub fn create_lock_ptx<R: RngCore>(
+    mut rng: R,
+    terms: EscrowTerms,
+    alice_sk: &SigningKey,
+    alice_nk: NullifierKeyContainer, // NullifierKeyContainer::Key
+) -> (ShieldedPartialTransaction, TokenNote, Note) {
+    let alice_auth =
+        TokenAuthorization::new(alice_sk.public_key(), *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let alice_token = Token::new(TokenName::new("btc").unwrap(), terms.amount)
+        .create_random_token_note(&mut rng, Nullifier::random(&mut rng), alice_nk, &alice_auth);
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+
+    let locked_token = terms.create_locked_token_note(
+        &mut rng,
+        TokenName::new("btc").unwrap(),
+        alice_token.get_nf().unwrap(),
+        alice_nk,
+    );
+    let escrow_note = terms.create_escrow_note(
+        &mut rng,
+        locked_token.note().commitment().inner(),
+        padding_input_note.get_nf().unwrap(),
+        alice_nk,
+    );
+
+    let input_notes = [*alice_token.note(), padding_input_note];
+    let output_notes = [*locked_token.note(), escrow_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+    let anchors = [alice_token.calculate_root(&merkle_path), anchor];
+
+    let escrow_vp = |rng: &mut R, owned_note_pub_id: pallas::Base| {
+        EscrowValidityPredicateCircuit::from_sk_and_sign(
+            rng,
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            terms,
+            EscrowAction::Lock,
+            LOCK_HEIGHT,
+            alice_sk,
+        )
+    };
+
+    // Create the input note proving info
+    let input_note_proving_info = alice_token.generate_input_token_note_proving_info(
+        &mut rng,
+        alice_auth,
+        alice_sk,
+        merkle_path.clone(),
+        anchors,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the locked token proving info, the escrow vp is the receiver vp
+    let locked_token_proving_info = {
+        let cm = locked_token.note().commitment().inner();
+        let token_vp = locked_token_vp(
+            &mut rng,
+            cm,
+            &terms,
+            &locked_token,
+            input_notes,
+            output_notes,
+        );
+        OutputNoteProvingInfo::new(
+            output_notes[0],
+            Box::new(token_vp),
+            vec![Box::new(escrow_vp(&mut rng, cm))],
+        )
+    };
+
+    // Create the escrow note proving info
+    let escrow_note_proving_info = {
+        let cm = escrow_note.commitment().inner();
+        OutputNoteProvingInfo::new(escrow_note, Box::new(escrow_vp(&mut rng, cm)), vec![])
+    };
+
+    // Create shielded partial tx
+    let ptx = ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [locked_token_proving_info, escrow_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+
+    (ptx, locked_token, escrow_note)
+}
+
+// Settle the escrow at the height with the action signed by the sk, the token goes to the payee
+#[allow(clippy::too_many_arguments)]
+pub fn create_settle_proving_infos<R: RngCore>(
+    mut rng: R,
+    terms: EscrowTerms,
+    locked_token: &TokenNote,
+    escrow_note: Note,
+    action: EscrowAction,
+    sk: &SigningKey,
+    payee: pallas::Point,
+    payee_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+    height: u64,
+) -> (
+    [InputNoteProvingInfo; NUM_NOTE],
+    [OutputNoteProvingInfo; NUM_NOTE],
+) {
+    let payee_auth = TokenAuthorization::new(payee, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let payout_note = Token::new(locked_token.token_name().clone(), terms.amount)
+        .create_random_token_note(
+            &mut rng,
+            locked_token.get_nf().unwrap(),
+            payee_nk_com,
+            &payee_auth,
+        );
+
+    // padding the zero note
+    let padding_output_note =
+        Note::random_padding_output_note(&mut rng, escrow_note.get_nf().unwrap());
+
+    let input_notes = [*locked_token.note(), escrow_note];
+    let output_notes = [*payout_note.note(), padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    let anchors = input_notes.map(|note| note.calculate_root(&merkle_path));
+
+    let escrow_vp = |rng: &mut R, owned_note_pub_id: pallas::Base| {
+        EscrowValidityPredicateCircuit::from_sk_and_sign(
+            rng,
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            terms,
+            action,
+            height,
+            sk,
+        )
+    };
+
+    // Create the locked token proving info, the escrow vp is the auth vp
+    let locked_token_proving_info = {
+        let nf = locked_token.get_nf().unwrap().inner();
+        let token_vp = locked_token_vp(
+            &mut rng,
+            nf,
+            &terms,
+            locked_token,
+            input_notes,
+            output_notes,
+        );
+        InputNoteProvingInfo::new(
+            input_notes[0],
+            merkle_path.clone(),
+            None,
+            Box::new(token_vp),
+            vec![Box::new(escrow_vp(&mut rng, nf))],
+        )
+    };
+
+    // Create the escrow note proving info
+    let escrow_note_proving_info = {
+        let nf = escrow_note.get_nf().unwrap().inner();
+        InputNoteProvingInfo::new(
+            escrow_note,
+            merkle_path,
+            None,
+            Box::new(escrow_vp(&mut rng, nf)),
+            vec![],
+        )
+    };
+
+    // Create the payout note proving info
+    let payout_note_proving_info = payout_note.generate_output_token_note_proving_info(
+        &mut rng,
+        payee_auth,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    (
+        [locked_token_proving_info, escrow_note_proving_info],
+        [payout_note_proving_info, padding_output_note_proving_info],
+    )
+}
+
+fn create_transaction<R: RngCore + CryptoRng>(
+    mut rng: R,
+    ptx: ShieldedPartialTransaction,
+) -> Result<Transaction, TransactionError> {
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle)
+}
+
+struct Parties {
+    alice_sk: SigningKey,
+    alice_nk: NullifierKeyContainer,
+    bob_sk: SigningKey,
+    arbiter_sk: SigningKey,
+    terms: EscrowTerms,
+}
+
+fn parties<R: RngCore>(mut rng: R) -> Parties {
+    let alice_sk = SigningKey::random(&mut rng);
+    let bob_sk = SigningKey::random(&mut rng);
+    let arbiter_sk = SigningKey::random(&mut rng);
+    let terms = EscrowTerms::new(
+        alice_sk.public_key(),
+        bob_sk.public_key(),
+        arbiter_sk.public_key(),
+        TIMEOUT_HEIGHT,
+        AMOUNT,
+    );
+    Parties {
+        alice_sk,
+        alice_nk: NullifierKeyContainer::random_key(&mut rng),
+        bob_sk,
+        arbiter_sk,
+        terms,
+    }
+}
+
+// Returns the lock and the settlement transactions of the three paths with their heights
+pub fn create_escrow_transactions<R: RngCore + CryptoRng>(mut rng: R) -> Vec<(Transaction, u64)> {
+    let Parties {
+        alice_sk,
+        alice_nk,
+        bob_sk,
+        arbiter_sk,
+        terms,
+    } = parties(&mut rng);
+
+    let mut txs = vec![];
+    for (action, sk, height) in [
+        (EscrowAction::Release, &alice_sk, 60),
+        (EscrowAction::Refund, &bob_sk, 60),
+        (EscrowAction::Arbitrate { to_bob: true }, &arbiter_sk, 150),
+    ] {
+        let (lock_ptx, locked_token, escrow_note) =
+            create_lock_ptx(&mut rng, terms, &alice_sk, alice_nk);
+        txs.push((create_transaction(&mut rng, lock_ptx).unwrap(), LOCK_HEIGHT));
+
+        let payee_nk_com = NullifierKeyContainer::random_commitment(&mut rng);
+        let (input_infos, output_infos) = create_settle_proving_infos(
+            &mut rng,
+            terms,
+            &locked_token,
+            escrow_note,
+            action,
+            sk,
+            terms.payee(action).unwrap(),
+            payee_nk_com,
+            height,
+        );
+        let settle_ptx =
+            ShieldedPartialTransaction::build(input_infos, output_infos, vec![], &mut rng).unwrap();
+        txs.push((create_transaction(&mut rng, settle_ptx).unwrap(), height));
+    }
+    txs
+}
+
+#[test]
+fn test_escrow_txs() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    for (tx, height) in create_escrow_transactions(&mut rng) {
+        tx.execute_with_context(&escrow_context(height)).unwrap();
+    }
+
+    let Parties {
+        alice_sk,
+        alice_nk,
+        bob_sk,
+        arbiter_sk,
+        terms,
+    } = parties(&mut rng);
+    let (_, locked_token, escrow_note) = create_lock_ptx(&mut rng, terms, &alice_sk, alice_nk);
+    let bob_nk_com = NullifierKeyContainer::random_commitment(&mut rng);
+
+    // Bob can't release the token to himself with his own signature
+    let (input_infos, output_infos) = create_settle_proving_infos(
+        &mut rng,
+        terms,
+        &locked_token,
+        escrow_note,
+        EscrowAction::Release,
+        &bob_sk,
+        terms.bob_pk,
+        bob_nk_com,
+        60,
+    );
+    assert!(matches!(
+        ShieldedPartialTransaction::dry_run(&input_infos, &output_infos, true),
+        Err(TransactionError::VPConstraintFailure { .. })
+    ));
+
+    // The arbiter can't act before the timeout
+    let action = EscrowAction::Arbitrate { to_bob: true };
+    assert!(!terms.can_arbitrate(TIMEOUT_HEIGHT));
+    let (input_infos, output_infos) = create_settle_proving_infos(
+        &mut rng,
+        terms,
+        &locked_token,
+        escrow_note,
+        action,
+        &arbiter_sk,
+        terms.bob_pk,
+        bob_nk_com,
+        TIMEOUT_HEIGHT,
+    );
+    assert!(matches!(
+        ShieldedPartialTransaction::dry_run(&input_infos, &output_infos, true),
+        Err(TransactionError::VPConstraintFailure { .. })
+    ));
+
+    // The settlement proofs are for the height of the proofs, not the height of the executor
+    let (input_infos, output_infos) = create_settle_proving_infos(
+        &mut rng,
+        terms,
+        &locked_token,
+        escrow_note,
+        action,
+        &arbiter_sk,
+        terms.bob_pk,
+        bob_nk_com,
+        150,
+    );
+    let ptx =
+        ShieldedPartialTransaction::build(input_infos, output_infos, vec![], &mut rng).unwrap();
+    let tx = create_transaction(&mut rng, ptx).unwrap();
+    assert!(matches!(
+        tx.execute_with_context(&escrow_context(TIMEOUT_HEIGHT)),
+        Err(TransactionError::HeightMismatch)
+    ));
+}
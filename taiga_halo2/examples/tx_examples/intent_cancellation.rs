@@ -0,0 +1,84 @@
+/// The token swap intent cancellation
+/// Alice has 2 "BTC" and wants 10 "ETH". Then Alice creates an intent for it.
+/// Nobody fills the intent, so Alice cancels it and gets the 2 "BTC" back.
+///
+use crate::partial_fulfillment_token_swap::create_token_intent_ptx;
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    circuit::vp_examples::{
+        partial_fulfillment_intent::Swap,
+        token::{Token, TokenName},
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::Note,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+// Returns the transaction, the swap and the cancelled intent note
+pub fn create_intent_cancellation_transaction_with_intent<R: RngCore + CryptoRng>(
+    mut rng: R,
+) -> (Transaction, Swap, Note) {
+    // Alice creates the intent
+    let alice_auth_sk = pallas::Scalar::random(&mut rng);
+    let sell = Token::new(TokenName::new("btc").unwrap(), 2u64);
+    let buy = Token::new(TokenName::new("eth").unwrap(), 10u64);
+    let (intent_ptx, swap, intent_note) =
+        create_token_intent_ptx(&mut rng, sell, buy, alice_auth_sk).unwrap();
+
+    // Alice cancels the intent
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    // Fetch a valid anchor for dummy notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+    let cancel_ptx = swap
+        .create_cancel_ptx(
+            &mut rng,
+            intent_note,
+            merkle_path,
+            anchor,
+            &SigningKey::new(alice_auth_sk),
+        )
+        .unwrap();
+
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![intent_ptx, cancel_ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    let tx = Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap();
+    (tx, swap, intent_note)
+}
+
+pub fn create_intent_cancellation_transaction<R: RngCore + CryptoRng>(rng: R) -> Transaction {
+    create_intent_cancellation_transaction_with_intent(rng).0
+}
+
+#[test]
+fn test_intent_cancellation_tx() {
+    use crate::partial_fulfillment_token_swap::consume_token_intent_ptx;
+    use group::{Curve, Group};
+    use rand::rngs::OsRng;
+    use taiga_halo2::state::{CheckpointedState, StateError};
+
+    let mut rng = OsRng;
+    let (tx, swap, intent_note) = create_intent_cancellation_transaction_with_intent(&mut rng);
+    tx.execute().unwrap();
+
+    let mut state = CheckpointedState::default();
+    for nf in tx.get_shielded_ptx_bundle().get_nullifiers() {
+        state.insert_nullifier(nf).unwrap();
+    }
+
+    // The cancelled intent can't be filled anymore
+    let bob_auth_pk = pallas::Point::generator().to_affine() * pallas::Scalar::random(&mut rng);
+    let offer = Token::new(TokenName::new("eth").unwrap(), 5u64);
+    let solver_ptx = consume_token_intent_ptx(&mut rng, swap, intent_note, offer, bob_auth_pk);
+    let intent_nf = intent_note.get_nf().unwrap();
+    let fill_nfs = ShieldedPartialTxBundle::new(vec![solver_ptx]).get_nullifiers();
+    assert!(fill_nfs.contains(&intent_nf));
+    assert!(state.contains_nullifier(&intent_nf));
+    assert_eq!(
+        state.insert_nullifier(intent_nf),
+        Err(StateError::DuplicateNullifier)
+    );
+}
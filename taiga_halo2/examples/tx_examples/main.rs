@@ -1,8 +1,22 @@
+mod auth_rotation;
+mod bridge;
 mod cascaded_partial_transactions;
+mod compliant_token_transfer;
+mod counter;
+mod cyclic_swap;
+mod dutch_auction;
+mod escrow;
+mod intent_cancellation;
+mod multisig_token_transfer;
+mod nft;
 mod partial_fulfillment_token_swap;
+mod payment_address;
+mod spending_cap;
 mod token;
+mod token_issuance;
 mod token_swap_with_intent;
 mod token_swap_without_intent;
+mod vesting;
 fn main() {
     use rand::rngs::OsRng;
 
@@ -16,6 +30,68 @@ fn main() {
     let tx = partial_fulfillment_token_swap::create_token_swap_transaction(rng);
     tx.execute().unwrap();
 
+    let tx = partial_fulfillment_token_swap::create_residual_token_swap_transaction(rng);
+    tx.execute().unwrap();
+
+    let tx = cyclic_swap::create_three_party_cycle_swap_transaction(rng);
+    tx.execute().unwrap();
+
+    let tx = intent_cancellation::create_intent_cancellation_transaction(rng);
+    tx.execute().unwrap();
+
+    let tx = dutch_auction::create_dutch_auction_transaction(rng, 10);
+    tx.execute_with_context(&dutch_auction::auction_context(10))
+        .unwrap();
+
     let tx = cascaded_partial_transactions::create_transaction(rng);
     tx.execute().unwrap();
+
+    let tx = multisig_token_transfer::create_multisig_transfer_transaction(rng);
+    tx.execute().unwrap();
+
+    let tx = token_issuance::create_token_mint_and_burn_transaction(rng);
+    tx.execute().unwrap();
+
+    let tx = nft::create_nft_mint_and_transfer_transaction(rng);
+    tx.execute().unwrap();
+
+    let tx = compliant_token_transfer::create_compliant_token_transfer_transaction(rng);
+    tx.execute().unwrap();
+
+    let txs = counter::create_counter_transactions(rng);
+    for tx in txs.iter() {
+        tx.execute().unwrap();
+    }
+
+    let txs = spending_cap::create_spending_cap_transactions(rng);
+    for tx in txs.iter() {
+        tx.execute().unwrap();
+    }
+
+    let txs = bridge::create_wrap_and_unwrap_transactions(rng);
+    for tx in txs.iter() {
+        tx.execute().unwrap();
+    }
+
+    let txs = auth_rotation::create_auth_rotation_transactions(rng);
+    for tx in txs.iter() {
+        tx.execute().unwrap();
+    }
+
+    let txs = payment_address::create_payment_to_address_transactions(rng);
+    for tx in txs.iter() {
+        tx.execute().unwrap();
+    }
+
+    let txs = vesting::create_vesting_transactions(rng);
+    for tx in txs.iter() {
+        tx.execute_with_context(&vesting::vesting_context(150))
+            .unwrap();
+    }
+
+    let txs = escrow::create_escrow_transactions(rng);
+    for (tx, height) in txs.iter() {
+        tx.execute_with_context(&escrow::escrow_context(*height))
+            .unwrap();
+    }
 }
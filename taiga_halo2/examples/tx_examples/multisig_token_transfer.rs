@@ -0,0 +1,162 @@
+/// Token transfer from a 2-of-3 multisig
+/// Alice, Bob and Carol share a note of 5 "BTC" protected by a 2-of-3 multisig
+/// Alice and Carol sign to send the 5 "BTC" to Dave
+///
+use group::Group;
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use std::sync::Arc;
+use taiga_halo2::{
+    circuit::vp_examples::{
+        multisig_authorization::{
+            MultisigAuthorization, MultisigAuthorizationValidityPredicateCircuit,
+        },
+        receiver_vp::COMPRESSED_RECEIVER_VK,
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName, TokenValidityPredicateCircuit},
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    keys::{SigningKey, SpendingKey},
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+pub fn create_multisig_transfer_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
+    let generator = pallas::Point::generator();
+    let btc_token = Token::new(TokenName::new("btc").unwrap(), 5);
+
+    // Alice, Bob and Carol set up the 2-of-3 multisig, along with a viewing key they share to
+    // decrypt the notes sent to the multisig
+    let sks = [(); 3].map(|_| pallas::Scalar::random(&mut rng));
+    let pks = sks.map(|sk| generator * sk);
+    let shared_ivk = SpendingKey::random(&mut rng).to_incoming_viewing_key();
+    let multisig = MultisigAuthorization::new(pks, 2, shared_ivk.get_rcv_pk()).unwrap();
+    let input_auth = multisig.to_token_authorization();
+    let input_nk = NullifierKeyContainer::random_key(&mut rng);
+
+    // input note
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let input_note = btc_token.create_random_token_note(&mut rng, rho, input_nk, &input_auth);
+
+    // output note to Dave
+    let dave_auth_sk = pallas::Scalar::random(&mut rng);
+    let dave_auth = TokenAuthorization::from_sk_vk(&dave_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+    let dave_nk = NullifierKeyContainer::random_key(&mut rng);
+    let input_note_nf = input_note.get_nf().unwrap();
+    let output_note = btc_token.create_random_token_note(
+        &mut rng,
+        input_note_nf,
+        dave_nk.to_commitment(),
+        &dave_auth,
+    );
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+    let anchors = [input_note.calculate_root(&merkle_path), anchor];
+
+    // Alice and Carol sign the spend of the input note
+    let partials = [sks[0], sks[2]].map(|sk| {
+        multisig
+            .sign_partial(
+                &mut rng,
+                &SigningKey::new(sk),
+                input_note_nf.inner(),
+                &anchors,
+                &input_notes,
+                &output_notes,
+            )
+            .unwrap()
+    });
+    let signature = multisig.aggregate(&partials).unwrap();
+
+    // Create the input note proving info
+    let input_note_proving_info = {
+        let nf = input_note_nf.inner();
+        let token_vp = TokenValidityPredicateCircuit {
+            owned_note_pub_id: nf,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            token_name: input_note.token_name().clone(),
+            auth: input_auth,
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+            rseed: RandomSeed::random(&mut rng),
+        };
+        let multisig_vp = MultisigAuthorizationValidityPredicateCircuit::new(
+            nf,
+            anchors,
+            input_notes,
+            output_notes,
+            multisig,
+            signature,
+        );
+        InputNoteProvingInfo::new(
+            *input_note.note(),
+            merkle_path.clone(),
+            None,
+            Box::new(token_vp),
+            vec![Box::new(multisig_vp)],
+        )
+    };
+
+    // Create the output note proving info
+    let output_note_proving_info = output_note.generate_output_token_note_proving_info(
+        &mut rng,
+        dave_auth,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    let ptx = ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+
+    // Create the final transaction
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+#[test]
+fn test_multisig_transfer_tx() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let tx = create_multisig_transfer_transaction(&mut rng);
+    tx.execute().unwrap();
+}
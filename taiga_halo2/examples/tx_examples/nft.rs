@@ -0,0 +1,437 @@
+/// The issuer mints NFTs of a collection and the holders transfer them.
+/// Mint: the issuer spends the ephemeral mint note of the item to create the nft note for the receiver.
+/// Transfer: the holder spends the nft notes to create the same nfts for the receiver, one per action.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    circuit::vp_examples::{
+        nft::{Nft, NftCollection},
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::TokenAuthorization,
+    },
+    constant::{NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH},
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+pub fn create_nft_mint_ptx<R: RngCore>(
+    mut rng: R,
+    nft: &Nft,
+    issuer_sk: pallas::Scalar,
+    mint_nk: pallas::Base,
+    receiver_auth_pk: pallas::Point,
+    receiver_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> ShieldedPartialTransaction {
+    // The ephemeral mint note
+    let mint_note = nft.create_mint_note(mint_nk);
+
+    // The minted note to the receiver
+    let receiver_auth =
+        TokenAuthorization::new(receiver_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let output_note = nft.create_random_nft_note(
+        &mut rng,
+        mint_note.get_nf().unwrap(),
+        receiver_nk_com,
+        &receiver_auth,
+    );
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*mint_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the mint note proving info, the issuer signs the ptx
+    let mint_note_proving_info = mint_note.generate_input_nft_note_proving_info(
+        &mut rng,
+        nft.issuer_auth(),
+        &SigningKey::new(issuer_sk),
+        merkle_path.clone(),
+        [mint_note.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the output note proving info
+    let output_note_proving_info = output_note.generate_output_nft_note_proving_info(
+        &mut rng,
+        receiver_auth,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    ShieldedPartialTransaction::build(
+        [mint_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+// Transfer up to NUM_NOTE nfts, the nft i goes from the input note i to the output note i
+pub fn create_nft_transfer_ptx<R: RngCore>(
+    mut rng: R,
+    nfts: &[Nft],
+    sender_auth_sk: pallas::Scalar,
+    sender_nk: NullifierKeyContainer, // NullifierKeyContainer::Key
+    receiver_auth_pk: pallas::Point,
+    receiver_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> ShieldedPartialTransaction {
+    assert!(!nfts.is_empty() && nfts.len() <= NUM_NOTE);
+    let sender_auth = TokenAuthorization::from_sk_vk(&sender_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+    let receiver_auth =
+        TokenAuthorization::new(receiver_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+
+    // The nft notes and the padding notes
+    let input_nft_notes: Vec<_> = nfts
+        .iter()
+        .map(|nft| {
+            let rho = Nullifier::from(pallas::Base::random(&mut rng));
+            nft.create_random_nft_note(&mut rng, rho, sender_nk, &sender_auth)
+        })
+        .collect();
+    let output_nft_notes: Vec<_> = nfts
+        .iter()
+        .zip(input_nft_notes.iter())
+        .map(|(nft, input)| {
+            nft.create_random_nft_note(
+                &mut rng,
+                input.get_nf().unwrap(),
+                receiver_nk_com,
+                &receiver_auth,
+            )
+        })
+        .collect();
+    let padding_input_notes: Vec<_> = (nfts.len()..NUM_NOTE)
+        .map(|_| Note::random_padding_input_note(&mut rng))
+        .collect();
+    let padding_output_notes: Vec<_> = padding_input_notes
+        .iter()
+        .map(|note| Note::random_padding_output_note(&mut rng, note.get_nf().unwrap()))
+        .collect();
+
+    let input_notes: [Note; NUM_NOTE] = input_nft_notes
+        .iter()
+        .map(|nft_note| *nft_note.note())
+        .chain(padding_input_notes.iter().copied())
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap();
+    let output_notes: [Note; NUM_NOTE] = output_nft_notes
+        .iter()
+        .map(|nft_note| *nft_note.note())
+        .chain(padding_output_notes.iter().copied())
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap();
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+    let anchors: [Anchor; NUM_NOTE] = input_nft_notes
+        .iter()
+        .map(|nft_note| nft_note.calculate_root(&merkle_path))
+        .chain(padding_input_notes.iter().map(|_| anchor))
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap();
+
+    // Create the input note proving infos
+    let sender_sk = SigningKey::new(sender_auth_sk);
+    let input_note_proving_infos = std::array::from_fn(|i| match input_nft_notes.get(i) {
+        Some(nft_note) => nft_note.generate_input_nft_note_proving_info(
+            &mut rng,
+            sender_auth,
+            &sender_sk,
+            merkle_path.clone(),
+            anchors,
+            input_notes,
+            output_notes,
+        ),
+        None => InputNoteProvingInfo::create_padding_note_proving_info(
+            input_notes[i],
+            merkle_path.clone(),
+            anchor,
+            input_notes,
+            output_notes,
+        ),
+    });
+
+    // Create the output note proving infos
+    let output_note_proving_infos = std::array::from_fn(|i| match output_nft_notes.get(i) {
+        Some(nft_note) => nft_note.generate_output_nft_note_proving_info(
+            &mut rng,
+            receiver_auth,
+            input_notes,
+            output_notes,
+        ),
+        None => OutputNoteProvingInfo::create_padding_note_proving_info(
+            output_notes[i],
+            input_notes,
+            output_notes,
+        ),
+    });
+
+    // Create shielded partial tx
+    ShieldedPartialTransaction::build(
+        input_note_proving_infos,
+        output_note_proving_infos,
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+pub fn create_nft_mint_and_transfer_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
+    use group::Group;
+
+    let generator = pallas::Point::generator();
+    let issuer_sk = pallas::Scalar::random(&mut rng);
+    let mint_nk = pallas::Base::random(&mut rng);
+    let collection = NftCollection::new(
+        "punks".to_string(),
+        generator * issuer_sk,
+        NullifierKeyContainer::from_key(mint_nk).get_commitment(),
+    );
+    let nfts = [1, 2].map(|id| collection.item(pallas::Base::from(id)));
+
+    // Mint the first item to Alice
+    let alice_auth_sk = pallas::Scalar::random(&mut rng);
+    let alice_nk = NullifierKeyContainer::random_key(&mut rng);
+    let mint_ptx = create_nft_mint_ptx(
+        &mut rng,
+        &nfts[0],
+        issuer_sk,
+        mint_nk,
+        generator * alice_auth_sk,
+        alice_nk.to_commitment(),
+    );
+
+    // Alice sends both items to Bob
+    let bob_nk = NullifierKeyContainer::random_key(&mut rng);
+    let transfer_ptx = create_nft_transfer_ptx(
+        &mut rng,
+        &nfts,
+        alice_auth_sk,
+        alice_nk,
+        pallas::Point::random(&mut rng),
+        bob_nk.to_commitment(),
+    );
+
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![mint_ptx, transfer_ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use group::Group;
+    use rand::rngs::OsRng;
+    use taiga_halo2::circuit::vp_examples::nft::COMPRESSED_NFT_VK;
+
+    fn build_tx(ptx: ShieldedPartialTransaction) -> Transaction {
+        let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+        let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+        Transaction::build(OsRng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+    }
+
+    fn collection(issuer_sk: pallas::Scalar, mint_nk: pallas::Base) -> NftCollection {
+        NftCollection::new(
+            "punks".to_string(),
+            pallas::Point::generator() * issuer_sk,
+            NullifierKeyContainer::from_key(mint_nk).get_commitment(),
+        )
+    }
+
+    #[test]
+    fn test_nft_mint_tx() {
+        let mut rng = OsRng;
+        let issuer_sk = pallas::Scalar::random(&mut rng);
+        let mint_nk = pallas::Base::random(&mut rng);
+        let nft = collection(issuer_sk, mint_nk).item(pallas::Base::from(1));
+        let receiver_nk = NullifierKeyContainer::random_key(&mut rng);
+        let ptx = create_nft_mint_ptx(
+            &mut rng,
+            &nft,
+            issuer_sk,
+            mint_nk,
+            pallas::Point::random(&mut rng),
+            receiver_nk.to_commitment(),
+        );
+        let mint_nf = nft.create_mint_note(mint_nk).get_nf().unwrap();
+        let tx = build_tx(ptx);
+        assert!(tx
+            .get_shielded_ptx_bundle()
+            .get_nullifiers()
+            .contains(&mint_nf));
+        tx.execute().unwrap();
+
+        // Minting the item again reveals the same nullifier
+        let ptx = create_nft_mint_ptx(
+            &mut rng,
+            &nft,
+            issuer_sk,
+            mint_nk,
+            pallas::Point::random(&mut rng),
+            receiver_nk.to_commitment(),
+        );
+        assert!(build_tx(ptx)
+            .get_shielded_ptx_bundle()
+            .get_nullifiers()
+            .contains(&mint_nf));
+    }
+
+    #[test]
+    fn test_nft_mint_tx_with_wrong_key() {
+        let mut rng = OsRng;
+        let issuer_sk = pallas::Scalar::random(&mut rng);
+        let mint_nk = pallas::Base::random(&mut rng);
+        let nft = collection(issuer_sk, mint_nk).item(pallas::Base::from(1));
+        let receiver_nk = NullifierKeyContainer::random_key(&mut rng);
+        let wrong_sk = pallas::Scalar::random(&mut rng);
+        let ptx = create_nft_mint_ptx(
+            &mut rng,
+            &nft,
+            wrong_sk,
+            mint_nk,
+            pallas::Point::random(&mut rng),
+            receiver_nk.to_commitment(),
+        );
+        assert!(build_tx(ptx).execute().is_err());
+    }
+
+    #[test]
+    fn test_nft_transfer_tx() {
+        let mut rng = OsRng;
+        let collection = collection(
+            pallas::Scalar::random(&mut rng),
+            pallas::Base::random(&mut rng),
+        );
+        let sender_nk = NullifierKeyContainer::random_key(&mut rng);
+        let receiver_nk = NullifierKeyContainer::random_key(&mut rng);
+        // One item, and two items of the collection in the same ptx
+        for ids in [vec![1], vec![1, 2]] {
+            let nfts: Vec<_> = ids
+                .into_iter()
+                .map(|id| collection.item(pallas::Base::from(id)))
+                .collect();
+            let ptx = create_nft_transfer_ptx(
+                &mut rng,
+                &nfts,
+                pallas::Scalar::random(&mut rng),
+                sender_nk,
+                pallas::Point::random(&mut rng),
+                receiver_nk.to_commitment(),
+            );
+            build_tx(ptx).execute().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_nft_split_tx() {
+        let mut rng = OsRng;
+        let nft = collection(
+            pallas::Scalar::random(&mut rng),
+            pallas::Base::random(&mut rng),
+        )
+        .item(pallas::Base::from(1));
+        let sender_auth_sk = pallas::Scalar::random(&mut rng);
+        let sender_auth =
+            TokenAuthorization::from_sk_vk(&sender_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+        let receiver_auth = TokenAuthorization::random(&mut rng);
+        let sender_nk = NullifierKeyContainer::random_key(&mut rng);
+        let receiver_nk_com = NullifierKeyContainer::random_commitment(&mut rng);
+
+        // The nft is spent and created twice, the second copy in the action of a padding note
+        let input_note = nft.create_random_nft_note(
+            &mut rng,
+            Nullifier::random(&mut rng),
+            sender_nk,
+            &sender_auth,
+        );
+        let padding_input_note = Note::random_padding_input_note(&mut rng);
+        let output_nft_notes = [input_note.get_nf(), padding_input_note.get_nf()].map(|nf| {
+            nft.create_random_nft_note(&mut rng, nf.unwrap(), receiver_nk_com, &receiver_auth)
+        });
+        assert_eq!(output_nft_notes[1].get_app_vk(), *COMPRESSED_NFT_VK);
+        let input_notes = [*input_note.note(), padding_input_note];
+        let output_notes = output_nft_notes.clone().map(|nft_note| *nft_note.note());
+
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let anchor = Anchor::from(pallas::Base::random(&mut rng));
+        let input_note_proving_info = input_note.generate_input_nft_note_proving_info(
+            &mut rng,
+            sender_auth,
+            &SigningKey::new(sender_auth_sk),
+            merkle_path.clone(),
+            [input_note.calculate_root(&merkle_path), anchor],
+            input_notes,
+            output_notes,
+        );
+        let padding_input_note_proving_info =
+            InputNoteProvingInfo::create_padding_note_proving_info(
+                padding_input_note,
+                merkle_path,
+                anchor,
+                input_notes,
+                output_notes,
+            );
+        let output_note_proving_infos = output_nft_notes.map(|nft_note| {
+            nft_note.generate_output_nft_note_proving_info(
+                &mut rng,
+                receiver_auth,
+                input_notes,
+                output_notes,
+            )
+        });
+        let ptx = ShieldedPartialTransaction::build(
+            [input_note_proving_info, padding_input_note_proving_info],
+            output_note_proving_infos,
+            vec![],
+            &mut rng,
+        )
+        .unwrap();
+        assert!(build_tx(ptx).execute().is_err());
+    }
+
+    #[test]
+    fn test_nft_mint_and_transfer_tx() {
+        let tx = create_nft_mint_and_transfer_transaction(OsRng);
+        tx.execute().unwrap();
+    }
+}
@@ -1,24 +1,40 @@
 /// The token swap intent can be partially fulfilled.
-/// Alice has 2 "BTC" and wants 10 "ETH". Then Alice creates an intent for it.
-/// Bob has 5 "ETH" and wants 1 "BTC".
-/// The Solver/Bob can partially fulfill Alice's intent and return 1 "BTC" back to Alice.
+/// Alice has 2 "BTC" and wants either 10 "ETH" or 8000 "XAN", and lets the solver keep up to 25 bps
+/// of a fill. Then Alice creates an intent for it with the note she received earlier.
+/// Bob has 4000 "XAN" and wants 1 "BTC".
+/// The Solver/Bob can partially fulfill the second ask of Alice's intent, keep the 10 "XAN" fee
+/// and return 1 "BTC" back to Alice.
+/// In the residual swap Alice sells 2 "BTC" for 10 "ETH" or 500 "XAN". Bob can keep the 1 "BTC"
+/// left for sale in a residual intent for 5 "ETH" or 250 "XAN", and another solver fills the
+/// residual with Carol's 5 "ETH".
 ///
 use crate::token::create_token_swap_ptx;
 use group::Group;
 use halo2_proofs::arithmetic::Field;
-use pasta_curves::{group::Curve, pallas};
-use rand::{CryptoRng, RngCore};
+use pasta_curves::{
+    group::{ff::PrimeField, Curve},
+    pallas,
+};
+use rand::{CryptoRng, Rng, RngCore};
+use std::sync::Arc;
 use taiga_halo2::{
     circuit::vp_examples::{
-        partial_fulfillment_intent::{PartialFulfillmentIntentValidityPredicateCircuit, Swap},
+        partial_fulfillment_intent::{
+            PartialFulfillmentIntentValidityPredicateCircuit, ResidualFill, Swap, SwapBuilder,
+            SwapError,
+        },
+        receiver_vp::{ReceiverValidityPredicateCircuit, COMPRESSED_RECEIVER_VK},
         signature_verification::COMPRESSED_TOKEN_AUTH_VK,
-        token::{Token, TokenAuthorization, TokenNote},
+        token::{Token, TokenAuthorization, TokenName, TokenNote},
     },
-    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    constant::{NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH},
+    keys::{SigningKey, SpendingKey},
     merkle_tree::{Anchor, MerklePath},
-    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
-    nullifier::NullifierKeyContainer,
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+    note_encryption::NoteKind,
+    nullifier::{Nullifier, NullifierKeyContainer},
     shielded_ptx::ShieldedPartialTransaction,
+    taiga_api::retrieve_owned_notes,
     transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
 };
 
@@ -27,10 +43,38 @@ pub fn create_token_intent_ptx<R: RngCore>(
     sell: Token,
     buy: Token,
     input_auth_sk: pallas::Scalar,
-) -> (ShieldedPartialTransaction, Swap) {
+) -> Result<(ShieldedPartialTransaction, Swap, Note), SwapError> {
     let input_auth = TokenAuthorization::from_sk_vk(&input_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
-    let swap = Swap::random(&mut rng, sell, buy, input_auth);
-    let intent_note = swap.create_intent_note(&mut rng);
+    let rho = Nullifier::random(&mut rng);
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+    let sell_note = sell.create_random_token_note(&mut rng, rho, nk, &input_auth);
+    create_token_intent_ptx_with_note(
+        &mut rng,
+        sell_note,
+        vec![buy],
+        0,
+        input_auth,
+        &SigningKey::new(input_auth_sk),
+    )
+}
+
+// The sold note is an existing note of the creator, with the nullifier key. The intent can be
+// filled with any one of the asks, and the solver can keep up to max_solver_fee_bps of a fill.
+// Fails if the swap can't be filled, see `Swap::validate`.
+pub fn create_token_intent_ptx_with_note<R: RngCore>(
+    mut rng: R,
+    sell: TokenNote,
+    asks: Vec<Token>,
+    max_solver_fee_bps: u64,
+    input_auth: TokenAuthorization,
+    input_auth_sk: &SigningKey,
+) -> Result<(ShieldedPartialTransaction, Swap, Note), SwapError> {
+    let mut builder = SwapBuilder::new(sell, asks[0].clone(), input_auth)
+        .with_max_solver_fee_bps(max_solver_fee_bps);
+    for ask in asks.into_iter().skip(1) {
+        builder = builder.or_buy(ask);
+    }
+    let (swap, intent_note) = builder.build(RandomSeed::random(&mut rng))?;
 
     // padding the zero notes
     let padding_input_note = Note::random_padding_input_note(&mut rng);
@@ -51,20 +95,42 @@ pub fn create_token_intent_ptx<R: RngCore>(
         input_auth,
         input_auth_sk,
         merkle_path.clone(),
+        [swap.sell.calculate_root(&merkle_path), anchor],
         input_notes,
         output_notes,
     );
 
     // Create the intent note proving info
     let intent_note_proving_info = {
-        let intent_vp = PartialFulfillmentIntentValidityPredicateCircuit {
-            owned_note_pub_id: intent_note.commitment().inner(),
+        let intent_vp = PartialFulfillmentIntentValidityPredicateCircuit::new(
+            intent_note.commitment().inner(),
             input_notes,
             output_notes,
-            swap: swap.clone(),
+            swap.clone(),
+        )?;
+
+        // The intent note is encrypted to the creator so that the pending intent can be recovered
+        let receiver_vp = ReceiverValidityPredicateCircuit {
+            owned_note_pub_id: intent_note.commitment().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            vp_vk: *COMPRESSED_RECEIVER_VK,
+            nonce: pallas::Base::from_u128(rng.gen()),
+            esk: pallas::Base::random(&mut rng),
+            rcv_pk: input_auth.rcv_pk,
+            auth_pk: input_auth.pk,
+            auth_vp_vk: *COMPRESSED_TOKEN_AUTH_VK,
+            note_kind: NoteKind::SelfTransfer.to_base(),
         };
 
-        OutputNoteProvingInfo::new(intent_note, Box::new(intent_vp), vec![])
+        let note_encryption = receiver_vp.note_encryption();
+        let mut proving_info = OutputNoteProvingInfo::new(
+            intent_note,
+            Box::new(intent_vp),
+            vec![Box::new(receiver_vp)],
+        );
+        proving_info.set_note_encryption(note_encryption);
+        proving_info
     };
 
     // Create the padding input note proving info
@@ -84,30 +150,48 @@ pub fn create_token_intent_ptx<R: RngCore>(
     );
 
     // Create shielded partial tx
-    let ptx = ShieldedPartialTransaction::build(
+    let mut ptx = ShieldedPartialTransaction::build(
         [input_note_proving_info, padding_input_note_proving_info],
         [intent_note_proving_info, padding_output_note_proving_info],
         vec![],
         &mut rng,
-    );
+    )
+    .unwrap();
+    // The swap terms are published with the intent note, see `Swap::from_decrypted_intent_note`
+    ptx.set_extra_data(swap.encrypt_terms(&mut rng).to_bytes());
 
-    (ptx, swap)
+    Ok((ptx, swap, intent_note))
 }
 
-#[allow(clippy::too_many_arguments)]
 pub fn consume_token_intent_ptx<R: RngCore>(
+    rng: R,
+    swap: Swap,
+    intent_note: Note,
+    offer: Token,
+    output_auth_pk: pallas::Point,
+) -> ShieldedPartialTransaction {
+    consume_token_intent_ptx_with_solver_fee(rng, swap, intent_note, offer, 0, output_auth_pk)
+}
+
+// Fills the intent with the offer, the solver keeps solver_fee of it and Alice gets the rest. The
+// fee is not in the ptx, it's left to the solver when balancing the transaction.
+pub fn consume_token_intent_ptx_with_solver_fee<R: RngCore>(
     mut rng: R,
     swap: Swap,
+    intent_note: Note,
     offer: Token,
+    solver_fee: u64,
     output_auth_pk: pallas::Point,
 ) -> ShieldedPartialTransaction {
-    let intent_note = swap.create_intent_note(&mut rng);
-    let (input_notes, output_notes) = swap.fill(&mut rng, intent_note, offer);
+    let ask = swap.ask(offer.name()).unwrap().clone();
+    let (input_notes, output_notes) = swap
+        .fill_with_solver_fee(&mut rng, intent_note, offer.clone(), solver_fee)
+        .unwrap();
     let [intent_note, padding_input_note] = input_notes;
     let [bought_note, returned_note] = output_notes;
 
     // output notes
-    let output_auth = TokenAuthorization::new(output_auth_pk, *COMPRESSED_TOKEN_AUTH_VK);
+    let output_auth = TokenAuthorization::new(output_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
     let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
 
     // Fetch a valid anchor for dummy notes
@@ -115,12 +199,13 @@ pub fn consume_token_intent_ptx<R: RngCore>(
 
     // Create the intent note proving info
     let intent_note_proving_info = {
-        let intent_vp = PartialFulfillmentIntentValidityPredicateCircuit {
-            owned_note_pub_id: intent_note.get_nf().unwrap().inner(),
+        let intent_vp = PartialFulfillmentIntentValidityPredicateCircuit::new(
+            intent_note.get_nf().unwrap().inner(),
             input_notes,
             output_notes,
-            swap: swap.clone(),
-        };
+            swap.clone(),
+        )
+        .unwrap();
 
         InputNoteProvingInfo::new(
             intent_note,
@@ -133,7 +218,7 @@ pub fn consume_token_intent_ptx<R: RngCore>(
 
     // Create the output note proving info
     let bought_note_proving_info = TokenNote {
-        token_name: swap.buy.name().clone(),
+        token_name: ask.name().clone(),
         note: bought_note,
     }
     .generate_output_token_note_proving_info(&mut rng, output_auth, input_notes, output_notes);
@@ -148,11 +233,25 @@ pub fn consume_token_intent_ptx<R: RngCore>(
     );
 
     // Create the returned note proving info
-    let returned_note_proving_info = TokenNote {
-        token_name: swap.sell.token_name().clone(),
-        note: returned_note,
-    }
-    .generate_output_token_note_proving_info(&mut rng, output_auth, input_notes, output_notes);
+    // The returned note is a padding note if the intent is fully filled
+    let returned_note_proving_info = if offer.value() < ask.value() {
+        TokenNote {
+            token_name: swap.sell.token_name().clone(),
+            note: returned_note,
+        }
+        .generate_output_token_note_proving_info(
+            &mut rng,
+            output_auth,
+            input_notes,
+            output_notes,
+        )
+    } else {
+        OutputNoteProvingInfo::create_padding_note_proving_info(
+            returned_note,
+            input_notes,
+            output_notes,
+        )
+    };
 
     // Create shielded partial tx
     ShieldedPartialTransaction::build(
@@ -161,31 +260,164 @@ pub fn consume_token_intent_ptx<R: RngCore>(
         vec![],
         &mut rng,
     )
+    .unwrap()
+}
+
+// Partially fills the intent, the rest of the swap is kept open in a residual intent note.
+// Returns the ptx along with the residual swap and its intent note.
+pub fn consume_token_intent_ptx_with_residual<R: RngCore>(
+    mut rng: R,
+    swap: Swap,
+    intent_note: Note,
+    offer: Token,
+    output_auth_pk: pallas::Point,
+) -> (ShieldedPartialTransaction, Swap, Note) {
+    let ask = swap.ask(offer.name()).unwrap().clone();
+    let (residual_fill, input_notes, output_notes) =
+        swap.fill_with_residual(&mut rng, intent_note, offer);
+    let [intent_note, padding_input_note] = input_notes;
+    let [bought_note, residual_intent_note] = output_notes;
+
+    let output_auth = TokenAuthorization::new(output_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for dummy notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Both intent vps check the residual terms
+    let intent_vp = |owned_note_pub_id: pallas::Base, swap: Swap| {
+        PartialFulfillmentIntentValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            swap,
+            cancel_signature: None,
+            anchors: [Anchor::default(); NUM_NOTE],
+            residual_fill: Some(residual_fill.clone()),
+        }
+    };
+    let ResidualFill { parent, residual } = residual_fill.clone();
+
+    // Create the intent note proving info
+    let intent_note_proving_info = InputNoteProvingInfo::new(
+        intent_note,
+        merkle_path.clone(),
+        Some(anchor),
+        Box::new(intent_vp(intent_note.get_nf().unwrap().inner(), parent)),
+        vec![],
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the bought note proving info
+    let bought_note_proving_info = TokenNote {
+        token_name: ask.name().clone(),
+        note: bought_note,
+    }
+    .generate_output_token_note_proving_info(&mut rng, output_auth, input_notes, output_notes);
+
+    // Create the residual intent note proving info
+    let residual_intent_note_proving_info = OutputNoteProvingInfo::new(
+        residual_intent_note,
+        Box::new(intent_vp(
+            residual_intent_note.commitment().inner(),
+            residual.clone(),
+        )),
+        vec![],
+    );
+
+    // Create shielded partial tx
+    let ptx = ShieldedPartialTransaction::build(
+        [intent_note_proving_info, padding_input_note_proving_info],
+        [bought_note_proving_info, residual_intent_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+
+    (ptx, residual, residual_intent_note)
 }
 
 pub fn create_token_swap_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
     let generator = pallas::Point::generator().to_affine();
 
-    // Alice creates the partial transaction with:
-    // - 2 BTC sell
-    // - intent output encoding 10 ETH ask
-    let alice_auth_sk = pallas::Scalar::random(&mut rng);
+    // Alice's auth key is also the key to decrypt her notes
+    let alice_rcv_sk = pallas::Base::random(&mut rng);
+    let alice_auth_sk = pallas::Scalar::from_repr(alice_rcv_sk.to_repr()).unwrap();
     let alice_auth_pk = generator * alice_auth_sk;
-    let sell = Token::new("btc".to_string(), 2u64);
-    let buy = Token::new("eth".to_string(), 10u64);
-    let (alice_ptx, swap) =
-        create_token_intent_ptx(&mut rng, sell.clone(), buy.clone(), alice_auth_sk);
+    let alice_nk = NullifierKeyContainer::random_key(&mut rng);
+
+    // Alice receives 2 BTC
+    let sell = Token::new(TokenName::new("btc").unwrap(), 2u64);
+    let funding_ptx = create_token_swap_ptx(
+        &mut rng,
+        sell.clone(),
+        alice_auth_sk,
+        alice_nk,
+        sell.clone(),
+        alice_auth_pk,
+        alice_nk.to_commitment(),
+    );
+    let funding_tx = Transaction::build(
+        &mut rng,
+        ShieldedPartialTxBundle::new(vec![funding_ptx]),
+        TransparentPartialTxBundle::default(),
+    )
+    .unwrap();
+    funding_tx.execute().unwrap();
 
-    // Bob creates the partial transaction with 1 DOLPHIN input and 5 BTC output
+    // Alice finds the BTC note in the transaction
+    let alice_sk = SpendingKey::new(alice_auth_sk, alice_nk.get_nk().unwrap(), alice_rcv_sk);
+    let received_note = retrieve_owned_notes(&alice_sk.to_incoming_viewing_key(), &funding_tx)
+        .into_iter()
+        .find(|note| note.get_app_data_static() == sell.encode_name())
+        .unwrap();
+    let sell_note = TokenNote {
+        token_name: sell.name().clone(),
+        note: Note {
+            nk_container: alice_sk.get_nk(),
+            ..received_note
+        },
+    };
+
+    // Alice creates the partial transaction with:
+    // - the 2 BTC note sell
+    // - intent output encoding the 10 ETH or 8000 XAN asks, and the 25 bps max solver fee
+    let asks = vec![
+        Token::new(TokenName::new("eth").unwrap(), 10u64),
+        Token::new(TokenName::new("xan").unwrap(), 8000u64),
+    ];
+    let (alice_ptx, swap, intent_note) = create_token_intent_ptx_with_note(
+        &mut rng,
+        sell_note,
+        asks,
+        25,
+        TokenAuthorization::from_sk_vk(&alice_auth_sk, &COMPRESSED_TOKEN_AUTH_VK),
+        &SigningKey::new(alice_auth_sk),
+    )
+    .unwrap();
+
+    // 1 BTC is worth 4000 XAN at Alice's price, the solver can keep 10 XAN of it
+    let offer = Token::new(TokenName::new("xan").unwrap(), 4000);
+    let solver_fee = swap.max_solver_fee(offer.value());
+    assert_eq!(solver_fee, 10);
+
+    // Bob creates the partial transaction with 3990 XAN input and 1 BTC output, he keeps the fee
     let bob_auth_sk = pallas::Scalar::random(&mut rng);
     let bob_auth_pk = generator * bob_auth_sk;
     let bob_nk = NullifierKeyContainer::random_key(&mut rng);
-    let offer = Token::new("eth".to_string(), 5);
-    let returned = Token::new("btc".to_string(), 1);
+    let returned = Token::new(TokenName::new("btc").unwrap(), 1);
 
     let bob_ptx = create_token_swap_ptx(
         &mut rng,
-        offer.clone(),
+        Token::new(TokenName::new("xan").unwrap(), offer.value() - solver_fee),
         bob_auth_sk,
         bob_nk,
         returned,
@@ -193,14 +425,116 @@ pub fn create_token_swap_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Tran
         bob_nk.to_commitment(),
     );
 
-    // Solver/Bob creates the partial transaction to consume the intent note
+    // Solver/Bob creates the partial transaction to consume the intent note, filling the XAN ask
     // The bob_ptx and solver_ptx can be merged to one ptx.
-    let solver_ptx = consume_token_intent_ptx(&mut rng, swap, offer, alice_auth_pk);
+    let solver_ptx = consume_token_intent_ptx_with_solver_fee(
+        &mut rng,
+        swap,
+        intent_note,
+        offer,
+        solver_fee,
+        alice_auth_pk,
+    );
 
     // Solver creates the final transaction
     let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![alice_ptx, bob_ptx, solver_ptx]);
     let transparent_ptx_bundle = TransparentPartialTxBundle::default();
-    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle)
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+// Bob buys 1 BTC and keeps the other one for sale in a residual intent, which is filled by Carol.
+// The intent notes are ephemeral and the residual intent holds the unsold BTC, so the residual
+// fill is settled in the same transaction.
+pub fn create_residual_token_swap_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
+    let generator = pallas::Point::generator().to_affine();
+
+    // Alice sells 2 BTC for 10 ETH or 500 XAN
+    let alice_auth_sk = pallas::Scalar::random(&mut rng);
+    let alice_auth_pk = generator * alice_auth_sk;
+    let input_auth = TokenAuthorization::from_sk_vk(&alice_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+    let rho = Nullifier::random(&mut rng);
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+    let sell_note = Token::new(TokenName::new("btc").unwrap(), 2u64).create_random_token_note(
+        &mut rng,
+        rho,
+        nk,
+        &input_auth,
+    );
+    let asks = vec![
+        Token::new(TokenName::new("eth").unwrap(), 10u64),
+        Token::new(TokenName::new("xan").unwrap(), 500u64),
+    ];
+    let (alice_ptx, swap, intent_note) = create_token_intent_ptx_with_note(
+        &mut rng,
+        sell_note,
+        asks,
+        0,
+        input_auth,
+        &SigningKey::new(alice_auth_sk),
+    )
+    .unwrap();
+
+    // Bob buys 1 BTC with 250 XAN, and the solver keeps the other BTC for sale
+    let bob_auth_sk = pallas::Scalar::random(&mut rng);
+    let bob_nk = NullifierKeyContainer::random_key(&mut rng);
+    let bob_offer = Token::new(TokenName::new("xan").unwrap(), 250);
+    let bob_ptx = create_token_swap_ptx(
+        &mut rng,
+        bob_offer.clone(),
+        bob_auth_sk,
+        bob_nk,
+        Token::new(TokenName::new("btc").unwrap(), 1),
+        generator * bob_auth_sk,
+        bob_nk.to_commitment(),
+    );
+    let (first_solver_ptx, residual, residual_intent_note) = consume_token_intent_ptx_with_residual(
+        &mut rng,
+        swap,
+        intent_note,
+        bob_offer,
+        alice_auth_pk,
+    );
+
+    // Another solver fills the residual: Carol buys the last BTC with 5 ETH
+    let carol_auth_sk = pallas::Scalar::random(&mut rng);
+    let carol_nk = NullifierKeyContainer::random_key(&mut rng);
+    // The first ask of the residual is 5 ETH
+    let carol_offer = residual.asks()[0].clone();
+    let carol_ptx = create_token_swap_ptx(
+        &mut rng,
+        carol_offer.clone(),
+        carol_auth_sk,
+        carol_nk,
+        Token::new(TokenName::new("btc").unwrap(), 1),
+        generator * carol_auth_sk,
+        carol_nk.to_commitment(),
+    );
+    let second_solver_ptx = consume_token_intent_ptx(
+        &mut rng,
+        residual,
+        residual_intent_note,
+        carol_offer,
+        alice_auth_pk,
+    );
+
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![
+        alice_ptx,
+        bob_ptx,
+        first_solver_ptx,
+        carol_ptx,
+        second_solver_ptx,
+    ]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+#[test]
+fn test_residual_token_swap_tx() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let tx = create_residual_token_swap_transaction(&mut rng);
+    tx.execute().unwrap();
 }
 
 #[test]
@@ -211,3 +545,75 @@ fn test_partial_fulfillment_token_swap_tx() {
     let tx = create_token_swap_transaction(&mut rng);
     tx.execute().unwrap();
 }
+
+#[test]
+fn test_recover_intent_from_ciphertext() {
+    use rand::rngs::OsRng;
+    use taiga_halo2::circuit::vp_examples::partial_fulfillment_intent::EncryptedSwapTerms;
+
+    let mut rng = OsRng;
+    let alice_sk = SpendingKey::random(&mut rng);
+    let alice_ivk = alice_sk.to_incoming_viewing_key();
+    let alice_auth =
+        TokenAuthorization::from_address(&alice_ivk.to_payment_address(*COMPRESSED_RECEIVER_VK));
+    let sell_note = Token::new(TokenName::new("btc").unwrap(), 2u64).create_random_token_note(
+        &mut rng,
+        Nullifier::random(&mut rng),
+        alice_sk.get_nk(),
+        &alice_auth,
+    );
+    let asks = vec![Token::new(TokenName::new("eth").unwrap(), 10u64)];
+    let (alice_ptx, _, _) = create_token_intent_ptx_with_note(
+        &mut rng,
+        sell_note,
+        asks,
+        0,
+        alice_auth,
+        &alice_sk.get_auth_sk(),
+    )
+    .unwrap();
+
+    // Alice loses the swap and finds the intent note and its terms again in the published ptx
+    let decrypted_note = alice_ptx
+        .get_output_vp_public_inputs()
+        .iter()
+        .find_map(|public_inputs| alice_ivk.decrypt_note(public_inputs).ok())
+        .unwrap()
+        .note;
+    let terms = EncryptedSwapTerms::from_bytes(&alice_ptx.get_extra_data())
+        .unwrap()
+        .decrypt(&alice_ivk)
+        .unwrap();
+    // The intent note is not checked in the merkle tree, and it's spent with Alice's nk
+    let intent_note = Note {
+        nk_container: alice_sk.get_nk(),
+        is_merkle_checked: false,
+        ..decrypted_note
+    };
+    let recovered_swap = Swap::from_decrypted_intent_note(&intent_note, &terms).unwrap();
+
+    // Alice reclaims the sold BTC with the recovered swap
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+    let cancel_ptx = recovered_swap
+        .create_cancel_ptx(
+            &mut rng,
+            intent_note,
+            merkle_path,
+            anchor,
+            &alice_sk.get_auth_sk(),
+        )
+        .unwrap();
+    let tx = Transaction::build(
+        &mut rng,
+        ShieldedPartialTxBundle::new(vec![alice_ptx, cancel_ptx]),
+        TransparentPartialTxBundle::default(),
+    )
+    .unwrap();
+    tx.execute().unwrap();
+
+    let btc = Token::new(TokenName::new("btc").unwrap(), 0).encode_name();
+    assert!(retrieve_owned_notes(&alice_ivk, &tx)
+        .iter()
+        .any(|note| note.get_app_data_static() == btc && note.value == 2));
+}
@@ -0,0 +1,267 @@
+/// Send a token to a payment address string.
+/// Alice only knows Bob's address, e.g. `taiga1...`, and builds the transfer from it. Bob finds the
+/// note with his incoming viewing key and spends it to Carol's address.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    address::PaymentAddress,
+    circuit::vp_examples::{
+        receiver_vp::COMPRESSED_RECEIVER_VK,
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName, TokenNote},
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    error::PaymentAddressError,
+    keys::{SigningKey, SpendingKey},
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    taiga_api::retrieve_owned_notes,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+// Spend the input note of the input_auth with the auth_sk and send the token to the address
+pub fn create_transfer_to_address_ptx<R: RngCore>(
+    mut rng: R,
+    input_note: &TokenNote,
+    input_auth: TokenAuthorization,
+    auth_sk: &SigningKey,
+    token: &Token,
+    addr: &str,
+) -> Result<ShieldedPartialTransaction, PaymentAddressError> {
+    let addr: PaymentAddress = addr.parse()?;
+
+    // output note
+    let output_note = token.create_note_to_address(&mut rng, &addr, input_note.get_nf().unwrap());
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the input note proving info
+    let input_note_proving_info = input_note.generate_input_token_note_proving_info(
+        &mut rng,
+        input_auth,
+        auth_sk,
+        merkle_path.clone(),
+        [input_note.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the output note proving info
+    let output_note_proving_info = output_note.generate_output_token_note_proving_info_to_address(
+        &mut rng,
+        &addr,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    Ok(ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap())
+}
+
+fn build_transaction<R: RngCore>(rng: R, ptx: ShieldedPartialTransaction) -> Transaction {
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+// The spending key of a user receiving at a payment address
+fn random_receiver<R: RngCore>(mut rng: R) -> SpendingKey {
+    let mut root = [0u8; 32];
+    rng.fill_bytes(&mut root);
+    SpendingKey::from_root(&root)
+}
+
+// The authorization of the notes received at the payment address of the key
+fn receiver_auth(sk: &SpendingKey) -> TokenAuthorization {
+    TokenAuthorization::from_address(
+        &sk.to_incoming_viewing_key()
+            .to_payment_address(*COMPRESSED_RECEIVER_VK),
+    )
+}
+
+// The receiver finds the token note in the transaction, with the nk to spend it
+fn find_received_note(sk: &SpendingKey, token: &Token, tx: &Transaction) -> Option<TokenNote> {
+    let received_note = retrieve_owned_notes(&sk.to_incoming_viewing_key(), tx)
+        .into_iter()
+        .find(|note| note.get_app_data_static() == token.encode_name())?;
+    Some(TokenNote {
+        token_name: token.name().clone(),
+        note: Note {
+            nk_container: sk.get_nk(),
+            ..received_note
+        },
+    })
+}
+
+// Alice sends 5 XAN to Bob's address, then Bob spends them to Carol's address
+pub fn create_payment_to_address_transactions<R: RngCore + CryptoRng>(
+    mut rng: R,
+) -> Vec<Transaction> {
+    let token = Token::new(TokenName::new("xan").unwrap(), 5);
+    let alice_auth_sk = SigningKey::random(&mut rng);
+    let alice_auth =
+        TokenAuthorization::new(alice_auth_sk.public_key(), *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let alice_nk = NullifierKeyContainer::random_key(&mut rng);
+    let alice_note = token.create_random_token_note(&mut rng, rho, alice_nk, &alice_auth);
+
+    let bob_sk = random_receiver(&mut rng);
+    let bob_addr = bob_sk
+        .to_incoming_viewing_key()
+        .to_payment_address(*COMPRESSED_RECEIVER_VK)
+        .to_string();
+    let alice_ptx = create_transfer_to_address_ptx(
+        &mut rng,
+        &alice_note,
+        alice_auth,
+        &alice_auth_sk,
+        &token,
+        &bob_addr,
+    )
+    .unwrap();
+    let alice_tx = build_transaction(&mut rng, alice_ptx);
+
+    let bob_note = find_received_note(&bob_sk, &token, &alice_tx).unwrap();
+    let carol_sk = random_receiver(&mut rng);
+    let carol_addr = carol_sk
+        .to_incoming_viewing_key()
+        .to_payment_address(*COMPRESSED_RECEIVER_VK)
+        .to_string();
+    let bob_ptx = create_transfer_to_address_ptx(
+        &mut rng,
+        &bob_note,
+        receiver_auth(&bob_sk),
+        &bob_sk.get_auth_sk(),
+        &token,
+        &carol_addr,
+    )
+    .unwrap();
+    let bob_tx = build_transaction(&mut rng, bob_ptx);
+    vec![alice_tx, bob_tx]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+    use taiga_halo2::taiga_api::check_spent;
+
+    #[test]
+    fn test_payment_to_address_txs() {
+        for tx in create_payment_to_address_transactions(OsRng) {
+            tx.execute().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_payment_to_address_receive_and_spend() {
+        let mut rng = OsRng;
+        let token = Token::new(TokenName::new("xan").unwrap(), 5);
+        let alice_auth_sk = SigningKey::random(&mut rng);
+        let alice_auth =
+            TokenAuthorization::new(alice_auth_sk.public_key(), *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+        let rho = Nullifier::from(pallas::Base::random(&mut rng));
+        let alice_nk = NullifierKeyContainer::random_key(&mut rng);
+        let alice_note = token.create_random_token_note(&mut rng, rho, alice_nk, &alice_auth);
+
+        // Only the address string is passed around
+        let bob_sk = random_receiver(&mut rng);
+        let bob_addr = bob_sk
+            .to_incoming_viewing_key()
+            .to_payment_address(*COMPRESSED_RECEIVER_VK)
+            .to_string();
+        let alice_ptx = create_transfer_to_address_ptx(
+            &mut rng,
+            &alice_note,
+            alice_auth,
+            &alice_auth_sk,
+            &token,
+            &bob_addr,
+        )
+        .unwrap();
+        let alice_tx = build_transaction(&mut rng, alice_ptx);
+        alice_tx.execute().unwrap();
+
+        // Bob decrypts the note, another key doesn't
+        let bob_note = find_received_note(&bob_sk, &token, &alice_tx).unwrap();
+        assert_eq!(bob_note.value, 5);
+        assert!(find_received_note(&random_receiver(&mut rng), &token, &alice_tx).is_none());
+
+        // Bob spends the note
+        let carol_addr = random_receiver(&mut rng)
+            .to_incoming_viewing_key()
+            .to_payment_address(*COMPRESSED_RECEIVER_VK)
+            .to_string();
+        let bob_ptx = create_transfer_to_address_ptx(
+            &mut rng,
+            &bob_note,
+            receiver_auth(&bob_sk),
+            &bob_sk.get_auth_sk(),
+            &token,
+            &carol_addr,
+        )
+        .unwrap();
+        let bob_tx = build_transaction(&mut rng, bob_ptx);
+        bob_tx.execute().unwrap();
+        assert!(check_spent(
+            &bob_sk.to_full_viewing_key(),
+            bob_note.note(),
+            &bob_tx
+        ));
+
+        // A typo in the address is caught before anything is proven
+        let mut typo = bob_addr.into_bytes();
+        let last = typo.len() - 1;
+        typo[last] = if typo[last] == b'q' { b'p' } else { b'q' };
+        assert_eq!(
+            create_transfer_to_address_ptx(
+                &mut rng,
+                &bob_note,
+                receiver_auth(&bob_sk),
+                &bob_sk.get_auth_sk(),
+                &token,
+                &String::from_utf8(typo).unwrap(),
+            )
+            .err(),
+            Some(PaymentAddressError::InvalidChecksum)
+        );
+    }
+}
@@ -0,0 +1,277 @@
+/// The spending cap flow
+/// Alice caps her "btc" spending at 50 per epoch and pays 20 "btc" to Bob in each transaction.
+/// In epoch 1, the master key opens the budget from the genesis note with the first payment, and
+/// the spend key signs the second payment. The third payment exceeds the remaining 10. In epoch 2,
+/// the master key refills the budget with the third payment.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use std::sync::Arc;
+use taiga_halo2::{
+    circuit::{
+        vp_circuit::ValidityPredicateCircuit,
+        vp_examples::{
+            receiver_vp::COMPRESSED_RECEIVER_VK,
+            signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+            spending_cap::{
+                BudgetState, SpendingCap, SpendingCapValidityPredicateCircuit,
+                SPENDING_CAP_EPOCH_PUBLIC_INPUT_IDX,
+            },
+            token::{Token, TokenAuthorization, TokenName, TokenValidityPredicateCircuit},
+        },
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    keys::SigningKey,
+    merkle_tree::MerklePath,
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+const CAP: u64 = 50;
+const PAYMENT: u64 = 20;
+
+// Pay PAYMENT "btc" to Bob with the budget note in the epoch, signed by the sk.
+// Returns the ptx and the new budget note.
+#[allow(clippy::too_many_arguments)]
+pub fn create_spending_cap_ptx<R: RngCore>(
+    mut rng: R,
+    account: SpendingCap,
+    sk: &SigningKey,
+    nk: NullifierKeyContainer, // NullifierKeyContainer::Key
+    budget_note: Note,
+    budget_state: BudgetState,
+    epoch: u64,
+    bob_auth: TokenAuthorization,
+    bob_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> (ShieldedPartialTransaction, Note) {
+    let output_state = budget_state.next(account.cap, epoch, PAYMENT).unwrap();
+
+    // Alice's token note under the cap
+    let token = Token::new(TokenName::new("btc").unwrap(), PAYMENT);
+    let auth = account.token_authorization();
+    let input_token_note =
+        token.create_random_token_note(&mut rng, Nullifier::random(&mut rng), nk, &auth);
+
+    // The payment to Bob and the new budget note
+    let output_token_note = token.create_random_token_note(
+        &mut rng,
+        input_token_note.get_nf().unwrap(),
+        bob_nk_com,
+        &bob_auth,
+    );
+    let output_budget_note =
+        account.create_budget_note(&mut rng, output_state, budget_note.get_nf().unwrap(), nk);
+
+    let input_notes = [*input_token_note.note(), budget_note];
+    let output_notes = [*output_token_note.note(), output_budget_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    let anchors = input_notes.map(|note| note.calculate_root(&merkle_path));
+    let spending_cap_vp = |rng: &mut R, owned_note_pub_id: pallas::Base| {
+        SpendingCapValidityPredicateCircuit::from_sk_and_sign(
+            rng,
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            account,
+            budget_state,
+            epoch,
+            sk,
+        )
+    };
+
+    // Create the input token note proving info, the spending cap vp is the auth dynamic vp
+    let input_token_note_proving_info = {
+        let nf = input_token_note.get_nf().unwrap().inner();
+        let token_vp = TokenValidityPredicateCircuit {
+            owned_note_pub_id: nf,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            token_name: token.name().clone(),
+            auth,
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+            rseed: RandomSeed::random(&mut rng),
+        };
+        InputNoteProvingInfo::new(
+            input_notes[0],
+            merkle_path.clone(),
+            None,
+            Box::new(token_vp),
+            vec![Box::new(spending_cap_vp(&mut rng, nf))],
+        )
+    };
+
+    // Create the input budget note proving info
+    let input_budget_note_proving_info = {
+        let nf = budget_note.get_nf().unwrap().inner();
+        InputNoteProvingInfo::new(
+            budget_note,
+            merkle_path,
+            None,
+            Box::new(spending_cap_vp(&mut rng, nf)),
+            vec![],
+        )
+    };
+
+    // Create the output token note proving info
+    let output_token_note_proving_info = output_token_note.generate_output_token_note_proving_info(
+        &mut rng,
+        bob_auth,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the output budget note proving info
+    let output_budget_note_proving_info = {
+        let cm = output_budget_note.commitment().inner();
+        OutputNoteProvingInfo::new(
+            output_budget_note,
+            Box::new(spending_cap_vp(&mut rng, cm)),
+            vec![],
+        )
+    };
+
+    // Create shielded partial tx
+    let ptx = ShieldedPartialTransaction::build(
+        [
+            input_token_note_proving_info,
+            input_budget_note_proving_info,
+        ],
+        [
+            output_token_note_proving_info,
+            output_budget_note_proving_info,
+        ],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+
+    // The validator checks the epoch published by the budget vp, it's the last output vp
+    let public_inputs = ptx.get_output_vp_public_inputs();
+    assert_eq!(
+        public_inputs
+            .last()
+            .unwrap()
+            .get_from_index(SPENDING_CAP_EPOCH_PUBLIC_INPUT_IDX),
+        pallas::Base::from(epoch)
+    );
+
+    (ptx, output_budget_note)
+}
+
+fn create_transaction<R: RngCore + CryptoRng>(
+    mut rng: R,
+    ptx: ShieldedPartialTransaction,
+) -> Transaction {
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+// Returns the two payments in epoch 1 and the refill payment in epoch 2
+pub fn create_spending_cap_transactions<R: RngCore + CryptoRng>(mut rng: R) -> Vec<Transaction> {
+    let spend_sk = SigningKey::random(&mut rng);
+    let master_sk = SigningKey::random(&mut rng);
+    let account = SpendingCap::new(&spend_sk, &master_sk, CAP);
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+
+    let bob_auth = TokenAuthorization::from_sk_vk(
+        &pallas::Scalar::random(&mut rng),
+        &COMPRESSED_TOKEN_AUTH_VK,
+    );
+    let bob_nk_com = NullifierKeyContainer::random_commitment(&mut rng);
+
+    let mut txs = vec![];
+
+    // Epoch 1: the master key opens the budget with the first payment
+    let rho = Nullifier::random(&mut rng);
+    let mut budget_note = account.create_genesis_budget_note(&mut rng, rho, nk);
+    let mut state = BudgetState::default();
+    for (epoch, sk) in [(1, &master_sk), (1, &spend_sk)] {
+        let (ptx, output_budget_note) = create_spending_cap_ptx(
+            &mut rng,
+            account,
+            sk,
+            nk,
+            budget_note,
+            state,
+            epoch,
+            bob_auth,
+            bob_nk_com,
+        );
+        txs.push(create_transaction(&mut rng, ptx));
+        budget_note = output_budget_note;
+        state = state.next(CAP, epoch, PAYMENT).unwrap();
+    }
+    assert_eq!(state, BudgetState::new(10, 1));
+
+    // The third payment exceeds the budget of epoch 1
+    assert_eq!(state.next(CAP, 1, PAYMENT), None);
+    let overspend_circuit = {
+        let token = Token::new(TokenName::new("btc").unwrap(), PAYMENT);
+        let token_note = token.create_random_token_note(
+            &mut rng,
+            Nullifier::random(&mut rng),
+            nk,
+            &account.token_authorization(),
+        );
+        let payment_note = token.create_random_token_note(
+            &mut rng,
+            token_note.get_nf().unwrap(),
+            bob_nk_com,
+            &bob_auth,
+        );
+        // There's no valid remaining budget, try zero
+        let output_budget_note = account.create_budget_note(
+            &mut rng,
+            BudgetState::new(0, 1),
+            budget_note.get_nf().unwrap(),
+            nk,
+        );
+        let input_notes = [*token_note.note(), budget_note];
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        SpendingCapValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            token_note.get_nf().unwrap().inner(),
+            input_notes.map(|note| note.calculate_root(&merkle_path)),
+            input_notes,
+            [*payment_note.note(), output_budget_note],
+            account,
+            state,
+            1,
+            &spend_sk,
+        )
+    };
+    assert!(overspend_circuit.dev_prove_and_check(&mut rng).is_err());
+
+    // Epoch 2: the master key refills the budget with the third payment
+    let (ptx, _) = create_spending_cap_ptx(
+        &mut rng,
+        account,
+        &master_sk,
+        nk,
+        budget_note,
+        state,
+        2,
+        bob_auth,
+        bob_nk_com,
+    );
+    txs.push(create_transaction(&mut rng, ptx));
+
+    txs
+}
+
+#[test]
+fn test_spending_cap_txs() {
+    use rand::rngs::OsRng;
+
+    let txs = create_spending_cap_transactions(OsRng);
+    for tx in txs.iter() {
+        tx.execute().unwrap();
+    }
+}
@@ -6,11 +6,13 @@ use rand::RngCore;
 use taiga_halo2::{
     circuit::vp_examples::{
         signature_verification::COMPRESSED_TOKEN_AUTH_VK,
-        token::{Token, TokenAuthorization},
+        token::{Token, TokenAuthorization, TokenName, TokenNote},
     },
-    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    constant::{NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH},
+    keys::SigningKey,
     merkle_tree::{Anchor, MerklePath},
-    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+    note_selection::NoteInTree,
     nullifier::{Nullifier, NullifierKeyContainer},
     shielded_ptx::ShieldedPartialTransaction,
 };
@@ -25,6 +27,34 @@ pub fn create_token_swap_ptx<R: RngCore>(
     output_auth_pk: pallas::Point,
     output_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
 ) -> ShieldedPartialTransaction {
+    let (input_info, output_info) = create_token_swap_proving_info(
+        &mut rng,
+        input_token,
+        input_auth_sk,
+        input_nk,
+        output_token,
+        output_auth_pk,
+        output_nk_com,
+    );
+
+    // Create shielded partial tx
+    ShieldedPartialTransaction::build(input_info, output_info, vec![], &mut rng).unwrap()
+}
+
+// The proving infos of create_token_swap_ptx, e.g. for a dry run
+#[allow(clippy::too_many_arguments)]
+pub fn create_token_swap_proving_info<R: RngCore>(
+    mut rng: R,
+    input_token: Token,
+    input_auth_sk: pallas::Scalar,
+    input_nk: NullifierKeyContainer, // NullifierKeyContainer::Key
+    output_token: Token,
+    output_auth_pk: pallas::Point,
+    output_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> (
+    [InputNoteProvingInfo; NUM_NOTE],
+    [OutputNoteProvingInfo; NUM_NOTE],
+) {
     let input_auth = TokenAuthorization::from_sk_vk(&input_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
 
     // input note
@@ -33,7 +63,7 @@ pub fn create_token_swap_ptx<R: RngCore>(
 
     // output note
     let input_note_nf = input_note.get_nf().unwrap();
-    let output_auth = TokenAuthorization::new(output_auth_pk, *COMPRESSED_TOKEN_AUTH_VK);
+    let output_auth = TokenAuthorization::new(output_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
     let output_note =
         output_token.create_random_token_note(&mut rng, input_note_nf, output_nk_com, &output_auth);
 
@@ -55,8 +85,9 @@ pub fn create_token_swap_ptx<R: RngCore>(
     let input_note_proving_info = input_note.generate_input_token_note_proving_info(
         &mut rng,
         input_auth,
-        input_auth_sk,
+        &SigningKey::new(input_auth_sk),
         merkle_path.clone(),
+        [input_note.calculate_root(&merkle_path), anchor],
         input_notes,
         output_notes,
     );
@@ -85,11 +116,378 @@ pub fn create_token_swap_ptx<R: RngCore>(
         output_notes,
     );
 
-    // Create shielded partial tx
-    ShieldedPartialTransaction::build(
+    (
         [input_note_proving_info, padding_input_note_proving_info],
         [output_note_proving_info, padding_output_note_proving_info],
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapWithChangeError {
+    /// The input note can't cover the swap amount.
+    InsufficientNoteValue { value: u64, swap_amount: u64 },
+}
+
+// Swap swap_amount of the token note in the tree for the output token. The rest of the note goes
+// back to the sender in a change note, or the padding note is created if there's no change.
+#[allow(clippy::too_many_arguments)]
+pub fn create_token_swap_with_change_ptx<R: RngCore>(
+    mut rng: R,
+    input_note_in_tree: NoteInTree,
+    input_token_name: TokenName,
+    swap_amount: u64,
+    input_auth: TokenAuthorization,
+    input_auth_sk: &SigningKey,
+    output_token: Token,
+    output_auth: TokenAuthorization,
+    output_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> Result<ShieldedPartialTransaction, SwapWithChangeError> {
+    let NoteInTree {
+        note: input_note,
+        merkle_path,
+    } = input_note_in_tree;
+    let change = input_note.value.checked_sub(swap_amount).ok_or(
+        SwapWithChangeError::InsufficientNoteValue {
+            value: input_note.value,
+            swap_amount,
+        },
+    )?;
+    let input_note = TokenNote {
+        token_name: input_token_name,
+        note: input_note,
+    };
+
+    // output note
+    let input_note_nf = input_note.get_nf().unwrap();
+    let output_note =
+        output_token.create_random_token_note(&mut rng, input_note_nf, output_nk_com, &output_auth);
+
+    // padding the zero input note, the change note takes the place of the padding output note
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let change_note = (change > 0).then(|| TokenNote {
+        token_name: input_note.token_name.clone(),
+        note: Note::new(
+            input_note.note_type.app_vk,
+            input_note.note_type.app_data_static,
+            input_auth.to_app_data_dynamic(),
+            change,
+            input_note.nk_container.to_commitment(),
+            padding_input_note_nf,
+            true,
+            RandomSeed::random(&mut rng),
+        ),
+    });
+    let second_output_note = match &change_note {
+        Some(change_note) => *change_note.note(),
+        None => Note::random_padding_output_note(&mut rng, padding_input_note_nf),
+    };
+
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), second_output_note];
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the input note proving info
+    let input_note_proving_info = input_note.generate_input_token_note_proving_info(
+        &mut rng,
+        input_auth,
+        input_auth_sk,
+        merkle_path.clone(),
+        [input_note.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the output note proving info
+    let output_note_proving_info = output_note.generate_output_token_note_proving_info(
+        &mut rng,
+        output_auth,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the change note proving info, it's encrypted to the sender
+    let second_output_note_proving_info = match change_note {
+        Some(change_note) => change_note.generate_output_token_note_proving_info(
+            &mut rng,
+            input_auth,
+            input_notes,
+            output_notes,
+        ),
+        None => OutputNoteProvingInfo::create_padding_note_proving_info(
+            second_output_note,
+            input_notes,
+            output_notes,
+        ),
+    };
+
+    // Create shielded partial tx
+    Ok(ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, second_output_note_proving_info],
         vec![],
         &mut rng,
     )
+    .unwrap())
+}
+
+#[test]
+fn test_token_swap_with_change() {
+    use rand::rngs::OsRng;
+    use taiga_halo2::{
+        circuit::vp_examples::receiver_vp::COMPRESSED_RECEIVER_VK,
+        keys::SpendingKey,
+        taiga_api::retrieve_owned_notes,
+        transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+    };
+
+    let mut rng = OsRng;
+    let btc = TokenName::new("btc").unwrap();
+    let eth = TokenName::new("eth").unwrap();
+
+    // The notes are signed for with the auth key and decrypted with the rcv key
+    let auth_of = |sk: &SpendingKey| {
+        TokenAuthorization::from_address(
+            &sk.to_incoming_viewing_key()
+                .to_payment_address(*COMPRESSED_RECEIVER_VK),
+        )
+    };
+    let alice_sk = SpendingKey::random(&mut rng);
+    let alice_nk = alice_sk.get_nk();
+    let alice_auth = auth_of(&alice_sk);
+    let bob_sk = SpendingKey::random(&mut rng);
+    let bob_nk = bob_sk.get_nk();
+    let bob_auth = auth_of(&bob_sk);
+
+    let note_in_tree = |token: Token, nk, auth: &TokenAuthorization, rng: &mut OsRng| {
+        let note =
+            token.create_random_token_note(&mut *rng, Nullifier::random(&mut *rng), nk, auth);
+        NoteInTree::new(
+            *note.note(),
+            MerklePath::random(rng, TAIGA_COMMITMENT_TREE_DEPTH),
+        )
+    };
+    let alice_note = note_in_tree(
+        Token::new(TokenName::new("btc").unwrap(), 7),
+        alice_nk,
+        &alice_auth,
+        &mut rng,
+    );
+    let bob_note = note_in_tree(
+        Token::new(TokenName::new("eth").unwrap(), 10),
+        bob_nk,
+        &bob_auth,
+        &mut rng,
+    );
+
+    // Alice can't swap more than her note
+    assert_eq!(
+        create_token_swap_with_change_ptx(
+            &mut rng,
+            alice_note.clone(),
+            btc.clone(),
+            8,
+            alice_auth,
+            &alice_sk.get_auth_sk(),
+            Token::new(TokenName::new("eth").unwrap(), 10),
+            alice_auth,
+            alice_nk.to_commitment(),
+        )
+        .err(),
+        Some(SwapWithChangeError::InsufficientNoteValue {
+            value: 7,
+            swap_amount: 8
+        })
+    );
+
+    // Alice swaps 5 of her 7 BTC for 10 ETH and gets 2 BTC back
+    let alice_ptx = create_token_swap_with_change_ptx(
+        &mut rng,
+        alice_note,
+        btc,
+        5,
+        alice_auth,
+        &alice_sk.get_auth_sk(),
+        Token::new(TokenName::new("eth").unwrap(), 10),
+        alice_auth,
+        alice_nk.to_commitment(),
+    )
+    .unwrap();
+    // Bob swaps his whole 10 ETH note for 5 BTC, there's no change
+    let bob_ptx = create_token_swap_with_change_ptx(
+        &mut rng,
+        bob_note,
+        eth,
+        10,
+        bob_auth,
+        &bob_sk.get_auth_sk(),
+        Token::new(TokenName::new("btc").unwrap(), 5),
+        bob_auth,
+        bob_nk.to_commitment(),
+    )
+    .unwrap();
+
+    let tx = Transaction::build(
+        &mut rng,
+        ShieldedPartialTxBundle::new(vec![alice_ptx, bob_ptx]),
+        TransparentPartialTxBundle::default(),
+    )
+    .unwrap();
+    tx.execute().unwrap();
+
+    // Alice finds the change note in the transaction
+    let notes = retrieve_owned_notes(&alice_sk.to_incoming_viewing_key(), &tx);
+    let btc_notes: Vec<_> = notes
+        .iter()
+        .filter(|note| {
+            note.get_app_data_static()
+                == Token::new(TokenName::new("btc").unwrap(), 0).encode_name()
+        })
+        .collect();
+    assert_eq!(btc_notes.len(), 1);
+    assert_eq!(btc_notes[0].value, 2);
+}
+
+#[test]
+fn test_token_transfer_note_kinds() {
+    use rand::rngs::OsRng;
+    use taiga_halo2::{
+        circuit::vp_examples::receiver_vp::COMPRESSED_RECEIVER_VK,
+        keys::{IncomingViewingKey, SpendingKey},
+        note_encryption::NoteKind,
+    };
+
+    let mut rng = OsRng;
+    // The notes are signed for with the auth key and decrypted with the rcv key
+    let new_key = |rng: &mut OsRng| {
+        let sk = SpendingKey::random(&mut *rng);
+        let ivk = sk.to_incoming_viewing_key();
+        let auth =
+            TokenAuthorization::from_address(&ivk.to_payment_address(*COMPRESSED_RECEIVER_VK));
+        (sk, auth, ivk)
+    };
+    let (alice_sk, alice_auth, alice_ivk) = new_key(&mut rng);
+    let (bob_sk, bob_auth, bob_ivk) = new_key(&mut rng);
+
+    // Alice pays 5 of her 7 BTC to Bob
+    let alice_note = Token::new(TokenName::new("btc").unwrap(), 7).create_random_token_note(
+        &mut rng,
+        Nullifier::random(&mut rng),
+        alice_sk.get_nk(),
+        &alice_auth,
+    );
+    let ptx = create_token_swap_with_change_ptx(
+        &mut rng,
+        NoteInTree::new(
+            *alice_note.note(),
+            MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH),
+        ),
+        TokenName::new("btc").unwrap(),
+        5,
+        alice_auth,
+        &alice_sk.get_auth_sk(),
+        Token::new(TokenName::new("btc").unwrap(), 5),
+        bob_auth,
+        bob_sk.get_nk().to_commitment(),
+    )
+    .unwrap();
+
+    // Bob gets an external payment and Alice gets the change
+    let decrypt = |ivk: &IncomingViewingKey| {
+        ptx.get_output_vp_public_inputs()
+            .iter()
+            .find_map(|public_inputs| ivk.decrypt_note(public_inputs).ok())
+            .unwrap()
+    };
+    let bob_note = decrypt(&bob_ivk);
+    assert_eq!(bob_note.note.value, 5);
+    assert_eq!(bob_note.kind, NoteKind::External);
+    let change_note = decrypt(&alice_ivk);
+    assert_eq!(change_note.note.value, 2);
+    assert_eq!(change_note.kind, NoteKind::Change);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_token_swap_ptx_metrics() {
+    use group::Group;
+    use pasta_curves::group::Curve;
+    use rand::rngs::OsRng;
+    use std::rc::Rc;
+    use taiga_halo2::metrics::{
+        clear_metrics_sink, set_metrics_sink, ProvingMetrics, ProvingStage,
+    };
+
+    let mut rng = OsRng;
+    let metrics = Rc::new(ProvingMetrics::new());
+    set_metrics_sink(metrics.clone());
+
+    let generator = pallas::Point::generator().to_affine();
+    let auth_sk = pallas::Scalar::random(&mut rng);
+    let auth_pk = generator * auth_sk;
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+    create_token_swap_ptx(
+        &mut rng,
+        Token::new(TokenName::new("btc").unwrap(), 5),
+        auth_sk,
+        nk,
+        Token::new(TokenName::new("eth").unwrap(), 10),
+        auth_pk,
+        nk.to_commitment(),
+    );
+    clear_metrics_sink();
+
+    let proving_events = metrics.events_of(ProvingStage::Proving);
+    let count = |label: &str| {
+        proving_events
+            .iter()
+            .filter(|event| event.circuit == label)
+            .count()
+    };
+    // One action proof per note pair
+    assert_eq!(count("ActionCircuit"), 2);
+    // The token VP of the input and output note
+    assert_eq!(count("TokenValidityPredicateCircuit"), 2);
+    // The dynamic VPs of the input and output note
+    assert_eq!(count("SignatureVerificationValidityPredicateCircuit"), 1);
+    assert_eq!(count("ReceiverValidityPredicateCircuit"), 1);
+    // The padding notes
+    assert_eq!(count("TrivialValidityPredicateCircuit"), 2);
+    assert!(proving_events.iter().all(|event| {
+        !event.duration.is_zero() && event.proof_size.unwrap() > 0 && event.k == Some(15)
+    }));
+
+    // Every proof records its witness synthesis, which is part of the proving time
+    let synthesis_events = metrics.events_of(ProvingStage::WitnessSynthesis);
+    assert_eq!(synthesis_events.len(), proving_events.len());
+    assert!(synthesis_events
+        .iter()
+        .zip(proving_events.iter())
+        .all(|(synthesis, proving)| {
+            synthesis.circuit == proving.circuit
+                && !synthesis.duration.is_zero()
+                && synthesis.duration <= proving.duration
+        }));
+
+    let keygen_events = metrics.events_of(ProvingStage::Keygen);
+    assert!(keygen_events
+        .iter()
+        .any(|event| event.circuit == "TokenValidityPredicateCircuit"));
+    assert_eq!(
+        metrics
+            .events_of(ProvingStage::PartialTransactionBuild)
+            .len(),
+        1
+    );
 }
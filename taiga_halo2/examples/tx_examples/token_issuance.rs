@@ -0,0 +1,277 @@
+/// The issuer mints and burns an issuable token.
+/// Mint: the issuer spends an ephemeral issuance note to create a token note for the receiver.
+/// Burn: the holder spends a token note to create an ephemeral issuance note, the issuer authorizes it.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use taiga_halo2::{
+    circuit::vp_examples::{
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName},
+    },
+    constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+pub fn create_token_mint_ptx<R: RngCore>(
+    mut rng: R,
+    token: Token,
+    issuer_sk: pallas::Scalar,
+    receiver_auth_pk: pallas::Point,
+    receiver_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+) -> ShieldedPartialTransaction {
+    // The ephemeral issuance note
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let issuance_nk = NullifierKeyContainer::random_key(&mut rng);
+    let issuance_note = token.create_issuance_note(&mut rng, rho, issuance_nk);
+
+    // The minted note to the receiver
+    let issuance_note_nf = issuance_note.get_nf().unwrap();
+    let receiver_auth =
+        TokenAuthorization::new(receiver_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    let output_note =
+        token.create_random_token_note(&mut rng, issuance_note_nf, receiver_nk_com, &receiver_auth);
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*issuance_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the issuance note proving info
+    let issuance_note_proving_info = issuance_note.generate_input_issuance_proving_info(
+        &mut rng,
+        &SigningKey::new(issuer_sk),
+        merkle_path.clone(),
+        [issuance_note.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the output note proving info
+    let output_note_proving_info = output_note.generate_output_token_note_proving_info(
+        &mut rng,
+        receiver_auth,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    ShieldedPartialTransaction::build(
+        [issuance_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+pub fn create_token_burn_ptx<R: RngCore>(
+    mut rng: R,
+    token: Token,
+    holder_auth_sk: pallas::Scalar,
+    holder_nk: NullifierKeyContainer, // NullifierKeyContainer::Key
+    issuer_sk: pallas::Scalar,
+) -> ShieldedPartialTransaction {
+    let holder_auth = TokenAuthorization::from_sk_vk(&holder_auth_sk, &COMPRESSED_TOKEN_AUTH_VK);
+
+    // The burned note
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let input_note = token.create_random_token_note(&mut rng, rho, holder_nk, &holder_auth);
+
+    // The ephemeral issuance note
+    let input_note_nf = input_note.get_nf().unwrap();
+    let issuance_nk = NullifierKeyContainer::random_key(&mut rng);
+    let issuance_note =
+        token.create_issuance_note(&mut rng, input_note_nf, issuance_nk.to_commitment());
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [*issuance_note.note(), padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    // Create the input note proving info
+    let input_note_proving_info = input_note.generate_input_token_note_proving_info(
+        &mut rng,
+        holder_auth,
+        &SigningKey::new(holder_auth_sk),
+        merkle_path.clone(),
+        [input_note.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the issuance note proving info
+    let issuance_note_proving_info = issuance_note.generate_output_issuance_proving_info(
+        &mut rng,
+        &SigningKey::new(issuer_sk),
+        [input_note.calculate_root(&merkle_path), anchor],
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    // Create shielded partial tx
+    ShieldedPartialTransaction::build(
+        [input_note_proving_info, padding_input_note_proving_info],
+        [issuance_note_proving_info, padding_output_note_proving_info],
+        vec![],
+        &mut rng,
+    )
+    .unwrap()
+}
+
+pub fn create_token_mint_and_burn_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
+    use group::Group;
+
+    let generator = pallas::Point::generator();
+    let issuer_sk = pallas::Scalar::random(&mut rng);
+    let token = Token::new(
+        TokenName::new_issuable("xan", generator * issuer_sk).unwrap(),
+        10,
+    );
+
+    let holder_auth_sk = pallas::Scalar::random(&mut rng);
+    let holder_nk = NullifierKeyContainer::random_key(&mut rng);
+    let mint_ptx = create_token_mint_ptx(
+        &mut rng,
+        token.clone(),
+        issuer_sk,
+        generator * holder_auth_sk,
+        holder_nk.to_commitment(),
+    );
+    let burn_ptx = create_token_burn_ptx(&mut rng, token, holder_auth_sk, holder_nk, issuer_sk);
+
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![mint_ptx, burn_ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use group::Group;
+    use rand::rngs::OsRng;
+
+    fn build_tx(ptx: ShieldedPartialTransaction) -> Transaction {
+        let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+        let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+        Transaction::build(OsRng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
+    }
+
+    #[test]
+    fn test_token_mint_tx() {
+        let mut rng = OsRng;
+        let generator = pallas::Point::generator();
+        let issuer_sk = pallas::Scalar::random(&mut rng);
+        let token = Token::new(
+            TokenName::new_issuable("xan", generator * issuer_sk).unwrap(),
+            10,
+        );
+        let receiver_nk = NullifierKeyContainer::random_key(&mut rng);
+        let ptx = create_token_mint_ptx(
+            &mut rng,
+            token,
+            issuer_sk,
+            pallas::Point::random(&mut rng),
+            receiver_nk.to_commitment(),
+        );
+        build_tx(ptx).execute().unwrap();
+    }
+
+    #[test]
+    fn test_token_mint_tx_with_wrong_key() {
+        let mut rng = OsRng;
+        let generator = pallas::Point::generator();
+        let issuer_sk = pallas::Scalar::random(&mut rng);
+        let token = Token::new(
+            TokenName::new_issuable("xan", generator * issuer_sk).unwrap(),
+            10,
+        );
+        let receiver_nk = NullifierKeyContainer::random_key(&mut rng);
+        let wrong_sk = pallas::Scalar::random(&mut rng);
+        let ptx = create_token_mint_ptx(
+            &mut rng,
+            token,
+            wrong_sk,
+            pallas::Point::random(&mut rng),
+            receiver_nk.to_commitment(),
+        );
+        assert!(build_tx(ptx).execute().is_err());
+    }
+
+    #[test]
+    fn test_token_burn_tx() {
+        let mut rng = OsRng;
+        let generator = pallas::Point::generator();
+        let issuer_sk = pallas::Scalar::random(&mut rng);
+        let token = Token::new(
+            TokenName::new_issuable("xan", generator * issuer_sk).unwrap(),
+            10,
+        );
+        let holder_auth_sk = pallas::Scalar::random(&mut rng);
+        let holder_nk = NullifierKeyContainer::random_key(&mut rng);
+        let ptx = create_token_burn_ptx(&mut rng, token, holder_auth_sk, holder_nk, issuer_sk);
+        build_tx(ptx).execute().unwrap();
+    }
+
+    #[test]
+    fn test_token_mint_and_burn_tx() {
+        let tx = create_token_mint_and_burn_transaction(OsRng);
+        tx.execute().unwrap();
+    }
+}
@@ -8,13 +8,15 @@ use group::Group;
 use halo2_proofs::arithmetic::Field;
 use pasta_curves::{group::Curve, pallas};
 use rand::{CryptoRng, RngCore};
+use std::sync::Arc;
 use taiga_halo2::{
     circuit::vp_examples::{
         or_relation_intent::{create_intent_note, OrRelationIntentValidityPredicateCircuit},
         signature_verification::COMPRESSED_TOKEN_AUTH_VK,
-        token::{Token, TokenAuthorization},
+        token::{Token, TokenAuthorization, TokenName},
     },
     constant::TAIGA_COMMITMENT_TREE_DEPTH,
+    keys::SigningKey,
     merkle_tree::{Anchor, MerklePath},
     note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
     nullifier::{Nullifier, NullifierKeyContainer},
@@ -71,8 +73,9 @@ pub fn create_token_intent_ptx<R: RngCore>(
     let input_note_proving_info = input_note.generate_input_token_note_proving_info(
         &mut rng,
         input_auth,
-        input_auth_sk,
+        &SigningKey::new(input_auth_sk),
         merkle_path.clone(),
+        [input_note.calculate_root(&merkle_path), anchor],
         input_notes,
         output_notes,
     );
@@ -81,8 +84,8 @@ pub fn create_token_intent_ptx<R: RngCore>(
     let intent_note_proving_info = {
         let intent_vp = OrRelationIntentValidityPredicateCircuit {
             owned_note_pub_id: intent_note.commitment().inner(),
-            input_notes,
-            output_notes,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
             token_1,
             token_2,
             receiver_nk_com: input_note_nk_com,
@@ -114,7 +117,8 @@ pub fn create_token_intent_ptx<R: RngCore>(
         [intent_note_proving_info, padding_output_note_proving_info],
         vec![],
         &mut rng,
-    );
+    )
+    .unwrap();
 
     (
         ptx,
@@ -150,7 +154,7 @@ pub fn consume_token_intent_ptx<R: RngCore>(
 
     // output note
     let input_note_nf = intent_note.get_nf().unwrap();
-    let output_auth = TokenAuthorization::new(output_auth_pk, *COMPRESSED_TOKEN_AUTH_VK);
+    let output_auth = TokenAuthorization::new(output_auth_pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
     let output_note = output_token.create_random_token_note(
         &mut rng,
         input_note_nf,
@@ -175,8 +179,8 @@ pub fn consume_token_intent_ptx<R: RngCore>(
     let intent_note_proving_info = {
         let intent_vp = OrRelationIntentValidityPredicateCircuit {
             owned_note_pub_id: input_note_nf.inner(),
-            input_notes,
-            output_notes,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
             token_1,
             token_2,
             receiver_nk_com,
@@ -223,6 +227,7 @@ pub fn consume_token_intent_ptx<R: RngCore>(
         vec![],
         &mut rng,
     )
+    .unwrap()
 }
 
 pub fn create_token_swap_intent_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
@@ -232,9 +237,9 @@ pub fn create_token_swap_intent_transaction<R: RngCore + CryptoRng>(mut rng: R)
     let alice_auth_sk = pallas::Scalar::random(&mut rng);
     let alice_auth_pk = generator * alice_auth_sk;
     let alice_nk = NullifierKeyContainer::random_key(&mut rng);
-    let token_1 = Token::new("dolphin".to_string(), 1u64);
-    let token_2 = Token::new("monkey".to_string(), 2u64);
-    let btc_token = Token::new("btc".to_string(), 5u64);
+    let token_1 = Token::new(TokenName::new("dolphin").unwrap(), 1u64);
+    let token_2 = Token::new(TokenName::new("monkey").unwrap(), 2u64);
+    let btc_token = Token::new(TokenName::new("btc").unwrap(), 5u64);
     let (alice_ptx, intent_nk, receiver_nk_com, receiver_app_data_dynamic, intent_rho) =
         create_token_intent_ptx(
             &mut rng,
@@ -277,7 +282,7 @@ pub fn create_token_swap_intent_transaction<R: RngCore + CryptoRng>(mut rng: R)
     // Solver creates the final transaction
     let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![alice_ptx, bob_ptx, solver_ptx]);
     let transparent_ptx_bundle = TransparentPartialTxBundle::default();
-    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle)
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
 }
 
 #[test]
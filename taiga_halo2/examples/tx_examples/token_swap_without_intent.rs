@@ -9,7 +9,7 @@ use halo2_proofs::arithmetic::Field;
 use pasta_curves::{group::Curve, pallas};
 use rand::{CryptoRng, RngCore};
 use taiga_halo2::{
-    circuit::vp_examples::token::Token,
+    circuit::vp_examples::token::{Token, TokenName},
     nullifier::NullifierKeyContainer,
     transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
 };
@@ -17,9 +17,9 @@ use taiga_halo2::{
 pub fn create_token_swap_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Transaction {
     let generator = pallas::Point::generator().to_affine();
 
-    let btc_token = Token::new("btc".to_string(), 5);
-    let eth_token = Token::new("eth".to_string(), 10);
-    let xan_token = Token::new("xan".to_string(), 15);
+    let btc_token = Token::new(TokenName::new("btc").unwrap(), 5);
+    let eth_token = Token::new(TokenName::new("eth").unwrap(), 10);
+    let xan_token = Token::new(TokenName::new("xan").unwrap(), 15);
 
     // Alice creates the partial transaction
     let alice_auth_sk = pallas::Scalar::random(&mut rng);
@@ -69,7 +69,7 @@ pub fn create_token_swap_transaction<R: RngCore + CryptoRng>(mut rng: R) -> Tran
     // Solver creates the final transaction
     let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![alice_ptx, bob_ptx, carol_ptx]);
     let transparent_ptx_bundle = TransparentPartialTxBundle::default();
-    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle)
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle).unwrap()
 }
 
 #[test]
@@ -80,3 +80,92 @@ fn test_basic_swap_tx() {
     let tx = create_token_swap_transaction(&mut rng);
     tx.execute().unwrap();
 }
+
+#[test]
+fn test_basic_swap_tx_dry_run() {
+    use crate::token::create_token_swap_proving_info;
+    use rand::rngs::OsRng;
+    use taiga_halo2::shielded_ptx::ShieldedPartialTransaction;
+
+    let mut rng = OsRng;
+    let generator = pallas::Point::generator().to_affine();
+    let btc_token = Token::new(TokenName::new("btc").unwrap(), 5);
+    let eth_token = Token::new(TokenName::new("eth").unwrap(), 10);
+    let xan_token = Token::new(TokenName::new("xan").unwrap(), 15);
+
+    // Every party sells a token and buys the next one
+    let ptx_plans: Vec<_> = [
+        (btc_token.clone(), eth_token.clone()),
+        (eth_token, xan_token.clone()),
+        (xan_token, btc_token),
+    ]
+    .into_iter()
+    .map(|(sell, buy)| {
+        let auth_sk = pallas::Scalar::random(&mut rng);
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let (input_info, output_info) = create_token_swap_proving_info(
+            &mut rng,
+            sell,
+            auth_sk,
+            nk,
+            buy,
+            generator * auth_sk,
+            nk.to_commitment(),
+        );
+        ShieldedPartialTransaction::dry_run(&input_info, &output_info, true).unwrap()
+    })
+    .collect();
+    assert!(ptx_plans.iter().all(|plan| !plan.is_balanced()));
+
+    let tx_plan = Transaction::dry_run(ptx_plans, None).unwrap();
+    assert_eq!(tx_plan.balances.len(), 3);
+    assert!(tx_plan
+        .balances
+        .iter()
+        .all(|balance| balance.is_balanced() && balance.input_value > 0));
+}
+
+// Run with `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+fn test_dry_run_timing() {
+    use crate::token::{create_token_swap_proving_info, create_token_swap_ptx};
+    use rand::rngs::OsRng;
+    use std::time::Instant;
+    use taiga_halo2::shielded_ptx::ShieldedPartialTransaction;
+
+    let mut rng = OsRng;
+    let generator = pallas::Point::generator().to_affine();
+    let auth_sk = pallas::Scalar::random(&mut rng);
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+    let btc_token = Token::new(TokenName::new("btc").unwrap(), 5);
+    let eth_token = Token::new(TokenName::new("eth").unwrap(), 10);
+
+    let (input_info, output_info) = create_token_swap_proving_info(
+        &mut rng,
+        btc_token.clone(),
+        auth_sk,
+        nk,
+        eth_token.clone(),
+        generator * auth_sk,
+        nk.to_commitment(),
+    );
+    let start = Instant::now();
+    ShieldedPartialTransaction::dry_run(&input_info, &output_info, false).unwrap();
+    let dry_run_time = start.elapsed();
+
+    let start = Instant::now();
+    create_token_swap_ptx(
+        &mut rng,
+        btc_token,
+        auth_sk,
+        nk,
+        eth_token,
+        generator * auth_sk,
+        nk.to_commitment(),
+    );
+    let build_time = start.elapsed();
+
+    println!("dry run: {dry_run_time:?}, build: {build_time:?}");
+    assert!(dry_run_time * 10 <= build_time);
+}
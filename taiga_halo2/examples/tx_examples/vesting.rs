@@ -0,0 +1,187 @@
+/// The vesting flow
+/// Alice receives a grant of 10 locked until height 100. At height 50 the timelock vp is not
+/// satisfied, the spend fails. At height 150 she moves the grant to her new key.
+/// The executor supplies the height in the execution context, it must be the height published by
+/// the timelock vps.
+///
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use rand::{CryptoRng, RngCore};
+use std::sync::Arc;
+use taiga_halo2::{
+    circuit::vp_examples::timelock::{
+        create_timelocked_note, TimelockValidityPredicateCircuit, COMPRESSED_TIMELOCK_VK,
+        TIMELOCK_HEIGHT_PUBLIC_INPUT_IDX,
+    },
+    constant::{NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH},
+    error::TransactionError,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{
+        ExecutionContext, ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle,
+    },
+};
+
+pub const UNLOCK_HEIGHT: u64 = 100;
+const GRANT: u64 = 10;
+
+// The context of the executor at the height
+pub fn vesting_context(height: u64) -> ExecutionContext {
+    ExecutionContext::new(height)
+        .bind_height(*COMPRESSED_TIMELOCK_VK, TIMELOCK_HEIGHT_PUBLIC_INPUT_IDX)
+}
+
+// Spend the locked note at the height and send it to the receiver
+pub fn create_vesting_proving_infos<R: RngCore>(
+    mut rng: R,
+    locked_note: Note,
+    receiver_nk_com: NullifierKeyContainer, // NullifierKeyContainer::Commitment
+    height: u64,
+) -> (
+    [InputNoteProvingInfo; NUM_NOTE],
+    [OutputNoteProvingInfo; NUM_NOTE],
+) {
+    let output_note = create_timelocked_note(
+        &mut rng,
+        UNLOCK_HEIGHT,
+        locked_note.value,
+        locked_note.get_nf().unwrap(),
+        receiver_nk_com,
+    );
+
+    // padding the zero notes
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_input_note_nf = padding_input_note.get_nf().unwrap();
+    let padding_output_note = Note::random_padding_output_note(&mut rng, padding_input_note_nf);
+
+    let input_notes = [locked_note, padding_input_note];
+    let output_notes = [output_note, padding_output_note];
+
+    // Generate proving info
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+
+    // Fetch a valid anchor for padding input notes
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+
+    let timelock_vp = |owned_note_pub_id: pallas::Base| TimelockValidityPredicateCircuit {
+        owned_note_pub_id,
+        input_notes: Arc::new(input_notes),
+        output_notes: Arc::new(output_notes),
+        unlock_height: UNLOCK_HEIGHT,
+        height,
+    };
+
+    // Create the input note proving info
+    let input_note_proving_info = InputNoteProvingInfo::new(
+        locked_note,
+        merkle_path.clone(),
+        None,
+        Box::new(timelock_vp(locked_note.get_nf().unwrap().inner())),
+        vec![],
+    );
+
+    // Create the output note proving info
+    let output_note_proving_info = OutputNoteProvingInfo::new(
+        output_note,
+        Box::new(timelock_vp(output_note.commitment().inner())),
+        vec![],
+    );
+
+    // Create the padding input note proving info
+    let padding_input_note_proving_info = InputNoteProvingInfo::create_padding_note_proving_info(
+        padding_input_note,
+        merkle_path,
+        anchor,
+        input_notes,
+        output_notes,
+    );
+
+    // Create the padding output note proving info
+    let padding_output_note_proving_info = OutputNoteProvingInfo::create_padding_note_proving_info(
+        padding_output_note,
+        input_notes,
+        output_notes,
+    );
+
+    (
+        [input_note_proving_info, padding_input_note_proving_info],
+        [output_note_proving_info, padding_output_note_proving_info],
+    )
+}
+
+pub fn create_vesting_transaction<R: RngCore + CryptoRng>(
+    mut rng: R,
+    locked_note: Note,
+    receiver_nk_com: NullifierKeyContainer,
+    height: u64,
+) -> Result<Transaction, TransactionError> {
+    let (input_infos, output_infos) =
+        create_vesting_proving_infos(&mut rng, locked_note, receiver_nk_com, height);
+    let ptx = ShieldedPartialTransaction::build(input_infos, output_infos, vec![], &mut rng)?;
+    let shielded_tx_bundle = ShieldedPartialTxBundle::new(vec![ptx]);
+    let transparent_ptx_bundle = TransparentPartialTxBundle::default();
+    Transaction::build(&mut rng, shielded_tx_bundle, transparent_ptx_bundle)
+}
+
+// Alice's grant locked until UNLOCK_HEIGHT
+pub fn create_grant_note<R: RngCore>(mut rng: R) -> Note {
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+    create_timelocked_note(
+        &mut rng,
+        UNLOCK_HEIGHT,
+        GRANT,
+        Nullifier::random(&mut rng),
+        nk,
+    )
+}
+
+// Returns the transaction spending the grant at height 150
+pub fn create_vesting_transactions<R: RngCore + CryptoRng>(mut rng: R) -> Vec<Transaction> {
+    let grant_note = create_grant_note(&mut rng);
+    let receiver_nk_com = NullifierKeyContainer::random_commitment(&mut rng);
+    let tx = create_vesting_transaction(&mut rng, grant_note, receiver_nk_com, 150).unwrap();
+    vec![tx]
+}
+
+#[test]
+fn test_vesting_txs() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let grant_note = create_grant_note(&mut rng);
+    let receiver_nk_com = NullifierKeyContainer::random_commitment(&mut rng);
+
+    // Height 50: the timelock vp of the grant is not satisfied
+    let (input_infos, output_infos) =
+        create_vesting_proving_infos(&mut rng, grant_note, receiver_nk_com, 50);
+    assert!(matches!(
+        ShieldedPartialTransaction::dry_run(&input_infos, &output_infos, true),
+        Err(TransactionError::VPConstraintFailure {
+            note_index: 0,
+            vp_index: 0,
+            ..
+        })
+    ));
+    let early_result = create_vesting_transaction(&mut rng, grant_note, receiver_nk_com, 50)
+        .and_then(|tx| tx.execute_with_context(&vesting_context(50)));
+    assert!(early_result.is_err());
+
+    // Height 150: the grant is unlocked
+    let tx = create_vesting_transaction(&mut rng, grant_note, receiver_nk_com, 150).unwrap();
+    tx.execute_with_context(&vesting_context(150)).unwrap();
+
+    // The proofs are for height 150, not the height of the executor
+    assert!(matches!(
+        tx.execute_with_context(&vesting_context(200)),
+        Err(TransactionError::HeightMismatch)
+    ));
+    assert!(matches!(
+        tx.execute_with_context(
+            &ExecutionContext::default()
+                .bind_height(*COMPRESSED_TIMELOCK_VK, TIMELOCK_HEIGHT_PUBLIC_INPUT_IDX)
+        ),
+        Err(TransactionError::MissingHeight)
+    ));
+}
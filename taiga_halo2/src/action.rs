@@ -1,8 +1,18 @@
 use crate::{
     circuit::action_circuit::ActionCircuit,
-    constant::{PRF_EXPAND_INPUT_VP_CM_R, PRF_EXPAND_OUTPUT_VP_CM_R},
+    constant::{
+        ACTION_ANCHOR_PUBLIC_INPUT_ROW_IDX, ACTION_CIPHER_COMMITMENT_ROW_IDX,
+        ACTION_INPUT_APP_VK_ROW_IDX, ACTION_INPUT_VP_CM_1_ROW_IDX, ACTION_INPUT_VP_CM_2_ROW_IDX,
+        ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX, ACTION_NET_VALUE_CM_Y_PUBLIC_INPUT_ROW_IDX,
+        ACTION_NF_PUBLIC_INPUT_ROW_IDX, ACTION_OUTPUT_APP_VK_ROW_IDX,
+        ACTION_OUTPUT_CM_PUBLIC_INPUT_ROW_IDX, ACTION_OUTPUT_VP_CM_1_ROW_IDX,
+        ACTION_OUTPUT_VP_CM_2_ROW_IDX, ACTION_PUBLIC_INPUT_LAYOUT_VERSION, ACTION_PUBLIC_INPUT_NUM,
+        NOTE_CIPHERTEXT_COMMITMENT_SENTINEL, PRF_EXPAND_INPUT_VP_CM_R, PRF_EXPAND_OUTPUT_VP_CM_R,
+    },
+    error::{NoteError, TransactionError},
     merkle_tree::{Anchor, MerklePath},
     note::{InputNoteProvingInfo, Note, NoteCommitment, OutputNoteProvingInfo, RandomSeed},
+    note_encryption::NoteEncryption,
     nullifier::Nullifier,
     value_commitment::ValueCommitment,
     vp_commitment::ValidityPredicateCommitment,
@@ -18,6 +28,8 @@ use serde;
 
 #[cfg(feature = "borsh")]
 use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "borsh")]
+use ff::PrimeField;
 
 /// The public inputs of action proof.
 #[derive(Debug, Clone)]
@@ -37,6 +49,12 @@ pub struct ActionPublicInputs {
     pub input_vp_commitment: ValidityPredicateCommitment,
     /// The commitment to output note application(static) vp
     pub output_vp_commitment: ValidityPredicateCommitment,
+    /// The commitment to the output note ciphertext, NOTE_CIPHERTEXT_COMMITMENT_SENTINEL if the note is not sent with an encrypted payload
+    pub cipher_commitment: pallas::Base,
+    /// The application vp (compressed) vk of the input note
+    pub input_app_vk: pallas::Base,
+    /// The application vp (compressed) vk of the output note
+    pub output_app_vk: pallas::Base,
 }
 
 /// The information to build ActionPublicInputs and ActionCircuit.
@@ -48,23 +66,65 @@ pub struct ActionInfo {
     output_note: Note,
     // rseed is to generate the randomness of the value commitment and vp commitments
     rseed: RandomSeed,
+    // The encryption of the output note, None if the note is not sent with an encrypted payload
+    note_encryption: Option<NoteEncryption>,
 }
 
 impl ActionPublicInputs {
-    pub fn to_instance(&self) -> Vec<pallas::Base> {
+    /// The instance of the action proof, in the rows of ACTION_PUBLIC_INPUT_LAYOUT_VERSION
+    pub fn to_vec(&self) -> Vec<pallas::Base> {
         let input_vp_commitment = self.input_vp_commitment.to_public_inputs();
         let output_vp_commitment = self.output_vp_commitment.to_public_inputs();
-        vec![
-            self.nf.inner(),
-            self.anchor.inner(),
-            self.cm.inner(),
-            self.cv_net.get_x(),
-            self.cv_net.get_y(),
-            input_vp_commitment[0],
-            input_vp_commitment[1],
-            output_vp_commitment[0],
-            output_vp_commitment[1],
-        ]
+        let mut instance = vec![pallas::Base::zero(); ACTION_PUBLIC_INPUT_NUM];
+        instance[ACTION_NF_PUBLIC_INPUT_ROW_IDX] = self.nf.inner();
+        instance[ACTION_ANCHOR_PUBLIC_INPUT_ROW_IDX] = self.anchor.inner();
+        instance[ACTION_OUTPUT_CM_PUBLIC_INPUT_ROW_IDX] = self.cm.inner();
+        instance[ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX] = self.cv_net.get_x();
+        instance[ACTION_NET_VALUE_CM_Y_PUBLIC_INPUT_ROW_IDX] = self.cv_net.get_y();
+        instance[ACTION_INPUT_VP_CM_1_ROW_IDX] = input_vp_commitment[0];
+        instance[ACTION_INPUT_VP_CM_2_ROW_IDX] = input_vp_commitment[1];
+        instance[ACTION_OUTPUT_VP_CM_1_ROW_IDX] = output_vp_commitment[0];
+        instance[ACTION_OUTPUT_VP_CM_2_ROW_IDX] = output_vp_commitment[1];
+        instance[ACTION_CIPHER_COMMITMENT_ROW_IDX] = self.cipher_commitment;
+        instance[ACTION_INPUT_APP_VK_ROW_IDX] = self.input_app_vk;
+        instance[ACTION_OUTPUT_APP_VK_ROW_IDX] = self.output_app_vk;
+        instance
+    }
+
+    /// Parses an instance produced by to_vec under `layout_version`. The instances of another
+    /// layout version, of the wrong length or with an invalid value commitment are rejected.
+    pub fn from_vec(
+        layout_version: u8,
+        instance: &[pallas::Base],
+    ) -> Result<Self, TransactionError> {
+        if layout_version != ACTION_PUBLIC_INPUT_LAYOUT_VERSION {
+            return Err(TransactionError::UnsupportedPublicInputLayout);
+        }
+        if instance.len() != ACTION_PUBLIC_INPUT_NUM {
+            return Err(TransactionError::PublicInputMalformed);
+        }
+        let cv_net = ValueCommitment::from_xy(
+            instance[ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX],
+            instance[ACTION_NET_VALUE_CM_Y_PUBLIC_INPUT_ROW_IDX],
+        )
+        .ok_or(TransactionError::PublicInputMalformed)?;
+        Ok(ActionPublicInputs {
+            anchor: Anchor::from(instance[ACTION_ANCHOR_PUBLIC_INPUT_ROW_IDX]),
+            nf: Nullifier::from(instance[ACTION_NF_PUBLIC_INPUT_ROW_IDX]),
+            cm: NoteCommitment::from(instance[ACTION_OUTPUT_CM_PUBLIC_INPUT_ROW_IDX]),
+            cv_net,
+            input_vp_commitment: ValidityPredicateCommitment::from_public_inputs(&[
+                instance[ACTION_INPUT_VP_CM_1_ROW_IDX],
+                instance[ACTION_INPUT_VP_CM_2_ROW_IDX],
+            ]),
+            output_vp_commitment: ValidityPredicateCommitment::from_public_inputs(&[
+                instance[ACTION_OUTPUT_VP_CM_1_ROW_IDX],
+                instance[ACTION_OUTPUT_VP_CM_2_ROW_IDX],
+            ]),
+            cipher_commitment: instance[ACTION_CIPHER_COMMITMENT_ROW_IDX],
+            input_app_vk: instance[ACTION_INPUT_APP_VK_ROW_IDX],
+            output_app_vk: instance[ACTION_OUTPUT_APP_VK_ROW_IDX],
+        })
     }
 }
 
@@ -77,6 +137,9 @@ impl BorshSerialize for ActionPublicInputs {
         writer.write_all(&self.cv_net.to_bytes())?;
         writer.write_all(&self.input_vp_commitment.to_bytes())?;
         writer.write_all(&self.output_vp_commitment.to_bytes())?;
+        writer.write_all(&self.cipher_commitment.to_repr())?;
+        writer.write_all(&self.input_app_vk.to_repr())?;
+        writer.write_all(&self.output_app_vk.to_repr())?;
         Ok(())
     }
 }
@@ -95,14 +158,29 @@ impl BorshDeserialize for ActionPublicInputs {
         let cm = Option::from(NoteCommitment::from_bytes(cm_bytes))
             .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "cm not in field"))?;
         let cv_net_bytes = <[u8; 32]>::deserialize_reader(reader)?;
-        let cv_net = Option::from(ValueCommitment::from_bytes(cv_net_bytes))
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "cv_net not in field"))?;
+        let cv_net = ValueCommitment::from_bytes(cv_net_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
         let input_vp_commitment_bytes = <[u8; 32]>::deserialize_reader(reader)?;
         let input_vp_commitment =
             ValidityPredicateCommitment::from_bytes(input_vp_commitment_bytes);
         let output_vp_commitment_bytes = <[u8; 32]>::deserialize_reader(reader)?;
         let output_vp_commitment =
             ValidityPredicateCommitment::from_bytes(output_vp_commitment_bytes);
+        let cipher_commitment_bytes = <[u8; 32]>::deserialize_reader(reader)?;
+        let cipher_commitment = Option::from(pallas::Base::from_repr(cipher_commitment_bytes))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "cipher_commitment not in field")
+            })?;
+        let input_app_vk_bytes = <[u8; 32]>::deserialize_reader(reader)?;
+        let input_app_vk =
+            Option::from(pallas::Base::from_repr(input_app_vk_bytes)).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "input_app_vk not in field")
+            })?;
+        let output_app_vk_bytes = <[u8; 32]>::deserialize_reader(reader)?;
+        let output_app_vk =
+            Option::from(pallas::Base::from_repr(output_app_vk_bytes)).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "output_app_vk not in field")
+            })?;
 
         Ok(ActionPublicInputs {
             anchor,
@@ -111,6 +189,9 @@ impl BorshDeserialize for ActionPublicInputs {
             cv_net,
             input_vp_commitment,
             output_vp_commitment,
+            cipher_commitment,
+            input_app_vk,
+            output_app_vk,
         })
     }
 }
@@ -123,12 +204,14 @@ impl ActionInfo {
         output_note: Note,
         rseed: RandomSeed,
     ) -> Self {
+        check_rho(&input_note, &output_note);
         Self {
             input_note,
             input_merkle_path,
             input_anchor,
             output_note,
             rseed,
+            note_encryption: None,
         }
     }
 
@@ -137,6 +220,7 @@ impl ActionInfo {
         output: OutputNoteProvingInfo,
         mut rng: R,
     ) -> Self {
+        check_rho(&input.note, &output.note);
         let rseed = RandomSeed::random(&mut rng);
         Self {
             input_note: input.note,
@@ -144,9 +228,23 @@ impl ActionInfo {
             input_anchor: input.anchor,
             output_note: output.note,
             rseed,
+            note_encryption: output.get_note_encryption(),
         }
     }
 
+    // The action commits to the ciphertext of the output note under the encryption
+    pub fn set_note_encryption(&mut self, note_encryption: NoteEncryption) {
+        self.note_encryption = Some(note_encryption);
+    }
+
+    // The commitment to the ciphertext of the output note, or the sentinel
+    pub fn get_cipher_commitment(&self) -> pallas::Base {
+        self.note_encryption
+            .map_or(NOTE_CIPHERTEXT_COMMITMENT_SENTINEL, |note_encryption| {
+                note_encryption.encrypt(&self.output_note).commitment()
+            })
+    }
+
     // Get the randomness of value commitment
     pub fn get_rcv(&self) -> pallas::Scalar {
         self.rseed.get_rcv()
@@ -162,13 +260,18 @@ impl ActionInfo {
         self.rseed.get_vp_cm_r(PRF_EXPAND_OUTPUT_VP_CM_R)
     }
 
+    /// Panics if the merkle path depth is not TAIGA_COMMITMENT_TREE_DEPTH.
     pub fn build(&self) -> (ActionPublicInputs, ActionCircuit) {
-        let nf = self.input_note.get_nf().unwrap();
-        assert_eq!(
-            nf, self.output_note.rho,
-            "The nf of input note should be equal to the rho of output note"
-        );
+        self.build_with_depth()
+            .expect("the merkle path depth should be TAIGA_COMMITMENT_TREE_DEPTH")
+    }
 
+    /// Builds the action circuit of a tree of depth DEPTH.
+    pub fn build_with_depth<const DEPTH: usize>(
+        &self,
+    ) -> Result<(ActionPublicInputs, ActionCircuit<DEPTH>), NoteError> {
+        let merkle_path = self.input_merkle_path.to_array()?;
+        let nf = self.input_note.get_nf().unwrap();
         let cm = self.output_note.commitment();
 
         let rcv = self.get_rcv();
@@ -189,21 +292,34 @@ impl ActionInfo {
             cv_net,
             input_vp_commitment,
             output_vp_commitment,
+            cipher_commitment: self.get_cipher_commitment(),
+            input_app_vk: self.input_note.get_app_vk(),
+            output_app_vk: self.output_note.get_app_vk(),
         };
 
         let action_circuit = ActionCircuit {
             input_note: self.input_note,
-            merkle_path: self.input_merkle_path.get_path().try_into().unwrap(),
+            merkle_path,
             output_note: self.output_note,
             rcv,
             input_vp_cm_r,
             output_vp_cm_r,
+            note_encryption: self.note_encryption,
         };
 
-        (action, action_circuit)
+        Ok((action, action_circuit))
     }
 }
 
+// The action circuit takes the rho of the output note from the nf of the input note
+fn check_rho(input_note: &Note, output_note: &Note) {
+    assert_eq!(
+        input_note.get_nf().unwrap(),
+        output_note.rho,
+        "The nf of input note should be equal to the rho of output note"
+    );
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::ActionInfo;
@@ -227,4 +343,75 @@ pub mod tests {
             rseed,
         )
     }
+
+    #[test]
+    fn test_action_public_inputs_layout() {
+        use super::ActionPublicInputs;
+        use crate::constant::{
+            ACTION_CIRCUIT_PARAMS_SIZE, ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX,
+            ACTION_PUBLIC_INPUT_LAYOUT_VERSION,
+        };
+        use crate::error::TransactionError;
+        use halo2_proofs::dev::MockProver;
+        use pasta_curves::pallas;
+        use rand::rngs::OsRng;
+
+        let (action, action_circuit) = random_action_info(OsRng).build();
+        let instance = action.to_vec();
+
+        // Round trip
+        let parsed =
+            ActionPublicInputs::from_vec(ACTION_PUBLIC_INPUT_LAYOUT_VERSION, &instance).unwrap();
+        assert_eq!(parsed.to_vec(), instance);
+
+        // The parsed instance still verifies
+        let prover = MockProver::<pallas::Base>::run(
+            ACTION_CIRCUIT_PARAMS_SIZE,
+            &action_circuit,
+            vec![parsed.to_vec()],
+        )
+        .unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // Another layout version
+        assert!(matches!(
+            ActionPublicInputs::from_vec(ACTION_PUBLIC_INPUT_LAYOUT_VERSION + 1, &instance),
+            Err(TransactionError::UnsupportedPublicInputLayout)
+        ));
+        // Wrong length
+        assert!(matches!(
+            ActionPublicInputs::from_vec(
+                ACTION_PUBLIC_INPUT_LAYOUT_VERSION,
+                &instance[..instance.len() - 1]
+            ),
+            Err(TransactionError::PublicInputMalformed)
+        ));
+        // The value commitment is not on the curve
+        let mut invalid_cv = instance;
+        invalid_cv[ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX] += pallas::Base::one();
+        assert!(matches!(
+            ActionPublicInputs::from_vec(ACTION_PUBLIC_INPUT_LAYOUT_VERSION, &invalid_cv),
+            Err(TransactionError::PublicInputMalformed)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "rho of output note")]
+    fn test_action_info_rho_mismatch() {
+        use crate::nullifier::Nullifier;
+        use rand::rngs::OsRng;
+
+        let mut rng = OsRng;
+        let input_note = random_input_note(&mut rng);
+        let output_note = random_output_note(&mut rng, Nullifier::random(&mut rng));
+        let input_merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let input_anchor = input_note.calculate_root(&input_merkle_path);
+        ActionInfo::new(
+            input_note,
+            input_merkle_path,
+            input_anchor,
+            output_note,
+            RandomSeed::random(&mut rng),
+        );
+    }
 }
@@ -0,0 +1,328 @@
+/// The payment address of a receiver: the auth pk, the rcv pk, the nk commitment and the
+/// compressed receiver vp vk, encoded in bech32m with the "taiga" hrp, e.g. `taiga1...`.
+/// The notes sent to the address are authorized by the auth pk and encrypted to the rcv pk, see
+/// `IncomingViewingKey::to_payment_address`.
+/// The address doesn't fit the 90 characters limit of BIP-173, so the limit is not enforced.
+use crate::constant::PAYMENT_ADDRESS_HRP;
+use crate::error::{PaymentAddressError, PointEncodingError};
+use crate::nullifier::NullifierKeyContainer;
+use crate::utils::{non_identity_point_from_bytes, point_to_bytes};
+use ff::PrimeField;
+use group::Group;
+use pasta_curves::pallas;
+use std::fmt;
+use std::str::FromStr;
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32M_CONST: u32 = 0x2bc830a3;
+const BECH32_CHECKSUM_LEN: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaymentAddress {
+    auth_pk: pallas::Point,
+    rcv_pk: pallas::Point,
+    nk_com: pallas::Base,
+    receiver_vp_vk: pallas::Base,
+}
+
+impl PaymentAddress {
+    // The identity can't be encoded in the app_data_dynamic, so it's rejected for both pks
+    pub fn new(
+        auth_pk: pallas::Point,
+        rcv_pk: pallas::Point,
+        nk_com: pallas::Base,
+        receiver_vp_vk: pallas::Base,
+    ) -> Result<Self, PaymentAddressError> {
+        if bool::from(auth_pk.is_identity()) || bool::from(rcv_pk.is_identity()) {
+            return Err(PaymentAddressError::InvalidPk(PointEncodingError::Identity));
+        }
+        Ok(Self {
+            auth_pk,
+            rcv_pk,
+            nk_com,
+            receiver_vp_vk,
+        })
+    }
+
+    pub fn get_auth_pk(&self) -> pallas::Point {
+        self.auth_pk
+    }
+
+    pub fn get_rcv_pk(&self) -> pallas::Point {
+        self.rcv_pk
+    }
+
+    pub fn get_nk_commitment(&self) -> pallas::Base {
+        self.nk_com
+    }
+
+    // The nk container of the notes sent to the address
+    pub fn get_nk_container(&self) -> NullifierKeyContainer {
+        NullifierKeyContainer::from_commitment(self.nk_com)
+    }
+
+    pub fn get_receiver_vp_vk(&self) -> pallas::Base {
+        self.receiver_vp_vk
+    }
+
+    // auth_pk || rcv_pk || nk_com || receiver_vp_vk
+    pub fn to_bytes(&self) -> [u8; 128] {
+        let mut bytes = [0u8; 128];
+        bytes[..32].copy_from_slice(&point_to_bytes(&self.auth_pk));
+        bytes[32..64].copy_from_slice(&point_to_bytes(&self.rcv_pk));
+        bytes[64..96].copy_from_slice(&self.nk_com.to_repr());
+        bytes[96..].copy_from_slice(&self.receiver_vp_vk.to_repr());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; 128]) -> Result<Self, PaymentAddressError> {
+        let auth_pk = non_identity_point_from_bytes(bytes[..32].try_into().unwrap())
+            .map_err(PaymentAddressError::InvalidPk)?;
+        let rcv_pk = non_identity_point_from_bytes(bytes[32..64].try_into().unwrap())
+            .map_err(PaymentAddressError::InvalidPk)?;
+        let nk_com = Option::from(pallas::Base::from_repr(bytes[64..96].try_into().unwrap()))
+            .ok_or(PaymentAddressError::NonCanonicalField)?;
+        let receiver_vp_vk = Option::from(pallas::Base::from_repr(bytes[96..].try_into().unwrap()))
+            .ok_or(PaymentAddressError::NonCanonicalField)?;
+        Ok(Self {
+            auth_pk,
+            rcv_pk,
+            nk_com,
+            receiver_vp_vk,
+        })
+    }
+}
+
+impl fmt::Display for PaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&bech32m_encode(PAYMENT_ADDRESS_HRP, &self.to_bytes()))
+    }
+}
+
+impl FromStr for PaymentAddress {
+    type Err = PaymentAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = bech32m_decode(PAYMENT_ADDRESS_HRP, s)?;
+        let bytes: [u8; 128] = bytes
+            .try_into()
+            .map_err(|_| PaymentAddressError::InvalidLength)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATORS: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for v in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ u32::from(*v);
+        for (i, generator) in GENERATORS.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values
+}
+
+// 8-bit bytes to 5-bit groups, the last group is padded with zeros
+fn to_base32(bytes: &[u8]) -> Vec<u8> {
+    let mut groups = vec![];
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    for b in bytes {
+        acc = (acc << 8) | u32::from(*b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            groups.push(((acc >> bits) & 31) as u8);
+        }
+        acc &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        groups.push(((acc << (5 - bits)) & 31) as u8);
+    }
+    groups
+}
+
+// 5-bit groups to 8-bit bytes, the padding must be less than a group and zero
+fn from_base32(groups: &[u8]) -> Result<Vec<u8>, PaymentAddressError> {
+    let mut bytes = vec![];
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    for g in groups {
+        acc = (acc << 5) | u32::from(*g);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push(((acc >> bits) & 0xff) as u8);
+        }
+        acc &= (1 << bits) - 1;
+    }
+    if bits >= 5 || acc != 0 {
+        return Err(PaymentAddressError::InvalidLength);
+    }
+    Ok(bytes)
+}
+
+fn bech32m_encode(hrp: &str, bytes: &[u8]) -> String {
+    let data = to_base32(bytes);
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend(&data);
+    values.extend([0u8; BECH32_CHECKSUM_LEN]);
+    let polymod = bech32_polymod(&values) ^ BECH32M_CONST;
+    let checksum = (0..BECH32_CHECKSUM_LEN).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8);
+
+    let mut s = String::from(hrp);
+    s.push('1');
+    s.extend(
+        data.into_iter()
+            .chain(checksum)
+            .map(|v| BECH32_CHARSET[v as usize] as char),
+    );
+    s
+}
+
+fn bech32m_decode(hrp: &str, s: &str) -> Result<Vec<u8>, PaymentAddressError> {
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(PaymentAddressError::MixedCase);
+    }
+    let s = s.to_ascii_lowercase();
+    let separator = s.rfind('1').ok_or(PaymentAddressError::MissingSeparator)?;
+    if &s[..separator] != hrp {
+        return Err(PaymentAddressError::InvalidHrp);
+    }
+    let data = s[separator + 1..]
+        .chars()
+        .map(|c| {
+            BECH32_CHARSET
+                .iter()
+                .position(|&v| v as char == c)
+                .map(|v| v as u8)
+                .ok_or(PaymentAddressError::InvalidCharacter(c))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    if data.len() < BECH32_CHECKSUM_LEN {
+        return Err(PaymentAddressError::InvalidLength);
+    }
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend(&data);
+    if bech32_polymod(&values) != BECH32M_CONST {
+        return Err(PaymentAddressError::InvalidChecksum);
+    }
+    from_base32(&data[..data.len() - BECH32_CHECKSUM_LEN])
+}
+
+#[cfg(test)]
+fn random_payment_address<R: rand::RngCore>(mut rng: R) -> PaymentAddress {
+    use ff::Field;
+    PaymentAddress::new(
+        pallas::Point::random(&mut rng),
+        pallas::Point::random(&mut rng),
+        pallas::Base::random(&mut rng),
+        pallas::Base::random(&mut rng),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_payment_address_round_trip() {
+    use rand::rngs::OsRng;
+
+    let addr = random_payment_address(OsRng);
+    let s = addr.to_string();
+    assert!(s.starts_with("taiga1"));
+    assert_eq!(s.parse::<PaymentAddress>(), Ok(addr));
+    // The upper case address is the same address
+    assert_eq!(s.to_ascii_uppercase().parse::<PaymentAddress>(), Ok(addr));
+}
+
+#[test]
+fn test_payment_address_checksum() {
+    use rand::rngs::OsRng;
+
+    let s = random_payment_address(OsRng).to_string();
+    // Any one character typo of the data is detected
+    for i in "taiga1".len()..s.len() {
+        let mut corrupted = s.clone().into_bytes();
+        corrupted[i] = if corrupted[i] == b'q' { b'p' } else { b'q' };
+        let corrupted = String::from_utf8(corrupted).unwrap();
+        assert_eq!(
+            corrupted.parse::<PaymentAddress>(),
+            Err(PaymentAddressError::InvalidChecksum)
+        );
+    }
+
+    // The bech32 checksum is not the bech32m one
+    let mut values = bech32_hrp_expand("taiga");
+    let data: Vec<u8> = s["taiga1".len()..s.len() - BECH32_CHECKSUM_LEN]
+        .chars()
+        .map(|c| BECH32_CHARSET.iter().position(|&v| v as char == c).unwrap() as u8)
+        .collect();
+    values.extend(&data);
+    values.extend([0u8; BECH32_CHECKSUM_LEN]);
+    let polymod = bech32_polymod(&values) ^ 1;
+    let mut bech32 = s[..s.len() - BECH32_CHECKSUM_LEN].to_string();
+    bech32.extend(
+        (0..BECH32_CHECKSUM_LEN)
+            .map(|i| BECH32_CHARSET[((polymod >> (5 * (5 - i))) & 31) as usize] as char),
+    );
+    assert_eq!(
+        bech32.parse::<PaymentAddress>(),
+        Err(PaymentAddressError::InvalidChecksum)
+    );
+}
+
+#[test]
+fn test_payment_address_parse_errors() {
+    use rand::rngs::OsRng;
+
+    let addr = random_payment_address(OsRng);
+    let s = addr.to_string();
+
+    let mixed_case = format!("TAIGA{}", &s["taiga".len()..]);
+    assert_eq!(
+        mixed_case.parse::<PaymentAddress>(),
+        Err(PaymentAddressError::MixedCase)
+    );
+    assert_eq!(
+        "taigaqpzry".parse::<PaymentAddress>(),
+        Err(PaymentAddressError::MissingSeparator)
+    );
+    assert_eq!(
+        bech32m_encode("zcash", &addr.to_bytes()).parse::<PaymentAddress>(),
+        Err(PaymentAddressError::InvalidHrp)
+    );
+    assert_eq!(
+        format!("{}b", &s).parse::<PaymentAddress>(),
+        Err(PaymentAddressError::InvalidCharacter('b'))
+    );
+    assert_eq!(
+        bech32m_encode("taiga", &addr.to_bytes()[..64]).parse::<PaymentAddress>(),
+        Err(PaymentAddressError::InvalidLength)
+    );
+
+    // A valid checksum doesn't make a valid address
+    for range in [0..32, 32..64] {
+        let mut bytes = addr.to_bytes();
+        bytes[range].copy_from_slice(&point_to_bytes(&pallas::Point::identity()));
+        assert_eq!(
+            bech32m_encode("taiga", &bytes).parse::<PaymentAddress>(),
+            Err(PaymentAddressError::InvalidPk(PointEncodingError::Identity))
+        );
+    }
+    let mut bytes = addr.to_bytes();
+    bytes[96..].copy_from_slice(&[0xff; 32]);
+    assert_eq!(
+        bech32m_encode("taiga", &bytes).parse::<PaymentAddress>(),
+        Err(PaymentAddressError::NonCanonicalField)
+    );
+}
@@ -0,0 +1,151 @@
+/// Commit-reveal of the seed of the vp public input padding.
+/// A ptx built with `ShieldedPartialTransaction::build_with_padding_seed` derives the padding of
+/// its vp proofs from a committed seed and records the commitment in its extra data. Once the
+/// opening is revealed, a coordinator can check with `audit_padding` that the padding is the one
+/// derived from the seed, i.e. no covert channel is hidden in the random looking values.
+use crate::circuit::vp_circuit::ValidityPredicatePublicInputs;
+use crate::constant::PADDING_SEED_COMMITMENT_PERSONALIZATION;
+use crate::note::RandomSeed;
+use crate::transaction::Transaction;
+use blake2s_simd::Params as Blake2sParams;
+use pasta_curves::pallas;
+use rand::{rngs::StdRng, SeedableRng};
+
+/// The Blake2s commitment to the opening of a padding seed, see `RandomSeed::from_commitment`.
+pub fn commit_padding_seed(opening: &[u8]) -> [u8; 32] {
+    let h = Blake2sParams::new()
+        .hash_length(32)
+        .personal(PADDING_SEED_COMMITMENT_PERSONALIZATION)
+        .hash(opening);
+    h.as_bytes().try_into().unwrap()
+}
+
+// The rng the vp_index-th vp of the owned note draws its public input padding from.
+pub(crate) fn vp_padding_rng(
+    padding_seed: &RandomSeed,
+    owned_note_pub_id: &pallas::Base,
+    vp_index: usize,
+) -> StdRng {
+    StdRng::from_seed(padding_seed.get_vp_padding_seed(owned_note_pub_id, vp_index))
+}
+
+// The vps draw one RandomSeed from the rng and pad the public inputs up to padding_end with it.
+// The padding length is not known, but only the honest one matches the derived padding.
+pub(crate) fn check_vp_padding(
+    public_inputs: &ValidityPredicatePublicInputs,
+    padding_end: usize,
+    mut padding_rng: StdRng,
+) -> bool {
+    let padding = RandomSeed::random(&mut padding_rng).get_random_padding(padding_end);
+    let public_inputs = &public_inputs.inner()[..padding_end];
+    (1..padding_end).any(|len| public_inputs[padding_end - len..] == padding[..len])
+}
+
+/// Whether the vp public input padding of the ptxs committed to the seed of `opening` is derived
+/// from the seed. It's false if no ptx of the transaction records the seed commitment.
+pub fn audit_padding(tx: &Transaction, opening: &[u8]) -> bool {
+    let seed_commitment = commit_padding_seed(opening);
+    let padding_seed = RandomSeed::from_commitment(seed_commitment, opening).unwrap();
+    let mut audited_ptxs = tx
+        .get_shielded_ptx_bundle()
+        .partial_txs()
+        .iter()
+        .filter(|ptx| ptx.get_extra_data().starts_with(&seed_commitment))
+        .peekable();
+    audited_ptxs.peek().is_some() && audited_ptxs.all(|ptx| ptx.check_padding(&padding_seed))
+}
+
+#[test]
+fn test_padding_seed_commitment() {
+    let opening = b"coordinator round 7";
+    let seed_commitment = commit_padding_seed(opening);
+    let seed = RandomSeed::from_commitment(seed_commitment, opening).unwrap();
+    assert_eq!(
+        RandomSeed::from_commitment(seed_commitment, opening),
+        Some(seed)
+    );
+    assert_eq!(
+        RandomSeed::from_commitment(seed_commitment, b"coordinator round 8"),
+        None
+    );
+    // The seed is not the commitment
+    assert_ne!(seed, RandomSeed::from_bytes(seed_commitment));
+}
+
+#[test]
+fn test_audit_padding() {
+    use crate::circuit::vp_circuit::ValidityPredicateVerifyingInfo;
+    use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
+    use crate::constant::VP_CIRCUIT_PUBLIC_INPUT_NUM;
+    use crate::error::TransactionError;
+    use crate::note::NoteType;
+    use crate::shielded_ptx::{
+        testing::{create_trivial_swap_proving_info, set_vp_public_input},
+        ShieldedPartialTransaction,
+    };
+    use crate::transaction::{ShieldedPartialTxBundle, TransparentPartialTxBundle};
+    use rand::rngs::OsRng;
+
+    let compressed_trivial_vp_vk = TrivialValidityPredicateCircuit::default()
+        .get_vp_vk()
+        .get_compressed();
+    let note_type = NoteType::new(compressed_trivial_vp_vk, pallas::Base::zero());
+    let swap_info = || {
+        create_trivial_swap_proving_info((note_type, 5), (note_type, 5), &RandomSeed::random(OsRng))
+    };
+    let build_tx = |ptx: ShieldedPartialTransaction| {
+        Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(vec![ptx]),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap()
+    };
+
+    let opening = b"coordinator round 7";
+    let seed_commitment = commit_padding_seed(opening);
+    let (input_info, output_info) = swap_info();
+    assert!(matches!(
+        ShieldedPartialTransaction::build_with_padding_seed(
+            input_info.clone(),
+            output_info.clone(),
+            vec![],
+            seed_commitment,
+            b"coordinator round 8",
+            OsRng,
+        ),
+        Err(TransactionError::InvalidPaddingSeedOpening)
+    ));
+    let ptx = ShieldedPartialTransaction::build_with_padding_seed(
+        input_info,
+        output_info,
+        vec![],
+        seed_commitment,
+        opening,
+        OsRng,
+    )
+    .unwrap();
+    assert_eq!(ptx.get_extra_data(), seed_commitment.to_vec());
+
+    // The honest padding is audited
+    let tx = build_tx(ptx.clone());
+    tx.execute().unwrap();
+    assert!(audit_padding(&tx, opening));
+    assert!(!audit_padding(&tx, b"coordinator round 8"));
+
+    // Replace the last padding element of the application vp of the first output note
+    let mut tampered_ptx = ptx;
+    set_vp_public_input(
+        &mut tampered_ptx,
+        2,
+        0,
+        VP_CIRCUIT_PUBLIC_INPUT_NUM - 1,
+        pallas::Base::from(7),
+    );
+    assert!(!audit_padding(&build_tx(tampered_ptx), opening));
+
+    // The ptx doesn't record the seed commitment
+    let (input_info, output_info) = swap_info();
+    let ptx = ShieldedPartialTransaction::build(input_info, output_info, vec![], OsRng).unwrap();
+    assert!(!audit_padding(&build_tx(ptx), opening));
+}
@@ -1,6 +1,7 @@
 use crate::constant::NOTE_COMMITMENT_R_GENERATOR;
+use crate::utils::{non_identity_point_from_bytes, point_from_bytes, point_to_bytes};
 use pasta_curves::group::cofactor::CofactorCurveAffine;
-use pasta_curves::group::{ff::PrimeField, GroupEncoding};
+use pasta_curves::group::ff::PrimeField;
 use pasta_curves::pallas;
 use rand::{CryptoRng, RngCore};
 use reddsa::{private, Error, SigType, Signature, SigningKey, VerificationKey};
@@ -65,8 +66,16 @@ impl BorshSerialize for BindingSignature {
 #[cfg(feature = "borsh")]
 impl BorshDeserialize for BindingSignature {
     fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        use std::io;
         let mut sig_bytes = [0u8; 64];
         reader.read_exact(&mut sig_bytes)?;
+        // R must be a canonical point and s a canonical scalar
+        point_from_bytes(sig_bytes[..32].try_into().unwrap())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Option::<pallas::Scalar>::from(pallas::Scalar::from_repr(
+            sig_bytes[32..].try_into().unwrap(),
+        ))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "s not in field"))?;
         Ok(Self::from_bytes(sig_bytes))
     }
 }
@@ -119,10 +128,21 @@ impl BindingVerificationKey {
     pub fn verify(&self, msg: &[u8], signature: &BindingSignature) -> Result<(), Error> {
         self.0.verify(msg, &signature.0)
     }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.into()
+    }
+
+    // The key must be the canonical encoding of a non-identity point
+    pub fn from_bytes(bytes: [u8; 32]) -> Result<Self, Error> {
+        non_identity_point_from_bytes(&bytes).map_err(|_| Error::MalformedVerificationKey)?;
+        let vk = VerificationKey::<TaigaBinding>::try_from(bytes)?;
+        Ok(Self(vk))
+    }
 }
 
 impl From<pallas::Point> for BindingVerificationKey {
     fn from(p: pallas::Point) -> Self {
-        BindingVerificationKey(p.to_bytes().try_into().unwrap())
+        BindingVerificationKey(point_to_bytes(&p).try_into().unwrap())
     }
 }
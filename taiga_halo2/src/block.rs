@@ -0,0 +1,475 @@
+//! Block validation: verifies the transactions of a block together and applies them in order.
+//!
+//! The proofs of all the transactions are checked in batches, one per verifying key, which costs
+//! one multi-scalar multiplication per batch instead of one per proof. A failed batch doesn't say
+//! which proof is invalid, the transactions are then re-verified one by one to find it.
+use crate::constant::{
+    get_params, ACTION_CIRCUIT_PARAMS_SIZE, ACTION_VERIFYING_KEY,
+    AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE, AGGREGATED_ACTION_VERIFYING_KEY, VP_CIRCUIT_PARAMS_SIZE,
+};
+use crate::error::TransactionError;
+use crate::mempool::Mempool;
+use crate::merkle_tree::Anchor;
+use crate::note::NoteCommitment;
+use crate::note_encryption::EncryptedNote;
+use crate::nullifier::Nullifier;
+use crate::proof::Proof;
+use crate::state::StateDiff;
+use crate::state::{StateError, StateObserver, TaigaState};
+use crate::transaction::{ShieldedPartialTxBundle, Transaction};
+use crate::vp_vk::ValidityPredicateVerifyingKey;
+use ff::PrimeField;
+use halo2_proofs::plonk::{BatchVerifier, VerifyingKey};
+use pasta_curves::{pallas, vesta};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The transaction at `tx_index` in the block is invalid.
+#[derive(Debug)]
+pub struct BlockVerificationError {
+    pub tx_index: usize,
+    pub error: TransactionError,
+}
+
+impl fmt::Display for BlockVerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format!(
+            "Transaction {} is invalid: {}",
+            self.tx_index, self.error
+        ))
+    }
+}
+
+// The proofs of a block, grouped by verifying key
+pub(crate) struct BlockProofBatch {
+    actions: BatchVerifier<vesta::Affine>,
+    // None until an aggregated action proof is added, so its key is only generated when used
+    aggregated_actions: Option<BatchVerifier<vesta::Affine>>,
+    // Keyed by the compressed vp vk
+    vps: HashMap<[u8; 32], (VerifyingKey<vesta::Affine>, BatchVerifier<vesta::Affine>)>,
+}
+
+impl BlockProofBatch {
+    pub(crate) fn new() -> Self {
+        Self {
+            actions: BatchVerifier::new(),
+            aggregated_actions: None,
+            vps: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn add_action_proof(&mut self, proof: &Proof, instance: Vec<pallas::Base>) {
+        self.actions.add_proof(vec![vec![instance]], proof.inner());
+    }
+
+    pub(crate) fn add_aggregated_action_proof(
+        &mut self,
+        proof: &Proof,
+        instance: Vec<pallas::Base>,
+    ) {
+        self.aggregated_actions
+            .get_or_insert_with(BatchVerifier::new)
+            .add_proof(vec![vec![instance]], proof.inner());
+    }
+
+    pub(crate) fn add_vp_proof(
+        &mut self,
+        vk: &VerifyingKey<vesta::Affine>,
+        proof: &Proof,
+        instance: Vec<pallas::Base>,
+    ) {
+        let key = ValidityPredicateVerifyingKey::compress(vk).to_repr();
+        self.vps
+            .entry(key)
+            .or_insert_with(|| (vk.clone(), BatchVerifier::new()))
+            .1
+            .add_proof(vec![vec![instance]], proof.inner());
+    }
+
+    // Whether all the proofs are valid
+    pub(crate) fn finalize(self) -> bool {
+        let action_params = get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+        let vp_params = get_params(VP_CIRCUIT_PARAMS_SIZE);
+        self.actions.finalize(&action_params, &ACTION_VERIFYING_KEY)
+            && self.aggregated_actions.into_iter().all(|batch| {
+                batch.finalize(
+                    &get_params(AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE),
+                    &AGGREGATED_ACTION_VERIFYING_KEY,
+                )
+            })
+            && self
+                .vps
+                .into_values()
+                .all(|(vk, batch)| batch.finalize(&vp_params, &vk))
+    }
+}
+
+/// Verifies the transactions of a block and applies them to the state in order, returning the
+/// state diff of each transaction.
+///
+/// The anchors must be known to the state before the block, and a nullifier can only be revealed
+/// once in the block. All the checks run before the state is touched, so an invalid block leaves
+/// the state unchanged. The error points at the first invalid transaction.
+pub fn verify_block(
+    txs: &[Transaction],
+    state: &mut impl TaigaState,
+) -> Result<Vec<StateDiff>, BlockVerificationError> {
+    verify_block_with_observer(txs, state, &mut ())
+}
+
+/// Same as verify_block, the observer sees the state changes of the transactions in order. It's
+/// not called if a check fails.
+pub fn verify_block_with_observer(
+    txs: &[Transaction],
+    state: &mut impl TaigaState,
+    observer: &mut dyn StateObserver,
+) -> Result<Vec<StateDiff>, BlockVerificationError> {
+    let at =
+        |tx_index: usize| move |error: TransactionError| BlockVerificationError { tx_index, error };
+
+    for (i, tx) in txs.iter().enumerate() {
+        tx.check_public_data().map_err(at(i))?;
+    }
+
+    let mut batch = BlockProofBatch::new();
+    txs.iter()
+        .for_each(|tx| tx.get_shielded_ptx_bundle().add_proofs_to_batch(&mut batch));
+    if !batch.finalize() {
+        // Find the invalid proof
+        for (i, tx) in txs.iter().enumerate() {
+            tx.verify_proofs().map_err(at(i))?;
+        }
+    }
+
+    let mut block_nfs = HashSet::new();
+    for (i, tx) in txs.iter().enumerate() {
+        if tx
+            .get_anchors()
+            .iter()
+            .any(|anchor| !state.is_known_anchor(anchor))
+        {
+            return Err(at(i)(TransactionError::UnknownAnchor));
+        }
+        for nf in tx.get_nullifiers() {
+            // Spent before the block, or by an earlier transaction of the block
+            if state.contains_nullifier(&nf) || !block_nfs.insert(nf.to_bytes()) {
+                return Err(at(i)(TransactionError::NullifierAlreadySpent));
+            }
+        }
+    }
+
+    // The anchors were checked against the state before the block, the state root moves with
+    // each applied transaction so the diffs are applied directly
+    txs.iter()
+        .enumerate()
+        .map(|(i, tx)| {
+            let diff = tx.state_diff();
+            state
+                .apply_diff_with_observer(diff.clone(), observer)
+                .map_err(|e| at(i)(e.into()))?;
+            Ok(diff)
+        })
+        .collect()
+}
+
+/// A block applied to the state, with the state diff of each transaction.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub height: u64,
+    pub txs: Vec<Transaction>,
+    pub diffs: Vec<StateDiff>,
+    // The root of the commitment tree after the block
+    pub root: Anchor,
+}
+
+/// Builds the blocks from the mempool transactions.
+#[derive(Debug, Clone)]
+pub struct BlockBuilder {
+    // The height of the next block
+    height: u64,
+    max_txs: usize,
+}
+
+impl BlockBuilder {
+    pub fn new(max_txs: usize) -> Self {
+        Self { height: 0, max_txs }
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Selects the mempool transactions in the submission order and applies them to the state.
+    ///
+    /// A transaction spending a nullifier of an earlier selected transaction stays in the
+    /// mempool for the next block, where it's usually pruned as its nullifier is spent. The
+    /// transactions with a spent nullifier or an unknown anchor are dropped. The proofs were
+    /// checked by the mempool, they are verified again with `verify_block`. If it fails, the
+    /// selected transactions are dropped and the state is unchanged.
+    pub fn build_block(
+        &mut self,
+        mempool: &mut Mempool,
+        state: &mut impl TaigaState,
+    ) -> Result<Block, BlockVerificationError> {
+        self.build_block_with_observer(mempool, state, &mut ())
+    }
+
+    /// Same as build_block, the observer sees the state changes of the block, with the block
+    /// height on the anchors.
+    pub fn build_block_with_observer(
+        &mut self,
+        mempool: &mut Mempool,
+        state: &mut impl TaigaState,
+        observer: &mut dyn StateObserver,
+    ) -> Result<Block, BlockVerificationError> {
+        let mut selected = vec![];
+        let mut deferred = vec![];
+        let mut bundle = ShieldedPartialTxBundle::default();
+        for tx in mempool.take_txs() {
+            if tx
+                .get_nullifiers()
+                .iter()
+                .any(|nf| state.contains_nullifier(nf))
+                || tx
+                    .get_anchors()
+                    .iter()
+                    .any(|anchor| !state.is_known_anchor(anchor))
+            {
+                continue;
+            }
+            let ptxs = tx.get_shielded_ptx_bundle().partial_txs();
+            if selected.len() == self.max_txs
+                || ptxs.iter().any(|ptx| bundle.conflicts_with(ptx).is_some())
+            {
+                deferred.push(tx);
+                continue;
+            }
+            ptxs.iter()
+                .for_each(|ptx| bundle.add_partial_tx(ptx.clone()));
+            selected.push(tx);
+        }
+        mempool.return_txs(deferred);
+
+        let mut block_state = AtHeight {
+            state,
+            height: self.height,
+        };
+        let diffs = verify_block_with_observer(&selected, &mut block_state, observer)?;
+        let block = Block {
+            height: self.height,
+            txs: selected,
+            diffs,
+            root: state.root(),
+        };
+        self.height += 1;
+        Ok(block)
+    }
+}
+
+// The state while the block at the height is applied
+struct AtHeight<'a, S: TaigaState> {
+    state: &'a mut S,
+    height: u64,
+}
+
+impl<'a, S: TaigaState> TaigaState for AtHeight<'a, S> {
+    fn is_known_anchor(&self, anchor: &Anchor) -> bool {
+        self.state.is_known_anchor(anchor)
+    }
+
+    fn contains_nullifier(&self, nf: &Nullifier) -> bool {
+        self.state.contains_nullifier(nf)
+    }
+
+    fn insert_nullifier(&mut self, nf: Nullifier) -> Result<(), StateError> {
+        self.state.insert_nullifier(nf)
+    }
+
+    fn append_commitment(&mut self, cm: NoteCommitment) -> Result<u64, StateError> {
+        self.state.append_commitment(cm)
+    }
+
+    fn root(&self) -> Anchor {
+        self.state.root()
+    }
+
+    fn record_anchor(&mut self, anchor: Anchor) {
+        self.state.record_anchor(anchor)
+    }
+
+    fn store_ciphertexts(&mut self, ciphertexts: Vec<(NoteCommitment, EncryptedNote)>) {
+        self.state.store_ciphertexts(ciphertexts)
+    }
+
+    fn height(&self) -> Option<u64> {
+        Some(self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shielded_ptx::testing::{corrupt_action_proof, create_shielded_ptx};
+    use crate::shielded_ptx::ShieldedPartialTransaction;
+    use crate::state::{CheckpointedState, StateEvent, VecObserver};
+    use crate::transaction::{ShieldedPartialTxBundle, TransparentPartialTxBundle};
+    use rand::rngs::OsRng;
+
+    // The test ptxs use random anchors, the state accepts the given ones
+    struct BlockState {
+        anchors: Vec<Anchor>,
+        inner: CheckpointedState,
+    }
+
+    impl TaigaState for BlockState {
+        fn is_known_anchor(&self, anchor: &Anchor) -> bool {
+            self.anchors.contains(anchor)
+        }
+
+        fn contains_nullifier(&self, nf: &Nullifier) -> bool {
+            self.inner.contains_nullifier(nf)
+        }
+
+        fn insert_nullifier(&mut self, nf: Nullifier) -> Result<(), StateError> {
+            self.inner.insert_nullifier(nf)
+        }
+
+        fn append_commitment(&mut self, cm: NoteCommitment) -> Result<u64, StateError> {
+            self.inner.append(cm)
+        }
+
+        fn root(&self) -> Anchor {
+            self.inner.root()
+        }
+    }
+
+    fn build_tx(ptx: ShieldedPartialTransaction) -> Transaction {
+        Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(vec![ptx]),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap()
+    }
+
+    fn block_state(txs: &[Transaction]) -> BlockState {
+        BlockState {
+            anchors: txs.iter().flat_map(|tx| tx.get_anchors()).collect(),
+            inner: CheckpointedState::default(),
+        }
+    }
+
+    #[test]
+    fn test_verify_block() {
+        let ptxs: Vec<_> = (0..3).map(|_| create_shielded_ptx()).collect();
+        let txs: Vec<_> = ptxs.iter().cloned().map(build_tx).collect();
+
+        // A valid block
+        let mut state = block_state(&txs);
+        let diffs = verify_block(&txs, &mut state).unwrap();
+        assert_eq!(diffs.len(), 3);
+        for (tx, diff) in txs.iter().zip(diffs.iter()) {
+            assert_eq!(diff.spent_nullifiers, tx.get_nullifiers());
+            assert_eq!(
+                diff.new_commitments,
+                tx.get_shielded_ptx_bundle().get_output_cms()
+            );
+        }
+        assert_eq!(state.inner.size(), 6);
+
+        // The third tx spends the notes of the second one again
+        let double_spend = vec![txs[0].clone(), txs[1].clone(), build_tx(ptxs[1].clone())];
+        let mut state = block_state(&double_spend);
+        let err = verify_block(&double_spend, &mut state).unwrap_err();
+        assert_eq!(err.tx_index, 2);
+        assert!(matches!(err.error, TransactionError::NullifierAlreadySpent));
+        assert_eq!(state.inner.size(), 0);
+
+        // The second tx has an invalid action proof
+        let mut corrupted_ptx = ptxs[1].clone();
+        corrupt_action_proof(&mut corrupted_ptx, 1);
+        let corrupted = vec![txs[0].clone(), build_tx(corrupted_ptx), txs[2].clone()];
+        let mut state = block_state(&corrupted);
+        let err = verify_block(&corrupted, &mut state).unwrap_err();
+        assert_eq!(err.tx_index, 1);
+        assert!(matches!(
+            err.error,
+            TransactionError::ActionProofVerificationFailure {
+                ptx_index: 0,
+                action_index: 1
+            }
+        ));
+        assert_eq!(state.inner.size(), 0);
+    }
+
+    #[test]
+    fn test_block_builder() {
+        let ptxs: Vec<_> = (0..3).map(|_| create_shielded_ptx()).collect();
+        let txs: Vec<_> = ptxs.iter().cloned().map(build_tx).collect();
+        // Spends the notes of the second tx again
+        let double_spend = Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(vec![ptxs[1].clone(), ptxs[2].clone()]),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+
+        let mut mempool = Mempool::new();
+        for tx in [txs[0].clone(), txs[1].clone(), double_spend] {
+            mempool.submit_tx(tx).unwrap();
+        }
+        let mut state = block_state(&txs);
+        let mut builder = BlockBuilder::new(10);
+
+        // The double spend waits for the next block, then it's dropped
+        let mut observer = VecObserver::default();
+        let block = builder
+            .build_block_with_observer(&mut mempool, &mut state, &mut observer)
+            .unwrap();
+        assert_eq!(block.height, 0);
+        assert_eq!(block.txs.len(), 2);
+        assert_eq!(block.diffs.len(), 2);
+        assert_eq!(block.root, state.root());
+        assert_eq!(mempool.txs().len(), 1);
+
+        // The observer sees the nullifiers of the txs, and the anchors at the block height
+        let spent: Vec<_> = observer
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                StateEvent::NullifierSpent { nf, tx_id } => Some((*nf, *tx_id)),
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<_> = block
+            .txs
+            .iter()
+            .flat_map(|tx| {
+                tx.get_nullifiers()
+                    .into_iter()
+                    .map(move |nf| (nf, Some(tx.id())))
+            })
+            .collect();
+        assert_eq!(spent, expected);
+        assert_eq!(
+            observer.events.last(),
+            Some(&StateEvent::Anchor {
+                anchor: block.root,
+                height: Some(0)
+            })
+        );
+        let block = builder.build_block(&mut mempool, &mut state).unwrap();
+        assert_eq!(block.height, 1);
+        assert!(block.txs.is_empty());
+        assert!(mempool.txs().is_empty());
+
+        // One tx per block
+        mempool.submit_tx(txs[2].clone()).unwrap();
+        mempool.submit_tx(build_tx(create_shielded_ptx())).unwrap();
+        let mut state = block_state(&[txs[2].clone(), mempool.txs()[1].clone()]);
+        let mut builder = BlockBuilder::new(1);
+        let block = builder.build_block(&mut mempool, &mut state).unwrap();
+        assert_eq!(block.txs[0].id(), txs[2].id());
+        assert_eq!(mempool.txs().len(), 1);
+    }
+}
@@ -1,55 +1,73 @@
 use crate::circuit::blake2s::{vp_commitment_gadget, Blake2sChip, Blake2sConfig};
-use crate::circuit::gadgets::assign_free_advice;
+use crate::circuit::gadgets::{
+    add::{AddChip, AddConfig},
+    assign_free_advice,
+};
 use crate::circuit::hash_to_curve::HashToCurveConfig;
-use crate::circuit::integrity::{check_input_note, check_output_note, compute_value_commitment};
-use crate::circuit::merkle_circuit::{
-    merkle_poseidon_gadget, MerklePoseidonChip, MerklePoseidonConfig,
+use crate::circuit::integrity::{
+    check_input_note, check_output_note, check_value_range, compute_value_commitment,
 };
+use crate::circuit::merkle_circuit::MerkleHashChip;
+use crate::circuit::note_encryption_circuit::{cipher_commitment_gadget, note_ciphertext_gadget};
 use crate::constant::{
-    TaigaFixedBases, ACTION_ANCHOR_PUBLIC_INPUT_ROW_IDX, ACTION_INPUT_VP_CM_1_ROW_IDX,
-    ACTION_INPUT_VP_CM_2_ROW_IDX, ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX,
-    ACTION_NET_VALUE_CM_Y_PUBLIC_INPUT_ROW_IDX, ACTION_NF_PUBLIC_INPUT_ROW_IDX,
+    TaigaFixedBases, ACTION_ANCHOR_PUBLIC_INPUT_ROW_IDX, ACTION_CIPHER_COMMITMENT_ROW_IDX,
+    ACTION_INPUT_APP_VK_ROW_IDX, ACTION_INPUT_VP_CM_1_ROW_IDX, ACTION_INPUT_VP_CM_2_ROW_IDX,
+    ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX, ACTION_NET_VALUE_CM_Y_PUBLIC_INPUT_ROW_IDX,
+    ACTION_NF_PUBLIC_INPUT_ROW_IDX, ACTION_OUTPUT_APP_VK_ROW_IDX,
     ACTION_OUTPUT_CM_PUBLIC_INPUT_ROW_IDX, ACTION_OUTPUT_VP_CM_1_ROW_IDX,
-    ACTION_OUTPUT_VP_CM_2_ROW_IDX, TAIGA_COMMITMENT_TREE_DEPTH,
+    ACTION_OUTPUT_VP_CM_2_ROW_IDX, ACTION_PUBLIC_INPUT_NUM, NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH,
 };
-use crate::merkle_tree::LR;
+use crate::merkle_tree::{Node, PoseidonMerkleHash, LR};
 use crate::note::Note;
+use crate::note_encryption::NoteEncryption;
 
+use group::Curve;
 use halo2_gadgets::{
-    ecc::chip::{EccChip, EccConfig},
+    ecc::{
+        chip::{EccChip, EccConfig},
+        NonIdentityPoint,
+    },
     poseidon::{primitives as poseidon, Pow5Chip as PoseidonChip, Pow5Config as PoseidonConfig},
     utilities::lookup_range_check::LookupRangeCheckConfig,
 };
 use halo2_proofs::{
+    arithmetic::Field,
     circuit::{floor_planner, Layouter, Value},
     plonk::{
-        Advice, Circuit, Column, ConstraintSystem, Constraints, Error, Instance, Selector,
-        TableColumn,
+        Advice, Circuit, Column, ConstraintSystem, Constraints, Error, Expression, Instance,
+        Selector, TableColumn,
     },
     poly::Rotation,
 };
 use pasta_curves::pallas;
 
 #[derive(Clone, Debug)]
-pub struct ActionConfig {
+pub struct ActionConfig<H: MerkleHashChip = PoseidonMerkleHash> {
     instances: Column<Instance>,
     advices: [Column<Advice>; 10],
     table_idx: TableColumn,
+    range_check: LookupRangeCheckConfig<pallas::Base, 10>,
     ecc_config: EccConfig<TaigaFixedBases>,
     poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
-    merkle_config: MerklePoseidonConfig,
+    merkle_config: H::Config,
     merkle_path_selector: Selector,
     hash_to_curve_config: HashToCurveConfig,
     blake2s_config: Blake2sConfig<pallas::Base>,
+    add_config: AddConfig,
+    cipher_commitment_selector: Selector,
 }
 
-/// The Action circuit.
-#[derive(Clone, Debug, Default)]
-pub struct ActionCircuit {
+/// The Action circuit of a note commitment tree of depth DEPTH, hashed with H. Each depth has its
+/// own keys, see get_action_proving_key.
+#[derive(Clone, Debug)]
+pub struct ActionCircuit<
+    const DEPTH: usize = TAIGA_COMMITMENT_TREE_DEPTH,
+    H: MerkleHashChip = PoseidonMerkleHash,
+> {
     /// Input note
     pub input_note: Note,
     /// The authorization path of input note
-    pub merkle_path: [(pallas::Base, LR); TAIGA_COMMITMENT_TREE_DEPTH],
+    pub merkle_path: [(Node<H>, LR); DEPTH],
     /// Output note
     pub output_note: Note,
     /// random scalar for net value commitment
@@ -58,10 +76,26 @@ pub struct ActionCircuit {
     pub input_vp_cm_r: pallas::Base,
     /// The randomness for output note application vp commitment
     pub output_vp_cm_r: pallas::Base,
+    /// The encryption of the output note, None if the note is not sent with an encrypted payload
+    pub note_encryption: Option<NoteEncryption>,
+}
+
+impl<const DEPTH: usize, H: MerkleHashChip> Default for ActionCircuit<DEPTH, H> {
+    fn default() -> Self {
+        Self {
+            input_note: Note::default(),
+            merkle_path: [(Node::from(pallas::Base::zero()), LR::default()); DEPTH],
+            output_note: Note::default(),
+            rcv: pallas::Scalar::zero(),
+            input_vp_cm_r: pallas::Base::zero(),
+            output_vp_cm_r: pallas::Base::zero(),
+            note_encryption: None,
+        }
+    }
 }
 
-impl Circuit<pallas::Base> for ActionCircuit {
-    type Config = ActionConfig;
+impl<const DEPTH: usize, H: MerkleHashChip> Circuit<pallas::Base> for ActionCircuit<DEPTH, H> {
+    type Config = ActionConfig<H>;
     type FloorPlanner = floor_planner::V1;
 
     fn without_witnesses(&self) -> Self {
@@ -132,9 +166,12 @@ impl Circuit<pallas::Base> for ActionCircuit {
             )
         });
 
-        let merkle_config = MerklePoseidonChip::configure(
+        let merkle_config = H::configure(
             meta,
-            advices[..5].try_into().unwrap(),
+            advices,
+            lagrange_coeffs[1],
+            table_idx,
+            range_check,
             poseidon_config.clone(),
         );
 
@@ -143,16 +180,45 @@ impl Circuit<pallas::Base> for ActionCircuit {
 
         let blake2s_config = Blake2sConfig::configure(meta, advices);
 
+        let add_config = AddChip::configure(meta, [advices[0], advices[1]]);
+
+        // The sentinel of the notes without an encrypted payload is zero
+        let cipher_commitment_selector = meta.selector();
+        meta.create_gate("cipher commitment check", |meta| {
+            let cipher_commitment_selector = meta.query_selector(cipher_commitment_selector);
+            let is_encrypted = meta.query_advice(advices[0], Rotation::cur());
+            let cipher_commitment = meta.query_advice(advices[1], Rotation::cur());
+            let published = meta.query_advice(advices[2], Rotation::cur());
+            let one = Expression::Constant(pallas::Base::one());
+
+            Constraints::with_selector(
+                cipher_commitment_selector,
+                [
+                    (
+                        "is_encrypted is a bool",
+                        is_encrypted.clone() * (one - is_encrypted.clone()),
+                    ),
+                    (
+                        "published = is_encrypted * cipher_commitment",
+                        published - is_encrypted * cipher_commitment,
+                    ),
+                ],
+            )
+        });
+
         Self::Config {
             instances,
             advices,
             table_idx,
+            range_check,
             ecc_config,
             poseidon_config,
             merkle_config,
             merkle_path_selector,
             hash_to_curve_config,
             blake2s_config,
+            add_config,
+            cipher_commitment_selector,
         }
     }
 
@@ -162,29 +228,28 @@ impl Circuit<pallas::Base> for ActionCircuit {
         mut layouter: impl Layouter<pallas::Base>,
     ) -> Result<(), Error> {
         // Construct an ECC chip
-        let ecc_chip = EccChip::construct(config.ecc_config);
-        layouter.assign_table(
-            || "table_idx",
-            |mut table| {
-                // We generate the row values lazily (we only need them during keygen).
-                for index in 0..(1 << 10) {
-                    table.assign_cell(
-                        || "table_idx",
-                        config.table_idx,
-                        index,
-                        || Value::known(pallas::Base::from(index as u64)),
-                    )?;
-                }
-                Ok(())
-            },
-        )?;
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
 
-        // Construct a merkle chip
-        let merkle_chip = MerklePoseidonChip::construct(config.merkle_config);
+        // Load the lookup tables of the range check and the merkle hash
+        H::load(&config.merkle_config, config.table_idx, &mut layouter)?;
 
         // Construct a blake2s chip
         let blake2s_chip = Blake2sChip::construct(config.blake2s_config);
 
+        self.synthesize_action(&config, &mut layouter, ecc_chip, &blake2s_chip, 0)
+    }
+}
+
+impl<const DEPTH: usize, H: MerkleHashChip> ActionCircuit<DEPTH, H> {
+    // The action constraints, the public inputs are at the instance rows from offset
+    fn synthesize_action(
+        &self,
+        config: &ActionConfig<H>,
+        layouter: &mut impl Layouter<pallas::Base>,
+        ecc_chip: EccChip<TaigaFixedBases>,
+        blake2s_chip: &Blake2sChip<pallas::Base>,
+        offset: usize,
+    ) -> Result<(), Error> {
         // Input note
         // Check the input note commitment
         let input_note_variables = check_input_note(
@@ -193,50 +258,65 @@ impl Circuit<pallas::Base> for ActionCircuit {
             config.instances,
             config.poseidon_config.clone(),
             self.input_note,
-            ACTION_NF_PUBLIC_INPUT_ROW_IDX,
+            offset + ACTION_NF_PUBLIC_INPUT_ROW_IDX,
         )?;
 
         // Check the merkle tree path validity and public the root
-        let root = merkle_poseidon_gadget(
-            layouter.namespace(|| "poseidon merkle"),
-            merkle_chip,
+        let root = H::root_gadget(
+            &config.merkle_config,
+            layouter.namespace(|| "merkle"),
             input_note_variables.cm,
             &self.merkle_path,
         )?;
 
         // Output note
+        // The rho of the output note is the nf of the input note, taken from the nf cell instead of
+        // a witness, so the output note commitments are unique. There is no exception for the
+        // padding notes: a padding output note is created from the nf of the padding input note.
         let output_note_vars = check_output_note(
             layouter.namespace(|| "check output note"),
             config.advices,
             config.instances,
-            config.poseidon_config,
+            config.poseidon_config.clone(),
             self.output_note,
             input_note_variables.nf,
-            ACTION_OUTPUT_CM_PUBLIC_INPUT_ROW_IDX,
+            offset + ACTION_OUTPUT_CM_PUBLIC_INPUT_ROW_IDX,
+        )?;
+
+        // The note values are u64, so the value totals can't wrap around the field
+        check_value_range(
+            layouter.namespace(|| "input value range check"),
+            &config.range_check,
+            &input_note_variables.note_variables.value,
+        )?;
+        check_value_range(
+            layouter.namespace(|| "output value range check"),
+            &config.range_check,
+            &output_note_vars.note_variables.value,
         )?;
 
         // compute and public net value commitment(input_value_commitment - output_value_commitment)
         let cv_net = compute_value_commitment(
             layouter.namespace(|| "net value commitment"),
-            ecc_chip,
+            ecc_chip.clone(),
             config.hash_to_curve_config.clone(),
             input_note_variables.note_variables.app_vk.clone(),
             input_note_variables.note_variables.app_data_static.clone(),
             input_note_variables.note_variables.value.clone(),
             output_note_vars.note_variables.app_vk.clone(),
             output_note_vars.note_variables.app_data_static.clone(),
-            output_note_vars.note_variables.value,
+            output_note_vars.note_variables.value.clone(),
             self.rcv,
         )?;
         layouter.constrain_instance(
             cv_net.inner().x().cell(),
             config.instances,
-            ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX,
+            offset + ACTION_NET_VALUE_CM_X_PUBLIC_INPUT_ROW_IDX,
         )?;
         layouter.constrain_instance(
             cv_net.inner().y().cell(),
             config.instances,
-            ACTION_NET_VALUE_CM_Y_PUBLIC_INPUT_ROW_IDX,
+            offset + ACTION_NET_VALUE_CM_Y_PUBLIC_INPUT_ROW_IDX,
         )?;
 
         // merkle path check
@@ -255,7 +335,7 @@ impl Circuit<pallas::Base> for ActionCircuit {
                 region.assign_advice_from_instance(
                     || "anchor",
                     config.instances,
-                    ACTION_ANCHOR_PUBLIC_INPUT_ROW_IDX,
+                    offset + ACTION_ANCHOR_PUBLIC_INPUT_ROW_IDX,
                     config.advices[1],
                     0,
                 )?;
@@ -271,20 +351,20 @@ impl Circuit<pallas::Base> for ActionCircuit {
             Value::known(self.input_vp_cm_r),
         )?;
         let input_vp_commitment = vp_commitment_gadget(
-            &mut layouter,
-            &blake2s_chip,
+            layouter,
+            blake2s_chip,
             input_note_variables.note_variables.app_vk.clone(),
             input_vp_cm_r,
         )?;
         layouter.constrain_instance(
             input_vp_commitment[0].cell(),
             config.instances,
-            ACTION_INPUT_VP_CM_1_ROW_IDX,
+            offset + ACTION_INPUT_VP_CM_1_ROW_IDX,
         )?;
         layouter.constrain_instance(
             input_vp_commitment[1].cell(),
             config.instances,
-            ACTION_INPUT_VP_CM_2_ROW_IDX,
+            offset + ACTION_INPUT_VP_CM_2_ROW_IDX,
         )?;
 
         // Output note application VP commitment
@@ -294,31 +374,187 @@ impl Circuit<pallas::Base> for ActionCircuit {
             Value::known(self.output_vp_cm_r),
         )?;
         let output_vp_commitment = vp_commitment_gadget(
-            &mut layouter,
-            &blake2s_chip,
+            layouter,
+            blake2s_chip,
             output_note_vars.note_variables.app_vk.clone(),
             output_vp_cm_r,
         )?;
         layouter.constrain_instance(
             output_vp_commitment[0].cell(),
             config.instances,
-            ACTION_OUTPUT_VP_CM_1_ROW_IDX,
+            offset + ACTION_OUTPUT_VP_CM_1_ROW_IDX,
         )?;
         layouter.constrain_instance(
             output_vp_commitment[1].cell(),
             config.instances,
-            ACTION_OUTPUT_VP_CM_2_ROW_IDX,
+            offset + ACTION_OUTPUT_VP_CM_2_ROW_IDX,
+        )?;
+
+        // Publicize the application vp vks, the ptx checks they are the vks of the attached vp proofs
+        layouter.constrain_instance(
+            input_note_variables.note_variables.app_vk.cell(),
+            config.instances,
+            offset + ACTION_INPUT_APP_VK_ROW_IDX,
+        )?;
+        layouter.constrain_instance(
+            output_note_vars.note_variables.app_vk.cell(),
+            config.instances,
+            offset + ACTION_OUTPUT_APP_VK_ROW_IDX,
+        )?;
+
+        // Output note cipher commitment
+        // Encrypt the output note and publicize the commitment to the ciphertext. The encryption
+        // vp of the note publishes the same ciphertext, see `check_note_ciphertexts`. The notes
+        // without an encrypted payload publish the sentinel, the encryption is then a dummy one.
+        let note_encryption = self.note_encryption.unwrap_or_default();
+        let is_encrypted = assign_free_advice(
+            layouter.namespace(|| "witness is_encrypted"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.note_encryption.is_some())),
+        )?;
+        let nonce = assign_free_advice(
+            layouter.namespace(|| "witness nonce"),
+            config.advices[0],
+            Value::known(note_encryption.nonce),
+        )?;
+        let esk = assign_free_advice(
+            layouter.namespace(|| "witness esk"),
+            config.advices[0],
+            Value::known(note_encryption.esk),
+        )?;
+        let rcv_pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness rcv_pk"),
+            Value::known(note_encryption.rcv_pk.to_affine()),
+        )?;
+        let note_kind = assign_free_advice(
+            layouter.namespace(|| "witness note kind"),
+            config.advices[0],
+            Value::known(note_encryption.note_kind),
+        )?;
+        let note_variables = output_note_vars.note_variables;
+        let mut message = vec![
+            note_variables.app_vk,
+            note_variables.app_data_static,
+            note_variables.app_data_dynamic,
+            note_variables.value,
+            note_variables.rho,
+            note_variables.nk_com,
+            note_variables.psi,
+            note_variables.rcm,
+            note_kind,
+        ];
+        let (cipher, _) = note_ciphertext_gadget(
+            layouter.namespace(|| "output note ciphertext"),
+            config.advices[0],
+            config.poseidon_config.clone(),
+            AddChip::construct(config.add_config.clone(), ()),
+            ecc_chip,
+            nonce,
+            esk,
+            rcv_pk,
+            &mut message,
+        )?;
+        let cipher_commitment = cipher_commitment_gadget(
+            layouter.namespace(|| "cipher commitment"),
+            config.advices[0],
+            config.poseidon_config.clone(),
+            &cipher,
+        )?;
+        let published_cipher_commitment = layouter.assign_region(
+            || "cipher commitment check",
+            |mut region| {
+                is_encrypted.copy_advice(|| "is_encrypted", &mut region, config.advices[0], 0)?;
+                cipher_commitment.copy_advice(
+                    || "cipher_commitment",
+                    &mut region,
+                    config.advices[1],
+                    0,
+                )?;
+                let published = is_encrypted
+                    .value()
+                    .zip(cipher_commitment.value())
+                    .map(|(is_encrypted, cipher_commitment)| is_encrypted * cipher_commitment);
+                config.cipher_commitment_selector.enable(&mut region, 0)?;
+                region.assign_advice(
+                    || "published cipher_commitment",
+                    config.advices[2],
+                    0,
+                    || published,
+                )
+            },
+        )?;
+        layouter.constrain_instance(
+            published_cipher_commitment.cell(),
+            config.instances,
+            offset + ACTION_CIPHER_COMMITMENT_ROW_IDX,
         )?;
 
         Ok(())
     }
 }
 
+/// The action circuits of the NUM_NOTE actions of a ptx in one circuit, with the config of the
+/// action circuit. The public inputs of the i-th action are the ActionPublicInputs rows from
+/// i * ACTION_PUBLIC_INPUT_NUM, see ShieldedPartialTransaction::build_aggregated.
+#[derive(Clone, Debug)]
+pub struct AggregatedActionCircuit<
+    const DEPTH: usize = TAIGA_COMMITMENT_TREE_DEPTH,
+    H: MerkleHashChip = PoseidonMerkleHash,
+> {
+    pub actions: [ActionCircuit<DEPTH, H>; NUM_NOTE],
+}
+
+impl<const DEPTH: usize, H: MerkleHashChip> Default for AggregatedActionCircuit<DEPTH, H> {
+    fn default() -> Self {
+        Self {
+            actions: std::array::from_fn(|_| ActionCircuit::default()),
+        }
+    }
+}
+
+impl<const DEPTH: usize, H: MerkleHashChip> Circuit<pallas::Base>
+    for AggregatedActionCircuit<DEPTH, H>
+{
+    type Config = ActionConfig<H>;
+    type FloorPlanner = floor_planner::V1;
+
+    fn without_witnesses(&self) -> Self {
+        Self::default()
+    }
+
+    fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+        ActionCircuit::<DEPTH, H>::configure(meta)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        // The lookup tables and the chips are shared by the actions
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
+        H::load(&config.merkle_config, config.table_idx, &mut layouter)?;
+        let blake2s_chip = Blake2sChip::construct(config.blake2s_config);
+
+        for (i, action) in self.actions.iter().enumerate() {
+            action.synthesize_action(
+                &config,
+                &mut layouter.namespace(|| format!("action {i}")),
+                ecc_chip.clone(),
+                &blake2s_chip,
+                i * ACTION_PUBLIC_INPUT_NUM,
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[test]
 fn test_halo2_action_circuit() {
     use crate::action::tests::random_action_info;
     use crate::constant::{
-        ACTION_CIRCUIT_PARAMS_SIZE, ACTION_PROVING_KEY, ACTION_VERIFYING_KEY, SETUP_PARAMS_MAP,
+        get_params, ACTION_CIRCUIT_PARAMS_SIZE, ACTION_PROVING_KEY, ACTION_VERIFYING_KEY,
     };
     use crate::proof::Proof;
     use halo2_proofs::dev::MockProver;
@@ -328,24 +564,216 @@ fn test_halo2_action_circuit() {
     let mut rng = OsRng;
     let action_info = random_action_info(&mut rng);
     let (action, action_circuit) = action_info.build();
-    let instances = vec![action.to_instance()];
+    let instances = vec![action.to_vec()];
     let prover =
         MockProver::<pallas::Base>::run(ACTION_CIRCUIT_PARAMS_SIZE, &action_circuit, instances)
             .unwrap();
     assert_eq!(prover.verify(), Ok(()));
 
     // Create action proof
-    let params = SETUP_PARAMS_MAP.get(&ACTION_CIRCUIT_PARAMS_SIZE).unwrap();
+    let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
     let proof = Proof::create(
         &ACTION_PROVING_KEY,
         params,
         action_circuit,
-        &[&action.to_instance()],
+        &[&action.to_vec()],
         &mut rng,
     )
     .unwrap();
 
     assert!(proof
-        .verify(&ACTION_VERIFYING_KEY, params, &[&action.to_instance()])
+        .verify(&ACTION_VERIFYING_KEY, params, &[&action.to_vec()])
         .is_ok());
 }
+
+#[test]
+fn test_halo2_aggregated_action_circuit() {
+    use crate::action::tests::random_action_info;
+    use crate::constant::AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE;
+    use halo2_proofs::dev::MockProver;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let (mut actions, circuits): (Vec<_>, Vec<_>) = (0..NUM_NOTE)
+        .map(|_| random_action_info(&mut rng).build())
+        .unzip();
+    let circuit = AggregatedActionCircuit {
+        actions: circuits.try_into().unwrap(),
+    };
+    let run = |actions: &[crate::action::ActionPublicInputs]| {
+        let instance = actions.iter().flat_map(|action| action.to_vec()).collect();
+        MockProver::<pallas::Base>::run(
+            AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE,
+            &circuit,
+            vec![instance],
+        )
+        .unwrap()
+        .verify()
+    };
+    assert_eq!(run(&actions), Ok(()));
+
+    // The actions are bound to their own rows
+    actions.swap(0, 1);
+    assert!(run(&actions).is_err());
+    actions.swap(0, 1);
+
+    // The public inputs of the second action are checked
+    actions[1].cipher_commitment = pallas::Base::random(&mut rng);
+    assert!(run(&actions).is_err());
+}
+
+#[test]
+fn test_halo2_action_circuit_cipher_commitment() {
+    use crate::action::tests::random_action_info;
+    use crate::constant::{ACTION_CIRCUIT_PARAMS_SIZE, NOTE_CIPHERTEXT_COMMITMENT_SENTINEL};
+    use crate::note_encryption::NoteKind;
+    use group::Group;
+    use halo2_proofs::dev::MockProver;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let run = |action: &crate::action::ActionPublicInputs, circuit: &ActionCircuit| {
+        MockProver::<pallas::Base>::run(ACTION_CIRCUIT_PARAMS_SIZE, circuit, vec![action.to_vec()])
+            .unwrap()
+            .verify()
+    };
+
+    let mut action_info = random_action_info(&mut rng);
+    let note_encryption = NoteEncryption {
+        nonce: pallas::Base::random(&mut rng),
+        esk: pallas::Base::random(&mut rng),
+        rcv_pk: pallas::Point::random(&mut rng),
+        note_kind: NoteKind::Change.to_base(),
+    };
+    action_info.set_note_encryption(note_encryption);
+    let (mut action, action_circuit) = action_info.build();
+    assert_eq!(
+        action.cipher_commitment,
+        note_encryption
+            .encrypt(&action_circuit.output_note)
+            .commitment()
+    );
+    assert_eq!(run(&action, &action_circuit), Ok(()));
+
+    // A commitment to another ciphertext
+    let other_encryption = NoteEncryption {
+        esk: pallas::Base::random(&mut rng),
+        ..note_encryption
+    };
+    action.cipher_commitment = other_encryption
+        .encrypt(&action_circuit.output_note)
+        .commitment();
+    assert!(run(&action, &action_circuit).is_err());
+
+    // The sentinel for an encrypted note
+    action.cipher_commitment = NOTE_CIPHERTEXT_COMMITMENT_SENTINEL;
+    assert!(run(&action, &action_circuit).is_err());
+
+    // A commitment for a note without an encrypted payload
+    let (mut action, action_circuit) = random_action_info(&mut rng).build();
+    assert_eq!(
+        action.cipher_commitment,
+        NOTE_CIPHERTEXT_COMMITMENT_SENTINEL
+    );
+    assert_eq!(run(&action, &action_circuit), Ok(()));
+    action.cipher_commitment = NoteEncryption::default()
+        .encrypt(&action_circuit.output_note)
+        .commitment();
+    assert!(run(&action, &action_circuit).is_err());
+}
+
+#[test]
+fn test_halo2_action_circuit_rho_mismatch() {
+    use crate::action::tests::random_action_info;
+    use crate::constant::ACTION_CIRCUIT_PARAMS_SIZE;
+    use crate::nullifier::Nullifier;
+    use halo2_proofs::dev::MockProver;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let (mut action, mut action_circuit) = random_action_info(&mut rng).build();
+
+    // An output note with an arbitrary rho, publicized with its own commitment
+    action_circuit.output_note.rho = Nullifier::random(&mut rng);
+    action.cm = action_circuit.output_note.commitment();
+    let instances = vec![action.to_vec()];
+    let prover =
+        MockProver::<pallas::Base>::run(ACTION_CIRCUIT_PARAMS_SIZE, &action_circuit, instances)
+            .unwrap();
+    assert!(prover.verify().is_err());
+}
+
+#[test]
+fn test_halo2_action_circuit_max_value() {
+    use crate::action::ActionInfo;
+    use crate::constant::{ACTION_CIRCUIT_PARAMS_SIZE, TAIGA_COMMITMENT_TREE_DEPTH};
+    use crate::merkle_tree::MerklePath;
+    use crate::note::{
+        tests::{random_input_note, random_output_note},
+        RandomSeed,
+    };
+    use halo2_proofs::dev::MockProver;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let input_note = Note {
+        value: u64::MAX,
+        ..random_input_note(&mut rng)
+    };
+    let output_note = Note {
+        value: u64::MAX,
+        ..random_output_note(&mut rng, input_note.get_nf().unwrap())
+    };
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    let anchor = input_note.calculate_root(&merkle_path);
+    let action_info = ActionInfo::new(
+        input_note,
+        merkle_path,
+        anchor,
+        output_note,
+        RandomSeed::random(&mut rng),
+    );
+    let (action, action_circuit) = action_info.build();
+    let instances = vec![action.to_vec()];
+    let prover =
+        MockProver::<pallas::Base>::run(ACTION_CIRCUIT_PARAMS_SIZE, &action_circuit, instances)
+            .unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+}
+
+#[test]
+fn test_halo2_action_circuit_sinsemilla_merkle_hash() {
+    use crate::action::{tests::random_action_info, ActionPublicInputs};
+    use crate::constant::ACTION_CIRCUIT_PARAMS_SIZE;
+    use crate::merkle_tree::{MerklePath, SinsemillaMerkleHash};
+    use halo2_proofs::dev::MockProver;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let (mut action, poseidon_circuit) = random_action_info(&mut rng).build();
+    let poseidon_anchor = action.anchor;
+
+    // The same action with the input note in a sinsemilla tree
+    let merkle_path: MerklePath<SinsemillaMerkleHash> =
+        MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    let circuit: ActionCircuit<TAIGA_COMMITMENT_TREE_DEPTH, SinsemillaMerkleHash> = ActionCircuit {
+        input_note: poseidon_circuit.input_note,
+        merkle_path: merkle_path.to_array().unwrap(),
+        output_note: poseidon_circuit.output_note,
+        rcv: poseidon_circuit.rcv,
+        input_vp_cm_r: poseidon_circuit.input_vp_cm_r,
+        output_vp_cm_r: poseidon_circuit.output_vp_cm_r,
+        note_encryption: poseidon_circuit.note_encryption,
+    };
+    action.anchor = merkle_path.root(Node::from(circuit.input_note.commitment()));
+    let run = |action: &ActionPublicInputs| {
+        MockProver::<pallas::Base>::run(ACTION_CIRCUIT_PARAMS_SIZE, &circuit, vec![action.to_vec()])
+            .unwrap()
+            .verify()
+    };
+    assert_eq!(run(&action), Ok(()));
+
+    // The anchor of the poseidon tree doesn't open with the sinsemilla hash
+    action.anchor = poseidon_anchor;
+    assert!(run(&action).is_err());
+}
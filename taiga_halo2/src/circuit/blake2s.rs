@@ -28,6 +28,36 @@ pub fn vp_commitment_gadget<F: PrimeField>(
     blake2s_chip.encode_result(layouter, &hash)
 }
 
+// Constrain the vp commitment, e.g. published by an earlier vp, to open to the vp with the rcm
+pub fn open_vp_commitment_gadget<F: PrimeField>(
+    layouter: &mut impl Layouter<F>,
+    blake2s_chip: &Blake2sChip<F>,
+    vp: AssignedCell<F, F>,
+    rcm: AssignedCell<F, F>,
+    vp_cm: &[AssignedCell<F, F>; 2],
+) -> Result<(), Error> {
+    let expected_vp_cm = vp_commitment_gadget(layouter, blake2s_chip, vp, rcm)?;
+    layouter.assign_region(
+        || "check vp commitment opening",
+        |mut region| {
+            region.constrain_equal(expected_vp_cm[0].cell(), vp_cm[0].cell())?;
+            region.constrain_equal(expected_vp_cm[1].cell(), vp_cm[1].cell())
+        },
+    )
+}
+
+// The dynamic vp commitment of ValidityPredicateCommitment::commit(vk, rcm), for the vps that
+// commit to a specific dynamic vp instead of publishing the default commitments
+pub fn dynamic_vp_commitment_gadget<F: PrimeField>(
+    layouter: &mut impl Layouter<F>,
+    blake2s_config: Blake2sConfig<F>,
+    vk: AssignedCell<F, F>,
+    rcm: AssignedCell<F, F>,
+) -> Result<[AssignedCell<F, F>; 2], Error> {
+    let blake2s_chip = Blake2sChip::construct(blake2s_config);
+    vp_commitment_gadget(layouter, &blake2s_chip, vk, rcm)
+}
+
 pub fn publicize_default_dynamic_vp_commitments<F: PrimeField>(
     layouter: &mut impl Layouter<F>,
     advice: Column<Advice>,
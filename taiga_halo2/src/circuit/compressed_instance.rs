@@ -0,0 +1,220 @@
+// The compressed vp instance.
+// With the `compressed_vp_instance` feature, the vp circuit exposes only one public input: the
+// poseidon hash of the public input vector. The vp constraints still constrain the public inputs
+// by rows, the `InstanceCollectingLayouter` collects these cells and constrains them to the
+// witnessed public input vector, which is hashed in circuit.
+// The prover passes the public input vector to the circuit with `with_witnessed_public_inputs`,
+// so the padding comes from the prover's rng and is not recomputed in synthesize.
+use crate::circuit::{
+    gadgets::{assign_free_advice, poseidon_hash::poseidon_hash_gadget},
+    vp_circuit::{ValidityPredicateConfig, ValidityPredicatePublicInputs},
+};
+use crate::constant::{VP_CIRCUIT_COMPRESSED_INSTANCE_IDX, VP_CIRCUIT_PUBLIC_INPUT_NUM};
+use halo2_proofs::{
+    circuit::{AssignedCell, Cell, Layouter, Region, Table, Value},
+    plonk::{Column, Error, Instance},
+};
+use pasta_curves::pallas;
+use std::cell::RefCell;
+
+thread_local! {
+    // The public inputs witnessed by the vp circuits synthesized in `with_witnessed_public_inputs`
+    static WITNESSED_PUBLIC_INPUTS: RefCell<Option<[pallas::Base; VP_CIRCUIT_PUBLIC_INPUT_NUM]>> =
+        RefCell::new(None);
+}
+
+// Run f, e.g. the proof creation, with the public inputs witnessed by the vp circuit when the
+// public inputs are compressed. Outside of it the witness is unknown, as in the keygen.
+pub fn with_witnessed_public_inputs<T>(
+    public_inputs: &ValidityPredicatePublicInputs,
+    f: impl FnOnce() -> T,
+) -> T {
+    struct Reset(Option<[pallas::Base; VP_CIRCUIT_PUBLIC_INPUT_NUM]>);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            WITNESSED_PUBLIC_INPUTS.with(|inputs| *inputs.borrow_mut() = self.0.take());
+        }
+    }
+
+    let previous =
+        WITNESSED_PUBLIC_INPUTS.with(|inputs| inputs.borrow_mut().replace(*public_inputs.inner()));
+    let _reset = Reset(previous);
+    f()
+}
+
+pub(crate) fn witnessed_public_inputs() -> Value<[pallas::Base; VP_CIRCUIT_PUBLIC_INPUT_NUM]> {
+    WITNESSED_PUBLIC_INPUTS.with(|inputs| match *inputs.borrow() {
+        Some(public_inputs) => Value::known(public_inputs),
+        None => Value::unknown(),
+    })
+}
+
+pub struct InstanceCollectingLayouter<L: Layouter<pallas::Base>> {
+    layouter: L,
+    // The cells constrained to the public inputs and their rows
+    instances: Vec<(Cell, usize)>,
+}
+
+impl<L: Layouter<pallas::Base>> InstanceCollectingLayouter<L> {
+    pub fn new(layouter: L) -> Self {
+        Self {
+            layouter,
+            instances: vec![],
+        }
+    }
+
+    // Witness the public input vector, constrain the collected cells and expose the hash.
+    pub fn compress(
+        mut self,
+        config: &ValidityPredicateConfig,
+        public_inputs: Value<[pallas::Base; VP_CIRCUIT_PUBLIC_INPUT_NUM]>,
+    ) -> Result<(), Error> {
+        let mut cells = vec![];
+        for i in 0..VP_CIRCUIT_PUBLIC_INPUT_NUM {
+            cells.push(assign_free_advice(
+                self.layouter.namespace(|| "witness public input"),
+                config.advices[0],
+                public_inputs.map(|inputs| inputs[i]),
+            )?);
+        }
+
+        for (cell, row) in self.instances.iter() {
+            self.layouter.assign_region(
+                || "constrain public input",
+                |mut region| region.constrain_equal(*cell, cells[*row].cell()),
+            )?;
+        }
+
+        let cells: [AssignedCell<pallas::Base, pallas::Base>; VP_CIRCUIT_PUBLIC_INPUT_NUM] =
+            cells.try_into().unwrap();
+        let compressed = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            self.layouter.namespace(|| "compress public inputs"),
+            cells,
+        )?;
+        self.layouter.constrain_instance(
+            compressed.cell(),
+            config.instances,
+            VP_CIRCUIT_COMPRESSED_INSTANCE_IDX,
+        )
+    }
+}
+
+impl<L: Layouter<pallas::Base>> Layouter<pallas::Base> for InstanceCollectingLayouter<L> {
+    type Root = Self;
+
+    fn assign_region<A, AR, N, NR>(&mut self, name: N, assignment: A) -> Result<AR, Error>
+    where
+        A: FnMut(Region<'_, pallas::Base>) -> Result<AR, Error>,
+        N: Fn() -> NR,
+        NR: Into<String>,
+    {
+        self.layouter.assign_region(name, assignment)
+    }
+
+    fn assign_table<A, N, NR>(&mut self, name: N, assignment: A) -> Result<(), Error>
+    where
+        A: FnMut(Table<'_, pallas::Base>) -> Result<(), Error>,
+        N: Fn() -> NR,
+        NR: Into<String>,
+    {
+        self.layouter.assign_table(name, assignment)
+    }
+
+    // The vp circuit only has one instance column
+    fn constrain_instance(
+        &mut self,
+        cell: Cell,
+        _column: Column<Instance>,
+        row: usize,
+    ) -> Result<(), Error> {
+        assert!(row < VP_CIRCUIT_PUBLIC_INPUT_NUM);
+        self.instances.push((cell, row));
+        Ok(())
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+        self.layouter.get_root().push_namespace(name_fn)
+    }
+
+    fn pop_namespace(&mut self, gadget_name: Option<String>) {
+        self.layouter.get_root().pop_namespace(gadget_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::vp_circuit::{
+        ValidityPredicateCircuit, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+    };
+    use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
+    use crate::constant::{VP_CIRCUIT_NULLIFIER_ONE_PUBLIC_INPUT_IDX, VP_CIRCUIT_PUBLIC_INPUT_NUM};
+    use pasta_curves::pallas;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_compress_public_inputs() {
+        let mut rng = OsRng;
+        let circuit = TrivialValidityPredicateCircuit::default();
+        let public_inputs = circuit.get_public_inputs(&mut rng);
+        assert_eq!(public_inputs.to_vec().len(), VP_CIRCUIT_PUBLIC_INPUT_NUM);
+
+        // Tamper the nullifier
+        let mut tampered = public_inputs.to_vec();
+        tampered[VP_CIRCUIT_NULLIFIER_ONE_PUBLIC_INPUT_IDX] += pallas::Base::one();
+        let tampered = ValidityPredicatePublicInputs::from(tampered);
+        assert_ne!(public_inputs.compress(), tampered.compress());
+    }
+
+    #[cfg(feature = "compressed_vp_instance")]
+    #[test]
+    fn test_compressed_vp_instance() {
+        use crate::circuit::vp_circuit::VPVerifyingInfo;
+        use crate::note::tests::{random_input_note, random_output_note};
+
+        let mut rng = OsRng;
+        let input_notes = [random_input_note(&mut rng), random_input_note(&mut rng)];
+        let output_notes =
+            input_notes.map(|note| random_output_note(&mut rng, note.get_nf().unwrap()));
+        let circuit = TrivialValidityPredicateCircuit::new(
+            input_notes[0].get_nf().unwrap().inner(),
+            input_notes,
+            output_notes,
+        );
+        let vp_info = circuit.get_verifying_info();
+
+        // Only the compressed public input is exposed
+        assert_eq!(vp_info.public_inputs.to_instance().len(), 1);
+        assert!(vp_info.public_inputs.to_instance().len() < VP_CIRCUIT_PUBLIC_INPUT_NUM);
+        vp_info.verify().unwrap();
+
+        // The padding is drawn from the prover's rng, not derived from the public owned note id
+        let other_vp_info = circuit.get_verifying_info();
+        assert_ne!(
+            other_vp_info.public_inputs.to_vec(),
+            vp_info.public_inputs.to_vec()
+        );
+        other_vp_info.verify().unwrap();
+
+        // Tamper the nullifier
+        let mut tampered = vp_info.public_inputs.to_vec();
+        tampered[VP_CIRCUIT_NULLIFIER_ONE_PUBLIC_INPUT_IDX] += pallas::Base::one();
+        let tampered_vp_info = VPVerifyingInfo {
+            public_inputs: tampered.into(),
+            ..vp_info.clone()
+        };
+        assert_ne!(
+            tampered_vp_info.public_inputs.to_instance(),
+            vp_info.public_inputs.to_instance()
+        );
+        assert!(tampered_vp_info.verify().is_err());
+    }
+}
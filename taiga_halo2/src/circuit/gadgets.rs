@@ -5,6 +5,8 @@ use halo2_proofs::{
 };
 
 pub mod add;
+pub mod comparison;
+pub mod conditional;
 pub mod conditional_equal;
 pub mod conditional_select;
 pub mod extended_or_relation;
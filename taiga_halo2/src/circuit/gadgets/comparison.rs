@@ -0,0 +1,268 @@
+/// Comparison of u64 values, e.g. the note values.
+/// The gadgets are only sound if both values are u64: a value of a note is range checked in the
+/// action circuit, any other value has to be range checked by the vp, see `check_value_range`.
+/// No gate is added, the comparisons are built on the add/sub/mul chips and the range check of
+/// the vp config.
+use crate::circuit::{
+    gadgets::{
+        add::{AddChip, AddConfig, AddInstructions},
+        assign_free_advice, assign_free_constant,
+        mul::{MulChip, MulConfig, MulInstructions},
+        sub::{SubChip, SubConfig, SubInstructions},
+    },
+    integrity::check_value_range,
+};
+use crate::utils::is_greater_or_equal_64 as native_is_greater_or_equal_64;
+use halo2_gadgets::utilities::lookup_range_check::LookupRangeCheckConfig;
+use halo2_proofs::{
+    circuit::{AssignedCell, Layouter},
+    plonk::{Advice, Column, Error},
+};
+use pasta_curves::pallas;
+
+#[derive(Clone, Debug)]
+pub struct ComparisonConfig {
+    advice: Column<Advice>,
+    range_check: LookupRangeCheckConfig<pallas::Base, 10>,
+    add_config: AddConfig,
+    sub_config: SubConfig,
+    mul_config: MulConfig,
+}
+
+impl ComparisonConfig {
+    pub fn construct(
+        advice: Column<Advice>,
+        range_check: LookupRangeCheckConfig<pallas::Base, 10>,
+        add_config: AddConfig,
+        sub_config: SubConfig,
+        mul_config: MulConfig,
+    ) -> Self {
+        Self {
+            advice,
+            range_check,
+            add_config,
+            sub_config,
+            mul_config,
+        }
+    }
+}
+
+// 2^64
+fn two_pow_64() -> pallas::Base {
+    pallas::Base::from(u64::MAX) + pallas::Base::one()
+}
+
+/// Returns the boolean flag of a >= b.
+/// a - b + 2^64 is in [1, 2^65), its bit 64 is the flag and the low 64 bits are range checked.
+pub fn is_greater_or_equal_64(
+    mut layouter: impl Layouter<pallas::Base>,
+    config: &ComparisonConfig,
+    a: &AssignedCell<pallas::Base, pallas::Base>,
+    b: &AssignedCell<pallas::Base, pallas::Base>,
+) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+    let add_chip = AddChip::construct(config.add_config.clone(), ());
+    let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+    let mul_chip = MulChip::construct(config.mul_config.clone());
+
+    let flag = assign_free_advice(
+        layouter.namespace(|| "witness a >= b"),
+        config.advice,
+        a.value()
+            .zip(b.value())
+            .map(|(a, b)| pallas::Base::from(native_is_greater_or_equal_64(*a, *b) as u64)),
+    )?;
+    let flag_square = MulInstructions::mul(
+        &mul_chip,
+        layouter.namespace(|| "flag * flag"),
+        &flag,
+        &flag,
+    )?;
+    layouter.assign_region(
+        || "bool(flag)",
+        |mut region| region.constrain_equal(flag_square.cell(), flag.cell()),
+    )?;
+
+    let two_pow_64 =
+        assign_free_constant(layouter.namespace(|| "2^64"), config.advice, two_pow_64())?;
+    let shifted_a =
+        AddInstructions::add(&add_chip, layouter.namespace(|| "a + 2^64"), a, &two_pow_64)?;
+    let shifted_diff = SubInstructions::sub(
+        &sub_chip,
+        layouter.namespace(|| "a + 2^64 - b"),
+        &shifted_a,
+        b,
+    )?;
+    let high_bit = MulInstructions::mul(
+        &mul_chip,
+        layouter.namespace(|| "flag * 2^64"),
+        &flag,
+        &two_pow_64,
+    )?;
+    let low_bits = SubInstructions::sub(
+        &sub_chip,
+        layouter.namespace(|| "a + 2^64 - b - flag * 2^64"),
+        &shifted_diff,
+        &high_bit,
+    )?;
+    check_value_range(
+        layouter.namespace(|| "range check the low bits"),
+        &config.range_check,
+        &low_bits,
+    )?;
+
+    Ok(flag)
+}
+
+/// Constrains a >= b, i.e. a - b is a u64.
+pub fn assert_greater_or_equal_64(
+    mut layouter: impl Layouter<pallas::Base>,
+    config: &ComparisonConfig,
+    a: &AssignedCell<pallas::Base, pallas::Base>,
+    b: &AssignedCell<pallas::Base, pallas::Base>,
+) -> Result<(), Error> {
+    let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+    let diff = SubInstructions::sub(&sub_chip, layouter.namespace(|| "a - b"), a, b)?;
+    check_value_range(
+        layouter.namespace(|| "range check a - b"),
+        &config.range_check,
+        &diff,
+    )
+}
+
+#[test]
+fn test_comparison_gadgets() {
+    use halo2_proofs::{
+        circuit::{SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Circuit, ConstraintSystem, Instance, TableColumn},
+    };
+
+    #[derive(Clone, Debug)]
+    struct MyConfig {
+        comparison_config: ComparisonConfig,
+        table_idx: TableColumn,
+        advice: Column<Advice>,
+        instance: Column<Instance>,
+    }
+
+    #[derive(Default)]
+    struct MyCircuit {
+        a: u64,
+        b: u64,
+        // Constrain a >= b
+        assert: bool,
+    }
+
+    impl Circuit<pallas::Base> for MyCircuit {
+        type Config = MyConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self::default()
+        }
+
+        fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+            let advices = [meta.advice_column(), meta.advice_column()];
+            for advice in advices.iter() {
+                meta.enable_equality(*advice);
+            }
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let constants = meta.fixed_column();
+            meta.enable_constant(constants);
+            let table_idx = meta.lookup_table_column();
+            let range_check = LookupRangeCheckConfig::configure(meta, advices[1], table_idx);
+            let comparison_config = ComparisonConfig::construct(
+                advices[0],
+                range_check,
+                AddChip::configure(meta, advices),
+                SubChip::configure(meta, advices),
+                MulChip::configure(meta, advices),
+            );
+            MyConfig {
+                comparison_config,
+                table_idx,
+                advice: advices[0],
+                instance,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<pallas::Base>,
+        ) -> Result<(), Error> {
+            layouter.assign_table(
+                || "table_idx",
+                |mut table| {
+                    for index in 0..(1 << 10) {
+                        table.assign_cell(
+                            || "table_idx",
+                            config.table_idx,
+                            index,
+                            || Value::known(pallas::Base::from(index as u64)),
+                        )?;
+                    }
+                    Ok(())
+                },
+            )?;
+            let [a, b] = [self.a, self.b].map(|v| {
+                assign_free_advice(
+                    layouter.namespace(|| "witness"),
+                    config.advice,
+                    Value::known(pallas::Base::from(v)),
+                )
+                .unwrap()
+            });
+            let flag = is_greater_or_equal_64(
+                layouter.namespace(|| "a >= b"),
+                &config.comparison_config,
+                &a,
+                &b,
+            )?;
+            if self.assert {
+                assert_greater_or_equal_64(
+                    layouter.namespace(|| "assert a >= b"),
+                    &config.comparison_config,
+                    &a,
+                    &b,
+                )?;
+            }
+            layouter.constrain_instance(flag.cell(), config.instance, 0)
+        }
+    }
+
+    let check = |a: u64, b: u64, assert: bool, flag: bool| {
+        let circuit = MyCircuit { a, b, assert };
+        let instance = vec![pallas::Base::from(flag as u64)];
+        let prover = MockProver::run(11, &circuit, vec![instance]).unwrap();
+        prover.verify().is_ok()
+    };
+
+    // a == b
+    assert!(check(7, 7, true, true));
+    assert!(!check(7, 7, false, false));
+    // a == b + 1
+    assert!(check(8, 7, true, true));
+    assert!(!check(8, 7, false, false));
+    // a == b - 1: the flag is 0 and the assertion fails
+    assert!(check(6, 7, false, false));
+    assert!(!check(6, 7, false, true));
+    assert!(!check(6, 7, true, false));
+    // Near 2^64
+    assert!(check(u64::MAX, u64::MAX - 1, true, true));
+    assert!(check(u64::MAX, 0, true, true));
+    assert!(check(0, u64::MAX, false, false));
+    assert!(!check(0, u64::MAX, true, false));
+    assert!(check(u64::MAX - 1, u64::MAX, false, false));
+    assert!(!check(u64::MAX - 1, u64::MAX, true, false));
+
+    // The native mirror
+    use crate::utils::is_greater_or_equal_64 as native;
+    assert!(native(
+        pallas::Base::from(u64::MAX),
+        pallas::Base::from(u64::MAX)
+    ));
+    assert!(native(pallas::Base::from(u64::MAX), pallas::Base::zero()));
+    assert!(!native(pallas::Base::zero(), pallas::Base::one()));
+}
@@ -0,0 +1,240 @@
+/// Conditional constraints for the branching vps.
+/// Every gate also constrains the flag to be boolean, so a flag derived by arithmetic (e.g.
+/// is_input * cancel_flag) can be passed directly: the proof fails if it's not 0 or 1.
+/// Soundness relies on the flag being boolean, the branch checks are only enforced when flag == 1.
+use halo2_proofs::{
+    circuit::{AssignedCell, Layouter},
+    plonk::{Advice, Column, ConstraintSystem, Constraints, Error, Expression, Selector},
+    poly::Rotation,
+};
+
+use pasta_curves::pallas;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConditionalConfig {
+    q_equal_if: Selector,
+    q_zero_if: Selector,
+    q_select: Selector,
+    advice: [Column<Advice>; 4],
+}
+
+impl ConditionalConfig {
+    pub fn configure(
+        meta: &mut ConstraintSystem<pallas::Base>,
+        advice: [Column<Advice>; 4],
+    ) -> Self {
+        let config = Self {
+            q_equal_if: meta.selector(),
+            q_zero_if: meta.selector(),
+            q_select: meta.selector(),
+            advice,
+        };
+
+        config.create_gate(meta);
+
+        config
+    }
+
+    fn create_gate(&self, meta: &mut ConstraintSystem<pallas::Base>) {
+        let one = Expression::Constant(pallas::Base::one());
+
+        meta.create_gate("assert equal if", |meta| {
+            let q_equal_if = meta.query_selector(self.q_equal_if);
+            let flag = meta.query_advice(self.advice[0], Rotation::cur());
+            let a = meta.query_advice(self.advice[1], Rotation::cur());
+            let b = meta.query_advice(self.advice[2], Rotation::cur());
+
+            Constraints::with_selector(
+                q_equal_if,
+                [
+                    ("bool(flag)", flag.clone() * (one.clone() - flag.clone())),
+                    ("flag * (a - b) = 0", flag * (a - b)),
+                ],
+            )
+        });
+
+        meta.create_gate("assert zero if", |meta| {
+            let q_zero_if = meta.query_selector(self.q_zero_if);
+            let flag = meta.query_advice(self.advice[0], Rotation::cur());
+            let a = meta.query_advice(self.advice[1], Rotation::cur());
+
+            Constraints::with_selector(
+                q_zero_if,
+                [
+                    ("bool(flag)", flag.clone() * (one.clone() - flag.clone())),
+                    ("flag * a = 0", flag * a),
+                ],
+            )
+        });
+
+        meta.create_gate("select", |meta| {
+            let q_select = meta.query_selector(self.q_select);
+            let flag = meta.query_advice(self.advice[0], Rotation::cur());
+            let a = meta.query_advice(self.advice[1], Rotation::cur());
+            let b = meta.query_advice(self.advice[2], Rotation::cur());
+            let ret = meta.query_advice(self.advice[3], Rotation::cur());
+
+            Constraints::with_selector(
+                q_select,
+                [
+                    ("bool(flag)", flag.clone() * (one.clone() - flag.clone())),
+                    (
+                        "flag * a + (1 - flag) * b = ret",
+                        flag.clone() * a + (one.clone() - flag) * b - ret,
+                    ),
+                ],
+            )
+        });
+    }
+
+    /// Constrain a == b if flag == 1
+    pub fn assert_equal_if(
+        &self,
+        mut layouter: impl Layouter<pallas::Base>,
+        flag: &AssignedCell<pallas::Base, pallas::Base>,
+        a: &AssignedCell<pallas::Base, pallas::Base>,
+        b: &AssignedCell<pallas::Base, pallas::Base>,
+    ) -> Result<(), Error> {
+        layouter.assign_region(
+            || "assert equal if",
+            |mut region| {
+                self.q_equal_if.enable(&mut region, 0)?;
+                flag.copy_advice(|| "flag", &mut region, self.advice[0], 0)?;
+                a.copy_advice(|| "a", &mut region, self.advice[1], 0)?;
+                b.copy_advice(|| "b", &mut region, self.advice[2], 0)?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Constrain a == 0 if flag == 1
+    pub fn assert_zero_if(
+        &self,
+        mut layouter: impl Layouter<pallas::Base>,
+        flag: &AssignedCell<pallas::Base, pallas::Base>,
+        a: &AssignedCell<pallas::Base, pallas::Base>,
+    ) -> Result<(), Error> {
+        layouter.assign_region(
+            || "assert zero if",
+            |mut region| {
+                self.q_zero_if.enable(&mut region, 0)?;
+                flag.copy_advice(|| "flag", &mut region, self.advice[0], 0)?;
+                a.copy_advice(|| "a", &mut region, self.advice[1], 0)?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Returns a if flag == 1, otherwise b
+    pub fn select(
+        &self,
+        mut layouter: impl Layouter<pallas::Base>,
+        flag: &AssignedCell<pallas::Base, pallas::Base>,
+        a: &AssignedCell<pallas::Base, pallas::Base>,
+        b: &AssignedCell<pallas::Base, pallas::Base>,
+    ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+        layouter.assign_region(
+            || "select",
+            |mut region| {
+                self.q_select.enable(&mut region, 0)?;
+                flag.copy_advice(|| "flag", &mut region, self.advice[0], 0)?;
+                a.copy_advice(|| "a", &mut region, self.advice[1], 0)?;
+                b.copy_advice(|| "b", &mut region, self.advice[2], 0)?;
+                let ret = flag
+                    .value()
+                    .zip(a.value())
+                    .zip(b.value())
+                    .map(|((flag, a), b)| if *flag == pallas::Base::one() { *a } else { *b });
+                region.assign_advice(|| "ret", self.advice[3], 0, || ret)
+            },
+        )
+    }
+}
+
+#[test]
+fn test_conditional_gadgets() {
+    use crate::circuit::gadgets::assign_free_advice;
+    use halo2_proofs::{
+        circuit::{SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Circuit, Instance},
+    };
+
+    #[derive(Clone, Debug)]
+    struct MyConfig {
+        conditional_config: ConditionalConfig,
+        advice: Column<Advice>,
+        instance: Column<Instance>,
+    }
+
+    #[derive(Default)]
+    struct MyCircuit {
+        flag: u64,
+        a: u64,
+        b: u64,
+    }
+
+    impl Circuit<pallas::Base> for MyCircuit {
+        type Config = MyConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self::default()
+        }
+
+        fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+            let advices = [
+                meta.advice_column(),
+                meta.advice_column(),
+                meta.advice_column(),
+                meta.advice_column(),
+            ];
+            for advice in advices.iter() {
+                meta.enable_equality(*advice);
+            }
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            MyConfig {
+                conditional_config: ConditionalConfig::configure(meta, advices),
+                advice: advices[0],
+                instance,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<pallas::Base>,
+        ) -> Result<(), Error> {
+            let [flag, a, b] = [self.flag, self.a, self.b].map(|v| {
+                assign_free_advice(
+                    layouter.namespace(|| "witness"),
+                    config.advice,
+                    Value::known(pallas::Base::from(v)),
+                )
+                .unwrap()
+            });
+            let conditional = config.conditional_config;
+            conditional.assert_equal_if(layouter.namespace(|| "a == b"), &flag, &a, &b)?;
+            conditional.assert_zero_if(layouter.namespace(|| "a == 0"), &flag, &a)?;
+            let ret = conditional.select(layouter.namespace(|| "select"), &flag, &a, &b)?;
+            layouter.constrain_instance(ret.cell(), config.instance, 0)
+        }
+    }
+
+    let check = |flag: u64, a: u64, b: u64, ret: u64| {
+        let circuit = MyCircuit { flag, a, b };
+        let prover = MockProver::run(4, &circuit, vec![vec![pallas::Base::from(ret)]]).unwrap();
+        prover.verify().is_ok()
+    };
+
+    // flag == 0: the checks are skipped and b is selected
+    assert!(check(0, 1, 2, 2));
+    assert!(!check(0, 1, 2, 1));
+    // flag == 1: a == b == 0 is enforced and a is selected
+    assert!(check(1, 0, 0, 0));
+    assert!(!check(1, 1, 1, 1));
+    assert!(!check(1, 0, 1, 0));
+    // The flag is not boolean, even if all the conditional checks hold: a == b == 0
+    assert!(!check(2, 0, 0, 0));
+}
@@ -1,6 +1,9 @@
+use crate::constant::{POSEIDON_HASH_MAX_LEN, POSEIDON_RATE};
+use crate::utils::VariableLength;
 use halo2_gadgets::poseidon::{
-    primitives as poseidon, primitives::ConstantLength, Hash as PoseidonHash,
-    Pow5Chip as PoseidonChip, Pow5Config as PoseidonConfig,
+    primitives as poseidon,
+    primitives::{Absorbing, ConstantLength, Domain, Squeezing},
+    PaddedWord, Pow5Chip as PoseidonChip, Pow5Config as PoseidonConfig, Sponge, StateWord,
 };
 use halo2_proofs::{
     circuit::{AssignedCell, Layouter},
@@ -8,17 +11,447 @@ use halo2_proofs::{
 };
 use pasta_curves::pallas;
 
+type Pow5Sponge<M, D> = Sponge<
+    pallas::Base,
+    PoseidonChip<pallas::Base, 3, POSEIDON_RATE>,
+    poseidon::P128Pow5T3,
+    M,
+    D,
+    3,
+    POSEIDON_RATE,
+>;
+
+enum SpongeState<D: Domain<pallas::Base, POSEIDON_RATE>> {
+    Absorbing {
+        sponge: Pow5Sponge<Absorbing<PaddedWord<pallas::Base>, POSEIDON_RATE>, D>,
+        len: usize,
+    },
+    Squeezing {
+        sponge: Pow5Sponge<Squeezing<StateWord<pallas::Base>, POSEIDON_RATE>, D>,
+        last_output: AssignedCell<pallas::Base, pallas::Base>,
+    },
+}
+
+/// The in-circuit duplex sponge, it gives the same outputs as the native `PoseidonSponge` of
+/// the same domain.
+///
+/// The padding of the domain `D` is absorbed on the first squeeze. Absorbing after a squeeze
+/// starts a new sponge that absorbs the last output first.
+pub struct PoseidonSpongeGadget<D: Domain<pallas::Base, POSEIDON_RATE> = VariableLength> {
+    config: PoseidonConfig<pallas::Base, 3, POSEIDON_RATE>,
+    // Only None while an absorb or a squeeze is in progress
+    state: Option<SpongeState<D>>,
+}
+
+impl<D: Domain<pallas::Base, POSEIDON_RATE>> PoseidonSpongeGadget<D> {
+    pub fn new(
+        config: PoseidonConfig<pallas::Base, 3, POSEIDON_RATE>,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<Self, Error> {
+        let sponge = Pow5Sponge::new(
+            PoseidonChip::construct(config.clone()),
+            layouter.namespace(|| "sponge init"),
+        )?;
+        Ok(Self {
+            config,
+            state: Some(SpongeState::Absorbing { sponge, len: 0 }),
+        })
+    }
+
+    pub fn absorb(
+        &mut self,
+        mut layouter: impl Layouter<pallas::Base>,
+        cells: &[AssignedCell<pallas::Base, pallas::Base>],
+    ) -> Result<(), Error> {
+        let (mut sponge, mut len) = match self.state.take().expect("sponge in use") {
+            SpongeState::Absorbing { sponge, len } => (sponge, len),
+            SpongeState::Squeezing { last_output, .. } => {
+                let mut sponge = Pow5Sponge::new(
+                    PoseidonChip::construct(self.config.clone()),
+                    layouter.namespace(|| "sponge reinit"),
+                )?;
+                sponge.absorb(
+                    layouter.namespace(|| "absorb last output"),
+                    PaddedWord::Message(last_output),
+                )?;
+                (sponge, 1)
+            }
+        };
+        for (i, cell) in cells.iter().enumerate() {
+            sponge.absorb(
+                layouter.namespace(|| format!("absorb {}", i)),
+                PaddedWord::Message(cell.clone()),
+            )?;
+        }
+        len += cells.len();
+        self.state = Some(SpongeState::Absorbing { sponge, len });
+        Ok(())
+    }
+
+    pub fn squeeze(
+        &mut self,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+        let mut sponge = match self.state.take().expect("sponge in use") {
+            SpongeState::Absorbing { mut sponge, len } => {
+                for (i, padding) in D::padding(len).into_iter().enumerate() {
+                    sponge.absorb(
+                        layouter.namespace(|| format!("absorb padding {}", i)),
+                        PaddedWord::Padding(padding),
+                    )?;
+                }
+                sponge.finish_absorbing(layouter.namespace(|| "finish absorbing"))?
+            }
+            SpongeState::Squeezing { sponge, .. } => sponge,
+        };
+        let output = sponge.squeeze(layouter.namespace(|| "squeeze"))?;
+        self.state = Some(SpongeState::Squeezing {
+            sponge,
+            last_output: output.clone(),
+        });
+        Ok(output)
+    }
+}
+
+// Hash a message of any length L. The sponge absorbs the message POSEIDON_RATE elements at a
+// time, so it matches the native `poseidon_hash_n::<L>`.
 pub fn poseidon_hash_gadget<const L: usize>(
     config: PoseidonConfig<pallas::Base, 3, 2>,
     mut layouter: impl Layouter<pallas::Base>,
     messages: [AssignedCell<pallas::Base, pallas::Base>; L],
 ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
-    let poseidon_chip = PoseidonChip::construct(config);
-    let poseidon_hasher =
-        PoseidonHash::<_, _, poseidon::P128Pow5T3, ConstantLength<L>, 3, 2>::init(
-            poseidon_chip,
-            layouter.namespace(|| "Poseidon init"),
-        )?;
+    let mut sponge = PoseidonSpongeGadget::<ConstantLength<L>>::new(
+        config,
+        layouter.namespace(|| "Poseidon init"),
+    )?;
+    sponge.absorb(layouter.namespace(|| "poseidon absorb"), &messages)?;
+    sponge.squeeze(layouter.namespace(|| "poseidon squeeze"))
+}
+
+// The in-circuit `poseidon_hash_slice`. Panics if the message is empty or longer than
+// POSEIDON_HASH_MAX_LEN.
+pub fn poseidon_hash_slice_gadget(
+    config: PoseidonConfig<pallas::Base, 3, 2>,
+    layouter: impl Layouter<pallas::Base>,
+    messages: &[AssignedCell<pallas::Base, pallas::Base>],
+) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+    macro_rules! hash_with_len {
+        ($($len:literal),*) => {
+            match messages.len() {
+                $($len => poseidon_hash_gadget::<$len>(
+                    config,
+                    layouter,
+                    messages.to_vec().try_into().unwrap(),
+                ),)*
+                len => panic!(
+                    "the poseidon message length {} is not in [1, {}]",
+                    len, POSEIDON_HASH_MAX_LEN
+                ),
+            }
+        };
+    }
+    hash_with_len!(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16)
+}
+
+#[test]
+fn test_poseidon_hash_gadget() {
+    use crate::circuit::gadgets::assign_free_advice;
+    use crate::utils::{poseidon_hash_n, poseidon_hash_slice};
+    use halo2_proofs::{
+        arithmetic::Field,
+        circuit::{SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Advice, Circuit, Column, ConstraintSystem, Instance},
+    };
+    use rand::rngs::OsRng;
+
+    #[derive(Clone, Debug)]
+    struct MyConfig {
+        advice: Column<Advice>,
+        instance: Column<Instance>,
+        poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
+    }
+
+    struct MyCircuit<const L: usize> {
+        message: [pallas::Base; L],
+    }
+
+    impl<const L: usize> Circuit<pallas::Base> for MyCircuit<L> {
+        type Config = MyConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                message: [pallas::Base::zero(); L],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+            let advice = meta.advice_column();
+            meta.enable_equality(advice);
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+
+            let state = (0..3).map(|_| meta.advice_column()).collect::<Vec<_>>();
+            let partial_sbox = meta.advice_column();
+            let rc_a = (0..3).map(|_| meta.fixed_column()).collect::<Vec<_>>();
+            let rc_b = (0..3).map(|_| meta.fixed_column()).collect::<Vec<_>>();
+            meta.enable_constant(rc_b[0]);
+            let poseidon_config = PoseidonChip::configure::<poseidon::P128Pow5T3>(
+                meta,
+                state.try_into().unwrap(),
+                partial_sbox,
+                rc_a.try_into().unwrap(),
+                rc_b.try_into().unwrap(),
+            );
+
+            MyConfig {
+                advice,
+                instance,
+                poseidon_config,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<pallas::Base>,
+        ) -> Result<(), Error> {
+            let mut message = vec![];
+            for m in self.message.iter() {
+                message.push(assign_free_advice(
+                    layouter.namespace(|| "witness message"),
+                    config.advice,
+                    Value::known(*m),
+                )?);
+            }
+            let hash = poseidon_hash_gadget(
+                config.poseidon_config,
+                layouter.namespace(|| "poseidon hash"),
+                message.try_into().unwrap(),
+            )?;
+            hash.value()
+                .map(|h| assert_eq!(*h, poseidon_hash_n(self.message)));
+            layouter.constrain_instance(hash.cell(), config.instance, 0)
+        }
+    }
+
+    fn check<const L: usize>() {
+        let message = [(); L].map(|_| pallas::Base::random(OsRng));
+        let expected = poseidon_hash_n(message);
+        assert_eq!(poseidon_hash_slice(&message), expected);
+
+        let circuit = MyCircuit { message };
+        let prover = MockProver::run(10, &circuit, vec![vec![expected]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let prover =
+            MockProver::run(10, &circuit, vec![vec![expected + pallas::Base::one()]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    check::<2>();
+    check::<8>();
+    check::<12>();
+    check::<16>();
+}
+
+#[test]
+fn test_poseidon_sponge_gadget() {
+    use crate::circuit::gadgets::assign_free_advice;
+    use crate::utils::{poseidon_hash_n, PoseidonSponge};
+    use halo2_proofs::{
+        arithmetic::Field,
+        circuit::{SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Advice, Circuit, Column, ConstraintSystem, Instance},
+    };
+    use rand::rngs::OsRng;
+
+    #[derive(Clone, Debug)]
+    enum SpongeOp {
+        Absorb(Vec<pallas::Base>),
+        Squeeze,
+    }
+
+    #[derive(Clone, Debug)]
+    struct MyConfig {
+        advice: Column<Advice>,
+        instance: Column<Instance>,
+        poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
+    }
+
+    // Runs the ops and exposes the squeezed outputs in order
+    struct MyCircuit {
+        ops: Vec<SpongeOp>,
+    }
+
+    impl Circuit<pallas::Base> for MyCircuit {
+        type Config = MyConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                ops: self.ops.clone(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+            let advice = meta.advice_column();
+            meta.enable_equality(advice);
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+
+            let state = (0..3).map(|_| meta.advice_column()).collect::<Vec<_>>();
+            let partial_sbox = meta.advice_column();
+            let rc_a = (0..3).map(|_| meta.fixed_column()).collect::<Vec<_>>();
+            let rc_b = (0..3).map(|_| meta.fixed_column()).collect::<Vec<_>>();
+            meta.enable_constant(rc_b[0]);
+            let poseidon_config = PoseidonChip::configure::<poseidon::P128Pow5T3>(
+                meta,
+                state.try_into().unwrap(),
+                partial_sbox,
+                rc_a.try_into().unwrap(),
+                rc_b.try_into().unwrap(),
+            );
+
+            MyConfig {
+                advice,
+                instance,
+                poseidon_config,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<pallas::Base>,
+        ) -> Result<(), Error> {
+            let mut sponge = PoseidonSpongeGadget::<VariableLength>::new(
+                config.poseidon_config,
+                layouter.namespace(|| "sponge"),
+            )?;
+            let mut outputs = 0;
+            for op in self.ops.iter() {
+                match op {
+                    SpongeOp::Absorb(elements) => {
+                        let cells = elements
+                            .iter()
+                            .map(|e| {
+                                assign_free_advice(
+                                    layouter.namespace(|| "witness element"),
+                                    config.advice,
+                                    Value::known(*e),
+                                )
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        sponge.absorb(layouter.namespace(|| "absorb"), &cells)?;
+                    }
+                    SpongeOp::Squeeze => {
+                        let output = sponge.squeeze(layouter.namespace(|| "squeeze"))?;
+                        layouter.constrain_instance(output.cell(), config.instance, outputs)?;
+                        outputs += 1;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn native_outputs(ops: &[SpongeOp]) -> Vec<pallas::Base> {
+        let mut sponge = PoseidonSponge::<VariableLength>::new();
+        ops.iter()
+            .filter_map(|op| match op {
+                SpongeOp::Absorb(elements) => {
+                    sponge.absorb(elements);
+                    None
+                }
+                SpongeOp::Squeeze => Some(sponge.squeeze()),
+            })
+            .collect()
+    }
+
+    fn check(ops: Vec<SpongeOp>) -> Vec<pallas::Base> {
+        let expected = native_outputs(&ops);
+        let circuit = MyCircuit { ops };
+        let prover = MockProver::run(11, &circuit, vec![expected.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let mut wrong = expected.clone();
+        wrong[0] += pallas::Base::one();
+        let prover = MockProver::run(11, &circuit, vec![wrong]).unwrap();
+        assert!(prover.verify().is_err());
+        expected
+    }
+
+    let random = |len: usize| {
+        (0..len)
+            .map(|_| pallas::Base::random(OsRng))
+            .collect::<Vec<_>>()
+    };
+
+    // The circuit matches the native sponge, the second squeeze of 1 and 9 elements permutes
+    // again
+    for len in [1, 2, 5, 9] {
+        let outputs = check(vec![
+            SpongeOp::Absorb(random(len)),
+            SpongeOp::Squeeze,
+            SpongeOp::Squeeze,
+            SpongeOp::Squeeze,
+        ]);
+        assert_ne!(outputs[0], outputs[1]);
+        assert_ne!(outputs[1], outputs[2]);
+    }
+
+    // The grouping of the absorbed elements doesn't matter
+    let message = random(5);
+    let grouped = check(vec![
+        SpongeOp::Absorb(message[..2].to_vec()),
+        SpongeOp::Absorb(vec![]),
+        SpongeOp::Absorb(message[2..].to_vec()),
+        SpongeOp::Squeeze,
+    ]);
+    assert_eq!(
+        grouped,
+        native_outputs(&[SpongeOp::Absorb(message.clone()), SpongeOp::Squeeze])
+    );
+
+    // A squeeze in between pads the first group, it's the hash of the first output and the rest
+    let duplexed = check(vec![
+        SpongeOp::Absorb(message[..2].to_vec()),
+        SpongeOp::Squeeze,
+        SpongeOp::Absorb(message[2..].to_vec()),
+        SpongeOp::Squeeze,
+    ]);
+    assert_ne!(duplexed[1], grouped[0]);
+    let mut chained = vec![duplexed[0]];
+    chained.extend_from_slice(&message[2..]);
+    assert_eq!(
+        duplexed[1],
+        native_outputs(&[SpongeOp::Absorb(chained), SpongeOp::Squeeze])[0]
+    );
+
+    // The padding is injective, trailing ones and zeros change the hash
+    let one_zero = [pallas::Base::one(), pallas::Base::zero()];
+    let hash =
+        |m: &[pallas::Base]| native_outputs(&[SpongeOp::Absorb(m.to_vec()), SpongeOp::Squeeze])[0];
+    assert_ne!(hash(&message[..1]), hash(&[message[0], one_zero[0]]));
+    assert_ne!(
+        hash(&message[..2]),
+        hash(&[message[0], message[1], one_zero[1]])
+    );
+    assert_ne!(
+        hash(&message[..1]),
+        hash(&[message[0], one_zero[0], one_zero[1]])
+    );
+    assert_ne!(hash(&[]), hash(&one_zero[..1]));
 
-    poseidon_hasher.hash(layouter.namespace(|| "poseidon hash"), messages)
+    // The variable-length domain is separated from the fixed-length hash, and the native sponge
+    // over ConstantLength gives the fixed-length hash
+    let fixed: [pallas::Base; 5] = message.clone().try_into().unwrap();
+    assert_ne!(grouped[0], poseidon_hash_n(fixed));
+    let mut sponge = PoseidonSponge::<ConstantLength<5>>::new();
+    sponge.absorb(&message[..3]);
+    sponge.absorb(&message[3..]);
+    assert_eq!(sponge.squeeze(), poseidon_hash_n(fixed));
 }
@@ -1,4 +1,11 @@
-use crate::circuit::vp_circuit::NoteSearchableVariablePair;
+use crate::circuit::gadgets::{
+    assign_free_constant,
+    sub::{SubChip, SubInstructions},
+};
+use crate::circuit::vp_circuit::{
+    BasicValidityPredicateVariables, NoteSearchableVariablePair, NoteVariables,
+    ValidityPredicateConfig,
+};
 use crate::constant::NUM_NOTE;
 use halo2_gadgets::utilities::bool_check;
 use halo2_proofs::{
@@ -47,6 +54,100 @@ pub fn get_is_input_note_flag(
     )
 }
 
+// The owned note variables and whether the owned note is an input or an output note.
+// Exactly one of is_input_note and is_output_note is one.
+#[derive(Debug, Clone)]
+pub struct OwnedNoteVariables {
+    pub is_input_note: AssignedCell<pallas::Base, pallas::Base>,
+    pub is_output_note: AssignedCell<pallas::Base, pallas::Base>,
+    pub note_variables: NoteVariables,
+}
+
+// Search the owned note and get the input/output flags and all the note variables.
+// The constraints fail if the owned_note_pub_id is neither an input nf nor an output cm.
+pub fn get_owned_note_variables(
+    config: &ValidityPredicateConfig,
+    mut layouter: impl Layouter<pallas::Base>,
+    basic_variables: &BasicValidityPredicateVariables,
+) -> Result<OwnedNoteVariables, Error> {
+    let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
+    let is_input_note = get_is_input_note_flag(
+        config.get_is_input_note_flag_config,
+        layouter.namespace(|| "get is_input_note_flag"),
+        &owned_note_pub_id,
+        &basic_variables.get_input_note_nfs(),
+        &basic_variables.get_output_note_cms(),
+    )?;
+    let is_output_note = {
+        let constant_one = assign_free_constant(
+            layouter.namespace(|| "one"),
+            config.advices[0],
+            pallas::Base::one(),
+        )?;
+        let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+        SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "1 - is_input_note"),
+            &constant_one,
+            &is_input_note,
+        )?
+    };
+
+    let mut get_variable =
+        |name: &'static str, pairs: [NoteSearchableVariablePair; NUM_NOTE * 2]| {
+            get_owned_note_variable(
+                config.get_owned_note_variable_config,
+                layouter.namespace(|| name),
+                &owned_note_pub_id,
+                &pairs,
+            )
+        };
+    let note_variables = NoteVariables {
+        app_vk: get_variable(
+            "get owned note app_vk",
+            basic_variables.get_app_vk_searchable_pairs(),
+        )?,
+        app_data_static: get_variable(
+            "get owned note app_data_static",
+            basic_variables.get_app_data_static_searchable_pairs(),
+        )?,
+        value: get_variable(
+            "get owned note value",
+            basic_variables.get_value_searchable_pairs(),
+        )?,
+        is_merkle_checked: get_variable(
+            "get owned note is_merkle_checked",
+            basic_variables.get_is_merkle_checked_searchable_pairs(),
+        )?,
+        app_data_dynamic: get_variable(
+            "get owned note app_data_dynamic",
+            basic_variables.get_app_data_dynamic_searchable_pairs(),
+        )?,
+        rho: get_variable(
+            "get owned note rho",
+            basic_variables.get_rho_searchable_pairs(),
+        )?,
+        nk_com: get_variable(
+            "get owned note nk_com",
+            basic_variables.get_nk_com_searchable_pairs(),
+        )?,
+        psi: get_variable(
+            "get owned note psi",
+            basic_variables.get_psi_searchable_pairs(),
+        )?,
+        rcm: get_variable(
+            "get owned note rcm",
+            basic_variables.get_rcm_searchable_pairs(),
+        )?,
+    };
+
+    Ok(OwnedNoteVariables {
+        is_input_note,
+        is_output_note,
+        note_variables,
+    })
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct GetOwnedNoteVariableConfig {
     q_get_owned_note_variable: Selector,
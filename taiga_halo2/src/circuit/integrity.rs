@@ -3,12 +3,16 @@ use crate::circuit::{
     hash_to_curve::{hash_to_curve_circuit, HashToCurveConfig},
     vp_circuit::{InputNoteVariables, NoteVariables, OutputNoteVariables},
 };
-use crate::constant::{TaigaFixedBases, TaigaFixedBasesFull, POSEIDON_TO_CURVE_INPUT_LEN};
-use crate::note::Note;
+use crate::constant::{
+    TaigaFixedBases, TaigaFixedBasesFull, NK_COMMITMENT_DOMAIN, NOTE_COMMITMENT_POSEIDON_DOMAIN,
+    POSEIDON_TO_CURVE_INPUT_LEN,
+};
+use crate::note::{Note, NoteCommitmentPreimage, NOTE_COMMITMENT_PREIMAGE_LABELS};
 use crate::utils::poseidon_to_curve;
 use halo2_gadgets::{
     ecc::{chip::EccChip, FixedPoint, NonIdentityPoint, Point, ScalarFixed, ScalarVar},
     poseidon::Pow5Config as PoseidonConfig,
+    utilities::lookup_range_check::LookupRangeCheckConfig,
 };
 use halo2_proofs::{
     circuit::{AssignedCell, Layouter, Value},
@@ -18,36 +22,23 @@ use pasta_curves::group::Curve;
 use pasta_curves::pallas;
 use std::ops::Neg;
 
-#[allow(clippy::too_many_arguments)]
 pub fn note_commitment_circuit(
     mut layouter: impl Layouter<pallas::Base>,
+    advice: Column<Advice>,
     poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
-    app_vp: AssignedCell<pallas::Base, pallas::Base>,
-    app_data_static: AssignedCell<pallas::Base, pallas::Base>,
-    app_data_dynamic: AssignedCell<pallas::Base, pallas::Base>,
-    nk_com: AssignedCell<pallas::Base, pallas::Base>,
-    rho: AssignedCell<pallas::Base, pallas::Base>,
-    psi: AssignedCell<pallas::Base, pallas::Base>,
-    value: AssignedCell<pallas::Base, pallas::Base>,
-    is_merkle_checked: AssignedCell<pallas::Base, pallas::Base>,
-    rcm: AssignedCell<pallas::Base, pallas::Base>,
+    preimage: NoteCommitmentPreimage<AssignedCell<pallas::Base, pallas::Base>>,
 ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+    let domain = assign_free_constant(
+        layouter.namespace(|| NOTE_COMMITMENT_PREIMAGE_LABELS[0]),
+        advice,
+        NOTE_COMMITMENT_POSEIDON_DOMAIN,
+    )?;
+
     // TODO: compose the value and is_merkle_checked to one field in order to save one poseidon absorb
-    let poseidon_message = [
-        app_vp,
-        app_data_static,
-        app_data_dynamic,
-        nk_com,
-        rho,
-        psi,
-        is_merkle_checked,
-        value,
-        rcm,
-    ];
     poseidon_hash_gadget(
         poseidon_config,
         layouter.namespace(|| "note commitment"),
-        poseidon_message,
+        preimage.to_field_elements_with_domain(domain),
     )
 }
 
@@ -88,17 +79,17 @@ pub fn check_input_note(
         Value::known(nk),
     )?;
 
-    let zero_constant = assign_free_constant(
-        layouter.namespace(|| "constant zero"),
+    let nk_com_domain = assign_free_constant(
+        layouter.namespace(|| "nk_com domain"),
         advices[0],
-        pallas::Base::zero(),
+        NK_COMMITMENT_DOMAIN,
     )?;
 
-    // nk_com = Com_r(nk, zero)
+    // nk_com = poseidon_hash(NK_COMMITMENT_DOMAIN || nk)
     let nk_com = poseidon_hash_gadget(
         poseidon_config.clone(),
         layouter.namespace(|| "nk_com encoding"),
-        [nk_var.clone(), zero_constant],
+        [nk_com_domain, nk_var.clone()],
     )?;
 
     // Witness app_data_dynamic
@@ -160,16 +151,19 @@ pub fn check_input_note(
     // Check note commitment
     let cm = note_commitment_circuit(
         layouter.namespace(|| "note commitment"),
+        advices[0],
         poseidon_config.clone(),
-        app_vk.clone(),
-        app_data_static.clone(),
-        app_data_dynamic.clone(),
-        nk_com.clone(),
-        rho.clone(),
-        psi.clone(),
-        value.clone(),
-        is_merkle_checked.clone(),
-        rcm.clone(),
+        NoteCommitmentPreimage {
+            app_vk: app_vk.clone(),
+            app_data_static: app_data_static.clone(),
+            app_data_dynamic: app_data_dynamic.clone(),
+            nk_commitment: nk_com.clone(),
+            rho: rho.clone(),
+            psi: psi.clone(),
+            is_merkle_checked: is_merkle_checked.clone(),
+            value: value.clone(),
+            rcm: rcm.clone(),
+        },
     )?;
 
     // Generate nullifier
@@ -275,16 +269,19 @@ pub fn check_output_note(
     // Check note commitment
     let cm = note_commitment_circuit(
         layouter.namespace(|| "note commitment"),
+        advices[0],
         poseidon_config.clone(),
-        app_vk.clone(),
-        app_data_static.clone(),
-        app_data_dynamic.clone(),
-        nk_com.clone(),
-        old_nf.clone(),
-        psi.clone(),
-        value.clone(),
-        is_merkle_checked.clone(),
-        rcm.clone(),
+        NoteCommitmentPreimage {
+            app_vk: app_vk.clone(),
+            app_data_static: app_data_static.clone(),
+            app_data_dynamic: app_data_dynamic.clone(),
+            nk_commitment: nk_com.clone(),
+            rho: old_nf.clone(),
+            psi: psi.clone(),
+            is_merkle_checked: is_merkle_checked.clone(),
+            value: value.clone(),
+            rcm: rcm.clone(),
+        },
     )?;
 
     // Public cm
@@ -339,6 +336,30 @@ pub fn derive_note_type(
     Ok(non_identity_point_var)
 }
 
+// Check the value is a u64: the low 60 bits in 6 words of 10 bits, and the high 4 bits in a short
+// range check.
+pub fn check_value_range(
+    mut layouter: impl Layouter<pallas::Base>,
+    range_check: &LookupRangeCheckConfig<pallas::Base, 10>,
+    value: &AssignedCell<pallas::Base, pallas::Base>,
+) -> Result<(), Error> {
+    let zs = range_check.copy_check(
+        layouter.namespace(|| "value low bits"),
+        value.clone(),
+        6,
+        false,
+    )?;
+    let high_bits = range_check.witness_short_check(
+        layouter.namespace(|| "value high bits"),
+        zs[6].value().copied(),
+        4,
+    )?;
+    layouter.assign_region(
+        || "constrain value high bits",
+        |mut region| region.constrain_equal(zs[6].cell(), high_bits.cell()),
+    )
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn compute_value_commitment(
     mut layouter: impl Layouter<pallas::Base>,
@@ -573,3 +594,123 @@ fn test_halo2_nullifier_circuit() {
     let prover = MockProver::run(11, &circuit, vec![]).unwrap();
     assert_eq!(prover.verify(), Ok(()))
 }
+
+#[test]
+fn test_halo2_note_commitment_circuit() {
+    use crate::circuit::gadgets::assign_free_advice;
+    use crate::note::tests::random_input_note;
+    use halo2_gadgets::poseidon::{
+        primitives as poseidon, Pow5Chip as PoseidonChip, Pow5Config as PoseidonConfig,
+    };
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Advice, Circuit, Column, ConstraintSystem, Error},
+    };
+    use rand::rngs::OsRng;
+
+    #[derive(Default)]
+    struct MyCircuit {
+        note: Note,
+    }
+
+    impl Circuit<pallas::Base> for MyCircuit {
+        #[allow(clippy::type_complexity)]
+        type Config = ([Column<Advice>; 4], PoseidonConfig<pallas::Base, 3, 2>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self::default()
+        }
+
+        fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+            let advices = [
+                meta.advice_column(),
+                meta.advice_column(),
+                meta.advice_column(),
+                meta.advice_column(),
+            ];
+            for advice in advices.iter() {
+                meta.enable_equality(*advice);
+            }
+
+            let lagrange_coeffs = [
+                meta.fixed_column(),
+                meta.fixed_column(),
+                meta.fixed_column(),
+                meta.fixed_column(),
+                meta.fixed_column(),
+                meta.fixed_column(),
+            ];
+            let constants = meta.fixed_column();
+            meta.enable_constant(constants);
+
+            let poseidon_config = PoseidonChip::configure::<poseidon::P128Pow5T3>(
+                meta,
+                advices[1..4].try_into().unwrap(),
+                advices[0],
+                lagrange_coeffs[0..3].try_into().unwrap(),
+                lagrange_coeffs[3..6].try_into().unwrap(),
+            );
+            (advices, poseidon_config)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<pallas::Base>,
+        ) -> Result<(), Error> {
+            let (advices, poseidon_config) = config;
+            // Witness the preimage without the domain
+            let preimage = self.note.commitment_preimage().to_field_elements();
+            let cells = preimage[1..]
+                .iter()
+                .zip(NOTE_COMMITMENT_PREIMAGE_LABELS[1..].iter())
+                .map(|(value, label)| {
+                    assign_free_advice(
+                        layouter.namespace(|| *label),
+                        advices[0],
+                        Value::known(*value),
+                    )
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            let cm = note_commitment_circuit(
+                layouter.namespace(|| "note commitment"),
+                advices[0],
+                poseidon_config,
+                NoteCommitmentPreimage {
+                    app_vk: cells[0].clone(),
+                    app_data_static: cells[1].clone(),
+                    app_data_dynamic: cells[2].clone(),
+                    nk_commitment: cells[3].clone(),
+                    rho: cells[4].clone(),
+                    psi: cells[5].clone(),
+                    is_merkle_checked: cells[6].clone(),
+                    value: cells[7].clone(),
+                    rcm: cells[8].clone(),
+                },
+            )?;
+
+            let expect_cm = assign_free_advice(
+                layouter.namespace(|| "witness cm"),
+                advices[0],
+                Value::known(self.note.commitment().inner()),
+            )?;
+
+            layouter.assign_region(
+                || "constrain result",
+                |mut region| region.constrain_equal(cm.cell(), expect_cm.cell()),
+            )
+        }
+    }
+
+    let mut rng = OsRng;
+    for _ in 0..4 {
+        let circuit = MyCircuit {
+            note: random_input_note(&mut rng),
+        };
+        let prover = MockProver::run(11, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()))
+    }
+}
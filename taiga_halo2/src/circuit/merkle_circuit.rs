@@ -1,14 +1,28 @@
 use crate::circuit::gadgets::poseidon_hash::poseidon_hash_gadget;
-use crate::merkle_tree::{is_left, LR};
+use crate::constant::{
+    NoteCommitmentDomain, NoteCommitmentHashDomain, TaigaFixedBases, MERKLE_CRH_Q,
+};
+use crate::merkle_tree::{is_left, MerkleHash, Node, PoseidonMerkleHash, SinsemillaMerkleHash, LR};
 use halo2_gadgets::{
     poseidon::Pow5Config as PoseidonConfig,
-    utilities::cond_swap::{CondSwapChip, CondSwapConfig, CondSwapInstructions},
+    sinsemilla::{
+        chip::{SinsemillaChip, SinsemillaConfig},
+        merkle::{
+            chip::{MerkleChip, MerkleConfig},
+            MerkleInstructions,
+        },
+    },
+    utilities::{
+        cond_swap::{CondSwapChip, CondSwapConfig, CondSwapInstructions},
+        lookup_range_check::LookupRangeCheckConfig,
+    },
 };
 use halo2_proofs::{
     circuit::{AssignedCell, Chip, Layouter, Value},
-    plonk::{Advice, Column, ConstraintSystem, Error},
+    plonk::{Advice, Column, ConstraintSystem, Error, Fixed, TableColumn},
 };
 use pasta_curves::pallas;
+use std::fmt::Debug;
 
 /// MerkleTreeChip based on poseidon hash.
 #[derive(Clone, Debug)]
@@ -105,11 +119,170 @@ pub fn merkle_poseidon_gadget(
     Ok(cur)
 }
 
+/// The circuit side of a MerkleHash, the action circuit checks the input note path with it.
+pub trait MerkleHashChip: MerkleHash {
+    type Config: Clone + Debug;
+
+    fn configure(
+        meta: &mut ConstraintSystem<pallas::Base>,
+        advices: [Column<Advice>; 10],
+        fixed_y_q: Column<Fixed>,
+        table_idx: TableColumn,
+        range_check: LookupRangeCheckConfig<pallas::Base, 10>,
+        poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
+    ) -> Self::Config;
+
+    /// Loads the lookup tables, including the table_idx of the range check
+    fn load(
+        config: &Self::Config,
+        table_idx: TableColumn,
+        layouter: &mut impl Layouter<pallas::Base>,
+    ) -> Result<(), Error>;
+
+    /// Returns the root of the path applied to the leaf
+    fn root_gadget(
+        config: &Self::Config,
+        layouter: impl Layouter<pallas::Base>,
+        leaf: AssignedCell<pallas::Base, pallas::Base>,
+        merkle_path: &[(Node<Self>, LR)],
+    ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error>;
+}
+
+impl MerkleHashChip for PoseidonMerkleHash {
+    type Config = MerklePoseidonConfig;
+
+    fn configure(
+        meta: &mut ConstraintSystem<pallas::Base>,
+        advices: [Column<Advice>; 10],
+        _fixed_y_q: Column<Fixed>,
+        _table_idx: TableColumn,
+        _range_check: LookupRangeCheckConfig<pallas::Base, 10>,
+        poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
+    ) -> Self::Config {
+        MerklePoseidonChip::configure(meta, advices[..5].try_into().unwrap(), poseidon_config)
+    }
+
+    fn load(
+        _config: &Self::Config,
+        table_idx: TableColumn,
+        layouter: &mut impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        // Only the range check table
+        layouter.assign_table(
+            || "table_idx",
+            |mut table| {
+                // We generate the row values lazily (we only need them during keygen).
+                for index in 0..(1 << 10) {
+                    table.assign_cell(
+                        || "table_idx",
+                        table_idx,
+                        index,
+                        || Value::known(pallas::Base::from(index as u64)),
+                    )?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    fn root_gadget(
+        config: &Self::Config,
+        layouter: impl Layouter<pallas::Base>,
+        leaf: AssignedCell<pallas::Base, pallas::Base>,
+        merkle_path: &[(Node<Self>, LR)],
+    ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+        let merkle_path: Vec<_> = merkle_path
+            .iter()
+            .map(|(node, lr)| (node.inner(), *lr))
+            .collect();
+        merkle_poseidon_gadget(
+            layouter,
+            MerklePoseidonChip::construct(config.clone()),
+            leaf,
+            &merkle_path,
+        )
+    }
+}
+
+/// MerkleTreeChip based on the sinsemilla MerkleCRH.
+#[derive(Clone, Debug)]
+pub struct MerkleSinsemillaConfig {
+    sinsemilla_config:
+        SinsemillaConfig<NoteCommitmentHashDomain, NoteCommitmentDomain, TaigaFixedBases>,
+    merkle_config: MerkleConfig<NoteCommitmentHashDomain, NoteCommitmentDomain, TaigaFixedBases>,
+}
+
+impl MerkleHashChip for SinsemillaMerkleHash {
+    type Config = MerkleSinsemillaConfig;
+
+    fn configure(
+        meta: &mut ConstraintSystem<pallas::Base>,
+        advices: [Column<Advice>; 10],
+        fixed_y_q: Column<Fixed>,
+        table_idx: TableColumn,
+        range_check: LookupRangeCheckConfig<pallas::Base, 10>,
+        _poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
+    ) -> Self::Config {
+        let lookup = (
+            table_idx,
+            meta.lookup_table_column(),
+            meta.lookup_table_column(),
+        );
+        let sinsemilla_config = SinsemillaChip::configure(
+            meta,
+            advices[..5].try_into().unwrap(),
+            advices[6],
+            fixed_y_q,
+            lookup,
+            range_check,
+        );
+        let merkle_config = MerkleChip::configure(meta, sinsemilla_config.clone());
+        MerkleSinsemillaConfig {
+            sinsemilla_config,
+            merkle_config,
+        }
+    }
+
+    fn load(
+        config: &Self::Config,
+        _table_idx: TableColumn,
+        layouter: &mut impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        // The sinsemilla table fills the table_idx as well
+        SinsemillaChip::load(config.sinsemilla_config.clone(), layouter)
+    }
+
+    fn root_gadget(
+        config: &Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        leaf: AssignedCell<pallas::Base, pallas::Base>,
+        merkle_path: &[(Node<Self>, LR)],
+    ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+        let chip = MerkleChip::construct(config.merkle_config.clone());
+        let mut cur = leaf;
+        for (level, (sibling, lr)) in merkle_path.iter().enumerate() {
+            let (left, right) = chip.swap(
+                layouter.namespace(|| "merkle swap"),
+                (cur, Value::known(sibling.inner())),
+                Value::known(is_left(*lr)),
+            )?;
+            cur = chip.hash_layer(
+                layouter.namespace(|| "merkle sinsemilla hash"),
+                *MERKLE_CRH_Q,
+                level,
+                left,
+                right,
+            )?;
+        }
+        Ok(cur)
+    }
+}
+
 #[test]
 fn test_halo2_merkle_circuit() {
     use crate::circuit::gadgets::assign_free_advice;
     use crate::constant::TAIGA_COMMITMENT_TREE_DEPTH;
-    use crate::merkle_tree::{MerklePath, Node};
+    use crate::merkle_tree::MerklePath;
     use halo2_gadgets::poseidon::{primitives as poseidon, Pow5Chip as PoseidonChip};
     use halo2_proofs::{
         arithmetic::Field,
@@ -120,13 +293,15 @@ fn test_halo2_merkle_circuit() {
     use rand::rngs::OsRng;
 
     #[derive(Default)]
-    struct MyCircuit {
+    struct MyCircuit<H: MerkleHashChip> {
         leaf: pallas::Base,
-        merkle_path: MerklePath,
+        merkle_path: MerklePath<H>,
+        // The root to check against, it's the root of the path by default
+        root: Option<pallas::Base>,
     }
 
-    impl Circuit<pallas::Base> for MyCircuit {
-        type Config = MerklePoseidonConfig;
+    impl<H: MerkleHashChip> Circuit<pallas::Base> for MyCircuit<H> {
+        type Config = ([Column<Advice>; 10], TableColumn, H::Config);
         type FloorPlanner = SimpleFloorPlanner;
 
         fn without_witnesses(&self) -> Self {
@@ -134,65 +309,71 @@ fn test_halo2_merkle_circuit() {
         }
 
         fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
-            let advices = [
-                meta.advice_column(),
-                meta.advice_column(),
-                meta.advice_column(),
-                meta.advice_column(),
-                meta.advice_column(),
-            ];
+            let advices = [(); 10].map(|_| meta.advice_column());
             for advice in advices.iter() {
                 meta.enable_equality(*advice);
             }
-            let cond_swap_config = CondSwapChip::configure(meta, advices);
 
-            let state = (0..3).map(|_| meta.advice_column()).collect::<Vec<_>>();
-            let partial_sbox = meta.advice_column();
+            let table_idx = meta.lookup_table_column();
+            let range_check = LookupRangeCheckConfig::configure(meta, advices[9], table_idx);
+
+            let constants = meta.fixed_column();
+            meta.enable_constant(constants);
+            let fixed_y_q = meta.fixed_column();
+
             let rc_a = (0..3).map(|_| meta.fixed_column()).collect::<Vec<_>>();
             let rc_b = (0..3).map(|_| meta.fixed_column()).collect::<Vec<_>>();
-            meta.enable_constant(rc_b[0]);
             let poseidon_config = PoseidonChip::configure::<poseidon::P128Pow5T3>(
                 meta,
-                state.try_into().unwrap(),
-                partial_sbox,
+                advices[6..9].try_into().unwrap(),
+                advices[5],
                 rc_a.try_into().unwrap(),
                 rc_b.try_into().unwrap(),
             );
 
-            Self::Config {
+            let merkle_config = H::configure(
+                meta,
                 advices,
-                cond_swap_config,
+                fixed_y_q,
+                table_idx,
+                range_check,
                 poseidon_config,
-            }
+            );
+            (advices, table_idx, merkle_config)
         }
 
         fn synthesize(
             &self,
-            config: Self::Config,
+            (advices, table_idx, merkle_config): Self::Config,
             mut layouter: impl Layouter<pallas::Base>,
         ) -> Result<(), Error> {
+            H::load(&merkle_config, table_idx, &mut layouter)?;
+
             // Witness leaf
             let leaf = assign_free_advice(
                 layouter.namespace(|| "witness leaf"),
-                config.advices[0],
+                advices[0],
                 Value::known(self.leaf),
             )?;
 
-            let merkle_chip = MerklePoseidonChip::construct(config.clone());
-
-            let root = merkle_poseidon_gadget(
-                layouter.namespace(|| "poseidon merkle"),
-                merkle_chip,
+            let root = H::root_gadget(
+                &merkle_config,
+                layouter.namespace(|| "merkle"),
                 leaf,
-                &self.merkle_path.get_path(),
+                &self
+                    .merkle_path
+                    .to_array::<TAIGA_COMMITMENT_TREE_DEPTH>()
+                    .unwrap(),
             )?;
 
             let expected_root = {
-                let root = self.merkle_path.root(Node::from(self.leaf));
+                let root = self
+                    .root
+                    .unwrap_or_else(|| self.merkle_path.root(Node::from(self.leaf)).inner());
                 assign_free_advice(
-                    layouter.namespace(|| "witness leaf"),
-                    config.advices[0],
-                    Value::known(root.inner()),
+                    layouter.namespace(|| "witness root"),
+                    advices[0],
+                    Value::known(root),
                 )?
             };
             layouter.assign_region(
@@ -202,13 +383,48 @@ fn test_halo2_merkle_circuit() {
         }
     }
 
-    let mut rng = OsRng;
+    fn run<H: MerkleHashChip>(
+        leaf: pallas::Base,
+        merkle_path: MerklePath<H>,
+        root: Option<pallas::Base>,
+    ) -> bool {
+        let circuit = MyCircuit {
+            leaf,
+            merkle_path,
+            root,
+        };
+        MockProver::run(12, &circuit, vec![])
+            .unwrap()
+            .verify()
+            .is_ok()
+    }
 
+    let mut rng = OsRng;
     let leaf = pallas::Base::random(rng);
-    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
-
-    let circuit = MyCircuit { leaf, merkle_path };
+    let poseidon_path: MerklePath<PoseidonMerkleHash> =
+        MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    let sinsemilla_path: MerklePath<SinsemillaMerkleHash> =
+        MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    assert!(run(leaf, poseidon_path.clone(), None));
+    assert!(run(leaf, sinsemilla_path.clone(), None));
 
-    let prover = MockProver::run(11, &circuit, vec![]).unwrap();
-    assert_eq!(prover.verify(), Ok(()))
+    // The root of one hash doesn't open in the circuit of the other
+    let poseidon_root = poseidon_path.root(Node::from(leaf)).inner();
+    let sinsemilla_root = sinsemilla_path.root(Node::from(leaf)).inner();
+    let as_sinsemilla_path: MerklePath<SinsemillaMerkleHash> = MerklePath::from_path(
+        poseidon_path
+            .get_path()
+            .into_iter()
+            .map(|(node, lr)| (Node::from(node), lr))
+            .collect(),
+    );
+    assert!(!run(leaf, as_sinsemilla_path, Some(poseidon_root)));
+    let as_poseidon_path: MerklePath<PoseidonMerkleHash> = MerklePath::from_path(
+        sinsemilla_path
+            .get_path()
+            .into_iter()
+            .map(|(node, lr)| (Node::from(node), lr))
+            .collect(),
+    );
+    assert!(!run(leaf, as_poseidon_path, Some(sinsemilla_root)));
 }
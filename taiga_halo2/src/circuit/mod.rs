@@ -6,6 +6,7 @@ pub mod merkle_circuit;
 #[macro_use]
 pub mod vp_circuit;
 pub mod blake2s;
+pub mod compressed_instance;
 pub mod curve;
 pub mod hash_to_curve;
 pub mod note_encryption_circuit;
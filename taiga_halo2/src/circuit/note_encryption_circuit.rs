@@ -1,14 +1,16 @@
 use crate::circuit::gadgets::{
     add::{AddChip, AddInstructions},
     assign_free_advice, assign_free_constant,
+    poseidon_hash::poseidon_hash_gadget,
 };
 use crate::constant::{
-    BaseFieldGenerators, TaigaFixedBases, NOTE_ENCRYPTION_PLAINTEXT_NUM, POSEIDON_RATE,
-    POSEIDON_WIDTH, VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX,
+    BaseFieldGenerators, TaigaFixedBases, NOTE_CIPHERTEXT_COMMITMENT_DOMAIN,
+    NOTE_ENCRYPTION_CIPHERTEXT_NUM, NOTE_ENCRYPTION_KEY_DOMAIN, NOTE_ENCRYPTION_PLAINTEXT_NUM,
+    POSEIDON_RATE, POSEIDON_WIDTH, VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX,
 };
 use ff::PrimeField;
 use halo2_gadgets::{
-    ecc::{chip::EccChip, FixedPointBaseField, NonIdentityPoint, ScalarVar},
+    ecc::{chip::EccChip, FixedPointBaseField, NonIdentityPoint, Point, ScalarVar},
     poseidon::{
         primitives::{self as poseidon, Absorbing, ConstantLength},
         PaddedWord, PoseidonInstructions, PoseidonSpongeInstructions, Pow5Chip as PoseidonChip,
@@ -22,6 +24,7 @@ use halo2_proofs::{
 };
 use pasta_curves::pallas;
 
+// Encrypt the message and publicize the ciphertext along with the ephemeral pk
 #[allow(clippy::too_many_arguments)]
 pub fn note_encryption_gadget(
     mut layouter: impl Layouter<pallas::Base>,
@@ -31,10 +34,58 @@ pub fn note_encryption_gadget(
     add_chip: AddChip<pallas::Base>,
     ecc_chip: EccChip<TaigaFixedBases>,
     nonce: AssignedCell<pallas::Base, pallas::Base>,
-    sender_sk: AssignedCell<pallas::Base, pallas::Base>,
+    esk: AssignedCell<pallas::Base, pallas::Base>,
     rcv_pk: NonIdentityPoint<pallas::Affine, EccChip<TaigaFixedBases>>,
     message: &mut Vec<AssignedCell<pallas::Base, pallas::Base>>,
 ) -> Result<(), Error> {
+    let (mut cipher, epk) = note_ciphertext_gadget(
+        layouter.namespace(|| "note ciphertext"),
+        advice,
+        poseidon_config,
+        add_chip,
+        ecc_chip,
+        nonce,
+        esk,
+        rcv_pk,
+        message,
+    )?;
+
+    // Add the ephemeral pk
+    cipher.push(epk.inner().x());
+    cipher.push(epk.inner().y());
+
+    // Publicize the cipher
+    for (i, ele) in cipher.iter().enumerate() {
+        layouter.constrain_instance(
+            ele.cell(),
+            instances,
+            VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX + i,
+        )?;
+    }
+
+    Ok(())
+}
+
+// The ciphertext(the encrypted message, the nonce and the MAC) and the ephemeral pk, see
+// `NoteCiphertext::encrypt`
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn note_ciphertext_gadget(
+    mut layouter: impl Layouter<pallas::Base>,
+    advice: Column<Advice>,
+    poseidon_config: PoseidonConfig<pallas::Base, POSEIDON_WIDTH, POSEIDON_RATE>,
+    add_chip: AddChip<pallas::Base>,
+    ecc_chip: EccChip<TaigaFixedBases>,
+    nonce: AssignedCell<pallas::Base, pallas::Base>,
+    esk: AssignedCell<pallas::Base, pallas::Base>,
+    rcv_pk: NonIdentityPoint<pallas::Affine, EccChip<TaigaFixedBases>>,
+    message: &mut Vec<AssignedCell<pallas::Base, pallas::Base>>,
+) -> Result<
+    (
+        Vec<AssignedCell<pallas::Base, pallas::Base>>,
+        Point<pallas::Affine, EccChip<TaigaFixedBases>>,
+    ),
+    Error,
+> {
     // message padding
     let padding_zero = assign_free_advice(
         layouter.namespace(|| "padding zero"),
@@ -45,15 +96,38 @@ pub fn note_encryption_gadget(
         std::iter::repeat(padding_zero).take(NOTE_ENCRYPTION_PLAINTEXT_NUM - message.len());
     message.extend(paddings);
 
-    // Compute symmetric secret key
-    let sk = ScalarVar::from_base(
+    // DH key agreement with the ephemeral key: epk = esk * G is published with the ciphertext,
+    // the receiver gets the same shared point from rcv_sk * epk. rcv_pk is a NonIdentityPoint so
+    // the shared point can't be the identity for a nonzero esk.
+    let esk_scalar = ScalarVar::from_base(
         ecc_chip.clone(),
         layouter.namespace(|| "ScalarVar from_base"),
-        &sender_sk,
+        &esk,
     )?;
     let generator = FixedPointBaseField::from_inner(ecc_chip, BaseFieldGenerators::BaseGenerator);
-    let sender_pk = generator.mul(layouter.namespace(|| "sender_sk * generator"), sender_sk)?;
-    let (secret_key, _) = rcv_pk.mul(layouter.namespace(|| "sender_sk * rcv_pk"), sk)?;
+    let epk = generator.mul(layouter.namespace(|| "esk * generator"), esk)?;
+    let (shared_point, _) = rcv_pk.mul(layouter.namespace(|| "esk * rcv_pk"), esk_scalar)?;
+
+    // symmetric_key = poseidon_hash(NOTE_ENCRYPTION_KEY_DOMAIN || shared_x || shared_y)
+    let key_domain = assign_free_constant(
+        layouter.namespace(|| "note encryption key domain"),
+        advice,
+        NOTE_ENCRYPTION_KEY_DOMAIN,
+    )?;
+    let symmetric_key = poseidon_hash_gadget(
+        poseidon_config.clone(),
+        layouter.namespace(|| "symmetric key"),
+        [
+            key_domain,
+            shared_point.inner().x(),
+            shared_point.inner().y(),
+        ],
+    )?;
+    let zero = assign_free_constant(
+        layouter.namespace(|| "constant zero"),
+        advice,
+        pallas::Base::zero(),
+    )?;
 
     // length_nonce = length * 2^128 + nonce
     let length_var = assign_free_constant(
@@ -70,8 +144,8 @@ pub fn note_encryption_gadget(
     // Init poseidon sponge state
     let poseidon_chip = PoseidonChip::construct(poseidon_config);
     let init_state = vec![
-        StateWord::from(secret_key.inner().x()),
-        StateWord::from(secret_key.inner().y()),
+        StateWord::from(symmetric_key),
+        StateWord::from(zero),
         StateWord::from(length_nonce),
     ];
     let mut state = init_state.try_into().unwrap();
@@ -121,18 +195,29 @@ pub fn note_encryption_gadget(
     >>::permute(&poseidon_chip, &mut layouter, &state)?;
     cipher.push(state[0].clone().into());
 
-    // Add sender's pk
-    cipher.push(sender_pk.inner().x());
-    cipher.push(sender_pk.inner().y());
-
-    // Publicize the cipher
-    for (i, ele) in cipher.iter().enumerate() {
-        layouter.constrain_instance(
-            ele.cell(),
-            instances,
-            VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX + i,
-        )?;
-    }
+    Ok((cipher, epk))
+}
 
-    Ok(())
+// cipher_commitment = poseidon_hash(NOTE_CIPHERTEXT_COMMITMENT_DOMAIN || ciphertext), see
+// `NoteCiphertext::commitment`
+pub fn cipher_commitment_gadget(
+    mut layouter: impl Layouter<pallas::Base>,
+    advice: Column<Advice>,
+    poseidon_config: PoseidonConfig<pallas::Base, POSEIDON_WIDTH, POSEIDON_RATE>,
+    cipher: &[AssignedCell<pallas::Base, pallas::Base>],
+) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+    assert_eq!(cipher.len(), NOTE_ENCRYPTION_CIPHERTEXT_NUM);
+    let domain = assign_free_constant(
+        layouter.namespace(|| "cipher commitment domain"),
+        advice,
+        NOTE_CIPHERTEXT_COMMITMENT_DOMAIN,
+    )?;
+    let messages: Vec<_> = std::iter::once(domain)
+        .chain(cipher.iter().cloned())
+        .collect();
+    poseidon_hash_gadget::<{ NOTE_ENCRYPTION_CIPHERTEXT_NUM + 1 }>(
+        poseidon_config,
+        layouter.namespace(|| "cipher commitment"),
+        messages.try_into().unwrap(),
+    )
 }
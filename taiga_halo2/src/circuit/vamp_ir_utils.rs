@@ -1,4 +1,7 @@
 /// This module consists of definitions that will eventually be incorporated into the vamp-ir library
+use crate::constant::{NUM_NOTE, VP_CIRCUIT_MANDATORY_PUBLIC_INPUT_NUM};
+use crate::note::Note;
+use crate::vp_commitment::ValidityPredicateCommitment;
 use pasta_curves::Fp;
 use std::collections::HashMap;
 use vamp_ir::ast::{Module, Pat, VariableId};
@@ -42,3 +45,64 @@ pub(crate) fn get_circuit_assignments(
 pub(crate) fn parse(unparsed_file: &str) -> Result<Module, String> {
     Module::parse(unparsed_file).map_err(|err| err.to_string())
 }
+
+const VP_SIGNED_ANCHORS_IDX_NAME: &str = "taiga_signed_anchors_idx";
+const VP_ENCRYPTION_VP_VK_NAME: &str = "taiga_encryption_vp_vk";
+
+/// The variables a VampIR vp declares public ahead of the publics of its source, in the order of
+/// the mandatory vp public inputs. The source can use them by name.
+pub(crate) const VP_MANDATORY_PUBLIC_INPUT_NAMES: [&str; VP_CIRCUIT_MANDATORY_PUBLIC_INPUT_NUM] = [
+    "taiga_nf_1",
+    "taiga_cm_1",
+    "taiga_nf_2",
+    "taiga_cm_2",
+    "taiga_owned_note_pub_id",
+    VP_SIGNED_ANCHORS_IDX_NAME,
+    VP_ENCRYPTION_VP_VK_NAME,
+    "taiga_first_dynamic_vp_cm_1",
+    "taiga_first_dynamic_vp_cm_2",
+    "taiga_second_dynamic_vp_cm_1",
+    "taiga_second_dynamic_vp_cm_2",
+];
+
+/// Prepends the declarations of the mandatory vp public inputs to a VampIR source, so they are
+/// the first instance rows and the publics declared in the source follow them.
+pub(crate) fn with_mandatory_public_inputs(vamp_ir_source: &str) -> String {
+    let mut layout = String::new();
+    for name in VP_MANDATORY_PUBLIC_INPUT_NAMES {
+        // The trivial constraint gets the variable assigned even if the source doesn't use it.
+        // A VampIR vp signs no anchors and encrypts no note, both are fixed to 0.
+        if name == VP_SIGNED_ANCHORS_IDX_NAME || name == VP_ENCRYPTION_VP_VK_NAME {
+            layout.push_str(&format!("pub {name};\n{name} = 0;\n"));
+        } else {
+            layout.push_str(&format!("pub {name};\n{name} = {name};\n"));
+        }
+    }
+    layout + vamp_ir_source
+}
+
+/// The assignments of the mandatory vp public inputs. The dynamic vp commitments are the default
+/// ones, a VampIR vp can't open dynamic vps yet.
+pub(crate) fn mandatory_public_input_assignments(
+    owned_note_pub_id: Fp,
+    input_notes: &[Note; NUM_NOTE],
+    output_notes: &[Note; NUM_NOTE],
+) -> HashMap<String, Fp> {
+    let mut public_inputs = vec![];
+    for (input_note, output_note) in input_notes.iter().zip(output_notes.iter()) {
+        public_inputs.push(input_note.get_nf().unwrap().inner());
+        public_inputs.push(output_note.commitment().inner());
+    }
+    public_inputs.push(owned_note_pub_id);
+    public_inputs.push(Fp::from(0));
+    public_inputs.push(Fp::from(0));
+    let default_vp_cm: [Fp; 2] = ValidityPredicateCommitment::default().to_public_inputs();
+    public_inputs.extend(default_vp_cm);
+    public_inputs.extend(default_vp_cm);
+
+    VP_MANDATORY_PUBLIC_INPUT_NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .zip(public_inputs)
+        .collect()
+}
@@ -1,9 +1,16 @@
 use crate::circuit::{
-    vp_circuit::{VPVerifyingInfo, ValidityPredicateVerifyingInfo, VampIRValidityPredicateCircuit},
+    vp_circuit::{VPVerifyingInfo, ValidityPredicate, VampIRValidityPredicateCircuit},
     vp_examples::TrivialValidityPredicateCircuit,
 };
-use crate::shielded_ptx::NoteVPVerifyingInfoSet;
+use crate::constant::NUM_NOTE;
+use crate::error::TransactionError;
+use crate::merkle_tree::{Anchor, MerklePath};
+use crate::note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo};
+use crate::shielded_ptx::{NoteVPVerifyingInfoSet, ShieldedPartialTransaction};
+use crate::vp_vk::{commit_source, SourceCommitment, ValidityPredicateVerifyingKey};
 use borsh::{BorshDeserialize, BorshSerialize};
+use pasta_curves::pallas;
+use rand::RngCore;
 #[cfg(feature = "serde")]
 use serde;
 use std::path::PathBuf;
@@ -31,6 +38,26 @@ pub struct ValidityPredicateByteCode {
 pub struct ApplicationByteCode {
     app_vp_bytecode: ValidityPredicateByteCode,
     dynamic_vp_bytecode: Vec<ValidityPredicateByteCode>,
+    // The source commitment of the application vp, see ValidityPredicateVerifyingKey::attest
+    source_commitment: SourceCommitment,
+}
+
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NoteWithBytecode {
+    pub note: Note,
+    pub app: ApplicationByteCode,
+}
+
+/// Everything a remote prover needs to build a ptx, in a serializable form.
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PartialTransactionByteCode {
+    pub inputs: [NoteWithBytecode; NUM_NOTE],
+    pub outputs: [NoteWithBytecode; NUM_NOTE],
+    pub merkle_paths: [MerklePath; NUM_NOTE],
+    pub anchors: [Anchor; NUM_NOTE],
+    pub hints: Vec<u8>,
 }
 
 impl ValidityPredicateByteCode {
@@ -38,23 +65,56 @@ impl ValidityPredicateByteCode {
         Self { circuit, inputs }
     }
 
-    pub fn generate_proof(self) -> VPVerifyingInfo {
-        match self.circuit {
+    pub fn to_vp(&self) -> Box<ValidityPredicate> {
+        match &self.circuit {
             ValidityPredicateRepresentation::VampIR(circuit) => {
                 // TDDO: use the file_name api atm,
                 // request vamp_ir to provide a api to generate circuit from bytes.
                 let vamp_ir_circuit_file =
-                    PathBuf::from(String::from_utf8_lossy(&circuit).to_string());
+                    PathBuf::from(String::from_utf8_lossy(circuit).to_string());
                 let inputs_file = PathBuf::from(String::from_utf8_lossy(&self.inputs).to_string());
-                let vp_circuit = VampIRValidityPredicateCircuit::from_vamp_ir_file(
+                Box::new(VampIRValidityPredicateCircuit::from_vamp_ir_file(
                     &vamp_ir_circuit_file,
                     &inputs_file,
-                );
-                vp_circuit.get_verifying_info()
+                ))
             }
+            ValidityPredicateRepresentation::Trivial => Box::new(
+                TrivialValidityPredicateCircuit::from_bytes(self.inputs.clone()),
+            ),
+        }
+    }
+
+    #[cfg(feature = "prover")]
+    pub fn generate_proof(self) -> VPVerifyingInfo {
+        self.to_vp().get_verifying_info()
+    }
+
+    // Only the verifying key is generated, without proving
+    pub fn get_vp_vk(&self) -> ValidityPredicateVerifyingKey {
+        self.to_vp().get_vp_vk()
+    }
+
+    // Check the vp is created over the given notes. The vampir inputs are opaque here, so only
+    // the trivial vp can be checked.
+    fn check_notes(
+        &self,
+        owned_note_pub_id: pallas::Base,
+        input_notes: &[Note; NUM_NOTE],
+        output_notes: &[Note; NUM_NOTE],
+    ) -> Result<(), TransactionError> {
+        match self.circuit {
+            ValidityPredicateRepresentation::VampIR(_) => Ok(()),
             ValidityPredicateRepresentation::Trivial => {
-                let vp = TrivialValidityPredicateCircuit::from_bytes(self.inputs);
-                vp.get_verifying_info()
+                let vp = TrivialValidityPredicateCircuit::try_from_slice(&self.inputs)
+                    .map_err(|_| TransactionError::InconsistentBytecodeNotes)?;
+                if vp.owned_note_pub_id == owned_note_pub_id
+                    && *vp.input_notes == *input_notes
+                    && *vp.output_notes == *output_notes
+                {
+                    Ok(())
+                } else {
+                    Err(TransactionError::InconsistentBytecodeNotes)
+                }
             }
         }
     }
@@ -66,11 +126,17 @@ impl ApplicationByteCode {
         dynamic_vp_bytecode: Vec<ValidityPredicateByteCode>,
     ) -> Self {
         Self {
+            source_commitment: commit_source(&app_vp_bytecode),
             app_vp_bytecode,
             dynamic_vp_bytecode,
         }
     }
 
+    pub fn source_commitment(&self) -> SourceCommitment {
+        self.source_commitment
+    }
+
+    #[cfg(feature = "prover")]
     pub fn generate_proofs(self) -> NoteVPVerifyingInfoSet {
         let app_vp_verifying_info = self.app_vp_bytecode.generate_proof();
 
@@ -81,4 +147,192 @@ impl ApplicationByteCode {
             .collect();
         NoteVPVerifyingInfoSet::new(app_vp_verifying_info, app_dynamic_vp_verifying_info)
     }
+
+    fn check_notes(
+        &self,
+        owned_note_pub_id: pallas::Base,
+        input_notes: &[Note; NUM_NOTE],
+        output_notes: &[Note; NUM_NOTE],
+    ) -> Result<(), TransactionError> {
+        std::iter::once(&self.app_vp_bytecode)
+            .chain(self.dynamic_vp_bytecode.iter())
+            .try_for_each(|bytecode| {
+                bytecode.check_notes(owned_note_pub_id, input_notes, output_notes)
+            })
+    }
+
+    fn to_vps(&self) -> (Box<ValidityPredicate>, Vec<Box<ValidityPredicate>>) {
+        let app_vp = self.app_vp_bytecode.to_vp();
+        let dynamic_vps = self
+            .dynamic_vp_bytecode
+            .iter()
+            .map(|bytecode| bytecode.to_vp())
+            .collect();
+        (app_vp, dynamic_vps)
+    }
+}
+
+impl PartialTransactionByteCode {
+    // Checks the vp bytecodes are consistent with the notes, then builds and proves the ptx.
+    #[cfg(feature = "prover")]
+    pub fn prove<R: RngCore>(self, rng: R) -> Result<ShieldedPartialTransaction, TransactionError> {
+        let input_notes = self.inputs.clone().map(|input| input.note);
+        let output_notes = self.outputs.clone().map(|output| output.note);
+        for input in self.inputs.iter() {
+            let nf = input.note.get_nf()?;
+            input
+                .app
+                .check_notes(nf.inner(), &input_notes, &output_notes)?;
+        }
+        for output in self.outputs.iter() {
+            output.app.check_notes(
+                output.note.commitment().inner(),
+                &input_notes,
+                &output_notes,
+            )?;
+        }
+
+        let mut paths = self.merkle_paths.into_iter().zip(self.anchors);
+        let input_info = self.inputs.map(|input| {
+            let (merkle_path, anchor) = paths.next().unwrap();
+            let (app_vp, dynamic_vps) = input.app.to_vps();
+            InputNoteProvingInfo::new(input.note, merkle_path, Some(anchor), app_vp, dynamic_vps)
+        });
+        let output_info = self.outputs.map(|output| {
+            let (app_vp, dynamic_vps) = output.app.to_vps();
+            OutputNoteProvingInfo::new(output.note, app_vp, dynamic_vps)
+        });
+        ShieldedPartialTransaction::build_strict(input_info, output_info, self.hints, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::vp_circuit::ValidityPredicateCircuit;
+    use crate::circuit::vp_examples::{
+        tests::random_trivial_vp_circuit, TrivialValidityPredicateParams, COMPRESSED_TRIVIAL_VP_VK,
+        TRIVIAL_VP_VK,
+    };
+    use crate::constant::{
+        TAIGA_COMMITMENT_TREE_DEPTH, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX,
+        VP_CIRCUIT_PARAMS_SIZE,
+    };
+    use crate::executable::Executable;
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    fn trivial_bytecode(params: TrivialValidityPredicateParams) -> ValidityPredicateByteCode {
+        random_trivial_vp_circuit(OsRng)
+            .with_params(params)
+            .to_bytecode()
+    }
+
+    #[test]
+    fn test_trivial_vp_bytecode() {
+        let bytecode = trivial_bytecode(TrivialValidityPredicateParams::default());
+        assert_eq!(bytecode.get_vp_vk(), *TRIVIAL_VP_VK);
+        assert!(bytecode.generate_proof().verify().is_ok());
+    }
+
+    #[test]
+    fn test_failing_vp_bytecode() {
+        let params = TrivialValidityPredicateParams {
+            fail: true,
+            ..Default::default()
+        };
+        let vp = random_trivial_vp_circuit(OsRng).with_params(params);
+        assert!(vp.dev_prove_and_check(OsRng).is_err());
+
+        // The proof is created, but doesn't verify
+        let bytecode = vp.to_bytecode();
+        assert_ne!(bytecode.get_vp_vk(), *TRIVIAL_VP_VK);
+        assert!(bytecode.generate_proof().verify().is_err());
+    }
+
+    #[test]
+    fn test_vp_bytecode_with_extra_public_inputs() {
+        let extra_public_inputs = vec![pallas::Base::from(1u64), pallas::Base::from(2u64)];
+        let params = TrivialValidityPredicateParams {
+            extra_rows: 10,
+            extra_public_inputs: extra_public_inputs.clone(),
+            ..Default::default()
+        };
+        let verifying_info = trivial_bytecode(params).generate_proof();
+        assert!(verifying_info.verify().is_ok());
+        assert_eq!(
+            verifying_info.public_inputs.inner()[VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX..]
+                [..extra_public_inputs.len()],
+            extra_public_inputs[..]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "keygen_vk failed")]
+    fn test_oversized_vp_bytecode() {
+        let params = TrivialValidityPredicateParams {
+            extra_rows: 1 << VP_CIRCUIT_PARAMS_SIZE,
+            ..Default::default()
+        };
+        trivial_bytecode(params).get_vp_vk();
+    }
+
+    fn random_ptx_bytecode() -> PartialTransactionByteCode {
+        let mut rng = OsRng;
+        let input_notes = [(); NUM_NOTE].map(|_| {
+            let mut note = random_input_note(&mut rng);
+            note.note_type.app_vk = *COMPRESSED_TRIVIAL_VP_VK;
+            note
+        });
+        let output_notes = input_notes.map(|input| {
+            let mut note = random_output_note(&mut rng, input.get_nf().unwrap());
+            note.note_type.app_vk = *COMPRESSED_TRIVIAL_VP_VK;
+            note
+        });
+        let app = |owned_note_pub_id| {
+            let vp =
+                TrivialValidityPredicateCircuit::new(owned_note_pub_id, input_notes, output_notes);
+            ApplicationByteCode::new(vp.to_bytecode(), vec![])
+        };
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        PartialTransactionByteCode {
+            inputs: input_notes.map(|note| NoteWithBytecode {
+                note,
+                app: app(note.get_nf().unwrap().inner()),
+            }),
+            outputs: output_notes.map(|note| NoteWithBytecode {
+                note,
+                app: app(note.commitment().inner()),
+            }),
+            merkle_paths: [(); NUM_NOTE].map(|_| merkle_path.clone()),
+            anchors: input_notes.map(|note| note.calculate_root(&merkle_path)),
+            hints: vec![],
+        }
+    }
+
+    // The server side: receives the serialized request and returns the serialized ptx.
+    fn prove_request(request: Vec<u8>) -> Result<Vec<u8>, TransactionError> {
+        let ptx_bytecode = PartialTransactionByteCode::try_from_slice(&request)?;
+        let ptx = ptx_bytecode.prove(OsRng)?;
+        Ok(borsh::to_vec(&ptx)?)
+    }
+
+    #[test]
+    fn test_ptx_bytecode_prove() {
+        let request = borsh::to_vec(&random_ptx_bytecode()).unwrap();
+        let response = prove_request(request).unwrap();
+        let ptx = ShieldedPartialTransaction::try_from_slice(&response).unwrap();
+        ptx.execute().unwrap();
+    }
+
+    #[test]
+    fn test_ptx_bytecode_inconsistent_notes() {
+        let mut ptx_bytecode = random_ptx_bytecode();
+        ptx_bytecode.outputs[1].note.value += 1;
+        let request = borsh::to_vec(&ptx_bytecode).unwrap();
+        assert!(matches!(
+            prove_request(request),
+            Err(TransactionError::InconsistentBytecodeNotes)
+        ));
+    }
 }
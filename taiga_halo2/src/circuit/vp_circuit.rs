@@ -2,9 +2,14 @@ use crate::{
     circuit::{
         blake2s::publicize_default_dynamic_vp_commitments,
         blake2s::Blake2sConfig,
+        compressed_instance::{
+            with_witnessed_public_inputs, witnessed_public_inputs, InstanceCollectingLayouter,
+        },
         gadgets::{
             add::{AddChip, AddConfig},
-            assign_free_advice,
+            assign_free_advice, assign_free_constant,
+            comparison::ComparisonConfig,
+            conditional::ConditionalConfig,
             conditional_equal::ConditionalEqualConfig,
             conditional_select::ConditionalSelectConfig,
             extended_or_relation::ExtendedOrRelationConfig,
@@ -13,24 +18,35 @@ use crate::{
             target_note_variable::{GetIsInputNoteFlagConfig, GetOwnedNoteVariableConfig},
         },
         integrity::{check_input_note, check_output_note},
-        vamp_ir_utils::{get_circuit_assignments, parse, VariableAssignmentError},
+        merkle_circuit::{MerklePoseidonChip, MerklePoseidonConfig},
+        vamp_ir_utils::{
+            get_circuit_assignments, mandatory_public_input_assignments, parse,
+            with_mandatory_public_inputs, VariableAssignmentError,
+        },
     },
     constant::{
-        TaigaFixedBases, NOTE_ENCRYPTION_CIPHERTEXT_NUM, NUM_NOTE, SETUP_PARAMS_MAP,
+        get_params, TaigaFixedBases, NOTE_ENCRYPTION_CIPHERTEXT_NUM, NUM_NOTE,
+        VP_CIRCUIT_ENCRYPTION_VP_PUBLIC_INPUT_IDX, VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1,
+        VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2, VP_CIRCUIT_INSTANCE_NUM,
         VP_CIRCUIT_NOTE_ENCRYPTION_PK_X_IDX, VP_CIRCUIT_NOTE_ENCRYPTION_PK_Y_IDX,
         VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX,
         VP_CIRCUIT_NULLIFIER_ONE_PUBLIC_INPUT_IDX, VP_CIRCUIT_NULLIFIER_TWO_PUBLIC_INPUT_IDX,
         VP_CIRCUIT_OUTPUT_CM_ONE_PUBLIC_INPUT_IDX, VP_CIRCUIT_OUTPUT_CM_TWO_PUBLIC_INPUT_IDX,
         VP_CIRCUIT_OWNED_NOTE_PUB_ID_PUBLIC_INPUT_IDX, VP_CIRCUIT_PARAMS_SIZE,
-        VP_CIRCUIT_PUBLIC_INPUT_NUM,
+        VP_CIRCUIT_PUBLIC_INPUT_NUM, VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_1,
+        VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_2, VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM,
+        VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX,
     },
+    error::{NoteError, TransactionError},
     note::{Note, NoteCommitment, RandomSeed},
-    note_encryption::{NoteCiphertext, SecretKey},
+    note_encryption::{EncryptedNote, NoteCiphertext},
     proof::Proof,
-    utils::mod_r_p,
+    utils::poseidon_hash_n,
+    vp_commitment::ValidityPredicateCommitment,
     vp_vk::ValidityPredicateVerifyingKey,
 };
 use dyn_clone::{clone_trait_object, DynClone};
+use ff::{Field, PrimeField};
 use group::cofactor::CofactorCurveAffine;
 use halo2_gadgets::{
     ecc::chip::EccChip,
@@ -38,12 +54,15 @@ use halo2_gadgets::{
     poseidon::{primitives as poseidon, Pow5Chip as PoseidonChip, Pow5Config as PoseidonConfig},
     utilities::lookup_range_check::LookupRangeCheckConfig,
 };
+#[cfg(feature = "prover")]
+use halo2_proofs::plonk::keygen_pk;
 use halo2_proofs::{
     arithmetic::CurveAffine,
     circuit::{AssignedCell, Layouter, Value},
+    dev::{MockProver, VerifyFailure},
     plonk::{
-        keygen_pk, keygen_vk, Advice, Circuit, Column, ConstraintSystem, Error, Instance,
-        TableColumn, VerifyingKey,
+        keygen_vk, Advice, Circuit, Column, ConstraintSystem, Error, Instance, TableColumn,
+        VerifyingKey,
     },
     poly::commitment::Params,
 };
@@ -114,7 +133,7 @@ impl<'a> Decoder<'a> for VPVerifyingInfo {
         ) = term.decode()?;
         if term == verifying_info() {
             use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
-            let params = SETUP_PARAMS_MAP.get(&VP_CIRCUIT_PARAMS_SIZE).unwrap();
+            let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
             let vk = VerifyingKey::from_bytes::<TrivialValidityPredicateCircuit>(&vk, params)
                 .map_err(|_e| rustler::Error::Atom("failure to decode"))?;
             Ok(VPVerifyingInfo {
@@ -132,6 +151,9 @@ impl<'a> Decoder<'a> for VPVerifyingInfo {
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValidityPredicatePublicInputs([pallas::Base; VP_CIRCUIT_PUBLIC_INPUT_NUM]);
 
+/// The first public inputs of a vp, see VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM.
+pub type SharedPublicInputs = [pallas::Base; VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM];
+
 #[cfg(feature = "nif")]
 impl Encoder for ValidityPredicatePublicInputs {
     fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
@@ -150,41 +172,52 @@ impl<'a> Decoder<'a> for ValidityPredicatePublicInputs {
 
 impl VPVerifyingInfo {
     pub fn verify(&self) -> Result<(), Error> {
-        let params = SETUP_PARAMS_MAP.get(&VP_CIRCUIT_PARAMS_SIZE).unwrap();
-        self.proof
-            .verify(&self.vk, params, &[self.public_inputs.inner()])
+        self.verify_with_vk(&self.vk)
+    }
+
+    // Verify the proof under a known vk, the attached vk is ignored
+    pub fn verify_with_vk(&self, vk: &VerifyingKey<vesta::Affine>) -> Result<(), Error> {
+        let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
+        self.proof.verify_single_instance(
+            vk,
+            params,
+            &self.public_inputs.to_instance(),
+            VP_CIRCUIT_INSTANCE_NUM,
+        )
+    }
+
+    pub fn proof_size(&self) -> usize {
+        self.proof.size()
+    }
+
+    // The number of instances checked by the verifier, it depends on compressed_vp_instance
+    pub fn instance_count(&self) -> usize {
+        self.public_inputs.to_instance().len()
+    }
+
+    // The length of the serialized vk
+    pub fn vk_size(&self) -> usize {
+        let mut bytes = vec![];
+        self.vk.write(&mut bytes).unwrap();
+        bytes.len()
     }
 
     pub fn get_nullifiers(&self) -> [pallas::Base; NUM_NOTE] {
-        [
-            self.public_inputs
-                .get_from_index(VP_CIRCUIT_NULLIFIER_ONE_PUBLIC_INPUT_IDX),
-            self.public_inputs
-                .get_from_index(VP_CIRCUIT_NULLIFIER_TWO_PUBLIC_INPUT_IDX),
-        ]
+        self.public_inputs.input_nullifiers()
     }
 
     pub fn get_note_commitments(&self) -> [NoteCommitment; NUM_NOTE] {
-        [
-            self.public_inputs
-                .get_from_index(VP_CIRCUIT_OUTPUT_CM_ONE_PUBLIC_INPUT_IDX)
-                .into(),
-            self.public_inputs
-                .get_from_index(VP_CIRCUIT_OUTPUT_CM_TWO_PUBLIC_INPUT_IDX)
-                .into(),
-        ]
+        self.public_inputs.output_commitments()
     }
 
     pub fn get_owned_note_pub_id(&self) -> pallas::Base {
-        self.public_inputs
-            .get_from_index(VP_CIRCUIT_OWNED_NOTE_PUB_ID_PUBLIC_INPUT_IDX)
+        self.public_inputs.owned_note_pub_id()
     }
 }
 
 #[cfg(feature = "borsh")]
 impl BorshSerialize for VPVerifyingInfo {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        use ff::PrimeField;
         // Write vk
         self.vk.write(writer)?;
         // Write proof
@@ -200,31 +233,80 @@ impl BorshSerialize for VPVerifyingInfo {
 #[cfg(feature = "borsh")]
 impl BorshDeserialize for VPVerifyingInfo {
     fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
-        use ff::PrimeField;
-        use std::io;
-        // Read vk
-        use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
-        let params = SETUP_PARAMS_MAP.get(&VP_CIRCUIT_PARAMS_SIZE).unwrap();
-        let vk = VerifyingKey::read::<_, TrivialValidityPredicateCircuit>(reader, params)?;
-        // Read proof
-        let proof = Proof::deserialize_reader(reader)?;
+        let (vk, proof) = Self::deserialize_vk_and_proof(reader)?;
         // Read public inputs
-        let public_inputs: Vec<_> = (0..VP_CIRCUIT_PUBLIC_INPUT_NUM)
-            .map(|_| {
-                let bytes = <[u8; 32]>::deserialize_reader(reader)?;
-                Option::from(pallas::Base::from_repr(bytes)).ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::InvalidData, "public input not in field")
-                })
-            })
-            .collect::<Result<_, _>>()?;
+        let reprs = read_reprs(reader, VP_CIRCUIT_PUBLIC_INPUT_NUM)?;
+        let public_inputs = ValidityPredicatePublicInputs::try_from_reprs(&reprs)
+            .map_err(|_| invalid_public_input_error())?;
         Ok(VPVerifyingInfo {
             vk,
             proof,
-            public_inputs: public_inputs.into(),
+            public_inputs,
         })
     }
 }
 
+#[cfg(feature = "borsh")]
+impl VPVerifyingInfo {
+    /// The encoding in a ptx: the shared public inputs are skipped if they are the ones the ptx
+    /// rebuilds from its actions, the public inputs have a length tag.
+    pub fn serialize_with_shared_prefix<W: std::io::Write>(
+        &self,
+        shared_prefix: &SharedPublicInputs,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        self.vk.write(writer)?;
+        self.proof.serialize(writer)?;
+        let stored = self.public_inputs.stored(shared_prefix);
+        (stored.len() as u8).serialize(writer)?;
+        for ele in stored.iter() {
+            writer.write_all(&ele.to_repr())?;
+        }
+        Ok(())
+    }
+
+    pub fn deserialize_with_shared_prefix<R: std::io::Read>(
+        reader: &mut R,
+        shared_prefix: &SharedPublicInputs,
+    ) -> std::io::Result<Self> {
+        let (vk, proof) = Self::deserialize_vk_and_proof(reader)?;
+        let len = u8::deserialize_reader(reader)?;
+        let reprs = read_reprs(reader, len as usize)?;
+        let public_inputs =
+            ValidityPredicatePublicInputs::try_from_stored_reprs(shared_prefix, &reprs)
+                .map_err(|_| invalid_public_input_error())?;
+        Ok(VPVerifyingInfo {
+            vk,
+            proof,
+            public_inputs,
+        })
+    }
+
+    fn deserialize_vk_and_proof<R: std::io::Read>(
+        reader: &mut R,
+    ) -> std::io::Result<(VerifyingKey<vesta::Affine>, Proof)> {
+        // Read vk
+        use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
+        let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
+        let vk = VerifyingKey::read::<_, TrivialValidityPredicateCircuit>(reader, params)?;
+        // Read proof
+        let proof = Proof::deserialize_reader(reader)?;
+        Ok((vk, proof))
+    }
+}
+
+#[cfg(feature = "borsh")]
+fn read_reprs<R: std::io::Read>(reader: &mut R, len: usize) -> std::io::Result<Vec<[u8; 32]>> {
+    (0..len)
+        .map(|_| <[u8; 32]>::deserialize_reader(reader))
+        .collect()
+}
+
+#[cfg(feature = "borsh")]
+fn invalid_public_input_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "public input not in field")
+}
+
 #[cfg(feature = "serde")]
 fn serde_serialize_verifying_key<S>(
     x: &VerifyingKey<vesta::Affine>,
@@ -247,7 +329,7 @@ where
     let buf: Vec<u8> = serde::Deserialize::deserialize(d)?;
 
     use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
-    let params = SETUP_PARAMS_MAP.get(&VP_CIRCUIT_PARAMS_SIZE).unwrap();
+    let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
     let vk = VerifyingKey::read::<_, TrivialValidityPredicateCircuit>(&mut buf.as_slice(), params)
         .map_err(|e| Error::custom(format!("Error reading VerifyingKey: {}", e)))?;
     Ok(vk)
@@ -263,6 +345,105 @@ impl ValidityPredicatePublicInputs {
         self.0[idx]
     }
 
+    /// Parses the public inputs of a vp proof, they must be padded to VP_CIRCUIT_PUBLIC_INPUT_NUM.
+    pub fn try_from_vec(public_inputs: Vec<pallas::Base>) -> Result<Self, TransactionError> {
+        public_inputs
+            .try_into()
+            .map(ValidityPredicatePublicInputs)
+            .map_err(|_| TransactionError::PublicInputMalformed)
+    }
+
+    /// Parses the encoded public inputs, the padding included. Non-canonical encodings are
+    /// rejected, so the public inputs have a unique encoding.
+    pub fn try_from_reprs(reprs: &[[u8; 32]]) -> Result<Self, TransactionError> {
+        Self::try_from_vec(parse_reprs(reprs)?)
+    }
+
+    /// Parses the public inputs stored without the shared prefix, see `stored`. The full public
+    /// inputs are parsed as try_from_reprs.
+    pub fn try_from_stored_reprs(
+        shared_prefix: &SharedPublicInputs,
+        reprs: &[[u8; 32]],
+    ) -> Result<Self, TransactionError> {
+        if reprs.len() == VP_CIRCUIT_PUBLIC_INPUT_NUM - VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM {
+            let mut public_inputs = shared_prefix.to_vec();
+            public_inputs.extend(parse_reprs(reprs)?);
+            Self::try_from_vec(public_inputs)
+        } else {
+            Self::try_from_reprs(reprs)
+        }
+    }
+
+    /// The shared public inputs of the vps of a note in the ptx of the nullifiers and output
+    /// commitments. The owned note pub id is the nullifier or the commitment of the note.
+    pub fn shared_prefix_of(
+        nfs: [pallas::Base; NUM_NOTE],
+        cms: [NoteCommitment; NUM_NOTE],
+        owned_note_pub_id: pallas::Base,
+    ) -> SharedPublicInputs {
+        let mut prefix = [pallas::Base::zero(); VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM];
+        prefix[VP_CIRCUIT_NULLIFIER_ONE_PUBLIC_INPUT_IDX] = nfs[0];
+        prefix[VP_CIRCUIT_NULLIFIER_TWO_PUBLIC_INPUT_IDX] = nfs[1];
+        prefix[VP_CIRCUIT_OUTPUT_CM_ONE_PUBLIC_INPUT_IDX] = cms[0].inner();
+        prefix[VP_CIRCUIT_OUTPUT_CM_TWO_PUBLIC_INPUT_IDX] = cms[1].inner();
+        prefix[VP_CIRCUIT_OWNED_NOTE_PUB_ID_PUBLIC_INPUT_IDX] = owned_note_pub_id;
+        prefix
+    }
+
+    pub fn shared_prefix(&self) -> SharedPublicInputs {
+        self.0[..VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM]
+            .try_into()
+            .unwrap()
+    }
+
+    /// The public inputs to store with the proof: the shared prefix is skipped if it's the
+    /// expected one, the verifier rebuilds it. Otherwise all of them.
+    pub fn stored(&self, shared_prefix: &SharedPublicInputs) -> &[pallas::Base] {
+        if self.shared_prefix() == *shared_prefix {
+            &self.0[VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM..]
+        } else {
+            &self.0
+        }
+    }
+
+    pub fn input_nullifiers(&self) -> [pallas::Base; NUM_NOTE] {
+        [
+            self.0[VP_CIRCUIT_NULLIFIER_ONE_PUBLIC_INPUT_IDX],
+            self.0[VP_CIRCUIT_NULLIFIER_TWO_PUBLIC_INPUT_IDX],
+        ]
+    }
+
+    pub fn output_commitments(&self) -> [NoteCommitment; NUM_NOTE] {
+        [
+            self.0[VP_CIRCUIT_OUTPUT_CM_ONE_PUBLIC_INPUT_IDX].into(),
+            self.0[VP_CIRCUIT_OUTPUT_CM_TWO_PUBLIC_INPUT_IDX].into(),
+        ]
+    }
+
+    pub fn owned_note_pub_id(&self) -> pallas::Base {
+        self.0[VP_CIRCUIT_OWNED_NOTE_PUB_ID_PUBLIC_INPUT_IDX]
+    }
+
+    // The compressed vk of the vp publishing the ciphertext of the owned note, zero if none
+    pub fn encryption_vp_vk(&self) -> pallas::Base {
+        self.0[VP_CIRCUIT_ENCRYPTION_VP_PUBLIC_INPUT_IDX]
+    }
+
+    // The two dynamic vp commitments, they are the default commitment if unused
+    pub fn dynamic_vp_commitments(&self) -> [ValidityPredicateCommitment; 2] {
+        [
+            [
+                VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1,
+                VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2,
+            ],
+            [
+                VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_1,
+                VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_2,
+            ],
+        ]
+        .map(|idx| ValidityPredicateCommitment::from_public_inputs(&idx.map(|i| self.0[i])))
+    }
+
     pub fn get_public_input_padding(input_len: usize, rseed: &RandomSeed) -> Vec<pallas::Base> {
         assert!(input_len < VP_CIRCUIT_PUBLIC_INPUT_NUM);
         rseed.get_random_padding(VP_CIRCUIT_PUBLIC_INPUT_NUM - input_len)
@@ -281,22 +462,60 @@ impl ValidityPredicatePublicInputs {
         self.0.to_vec()
     }
 
-    pub fn decrypt(&self, sk: pallas::Base) -> Option<Vec<pallas::Base>> {
-        let cipher: NoteCiphertext = self.0[VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX
+    // The poseidon hash of the public inputs
+    pub fn compress(&self) -> pallas::Base {
+        poseidon_hash_n(self.0)
+    }
+
+    // The instance of the vp proof, only the compressed public inputs with the compressed_vp_instance feature
+    pub fn to_instance(&self) -> Vec<pallas::Base> {
+        if cfg!(feature = "compressed_vp_instance") {
+            vec![self.compress()]
+        } else {
+            self.to_vec()
+        }
+    }
+
+    pub fn get_ciphertext(&self) -> NoteCiphertext {
+        self.0[VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX
             ..VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX + NOTE_ENCRYPTION_CIPHERTEXT_NUM]
             .to_vec()
-            .into();
-        let sender_pk = pallas::Affine::from_xy(
+            .into()
+    }
+
+    // The published ciphertext along with the sender pk
+    pub fn get_encrypted_note(&self) -> Result<EncryptedNote, NoteError> {
+        // The vp may not publish a ciphertext, the pk is not a valid point then.
+        let sender_pk = Option::<pallas::Affine>::from(pallas::Affine::from_xy(
             self.get_from_index(VP_CIRCUIT_NOTE_ENCRYPTION_PK_X_IDX),
             self.get_from_index(VP_CIRCUIT_NOTE_ENCRYPTION_PK_Y_IDX),
-        )
-        .unwrap()
-        .to_curve();
-        let key = SecretKey::from_dh_exchange(&sender_pk, &mod_r_p(sk));
-        cipher.decrypt(&key)
+        ))
+        .ok_or(NoteError::MalformedCiphertext)?;
+        if bool::from(sender_pk.is_identity()) {
+            return Err(NoteError::MalformedCiphertext);
+        }
+        Ok(EncryptedNote {
+            ciphertext: self.get_ciphertext(),
+            sender_pk: sender_pk.to_curve(),
+        })
+    }
+
+    pub fn decrypt(&self, sk: pallas::Base) -> Result<Vec<pallas::Base>, NoteError> {
+        self.get_encrypted_note()?.decrypt(sk)
     }
 }
 
+// Non-canonical encodings are rejected
+fn parse_reprs(reprs: &[[u8; 32]]) -> Result<Vec<pallas::Base>, TransactionError> {
+    reprs
+        .iter()
+        .map(|repr| {
+            Option::from(pallas::Base::from_repr(*repr))
+                .ok_or(TransactionError::PublicInputMalformed)
+        })
+        .collect()
+}
+
 impl From<Vec<pallas::Base>> for ValidityPredicatePublicInputs {
     fn from(public_input_vec: Vec<pallas::Base>) -> Self {
         ValidityPredicatePublicInputs(
@@ -312,17 +531,22 @@ pub struct ValidityPredicateConfig {
     pub advices: [Column<Advice>; 10],
     pub instances: Column<Instance>,
     pub table_idx: TableColumn,
+    // The 10-bit lookup range check, shared with the ecc chip
+    pub range_check: LookupRangeCheckConfig<pallas::Base, 10>,
     pub ecc_config: EccConfig<TaigaFixedBases>,
     pub poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
     pub get_is_input_note_flag_config: GetIsInputNoteFlagConfig,
     pub get_owned_note_variable_config: GetOwnedNoteVariableConfig,
     pub conditional_equal_config: ConditionalEqualConfig,
     pub conditional_select_config: ConditionalSelectConfig,
+    pub conditional_config: ConditionalConfig,
     pub extended_or_relation_config: ExtendedOrRelationConfig,
     pub add_config: AddConfig,
     pub sub_config: SubConfig,
     pub mul_config: MulConfig,
+    pub comparison_config: ComparisonConfig,
     pub blake2s_config: Blake2sConfig<pallas::Base>,
+    pub merkle_config: MerklePoseidonConfig,
 }
 
 impl ValidityPredicateConfig {
@@ -387,36 +611,79 @@ impl ValidityPredicateConfig {
             ConditionalEqualConfig::configure(meta, [advices[0], advices[1], advices[2]]);
         let conditional_select_config =
             ConditionalSelectConfig::configure(meta, [advices[0], advices[1]]);
+        let conditional_config =
+            ConditionalConfig::configure(meta, [advices[0], advices[1], advices[2], advices[3]]);
 
         let add_config = AddChip::configure(meta, [advices[0], advices[1]]);
         let sub_config = SubChip::configure(meta, [advices[0], advices[1]]);
         let mul_config = MulChip::configure(meta, [advices[0], advices[1]]);
+        let comparison_config = ComparisonConfig::construct(
+            advices[0],
+            range_check,
+            add_config.clone(),
+            sub_config.clone(),
+            mul_config.clone(),
+        );
 
         let extended_or_relation_config =
             ExtendedOrRelationConfig::configure(meta, [advices[0], advices[1], advices[2]]);
         let blake2s_config = Blake2sConfig::configure(meta, advices);
+        let merkle_config = MerklePoseidonChip::configure(
+            meta,
+            advices[..5].try_into().unwrap(),
+            poseidon_config.clone(),
+        );
         Self {
             advices,
             instances,
             table_idx,
+            range_check,
             ecc_config,
             poseidon_config,
             get_is_input_note_flag_config,
             get_owned_note_variable_config,
             conditional_equal_config,
             conditional_select_config,
+            conditional_config,
             extended_or_relation_config,
             add_config,
             sub_config,
             mul_config,
+            comparison_config,
             blake2s_config,
+            merkle_config,
         }
     }
 }
 
+// The errors of the MockProver runs of the vp circuits
+#[derive(Debug, PartialEq)]
+pub enum DevCheckError {
+    // The instances don't fit the instance column of the vp circuit
+    InvalidInstanceLen { expected: usize, actual: usize },
+    // The constraints are not satisfied
+    VerifyFailures(Vec<VerifyFailure>),
+}
+
 pub trait ValidityPredicateVerifyingInfo: DynClone {
-    fn get_verifying_info(&self) -> VPVerifyingInfo;
+    // Creates the vp proof
+    #[cfg(feature = "prover")]
+    fn get_verifying_info(&self) -> VPVerifyingInfo {
+        self.get_verifying_info_with_padding_rng(&mut OsRng)
+    }
+    // Creates the vp proof with the public input padding drawn from padding_rng, e.g. to derive
+    // the padding from a committed seed. The proof itself is always randomized with OsRng.
+    #[cfg(feature = "prover")]
+    fn get_verifying_info_with_padding_rng(&self, padding_rng: &mut dyn RngCore)
+        -> VPVerifyingInfo;
     fn get_vp_vk(&self) -> ValidityPredicateVerifyingKey;
+    // Runs the MockProver on the vp, used by the dry runs
+    fn dev_check(&self) -> Result<(), DevCheckError>;
+    // The name of the vp circuit type, e.g. in the proving progress
+    fn circuit_name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 
 clone_trait_object!(ValidityPredicateVerifyingInfo);
@@ -487,8 +754,43 @@ pub trait ValidityPredicateCircuit: Circuit<pallas::Base> + ValidityPredicateVer
             VP_CIRCUIT_OWNED_NOTE_PUB_ID_PUBLIC_INPUT_IDX,
         )?;
 
+        // Publicize the index of the signed anchors, it's a constant so the vk commits to it
+        let signed_anchors_public_input_idx = self.get_signed_anchors_public_input_idx();
+        let signed_anchors_idx = assign_free_constant(
+            layouter.namespace(|| "signed anchors idx"),
+            config.advices[0],
+            pallas::Base::from(signed_anchors_public_input_idx.unwrap_or(0) as u64),
+        )?;
+        layouter.constrain_instance(
+            signed_anchors_idx.cell(),
+            config.instances,
+            VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX,
+        )?;
+
+        // Publicize the vk of the vp encrypting the owned note. The vps declaring one must
+        // constrain encryption_vp_vk in custom_constraints, the others publicize zero.
+        let encryption_vp_vk = match self.get_encryption_vp_vk() {
+            Some(vk) => assign_free_advice(
+                layouter.namespace(|| "encryption vp vk"),
+                config.advices[0],
+                Value::known(vk),
+            )?,
+            None => assign_free_constant(
+                layouter.namespace(|| "no encryption vp"),
+                config.advices[0],
+                pallas::Base::zero(),
+            )?,
+        };
+        layouter.constrain_instance(
+            encryption_vp_vk.cell(),
+            config.instances,
+            VP_CIRCUIT_ENCRYPTION_VP_PUBLIC_INPUT_IDX,
+        )?;
+
         Ok(BasicValidityPredicateVariables {
             owned_note_pub_id,
+            signed_anchors_public_input_idx,
+            encryption_vp_vk,
             input_note_variables: input_note_variables.try_into().unwrap(),
             output_note_variables: output_note_variables.try_into().unwrap(),
         })
@@ -515,6 +817,83 @@ pub trait ValidityPredicateCircuit: Circuit<pallas::Base> + ValidityPredicateVer
         Ok(())
     }
 
+    // Synthesize the basic and custom constraints.
+    // With the compressed_vp_instance feature, the public inputs are hashed in circuit.
+    fn synthesize_constraints(
+        &self,
+        config: ValidityPredicateConfig,
+        layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        if cfg!(feature = "compressed_vp_instance") {
+            let mut layouter = InstanceCollectingLayouter::new(layouter);
+            let basic_variables =
+                self.basic_constraints(config.clone(), layouter.namespace(|| "basic constraints"))?;
+            self.custom_constraints(
+                config.clone(),
+                layouter.namespace(|| "custom constraints"),
+                basic_variables,
+            )?;
+            layouter.compress(&config, witnessed_public_inputs())
+        } else {
+            let mut layouter = layouter;
+            let basic_variables =
+                self.basic_constraints(config.clone(), layouter.namespace(|| "basic constraints"))?;
+            self.custom_constraints(
+                config,
+                layouter.namespace(|| "custom constraints"),
+                basic_variables,
+            )
+        }
+    }
+
+    // Run the MockProver on the circuit with its own public inputs. Only for tests and debugging.
+    fn dev_prove_and_check(&self, rng: impl RngCore) -> Result<(), DevCheckError>
+    where
+        Self: Sized,
+    {
+        let public_inputs = self.get_public_inputs(rng);
+        self.dev_prove_and_check_with_public_inputs(&public_inputs)
+    }
+
+    // Run the MockProver on the circuit with custom public inputs, e.g. tampered ones. With the
+    // compressed_vp_instance feature the circuit witnesses them and exposes their hash.
+    fn dev_prove_and_check_with_public_inputs(
+        &self,
+        public_inputs: &ValidityPredicatePublicInputs,
+    ) -> Result<(), DevCheckError>
+    where
+        Self: Sized,
+    {
+        with_witnessed_public_inputs(public_inputs, || {
+            self.dev_prove_and_check_with_instances(public_inputs.to_instance())
+        })
+    }
+
+    // Run the MockProver on the circuit with custom instances, e.g. tampered public inputs.
+    fn dev_prove_and_check_with_instances(
+        &self,
+        instances: Vec<pallas::Base>,
+    ) -> Result<(), DevCheckError>
+    where
+        Self: Sized,
+    {
+        let expected = if cfg!(feature = "compressed_vp_instance") {
+            1
+        } else {
+            VP_CIRCUIT_PUBLIC_INPUT_NUM
+        };
+        if instances.len() != expected {
+            return Err(DevCheckError::InvalidInstanceLen {
+                expected,
+                actual: instances.len(),
+            });
+        }
+        MockProver::<pallas::Base>::run(VP_CIRCUIT_PARAMS_SIZE, self, vec![instances])
+            .unwrap()
+            .verify()
+            .map_err(DevCheckError::VerifyFailures)
+    }
+
     fn get_mandatory_public_inputs(&self) -> Vec<pallas::Base> {
         let mut public_inputs = vec![];
         self.get_input_notes()
@@ -527,6 +906,10 @@ pub trait ValidityPredicateCircuit: Circuit<pallas::Base> + ValidityPredicateVer
                 public_inputs.push(cm.inner());
             });
         public_inputs.push(self.get_owned_note_pub_id());
+        public_inputs.push(pallas::Base::from(
+            self.get_signed_anchors_public_input_idx().unwrap_or(0) as u64,
+        ));
+        public_inputs.push(self.get_encryption_vp_vk().unwrap_or(pallas::Base::zero()));
         public_inputs
     }
     fn get_input_notes(&self) -> &[Note; NUM_NOTE];
@@ -536,6 +919,16 @@ pub trait ValidityPredicateCircuit: Circuit<pallas::Base> + ValidityPredicateVer
     // The owned_note_pub_id is the key to look up the target variables and
     // help determine whether the owned note is the input note or not in VP circuit.
     fn get_owned_note_pub_id(&self) -> pallas::Base;
+    // The index of the public inputs where the vp publicizes the anchors it signs, see
+    // signing_message_gadget. The vps that sign nothing keep the default.
+    fn get_signed_anchors_public_input_idx(&self) -> Option<usize> {
+        None
+    }
+    // The compressed vk of the vp publishing the ciphertext of the owned note. The vps returning
+    // Some must do so for any witness and constrain basic_variables.encryption_vp_vk.
+    fn get_encryption_vp_vk(&self) -> Option<pallas::Base> {
+        None
+    }
 }
 
 /// BasicValidityPredicateVariables are generally constrained in ValidityPredicateCircuit::basic_constraints
@@ -543,6 +936,8 @@ pub trait ValidityPredicateCircuit: Circuit<pallas::Base> + ValidityPredicateVer
 #[derive(Debug, Clone)]
 pub struct BasicValidityPredicateVariables {
     pub owned_note_pub_id: AssignedCell<pallas::Base, pallas::Base>,
+    pub signed_anchors_public_input_idx: Option<usize>,
+    pub encryption_vp_vk: AssignedCell<pallas::Base, pallas::Base>,
     pub input_note_variables: [InputNoteVariables; NUM_NOTE],
     pub output_note_variables: [OutputNoteVariables; NUM_NOTE],
 }
@@ -723,16 +1118,7 @@ macro_rules! vp_circuit_impl {
                 config: Self::Config,
                 mut layouter: impl Layouter<pallas::Base>,
             ) -> Result<(), Error> {
-                let basic_variables = self.basic_constraints(
-                    config.clone(),
-                    layouter.namespace(|| "basic constraints"),
-                )?;
-                self.custom_constraints(
-                    config,
-                    layouter.namespace(|| "custom constraints"),
-                    basic_variables,
-                )?;
-                Ok(())
+                self.synthesize_constraints(config, layouter.namespace(|| "vp constraints"))
             }
         }
     };
@@ -743,18 +1129,38 @@ macro_rules! vp_circuit_impl {
 macro_rules! vp_verifying_info_impl {
     ($name:ident) => {
         impl ValidityPredicateVerifyingInfo for $name {
-            fn get_verifying_info(&self) -> VPVerifyingInfo {
+            #[cfg(feature = "prover")]
+            fn get_verifying_info_with_padding_rng(
+                &self,
+                padding_rng: &mut dyn rand::RngCore,
+            ) -> VPVerifyingInfo {
                 let mut rng = OsRng;
-                let params = SETUP_PARAMS_MAP.get(&15).unwrap();
+                let params =
+                    &$crate::constant::get_params($crate::constant::VP_CIRCUIT_PARAMS_SIZE);
+                #[cfg(feature = "metrics")]
+                let keygen_start = std::time::Instant::now();
                 let vk = keygen_vk(params, self).expect("keygen_vk should not fail");
                 let pk = keygen_pk(params, vk.clone(), self).expect("keygen_pk should not fail");
-                let public_inputs = self.get_public_inputs(&mut rng);
-                let proof = Proof::create(
-                    &pk,
-                    params,
-                    self.clone(),
-                    &[public_inputs.inner()],
-                    &mut rng,
+                #[cfg(feature = "metrics")]
+                $crate::metrics::record_span(
+                    $crate::metrics::ProvingStage::Keygen,
+                    stringify!($name),
+                    Some($crate::metrics::params_k(params)),
+                    keygen_start,
+                    None,
+                );
+                let public_inputs = self.get_public_inputs(padding_rng);
+                let proof = $crate::circuit::compressed_instance::with_witnessed_public_inputs(
+                    &public_inputs,
+                    || {
+                        Proof::create(
+                            &pk,
+                            params,
+                            self.clone(),
+                            &[&public_inputs.to_instance()],
+                            &mut rng,
+                        )
+                    },
                 )
                 .unwrap();
                 VPVerifyingInfo {
@@ -765,10 +1171,25 @@ macro_rules! vp_verifying_info_impl {
             }
 
             fn get_vp_vk(&self) -> ValidityPredicateVerifyingKey {
-                let params = SETUP_PARAMS_MAP.get(&15).unwrap();
+                let params =
+                    &$crate::constant::get_params($crate::constant::VP_CIRCUIT_PARAMS_SIZE);
+                #[cfg(feature = "metrics")]
+                let keygen_start = std::time::Instant::now();
                 let vk = keygen_vk(params, self).expect("keygen_vk should not fail");
+                #[cfg(feature = "metrics")]
+                $crate::metrics::record_span(
+                    $crate::metrics::ProvingStage::Keygen,
+                    stringify!($name),
+                    Some($crate::metrics::params_k(params)),
+                    keygen_start,
+                    None,
+                );
                 ValidityPredicateVerifyingKey::from_vk(vk)
             }
+
+            fn dev_check(&self) -> Result<(), $crate::circuit::vp_circuit::DevCheckError> {
+                self.dev_prove_and_check(rand::rngs::OsRng)
+            }
         }
     };
 }
@@ -786,6 +1207,8 @@ pub struct VampIRValidityPredicateCircuit {
 pub enum VampIRCircuitError {
     MissingAssignment(String),
     SourceParsingError(String),
+    // The mandatory and declared public inputs leave no room for the padding
+    TooManyPublicInputs(usize),
 }
 
 impl VampIRCircuitError {
@@ -834,6 +1257,38 @@ impl VampIRValidityPredicateCircuit {
         })
     }
 
+    /// A vp of the owned note with the public input layout of the other vps: the mandatory public
+    /// inputs, with the default dynamic vp commitments, then the publics declared in the source.
+    /// The source can use the mandatory ones as `taiga_nf_1`, `taiga_cm_1`, `taiga_nf_2`,
+    /// `taiga_cm_2` and `taiga_owned_note_pub_id`.
+    pub fn from_vamp_ir_source_with_notes(
+        vamp_ir_source: &str,
+        mut named_field_assignments: HashMap<String, Fp>,
+        owned_note_pub_id: pallas::Base,
+        input_notes: &[Note; NUM_NOTE],
+        output_notes: &[Note; NUM_NOTE],
+    ) -> Result<Self, VampIRCircuitError> {
+        named_field_assignments.extend(mandatory_public_input_assignments(
+            owned_note_pub_id,
+            input_notes,
+            output_notes,
+        ));
+        let mut vp = Self::from_vamp_ir_source(
+            &with_mandatory_public_inputs(vamp_ir_source),
+            named_field_assignments,
+        )?;
+        if vp.public_inputs.len() >= VP_CIRCUIT_PUBLIC_INPUT_NUM {
+            return Err(VampIRCircuitError::TooManyPublicInputs(
+                vp.public_inputs.len(),
+            ));
+        }
+        // Prove with the vp params so that the ptx verifies the proof like the other vps
+        if vp.params.k() <= VP_CIRCUIT_PARAMS_SIZE {
+            vp.params = (*get_params(VP_CIRCUIT_PARAMS_SIZE)).clone();
+        }
+        Ok(vp)
+    }
+
     pub fn from_vamp_ir_file(vamp_ir_file: &PathBuf, inputs_file: &PathBuf) -> Self {
         let config = Config { quiet: true };
         let vamp_ir_source = fs::read_to_string(vamp_ir_file).expect("cannot read vamp-ir file");
@@ -872,14 +1327,18 @@ impl VampIRValidityPredicateCircuit {
 }
 
 impl ValidityPredicateVerifyingInfo for VampIRValidityPredicateCircuit {
-    fn get_verifying_info(&self) -> VPVerifyingInfo {
+    #[cfg(feature = "prover")]
+    fn get_verifying_info_with_padding_rng(
+        &self,
+        padding_rng: &mut dyn RngCore,
+    ) -> VPVerifyingInfo {
         let mut rng = OsRng;
         let vk = keygen_vk(&self.params, &self.circuit).expect("keygen_vk should not fail");
         let pk =
             keygen_pk(&self.params, vk.clone(), &self.circuit).expect("keygen_pk should not fail");
 
         let mut public_inputs = self.public_inputs.clone();
-        let rseed = RandomSeed::random(&mut rng);
+        let rseed = RandomSeed::random(padding_rng);
         public_inputs.extend(ValidityPredicatePublicInputs::get_public_input_padding(
             self.public_inputs.len(),
             &rseed,
@@ -904,6 +1363,19 @@ impl ValidityPredicateVerifyingInfo for VampIRValidityPredicateCircuit {
         let vk = keygen_vk(&self.params, &self.circuit).expect("keygen_vk should not fail");
         ValidityPredicateVerifyingKey::from_vk(vk)
     }
+
+    fn dev_check(&self) -> Result<(), DevCheckError> {
+        let mut public_inputs = self.public_inputs.clone();
+        let rseed = RandomSeed::random(OsRng);
+        public_inputs.extend(ValidityPredicatePublicInputs::get_public_input_padding(
+            self.public_inputs.len(),
+            &rseed,
+        ));
+        MockProver::<pallas::Base>::run(self.params.k(), &self.circuit, vec![public_inputs])
+            .unwrap()
+            .verify()
+            .map_err(DevCheckError::VerifyFailures)
+    }
 }
 
 #[cfg(test)]
@@ -911,6 +1383,7 @@ mod tests {
     use crate::circuit::vp_circuit::{
         ValidityPredicateVerifyingInfo, VampIRValidityPredicateCircuit,
     };
+    use ff::Field;
     use num_bigint::BigInt;
     use std::collections::HashMap;
     use std::path::PathBuf;
@@ -1006,6 +1479,123 @@ mod tests {
             .is_err());
     }
 
+    #[test]
+    fn test_vamp_ir_vp_public_input_layout() {
+        use crate::circuit::vamp_ir_utils::mandatory_public_input_assignments;
+        use crate::circuit::vamp_ir_utils::VP_MANDATORY_PUBLIC_INPUT_NAMES;
+        use crate::circuit::vp_circuit::VampIRCircuitError;
+        use crate::note::Note;
+        use pasta_curves::pallas;
+        use rand::rngs::OsRng;
+
+        let mut rng = OsRng;
+        let input_notes = [(); 2].map(|_| Note::random_padding_input_note(&mut rng));
+        let output_notes = input_notes
+            .map(|note| Note::random_padding_output_note(&mut rng, note.get_nf().unwrap()));
+        let owned_note_pub_id = input_notes[0].get_nf().unwrap().inner();
+        let y = owned_note_pub_id + pallas::Base::one();
+        let vp_circuit = VampIRValidityPredicateCircuit::from_vamp_ir_source_with_notes(
+            "pub y;\ny = taiga_owned_note_pub_id + 1;",
+            HashMap::from([(String::from("y"), y)]),
+            owned_note_pub_id,
+            &input_notes,
+            &output_notes,
+        )
+        .unwrap();
+
+        // The mandatory public inputs, then the declared one
+        let mandatory =
+            mandatory_public_input_assignments(owned_note_pub_id, &input_notes, &output_notes);
+        let mut expected: Vec<pallas::Base> = VP_MANDATORY_PUBLIC_INPUT_NAMES
+            .iter()
+            .map(|name| mandatory[*name])
+            .collect();
+        expected.push(y);
+        assert_eq!(vp_circuit.public_inputs, expected);
+        assert!(vp_circuit.dev_check().is_ok());
+
+        let too_many = (0..16)
+            .map(|i| format!("pub x{i};\nx{i} = 0;\n"))
+            .collect::<String>();
+        let assignments = (0..16)
+            .map(|i| (format!("x{i}"), pallas::Base::zero()))
+            .collect();
+        assert!(matches!(
+            VampIRValidityPredicateCircuit::from_vamp_ir_source_with_notes(
+                &too_many,
+                assignments,
+                owned_note_pub_id,
+                &input_notes,
+                &output_notes,
+            ),
+            Err(VampIRCircuitError::TooManyPublicInputs(27))
+        ));
+    }
+
+    #[test]
+    fn test_vamp_ir_vp_in_ptx() {
+        use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
+        use crate::constant::NUM_NOTE;
+        use crate::executable::Executable;
+        use crate::note::{InputNoteProvingInfo, Note, NoteType, RandomSeed};
+        use crate::shielded_ptx::{
+            testing::create_trivial_swap_proving_info, ShieldedPartialTransaction,
+        };
+        use pasta_curves::pallas;
+        use rand::rngs::OsRng;
+
+        let mut rng = OsRng;
+        let source = "pub y;\ny = taiga_owned_note_pub_id + 1;";
+        let vamp_ir_vp = |owned_note_pub_id: pallas::Base,
+                          input_notes: &[Note; NUM_NOTE],
+                          output_notes: &[Note; NUM_NOTE]| {
+            VampIRValidityPredicateCircuit::from_vamp_ir_source_with_notes(
+                source,
+                HashMap::from([(String::from("y"), owned_note_pub_id + pallas::Base::one())]),
+                owned_note_pub_id,
+                input_notes,
+                output_notes,
+            )
+            .unwrap()
+        };
+
+        // The vk doesn't depend on the notes
+        let notes = [(); 2].map(|_| Note::random_padding_input_note(&mut rng));
+        let app_vk = vamp_ir_vp(pallas::Base::zero(), &notes, &notes)
+            .get_vp_vk()
+            .get_compressed();
+        let vamp_ir_note_type = NoteType::new(app_vk, pallas::Base::zero());
+        let trivial_note_type = NoteType::new(
+            TrivialValidityPredicateCircuit::default()
+                .get_vp_vk()
+                .get_compressed(),
+            pallas::Base::zero(),
+        );
+        let (input_info, output_info) = create_trivial_swap_proving_info(
+            (vamp_ir_note_type, 5),
+            (trivial_note_type, 5),
+            &RandomSeed::random(&mut rng),
+        );
+
+        // The VampIR vp is the application vp of the first input note
+        let input_notes: [Note; NUM_NOTE] = std::array::from_fn(|i| input_info[i].note);
+        let output_notes: [Note; NUM_NOTE] = std::array::from_fn(|i| output_info[i].note);
+        let [input, padding_input] = input_info;
+        let owned_note_pub_id = input.note.get_nf().unwrap().inner();
+        let input = InputNoteProvingInfo::new(
+            input.note,
+            input.merkle_path,
+            Some(input.anchor),
+            Box::new(vamp_ir_vp(owned_note_pub_id, &input_notes, &output_notes)),
+            vec![],
+        );
+
+        let ptx =
+            ShieldedPartialTransaction::build([input, padding_input], output_info, vec![], rng)
+                .unwrap();
+        ptx.execute().unwrap();
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn test_vk_serialize() {
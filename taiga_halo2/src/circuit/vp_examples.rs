@@ -1,34 +1,68 @@
 #[cfg(feature = "borsh")]
 use crate::circuit::vp_bytecode::{ValidityPredicateByteCode, ValidityPredicateRepresentation};
 use crate::{
-    circuit::vp_circuit::{
-        VPVerifyingInfo, ValidityPredicateCircuit, ValidityPredicateConfig,
-        ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{assign_free_advice, assign_free_constant},
+        vp_circuit::{
+            BasicValidityPredicateVariables, DevCheckError, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+    },
+    constant::{
+        get_params, NUM_NOTE, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX, VP_CIRCUIT_PARAMS_SIZE,
+        VP_CIRCUIT_PUBLIC_INPUT_NUM,
     },
-    constant::{NUM_NOTE, SETUP_PARAMS_MAP, VP_CIRCUIT_PARAMS_SIZE},
     note::{Note, RandomSeed},
-    proof::Proof,
     vp_commitment::ValidityPredicateCommitment,
     vp_vk::ValidityPredicateVerifyingKey,
 };
+#[cfg(feature = "prover")]
+use crate::{
+    circuit::{compressed_instance::with_witnessed_public_inputs, vp_circuit::VPVerifyingInfo},
+    proof::Proof,
+};
 #[cfg(feature = "borsh")]
 use borsh::{BorshDeserialize, BorshSerialize};
-use halo2_proofs::plonk::{keygen_pk, keygen_vk, ProvingKey};
+use halo2_proofs::plonk::keygen_vk;
+#[cfg(feature = "prover")]
+use halo2_proofs::plonk::{keygen_pk, ProvingKey};
 use halo2_proofs::{
-    circuit::{floor_planner, Layouter},
+    circuit::{floor_planner, Layouter, Value},
     plonk::{Circuit, ConstraintSystem, Error},
 };
 use lazy_static::lazy_static;
-use pasta_curves::{pallas, vesta};
-use rand::{rngs::OsRng, RngCore};
+use pasta_curves::pallas;
+#[cfg(feature = "prover")]
+use pasta_curves::vesta;
+#[cfg(feature = "prover")]
+use rand::rngs::OsRng;
+use rand::RngCore;
 #[cfg(feature = "nif")]
 use rustler::{Decoder, Encoder, Env, NifResult, NifStruct, Term};
+use std::sync::Arc;
 
+#[cfg(feature = "examples")]
+pub mod balance;
+#[cfg(feature = "examples")]
+pub mod bridge_vp;
 #[cfg(feature = "examples")]
 pub mod cascade_intent;
 #[cfg(feature = "examples")]
+pub mod compliance_vp;
+#[cfg(feature = "examples")]
+pub mod counter;
+#[cfg(feature = "examples")]
+pub mod dutch_auction_intent;
+#[cfg(feature = "examples")]
+pub mod escrow;
+#[cfg(feature = "examples")]
 mod field_addition;
 #[cfg(feature = "examples")]
+pub mod multisig_authorization;
+#[cfg(feature = "examples")]
+pub mod nft;
+#[cfg(feature = "examples")]
 pub mod or_relation_intent;
 #[cfg(feature = "examples")]
 pub mod partial_fulfillment_intent;
@@ -37,30 +71,53 @@ pub mod receiver_vp;
 #[cfg(feature = "examples")]
 pub mod signature_verification;
 #[cfg(feature = "examples")]
+pub mod spending_cap;
+#[cfg(feature = "examples")]
+pub mod timelock;
+#[cfg(feature = "examples")]
 pub mod token;
+#[cfg(feature = "examples")]
+pub mod token_issuance;
 
 lazy_static! {
     pub static ref TRIVIAL_VP_VK: ValidityPredicateVerifyingKey = {
-        let params = SETUP_PARAMS_MAP.get(&VP_CIRCUIT_PARAMS_SIZE).unwrap();
+        let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
         let empty_circuit = TrivialValidityPredicateCircuit::default();
         let vk = keygen_vk(params, &empty_circuit).expect("keygen_vk should not fail");
         ValidityPredicateVerifyingKey::from_vk(vk)
     };
+    pub static ref COMPRESSED_TRIVIAL_VP_VK: pallas::Base = TRIVIAL_VP_VK.get_compressed();
+}
+
+#[cfg(feature = "prover")]
+lazy_static! {
     pub static ref TRIVIAL_VP_PK: ProvingKey<vesta::Affine> = {
-        let params = SETUP_PARAMS_MAP.get(&VP_CIRCUIT_PARAMS_SIZE).unwrap();
+        let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
         let empty_circuit = TrivialValidityPredicateCircuit::default();
         keygen_pk(params, TRIVIAL_VP_VK.get_vk().unwrap(), &empty_circuit)
             .expect("keygen_pk should not fail")
     };
-    pub static ref COMPRESSED_TRIVIAL_VP_VK: pallas::Base = TRIVIAL_VP_VK.get_compressed();
 }
 
-// TrivialValidityPredicateCircuit with empty custom constraints.
+// TrivialValidityPredicateCircuit with empty custom constraints, unless the test knobs are set.
 #[derive(Clone, Debug, Default)]
 pub struct TrivialValidityPredicateCircuit {
     pub owned_note_pub_id: pallas::Base,
-    pub input_notes: [Note; NUM_NOTE],
-    pub output_notes: [Note; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    pub params: TrivialValidityPredicateParams,
+}
+
+/// The knobs to exercise the error paths and size limits in tests. The default params keep the
+/// trivial vp, with the cached vk and pk.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrivialValidityPredicateParams {
+    // Adds an unsatisfiable constraint
+    pub fail: bool,
+    // Adds dummy single-row regions
+    pub extra_rows: usize,
+    // Publicized after the default dynamic vp commitments
+    pub extra_public_inputs: Vec<pallas::Base>,
 }
 
 // I only exist to allow trivial derivation of the nifstruct
@@ -81,11 +138,25 @@ impl TrivialValidityPredicateCircuit {
     ) -> Self {
         Self {
             owned_note_pub_id,
-            input_notes,
-            output_notes,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            params: TrivialValidityPredicateParams::default(),
         }
     }
 
+    pub fn with_params(self, params: TrivialValidityPredicateParams) -> Self {
+        assert!(
+            params.extra_public_inputs.len()
+                <= VP_CIRCUIT_PUBLIC_INPUT_NUM - VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX,
+            "too many extra public inputs"
+        );
+        Self { params, ..self }
+    }
+
+    fn has_default_params(&self) -> bool {
+        self.params == TrivialValidityPredicateParams::default()
+    }
+
     // Only for test
     #[cfg(feature = "borsh")]
     pub fn to_bytecode(&self) -> ValidityPredicateByteCode {
@@ -125,7 +196,47 @@ impl BorshSerialize for TrivialValidityPredicateCircuit {
         for output in self.output_notes.iter() {
             output.serialize(writer)?;
         }
-        Ok(())
+        self.params.serialize(writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshSerialize for TrivialValidityPredicateParams {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        use ff::PrimeField;
+        self.fail.serialize(writer)?;
+        (self.extra_rows as u64).serialize(writer)?;
+        let extra_public_inputs: Vec<[u8; 32]> = self
+            .extra_public_inputs
+            .iter()
+            .map(|input| input.to_repr())
+            .collect();
+        extra_public_inputs.serialize(writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshDeserialize for TrivialValidityPredicateParams {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        use ff::PrimeField;
+        let fail = bool::deserialize_reader(reader)?;
+        let extra_rows = u64::deserialize_reader(reader)? as usize;
+        let extra_public_inputs = Vec::<[u8; 32]>::deserialize_reader(reader)?
+            .into_iter()
+            .map(|bytes| {
+                Option::from(pallas::Base::from_repr(bytes)).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "extra public input not in field",
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            fail,
+            extra_rows,
+            extra_public_inputs,
+        })
     }
 }
 
@@ -147,10 +258,12 @@ impl BorshDeserialize for TrivialValidityPredicateCircuit {
         let output_notes: Vec<_> = (0..NUM_NOTE)
             .map(|_| Note::deserialize_reader(reader))
             .collect::<Result<_, _>>()?;
+        let params = TrivialValidityPredicateParams::deserialize_reader(reader)?;
         Ok(Self {
             owned_note_pub_id,
-            input_notes: input_notes.try_into().unwrap(),
-            output_notes: output_notes.try_into().unwrap(),
+            input_notes: Arc::new(input_notes.try_into().unwrap()),
+            output_notes: Arc::new(output_notes.try_into().unwrap()),
+            params,
         })
     }
 }
@@ -160,11 +273,11 @@ impl TrivialValidtyPredicateCircuitProxy {
         let input_notes = self.input_notes.clone().try_into().ok()?;
         let output_notes = self.output_notes.clone().try_into().ok()?;
         let owned_note_pub_id = self.owned_note_pub_id;
-        Some(TrivialValidityPredicateCircuit {
+        Some(TrivialValidityPredicateCircuit::new(
             owned_note_pub_id,
             input_notes,
             output_notes,
-        })
+        ))
     }
 }
 #[cfg(feature = "nif")]
@@ -183,6 +296,60 @@ impl<'a> Decoder<'a> for TrivialValidityPredicateCircuit {
 }
 
 impl ValidityPredicateCircuit for TrivialValidityPredicateCircuit {
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        _basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        if self.params.fail {
+            let one = assign_free_constant(
+                layouter.namespace(|| "one"),
+                config.advices[0],
+                pallas::Base::one(),
+            )?;
+            let zero = assign_free_constant(
+                layouter.namespace(|| "zero"),
+                config.advices[0],
+                pallas::Base::zero(),
+            )?;
+            layouter.assign_region(
+                || "one = zero",
+                |mut region| region.constrain_equal(one.cell(), zero.cell()),
+            )?;
+        }
+
+        for i in 0..self.params.extra_rows {
+            assign_free_advice(
+                layouter.namespace(|| format!("extra row {i}")),
+                config.advices[0],
+                Value::known(pallas::Base::zero()),
+            )?;
+        }
+
+        for (i, input) in self.params.extra_public_inputs.iter().enumerate() {
+            let input = assign_free_advice(
+                layouter.namespace(|| format!("extra public input {i}")),
+                config.advices[0],
+                Value::known(*input),
+            )?;
+            layouter.constrain_instance(
+                input.cell(),
+                config.instances,
+                VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX + i,
+            )?;
+        }
+
+        Ok(())
+    }
+
     fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
         &self.input_notes
     }
@@ -197,6 +364,7 @@ impl ValidityPredicateCircuit for TrivialValidityPredicateCircuit {
             ValidityPredicateCommitment::default().to_public_inputs();
         public_inputs.extend(default_vp_cm);
         public_inputs.extend(default_vp_cm);
+        public_inputs.extend(self.params.extra_public_inputs.iter());
         let padding = ValidityPredicatePublicInputs::get_public_input_padding(
             public_inputs.len(),
             &RandomSeed::random(&mut rng),
@@ -210,30 +378,76 @@ impl ValidityPredicateCircuit for TrivialValidityPredicateCircuit {
     }
 }
 
-vp_circuit_impl!(TrivialValidityPredicateCircuit);
+// The knobs change the circuit shape, so they are kept without the witnesses
+impl Circuit<pallas::Base> for TrivialValidityPredicateCircuit {
+    type Config = ValidityPredicateConfig;
+    type FloorPlanner = floor_planner::V1;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            params: self.params.clone(),
+            ..Self::default()
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+        Self::Config::configure(meta)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        self.synthesize_constraints(config, layouter.namespace(|| "vp constraints"))
+    }
+}
 
 impl ValidityPredicateVerifyingInfo for TrivialValidityPredicateCircuit {
-    fn get_verifying_info(&self) -> VPVerifyingInfo {
+    #[cfg(feature = "prover")]
+    fn get_verifying_info_with_padding_rng(
+        &self,
+        padding_rng: &mut dyn rand::RngCore,
+    ) -> VPVerifyingInfo {
         let mut rng = OsRng;
-        let params = SETUP_PARAMS_MAP.get(&15).unwrap();
-        let public_inputs = self.get_public_inputs(&mut rng);
-        let proof = Proof::create(
-            &TRIVIAL_VP_PK,
-            params,
-            self.clone(),
-            &[public_inputs.inner()],
-            &mut rng,
-        )
+        let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
+        let custom_pk;
+        let pk = if self.has_default_params() {
+            &*TRIVIAL_VP_PK
+        } else {
+            let vk = self.get_vp_vk().get_vk().unwrap();
+            custom_pk = keygen_pk(params, vk, self).expect("keygen_pk should not fail");
+            &custom_pk
+        };
+        let public_inputs = self.get_public_inputs(padding_rng);
+        let proof = with_witnessed_public_inputs(&public_inputs, || {
+            Proof::create(
+                pk,
+                params,
+                self.clone(),
+                &[&public_inputs.to_instance()],
+                &mut rng,
+            )
+        })
         .unwrap();
         VPVerifyingInfo {
-            vk: TRIVIAL_VP_PK.get_vk().clone(),
+            vk: pk.get_vk().clone(),
             proof,
             public_inputs,
         }
     }
 
     fn get_vp_vk(&self) -> ValidityPredicateVerifyingKey {
-        TRIVIAL_VP_VK.clone()
+        if self.has_default_params() {
+            return TRIVIAL_VP_VK.clone();
+        }
+        let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
+        // e.g. the extra rows don't fit in the params
+        let vk = keygen_vk(params, self).unwrap_or_else(|e| panic!("keygen_vk failed: {e}"));
+        ValidityPredicateVerifyingKey::from_vk(vk)
+    }
+    fn dev_check(&self) -> Result<(), DevCheckError> {
+        self.dev_prove_and_check(rand::rngs::OsRng)
     }
 }
 
@@ -264,20 +478,38 @@ pub mod tests {
     #[test]
     fn test_halo2_trivial_vp_circuit() {
         use crate::circuit::vp_circuit::ValidityPredicateCircuit;
-        use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
-        use halo2_proofs::dev::MockProver;
         use rand::rngs::OsRng;
 
         let mut rng = OsRng;
         let circuit = random_trivial_vp_circuit(&mut rng);
-        let public_inputs = circuit.get_public_inputs(&mut rng);
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
 
-        let prover = MockProver::<pallas::Base>::run(
-            VP_CIRCUIT_PARAMS_SIZE,
-            &circuit,
-            vec![public_inputs.to_vec()],
-        )
-        .unwrap();
-        assert_eq!(prover.verify(), Ok(()));
+        // Tampered public inputs fail
+        let mut public_inputs = circuit.get_public_inputs(&mut rng).to_vec();
+        public_inputs[0] += pallas::Base::one();
+        assert!(circuit
+            .dev_prove_and_check_with_public_inputs(&public_inputs.into())
+            .is_err());
+    }
+
+    #[test]
+    fn test_dev_prove_and_check_wrong_instance_len() {
+        use crate::circuit::vp_circuit::{DevCheckError, ValidityPredicateCircuit};
+        use crate::constant::VP_CIRCUIT_PUBLIC_INPUT_NUM;
+        use rand::rngs::OsRng;
+
+        let circuit = random_trivial_vp_circuit(OsRng);
+        let expected = if cfg!(feature = "compressed_vp_instance") {
+            1
+        } else {
+            VP_CIRCUIT_PUBLIC_INPUT_NUM
+        };
+        assert_eq!(
+            circuit.dev_prove_and_check_with_instances(vec![pallas::Base::zero(); expected + 1]),
+            Err(DevCheckError::InvalidInstanceLen {
+                expected,
+                actual: expected + 1
+            })
+        );
     }
 }
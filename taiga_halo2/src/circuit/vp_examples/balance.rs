@@ -0,0 +1,281 @@
+/// The balance vp checks the notes of the application are balanced within the partial
+/// transaction: the sum of the input values equals the sum of the output values.
+/// All the notes have the app_vk of the owned note, except the padding notes that have a zero
+/// value and the trivial vp vk.
+///
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            add::{AddChip, AddInstructions},
+            assign_free_constant,
+            mul::{MulChip, MulInstructions},
+            sub::{SubChip, SubInstructions},
+            target_note_variable::get_owned_note_variable,
+        },
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::COMPRESSED_TRIVIAL_VP_VK,
+    },
+    constant::NUM_NOTE,
+    note::{Note, RandomSeed},
+    proof::Proof,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_proofs::{
+    circuit::{floor_planner, Layouter},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::pallas;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+lazy_static! {
+    pub static ref BALANCE_VK: ValidityPredicateVerifyingKey =
+        BalanceValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_BALANCE_VK: pallas::Base = BALANCE_VK.get_compressed();
+}
+
+// BalanceValidityPredicateCircuit
+#[derive(Clone, Debug, Default)]
+pub struct BalanceValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+}
+
+impl BalanceValidityPredicateCircuit {
+    pub fn new(
+        owned_note_pub_id: pallas::Base,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> Self {
+        Self {
+            owned_note_pub_id,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+        }
+    }
+}
+
+impl ValidityPredicateCircuit for BalanceValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let add_chip = AddChip::<pallas::Base>::construct(config.add_config.clone(), ());
+        let sub_chip = SubChip::<pallas::Base>::construct(config.sub_config.clone(), ());
+        let mul_chip = MulChip::<pallas::Base>::construct(config.mul_config.clone());
+
+        let constant_zero = assign_free_constant(
+            layouter.namespace(|| "zero"),
+            config.advices[0],
+            pallas::Base::zero(),
+        )?;
+        let trivial_vp_vk = assign_free_constant(
+            layouter.namespace(|| "trivial vp vk"),
+            config.advices[0],
+            *COMPRESSED_TRIVIAL_VP_VK,
+        )?;
+
+        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
+        let owned_app_vk = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get owned note app_vk"),
+            &owned_note_pub_id,
+            &basic_variables.get_app_vk_searchable_pairs(),
+        )?;
+
+        let input_notes = basic_variables
+            .input_note_variables
+            .iter()
+            .map(|variables| &variables.note_variables);
+        let output_notes = basic_variables
+            .output_note_variables
+            .iter()
+            .map(|variables| &variables.note_variables);
+
+        // A note of another app must be a padding note:
+        // (app_vk - owned_app_vk) * value = 0 and (app_vk - owned_app_vk) * (app_vk - trivial_vp_vk) = 0
+        for (i, note) in input_notes.clone().chain(output_notes.clone()).enumerate() {
+            let app_vk_diff = SubInstructions::sub(
+                &sub_chip,
+                layouter.namespace(|| format!("note {i}: app_vk - owned_app_vk")),
+                &note.app_vk,
+                &owned_app_vk,
+            )?;
+            let other_app_value = MulInstructions::mul(
+                &mul_chip,
+                layouter.namespace(|| format!("note {i}: app_vk_diff * value")),
+                &app_vk_diff,
+                &note.value,
+            )?;
+            let trivial_vk_diff = SubInstructions::sub(
+                &sub_chip,
+                layouter.namespace(|| format!("note {i}: app_vk - trivial_vp_vk")),
+                &note.app_vk,
+                &trivial_vp_vk,
+            )?;
+            let other_app_vk = MulInstructions::mul(
+                &mul_chip,
+                layouter.namespace(|| format!("note {i}: app_vk_diff * trivial_vk_diff")),
+                &app_vk_diff,
+                &trivial_vk_diff,
+            )?;
+            layouter.assign_region(
+                || format!("check note {i} app_vk"),
+                |mut region| {
+                    region.constrain_equal(other_app_value.cell(), constant_zero.cell())?;
+                    region.constrain_equal(other_app_vk.cell(), constant_zero.cell())
+                },
+            )?;
+        }
+
+        // The padding notes have zero value, so they can be summed up as well
+        let mut input_sum = constant_zero.clone();
+        for (i, note) in input_notes.enumerate() {
+            input_sum = AddInstructions::add(
+                &add_chip,
+                layouter.namespace(|| format!("add input value {i}")),
+                &input_sum,
+                &note.value,
+            )?;
+        }
+        let mut output_sum = constant_zero;
+        for (i, note) in output_notes.enumerate() {
+            output_sum = AddInstructions::add(
+                &add_chip,
+                layouter.namespace(|| format!("add output value {i}")),
+                &output_sum,
+                &note.value,
+            )?;
+        }
+        layouter.assign_region(
+            || "check balance",
+            |mut region| region.constrain_equal(input_sum.cell(), output_sum.cell()),
+        )?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+}
+
+vp_circuit_impl!(BalanceValidityPredicateCircuit);
+vp_verifying_info_impl!(BalanceValidityPredicateCircuit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::tests::{random_input_note, random_output_note};
+    use halo2_proofs::arithmetic::Field;
+
+    // The notes of the app with the values, padded with padding notes
+    fn balance_circuit(
+        input_values: &[u64],
+        output_values: &[u64],
+    ) -> BalanceValidityPredicateCircuit {
+        let mut rng = OsRng;
+        let input_notes: [Note; NUM_NOTE] = std::array::from_fn(|i| match input_values.get(i) {
+            Some(value) => {
+                let mut note = random_input_note(&mut rng);
+                note.note_type.app_vk = *COMPRESSED_BALANCE_VK;
+                note.value = *value;
+                note
+            }
+            None => Note::random_padding_input_note(&mut rng),
+        });
+        let output_notes: [Note; NUM_NOTE] = std::array::from_fn(|i| {
+            let rho = input_notes[i].get_nf().unwrap();
+            match output_values.get(i) {
+                Some(value) => {
+                    let mut note = random_output_note(&mut rng, rho);
+                    note.note_type.app_vk = *COMPRESSED_BALANCE_VK;
+                    note.value = *value;
+                    note
+                }
+                None => Note::random_padding_output_note(&mut rng, rho),
+            }
+        });
+        BalanceValidityPredicateCircuit::new(
+            input_notes[0].get_nf().unwrap().inner(),
+            input_notes,
+            output_notes,
+        )
+    }
+
+    fn check(circuit: &BalanceValidityPredicateCircuit) -> bool {
+        circuit.dev_prove_and_check(OsRng).is_ok()
+    }
+
+    #[test]
+    fn test_halo2_balance_vp_circuit() {
+        assert!(check(&balance_circuit(&[3, 5], &[6, 2])));
+        // with padding notes
+        assert!(check(&balance_circuit(&[3, 5], &[8])));
+        assert!(check(&balance_circuit(&[7], &[7])));
+    }
+
+    #[test]
+    fn test_halo2_balance_vp_circuit_unbalanced() {
+        assert!(!check(&balance_circuit(&[3, 5], &[6, 3])));
+        assert!(!check(&balance_circuit(&[3, 5], &[7])));
+    }
+
+    #[test]
+    fn test_halo2_balance_vp_circuit_mixed_app() {
+        // A note of another app
+        let mut circuit = balance_circuit(&[3, 5], &[6, 2]);
+        Arc::make_mut(&mut circuit.output_notes)[1].note_type.app_vk = pallas::Base::random(OsRng);
+        assert!(!check(&circuit));
+
+        // A zero value note of another app is not a padding note
+        let mut circuit = balance_circuit(&[3], &[3, 0]);
+        Arc::make_mut(&mut circuit.output_notes)[1].note_type.app_vk = pallas::Base::random(OsRng);
+        assert!(!check(&circuit));
+
+        // A padding note with a value
+        let mut circuit = balance_circuit(&[3, 5], &[3]);
+        Arc::make_mut(&mut circuit.output_notes)[1].value = 5;
+        assert!(!check(&circuit));
+    }
+}
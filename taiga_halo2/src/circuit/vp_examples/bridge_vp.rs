@@ -0,0 +1,377 @@
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            assign_free_advice, assign_free_constant,
+            poseidon_hash::poseidon_hash_gadget,
+            sub::{SubChip, SubInstructions},
+            target_note_variable::get_owned_note_variable,
+        },
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::token::Token,
+    },
+    constant::{NUM_NOTE, TOKEN_BRIDGE_DOMAIN, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX},
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, NoteType, OutputNoteProvingInfo, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    proof::Proof,
+    utils::poseidon_hash_n,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_proofs::{
+    circuit::{floor_planner, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::pallas;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+lazy_static! {
+    pub static ref BRIDGE_VK: ValidityPredicateVerifyingKey =
+        BridgeValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_BRIDGE_VK: pallas::Base = BRIDGE_VK.get_compressed();
+}
+
+/// A token resource on the transparent side of the bridge.
+/// The wrapped notes of the resource are of the note type (bridge vp vk, app_data_static), where
+/// app_data_static = poseidon(bridge domain, token name, data hash) binds the token and the data
+/// hash of the transparent resource.
+#[derive(Clone, Debug, Default)]
+pub struct TransparentTokenResource {
+    pub token: Token,
+    pub data_hash: pallas::Base,
+}
+
+impl TransparentTokenResource {
+    pub fn new(token: Token, data_hash: pallas::Base) -> Self {
+        Self { token, data_hash }
+    }
+
+    pub fn app_data_static(&self) -> pallas::Base {
+        poseidon_hash_n([
+            TOKEN_BRIDGE_DOMAIN,
+            self.token.encode_name(),
+            self.data_hash,
+        ])
+    }
+
+    pub fn note_type(&self) -> NoteType {
+        NoteType::new(*COMPRESSED_BRIDGE_VK, self.app_data_static())
+    }
+
+    // The custom public input of the bridge vp, the executor looks it up in the transparent ptx
+    pub fn commitment(&self) -> pallas::Base {
+        poseidon_hash_n([
+            TOKEN_BRIDGE_DOMAIN,
+            self.token.encode_name(),
+            self.token.encode_value(),
+            self.data_hash,
+        ])
+    }
+
+    // Create a wrapped note of the resource. The ephemeral notes stand for the transparent side:
+    // spending one wraps the resource and creating one unwraps it.
+    pub fn create_wrapped_note<R: RngCore>(
+        &self,
+        mut rng: R,
+        rho: Nullifier,
+        nk_container: NullifierKeyContainer,
+        is_ephemeral: bool,
+    ) -> Note {
+        let note_type = self.note_type();
+        let rseed = RandomSeed::random(&mut rng);
+        Note::new(
+            note_type.app_vk,
+            note_type.app_data_static,
+            pallas::Base::zero(),
+            self.token.value(),
+            nk_container,
+            rho,
+            !is_ephemeral,
+            rseed,
+        )
+    }
+
+    pub fn generate_input_proving_info(
+        &self,
+        note: Note,
+        merkle_path: MerklePath,
+        custom_anchor: Option<Anchor>,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> InputNoteProvingInfo {
+        let bridge_vp = BridgeValidityPredicateCircuit {
+            owned_note_pub_id: note.get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            resource: self.clone(),
+        };
+        InputNoteProvingInfo::new(
+            note,
+            merkle_path,
+            custom_anchor,
+            Box::new(bridge_vp),
+            vec![],
+        )
+    }
+
+    pub fn generate_output_proving_info(
+        &self,
+        note: Note,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> OutputNoteProvingInfo {
+        let bridge_vp = BridgeValidityPredicateCircuit {
+            owned_note_pub_id: note.commitment().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            resource: self.clone(),
+        };
+        OutputNoteProvingInfo::new(note, Box::new(bridge_vp), vec![])
+    }
+}
+
+// BridgeValidityPredicateCircuit is the application vp of the wrapped notes.
+// The owned note must be a wrapped note of the transparent resource. If it's ephemeral(the
+// transparent side of a wrap or an unwrap), its value must be the value of the resource too.
+// The resource commitment is published, the executor checks the transparent ptx spends(wrap)
+// or creates(unwrap) the resource.
+#[derive(Clone, Debug, Default)]
+pub struct BridgeValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    pub resource: TransparentTokenResource,
+}
+
+impl ValidityPredicateCircuit for BridgeValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
+
+        let bridge_domain = assign_free_constant(
+            layouter.namespace(|| "bridge domain"),
+            config.advices[0],
+            TOKEN_BRIDGE_DOMAIN,
+        )?;
+        let token_name = assign_free_advice(
+            layouter.namespace(|| "witness token name"),
+            config.advices[0],
+            Value::known(self.resource.token.encode_name()),
+        )?;
+        let transparent_value = assign_free_advice(
+            layouter.namespace(|| "witness transparent value"),
+            config.advices[0],
+            Value::known(self.resource.token.encode_value()),
+        )?;
+        let data_hash = assign_free_advice(
+            layouter.namespace(|| "witness data hash"),
+            config.advices[0],
+            Value::known(self.resource.data_hash),
+        )?;
+
+        // Check the app_data_static binds the token and the data hash of the resource
+        let app_data_static = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get owned note app_data_static"),
+            &owned_note_pub_id,
+            &basic_variables.get_app_data_static_searchable_pairs(),
+        )?;
+        let encoded_app_data_static = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "app_data_static encoding"),
+            [bridge_domain.clone(), token_name.clone(), data_hash.clone()],
+        )?;
+        layouter.assign_region(
+            || "check app_data_static encoding",
+            |mut region| {
+                region.constrain_equal(encoded_app_data_static.cell(), app_data_static.cell())
+            },
+        )?;
+
+        // The ephemeral note carries the value of the resource:
+        // (1 - is_merkle_checked) * (value - transparent_value) = 0
+        let value = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get owned note value"),
+            &owned_note_pub_id,
+            &basic_variables.get_value_searchable_pairs(),
+        )?;
+        let is_merkle_checked = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get is_merkle_checked"),
+            &owned_note_pub_id,
+            &basic_variables.get_is_merkle_checked_searchable_pairs(),
+        )?;
+        let constant_one = assign_free_constant(
+            layouter.namespace(|| "one"),
+            config.advices[0],
+            pallas::Base::one(),
+        )?;
+        let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+        let is_ephemeral = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "1 - is_merkle_checked"),
+            &constant_one,
+            &is_merkle_checked,
+        )?;
+        layouter.assign_region(
+            || "conditional equal: check the ephemeral note value",
+            |mut region| {
+                config.conditional_equal_config.assign_region(
+                    &is_ephemeral,
+                    &value,
+                    &transparent_value,
+                    0,
+                    &mut region,
+                )
+            },
+        )?;
+
+        // Publicize the resource commitment
+        let resource_commitment = poseidon_hash_gadget(
+            config.poseidon_config,
+            layouter.namespace(|| "resource commitment"),
+            [bridge_domain, token_name, transparent_value, data_hash],
+        )?;
+        layouter.constrain_instance(
+            resource_commitment.cell(),
+            config.instances,
+            VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX,
+        )?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        public_inputs.push(self.resource.commitment());
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+}
+
+vp_circuit_impl!(BridgeValidityPredicateCircuit);
+vp_verifying_info_impl!(BridgeValidityPredicateCircuit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::vp_examples::token::TokenName;
+    use crate::note::tests::{random_input_note, random_output_note};
+    use halo2_proofs::arithmetic::Field;
+    use rand::rngs::OsRng;
+
+    // Wrap: spend the ephemeral note of the transparent resource
+    fn wrap_circuit(
+        resource: &TransparentTokenResource,
+        witness: TransparentTokenResource,
+    ) -> BridgeValidityPredicateCircuit {
+        let mut rng = OsRng;
+        let rho = Nullifier::from(pallas::Base::random(&mut rng));
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let ephemeral_note = resource.create_wrapped_note(&mut rng, rho, nk, true);
+        let input_notes = [ephemeral_note, random_input_note(&mut rng)];
+        let output_notes =
+            input_notes.map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+        BridgeValidityPredicateCircuit {
+            owned_note_pub_id: ephemeral_note.get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            resource: witness,
+        }
+    }
+
+    fn check(circuit: &BridgeValidityPredicateCircuit) -> bool {
+        circuit.dev_prove_and_check(OsRng).is_ok()
+    }
+
+    #[test]
+    fn test_bridge_vp_wrap() {
+        let data_hash = pallas::Base::random(OsRng);
+        let resource =
+            TransparentTokenResource::new(Token::new(TokenName::new("btc").unwrap(), 5), data_hash);
+        assert!(check(&wrap_circuit(&resource, resource.clone())));
+
+        // The value of the resource differs
+        let witness =
+            TransparentTokenResource::new(Token::new(TokenName::new("btc").unwrap(), 6), data_hash);
+        assert!(!check(&wrap_circuit(&resource, witness)));
+
+        // The token of the resource differs
+        let witness =
+            TransparentTokenResource::new(Token::new(TokenName::new("eth").unwrap(), 5), data_hash);
+        assert!(!check(&wrap_circuit(&resource, witness)));
+
+        // The data hash of the resource differs
+        let witness = TransparentTokenResource::new(
+            Token::new(TokenName::new("btc").unwrap(), 5),
+            pallas::Base::random(OsRng),
+        );
+        assert!(!check(&wrap_circuit(&resource, witness)));
+    }
+
+    #[test]
+    fn test_bridge_vp_wrapped_note_transfer() {
+        // The value is only bound for the ephemeral notes
+        let mut rng = OsRng;
+        let resource = TransparentTokenResource::new(
+            Token::new(TokenName::new("btc").unwrap(), 5),
+            pallas::Base::random(&mut rng),
+        );
+        let rho = Nullifier::from(pallas::Base::random(&mut rng));
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let wrapped_note = resource.create_wrapped_note(&mut rng, rho, nk, false);
+        let input_notes = [wrapped_note, random_input_note(&mut rng)];
+        let output_notes =
+            input_notes.map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+        let circuit = BridgeValidityPredicateCircuit {
+            owned_note_pub_id: wrapped_note.get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            resource: TransparentTokenResource::new(
+                Token::new(TokenName::new("btc").unwrap(), 6),
+                resource.data_hash,
+            ),
+        };
+        assert!(check(&circuit));
+    }
+}
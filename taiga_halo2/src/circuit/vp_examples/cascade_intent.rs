@@ -16,7 +16,7 @@ use crate::{
             ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
         },
     },
-    constant::{NUM_NOTE, SETUP_PARAMS_MAP},
+    constant::NUM_NOTE,
     note::{Note, RandomSeed},
     nullifier::{Nullifier, NullifierKeyContainer},
     proof::Proof,
@@ -31,6 +31,7 @@ use lazy_static::lazy_static;
 use pasta_curves::pallas;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use std::sync::Arc;
 
 lazy_static! {
     pub static ref CASCADE_INTENT_VK: ValidityPredicateVerifyingKey =
@@ -42,8 +43,8 @@ lazy_static! {
 #[derive(Clone, Debug, Default)]
 pub struct CascadeIntentValidityPredicateCircuit {
     pub owned_note_pub_id: pallas::Base,
-    pub input_notes: [Note; NUM_NOTE],
-    pub output_notes: [Note; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
     // use the note commitment to identify the note.
     pub cascade_note_cm: pallas::Base,
 }
@@ -170,10 +171,8 @@ pub fn create_intent_note<R: RngCore>(
 
 #[test]
 fn test_halo2_cascade_intent_vp_circuit() {
-    use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
     use crate::note::tests::{random_input_note, random_output_note};
     use halo2_proofs::arithmetic::Field;
-    use halo2_proofs::dev::MockProver;
     use rand::rngs::OsRng;
 
     let mut rng = OsRng;
@@ -191,18 +190,10 @@ fn test_halo2_cascade_intent_vp_circuit() {
 
         CascadeIntentValidityPredicateCircuit {
             owned_note_pub_id: input_notes[0].get_nf().unwrap().inner(),
-            input_notes,
-            output_notes: output_notes.try_into().unwrap(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes.try_into().unwrap()),
             cascade_note_cm,
         }
     };
-    let public_inputs = circuit.get_public_inputs(&mut rng);
-
-    let prover = MockProver::<pallas::Base>::run(
-        VP_CIRCUIT_PARAMS_SIZE,
-        &circuit,
-        vec![public_inputs.to_vec()],
-    )
-    .unwrap();
-    assert_eq!(prover.verify(), Ok(()));
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
 }
@@ -0,0 +1,655 @@
+// The compliance VP screens the receivers of a token: it's a dynamic VP of the output token notes
+// and proves the receiver auth key, i.e. the owner of the note, is in an allowlist merkle tree.
+//
+// The allowlist root is a constant of the circuit, so the compressed vk pins the allowlist. The
+// token note puts the compliance vk in the receiver vp slot of its app_data_dynamic, the token VP
+// then commits to the compliance vk in the dynamic vp commitment and the note can't be created
+// without the compliance VP. Holding the receiver vp slot, the compliance VP also encrypts the note
+// to the receiver.
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            assign_free_advice, assign_free_constant, poseidon_hash::poseidon_hash_gadget,
+            target_note_variable::get_owned_note_variables,
+        },
+        merkle_circuit::{merkle_poseidon_gadget, MerklePoseidonChip},
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::{
+            receiver_vp::{
+                output_note_kind, owned_note_encryption_gadget, owned_note_encryption_public_inputs,
+            },
+            signature_verification::SignatureVerificationValidityPredicateCircuit,
+            token::{
+                Token, TokenAuthorization, TokenNote, TokenValidityPredicateCircuit,
+                TOKEN_AUTHORIZATION_SCHEMA,
+            },
+        },
+    },
+    constant::{NUM_NOTE, PRF_EXPAND_DYNAMIC_VP_1_CM_R},
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath, Node, LR},
+    note::{
+        encoding::StaticDataValue, InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed,
+    },
+    note_encryption::{NoteEncryption, NoteKind},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    proof::Proof,
+    utils::poseidon_hash_n,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use ff::{Field, PrimeField};
+use group::Curve;
+use halo2_gadgets::ecc::{chip::EccChip, NonIdentityPoint};
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    circuit::{floor_planner, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use pasta_curves::{group::Group, pallas};
+use rand::{rngs::OsRng, Rng, RngCore};
+use std::sync::Arc;
+
+// The allowlist holds up to 2^ALLOWLIST_TREE_DEPTH receiver keys
+pub const ALLOWLIST_TREE_DEPTH: usize = 8;
+
+// The allowlist of the receiver keys, the leaves are poseidon_hash(pk.x, pk.y)
+#[derive(Clone, Debug)]
+pub struct ComplianceAllowlist {
+    keys: Vec<pallas::Point>,
+    // The compressed vk of the compliance vp of this allowlist
+    vp_vk: pallas::Base,
+}
+
+impl ComplianceAllowlist {
+    pub fn new(keys: Vec<pallas::Point>) -> Self {
+        assert!(keys.len() <= 1 << ALLOWLIST_TREE_DEPTH);
+        let root = Self::layers(&keys).last().unwrap()[0].inner();
+        let vp_vk = ComplianceValidityPredicateCircuit {
+            allowlist_root: root,
+            ..Default::default()
+        }
+        .get_vp_vk()
+        .get_compressed();
+        Self { keys, vp_vk }
+    }
+
+    pub fn leaf(pk: &pallas::Point) -> Node {
+        let pk_coord = pk.to_affine().coordinates().unwrap();
+        Node::from(poseidon_hash_n([*pk_coord.x(), *pk_coord.y()]))
+    }
+
+    // The tree layers from the leaves to the root, the empty leaves are zero
+    fn layers(keys: &[pallas::Point]) -> Vec<Vec<Node>> {
+        let mut layer: Vec<Node> = keys.iter().map(Self::leaf).collect();
+        layer.resize(1 << ALLOWLIST_TREE_DEPTH, Node::from(pallas::Base::zero()));
+        let mut layers = vec![layer];
+        for _ in 0..ALLOWLIST_TREE_DEPTH {
+            let next = layers
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| Node::combine(&pair[0], &pair[1]))
+                .collect();
+            layers.push(next);
+        }
+        layers
+    }
+
+    pub fn root(&self) -> pallas::Base {
+        Self::layers(&self.keys).last().unwrap()[0].inner()
+    }
+
+    pub fn vp_vk(&self) -> pallas::Base {
+        self.vp_vk
+    }
+
+    // Returns None if the key is not in the allowlist
+    pub fn merkle_path(&self, pk: &pallas::Point) -> Option<MerklePath> {
+        let mut position = self.keys.iter().position(|key| key == pk)?;
+        let layers = Self::layers(&self.keys);
+        let path = layers[..ALLOWLIST_TREE_DEPTH]
+            .iter()
+            .map(|layer| {
+                // The LR is the position of the sibling
+                let sibling = if position % 2 == 0 {
+                    (layer[position + 1], LR::R)
+                } else {
+                    (layer[position - 1], LR::L)
+                };
+                position /= 2;
+                sibling
+            })
+            .collect();
+        Some(MerklePath::from_path(path))
+    }
+
+    // The token app_data_dynamic with the compliance vp in the receiver vp slot
+    pub fn to_app_data_dynamic(&self, auth: &TokenAuthorization) -> pallas::Base {
+        TOKEN_AUTHORIZATION_SCHEMA
+            .encode(&[
+                StaticDataValue::Point(auth.pk),
+                StaticDataValue::Base(auth.vk),
+                StaticDataValue::Base(self.vp_vk),
+                StaticDataValue::Point(auth.rcv_pk),
+            ])
+            .expect("the auth pks are the identity")
+    }
+
+    pub fn create_compliant_token_note<R: RngCore>(
+        &self,
+        mut rng: R,
+        token: &Token,
+        rho: Nullifier,
+        nk_container: NullifierKeyContainer,
+        auth: &TokenAuthorization,
+    ) -> TokenNote {
+        let mut token_note = token.create_random_token_note(&mut rng, rho, nk_container, auth);
+        token_note.note.app_data_dynamic = self.to_app_data_dynamic(auth);
+        token_note
+    }
+
+    // Same as TokenNote::generate_input_token_note_proving_info, under the compliant encoding
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate_input_token_note_proving_info<R: RngCore>(
+        &self,
+        mut rng: R,
+        token_note: &TokenNote,
+        auth: TokenAuthorization,
+        auth_sk: &SigningKey,
+        merkle_path: MerklePath,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> InputNoteProvingInfo {
+        // The vps share the notes
+        let input_notes = Arc::new(input_notes);
+        let output_notes = Arc::new(output_notes);
+        let nf = token_note.get_nf().unwrap().inner();
+        let token_vp = TokenValidityPredicateCircuit {
+            owned_note_pub_id: nf,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
+            token_name: token_note.token_name().clone(),
+            auth,
+            receiver_vp_vk: self.vp_vk,
+            rseed: RandomSeed::random(&mut rng),
+        };
+        let token_auth_vp = SignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            nf,
+            anchors,
+            input_notes,
+            output_notes,
+            auth.vk,
+            auth_sk,
+            self.vp_vk,
+            auth.rcv_pk,
+        );
+        InputNoteProvingInfo::new(
+            *token_note.note(),
+            merkle_path,
+            None,
+            Box::new(token_vp),
+            vec![Box::new(token_auth_vp)],
+        )
+    }
+
+    // The output token note is proved by the token vp and the compliance vp, which encrypts it.
+    // Returns the opening of the dynamic vp commitment along with the proving info, or None if
+    // the receiver is not in the allowlist.
+    pub fn generate_output_token_note_proving_info<R: RngCore>(
+        &self,
+        mut rng: R,
+        token_note: &TokenNote,
+        auth: TokenAuthorization,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> Option<(OutputNoteProvingInfo, pallas::Base)> {
+        // The vps share the notes
+        let input_notes = Arc::new(input_notes);
+        let output_notes = Arc::new(output_notes);
+        let auth_path = self.merkle_path(&auth.pk)?;
+        let owned_note_pub_id = token_note.commitment().inner();
+        let rseed = RandomSeed::random(&mut rng);
+        let token_vp = TokenValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
+            token_name: token_note.token_name().clone(),
+            auth,
+            receiver_vp_vk: self.vp_vk,
+            rseed,
+        };
+        let compliance_vp = ComplianceValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
+            allowlist_root: self.root(),
+            vp_vk: self.vp_vk,
+            auth_vp_vk: auth.vk,
+            auth_pk: auth.pk,
+            auth_path,
+            rcv_pk: auth.rcv_pk,
+            nonce: pallas::Base::from_u128(rng.gen()),
+            esk: pallas::Base::random(&mut rng),
+            note_kind: output_note_kind(token_note.note(), &input_notes, &output_notes).to_base(),
+        };
+
+        let note_encryption = compliance_vp.note_encryption();
+        let mut proving_info = OutputNoteProvingInfo::new(
+            *token_note.note(),
+            Box::new(token_vp),
+            vec![Box::new(compliance_vp)],
+        );
+        proving_info.set_note_encryption(note_encryption);
+        Some((
+            proving_info,
+            rseed.get_vp_cm_r(PRF_EXPAND_DYNAMIC_VP_1_CM_R),
+        ))
+    }
+}
+
+// ComplianceValidityPredicateCircuit
+#[derive(Clone, Debug)]
+pub struct ComplianceValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    // The allowlist root is a circuit constant
+    pub allowlist_root: pallas::Base,
+    // The compressed vk of this vp, it's encoded in the token app_data_dynamic
+    pub vp_vk: pallas::Base,
+    pub auth_vp_vk: pallas::Base,
+    // The auth pk is checked in the allowlist
+    pub auth_pk: pallas::Point,
+    pub auth_path: MerklePath,
+    pub rcv_pk: pallas::Point,
+    // The note encryption, the same as the receiver vp
+    pub nonce: pallas::Base,
+    pub esk: pallas::Base,
+    pub note_kind: pallas::Base,
+}
+
+impl ComplianceValidityPredicateCircuit {
+    // The encryption of the owned note, the action of the note commits to the same ciphertext
+    pub fn note_encryption(&self) -> NoteEncryption {
+        NoteEncryption {
+            nonce: self.nonce,
+            esk: self.esk,
+            rcv_pk: self.rcv_pk,
+            note_kind: self.note_kind,
+        }
+    }
+}
+
+impl Default for ComplianceValidityPredicateCircuit {
+    fn default() -> Self {
+        Self {
+            owned_note_pub_id: pallas::Base::zero(),
+            input_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            output_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            allowlist_root: pallas::Base::zero(),
+            vp_vk: pallas::Base::zero(),
+            auth_vp_vk: pallas::Base::zero(),
+            auth_pk: pallas::Point::generator(),
+            auth_path: MerklePath::from_path(vec![
+                (Node::from(pallas::Base::zero()), LR::L);
+                ALLOWLIST_TREE_DEPTH
+            ]),
+            rcv_pk: pallas::Point::generator(),
+            nonce: pallas::Base::zero(),
+            esk: pallas::Base::zero(),
+            note_kind: NoteKind::default().to_base(),
+        }
+    }
+}
+
+impl ValidityPredicateCircuit for ComplianceValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let owned_note = get_owned_note_variables(
+            &config,
+            layouter.namespace(|| "get owned note variables"),
+            &basic_variables,
+        )?;
+
+        // Only the output notes are screened
+        let is_input_note = owned_note.is_input_note;
+        let constant_zero = assign_free_constant(
+            layouter.namespace(|| "zero"),
+            config.advices[0],
+            pallas::Base::zero(),
+        )?;
+        layouter.assign_region(
+            || "check output note",
+            |mut region| region.constrain_equal(is_input_note.cell(), constant_zero.cell()),
+        )?;
+
+        // Construct an ECC chip
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
+
+        let auth_pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness auth_pk"),
+            Value::known(self.auth_pk.to_affine()),
+        )?;
+
+        let rcv_pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness rcv_pk"),
+            Value::known(self.rcv_pk.to_affine()),
+        )?;
+
+        let auth_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness auth vp vk"),
+            config.advices[0],
+            Value::known(self.auth_vp_vk),
+        )?;
+
+        let compliance_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness compliance vp vk"),
+            config.advices[0],
+            Value::known(self.vp_vk),
+        )?;
+
+        let app_data_dynamic = owned_note.note_variables.app_data_dynamic.clone();
+
+        // The receiver keys come from the token app_data_dynamic
+        let encoded_app_data_dynamic = TOKEN_AUTHORIZATION_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "app_data_dynamic encoding"),
+            &[
+                auth_pk.inner().x(),
+                auth_pk.inner().y(),
+                auth_vp_vk,
+                compliance_vp_vk,
+                rcv_pk.inner().x(),
+                rcv_pk.inner().y(),
+            ],
+        )?;
+
+        layouter.assign_region(
+            || "check app_data_dynamic encoding",
+            |mut region| {
+                region.constrain_equal(encoded_app_data_dynamic.cell(), app_data_dynamic.cell())
+            },
+        )?;
+
+        // The receiver auth key is in the allowlist
+        let leaf = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "allowlist leaf"),
+            [auth_pk.inner().x(), auth_pk.inner().y()],
+        )?;
+        let merkle_chip = MerklePoseidonChip::construct(config.merkle_config.clone());
+        let root = merkle_poseidon_gadget(
+            layouter.namespace(|| "allowlist merkle root"),
+            merkle_chip,
+            leaf,
+            &self.auth_path.get_path(),
+        )?;
+        let allowlist_root = assign_free_constant(
+            layouter.namespace(|| "allowlist root"),
+            config.advices[0],
+            self.allowlist_root,
+        )?;
+        layouter.assign_region(
+            || "check allowlist root",
+            |mut region| region.constrain_equal(root.cell(), allowlist_root.cell()),
+        )?;
+
+        // Encrypt the note to the receiver
+        let nonce = assign_free_advice(
+            layouter.namespace(|| "witness nonce"),
+            config.advices[0],
+            Value::known(self.nonce),
+        )?;
+        let esk = assign_free_advice(
+            layouter.namespace(|| "witness esk"),
+            config.advices[0],
+            Value::known(self.esk),
+        )?;
+        owned_note_encryption_gadget(
+            &config,
+            layouter.namespace(|| "owned note encryption"),
+            ecc_chip,
+            owned_note.note_variables,
+            self.note_kind,
+            nonce,
+            esk,
+            rcv_pk,
+        )?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        let custom_public_input_padding =
+            ValidityPredicatePublicInputs::get_custom_public_input_padding(
+                public_inputs.len(),
+                &RandomSeed::random(&mut rng),
+            );
+        public_inputs.extend(custom_public_input_padding.iter());
+        let owned_note = self
+            .output_notes
+            .iter()
+            .find(|note| note.commitment().inner() == self.owned_note_pub_id)
+            .unwrap_or(&self.output_notes[0]);
+        public_inputs.extend(owned_note_encryption_public_inputs(
+            &self.note_encryption(),
+            owned_note,
+        ));
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+}
+
+vp_circuit_impl!(ComplianceValidityPredicateCircuit);
+vp_verifying_info_impl!(ComplianceValidityPredicateCircuit);
+
+#[test]
+fn test_halo2_compliance_vp_circuit() {
+    use crate::circuit::vp_examples::signature_verification::COMPRESSED_TOKEN_AUTH_VK;
+    use crate::note::tests::{random_input_note, random_output_note};
+
+    let mut rng = OsRng;
+    let alice = pallas::Point::random(&mut rng);
+    let bob = pallas::Point::random(&mut rng);
+    let allowlist = ComplianceAllowlist::new(vec![alice, bob]);
+    // The vk pins the allowlist
+    assert_ne!(
+        allowlist.vp_vk(),
+        ComplianceAllowlist::new(vec![alice]).vp_vk()
+    );
+    assert!(allowlist
+        .merkle_path(&pallas::Point::random(&mut rng))
+        .is_none());
+
+    let compliance_circuit = |auth_pk: pallas::Point, auth_path: MerklePath| {
+        let mut rng = OsRng;
+        let input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+        let mut output_notes = input_notes
+            .iter()
+            .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()))
+            .collect::<Vec<_>>();
+        let auth = TokenAuthorization::new(auth_pk, *COMPRESSED_TOKEN_AUTH_VK)
+            .unwrap()
+            .with_rcv_pk(pallas::Point::random(&mut rng))
+            .unwrap();
+        output_notes[0].app_data_dynamic = allowlist.to_app_data_dynamic(&auth);
+        ComplianceValidityPredicateCircuit {
+            owned_note_pub_id: output_notes[0].commitment().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes.try_into().unwrap()),
+            allowlist_root: allowlist.root(),
+            vp_vk: allowlist.vp_vk(),
+            auth_vp_vk: auth.vk,
+            auth_pk,
+            auth_path,
+            rcv_pk: auth.rcv_pk,
+            nonce: pallas::Base::from_u128(rng.gen()),
+            esk: pallas::Base::random(&mut rng),
+            note_kind: NoteKind::External.to_base(),
+        }
+    };
+
+    // The allowlisted receiver passes
+    let circuit = compliance_circuit(bob, allowlist.merkle_path(&bob).unwrap());
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+    // A receiver not in the allowlist can't reuse the path of an allowlisted key
+    let carol = pallas::Point::random(&mut rng);
+    let circuit = compliance_circuit(carol, allowlist.merkle_path(&bob).unwrap());
+    assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+}
+
+#[test]
+fn test_compliance_vp_cannot_be_omitted() {
+    use crate::circuit::vp_examples::signature_verification::COMPRESSED_TOKEN_AUTH_VK;
+    use crate::circuit::vp_examples::token::TokenName;
+    use crate::constant::TAIGA_COMMITMENT_TREE_DEPTH;
+    use crate::error::TransactionError;
+    use crate::shielded_ptx::ShieldedPartialTransaction;
+
+    let mut rng = OsRng;
+    let generator = pallas::Point::generator().to_affine();
+    let sender_sk = pallas::Scalar::random(&mut rng);
+    let sender_auth = TokenAuthorization::from_sk_vk(&sender_sk, &COMPRESSED_TOKEN_AUTH_VK);
+    let receiver_auth =
+        TokenAuthorization::new(pallas::Point::random(&mut rng), *COMPRESSED_TOKEN_AUTH_VK)
+            .unwrap();
+    let allowlist = ComplianceAllowlist::new(vec![generator * sender_sk, receiver_auth.pk]);
+
+    let token = Token::new(TokenName::new("usdc").unwrap(), 5);
+    let rho = Nullifier::from(pallas::Base::random(&mut rng));
+    let sender_nk = NullifierKeyContainer::random_key(&mut rng);
+    let input_note =
+        allowlist.create_compliant_token_note(&mut rng, &token, rho, sender_nk, &sender_auth);
+    let receiver_nk = NullifierKeyContainer::random_key(&mut rng);
+    let output_note = allowlist.create_compliant_token_note(
+        &mut rng,
+        &token,
+        input_note.get_nf().unwrap(),
+        receiver_nk.to_commitment(),
+        &receiver_auth,
+    );
+    let padding_input_note = Note::random_padding_input_note(&mut rng);
+    let padding_output_note =
+        Note::random_padding_output_note(&mut rng, padding_input_note.get_nf().unwrap());
+    let input_notes = [*input_note.note(), padding_input_note];
+    let output_notes = [*output_note.note(), padding_output_note];
+
+    let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+    let anchor = Anchor::from(pallas::Base::random(&mut rng));
+    let input_proving_info = || {
+        [
+            allowlist.generate_input_token_note_proving_info(
+                OsRng,
+                &input_note,
+                sender_auth,
+                &SigningKey::new(sender_sk),
+                merkle_path.clone(),
+                [input_note.calculate_root(&merkle_path), anchor],
+                input_notes,
+                output_notes,
+            ),
+            InputNoteProvingInfo::create_padding_note_proving_info(
+                padding_input_note,
+                merkle_path.clone(),
+                anchor,
+                input_notes,
+                output_notes,
+            ),
+        ]
+    };
+    let padding_output_proving_info = || {
+        OutputNoteProvingInfo::create_padding_note_proving_info(
+            padding_output_note,
+            input_notes,
+            output_notes,
+        )
+    };
+    let (output_proving_info, vp_cm_r) = allowlist
+        .generate_output_token_note_proving_info(
+            &mut rng,
+            &output_note,
+            receiver_auth,
+            input_notes,
+            output_notes,
+        )
+        .unwrap();
+
+    // The prover drops the compliance vp, the proofs are still valid
+    let mut dynamic_vps = output_proving_info.get_dynamic_vps();
+    dynamic_vps.remove(0);
+    let mut omitted_proving_info = OutputNoteProvingInfo::new(
+        output_proving_info.note,
+        output_proving_info.get_application_vp(),
+        dynamic_vps,
+    );
+    omitted_proving_info.set_note_encryption(output_proving_info.get_note_encryption().unwrap());
+    let ptx = ShieldedPartialTransaction::build(
+        input_proving_info(),
+        [omitted_proving_info, padding_output_proving_info()],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+    assert!(ptx.verify_proof().is_ok());
+    // but the token vp committed to the compliance vp
+    assert!(matches!(
+        ptx.check_output_dynamic_vp(0, allowlist.vp_vk(), vp_cm_r),
+        Err(TransactionError::MissingDynamicVP)
+    ));
+
+    let ptx = ShieldedPartialTransaction::build(
+        input_proving_info(),
+        [output_proving_info, padding_output_proving_info()],
+        vec![],
+        &mut rng,
+    )
+    .unwrap();
+    assert!(ptx.verify_proof().is_ok());
+    assert!(ptx
+        .check_output_dynamic_vp(0, allowlist.vp_vk(), vp_cm_r)
+        .is_ok());
+    // The commitment opens to the compliance vp only
+    assert!(matches!(
+        ptx.check_output_dynamic_vp(0, allowlist.vp_vk(), pallas::Base::random(&mut rng)),
+        Err(TransactionError::InconsistentDynamicVPCommitment)
+    ));
+}
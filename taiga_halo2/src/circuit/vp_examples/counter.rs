@@ -0,0 +1,422 @@
+/// The counter application shows how to evolve an application state across notes.
+/// A counter note has value 1, the app_data_static is the counter id and the app_data_dynamic
+/// encodes the counter state. The app_data_static(and hence the value base) stays the same
+/// across increments, so the partial transactions are balanced.
+/// The counter note pair is in the first action: the output note counter = the input note counter + 1.
+/// A counter is created by spending an ephemeral genesis note with the init flag, the output
+/// counter of the genesis action is 0.
+///
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            add::{AddChip, AddInstructions},
+            assign_free_advice, assign_free_constant,
+            mul::{MulChip, MulInstructions},
+            poseidon_hash::poseidon_hash_gadget,
+            sub::{SubChip, SubInstructions},
+            target_note_variable::get_owned_note_variable,
+        },
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+    },
+    constant::NUM_NOTE,
+    note::{Note, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    proof::Proof,
+    utils::poseidon_hash_n,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_proofs::{
+    arithmetic::Field,
+    circuit::{floor_planner, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::pallas;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+lazy_static! {
+    pub static ref COUNTER_VK: ValidityPredicateVerifyingKey =
+        CounterValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_COUNTER_VK: pallas::Base = COUNTER_VK.get_compressed();
+}
+
+// The state encoded in the app_data_dynamic of counter notes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CounterState {
+    pub counter: u64,
+    // The genesis note has the init flag
+    pub is_init: bool,
+}
+
+impl CounterState {
+    pub fn genesis() -> Self {
+        Self {
+            counter: 0,
+            is_init: true,
+        }
+    }
+
+    pub fn new(counter: u64) -> Self {
+        Self {
+            counter,
+            is_init: false,
+        }
+    }
+
+    // The state after the counter note is spent
+    pub fn next(&self) -> Self {
+        if self.is_init {
+            Self::new(0)
+        } else {
+            Self::new(self.counter + 1)
+        }
+    }
+
+    // app_data_dynamic = poseidon_hash(counter || is_init)
+    pub fn encode(&self) -> pallas::Base {
+        poseidon_hash_n([
+            pallas::Base::from(self.counter),
+            pallas::Base::from(self.is_init as u64),
+        ])
+    }
+}
+
+// CounterValidityPredicateCircuit
+#[derive(Clone, Debug, Default)]
+pub struct CounterValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    // The state of the counter note in the first input
+    pub input_state: CounterState,
+    // The state of the counter note in the first output
+    pub output_state: CounterState,
+}
+
+impl CounterValidityPredicateCircuit {
+    pub fn new(
+        owned_note_pub_id: pallas::Base,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+        input_state: CounterState,
+    ) -> Self {
+        Self {
+            owned_note_pub_id,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            input_state,
+            output_state: input_state.next(),
+        }
+    }
+}
+
+impl ValidityPredicateCircuit for CounterValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let add_chip = AddChip::<pallas::Base>::construct(config.add_config.clone(), ());
+        let sub_chip = SubChip::<pallas::Base>::construct(config.sub_config.clone(), ());
+        let mul_chip = MulChip::<pallas::Base>::construct(config.mul_config.clone());
+
+        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
+        let input_note = &basic_variables.input_note_variables[0].note_variables;
+        let output_note = &basic_variables.output_note_variables[0].note_variables;
+
+        let constant_zero = assign_free_constant(
+            layouter.namespace(|| "zero"),
+            config.advices[0],
+            pallas::Base::zero(),
+        )?;
+        let constant_one = assign_free_constant(
+            layouter.namespace(|| "one"),
+            config.advices[0],
+            pallas::Base::one(),
+        )?;
+
+        // The owned note, the first input and the first output are notes of the same counter
+        let owned_app_vk = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get owned note app_vk"),
+            &owned_note_pub_id,
+            &basic_variables.get_app_vk_searchable_pairs(),
+        )?;
+        layouter.assign_region(
+            || "check the counter notes",
+            |mut region| {
+                region.constrain_equal(owned_app_vk.cell(), input_note.app_vk.cell())?;
+                region.constrain_equal(input_note.app_vk.cell(), output_note.app_vk.cell())?;
+                region.constrain_equal(
+                    input_note.app_data_static.cell(),
+                    output_note.app_data_static.cell(),
+                )?;
+                region.constrain_equal(input_note.value.cell(), constant_one.cell())?;
+                region.constrain_equal(output_note.value.cell(), constant_one.cell())
+            },
+        )?;
+
+        // Decode the input counter state
+        let input_counter = assign_free_advice(
+            layouter.namespace(|| "witness input counter"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.input_state.counter)),
+        )?;
+        let is_init = assign_free_advice(
+            layouter.namespace(|| "witness is_init"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.input_state.is_init as u64)),
+        )?;
+        let encoded_input_state = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "input state encoding"),
+            [input_counter.clone(), is_init.clone()],
+        )?;
+
+        // Decode the output counter state, the output note is never a genesis note
+        let output_counter = assign_free_advice(
+            layouter.namespace(|| "witness output counter"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.output_state.counter)),
+        )?;
+        let encoded_output_state = poseidon_hash_gadget(
+            config.poseidon_config,
+            layouter.namespace(|| "output state encoding"),
+            [output_counter.clone(), constant_zero.clone()],
+        )?;
+
+        layouter.assign_region(
+            || "check the counter states",
+            |mut region| {
+                region.constrain_equal(
+                    encoded_input_state.cell(),
+                    input_note.app_data_dynamic.cell(),
+                )?;
+                region.constrain_equal(
+                    encoded_output_state.cell(),
+                    output_note.app_data_dynamic.cell(),
+                )
+            },
+        )?;
+
+        // is_init is boolean
+        let is_init_square = MulInstructions::mul(
+            &mul_chip,
+            layouter.namespace(|| "is_init * is_init"),
+            &is_init,
+            &is_init,
+        )?;
+        layouter.assign_region(
+            || "check is_init",
+            |mut region| region.constrain_equal(is_init_square.cell(), is_init.cell()),
+        )?;
+
+        // The genesis note must be ephemeral and have a zero counter:
+        // is_init * is_merkle_checked = 0 and is_init * input_counter = 0
+        let init_merkle_checked = MulInstructions::mul(
+            &mul_chip,
+            layouter.namespace(|| "is_init * is_merkle_checked"),
+            &is_init,
+            &input_note.is_merkle_checked,
+        )?;
+        let init_counter = MulInstructions::mul(
+            &mul_chip,
+            layouter.namespace(|| "is_init * input_counter"),
+            &is_init,
+            &input_counter,
+        )?;
+        layouter.assign_region(
+            || "check the genesis note",
+            |mut region| {
+                region.constrain_equal(init_merkle_checked.cell(), constant_zero.cell())?;
+                region.constrain_equal(init_counter.cell(), constant_zero.cell())
+            },
+        )?;
+
+        // output_counter = input_counter + 1 - is_init
+        let increment = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "1 - is_init"),
+            &constant_one,
+            &is_init,
+        )?;
+        let expected_output_counter = AddInstructions::add(
+            &add_chip,
+            layouter.namespace(|| "input_counter + increment"),
+            &input_counter,
+            &increment,
+        )?;
+        layouter.assign_region(
+            || "check the output counter",
+            |mut region| {
+                region.constrain_equal(expected_output_counter.cell(), output_counter.cell())
+            },
+        )?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+}
+
+vp_circuit_impl!(CounterValidityPredicateCircuit);
+vp_verifying_info_impl!(CounterValidityPredicateCircuit);
+
+// Create the ephemeral genesis note of the counter
+pub fn create_genesis_counter_note<R: RngCore>(
+    mut rng: R,
+    counter_id: pallas::Base,
+    rho: Nullifier,
+    nk: NullifierKeyContainer,
+) -> Note {
+    let rseed = RandomSeed::random(&mut rng);
+    Note::new(
+        *COMPRESSED_COUNTER_VK,
+        counter_id,
+        CounterState::genesis().encode(),
+        1u64,
+        nk,
+        rho,
+        false,
+        rseed,
+    )
+}
+
+pub fn create_counter_note<R: RngCore>(
+    mut rng: R,
+    counter_id: pallas::Base,
+    state: CounterState,
+    rho: Nullifier,
+    nk: NullifierKeyContainer,
+) -> Note {
+    let rseed = RandomSeed::random(&mut rng);
+    Note::new(
+        *COMPRESSED_COUNTER_VK,
+        counter_id,
+        state.encode(),
+        1u64,
+        nk,
+        rho,
+        true,
+        rseed,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter_circuit(
+        input_state: CounterState,
+        output_state: CounterState,
+    ) -> CounterValidityPredicateCircuit {
+        let mut rng = OsRng;
+        let counter_id = pallas::Base::random(&mut rng);
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let rho = Nullifier::from(pallas::Base::random(&mut rng));
+        let input_note = if input_state.is_init {
+            create_genesis_counter_note(&mut rng, counter_id, rho, nk)
+        } else {
+            create_counter_note(&mut rng, counter_id, input_state, rho, nk)
+        };
+        let output_note = create_counter_note(
+            &mut rng,
+            counter_id,
+            output_state,
+            input_note.get_nf().unwrap(),
+            nk,
+        );
+        let padding_input_note = Note::random_padding_input_note(&mut rng);
+        let padding_output_note =
+            Note::random_padding_output_note(&mut rng, padding_input_note.get_nf().unwrap());
+        CounterValidityPredicateCircuit {
+            owned_note_pub_id: output_note.commitment().inner(),
+            input_notes: Arc::new([input_note, padding_input_note]),
+            output_notes: Arc::new([output_note, padding_output_note]),
+            input_state,
+            output_state,
+        }
+    }
+
+    fn check(circuit: &CounterValidityPredicateCircuit) -> bool {
+        circuit.dev_prove_and_check(OsRng).is_ok()
+    }
+
+    #[test]
+    fn test_halo2_counter_vp_circuit() {
+        // genesis
+        let state = CounterState::genesis();
+        assert!(check(&counter_circuit(state, state.next())));
+
+        // increment
+        let state = CounterState::new(5);
+        assert!(check(&counter_circuit(state, state.next())));
+
+        // the owned note is the input note
+        let mut circuit = counter_circuit(state, state.next());
+        circuit.owned_note_pub_id = circuit.input_notes[0].get_nf().unwrap().inner();
+        assert!(check(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_counter_vp_circuit_jump() {
+        let state = CounterState::new(5);
+        assert!(!check(&counter_circuit(state, CounterState::new(7))));
+    }
+
+    #[test]
+    fn test_halo2_counter_vp_circuit_changed_app_vk() {
+        let state = CounterState::new(5);
+        let mut circuit = counter_circuit(state, state.next());
+        Arc::make_mut(&mut circuit.output_notes)[0].note_type.app_vk = pallas::Base::random(OsRng);
+        circuit.owned_note_pub_id = circuit.output_notes[0].commitment().inner();
+        assert!(!check(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_counter_vp_circuit_non_ephemeral_genesis() {
+        let mut circuit = counter_circuit(CounterState::genesis(), CounterState::new(0));
+        Arc::make_mut(&mut circuit.input_notes)[0].is_merkle_checked = true;
+        assert!(!check(&circuit));
+    }
+}
@@ -0,0 +1,535 @@
+/// The dutch auction intent, the ask price decays over time.
+/// For example, Alice sells 1 "monalisa" starting at 100 "eth", and the price decreases by 5 "eth"
+/// per time unit. At time t, any payment >= 100 - 5 * t is accepted, the first solver to pay the
+/// floor price gets the "monalisa".
+/// The current time is a custom public input, the executor checks it's the timestamp of the
+/// transaction, see `ExecutionContext::bind_timestamp`. The auction expires once the price would
+/// go below zero.
+///
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            assign_free_advice,
+            mul::{MulChip, MulInstructions},
+            sub::{SubChip, SubInstructions},
+            target_note_variable::get_owned_note_variables,
+        },
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::token::{Token, TokenAuthorization, TokenName, TokenNote, TOKEN_VK},
+    },
+    constant::{DUTCH_AUCTION_DOMAIN, NUM_NOTE, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX},
+    note::{
+        encoding::{StaticDataField, StaticDataSchema, StaticDataValue},
+        Note, RandomSeed,
+    },
+    nullifier::{Nullifier, NullifierKeyContainer},
+    proof::Proof,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_proofs::{
+    circuit::{floor_planner, AssignedCell, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::pallas;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// The current time is the first custom public input.
+pub const DUTCH_AUCTION_TIME_PUBLIC_INPUT_IDX: usize = VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX;
+
+// The differences are range checked in 7 words of 10 bits. 70 bits hold any u64 difference,
+// while an underflow wraps around the field and fails the check.
+const RANGE_CHECK_WORDS: usize = 7;
+
+lazy_static! {
+    pub static ref DUTCH_AUCTION_INTENT_VK: ValidityPredicateVerifyingKey =
+        DutchAuctionIntentValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_DUTCH_AUCTION_INTENT_VK: pallas::Base =
+        DUTCH_AUCTION_INTENT_VK.get_compressed();
+    // The app_data_static of auction notes: (sold token name, sold value, payment token name,
+    // start price, decay rate, start time, token vp vk, receiver nk_com, receiver app_data_dynamic)
+    pub static ref DUTCH_AUCTION_SCHEMA: StaticDataSchema = StaticDataSchema::new(
+        DUTCH_AUCTION_DOMAIN,
+        vec![
+            StaticDataField::Base,
+            StaticDataField::U64,
+            StaticDataField::Base,
+            StaticDataField::U64,
+            StaticDataField::U64,
+            StaticDataField::U64,
+            StaticDataField::Base,
+            StaticDataField::Base,
+            StaticDataField::Base,
+        ]
+    );
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DutchAuction {
+    pub sell: TokenNote,
+    pub payment: TokenName,
+    pub start_price: u64,
+    pub decay_rate: u64,
+    pub start_time: u64,
+    pub auth: TokenAuthorization,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DutchAuctionIntentValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    pub auction: DutchAuction,
+    pub current_time: u64,
+}
+
+impl DutchAuction {
+    pub fn random(
+        mut rng: impl RngCore,
+        sell: Token,
+        payment: TokenName,
+        start_price: u64,
+        decay_rate: u64,
+        start_time: u64,
+        auth: TokenAuthorization,
+    ) -> Self {
+        let sell = {
+            let rho = Nullifier::random(&mut rng);
+            let nk = NullifierKeyContainer::random_key(&mut rng);
+            sell.create_random_token_note(&mut rng, rho, nk, &auth)
+        };
+
+        Self {
+            sell,
+            payment,
+            start_price,
+            decay_rate,
+            start_time,
+            auth,
+        }
+    }
+
+    // The lowest accepted payment at the time, None if the auction hasn't started or has expired
+    pub fn floor_price(&self, time: u64) -> Option<u64> {
+        let elapsed = time.checked_sub(self.start_time)?;
+        let decay = self.decay_rate.checked_mul(elapsed)?;
+        self.start_price.checked_sub(decay)
+    }
+
+    pub fn to_static_data_values(&self) -> Vec<StaticDataValue> {
+        vec![
+            StaticDataValue::Base(self.sell.encode_name()),
+            StaticDataValue::U64(self.sell.note().value),
+            StaticDataValue::Base(self.payment.encode()),
+            StaticDataValue::U64(self.start_price),
+            StaticDataValue::U64(self.decay_rate),
+            StaticDataValue::U64(self.start_time),
+            // The sold and payment tokens have the same TOKEN_VK
+            StaticDataValue::Base(TOKEN_VK.get_compressed()),
+            StaticDataValue::Base(self.sell.note().get_nk_commitment()),
+            StaticDataValue::Base(self.sell.note().app_data_dynamic),
+        ]
+    }
+
+    pub fn encode_app_data_static(&self) -> pallas::Base {
+        DUTCH_AUCTION_SCHEMA
+            .encode(&self.to_static_data_values())
+            .unwrap()
+    }
+
+    pub fn create_intent_note<R: RngCore>(&self, mut rng: R) -> Note {
+        let rseed = RandomSeed::random(&mut rng);
+
+        Note::new(
+            *COMPRESSED_DUTCH_AUCTION_INTENT_VK,
+            self.encode_app_data_static(),
+            self.auth.to_app_data_dynamic(),
+            1u64,
+            self.sell.note().nk_container,
+            self.sell.note().get_nf().unwrap(),
+            false,
+            rseed,
+        )
+    }
+
+    /// Fills the auction with the payment to the creator. The sold token is routed to the buyer
+    /// in another ptx, the transaction balance enforces it.
+    pub fn fill(
+        &self,
+        mut rng: impl RngCore,
+        intent_note: Note,
+        payment_value: u64,
+    ) -> ([Note; NUM_NOTE], [Note; NUM_NOTE]) {
+        let payment_note = Token::new(self.payment.clone(), payment_value)
+            .create_random_token_note(
+                &mut rng,
+                intent_note.get_nf().unwrap(),
+                self.sell.note().nk_container,
+                &self.auth,
+            );
+
+        let input_padding_note = Note::random_padding_input_note(&mut rng);
+        let output_padding_note =
+            Note::random_padding_output_note(&mut rng, input_padding_note.get_nf().unwrap());
+
+        (
+            [intent_note, input_padding_note],
+            [*payment_note.note(), output_padding_note],
+        )
+    }
+}
+
+impl DutchAuctionIntentValidityPredicateCircuit {
+    fn assign_app_data_static(
+        &self,
+        config: &ValidityPredicateConfig,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<Vec<AssignedCell<pallas::Base, pallas::Base>>, Error> {
+        DUTCH_AUCTION_SCHEMA
+            .to_field_elements(&self.auction.to_static_data_values())
+            .unwrap()
+            .into_iter()
+            .map(|element| {
+                assign_free_advice(
+                    layouter.namespace(|| "witness app_data_static element"),
+                    config.advices[0],
+                    Value::known(element),
+                )
+            })
+            .collect()
+    }
+
+    fn range_check(
+        config: &ValidityPredicateConfig,
+        layouter: impl Layouter<pallas::Base>,
+        element: &AssignedCell<pallas::Base, pallas::Base>,
+    ) -> Result<(), Error> {
+        config
+            .range_check
+            .copy_check(layouter, element.clone(), RANGE_CHECK_WORDS, true)?;
+        Ok(())
+    }
+}
+
+impl ValidityPredicateCircuit for DutchAuctionIntentValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+        let mul_chip = MulChip::construct(config.mul_config.clone());
+
+        let app_data_static =
+            self.assign_app_data_static(&config, layouter.namespace(|| "assign app_data_static"))?;
+        // The elements in the schema order
+        let sold_token = &app_data_static[0];
+        let sold_value = &app_data_static[1];
+        let payment_token = &app_data_static[2];
+        let start_price = &app_data_static[3];
+        let decay_rate = &app_data_static[4];
+        let start_time = &app_data_static[5];
+        let token_vp_vk = &app_data_static[6];
+        let receiver_nk_com = &app_data_static[7];
+        let receiver_app_data_dynamic = &app_data_static[8];
+        let encoded_app_data_static = DUTCH_AUCTION_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "encode app_data_static"),
+            &app_data_static,
+        )?;
+
+        let owned_note = get_owned_note_variables(
+            &config,
+            layouter.namespace(|| "get owned note variables"),
+            &basic_variables,
+        )?;
+        layouter.assign_region(
+            || "check app_data_static",
+            |mut region| {
+                region.constrain_equal(
+                    encoded_app_data_static.cell(),
+                    owned_note.note_variables.app_data_static.cell(),
+                )
+            },
+        )?;
+
+        // Create the auction: the sold note is the input note
+        let sold_note = &basic_variables.input_note_variables[0].note_variables;
+        let checks = [
+            ("sold token vk", token_vp_vk, &sold_note.app_vk),
+            ("sold token", sold_token, &sold_note.app_data_static),
+            ("sold value", sold_value, &sold_note.value),
+        ];
+        for (name, expected, actual) in checks {
+            config.conditional_config.assert_equal_if(
+                layouter.namespace(|| format!("check {name}")),
+                &owned_note.is_output_note,
+                expected,
+                actual,
+            )?;
+        }
+
+        // Fill the auction: the payment note goes to the creator
+        let is_fill = owned_note.is_input_note;
+        let payment_note = &basic_variables.output_note_variables[0].note_variables;
+        let checks = [
+            ("payment token vk", token_vp_vk, &payment_note.app_vk),
+            (
+                "payment token",
+                payment_token,
+                &payment_note.app_data_static,
+            ),
+            ("payment nk_com", receiver_nk_com, &payment_note.nk_com),
+            (
+                "payment app_data_dynamic",
+                receiver_app_data_dynamic,
+                &payment_note.app_data_dynamic,
+            ),
+        ];
+        for (name, expected, actual) in checks {
+            config.conditional_config.assert_equal_if(
+                layouter.namespace(|| format!("check {name}")),
+                &is_fill,
+                expected,
+                actual,
+            )?;
+        }
+
+        // The current time is published in the custom public inputs
+        let current_time = assign_free_advice(
+            layouter.namespace(|| "witness current time"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.current_time)),
+        )?;
+        layouter.constrain_instance(
+            current_time.cell(),
+            config.instances,
+            DUTCH_AUCTION_TIME_PUBLIC_INPUT_IDX,
+        )?;
+
+        // The price checks only apply to the fill: when the auction is created, the elapsed time
+        // and the payment margin are both selected to be zero.
+        let current_time = config.conditional_config.select(
+            layouter.namespace(|| "select current time"),
+            &is_fill,
+            &current_time,
+            start_time,
+        )?;
+        let elapsed = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "current_time - start_time"),
+            &current_time,
+            start_time,
+        )?;
+        Self::range_check(
+            &config,
+            layouter.namespace(|| "check the auction has started"),
+            &elapsed,
+        )?;
+        let decay = MulInstructions::mul(
+            &mul_chip,
+            layouter.namespace(|| "decay_rate * elapsed"),
+            decay_rate,
+            &elapsed,
+        )?;
+        let floor_price = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "start_price - decay"),
+            start_price,
+            &decay,
+        )?;
+        Self::range_check(
+            &config,
+            layouter.namespace(|| "check the auction has not expired"),
+            &floor_price,
+        )?;
+        let payment_value = config.conditional_config.select(
+            layouter.namespace(|| "select payment value"),
+            &is_fill,
+            &payment_note.value,
+            &floor_price,
+        )?;
+        let margin = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "payment_value - floor_price"),
+            &payment_value,
+            &floor_price,
+        )?;
+        Self::range_check(
+            &config,
+            layouter.namespace(|| "check payment >= floor price"),
+            &margin,
+        )?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        public_inputs.push(pallas::Base::from(self.current_time));
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+}
+
+vp_circuit_impl!(DutchAuctionIntentValidityPredicateCircuit);
+vp_verifying_info_impl!(DutchAuctionIntentValidityPredicateCircuit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::vp_examples::signature_verification::COMPRESSED_TOKEN_AUTH_VK;
+    use halo2_proofs::arithmetic::Field;
+    use rand::rngs::OsRng;
+
+    // Alice sells 1 monalisa, starting at 100 eth and decaying 5 eth per time unit from t = 0
+    fn auction(mut rng: impl RngCore) -> DutchAuction {
+        let sk = pallas::Scalar::random(&mut rng);
+        let auth = TokenAuthorization::from_sk_vk(&sk, &COMPRESSED_TOKEN_AUTH_VK);
+        DutchAuction::random(
+            &mut rng,
+            Token::new(TokenName::new("monalisa").unwrap(), 1u64),
+            TokenName::new("eth").unwrap(),
+            100,
+            5,
+            0,
+            auth,
+        )
+    }
+
+    fn fill_circuit(
+        mut rng: impl RngCore,
+        auction: &DutchAuction,
+        current_time: u64,
+        payment_value: u64,
+    ) -> DutchAuctionIntentValidityPredicateCircuit {
+        let intent_note = auction.create_intent_note(&mut rng);
+        let (input_notes, output_notes) = auction.fill(&mut rng, intent_note, payment_value);
+        DutchAuctionIntentValidityPredicateCircuit {
+            owned_note_pub_id: intent_note.get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            auction: auction.clone(),
+            current_time,
+        }
+    }
+
+    #[test]
+    fn test_floor_price() {
+        let auction = auction(OsRng);
+        assert_eq!(auction.floor_price(0), Some(100));
+        assert_eq!(auction.floor_price(10), Some(50));
+        assert_eq!(auction.floor_price(20), Some(0));
+        assert_eq!(auction.floor_price(21), None);
+        assert_eq!(auction.floor_price(u64::MAX), None);
+
+        let late_auction = DutchAuction {
+            start_time: 10,
+            ..auction
+        };
+        assert_eq!(late_auction.floor_price(5), None);
+    }
+
+    #[test]
+    fn test_create_auction() {
+        let mut rng = OsRng;
+        let auction = auction(&mut rng);
+        let intent_note = auction.create_intent_note(&mut rng);
+
+        let input_padding_note = Note::random_padding_input_note(&mut rng);
+        let output_padding_note =
+            Note::random_padding_output_note(&mut rng, input_padding_note.get_nf().unwrap());
+        let circuit = DutchAuctionIntentValidityPredicateCircuit {
+            owned_note_pub_id: intent_note.commitment().inner(),
+            input_notes: Arc::new([*auction.sell.note(), input_padding_note]),
+            output_notes: Arc::new([intent_note, output_padding_note]),
+            auction,
+            current_time: 0,
+        };
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+    }
+
+    #[test]
+    fn test_fill_auction() {
+        let mut rng = OsRng;
+        let auction = auction(&mut rng);
+
+        // Exactly at the floor price
+        let circuit = fill_circuit(&mut rng, &auction, 10, 50);
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+        // Above the floor price
+        let circuit = fill_circuit(&mut rng, &auction, 10, 60);
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+        // Below the floor price
+        let circuit = fill_circuit(&mut rng, &auction, 10, 49);
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+
+        // The published time is not the one used for the price
+        let circuit = fill_circuit(&mut rng, &auction, 10, 50);
+        let mut public_inputs = circuit.get_public_inputs(&mut rng).to_vec();
+        public_inputs[DUTCH_AUCTION_TIME_PUBLIC_INPUT_IDX] = pallas::Base::from(12u64);
+        assert!(circuit
+            .dev_prove_and_check_with_public_inputs(&public_inputs.into())
+            .is_err());
+    }
+
+    #[test]
+    fn test_fill_expired_auction() {
+        let mut rng = OsRng;
+        let auction = auction(&mut rng);
+
+        // The decay exceeds the start price, the floor price underflows
+        assert_eq!(auction.floor_price(21), None);
+        let circuit = fill_circuit(&mut rng, &auction, 21, 0);
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+        let circuit = fill_circuit(&mut rng, &auction, 21, 100);
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+
+        // The auction hasn't started
+        let late_auction = DutchAuction {
+            start_time: 10,
+            ..auction
+        };
+        let circuit = fill_circuit(&mut rng, &late_auction, 5, 100);
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+    }
+}
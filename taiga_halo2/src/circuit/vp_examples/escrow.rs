@@ -0,0 +1,900 @@
+/// The escrow application, Alice locks a token for Bob and an arbiter resolves the disputes.
+/// The locked token is authorized by the escrow VP, for the spend and the creation, under Alice's
+/// pk. A companion escrow note of value 0 is created with it: the app_data_static encodes the
+/// terms (alice pk, bob pk, arbiter pk, timeout height, amount) and the app_data_dynamic is the
+/// commitment of the locked token. The same circuit is the application VP of the escrow notes.
+/// The notes are in fixed positions: the first input/output is the token note, the second
+/// input/output is the escrow note.
+/// - Lock: the notes are created, signed by Alice.
+/// - Release: the token goes to Bob, signed by Alice.
+/// - Refund: the token goes back to Alice, signed by Bob.
+/// - Arbitrate: the token goes to either of them, signed by the arbiter after the timeout height.
+/// The height is a custom public input, the verifier checks it against the height of the block,
+/// see `ExecutionContext`.
+///
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            assign_free_advice, assign_free_constant,
+            comparison::is_greater_or_equal_64,
+            sub::{SubChip, SubInstructions},
+            target_note_variable::get_owned_note_variables,
+        },
+        integrity::check_value_range,
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::{
+            receiver_vp::COMPRESSED_RECEIVER_VK,
+            signature_verification::{
+                schnorr_challenge_gadget, signing_message, signing_message_gadget,
+                SchnorrSignature, COMPRESSED_TOKEN_AUTH_VK, SIGNED_ANCHORS_PUBLIC_INPUT_IDX,
+            },
+            token::{Token, TokenAuthorization, TokenName, TokenNote, TOKEN_AUTHORIZATION_SCHEMA},
+        },
+    },
+    constant::{
+        TaigaFixedBasesFull, ESCROW_DOMAIN, NUM_NOTE, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX,
+    },
+    keys::SigningKey,
+    merkle_tree::Anchor,
+    note::{
+        encoding::{StaticDataField, StaticDataSchema, StaticDataValue},
+        Note, RandomSeed,
+    },
+    nullifier::{Nullifier, NullifierKeyContainer},
+    proof::Proof,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_gadgets::ecc::{chip::EccChip, FixedPoint, NonIdentityPoint, ScalarFixed};
+use halo2_proofs::{
+    arithmetic::Field,
+    circuit::{floor_planner, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::{
+    group::{Curve, Group},
+    pallas,
+};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// The height follows the anchors in the custom public inputs.
+pub const ESCROW_HEIGHT_PUBLIC_INPUT_IDX: usize =
+    VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX + NUM_NOTE;
+
+lazy_static! {
+    pub static ref ESCROW_VK: ValidityPredicateVerifyingKey =
+        EscrowValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_ESCROW_VK: pallas::Base = ESCROW_VK.get_compressed();
+    // The app_data_static of escrow notes: (alice pk, bob pk, arbiter pk, timeout height, amount)
+    pub static ref ESCROW_SCHEMA: StaticDataSchema = StaticDataSchema::new(
+        ESCROW_DOMAIN,
+        vec![
+            StaticDataField::Point,
+            StaticDataField::Point,
+            StaticDataField::Point,
+            StaticDataField::U64,
+            StaticDataField::U64,
+        ]
+    );
+}
+
+// The terms of an escrow, encoded in the app_data_static of the escrow notes
+#[derive(Clone, Copy, Debug)]
+pub struct EscrowTerms {
+    // Locks the token, releases it to Bob and receives the refunds
+    pub alice_pk: pallas::Point,
+    // Refunds Alice and receives the token
+    pub bob_pk: pallas::Point,
+    // Resolves the disputes after the timeout
+    pub arbiter_pk: pallas::Point,
+    // The arbiter can only act after this height
+    pub timeout_height: u64,
+    // The value of the locked token
+    pub amount: u64,
+}
+
+impl Default for EscrowTerms {
+    fn default() -> Self {
+        Self {
+            alice_pk: pallas::Point::generator(),
+            bob_pk: pallas::Point::generator(),
+            arbiter_pk: pallas::Point::generator(),
+            timeout_height: 0,
+            amount: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EscrowAction {
+    // Create the escrow
+    #[default]
+    Lock,
+    // Pay Bob
+    Release,
+    // Pay Alice back
+    Refund,
+    // Pay Bob or Alice after the timeout
+    Arbitrate {
+        to_bob: bool,
+    },
+}
+
+impl EscrowTerms {
+    pub fn new(
+        alice_pk: pallas::Point,
+        bob_pk: pallas::Point,
+        arbiter_pk: pallas::Point,
+        timeout_height: u64,
+        amount: u64,
+    ) -> Self {
+        Self {
+            alice_pk,
+            bob_pk,
+            arbiter_pk,
+            timeout_height,
+            amount,
+        }
+    }
+
+    pub fn to_static_data_values(&self) -> Vec<StaticDataValue> {
+        vec![
+            StaticDataValue::Point(self.alice_pk),
+            StaticDataValue::Point(self.bob_pk),
+            StaticDataValue::Point(self.arbiter_pk),
+            StaticDataValue::U64(self.timeout_height),
+            StaticDataValue::U64(self.amount),
+        ]
+    }
+
+    pub fn encode_app_data_static(&self) -> pallas::Base {
+        ESCROW_SCHEMA.encode(&self.to_static_data_values()).unwrap()
+    }
+
+    // The app_data_dynamic of the locked token: the escrow vp is both the auth vp and the receiver
+    // vp under Alice's pk, it's also the rcv pk.
+    pub fn locked_token_app_data_dynamic(&self) -> pallas::Base {
+        TOKEN_AUTHORIZATION_SCHEMA
+            .encode(&[
+                StaticDataValue::Point(self.alice_pk),
+                StaticDataValue::Base(*COMPRESSED_ESCROW_VK),
+                StaticDataValue::Base(*COMPRESSED_ESCROW_VK),
+                StaticDataValue::Point(self.alice_pk),
+            ])
+            .unwrap()
+    }
+
+    // The auth of the locked token in the token vp, the receiver vp vk is also the escrow vk
+    pub fn locked_token_authorization(&self) -> TokenAuthorization {
+        TokenAuthorization {
+            pk: self.alice_pk,
+            vk: *COMPRESSED_ESCROW_VK,
+            rcv_pk: self.alice_pk,
+        }
+    }
+
+    // The key signing the action
+    pub fn signer(&self, action: EscrowAction) -> pallas::Point {
+        match action {
+            EscrowAction::Lock | EscrowAction::Release => self.alice_pk,
+            EscrowAction::Refund => self.bob_pk,
+            EscrowAction::Arbitrate { .. } => self.arbiter_pk,
+        }
+    }
+
+    // The auth pk of the paid token, None for the lock
+    pub fn payee(&self, action: EscrowAction) -> Option<pallas::Point> {
+        match action {
+            EscrowAction::Lock => None,
+            EscrowAction::Release | EscrowAction::Arbitrate { to_bob: true } => Some(self.bob_pk),
+            EscrowAction::Refund | EscrowAction::Arbitrate { to_bob: false } => Some(self.alice_pk),
+        }
+    }
+
+    // Whether the arbiter can act at the height
+    pub fn can_arbitrate(&self, height: u64) -> bool {
+        height > self.timeout_height
+    }
+
+    // Create the locked token note of value amount
+    pub fn create_locked_token_note<R: RngCore>(
+        &self,
+        mut rng: R,
+        token_name: TokenName,
+        rho: Nullifier,
+        nk: NullifierKeyContainer,
+    ) -> TokenNote {
+        let token = Token::new(token_name, self.amount);
+        let note_type = token.note_type();
+        let rseed = RandomSeed::random(&mut rng);
+        let note = Note::new(
+            note_type.app_vk,
+            note_type.app_data_static,
+            self.locked_token_app_data_dynamic(),
+            self.amount,
+            nk,
+            rho,
+            true,
+            rseed,
+        );
+        TokenNote {
+            token_name: token.name().clone(),
+            note,
+        }
+    }
+
+    // Create the escrow note of the locked token
+    pub fn create_escrow_note<R: RngCore>(
+        &self,
+        mut rng: R,
+        locked_token_cm: pallas::Base,
+        rho: Nullifier,
+        nk: NullifierKeyContainer,
+    ) -> Note {
+        let rseed = RandomSeed::random(&mut rng);
+        Note::new(
+            *COMPRESSED_ESCROW_VK,
+            self.encode_app_data_static(),
+            locked_token_cm,
+            0u64,
+            nk,
+            rho,
+            true,
+            rseed,
+        )
+    }
+}
+
+// EscrowValidityPredicateCircuit
+#[derive(Clone, Debug, Default)]
+pub struct EscrowValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    // The anchors of the input notes, they're signed and publicized as the custom public inputs
+    pub anchors: [Anchor; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    pub terms: EscrowTerms,
+    pub action: EscrowAction,
+    // The height of the transaction
+    pub height: u64,
+    // Signed by the signer of the action, see `EscrowTerms::signer`
+    pub signature: SchnorrSignature,
+}
+
+impl EscrowValidityPredicateCircuit {
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_sk_and_sign<R: RngCore>(
+        mut rng: R,
+        owned_note_pub_id: pallas::Base,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        output_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        terms: EscrowTerms,
+        action: EscrowAction,
+        height: u64,
+        sk: &SigningKey,
+    ) -> Self {
+        let input_notes: Arc<[Note; NUM_NOTE]> = input_notes.into();
+        let output_notes: Arc<[Note; NUM_NOTE]> = output_notes.into();
+        let message = signing_message(&anchors, owned_note_pub_id, &input_notes, &output_notes);
+        let signature = SchnorrSignature::sign(&mut rng, sk, message);
+        Self {
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            terms,
+            action,
+            height,
+            signature,
+        }
+    }
+}
+
+impl ValidityPredicateCircuit for EscrowValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
+
+        let owned_note = get_owned_note_variables(
+            &config,
+            layouter.namespace(|| "get owned note variables"),
+            &basic_variables,
+        )?;
+        // The notes are spent in the settlement and created in the lock
+        let is_settle = owned_note.is_input_note;
+
+        let input_token = &basic_variables.input_note_variables[0];
+        let input_escrow = &basic_variables.input_note_variables[1];
+        let output_token = &basic_variables.output_note_variables[0];
+        let output_escrow = &basic_variables.output_note_variables[1];
+
+        let constant_one = assign_free_constant(
+            layouter.namespace(|| "one"),
+            config.advices[0],
+            pallas::Base::one(),
+        )?;
+
+        // Select the locked token and the escrow note: (token cm, token value, token
+        // app_data_dynamic, escrow app_vk, escrow app_data_static, escrow app_data_dynamic)
+        let locked = [
+            (&input_token.cm, &output_token.cm),
+            (
+                &input_token.note_variables.value,
+                &output_token.note_variables.value,
+            ),
+            (
+                &input_token.note_variables.app_data_dynamic,
+                &output_token.note_variables.app_data_dynamic,
+            ),
+            (
+                &input_escrow.note_variables.app_vk,
+                &output_escrow.note_variables.app_vk,
+            ),
+            (
+                &input_escrow.note_variables.app_data_static,
+                &output_escrow.note_variables.app_data_static,
+            ),
+            (
+                &input_escrow.note_variables.app_data_dynamic,
+                &output_escrow.note_variables.app_data_dynamic,
+            ),
+        ]
+        .into_iter()
+        .map(|(input, output)| {
+            config.conditional_config.select(
+                layouter.namespace(|| "select locked note variable"),
+                &is_settle,
+                input,
+                output,
+            )
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+        let (token_cm, token_value, token_auth, escrow_vk, escrow_terms, escrow_token_cm) = (
+            &locked[0], &locked[1], &locked[2], &locked[3], &locked[4], &locked[5],
+        );
+
+        // Decode the terms: (alice_x, alice_y, bob_x, bob_y, arbiter_x, arbiter_y, timeout, amount)
+        let terms = ESCROW_SCHEMA
+            .to_field_elements(&self.terms.to_static_data_values())
+            .unwrap()
+            .into_iter()
+            .map(|element| {
+                assign_free_advice(
+                    layouter.namespace(|| "witness terms element"),
+                    config.advices[0],
+                    Value::known(element),
+                )
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let encoded_terms = ESCROW_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "terms encoding"),
+            &terms,
+        )?;
+        let (alice_x, alice_y, bob_x, bob_y, arbiter_x, arbiter_y, timeout, amount) = (
+            &terms[0], &terms[1], &terms[2], &terms[3], &terms[4], &terms[5], &terms[6], &terms[7],
+        );
+        check_value_range(
+            layouter.namespace(|| "range check timeout"),
+            &config.range_check,
+            timeout,
+        )?;
+
+        // The token is locked under Alice's pk, the escrow vk is both its auth and receiver vp
+        let encoded_locked_auth = TOKEN_AUTHORIZATION_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "locked token authorization encoding"),
+            &[
+                alice_x.clone(),
+                alice_y.clone(),
+                escrow_vk.clone(),
+                escrow_vk.clone(),
+                alice_x.clone(),
+                alice_y.clone(),
+            ],
+        )?;
+        layouter.assign_region(
+            || "check the escrow",
+            |mut region| {
+                region.constrain_equal(encoded_terms.cell(), escrow_terms.cell())?;
+                region.constrain_equal(escrow_token_cm.cell(), token_cm.cell())?;
+                region.constrain_equal(encoded_locked_auth.cell(), token_auth.cell())?;
+                region.constrain_equal(amount.cell(), token_value.cell())
+            },
+        )?;
+
+        // The flags are boolean checked in the conditional gates, is_arbitrate is not boolean
+        // if more than one action is set or if an action is set in the lock.
+        let is_release = assign_free_advice(
+            layouter.namespace(|| "witness is_release"),
+            config.advices[0],
+            Value::known(pallas::Base::from(
+                (self.action == EscrowAction::Release) as u64,
+            )),
+        )?;
+        let is_refund = assign_free_advice(
+            layouter.namespace(|| "witness is_refund"),
+            config.advices[0],
+            Value::known(pallas::Base::from(
+                (self.action == EscrowAction::Refund) as u64,
+            )),
+        )?;
+        let to_bob = assign_free_advice(
+            layouter.namespace(|| "witness to_bob"),
+            config.advices[0],
+            Value::known(pallas::Base::from(
+                (self.action == EscrowAction::Arbitrate { to_bob: true }) as u64,
+            )),
+        )?;
+        let is_arbitrate = {
+            let not_release = SubInstructions::sub(
+                &sub_chip,
+                layouter.namespace(|| "is_settle - is_release"),
+                &is_settle,
+                &is_release,
+            )?;
+            SubInstructions::sub(
+                &sub_chip,
+                layouter.namespace(|| "is_settle - is_release - is_refund"),
+                &not_release,
+                &is_refund,
+            )?
+        };
+
+        // The arbiter acts after the timeout: timeout >= height is false
+        let height = assign_free_advice(
+            layouter.namespace(|| "witness height"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.height)),
+        )?;
+        check_value_range(
+            layouter.namespace(|| "range check height"),
+            &config.range_check,
+            &height,
+        )?;
+        layouter.constrain_instance(
+            height.cell(),
+            config.instances,
+            ESCROW_HEIGHT_PUBLIC_INPUT_IDX,
+        )?;
+        let before_timeout = is_greater_or_equal_64(
+            layouter.namespace(|| "timeout >= height"),
+            &config.comparison_config,
+            timeout,
+            &height,
+        )?;
+        config.conditional_config.assert_zero_if(
+            layouter.namespace(|| "arbitrate after the timeout"),
+            &is_arbitrate,
+            &before_timeout,
+        )?;
+
+        // The settlement pays the locked token to the payee under the default token auth, the
+        // payee pk is also the rcv pk: Bob for a release, Alice for a refund and either of them
+        // for an arbitration.
+        let mut payee = vec![];
+        for (alice, bob) in [(alice_x, bob_x), (alice_y, bob_y)] {
+            let released = config.conditional_config.select(
+                layouter.namespace(|| "select release payee"),
+                &is_release,
+                bob,
+                alice,
+            )?;
+            let arbitrated = config.conditional_config.select(
+                layouter.namespace(|| "select arbitration payee"),
+                &to_bob,
+                bob,
+                alice,
+            )?;
+            payee.push(config.conditional_config.select(
+                layouter.namespace(|| "select payee"),
+                &is_arbitrate,
+                &arbitrated,
+                &released,
+            )?);
+        }
+        let auth_vp_vk = assign_free_constant(
+            layouter.namespace(|| "token auth vp vk"),
+            config.advices[0],
+            *COMPRESSED_TOKEN_AUTH_VK,
+        )?;
+        let receiver_vp_vk = assign_free_constant(
+            layouter.namespace(|| "receiver vp vk"),
+            config.advices[0],
+            *COMPRESSED_RECEIVER_VK,
+        )?;
+        let encoded_payee_auth = TOKEN_AUTHORIZATION_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "payee authorization encoding"),
+            &[
+                payee[0].clone(),
+                payee[1].clone(),
+                auth_vp_vk,
+                receiver_vp_vk,
+                payee[0].clone(),
+                payee[1].clone(),
+            ],
+        )?;
+        let payout = &output_token.note_variables;
+        for (a, b) in [
+            (&payout.app_data_dynamic, &encoded_payee_auth),
+            (&payout.app_vk, &input_token.note_variables.app_vk),
+            (
+                &payout.app_data_static,
+                &input_token.note_variables.app_data_static,
+            ),
+            (&payout.value, &input_token.note_variables.value),
+            // The spent escrow note was created in a lock
+            (
+                &input_escrow.note_variables.is_merkle_checked,
+                &constant_one,
+            ),
+        ] {
+            config.conditional_config.assert_equal_if(
+                layouter.namespace(|| "check the settlement"),
+                &is_settle,
+                a,
+                b,
+            )?;
+        }
+
+        // The signer is Bob for a refund, the arbiter for an arbitration and Alice otherwise
+        let mut signer = vec![];
+        for (alice, bob, arbiter) in [(alice_x, bob_x, arbiter_x), (alice_y, bob_y, arbiter_y)] {
+            let refunded = config.conditional_config.select(
+                layouter.namespace(|| "select refund signer"),
+                &is_refund,
+                bob,
+                alice,
+            )?;
+            signer.push(config.conditional_config.select(
+                layouter.namespace(|| "select signer"),
+                &is_arbitrate,
+                arbiter,
+                &refunded,
+            )?);
+        }
+        let pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness pk"),
+            Value::known(self.signature.pk.to_affine()),
+        )?;
+        layouter.assign_region(
+            || "check the signer",
+            |mut region| {
+                region.constrain_equal(pk.inner().x().cell(), signer[0].cell())?;
+                region.constrain_equal(pk.inner().y().cell(), signer[1].cell())
+            },
+        )?;
+
+        let r = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness r"),
+            Value::known(self.signature.r.to_affine()),
+        )?;
+        let s_scalar = ScalarFixed::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness s"),
+            Value::known(self.signature.s),
+        )?;
+
+        // Verify: s*G = R + Hash(r||P||m)*P
+        let generator = FixedPoint::from_inner(ecc_chip, TaigaFixedBasesFull::BaseGenerator);
+        let (s_g, _) = generator.mul(layouter.namespace(|| "s_scalar * generator"), &s_scalar)?;
+        let message = signing_message_gadget(
+            &config,
+            layouter.namespace(|| "signing message"),
+            &basic_variables,
+            &self.anchors,
+        )?;
+        let h_scalar = schnorr_challenge_gadget(
+            &config,
+            layouter.namespace(|| "schnorr challenge"),
+            &r,
+            &pk,
+            &message,
+        )?;
+        let (h_p, _) = pk.mul(layouter.namespace(|| "hP"), h_scalar)?;
+        let rhs = r.add(layouter.namespace(|| "R + Hash(r||P||m)*P"), &h_p)?;
+        s_g.constrain_equal(layouter.namespace(|| "s*G = R + Hash(r||P||m)*P"), &rhs)?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(self.anchors.map(|anchor| anchor.inner()));
+        public_inputs.push(pallas::Base::from(self.height));
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+
+    fn get_signed_anchors_public_input_idx(&self) -> Option<usize> {
+        Some(SIGNED_ANCHORS_PUBLIC_INPUT_IDX)
+    }
+}
+
+vp_circuit_impl!(EscrowValidityPredicateCircuit);
+vp_verifying_info_impl!(EscrowValidityPredicateCircuit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::tests::random_output_note;
+
+    const TIMEOUT: u64 = 100;
+
+    struct Parties {
+        alice_sk: SigningKey,
+        bob_sk: SigningKey,
+        arbiter_sk: SigningKey,
+        terms: EscrowTerms,
+    }
+
+    fn parties() -> Parties {
+        let alice_sk = SigningKey::random(OsRng);
+        let bob_sk = SigningKey::random(OsRng);
+        let arbiter_sk = SigningKey::random(OsRng);
+        let terms = EscrowTerms::new(
+            alice_sk.public_key(),
+            bob_sk.public_key(),
+            arbiter_sk.public_key(),
+            TIMEOUT,
+            5,
+        );
+        Parties {
+            alice_sk,
+            bob_sk,
+            arbiter_sk,
+            terms,
+        }
+    }
+
+    // Lock the token, the escrow note owns the vp
+    fn lock_circuit(terms: EscrowTerms, sk: &SigningKey) -> EscrowValidityPredicateCircuit {
+        let mut rng = OsRng;
+        let input_notes = [(); NUM_NOTE].map(|_| Note::random_padding_input_note(&mut rng));
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let token_note = terms.create_locked_token_note(
+            &mut rng,
+            TokenName::new("btc").unwrap(),
+            input_notes[0].get_nf().unwrap(),
+            nk,
+        );
+        let escrow_note = terms.create_escrow_note(
+            &mut rng,
+            token_note.note().commitment().inner(),
+            input_notes[1].get_nf().unwrap(),
+            nk,
+        );
+        let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+        EscrowValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            escrow_note.commitment().inner(),
+            anchors,
+            input_notes,
+            [*token_note.note(), escrow_note],
+            terms,
+            EscrowAction::Lock,
+            50,
+            sk,
+        )
+    }
+
+    // Settle the escrow at the height, paying the token to the payee
+    fn settle_circuit(
+        terms: EscrowTerms,
+        action: EscrowAction,
+        payee: pallas::Point,
+        height: u64,
+        sk: &SigningKey,
+    ) -> EscrowValidityPredicateCircuit {
+        let mut rng = OsRng;
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let token_note = terms.create_locked_token_note(
+            &mut rng,
+            TokenName::new("btc").unwrap(),
+            Nullifier::random(&mut rng),
+            nk,
+        );
+        let escrow_note = terms.create_escrow_note(
+            &mut rng,
+            token_note.note().commitment().inner(),
+            Nullifier::random(&mut rng),
+            nk,
+        );
+        let payee_auth = TokenAuthorization::new(payee, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+        let payout_note = Token::new(TokenName::new("btc").unwrap(), terms.amount)
+            .create_random_token_note(
+                &mut rng,
+                token_note.get_nf().unwrap(),
+                NullifierKeyContainer::random_commitment(&mut rng),
+                &payee_auth,
+            );
+        let input_notes = [*token_note.note(), escrow_note];
+        let output_notes = [
+            *payout_note.note(),
+            random_output_note(&mut rng, escrow_note.get_nf().unwrap()),
+        ];
+        let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+        EscrowValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            token_note.get_nf().unwrap().inner(),
+            anchors,
+            input_notes,
+            output_notes,
+            terms,
+            action,
+            height,
+            sk,
+        )
+    }
+
+    fn check(circuit: &EscrowValidityPredicateCircuit) -> bool {
+        circuit.dev_prove_and_check(OsRng).is_ok()
+    }
+
+    #[test]
+    fn test_escrow_terms() {
+        let Parties { terms, .. } = parties();
+        assert_eq!(terms.payee(EscrowAction::Lock), None);
+        assert_eq!(terms.payee(EscrowAction::Release), Some(terms.bob_pk));
+        assert_eq!(terms.payee(EscrowAction::Refund), Some(terms.alice_pk));
+        assert_eq!(terms.signer(EscrowAction::Refund), terms.bob_pk);
+        assert_eq!(
+            terms.signer(EscrowAction::Arbitrate { to_bob: false }),
+            terms.arbiter_pk
+        );
+        assert!(!terms.can_arbitrate(TIMEOUT));
+        assert!(terms.can_arbitrate(TIMEOUT + 1));
+    }
+
+    #[test]
+    fn test_halo2_escrow_vp_circuit_lock() {
+        let Parties {
+            alice_sk,
+            bob_sk,
+            terms,
+            ..
+        } = parties();
+        let circuit = lock_circuit(terms, &alice_sk);
+        assert!(check(&circuit));
+
+        // The token vp of the locked token also runs the escrow vp
+        let owned_note_pub_id = circuit.output_notes[0].commitment().inner();
+        let circuit = EscrowValidityPredicateCircuit::from_sk_and_sign(
+            OsRng,
+            owned_note_pub_id,
+            circuit.anchors,
+            circuit.input_notes,
+            circuit.output_notes,
+            terms,
+            EscrowAction::Lock,
+            50,
+            &alice_sk,
+        );
+        assert!(check(&circuit));
+
+        // Only Alice can lock her token
+        let circuit = lock_circuit(terms, &bob_sk);
+        assert!(!check(&circuit));
+
+        // The lock can't pretend to be a release
+        let mut circuit = lock_circuit(terms, &alice_sk);
+        circuit.action = EscrowAction::Release;
+        assert!(!check(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_escrow_vp_circuit_release_and_refund() {
+        let Parties {
+            alice_sk,
+            bob_sk,
+            terms,
+            ..
+        } = parties();
+        let circuit = settle_circuit(terms, EscrowAction::Release, terms.bob_pk, 50, &alice_sk);
+        assert!(check(&circuit));
+
+        // The escrow note owns the vp
+        let owned_note_pub_id = circuit.input_notes[1].get_nf().unwrap().inner();
+        let circuit = EscrowValidityPredicateCircuit::from_sk_and_sign(
+            OsRng,
+            owned_note_pub_id,
+            circuit.anchors,
+            circuit.input_notes,
+            circuit.output_notes,
+            terms,
+            EscrowAction::Release,
+            50,
+            &alice_sk,
+        );
+        assert!(check(&circuit));
+
+        let circuit = settle_circuit(terms, EscrowAction::Refund, terms.alice_pk, 50, &bob_sk);
+        assert!(check(&circuit));
+
+        // Bob can't release the token to himself
+        let circuit = settle_circuit(terms, EscrowAction::Release, terms.bob_pk, 50, &bob_sk);
+        assert!(!check(&circuit));
+
+        // The refund goes back to Alice
+        let circuit = settle_circuit(terms, EscrowAction::Refund, terms.bob_pk, 50, &bob_sk);
+        assert!(!check(&circuit));
+
+        // The terms are not the ones of the escrow note
+        let mut circuit = settle_circuit(terms, EscrowAction::Release, terms.bob_pk, 50, &alice_sk);
+        circuit.terms.amount = 6;
+        assert!(!check(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_escrow_vp_circuit_arbitrate() {
+        let Parties {
+            arbiter_sk, terms, ..
+        } = parties();
+        let action = EscrowAction::Arbitrate { to_bob: true };
+        let circuit = settle_circuit(terms, action, terms.bob_pk, TIMEOUT + 1, &arbiter_sk);
+        assert!(check(&circuit));
+
+        let action = EscrowAction::Arbitrate { to_bob: false };
+        let circuit = settle_circuit(terms, action, terms.alice_pk, TIMEOUT + 1, &arbiter_sk);
+        assert!(check(&circuit));
+
+        // The arbiter can't act before the timeout
+        let circuit = settle_circuit(terms, action, terms.alice_pk, TIMEOUT, &arbiter_sk);
+        assert!(!check(&circuit));
+
+        // The published height is not the one compared with the timeout
+        let circuit = settle_circuit(terms, action, terms.alice_pk, TIMEOUT + 1, &arbiter_sk);
+        let mut public_inputs = circuit.get_public_inputs(OsRng).to_vec();
+        public_inputs[ESCROW_HEIGHT_PUBLIC_INPUT_IDX] = pallas::Base::from(TIMEOUT);
+        assert!(circuit
+            .dev_prove_and_check_with_public_inputs(&public_inputs.into())
+            .is_err());
+    }
+}
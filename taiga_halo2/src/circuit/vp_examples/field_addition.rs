@@ -10,7 +10,7 @@ use crate::{
             ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
         },
     },
-    constant::{NUM_NOTE, SETUP_PARAMS_MAP, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX},
+    constant::{NUM_NOTE, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX},
     note::{Note, RandomSeed},
     proof::Proof,
     vp_commitment::ValidityPredicateCommitment,
@@ -23,13 +23,14 @@ use halo2_proofs::{
 use pasta_curves::pallas;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use std::sync::Arc;
 
 // FieldAdditionValidityPredicateCircuit with a trivial constraint a + b = c.
 #[derive(Clone, Debug, Default)]
 struct FieldAdditionValidityPredicateCircuit {
     owned_note_pub_id: pallas::Base,
-    input_notes: [Note; NUM_NOTE],
-    output_notes: [Note; NUM_NOTE],
+    input_notes: Arc<[Note; NUM_NOTE]>,
+    output_notes: Arc<[Note; NUM_NOTE]>,
     a: pallas::Base,
     b: pallas::Base,
 }
@@ -109,10 +110,8 @@ vp_verifying_info_impl!(FieldAdditionValidityPredicateCircuit);
 
 #[test]
 fn test_halo2_addition_vp_circuit() {
-    use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
     use crate::note::tests::{random_input_note, random_output_note};
     use halo2_proofs::arithmetic::Field;
-    use halo2_proofs::dev::MockProver;
     use rand::rngs::OsRng;
 
     let mut rng = OsRng;
@@ -127,19 +126,11 @@ fn test_halo2_addition_vp_circuit() {
         let owned_note_pub_id = pallas::Base::random(&mut rng);
         FieldAdditionValidityPredicateCircuit {
             owned_note_pub_id,
-            input_notes,
-            output_notes: output_notes.try_into().unwrap(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes.try_into().unwrap()),
             a,
             b,
         }
     };
-    let public_inputs = circuit.get_public_inputs(&mut rng);
-
-    let prover = MockProver::<pallas::Base>::run(
-        VP_CIRCUIT_PARAMS_SIZE,
-        &circuit,
-        vec![public_inputs.to_vec()],
-    )
-    .unwrap();
-    assert_eq!(prover.verify(), Ok(()));
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
 }
@@ -0,0 +1,750 @@
+/// The multisig authorization is a k-of-n variant of the token authorization(signature verification VP).
+/// A note owned by n keys can be spent only if at least k of the keys sign the partial transaction.
+/// The keys and the threshold are committed to a point that takes the place of the single
+/// public key in the token app_data_dynamic encoding, so the token VP stays unchanged.
+/// The keys commitment is public, so the notes are encrypted to a viewing key shared by the
+/// signers instead, its pk is the rcv pk of the encoding.
+///
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            add::{AddChip, AddInstructions},
+            assign_free_advice, assign_free_constant,
+            mul::{MulChip, MulInstructions},
+            poseidon_hash::poseidon_hash_gadget,
+            sub::{SubChip, SubInstructions},
+            target_note_variable::get_owned_note_variable,
+        },
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::{
+            receiver_vp::COMPRESSED_RECEIVER_VK,
+            signature_verification::{
+                schnorr_challenge_gadget, signing_message, signing_message_gadget,
+                SchnorrSignature, SIGNED_ANCHORS_PUBLIC_INPUT_IDX,
+            },
+            token::TokenAuthorization,
+        },
+    },
+    constant::{
+        BaseFieldGenerators, TaigaFixedBasesFull, APP_DATA_DYNAMIC_DOMAIN, GENERATOR,
+        MULTISIG_KEYS_DOMAIN, NUM_NOTE,
+    },
+    keys::SigningKey,
+    merkle_tree::Anchor,
+    note::{Note, RandomSeed},
+    proof::Proof,
+    utils::{mod_r_p, poseidon_hash_n},
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_gadgets::ecc::{
+    chip::EccChip, FixedPoint, FixedPointBaseField, NonIdentityPoint, ScalarFixed,
+};
+use halo2_proofs::{
+    arithmetic::Field,
+    circuit::{floor_planner, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::{
+    arithmetic::CurveAffine,
+    group::{Curve, Group},
+    pallas,
+};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// The number of keys in the multisig
+pub const MULTISIG_KEY_NUM: usize = 3;
+
+lazy_static! {
+    pub static ref MULTISIG_AUTH_VK: ValidityPredicateVerifyingKey =
+        MultisigAuthorizationValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_MULTISIG_AUTH_VK: pallas::Base = MULTISIG_AUTH_VK.get_compressed();
+}
+
+#[derive(Clone, Debug)]
+pub struct MultisigAuthorization {
+    pub pks: [pallas::Point; MULTISIG_KEY_NUM],
+    pub threshold: u64,
+    // The pk of the viewing key shared by the signers, the notes are encrypted to it
+    pub rcv_pk: pallas::Point,
+}
+
+// A signature of one of the keys
+#[derive(Clone, Debug)]
+pub struct MultisigPartialSignature {
+    pub index: usize,
+    pub signature: SchnorrSignature,
+}
+
+// The aggregated signatures, the selections indicate which keys signed
+#[derive(Clone, Debug, Default)]
+pub struct MultisigSignature {
+    pub selections: [bool; MULTISIG_KEY_NUM],
+    pub signatures: [SchnorrSignature; MULTISIG_KEY_NUM],
+}
+
+impl Default for MultisigAuthorization {
+    fn default() -> Self {
+        let generator = pallas::Point::generator();
+        Self {
+            pks: [1u64, 2, 3].map(|i| generator * pallas::Scalar::from(i)),
+            threshold: 1,
+            rcv_pk: generator,
+        }
+    }
+}
+
+impl MultisigAuthorization {
+    // Returns None if the keys are not distinct, the threshold is not in [1, MULTISIG_KEY_NUM]
+    // or the rcv pk is the identity
+    pub fn new(
+        pks: [pallas::Point; MULTISIG_KEY_NUM],
+        threshold: u64,
+        rcv_pk: pallas::Point,
+    ) -> Option<Self> {
+        if threshold == 0 || threshold > MULTISIG_KEY_NUM as u64 {
+            return None;
+        }
+        if bool::from(rcv_pk.is_identity()) {
+            return None;
+        }
+        for i in 0..MULTISIG_KEY_NUM {
+            for j in (i + 1)..MULTISIG_KEY_NUM {
+                if pks[i].to_affine().coordinates().unwrap().x()
+                    == pks[j].to_affine().coordinates().unwrap().x()
+                {
+                    return None;
+                }
+            }
+        }
+        Some(Self {
+            pks,
+            threshold,
+            rcv_pk,
+        })
+    }
+
+    // keys_commitment = poseidon_hash(MULTISIG_KEYS_DOMAIN || pk_1 || ... || pk_n || threshold)
+    pub fn keys_commitment(&self) -> pallas::Base {
+        let mut message = [pallas::Base::zero(); MULTISIG_KEY_NUM * 2 + 2];
+        message[0] = MULTISIG_KEYS_DOMAIN;
+        self.pks.iter().enumerate().for_each(|(i, pk)| {
+            let pk_coord = pk.to_affine().coordinates().unwrap();
+            message[i * 2 + 1] = *pk_coord.x();
+            message[i * 2 + 2] = *pk_coord.y();
+        });
+        message[MULTISIG_KEY_NUM * 2 + 1] = pallas::Base::from(self.threshold);
+        poseidon_hash_n(message)
+    }
+
+    // The multisig point replaces the single pk in the token authorization. It's derived from
+    // public keys only, so the notes are encrypted to the shared rcv pk.
+    pub fn to_token_authorization(&self) -> TokenAuthorization {
+        let pk = GENERATOR.to_curve() * mod_r_p(self.keys_commitment());
+        TokenAuthorization::new(pk, *COMPRESSED_MULTISIG_AUTH_VK)
+            .and_then(|auth| auth.with_rcv_pk(self.rcv_pk))
+            .expect("the multisig point or the rcv pk is the identity")
+    }
+
+    pub fn to_app_data_dynamic(&self) -> pallas::Base {
+        self.to_token_authorization().to_app_data_dynamic()
+    }
+
+    // Signs the spend of the owned note with one of the keys, returns None if the key is not in the multisig.
+    pub fn sign_partial<R: RngCore>(
+        &self,
+        mut rng: R,
+        sk: &SigningKey,
+        owned_note_pub_id: pallas::Base,
+        anchors: &[Anchor; NUM_NOTE],
+        input_notes: &[Note; NUM_NOTE],
+        output_notes: &[Note; NUM_NOTE],
+    ) -> Option<MultisigPartialSignature> {
+        let pk = sk.public_key();
+        let index = self.pks.iter().position(|key| *key == pk)?;
+        let message = signing_message(anchors, owned_note_pub_id, input_notes, output_notes);
+        let signature = SchnorrSignature::sign(&mut rng, sk, message);
+        Some(MultisigPartialSignature { index, signature })
+    }
+
+    // Aggregates the partial signatures, returns None if a key is used twice or
+    // there are not enough signatures.
+    pub fn aggregate(&self, partials: &[MultisigPartialSignature]) -> Option<MultisigSignature> {
+        let mut ret = MultisigSignature::default();
+        for partial in partials.iter() {
+            if partial.index >= MULTISIG_KEY_NUM
+                || ret.selections[partial.index]
+                || partial.signature.pk != self.pks[partial.index]
+            {
+                return None;
+            }
+            ret.selections[partial.index] = true;
+            ret.signatures[partial.index] = partial.signature.clone();
+        }
+
+        if (partials.len() as u64) < self.threshold {
+            return None;
+        }
+
+        Some(ret)
+    }
+}
+
+// MultisigAuthorizationValidityPredicateCircuit verifies at least threshold schnorr signatures.
+#[derive(Clone, Debug, Default)]
+pub struct MultisigAuthorizationValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    pub anchors: [Anchor; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    pub vp_vk: pallas::Base,
+    pub auth: MultisigAuthorization,
+    pub signature: MultisigSignature,
+    pub receiver_vp_vk: pallas::Base,
+}
+
+impl MultisigAuthorizationValidityPredicateCircuit {
+    pub fn new(
+        owned_note_pub_id: pallas::Base,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+        auth: MultisigAuthorization,
+        signature: MultisigSignature,
+    ) -> Self {
+        Self {
+            owned_note_pub_id,
+            anchors,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            vp_vk: *COMPRESSED_MULTISIG_AUTH_VK,
+            auth,
+            signature,
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+        }
+    }
+}
+
+impl ValidityPredicateCircuit for MultisigAuthorizationValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        // Construct the chips
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
+        let add_chip = AddChip::<pallas::Base>::construct(config.add_config.clone(), ());
+        let sub_chip = SubChip::<pallas::Base>::construct(config.sub_config.clone(), ());
+        let mul_chip = MulChip::<pallas::Base>::construct(config.mul_config.clone());
+
+        let constant_zero = assign_free_constant(
+            layouter.namespace(|| "zero"),
+            config.advices[0],
+            pallas::Base::zero(),
+        )?;
+        let constant_one = assign_free_constant(
+            layouter.namespace(|| "one"),
+            config.advices[0],
+            pallas::Base::one(),
+        )?;
+
+        // Witness the keys and the threshold
+        let pks = self
+            .auth
+            .pks
+            .iter()
+            .map(|pk| {
+                NonIdentityPoint::new(
+                    ecc_chip.clone(),
+                    layouter.namespace(|| "witness pk"),
+                    Value::known(pk.to_affine()),
+                )
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let threshold = assign_free_advice(
+            layouter.namespace(|| "witness threshold"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.auth.threshold)),
+        )?;
+
+        // keys_commitment = poseidon_hash(MULTISIG_KEYS_DOMAIN || pk_1 || ... || pk_n || threshold)
+        let keys_commitment = {
+            let keys_domain = assign_free_constant(
+                layouter.namespace(|| "multisig keys domain"),
+                config.advices[0],
+                MULTISIG_KEYS_DOMAIN,
+            )?;
+            let mut message = vec![keys_domain];
+            pks.iter().for_each(|pk| {
+                message.push(pk.inner().x());
+                message.push(pk.inner().y());
+            });
+            message.push(threshold.clone());
+            poseidon_hash_gadget(
+                config.poseidon_config.clone(),
+                layouter.namespace(|| "keys commitment"),
+                message.try_into().unwrap(),
+            )?
+        };
+
+        // multisig_pk = keys_commitment * generator
+        let multisig_pk = {
+            let generator = FixedPointBaseField::from_inner(
+                ecc_chip.clone(),
+                BaseFieldGenerators::BaseGenerator,
+            );
+            generator.mul(
+                layouter.namespace(|| "keys_commitment * generator"),
+                keys_commitment,
+            )?
+        };
+
+        // search target note and get the app_data_dynamic
+        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
+        let app_data_dynamic = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get owned note app_data_dynamic"),
+            &owned_note_pub_id,
+            &basic_variables.get_app_data_dynamic_searchable_pairs(),
+        )?;
+
+        let auth_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness auth vp vk"),
+            config.advices[0],
+            Value::known(self.vp_vk),
+        )?;
+        let receiver_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness receiver vp vk"),
+            config.advices[0],
+            Value::known(self.receiver_vp_vk),
+        )?;
+        let app_data_dynamic_domain = assign_free_constant(
+            layouter.namespace(|| "app_data_dynamic domain"),
+            config.advices[0],
+            APP_DATA_DYNAMIC_DOMAIN,
+        )?;
+        let rcv_pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness rcv_pk"),
+            Value::known(self.auth.rcv_pk.to_affine()),
+        )?;
+
+        // Check the app_data_dynamic encoding, the same as the token authorization. The rcv pk
+        // is the shared viewing key, see to_token_authorization.
+        let encoded_app_data_dynamic = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "app_data_dynamic encoding"),
+            [
+                app_data_dynamic_domain,
+                multisig_pk.inner().x(),
+                multisig_pk.inner().y(),
+                auth_vp_vk,
+                receiver_vp_vk,
+                rcv_pk.inner().x(),
+                rcv_pk.inner().y(),
+            ],
+        )?;
+
+        layouter.assign_region(
+            || "check app_data_dynamic encoding",
+            |mut region| {
+                region.constrain_equal(encoded_app_data_dynamic.cell(), app_data_dynamic.cell())
+            },
+        )?;
+
+        // The keys must be distinct: (pk_i.x - pk_j.x) * inv = 1
+        for i in 0..MULTISIG_KEY_NUM {
+            for j in (i + 1)..MULTISIG_KEY_NUM {
+                let diff = SubInstructions::sub(
+                    &sub_chip,
+                    layouter.namespace(|| "pk_i.x - pk_j.x"),
+                    &pks[i].inner().x(),
+                    &pks[j].inner().x(),
+                )?;
+                let inv = assign_free_advice(
+                    layouter.namespace(|| "witness inverse"),
+                    config.advices[0],
+                    diff.value()
+                        .map(|diff| diff.invert().unwrap_or(pallas::Base::zero())),
+                )?;
+                let product = MulInstructions::mul(
+                    &mul_chip,
+                    layouter.namespace(|| "diff * inv"),
+                    &diff,
+                    &inv,
+                )?;
+                layouter.assign_region(
+                    || "check distinct keys",
+                    |mut region| region.constrain_equal(product.cell(), constant_one.cell()),
+                )?;
+            }
+        }
+
+        // Verify the selected signatures: s*G = R + Hash(r||P||m)*P
+        let message = signing_message_gadget(
+            &config,
+            layouter.namespace(|| "signing message"),
+            &basic_variables,
+            &self.anchors,
+        )?;
+        let mut selection_sum = constant_zero.clone();
+        for (i, pk) in pks.iter().enumerate() {
+            let selection = assign_free_advice(
+                layouter.namespace(|| "witness selection"),
+                config.advices[0],
+                Value::known(pallas::Base::from(self.signature.selections[i])),
+            )?;
+            // Check the selection is boolean
+            let selection_square = MulInstructions::mul(
+                &mul_chip,
+                layouter.namespace(|| "selection * selection"),
+                &selection,
+                &selection,
+            )?;
+            layouter.assign_region(
+                || "check boolean selection",
+                |mut region| region.constrain_equal(selection_square.cell(), selection.cell()),
+            )?;
+            selection_sum = AddInstructions::add(
+                &add_chip,
+                layouter.namespace(|| "selection sum"),
+                &selection_sum,
+                &selection,
+            )?;
+
+            let signature = &self.signature.signatures[i];
+            let r = NonIdentityPoint::new(
+                ecc_chip.clone(),
+                layouter.namespace(|| "witness r"),
+                Value::known(signature.r.to_affine()),
+            )?;
+            let s_scalar = ScalarFixed::new(
+                ecc_chip.clone(),
+                layouter.namespace(|| "witness s"),
+                Value::known(signature.s),
+            )?;
+
+            // s*G
+            let generator =
+                FixedPoint::from_inner(ecc_chip.clone(), TaigaFixedBasesFull::BaseGenerator);
+            let (s_g, _) =
+                generator.mul(layouter.namespace(|| "s_scalar * generator"), &s_scalar)?;
+
+            // Hash(r||P||m)
+            let h_scalar = schnorr_challenge_gadget(
+                &config,
+                layouter.namespace(|| "schnorr challenge"),
+                &r,
+                pk,
+                &message,
+            )?;
+
+            // R + Hash(r||P||m)*P
+            let (h_p, _) = pk.mul(layouter.namespace(|| "hP"), h_scalar)?;
+            let rhs = r.add(layouter.namespace(|| "R + Hash(r||P||m)*P"), &h_p)?;
+
+            // Check the signature if it's selected
+            layouter.assign_region(
+                || "conditional equal: check signature x",
+                |mut region| {
+                    config.conditional_equal_config.assign_region(
+                        &selection,
+                        &s_g.inner().x(),
+                        &rhs.inner().x(),
+                        0,
+                        &mut region,
+                    )
+                },
+            )?;
+            layouter.assign_region(
+                || "conditional equal: check signature y",
+                |mut region| {
+                    config.conditional_equal_config.assign_region(
+                        &selection,
+                        &s_g.inner().y(),
+                        &rhs.inner().y(),
+                        0,
+                        &mut region,
+                    )
+                },
+            )?;
+        }
+
+        // Check selection_sum >= threshold:
+        // (selection_sum - threshold) is in [0, MULTISIG_KEY_NUM]
+        let diff = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "selection_sum - threshold"),
+            &selection_sum,
+            &threshold,
+        )?;
+        let mut range_check = diff.clone();
+        for j in 1..=MULTISIG_KEY_NUM {
+            let constant_j = assign_free_constant(
+                layouter.namespace(|| "constant j"),
+                config.advices[0],
+                pallas::Base::from(j as u64),
+            )?;
+            let diff_j = SubInstructions::sub(
+                &sub_chip,
+                layouter.namespace(|| "diff - j"),
+                &diff,
+                &constant_j,
+            )?;
+            range_check = MulInstructions::mul(
+                &mul_chip,
+                layouter.namespace(|| "range check"),
+                &range_check,
+                &diff_j,
+            )?;
+        }
+        layouter.assign_region(
+            || "check selection_sum >= threshold",
+            |mut region| region.constrain_equal(range_check.cell(), constant_zero.cell()),
+        )?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(self.anchors.map(|anchor| anchor.inner()));
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+
+    fn get_signed_anchors_public_input_idx(&self) -> Option<usize> {
+        Some(SIGNED_ANCHORS_PUBLIC_INPUT_IDX)
+    }
+}
+
+vp_circuit_impl!(MultisigAuthorizationValidityPredicateCircuit);
+vp_verifying_info_impl!(MultisigAuthorizationValidityPredicateCircuit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    fn random_keys<R: RngCore>(
+        mut rng: R,
+    ) -> (
+        [pallas::Scalar; MULTISIG_KEY_NUM],
+        [pallas::Point; MULTISIG_KEY_NUM],
+    ) {
+        let sks = [(); MULTISIG_KEY_NUM].map(|_| pallas::Scalar::random(&mut rng));
+        let pks = sks.map(|sk| pallas::Point::generator() * sk);
+        (sks, pks)
+    }
+
+    // The pk of the viewing key shared by the signers
+    fn random_rcv_pk<R: RngCore>(rng: R) -> pallas::Point {
+        GENERATOR.to_curve() * mod_r_p(pallas::Base::random(rng))
+    }
+
+    // Creates the circuit with the owned input note protected by the auth
+    fn multisig_circuit<R: RngCore>(
+        mut rng: R,
+        auth: MultisigAuthorization,
+        signers: &[pallas::Scalar],
+    ) -> MultisigAuthorizationValidityPredicateCircuit {
+        let mut input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+        input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
+        let output_notes = input_notes
+            .clone()
+            .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+        let owned_note_pub_id = input_notes[0].get_nf().unwrap().inner();
+        let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+        let partials = signers
+            .iter()
+            .map(|sk| {
+                auth.sign_partial(
+                    &mut rng,
+                    &SigningKey::new(*sk),
+                    owned_note_pub_id,
+                    &anchors,
+                    &input_notes,
+                    &output_notes,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        // Fill the signatures directly so that invalid multisigs can be tested in the circuit
+        let mut signature = MultisigSignature::default();
+        partials.into_iter().for_each(|partial| {
+            signature.selections[partial.index] = true;
+            signature.signatures[partial.index] = partial.signature;
+        });
+        MultisigAuthorizationValidityPredicateCircuit::new(
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            auth,
+            signature,
+        )
+    }
+
+    fn verify(circuit: &MultisigAuthorizationValidityPredicateCircuit) -> bool {
+        circuit.dev_prove_and_check(OsRng).is_ok()
+    }
+
+    #[test]
+    fn test_halo2_multisig_exactly_threshold() {
+        let mut rng = OsRng;
+        let (sks, pks) = random_keys(&mut rng);
+        let auth = MultisigAuthorization::new(pks, 2, random_rcv_pk(&mut rng)).unwrap();
+        let circuit = multisig_circuit(&mut rng, auth, &[sks[0], sks[2]]);
+        assert!(verify(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_multisig_more_than_threshold() {
+        let mut rng = OsRng;
+        let (sks, pks) = random_keys(&mut rng);
+        let auth = MultisigAuthorization::new(pks, 2, random_rcv_pk(&mut rng)).unwrap();
+        let circuit = multisig_circuit(&mut rng, auth.clone(), &sks);
+        let partials = circuit
+            .signature
+            .signatures
+            .iter()
+            .enumerate()
+            .map(|(index, signature)| MultisigPartialSignature {
+                index,
+                signature: signature.clone(),
+            })
+            .collect::<Vec<_>>();
+        assert!(auth.aggregate(&partials).is_some());
+        assert!(verify(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_multisig_below_threshold() {
+        let mut rng = OsRng;
+        let (sks, pks) = random_keys(&mut rng);
+        let auth = MultisigAuthorization::new(pks, 2, random_rcv_pk(&mut rng)).unwrap();
+        let input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+        let output_notes = input_notes
+            .clone()
+            .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+        let partial = auth
+            .sign_partial(
+                &mut rng,
+                &SigningKey::new(sks[1]),
+                input_notes[0].get_nf().unwrap().inner(),
+                &[Anchor::default(); NUM_NOTE],
+                &input_notes,
+                &output_notes,
+            )
+            .unwrap();
+        assert!(auth.aggregate(&[partial]).is_none());
+
+        // Drop one of the two selected signatures in the circuit
+        let mut circuit = multisig_circuit(&mut rng, auth, &[sks[0], sks[1]]);
+        circuit.signature.selections[1] = false;
+        assert!(!verify(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_multisig_duplicate_key() {
+        let mut rng = OsRng;
+        let (sks, pks) = random_keys(&mut rng);
+        let duplicate_pks = [pks[0], pks[0], pks[2]];
+        assert!(MultisigAuthorization::new(duplicate_pks, 2, random_rcv_pk(&mut rng)).is_none());
+
+        // The same key can't be used twice in the aggregation
+        let auth = MultisigAuthorization::new(pks, 2, random_rcv_pk(&mut rng)).unwrap();
+        let input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+        let output_notes = input_notes
+            .clone()
+            .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+        let partial = auth
+            .sign_partial(
+                &mut rng,
+                &SigningKey::new(sks[0]),
+                input_notes[0].get_nf().unwrap().inner(),
+                &[Anchor::default(); NUM_NOTE],
+                &input_notes,
+                &output_notes,
+            )
+            .unwrap();
+        assert!(auth.aggregate(&[partial.clone(), partial]).is_none());
+
+        // A multisig with duplicate keys is rejected by the circuit
+        let duplicate_auth = MultisigAuthorization {
+            pks: duplicate_pks,
+            threshold: 2,
+            rcv_pk: random_rcv_pk(&mut rng),
+        };
+        let mut circuit = multisig_circuit(&mut rng, duplicate_auth, &[sks[0]]);
+        let signature = circuit.signature.signatures[0].clone();
+        circuit.signature.selections[1] = true;
+        circuit.signature.signatures[1] = signature;
+        assert!(!verify(&circuit));
+    }
+
+    #[test]
+    fn test_multisig_rcv_pk() {
+        let mut rng = OsRng;
+        let (sks, pks) = random_keys(&mut rng);
+        assert!(MultisigAuthorization::new(pks, 2, pallas::Point::identity()).is_none());
+
+        // The notes are encrypted to the shared viewing key, not to the public multisig point
+        let auth = MultisigAuthorization::new(pks, 2, random_rcv_pk(&mut rng)).unwrap();
+        let token_auth = auth.to_token_authorization();
+        assert_eq!(token_auth.rcv_pk, auth.rcv_pk);
+        assert_ne!(token_auth.rcv_pk, token_auth.pk);
+
+        // The rcv pk is bound to the app_data_dynamic
+        let mut circuit = multisig_circuit(&mut rng, auth, &[sks[0], sks[1]]);
+        circuit.auth.rcv_pk = random_rcv_pk(&mut rng);
+        assert!(!verify(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_multisig_bad_signature() {
+        let mut rng = OsRng;
+        let (sks, pks) = random_keys(&mut rng);
+        let auth = MultisigAuthorization::new(pks, 2, random_rcv_pk(&mut rng)).unwrap();
+        let mut circuit = multisig_circuit(&mut rng, auth, &[sks[0], sks[1]]);
+        circuit.signature.signatures[1].s += pallas::Scalar::one();
+        assert!(!verify(&circuit));
+    }
+}
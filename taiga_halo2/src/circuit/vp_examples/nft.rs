@@ -0,0 +1,955 @@
+use crate::{
+    circuit::{
+        blake2s::dynamic_vp_commitment_gadget,
+        gadgets::{
+            assign_free_advice, assign_free_constant,
+            mul::{MulChip, MulInstructions},
+            poseidon_hash::poseidon_hash_gadget,
+            sub::{SubChip, SubInstructions},
+            target_note_variable::{get_owned_note_variable, get_owned_note_variables},
+        },
+        vp_circuit::{
+            BasicValidityPredicateVariables, NoteSearchableVariablePair, NoteVariables,
+            VPVerifyingInfo, ValidityPredicateCircuit, ValidityPredicateConfig,
+            ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::{
+            receiver_vp::{
+                output_note_kind, ReceiverValidityPredicateCircuit, COMPRESSED_RECEIVER_VK,
+            },
+            signature_verification::{
+                SignatureVerificationValidityPredicateCircuit, COMPRESSED_TOKEN_AUTH_VK,
+            },
+            token::{TokenAuthorization, TOKEN_AUTHORIZATION_SCHEMA},
+        },
+    },
+    constant::{
+        NUM_NOTE, PRF_EXPAND_DYNAMIC_VP_1_CM_R, VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1,
+        VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2, VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_1,
+        VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_2,
+    },
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, NoteType, OutputNoteProvingInfo, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    proof::Proof,
+    utils::poseidon_hash_n,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use ff::Field;
+use group::{Curve, Group};
+use halo2_gadgets::ecc::{chip::EccChip, NonIdentityPoint};
+use halo2_proofs::{
+    circuit::{floor_planner, AssignedCell, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::arithmetic::CurveAffine;
+use pasta_curves::{group::ff::PrimeField, pallas};
+use rand::{rngs::OsRng, Rng, RngCore};
+use std::sync::Arc;
+
+lazy_static! {
+    pub static ref NFT_VK: ValidityPredicateVerifyingKey =
+        NftValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_NFT_VK: pallas::Base = NFT_VK.get_compressed();
+}
+
+// A collection of NFTs. The issuer mints the items, and the mint notes are spent with the mint
+// nullifier key, committed to the collection, so that every item is minted once.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NftCollection {
+    name: String,
+    issuer: pallas::Point,
+    mint_nk_com: pallas::Base,
+}
+
+impl NftCollection {
+    pub fn new(name: String, issuer: pallas::Point, mint_nk_com: pallas::Base) -> Self {
+        Self {
+            name,
+            issuer,
+            mint_nk_com,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn issuer(&self) -> pallas::Point {
+        self.issuer
+    }
+
+    pub fn mint_nk_com(&self) -> pallas::Base {
+        self.mint_nk_com
+    }
+
+    pub fn encode_name_bytes(&self) -> pallas::Base {
+        assert!(self.name.len() < 32);
+        let mut bytes: [u8; 32] = [0; 32];
+        bytes[..self.name.len()].copy_from_slice(self.name.as_bytes());
+        pallas::Base::from_repr(bytes).unwrap()
+    }
+
+    pub fn item(&self, id: pallas::Base) -> Nft {
+        Nft {
+            collection: self.clone(),
+            id,
+        }
+    }
+}
+
+impl Default for NftCollection {
+    fn default() -> Self {
+        Self {
+            name: "NFT_collection".to_string(),
+            issuer: pallas::Point::generator(),
+            mint_nk_com: pallas::Base::zero(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Nft {
+    collection: NftCollection,
+    id: pallas::Base,
+}
+
+impl Nft {
+    pub fn collection(&self) -> &NftCollection {
+        &self.collection
+    }
+
+    pub fn id(&self) -> pallas::Base {
+        self.id
+    }
+
+    // The encoding goes to app_data_static, every item has its own note type.
+    // encode = poseidon_hash(name_bytes || issuer || mint_nk_com || id)
+    pub fn encode(&self) -> pallas::Base {
+        let issuer_coord = self.collection.issuer.to_affine().coordinates().unwrap();
+        poseidon_hash_n([
+            self.collection.encode_name_bytes(),
+            *issuer_coord.x(),
+            *issuer_coord.y(),
+            self.collection.mint_nk_com,
+            self.id,
+        ])
+    }
+
+    pub fn note_type(&self) -> NoteType {
+        NoteType::new(*COMPRESSED_NFT_VK, self.encode())
+    }
+
+    // The auth of the mint notes, the issuer signs the mint ptx
+    pub fn issuer_auth(&self) -> TokenAuthorization {
+        TokenAuthorization::new(self.collection.issuer, *COMPRESSED_TOKEN_AUTH_VK)
+            .expect("the issuer is not a valid key")
+    }
+
+    pub fn create_random_nft_note<R: RngCore>(
+        &self,
+        mut rng: R,
+        rho: Nullifier,
+        nk_container: NullifierKeyContainer,
+        auth: &TokenAuthorization,
+    ) -> NftNote {
+        let note_type = self.note_type();
+        let rseed = RandomSeed::random(&mut rng);
+        let note = Note::new(
+            note_type.app_vk,
+            note_type.app_data_static,
+            auth.to_app_data_dynamic(),
+            1,
+            nk_container,
+            rho,
+            true,
+            rseed,
+        );
+        NftNote {
+            nft: self.clone(),
+            note,
+        }
+    }
+
+    // The ephemeral note spent to mint the item. All its fields are fixed by the item, so it
+    // always has the same nullifier and the item can't be minted twice.
+    pub fn create_mint_note(&self, mint_nk: pallas::Base) -> NftNote {
+        let nk_container = NullifierKeyContainer::from_key(mint_nk);
+        assert_eq!(
+            nk_container.get_commitment(),
+            self.collection.mint_nk_com,
+            "the mint nk is not the nk of the collection"
+        );
+        let note_type = self.note_type();
+        let note = Note::from_full(
+            note_type.app_vk,
+            note_type.app_data_static,
+            self.issuer_auth().to_app_data_dynamic(),
+            1,
+            nk_container,
+            Nullifier::from(note_type.app_data_static),
+            false,
+            pallas::Base::zero(),
+            pallas::Base::zero(),
+        );
+        NftNote {
+            nft: self.clone(),
+            note,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NftNote {
+    pub nft: Nft,
+    pub note: Note,
+}
+
+impl std::ops::Deref for NftNote {
+    type Target = Note;
+
+    fn deref(&self) -> &Self::Target {
+        &self.note
+    }
+}
+
+impl NftNote {
+    pub fn nft(&self) -> &Nft {
+        &self.nft
+    }
+
+    pub fn note(&self) -> &Note {
+        &self.note
+    }
+
+    // Spend the nft, or mint it when it's a mint note and the auth is the issuer auth.
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate_input_nft_note_proving_info<R: RngCore>(
+        &self,
+        mut rng: R,
+        auth: TokenAuthorization,
+        auth_sk: &SigningKey,
+        merkle_path: MerklePath,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> InputNoteProvingInfo {
+        // The vps share the notes
+        let input_notes = Arc::new(input_notes);
+        let output_notes = Arc::new(output_notes);
+        let nf = self.note.get_nf().unwrap().inner();
+        let nft_vp = NftValidityPredicateCircuit {
+            owned_note_pub_id: nf,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
+            nft: self.nft.clone(),
+            auth,
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+            rseed: RandomSeed::random(&mut rng),
+        };
+        let auth_vp = SignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            nf,
+            anchors,
+            input_notes,
+            output_notes,
+            auth.vk,
+            auth_sk,
+            *COMPRESSED_RECEIVER_VK,
+            auth.rcv_pk,
+        );
+        InputNoteProvingInfo::new(
+            self.note,
+            merkle_path,
+            None,
+            Box::new(nft_vp),
+            vec![Box::new(auth_vp)],
+        )
+    }
+
+    pub fn generate_output_nft_note_proving_info<R: RngCore>(
+        &self,
+        mut rng: R,
+        auth: TokenAuthorization,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> OutputNoteProvingInfo {
+        // The vps share the notes
+        let input_notes = Arc::new(input_notes);
+        let output_notes = Arc::new(output_notes);
+        let owned_note_pub_id = self.note.commitment().inner();
+        let nft_vp = NftValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
+            nft: self.nft.clone(),
+            auth,
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+            rseed: RandomSeed::random(&mut rng),
+        };
+        let receiver_vp = ReceiverValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
+            vp_vk: *COMPRESSED_RECEIVER_VK,
+            nonce: pallas::Base::from_u128(rng.gen()),
+            esk: pallas::Base::random(&mut rng),
+            rcv_pk: auth.rcv_pk,
+            auth_pk: auth.pk,
+            auth_vp_vk: *COMPRESSED_TOKEN_AUTH_VK,
+            note_kind: output_note_kind(&self.note, &input_notes, &output_notes).to_base(),
+        };
+        let note_encryption = receiver_vp.note_encryption();
+        let mut proving_info =
+            OutputNoteProvingInfo::new(self.note, Box::new(nft_vp), vec![Box::new(receiver_vp)]);
+        // The receiver vp publishes the note ciphertext
+        proving_info.set_note_encryption(note_encryption);
+        proving_info
+    }
+}
+
+// NftValidityPredicateCircuit
+// - every nft note has value 1
+// - the nft goes from the input note to the output note of the same action, and the other way
+//   round, so it can't be split or swapped with another item
+// - the ephemeral notes are authorized by the issuer, the ephemeral input note is the mint note
+//   of the item
+#[derive(Clone, Debug)]
+pub struct NftValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    // The nft goes to app_data_static
+    pub nft: Nft,
+    // The auth goes to app_data_dynamic, see TOKEN_AUTHORIZATION_SCHEMA
+    pub auth: TokenAuthorization,
+    pub receiver_vp_vk: pallas::Base,
+    // rseed is to generate the randomness for vp commitment
+    pub rseed: RandomSeed,
+}
+
+impl Default for NftValidityPredicateCircuit {
+    fn default() -> Self {
+        Self {
+            owned_note_pub_id: pallas::Base::zero(),
+            input_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            output_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            nft: Nft::default(),
+            auth: TokenAuthorization::default(),
+            receiver_vp_vk: pallas::Base::zero(),
+            rseed: RandomSeed::default(),
+        }
+    }
+}
+
+// Search the note in the same action as the owned note: the output note i for the input note i,
+// and the input note i for the output note i
+fn action_pair_searchable_pairs(
+    basic_variables: &BasicValidityPredicateVariables,
+    target_variable: impl Fn(&NoteVariables) -> AssignedCell<pallas::Base, pallas::Base>,
+) -> [NoteSearchableVariablePair; NUM_NOTE * 2] {
+    let inputs = basic_variables.input_note_variables.iter();
+    let outputs = basic_variables.output_note_variables.iter();
+    inputs
+        .clone()
+        .zip(outputs.clone())
+        .map(|(input, output)| NoteSearchableVariablePair {
+            src_variable: input.nf.clone(),
+            target_variable: target_variable(&output.note_variables),
+        })
+        .chain(
+            outputs
+                .zip(inputs)
+                .map(|(output, input)| NoteSearchableVariablePair {
+                    src_variable: output.cm.clone(),
+                    target_variable: target_variable(&input.note_variables),
+                }),
+        )
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap()
+}
+
+impl ValidityPredicateCircuit for NftValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
+        let owned_note = get_owned_note_variables(
+            &config,
+            layouter.namespace(|| "get owned note variables"),
+            &basic_variables,
+        )?;
+        let note_variables = &owned_note.note_variables;
+
+        let constant_zero = assign_free_constant(
+            layouter.namespace(|| "zero"),
+            config.advices[0],
+            pallas::Base::zero(),
+        )?;
+        let constant_one = assign_free_constant(
+            layouter.namespace(|| "one"),
+            config.advices[0],
+            pallas::Base::one(),
+        )?;
+
+        // The nft notes have value 1
+        layouter.assign_region(
+            || "check value",
+            |mut region| region.constrain_equal(note_variables.value.cell(), constant_one.cell()),
+        )?;
+
+        // Check the app_data_static encoding
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
+        let issuer = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness issuer"),
+            Value::known(self.nft.collection.issuer.to_affine()),
+        )?;
+        let name_bytes = assign_free_advice(
+            layouter.namespace(|| "witness collection name bytes"),
+            config.advices[0],
+            Value::known(self.nft.collection.encode_name_bytes()),
+        )?;
+        let mint_nk_com = assign_free_advice(
+            layouter.namespace(|| "witness mint nk_com"),
+            config.advices[0],
+            Value::known(self.nft.collection.mint_nk_com),
+        )?;
+        let id = assign_free_advice(
+            layouter.namespace(|| "witness id"),
+            config.advices[0],
+            Value::known(self.nft.id),
+        )?;
+        let encoded_app_data_static = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "app_data_static encoding"),
+            [
+                name_bytes,
+                issuer.inner().x(),
+                issuer.inner().y(),
+                mint_nk_com.clone(),
+                id,
+            ],
+        )?;
+        layouter.assign_region(
+            || "check app_data_static encoding",
+            |mut region| {
+                region.constrain_equal(
+                    encoded_app_data_static.cell(),
+                    note_variables.app_data_static.cell(),
+                )
+            },
+        )?;
+
+        // The note in the same action is the same nft
+        let paired_app_vk = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get paired note app_vk"),
+            &owned_note_pub_id,
+            &action_pair_searchable_pairs(&basic_variables, |variables| variables.app_vk.clone()),
+        )?;
+        let paired_app_data_static = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get paired note app_data_static"),
+            &owned_note_pub_id,
+            &action_pair_searchable_pairs(&basic_variables, |variables| {
+                variables.app_data_static.clone()
+            }),
+        )?;
+        layouter.assign_region(
+            || "check paired note",
+            |mut region| {
+                region.constrain_equal(paired_app_vk.cell(), note_variables.app_vk.cell())?;
+                region.constrain_equal(
+                    paired_app_data_static.cell(),
+                    note_variables.app_data_static.cell(),
+                )
+            },
+        )?;
+
+        // Check the app_data_dynamic encoding
+        let pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness pk"),
+            Value::known(self.auth.pk.to_affine()),
+        )?;
+        let rcv_pk = NonIdentityPoint::new(
+            ecc_chip,
+            layouter.namespace(|| "witness rcv_pk"),
+            Value::known(self.auth.rcv_pk.to_affine()),
+        )?;
+        let auth_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness auth vp vk"),
+            config.advices[0],
+            Value::known(self.auth.vk),
+        )?;
+        let receiver_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness receiver vp vk"),
+            config.advices[0],
+            Value::known(self.receiver_vp_vk),
+        )?;
+        let encoded_app_data_dynamic = TOKEN_AUTHORIZATION_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "app_data_dynamic encoding"),
+            &[
+                pk.inner().x(),
+                pk.inner().y(),
+                auth_vp_vk.clone(),
+                receiver_vp_vk.clone(),
+                rcv_pk.inner().x(),
+                rcv_pk.inner().y(),
+            ],
+        )?;
+        layouter.assign_region(
+            || "check app_data_dynamic encoding",
+            |mut region| {
+                region.constrain_equal(
+                    encoded_app_data_dynamic.cell(),
+                    note_variables.app_data_dynamic.cell(),
+                )
+            },
+        )?;
+
+        // The ephemeral notes have the issuer auth, the issuer is also the rcv pk
+        let token_auth_vp_vk = assign_free_constant(
+            layouter.namespace(|| "token auth vp vk"),
+            config.advices[0],
+            *COMPRESSED_TOKEN_AUTH_VK,
+        )?;
+        let constant_receiver_vp_vk = assign_free_constant(
+            layouter.namespace(|| "receiver vp vk"),
+            config.advices[0],
+            *COMPRESSED_RECEIVER_VK,
+        )?;
+        let issuer_app_data_dynamic = TOKEN_AUTHORIZATION_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "issuer app_data_dynamic encoding"),
+            &[
+                issuer.inner().x(),
+                issuer.inner().y(),
+                token_auth_vp_vk,
+                constant_receiver_vp_vk,
+                issuer.inner().x(),
+                issuer.inner().y(),
+            ],
+        )?;
+        let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+        let mul_chip = MulChip::construct(config.mul_config.clone());
+        let is_ephemeral = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "1 - is_merkle_checked"),
+            &constant_one,
+            &note_variables.is_merkle_checked,
+        )?;
+        layouter.assign_region(
+            || "conditional equal: check the issuer auth",
+            |mut region| {
+                config.conditional_equal_config.assign_region(
+                    &is_ephemeral,
+                    &note_variables.app_data_dynamic,
+                    &issuer_app_data_dynamic,
+                    0,
+                    &mut region,
+                )
+            },
+        )?;
+
+        // The mint note is fixed by the nft: nk_com = mint_nk_com, rho = app_data_static,
+        // psi = rcm = 0
+        let is_mint = MulInstructions::mul(
+            &mul_chip,
+            layouter.namespace(|| "is_input_note * is_ephemeral"),
+            &owned_note.is_input_note,
+            &is_ephemeral,
+        )?;
+        for (name, lhs, rhs) in [
+            ("nk_com", &note_variables.nk_com, &mint_nk_com),
+            ("rho", &note_variables.rho, &note_variables.app_data_static),
+            ("psi", &note_variables.psi, &constant_zero),
+            ("rcm", &note_variables.rcm, &constant_zero),
+        ] {
+            layouter.assign_region(
+                || format!("conditional equal: check the mint note {}", name),
+                |mut region| {
+                    config.conditional_equal_config.assign_region(
+                        &is_mint,
+                        lhs,
+                        rhs,
+                        0,
+                        &mut region,
+                    )
+                },
+            )?;
+        }
+
+        // VP Commitment, the same as the token vp
+        // Commit the sender(authorization method included) vp if it's an input note;
+        // Commit the receiver(note encryption constraints included) vp if it's an output note;
+        // Commit the issuer auth vp if it's an ephemeral note.
+        let first_dynamic_vp = {
+            let transfer_vp = layouter.assign_region(
+                || "conditional select: ",
+                |mut region| {
+                    config.conditional_select_config.assign_region(
+                        &owned_note.is_input_note,
+                        &auth_vp_vk,
+                        &receiver_vp_vk,
+                        0,
+                        &mut region,
+                    )
+                },
+            )?;
+            layouter.assign_region(
+                || "conditional select: ",
+                |mut region| {
+                    config.conditional_select_config.assign_region(
+                        &note_variables.is_merkle_checked,
+                        &transfer_vp,
+                        &auth_vp_vk,
+                        0,
+                        &mut region,
+                    )
+                },
+            )?
+        };
+
+        // The receiver vp encrypts the owned note if it's a transferred output note
+        let encryption_vp_vk = {
+            let output_vp = layouter.assign_region(
+                || "conditional select: ",
+                |mut region| {
+                    config.conditional_select_config.assign_region(
+                        &owned_note.is_input_note,
+                        &constant_zero,
+                        &receiver_vp_vk,
+                        0,
+                        &mut region,
+                    )
+                },
+            )?;
+            layouter.assign_region(
+                || "conditional select: ",
+                |mut region| {
+                    config.conditional_select_config.assign_region(
+                        &note_variables.is_merkle_checked,
+                        &output_vp,
+                        &constant_zero,
+                        0,
+                        &mut region,
+                    )
+                },
+            )?
+        };
+        layouter.assign_region(
+            || "check encryption vp vk",
+            |mut region| {
+                region.constrain_equal(
+                    encryption_vp_vk.cell(),
+                    basic_variables.encryption_vp_vk.cell(),
+                )
+            },
+        )?;
+
+        let vp_cm_r = assign_free_advice(
+            layouter.namespace(|| "vp_cm_r"),
+            config.advices[0],
+            Value::known(self.rseed.get_vp_cm_r(PRF_EXPAND_DYNAMIC_VP_1_CM_R)),
+        )?;
+        let first_dynamic_vp_cm = dynamic_vp_commitment_gadget(
+            &mut layouter,
+            config.blake2s_config,
+            first_dynamic_vp,
+            vp_cm_r,
+        )?;
+        layouter.constrain_instance(
+            first_dynamic_vp_cm[0].cell(),
+            config.instances,
+            VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1,
+        )?;
+        layouter.constrain_instance(
+            first_dynamic_vp_cm[1].cell(),
+            config.instances,
+            VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2,
+        )?;
+
+        // Publicize the second dynamic vp commitment with default value
+        let vp_cm_fields: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        let vp_cm_1 = assign_free_advice(
+            layouter.namespace(|| "vp_cm 1"),
+            config.advices[0],
+            Value::known(vp_cm_fields[0]),
+        )?;
+        let vp_cm_2 = assign_free_advice(
+            layouter.namespace(|| "vp_cm 2"),
+            config.advices[0],
+            Value::known(vp_cm_fields[1]),
+        )?;
+        layouter.constrain_instance(
+            vp_cm_1.cell(),
+            config.instances,
+            VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_1,
+        )?;
+        layouter.constrain_instance(
+            vp_cm_2.cell(),
+            config.instances,
+            VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_2,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let owned_output_note = self
+            .output_notes
+            .iter()
+            .find(|note| note.commitment().inner() == self.owned_note_pub_id);
+        let dynamic_vp = match owned_output_note {
+            Some(note) if note.is_merkle_checked => self.receiver_vp_vk,
+            _ => self.auth.vk,
+        };
+
+        let vp_com_r = self.rseed.get_vp_cm_r(PRF_EXPAND_DYNAMIC_VP_1_CM_R);
+        let vp_com: [pallas::Base; 2] =
+            ValidityPredicateCommitment::commit(&dynamic_vp, &vp_com_r).to_public_inputs();
+
+        public_inputs.extend(vp_com);
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+
+    fn get_encryption_vp_vk(&self) -> Option<pallas::Base> {
+        let is_transferred_output = self.output_notes.iter().any(|note| {
+            note.commitment().inner() == self.owned_note_pub_id && note.is_merkle_checked
+        });
+        Some(if is_transferred_output {
+            self.receiver_vp_vk
+        } else {
+            pallas::Base::zero()
+        })
+    }
+}
+
+vp_circuit_impl!(NftValidityPredicateCircuit);
+vp_verifying_info_impl!(NftValidityPredicateCircuit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::tests::{random_input_note, random_output_note};
+
+    fn random_collection<R: RngCore>(mut rng: R) -> (NftCollection, SigningKey, pallas::Base) {
+        let issuer_sk = SigningKey::random(&mut rng);
+        let mint_nk = pallas::Base::random(&mut rng);
+        let mint_nk_com = NullifierKeyContainer::from_key(mint_nk).get_commitment();
+        let collection =
+            NftCollection::new("punks".to_string(), issuer_sk.public_key(), mint_nk_com);
+        (collection, issuer_sk, mint_nk)
+    }
+
+    fn nft_vp(
+        owned_note_pub_id: pallas::Base,
+        nft: &Nft,
+        auth: TokenAuthorization,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> NftValidityPredicateCircuit {
+        NftValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            nft: nft.clone(),
+            auth,
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+            rseed: RandomSeed::random(OsRng),
+        }
+    }
+
+    fn check(circuit: &NftValidityPredicateCircuit) -> bool {
+        circuit.dev_prove_and_check(OsRng).is_ok()
+    }
+
+    // Transfer the nft from the input note 0 to the output note 0
+    fn transfer_notes(nft: &Nft) -> ([Note; NUM_NOTE], [Note; NUM_NOTE], TokenAuthorization) {
+        let mut rng = OsRng;
+        let auth = TokenAuthorization::random(&mut rng);
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let rho = Nullifier::random(&mut rng);
+        let input = nft.create_random_nft_note(&mut rng, rho, nk, &auth);
+        let output = nft.create_random_nft_note(
+            &mut rng,
+            input.get_nf().unwrap(),
+            nk.to_commitment(),
+            &auth,
+        );
+        let padding_input = random_input_note(&mut rng);
+        let padding_output = random_output_note(&mut rng, padding_input.get_nf().unwrap());
+        (
+            [*input.note(), padding_input],
+            [*output.note(), padding_output],
+            auth,
+        )
+    }
+
+    #[test]
+    fn test_halo2_nft_vp_transfer() {
+        let (collection, _, _) = random_collection(OsRng);
+        let nft = collection.item(pallas::Base::from(7));
+        let (input_notes, output_notes, auth) = transfer_notes(&nft);
+        let nf = input_notes[0].get_nf().unwrap().inner();
+        let cm = output_notes[0].commitment().inner();
+        assert!(check(&nft_vp(nf, &nft, auth, input_notes, output_notes)));
+        assert!(check(&nft_vp(cm, &nft, auth, input_notes, output_notes)));
+
+        // Another item of the collection
+        let other = collection.item(pallas::Base::from(8));
+        assert!(!check(&nft_vp(nf, &other, auth, input_notes, output_notes)));
+
+        // The value is not 1
+        let mut output_notes = output_notes;
+        output_notes[0].value = 2;
+        let cm = output_notes[0].commitment().inner();
+        assert!(!check(&nft_vp(cm, &nft, auth, input_notes, output_notes)));
+    }
+
+    #[test]
+    fn test_halo2_nft_vp_mint() {
+        let mut rng = OsRng;
+        let (collection, _, mint_nk) = random_collection(&mut rng);
+        let nft = collection.item(pallas::Base::from(7));
+        let mint_note = nft.create_mint_note(mint_nk);
+        let auth = TokenAuthorization::random(&mut rng);
+        let nk_com = NullifierKeyContainer::random_commitment(&mut rng);
+        let output =
+            nft.create_random_nft_note(&mut rng, mint_note.get_nf().unwrap(), nk_com, &auth);
+        let padding_input = random_input_note(&mut rng);
+        let padding_output = random_output_note(&mut rng, padding_input.get_nf().unwrap());
+        let input_notes = [*mint_note.note(), padding_input];
+        let output_notes = [*output.note(), padding_output];
+        let nf = mint_note.get_nf().unwrap().inner();
+        let cm = output.commitment().inner();
+        assert!(check(&nft_vp(
+            nf,
+            &nft,
+            nft.issuer_auth(),
+            input_notes,
+            output_notes
+        )));
+        assert!(check(&nft_vp(cm, &nft, auth, input_notes, output_notes)));
+
+        // An ephemeral note with a fresh rho would give another mint nullifier
+        let mut input_notes = input_notes;
+        input_notes[0] = nft
+            .create_random_nft_note(
+                &mut rng,
+                Nullifier::random(&mut rng),
+                NullifierKeyContainer::from_key(mint_nk),
+                &nft.issuer_auth(),
+            )
+            .note;
+        input_notes[0].is_merkle_checked = false;
+        let nf = input_notes[0].get_nf().unwrap().inner();
+        assert!(!check(&nft_vp(
+            nf,
+            &nft,
+            nft.issuer_auth(),
+            input_notes,
+            output_notes
+        )));
+
+        // A mint note not authorized by the issuer
+        let mut input_notes = [*mint_note.note(), padding_input];
+        input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
+        let nf = input_notes[0].get_nf().unwrap().inner();
+        assert!(!check(&nft_vp(nf, &nft, auth, input_notes, output_notes)));
+    }
+
+    #[test]
+    fn test_halo2_nft_vp_split() {
+        let mut rng = OsRng;
+        let (collection, _, _) = random_collection(&mut rng);
+        let nft = collection.item(pallas::Base::from(7));
+        let (input_notes, mut output_notes, auth) = transfer_notes(&nft);
+        // The second output copies the nft, but it's paired with the padding input note
+        output_notes[1] = nft
+            .create_random_nft_note(
+                &mut rng,
+                input_notes[1].get_nf().unwrap(),
+                NullifierKeyContainer::random_commitment(&mut rng),
+                &auth,
+            )
+            .note;
+        let cm = output_notes[1].commitment().inner();
+        assert!(!check(&nft_vp(cm, &nft, auth, input_notes, output_notes)));
+    }
+
+    #[test]
+    fn test_halo2_nft_vp_two_items() {
+        let mut rng = OsRng;
+        let (collection, _, _) = random_collection(&mut rng);
+        let nfts = [1, 2].map(|id| collection.item(pallas::Base::from(id)));
+        let auth = TokenAuthorization::random(&mut rng);
+        let input_notes = nfts.clone().map(|nft| {
+            let nk = NullifierKeyContainer::random_key(&mut rng);
+            nft.create_random_nft_note(&mut rng, Nullifier::random(&mut rng), nk, &auth)
+                .note
+        });
+        let create_outputs = |items: [&Nft; NUM_NOTE]| {
+            let mut rng = OsRng;
+            let mut notes = input_notes;
+            for ((note, input), nft) in notes.iter_mut().zip(input_notes.iter()).zip(items) {
+                let nk_com = NullifierKeyContainer::random_commitment(&mut rng);
+                *note = nft
+                    .create_random_nft_note(&mut rng, input.get_nf().unwrap(), nk_com, &auth)
+                    .note;
+            }
+            notes
+        };
+
+        // Each item goes to the output note of its action
+        let output_notes = create_outputs([&nfts[0], &nfts[1]]);
+        for (i, nft) in nfts.iter().enumerate() {
+            let nf = input_notes[i].get_nf().unwrap().inner();
+            let cm = output_notes[i].commitment().inner();
+            assert!(check(&nft_vp(nf, nft, auth, input_notes, output_notes)));
+            assert!(check(&nft_vp(cm, nft, auth, input_notes, output_notes)));
+        }
+
+        // The items are swapped between the actions
+        let output_notes = create_outputs([&nfts[1], &nfts[0]]);
+        for (i, nft) in nfts.iter().enumerate() {
+            let nf = input_notes[i].get_nf().unwrap().inner();
+            assert!(!check(&nft_vp(nf, nft, auth, input_notes, output_notes)));
+        }
+    }
+}
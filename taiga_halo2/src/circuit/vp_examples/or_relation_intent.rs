@@ -16,7 +16,7 @@ use crate::{
         },
         vp_examples::token::{Token, TOKEN_VK},
     },
-    constant::{NUM_NOTE, SETUP_PARAMS_MAP},
+    constant::NUM_NOTE,
     note::{Note, RandomSeed},
     nullifier::{Nullifier, NullifierKeyContainer},
     proof::Proof,
@@ -32,6 +32,7 @@ use lazy_static::lazy_static;
 use pasta_curves::pallas;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use std::sync::Arc;
 
 lazy_static! {
     pub static ref OR_RELATION_INTENT_VK: ValidityPredicateVerifyingKey =
@@ -44,8 +45,8 @@ lazy_static! {
 #[derive(Clone, Debug, Default)]
 pub struct OrRelationIntentValidityPredicateCircuit {
     pub owned_note_pub_id: pallas::Base,
-    pub input_notes: [Note; NUM_NOTE],
-    pub output_notes: [Note; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
     pub token_1: Token,
     pub token_2: Token,
     pub receiver_nk_com: pallas::Base,
@@ -304,13 +305,9 @@ pub fn create_intent_note<R: RngCore>(
 
 #[test]
 fn test_halo2_or_relation_intent_vp_circuit() {
-    use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
-    use crate::{
-        circuit::vp_examples::token::COMPRESSED_TOKEN_VK, note::tests::random_output_note,
-        nullifier::tests::random_nullifier,
-    };
+    use crate::circuit::vp_examples::token::TokenName;
+    use crate::{note::tests::random_output_note, nullifier::tests::random_nullifier};
     use halo2_proofs::arithmetic::Field;
-    use halo2_proofs::dev::MockProver;
     use rand::rngs::OsRng;
 
     let mut rng = OsRng;
@@ -319,10 +316,9 @@ fn test_halo2_or_relation_intent_vp_circuit() {
             let padding_rho = random_nullifier(&mut rng);
             random_output_note(&mut rng, padding_rho)
         });
-        let token_1 = Token::new("token1".to_string(), 1u64);
-        let token_2 = Token::new("token2".to_string(), 2u64);
-        output_notes[0].note_type.app_vk = *COMPRESSED_TOKEN_VK;
-        output_notes[0].note_type.app_data_static = token_1.encode_name();
+        let token_1 = Token::new(TokenName::new("token1").unwrap(), 1u64);
+        let token_2 = Token::new(TokenName::new("token2").unwrap(), 2u64);
+        output_notes[0].note_type = token_1.note_type();
         output_notes[0].value = token_1.value();
 
         let rho = Nullifier::from(pallas::Base::random(&mut rng));
@@ -341,21 +337,13 @@ fn test_halo2_or_relation_intent_vp_circuit() {
         let input_notes = [intent_note, padding_input_note];
         OrRelationIntentValidityPredicateCircuit {
             owned_note_pub_id: input_notes[0].get_nf().unwrap().inner(),
-            input_notes,
-            output_notes,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
             token_1,
             token_2,
             receiver_nk_com: nk_com,
             receiver_app_data_dynamic: output_notes[0].app_data_dynamic,
         }
     };
-    let public_inputs = circuit.get_public_inputs(&mut rng);
-
-    let prover = MockProver::<pallas::Base>::run(
-        VP_CIRCUIT_PARAMS_SIZE,
-        &circuit,
-        vec![public_inputs.to_vec()],
-    )
-    .unwrap();
-    assert_eq!(prover.verify(), Ok(()));
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
 }
@@ -1,42 +1,71 @@
 /// The intent can be partially fulfilled.
 /// For example, Alice has 5 BTC and wants 10 ETH.
 /// Alice utilizes this intent to do a partial swap in proportion. She can exchange 2 BTC for 4 ETH and get 3 BTC back.
+/// Alice can also cancel the intent with her signature and get the 5 BTC back.
+/// Instead of getting the 3 BTC back, a partial fill can keep them for sale in a residual intent
+/// note for 6 ETH, the residual is filled by the next solvers.
+/// The intent can ask for any one of several tokens, e.g. 10 ETH or 500 XAN. A fill buys a single
+/// ask, and the partial fill ratio is the one of that ask.
+/// The solver of a fill can keep a fee out of the pro-rata value, up to the max fee in basis points
+/// committed in the intent. The fee is a public input so that it can be audited.
 ///
 use crate::{
     circuit::{
         blake2s::publicize_default_dynamic_vp_commitments,
         gadgets::{
-            assign_free_constant,
-            mul::MulChip,
+            add::{AddChip, AddInstructions},
+            assign_free_advice, assign_free_constant,
+            mul::{MulChip, MulInstructions},
+            poseidon_hash::poseidon_hash_gadget,
             sub::{SubChip, SubInstructions},
-            target_note_variable::{get_is_input_note_flag, get_owned_note_variable},
+            target_note_variable::get_owned_note_variables,
         },
+        integrity::check_value_range,
         vp_circuit::{
             BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
             ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
         },
+        vp_examples::{
+            receiver_vp::COMPRESSED_RECEIVER_VK,
+            signature_verification::{
+                schnorr_challenge_gadget, signing_message_gadget, SchnorrSignature,
+                SIGNED_ANCHORS_PUBLIC_INPUT_IDX,
+            },
+        },
     },
-    constant::{NUM_NOTE, SETUP_PARAMS_MAP},
+    constant::{
+        TaigaFixedBasesFull, APP_DATA_DYNAMIC_DOMAIN, NUM_NOTE,
+        VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX,
+    },
+    merkle_tree::Anchor,
     note::{Note, RandomSeed},
     proof::Proof,
     vp_commitment::ValidityPredicateCommitment,
     vp_vk::ValidityPredicateVerifyingKey,
 };
+use halo2_gadgets::ecc::{chip::EccChip, FixedPoint, NonIdentityPoint, ScalarFixed};
 use halo2_proofs::{
-    circuit::{floor_planner, Layouter},
+    circuit::{floor_planner, AssignedCell, Layouter, Value},
     plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
 };
 use lazy_static::lazy_static;
-use pasta_curves::pallas;
+use pasta_curves::{group::Curve, pallas};
 use rand::rngs::OsRng;
 use rand::RngCore;
+use std::sync::Arc;
 
 pub mod swap;
-pub use swap::Swap;
+pub use swap::{
+    EncryptedSwapTerms, IntentAdvert, ResidualFill, Swap, SwapBuilder, SwapError,
+    MAX_SOLVER_FEE_BPS, MAX_SWAP_ASKS,
+};
 
 mod data_static;
 use data_static::PartialFulfillmentIntentDataStatic;
 
+/// The solver fee of a fill follows the anchors in the custom public inputs, it's 0 otherwise.
+pub const SOLVER_FEE_PUBLIC_INPUT_IDX: usize = VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX + NUM_NOTE;
+
 lazy_static! {
     pub static ref PARTIAL_FULFILLMENT_INTENT_VK: ValidityPredicateVerifyingKey =
         PartialFulfillmentIntentValidityPredicateCircuit::default().get_vp_vk();
@@ -48,9 +77,211 @@ lazy_static! {
 #[derive(Clone, Debug, Default)]
 pub struct PartialFulfillmentIntentValidityPredicateCircuit {
     pub owned_note_pub_id: pallas::Base,
-    pub input_notes: [Note; NUM_NOTE],
-    pub output_notes: [Note; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
     pub swap: Swap,
+    // The creator's signature on the cancellation, None if the intent is filled
+    pub cancel_signature: Option<SchnorrSignature>,
+    // The anchors of the input notes signed in the cancellation
+    pub anchors: [Anchor; NUM_NOTE],
+    // The swaps of a partial fill keeping the rest open in a residual intent note, the same for
+    // the vps of both intent notes
+    pub residual_fill: Option<ResidualFill>,
+}
+
+impl PartialFulfillmentIntentValidityPredicateCircuit {
+    /// The vp of the intent note created or filled in the notes, without cancel signature or
+    /// residual fill. The swap is validated, an unsatisfiable one fails here rather than in the
+    /// proving.
+    pub fn new(
+        owned_note_pub_id: pallas::Base,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+        swap: Swap,
+    ) -> Result<Self, SwapError> {
+        swap.validate()?;
+        Ok(Self {
+            owned_note_pub_id,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            swap,
+            cancel_signature: None,
+            anchors: [Anchor::default(); NUM_NOTE],
+            residual_fill: None,
+        })
+    }
+
+    // Verify the cancel signature against the creator pk encoded in receiver_app_data_dynamic
+    fn check_cancel_signature(
+        &self,
+        config: &ValidityPredicateConfig,
+        is_cancel: &AssignedCell<pallas::Base, pallas::Base>,
+        receiver_app_data_dynamic: &AssignedCell<pallas::Base, pallas::Base>,
+        basic_variables: &BasicValidityPredicateVariables,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        let signature = self.cancel_signature.clone().unwrap_or_default();
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
+
+        let pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness pk"),
+            Value::known(signature.pk.to_affine()),
+        )?;
+        let rcv_pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness rcv_pk"),
+            Value::known(self.swap.auth.rcv_pk.to_affine()),
+        )?;
+
+        // The pk must be the creator's
+        let encoded_auth = {
+            let app_data_dynamic_domain = assign_free_constant(
+                layouter.namespace(|| "app_data_dynamic domain"),
+                config.advices[0],
+                APP_DATA_DYNAMIC_DOMAIN,
+            )?;
+            let auth_vp_vk = assign_free_advice(
+                layouter.namespace(|| "witness auth vp vk"),
+                config.advices[0],
+                Value::known(self.swap.auth.vk),
+            )?;
+            let receiver_vp_vk = assign_free_advice(
+                layouter.namespace(|| "witness receiver vp vk"),
+                config.advices[0],
+                Value::known(*COMPRESSED_RECEIVER_VK),
+            )?;
+            poseidon_hash_gadget(
+                config.poseidon_config.clone(),
+                layouter.namespace(|| "auth encoding"),
+                [
+                    app_data_dynamic_domain,
+                    pk.inner().x(),
+                    pk.inner().y(),
+                    auth_vp_vk,
+                    receiver_vp_vk,
+                    rcv_pk.inner().x(),
+                    rcv_pk.inner().y(),
+                ],
+            )?
+        };
+        config.conditional_config.assert_equal_if(
+            layouter.namespace(|| "check creator pk"),
+            is_cancel,
+            &encoded_auth,
+            receiver_app_data_dynamic,
+        )?;
+
+        let r = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness r"),
+            Value::known(signature.r.to_affine()),
+        )?;
+        let s_scalar = ScalarFixed::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness s"),
+            Value::known(signature.s),
+        )?;
+
+        // s*G
+        let generator =
+            FixedPoint::from_inner(ecc_chip.clone(), TaigaFixedBasesFull::BaseGenerator);
+        let (s_g, _) = generator.mul(layouter.namespace(|| "s_scalar * generator"), &s_scalar)?;
+
+        // Hash(r||P||m), the message is the same as in the signature verification vp
+        let message = signing_message_gadget(
+            config,
+            layouter.namespace(|| "signing message"),
+            basic_variables,
+            &self.anchors,
+        )?;
+        let h_scalar = schnorr_challenge_gadget(
+            config,
+            layouter.namespace(|| "schnorr challenge"),
+            &r,
+            &pk,
+            &message,
+        )?;
+
+        // R + Hash(r||P||m)*P
+        let (h_p, _) = pk.mul(layouter.namespace(|| "hP"), h_scalar)?;
+        let rhs = r.add(layouter.namespace(|| "R + Hash(r||P||m)*P"), &h_p)?;
+
+        // Check s*G = R + Hash(r||P||m)*P if is_cancel == 1
+        config.conditional_config.assert_equal_if(
+            layouter.namespace(|| "check signature x"),
+            is_cancel,
+            &s_g.inner().x(),
+            &rhs.inner().x(),
+        )?;
+        config.conditional_config.assert_equal_if(
+            layouter.namespace(|| "check signature y"),
+            is_cancel,
+            &s_g.inner().y(),
+            &rhs.inner().y(),
+        )
+    }
+
+    // The index of the bought ask in the padded asks, the first ask of the bought note token.
+    // It's 0 if no ask matches, the fill checks fail then.
+    fn ask_index(&self) -> usize {
+        let bought_token = self.output_notes[0].get_app_data_static();
+        self.swap
+            .padded_asks()
+            .iter()
+            .position(|ask| ask.encode_name() == bought_token)
+            .unwrap_or_default()
+    }
+
+    // A fill is partial if it leaves some of the sold token, returned or in a residual intent
+    fn is_partial_fill(&self) -> bool {
+        self.cancel_signature.is_none() && self.remaining_value() != 0
+    }
+
+    // The owned note is the consumed intent note, and the intent is not cancelled
+    fn is_fill(&self) -> bool {
+        self.cancel_signature.is_none()
+            && self
+                .input_notes
+                .iter()
+                .any(|note| note.get_nf().ok().map(|nf| nf.inner()) == Some(self.owned_note_pub_id))
+    }
+
+    // The value of the bought ask in proportion to the sold value of the fill
+    fn pro_rata_value(&self) -> u64 {
+        let ask_value = self.swap.padded_asks()[self.ask_index()].value();
+        let sold_value = self.swap.sell.value.saturating_sub(self.remaining_value());
+        (ask_value as u128 * sold_value as u128)
+            .checked_div(self.swap.sell.value as u128)
+            .unwrap_or_default() as u64
+    }
+
+    /// The part of the pro-rata value kept by the solver of a fill, 0 if the intent is not filled.
+    pub fn solver_fee(&self) -> u64 {
+        if !self.is_fill() {
+            return 0;
+        }
+        self.pro_rata_value()
+            .saturating_sub(self.output_notes[0].value)
+    }
+
+    // The quotient and the remainder of the max fee of the paid value, see `solver_fee_checks`
+    fn allowed_fee(&self) -> (u64, u64) {
+        let paid_value = self.output_notes[0].value as u128 + self.solver_fee() as u128;
+        let max_fee = paid_value * self.swap.max_solver_fee_bps as u128;
+        (
+            (max_fee / MAX_SOLVER_FEE_BPS as u128) as u64,
+            (max_fee % MAX_SOLVER_FEE_BPS as u128) as u64,
+        )
+    }
+
+    // The sold value left by a partial fill, in the residual intent or in the returned note
+    fn remaining_value(&self) -> u64 {
+        match &self.residual_fill {
+            Some(residual_fill) => residual_fill.residual.sell.value,
+            None => self.output_notes[1].value,
+        }
+    }
 }
 
 impl ValidityPredicateCircuit for PartialFulfillmentIntentValidityPredicateCircuit {
@@ -61,27 +292,28 @@ impl ValidityPredicateCircuit for PartialFulfillmentIntentValidityPredicateCircu
         mut layouter: impl Layouter<pallas::Base>,
         basic_variables: BasicValidityPredicateVariables,
     ) -> Result<(), Error> {
+        let add_chip = AddChip::construct(config.add_config.clone(), ());
         let sub_chip = SubChip::construct(config.sub_config.clone(), ());
         let mul_chip = MulChip::construct(config.mul_config.clone());
 
-        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
-
         let app_data_static = self.swap.assign_app_data_static(
             config.advices[0],
+            self.ask_index(),
             layouter.namespace(|| "assign app_data_static"),
         )?;
         let encoded_app_data_static = app_data_static.encode(
             config.poseidon_config.clone(),
+            config.advices[0],
             layouter.namespace(|| "encode app_data_static"),
         )?;
 
         // search target note and get the intent app_static_data
-        let owned_note_app_data_static = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note app_data_static"),
-            &owned_note_pub_id,
-            &basic_variables.get_app_data_static_searchable_pairs(),
+        let owned_note = get_owned_note_variables(
+            &config,
+            layouter.namespace(|| "get owned note variables"),
+            &basic_variables,
         )?;
+        let owned_note_app_data_static = owned_note.note_variables.app_data_static;
 
         // Enforce consistency of app_data_static:
         //  - as witnessed in the swap, and
@@ -96,51 +328,240 @@ impl ValidityPredicateCircuit for PartialFulfillmentIntentValidityPredicateCircu
             },
         )?;
 
-        let is_input_note = get_is_input_note_flag(
-            config.get_is_input_note_flag_config,
-            layouter.namespace(|| "get is_input_note_flag"),
-            &owned_note_pub_id,
-            &basic_variables.get_input_note_nfs(),
-            &basic_variables.get_output_note_cms(),
+        // The bought token and value are the ones of a committed ask
+        app_data_static.ask_membership_checks(
+            &config.conditional_config,
+            &add_chip,
+            config.advices[0],
+            layouter.namespace(|| "ask membership checks"),
         )?;
-        // Conditional checks if is_input_note == 1
-        app_data_static.is_input_note_checks(
+
+        let is_input_note = owned_note.is_input_note;
+
+        // The intent is either filled or cancelled when it's consumed
+        let is_cancel = {
+            let cancel_flag = assign_free_advice(
+                layouter.namespace(|| "witness cancel flag"),
+                config.advices[0],
+                Value::known(pallas::Base::from(self.cancel_signature.is_some() as u64)),
+            )?;
+            MulInstructions::mul(
+                &mul_chip,
+                layouter.namespace(|| "is_input * cancel_flag"),
+                &is_input_note,
+                &cancel_flag,
+            )?
+        };
+        let is_fill = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "is_input - is_cancel"),
             &is_input_note,
-            &basic_variables,
-            &config.conditional_equal_config,
-            layouter.namespace(|| "is_input_note checks"),
+            &is_cancel,
         )?;
 
-        let is_output_note = {
-            let constant_one = assign_free_constant(
-                layouter.namespace(|| "one"),
+        // A filled intent is either fully or partially filled
+        let is_partial_fill = {
+            let partial_flag = assign_free_advice(
+                layouter.namespace(|| "witness partial flag"),
                 config.advices[0],
-                pallas::Base::one(),
+                Value::known(pallas::Base::from(self.is_partial_fill() as u64)),
             )?;
-            // TODO: use a nor gate to replace the sub gate.
-            SubInstructions::sub(
-                &sub_chip,
-                layouter.namespace(|| "expected_sold_value - returned_value"),
-                &is_input_note,
-                &constant_one,
+            MulInstructions::mul(
+                &mul_chip,
+                layouter.namespace(|| "is_fill * partial_flag"),
+                &is_fill,
+                &partial_flag,
+            )?
+        };
+
+        // A partial fill either returns the rest of the sold token or keeps it open in a residual
+        // intent note. The residual intent note is created by a residual fill only.
+        let residual_flag = assign_free_advice(
+            layouter.namespace(|| "witness residual flag"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.residual_fill.is_some() as u64)),
+        )?;
+        let is_residual_fill = MulInstructions::mul(
+            &mul_chip,
+            layouter.namespace(|| "is_partial_fill * residual_flag"),
+            &is_partial_fill,
+            &residual_flag,
+        )?;
+        let is_returned = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "is_partial_fill - is_residual_fill"),
+            &is_partial_fill,
+            &is_residual_fill,
+        )?;
+        let is_residual_intent = MulInstructions::mul(
+            &mul_chip,
+            layouter.namespace(|| "is_output_note * residual_flag"),
+            &owned_note.is_output_note,
+            &residual_flag,
+        )?;
+        let is_new_intent = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "is_output_note - is_residual_intent"),
+            &owned_note.is_output_note,
+            &is_residual_intent,
+        )?;
+        let is_residual = AddInstructions::add(
+            &add_chip,
+            layouter.namespace(|| "is_residual_fill + is_residual_intent"),
+            &is_residual_fill,
+            &is_residual_intent,
+        )?;
+
+        let remaining_value = assign_free_advice(
+            layouter.namespace(|| "witness remaining value"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.remaining_value())),
+        )?;
+        check_value_range(
+            layouter.namespace(|| "range check remaining value"),
+            &config.range_check,
+            &remaining_value,
+        )?;
+
+        // The solver keeps the fee out of the pro-rata value of a fill, the creator gets the rest
+        let solver_fee = {
+            let fee = assign_free_advice(
+                layouter.namespace(|| "witness solver fee"),
+                config.advices[0],
+                Value::known(pallas::Base::from(self.solver_fee())),
+            )?;
+            check_value_range(
+                layouter.namespace(|| "range check solver fee"),
+                &config.range_check,
+                &fee,
+            )?;
+            MulInstructions::mul(
+                &mul_chip,
+                layouter.namespace(|| "is_fill * fee"),
+                &is_fill,
+                &fee,
             )?
         };
-        // Conditional checks if is_output_note == 1
+        layouter.constrain_instance(
+            solver_fee.cell(),
+            config.instances,
+            SOLVER_FEE_PUBLIC_INPUT_IDX,
+        )?;
+        let paid_value = AddInstructions::add(
+            &add_chip,
+            layouter.namespace(|| "bought value + solver fee"),
+            &basic_variables.output_note_variables[0]
+                .note_variables
+                .value,
+            &solver_fee,
+        )?;
+        let (allowed_fee, fee_remainder) = self.allowed_fee();
+        let allowed_fee = assign_free_advice(
+            layouter.namespace(|| "witness allowed fee"),
+            config.advices[0],
+            Value::known(pallas::Base::from(allowed_fee)),
+        )?;
+        let fee_remainder = assign_free_advice(
+            layouter.namespace(|| "witness fee remainder"),
+            config.advices[0],
+            Value::known(pallas::Base::from(fee_remainder)),
+        )?;
+        for (name, value) in [
+            ("allowed fee", &allowed_fee),
+            ("fee remainder", &fee_remainder),
+        ] {
+            check_value_range(
+                layouter.namespace(|| format!("range check {name}")),
+                &config.range_check,
+                value,
+            )?;
+        }
+
+        // The conditional gadgets constrain the flags to be boolean
+        // Conditional checks if is_fill == 1
+        app_data_static.is_fill_checks(
+            &is_fill,
+            &basic_variables,
+            &config.conditional_config,
+            layouter.namespace(|| "is_fill checks"),
+        )?;
+        app_data_static.fill_branch_checks(
+            &is_fill,
+            &is_partial_fill,
+            &is_returned,
+            &paid_value,
+            &remaining_value,
+            &basic_variables,
+            &config.conditional_config,
+            &config.comparison_config,
+            &sub_chip,
+            &mul_chip,
+            layouter.namespace(|| "full or partial fill checks"),
+        )?;
+        app_data_static.solver_fee_checks(
+            &is_fill,
+            &paid_value,
+            &solver_fee,
+            &allowed_fee,
+            &fee_remainder,
+            &config.conditional_config,
+            &config.comparison_config,
+            &add_chip,
+            &mul_chip,
+            config.advices[0],
+            layouter.namespace(|| "solver fee checks"),
+        )?;
+
+        // Conditional checks if is_residual == 1, for the parent intent note and the residual one
+        let residual_fill = self.residual_fill.clone().unwrap_or_default();
+        let parent_data_static = residual_fill.parent.assign_app_data_static(
+            config.advices[0],
+            0,
+            layouter.namespace(|| "assign parent app_data_static"),
+        )?;
+        let residual_data_static = residual_fill.residual.assign_app_data_static(
+            config.advices[0],
+            0,
+            layouter.namespace(|| "assign residual app_data_static"),
+        )?;
+        parent_data_static.residual_checks(
+            &residual_data_static,
+            &is_residual,
+            &basic_variables,
+            &config.conditional_config,
+            config.poseidon_config.clone(),
+            &mul_chip,
+            config.advices[0],
+            layouter.namespace(|| "residual checks"),
+        )?;
+        config.conditional_config.assert_equal_if(
+            layouter.namespace(|| "check the remaining value is in the residual"),
+            &is_residual_fill,
+            &remaining_value,
+            &residual_data_static.sold_token_value,
+        )?;
+
+        // Conditional checks if the owned note is a new intent note
         app_data_static.is_output_note_checks(
-            &is_output_note,
+            &is_new_intent,
             &basic_variables,
-            &config.conditional_equal_config,
+            &config.conditional_config,
             layouter.namespace(|| "is_output_note checks"),
         )?;
 
-        // Conditional checks if is_partial_fulfillment == 1
-        app_data_static.is_partial_fulfillment_checks(
-            &is_input_note,
+        // Conditional checks if is_cancel == 1
+        app_data_static.is_cancel_checks(
+            &is_cancel,
             &basic_variables,
-            &config.conditional_equal_config,
-            &sub_chip,
-            &mul_chip,
-            layouter.namespace(|| "is_partial_fulfillment checks"),
+            &config.conditional_config,
+            layouter.namespace(|| "is_cancel checks"),
+        )?;
+        self.check_cancel_signature(
+            &config,
+            &is_cancel,
+            &app_data_static.receiver_app_data_dynamic,
+            &basic_variables,
+            layouter.namespace(|| "check cancel signature"),
         )?;
 
         // Publicize the dynamic vp commitments with default value
@@ -167,6 +588,8 @@ impl ValidityPredicateCircuit for PartialFulfillmentIntentValidityPredicateCircu
             ValidityPredicateCommitment::default().to_public_inputs();
         public_inputs.extend(default_vp_cm);
         public_inputs.extend(default_vp_cm);
+        public_inputs.extend(self.anchors.map(|anchor| anchor.inner()));
+        public_inputs.push(pallas::Base::from(self.solver_fee()));
         let padding = ValidityPredicatePublicInputs::get_public_input_padding(
             public_inputs.len(),
             &RandomSeed::random(&mut rng),
@@ -178,6 +601,10 @@ impl ValidityPredicateCircuit for PartialFulfillmentIntentValidityPredicateCircu
     fn get_owned_note_pub_id(&self) -> pallas::Base {
         self.owned_note_pub_id
     }
+
+    fn get_signed_anchors_public_input_idx(&self) -> Option<usize> {
+        Some(SIGNED_ANCHORS_PUBLIC_INPUT_IDX)
+    }
 }
 
 vp_circuit_impl!(PartialFulfillmentIntentValidityPredicateCircuit);
@@ -187,12 +614,12 @@ vp_verifying_info_impl!(PartialFulfillmentIntentValidityPredicateCircuit);
 mod tests {
     use super::*;
     use crate::circuit::vp_examples::{
-        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
-        token::{Token, TokenAuthorization},
+        signature_verification::{signing_message, COMPRESSED_TOKEN_AUTH_VK},
+        token::{Token, TokenAuthorization, TokenName},
     };
-    use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
+    use crate::keys::SigningKey;
+    use crate::note::NoteType;
     use halo2_proofs::arithmetic::Field;
-    use halo2_proofs::dev::MockProver;
     use rand::rngs::OsRng;
     use rand::RngCore;
 
@@ -204,13 +631,35 @@ mod tests {
         Swap::random(&mut rng, sell, buy, auth)
     }
 
+    // The intent vp consuming the intent note to cancel the swap, signed with sk
+    fn cancel_circuit(
+        mut rng: impl RngCore,
+        swap: Swap,
+        sk: &SigningKey,
+    ) -> PartialFulfillmentIntentValidityPredicateCircuit {
+        let intent_note = swap.create_intent_note(&mut rng);
+        let (input_notes, output_notes) = swap.cancel(&mut rng, intent_note);
+        let owned_note_pub_id = intent_note.get_nf().unwrap().inner();
+        let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+        let message = signing_message(&anchors, owned_note_pub_id, &input_notes, &output_notes);
+        PartialFulfillmentIntentValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            swap,
+            cancel_signature: Some(SchnorrSignature::sign(&mut rng, sk, message)),
+            anchors,
+            residual_fill: None,
+        }
+    }
+
     #[test]
     fn create_intent() {
         use crate::nullifier::Nullifier;
 
         let mut rng = OsRng;
-        let sell = Token::new("token1".to_string(), 2u64);
-        let buy = Token::new("token2".to_string(), 4u64);
+        let sell = Token::new(TokenName::new("token1").unwrap(), 2u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 4u64);
 
         let swap = swap(&mut rng, sell, buy);
         let intent_note = swap.create_intent_note(&mut rng);
@@ -224,76 +673,448 @@ mod tests {
 
         let circuit = PartialFulfillmentIntentValidityPredicateCircuit {
             owned_note_pub_id: intent_note.commitment().inner(),
-            input_notes,
-            output_notes,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
             swap,
+            cancel_signature: None,
+            anchors: [Anchor::default(); NUM_NOTE],
+            residual_fill: None,
         };
-        let public_inputs = circuit.get_public_inputs(&mut rng);
-
-        let prover = MockProver::<pallas::Base>::run(
-            VP_CIRCUIT_PARAMS_SIZE,
-            &circuit,
-            vec![public_inputs.to_vec()],
-        )
-        .unwrap();
-        prover.assert_satisfied();
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
     }
 
     #[test]
     fn full_fulfillment() {
         let mut rng = OsRng;
-        let sell = Token::new("token1".to_string(), 2u64);
-        let buy = Token::new("token2".to_string(), 4u64);
+        let sell = Token::new(TokenName::new("token1").unwrap(), 2u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 4u64);
 
         let swap = swap(&mut rng, sell, buy);
         let intent_note = swap.create_intent_note(&mut rng);
 
-        let bob_sell = swap.buy.clone();
+        let bob_sell = swap.asks[0].clone();
         let (input_notes, output_notes) = swap.fill(&mut rng, intent_note, bob_sell);
 
         let circuit = PartialFulfillmentIntentValidityPredicateCircuit {
             owned_note_pub_id: intent_note.get_nf().unwrap().inner(),
-            input_notes,
-            output_notes,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
             swap,
+            cancel_signature: None,
+            anchors: [Anchor::default(); NUM_NOTE],
+            residual_fill: None,
         };
-        let public_inputs = circuit.get_public_inputs(&mut rng);
-
-        let prover = MockProver::<pallas::Base>::run(
-            VP_CIRCUIT_PARAMS_SIZE,
-            &circuit,
-            vec![public_inputs.to_vec()],
-        )
-        .unwrap();
-        prover.assert_satisfied();
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
     }
 
     #[test]
     fn partial_fulfillment() {
         let mut rng = OsRng;
-        let sell = Token::new("token1".to_string(), 2u64);
-        let buy = Token::new("token2".to_string(), 4u64);
+        let sell = Token::new(TokenName::new("token1").unwrap(), 2u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 4u64);
 
         let swap = swap(&mut rng, sell, buy);
         let intent_note = swap.create_intent_note(&mut rng);
 
-        let bob_sell = Token::new(swap.buy.name().inner().to_string(), 2u64);
+        let bob_sell = Token::new(swap.asks[0].name().clone(), 2u64);
         let (input_notes, output_notes) = swap.fill(&mut rng, intent_note, bob_sell);
 
         let circuit = PartialFulfillmentIntentValidityPredicateCircuit {
             owned_note_pub_id: intent_note.get_nf().unwrap().inner(),
-            input_notes,
-            output_notes,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
             swap,
+            cancel_signature: None,
+            anchors: [Anchor::default(); NUM_NOTE],
+            residual_fill: None,
         };
-        let public_inputs = circuit.get_public_inputs(&mut rng);
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+    }
+
+    #[test]
+    fn partial_fulfillment_wrong_returned_value() {
+        let mut rng = OsRng;
+        let sell = Token::new(TokenName::new("token1").unwrap(), 2u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 4u64);
+
+        let swap = swap(&mut rng, sell, buy);
+        let intent_note = swap.create_intent_note(&mut rng);
+
+        let bob_sell = Token::new(swap.asks[0].name().clone(), 2u64);
+        let (input_notes, mut output_notes) = swap.fill(&mut rng, intent_note, bob_sell);
+        // The solver keeps more of the sold token than the ratio allows
+        output_notes[1].value -= 1;
 
-        let prover = MockProver::<pallas::Base>::run(
-            VP_CIRCUIT_PARAMS_SIZE,
-            &circuit,
-            vec![public_inputs.to_vec()],
+        let circuit = PartialFulfillmentIntentValidityPredicateCircuit {
+            owned_note_pub_id: intent_note.get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            swap,
+            cancel_signature: None,
+            anchors: [Anchor::default(); NUM_NOTE],
+            residual_fill: None,
+        };
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+    }
+
+    #[test]
+    fn unsatisfiable_swap() {
+        let mut rng = OsRng;
+        let sell = Token::new(TokenName::new("token1").unwrap(), 2u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 4u64);
+        let swap = swap(&mut rng, sell, buy);
+        let intent_note = swap.create_intent_note(&mut rng);
+        let (input_notes, output_notes) = swap.fill(
+            &mut rng,
+            intent_note,
+            Token::new(TokenName::new("token2").unwrap(), 4u64),
+        );
+        let owned_note_pub_id = intent_note.get_nf().unwrap().inner();
+
+        let circuit = PartialFulfillmentIntentValidityPredicateCircuit::new(
+            owned_note_pub_id,
+            input_notes,
+            output_notes,
+            swap.clone(),
         )
         .unwrap();
-        prover.assert_satisfied();
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+        // The vp of a swap buying the sold token is not constructed
+        let same_token_swap = Swap {
+            asks: vec![Token::new(TokenName::new("token1").unwrap(), 4u64)],
+            ..swap
+        };
+        assert_eq!(
+            PartialFulfillmentIntentValidityPredicateCircuit::new(
+                owned_note_pub_id,
+                input_notes,
+                output_notes,
+                same_token_swap,
+            )
+            .unwrap_err(),
+            SwapError::SameToken
+        );
+    }
+
+    // Alice sells 2 token1 for either 4 token2 or 10 token3
+    fn multi_ask_swap(mut rng: impl RngCore) -> Swap {
+        let sk = pallas::Scalar::random(&mut rng);
+        let auth = TokenAuthorization::from_sk_vk(&sk, &COMPRESSED_TOKEN_AUTH_VK);
+        Swap::random_with_asks(
+            &mut rng,
+            Token::new(TokenName::new("token1").unwrap(), 2u64),
+            vec![
+                Token::new(TokenName::new("token2").unwrap(), 4u64),
+                Token::new(TokenName::new("token3").unwrap(), 10u64),
+            ],
+            auth,
+        )
+    }
+
+    fn fill_circuit(
+        swap: Swap,
+        intent_note: Note,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> PartialFulfillmentIntentValidityPredicateCircuit {
+        PartialFulfillmentIntentValidityPredicateCircuit {
+            owned_note_pub_id: intent_note.get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            swap,
+            cancel_signature: None,
+            anchors: [Anchor::default(); NUM_NOTE],
+            residual_fill: None,
+        }
+    }
+
+    #[test]
+    fn multi_ask_fulfillment() {
+        let mut rng = OsRng;
+        let swap = multi_ask_swap(&mut rng);
+        let intent_note = swap.create_intent_note(&mut rng);
+
+        // Fully or partially fill the second ask
+        for value in [10u64, 5u64] {
+            let offer = Token::new(TokenName::new("token3").unwrap(), value);
+            let (input_notes, output_notes) = swap.fill(&mut rng, intent_note, offer);
+            let circuit = fill_circuit(swap.clone(), intent_note, input_notes, output_notes);
+            assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+        }
+    }
+
+    #[test]
+    fn multi_ask_invalid_fill() {
+        let mut rng = OsRng;
+        let swap = multi_ask_swap(&mut rng);
+        let intent_note = swap.create_intent_note(&mut rng);
+
+        // Fill the intent note with the terms of another swap
+        let fill_with_asks = |rng: &mut OsRng, asks: Vec<Token>, offer: Token| {
+            let other_swap = Swap {
+                asks,
+                ..swap.clone()
+            };
+            let (input_notes, output_notes) = other_swap.fill(rng, intent_note, offer);
+            fill_circuit(swap.clone(), intent_note, input_notes, output_notes)
+        };
+
+        // The bought token is not in the asks
+        let circuit = fill_with_asks(
+            &mut rng,
+            vec![Token::new(TokenName::new("token4").unwrap(), 4u64)],
+            Token::new(TokenName::new("token4").unwrap(), 4u64),
+        );
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+
+        // The bought value of a committed token is not the one of its ask
+        let circuit = fill_with_asks(
+            &mut rng,
+            vec![Token::new(TokenName::new("token3").unwrap(), 20u64)],
+            Token::new(TokenName::new("token3").unwrap(), 20u64),
+        );
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+
+        // Mixed fill: the second token at the first ask terms, 4 token3 for all the token1
+        let circuit = fill_with_asks(
+            &mut rng,
+            vec![Token::new(TokenName::new("token3").unwrap(), 4u64)],
+            Token::new(TokenName::new("token3").unwrap(), 4u64),
+        );
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+
+        // Mixed partial fill: 2 token3 for 1 token1 at the first ask ratio
+        let circuit = fill_with_asks(
+            &mut rng,
+            vec![Token::new(TokenName::new("token3").unwrap(), 4u64)],
+            Token::new(TokenName::new("token3").unwrap(), 2u64),
+        );
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+    }
+
+    // The vps of the parent intent note and of the residual intent note in a residual fill
+    fn residual_fill_circuits(
+        residual_fill: ResidualFill,
+        intent_note: Note,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> [PartialFulfillmentIntentValidityPredicateCircuit; 2] {
+        let parent = PartialFulfillmentIntentValidityPredicateCircuit {
+            residual_fill: Some(residual_fill.clone()),
+            ..fill_circuit(
+                residual_fill.parent.clone(),
+                intent_note,
+                input_notes,
+                output_notes,
+            )
+        };
+        let residual = PartialFulfillmentIntentValidityPredicateCircuit {
+            owned_note_pub_id: output_notes[1].commitment().inner(),
+            swap: residual_fill.residual.clone(),
+            ..parent.clone()
+        };
+        [parent, residual]
+    }
+
+    #[test]
+    fn residual_fulfillment() {
+        let mut rng = OsRng;
+        let sell = Token::new(TokenName::new("token1").unwrap(), 4u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 8u64);
+
+        let swap = swap(&mut rng, sell, buy);
+        let intent_note = swap.create_intent_note(&mut rng);
+
+        // The first solver buys 1 token1 and keeps the other 3 for sale
+        let first_offer = Token::new(TokenName::new("token2").unwrap(), 2u64);
+        let (residual_fill, input_notes, output_notes) =
+            swap.fill_with_residual(&mut rng, intent_note, first_offer.clone());
+        let residual = residual_fill.residual.clone();
+        assert_eq!(residual.sell.value, 3);
+        assert_eq!(residual.asks[0].value(), 6);
+        assert_eq!(residual.ratio(&residual.asks[0]), swap.ratio(&swap.asks[0]));
+        let residual_intent_note = output_notes[1];
+        assert_eq!(
+            residual_intent_note.get_app_data_static(),
+            residual.encode_app_data_static()
+        );
+        for circuit in residual_fill_circuits(residual_fill, intent_note, input_notes, output_notes)
+        {
+            assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+        }
+
+        // The second solver buys the residual
+        let second_offer = residual.asks[0].clone();
+        let (input_notes, output_notes) =
+            residual.fill(&mut rng, residual_intent_note, second_offer.clone());
+        let circuit = fill_circuit(residual, residual_intent_note, input_notes, output_notes);
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+        // Both fills buy the original ask
+        assert_eq!(
+            first_offer.value() + second_offer.value(),
+            swap.asks[0].value()
+        );
+    }
+
+    #[test]
+    fn residual_with_wrong_ratio() {
+        let mut rng = OsRng;
+        let sell = Token::new(TokenName::new("token1").unwrap(), 4u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 8u64);
+
+        let swap = swap(&mut rng, sell, buy);
+        let intent_note = swap.create_intent_note(&mut rng);
+
+        let offer = Token::new(TokenName::new("token2").unwrap(), 2u64);
+        let (mut residual_fill, input_notes, mut output_notes) =
+            swap.fill_with_residual(&mut rng, intent_note, offer);
+
+        // The residual asks 4 token2 for the 3 token1 left instead of 6
+        residual_fill.residual.asks = vec![Token::new(TokenName::new("token2").unwrap(), 4u64)];
+        output_notes[1] = Note {
+            note_type: NoteType {
+                app_data_static: residual_fill.residual.encode_app_data_static(),
+                ..output_notes[1].note_type
+            },
+            ..output_notes[1]
+        };
+        for circuit in residual_fill_circuits(residual_fill, intent_note, input_notes, output_notes)
+        {
+            assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+        }
+
+        // The residual keeps more of the sold token than the fill leaves, at the same ratio. The
+        // parent intent vp checks the remaining value.
+        let offer = Token::new(TokenName::new("token2").unwrap(), 2u64);
+        let (mut residual_fill, input_notes, mut output_notes) =
+            swap.fill_with_residual(&mut rng, intent_note, offer);
+        residual_fill.residual = swap.residual_after(0);
+        output_notes[1] = Note {
+            note_type: NoteType {
+                app_data_static: residual_fill.residual.encode_app_data_static(),
+                ..output_notes[1].note_type
+            },
+            ..output_notes[1]
+        };
+        let [parent, _] =
+            residual_fill_circuits(residual_fill, intent_note, input_notes, output_notes);
+        assert!(parent.dev_prove_and_check(&mut rng).is_err());
+    }
+
+    #[test]
+    fn solver_fee() {
+        let mut rng = OsRng;
+        let sell = Token::new(TokenName::new("token1").unwrap(), 2u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 8000u64);
+
+        // The solver can keep 25 bps of the fill
+        let swap = Swap {
+            max_solver_fee_bps: 25,
+            ..swap(&mut rng, sell, buy)
+        };
+        let intent_note = swap.create_intent_note(&mut rng);
+
+        // 20 of the full fill, 10 of the partial one
+        for (value, fee) in [(8000u64, 20u64), (4000, 10), (4000, 3)] {
+            let offer = Token::new(TokenName::new("token2").unwrap(), value);
+            let (input_notes, output_notes) = swap
+                .fill_with_solver_fee(&mut rng, intent_note, offer, fee)
+                .unwrap();
+            let circuit = fill_circuit(swap.clone(), intent_note, input_notes, output_notes);
+            assert_eq!(circuit.solver_fee(), fee);
+            let public_inputs = circuit.get_public_inputs(&mut rng);
+            assert_eq!(
+                public_inputs.inner()[SOLVER_FEE_PUBLIC_INPUT_IDX],
+                pallas::Base::from(fee)
+            );
+            assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+            // The fee is audited in the public inputs
+            let mut public_inputs = public_inputs.to_vec();
+            public_inputs[SOLVER_FEE_PUBLIC_INPUT_IDX] = pallas::Base::from(fee + 1);
+            assert!(circuit
+                .dev_prove_and_check_with_public_inputs(&public_inputs.into())
+                .is_err());
+        }
+
+        // The solver keeps 11 of the 4000 paid for 1 token1, more than 25 bps
+        let offer = Token::new(TokenName::new("token2").unwrap(), 4000u64);
+        let (input_notes, mut output_notes) = swap
+            .fill_with_solver_fee(&mut rng, intent_note, offer, 10)
+            .unwrap();
+        output_notes[0].value -= 1;
+        let circuit = fill_circuit(swap.clone(), intent_note, input_notes, output_notes);
+        assert_eq!(circuit.solver_fee(), 11);
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+
+        // No fee is allowed by default
+        let no_fee_swap = Swap {
+            max_solver_fee_bps: 0,
+            ..swap
+        };
+        let intent_note = no_fee_swap.create_intent_note(&mut rng);
+        let (input_notes, mut output_notes) = no_fee_swap.fill(
+            &mut rng,
+            intent_note,
+            Token::new(TokenName::new("token2").unwrap(), 8000u64),
+        );
+        output_notes[0].value -= 1;
+        let circuit = fill_circuit(no_fee_swap, intent_note, input_notes, output_notes);
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+    }
+
+    #[test]
+    fn cancellation() {
+        let mut rng = OsRng;
+        let sell = Token::new(TokenName::new("token1").unwrap(), 2u64);
+        let buy = Token::new(TokenName::new("token2").unwrap(), 4u64);
+
+        let sk = SigningKey::random(&mut rng);
+        let auth = TokenAuthorization::from_sk_vk(sk.inner(), &COMPRESSED_TOKEN_AUTH_VK);
+        let swap = Swap::random(&mut rng, sell, buy, auth);
+
+        // The creator cancels the intent
+        let circuit = cancel_circuit(&mut rng, swap.clone(), &sk);
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+        // A third party can't cancel the intent
+        let third_party_sk = SigningKey::random(&mut rng);
+        let circuit = cancel_circuit(&mut rng, swap, &third_party_sk);
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn fill_conserves_value(
+            (swap, filled_value) in swap::testing::arb_swap()
+                .prop_flat_map(|swap| {
+                    let sell_value = swap.sell.value;
+                    (proptest::strategy::Just(swap), 1..=sell_value)
+                })
+        ) {
+            use proptest::prop_assert_eq;
+
+            let mut rng = OsRng;
+            let ask = swap.asks[0].clone();
+            let ratio = swap.ratio(&ask);
+            let offer = Token::new(ask.name().clone(), filled_value * ratio);
+            let intent_note = swap.create_intent_note(&mut rng);
+            let (input_notes, output_notes) = swap.fill(&mut rng, intent_note, offer.clone());
+            let [offer_note, returned_note] = output_notes;
+
+            prop_assert_eq!(input_notes[0], intent_note);
+            prop_assert_eq!(offer_note.value, offer.value());
+            prop_assert_eq!(offer_note.get_app_data_static(), ask.encode_name());
+            // The sold value is either bought or returned
+            prop_assert_eq!(filled_value + returned_note.value, swap.sell.value);
+            if returned_note.value > 0 {
+                prop_assert_eq!(
+                    returned_note.get_app_data_static(),
+                    swap.sell.encode_name()
+                );
+            }
+        }
     }
 }
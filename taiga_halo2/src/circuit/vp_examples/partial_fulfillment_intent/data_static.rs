@@ -1,8 +1,12 @@
+use super::swap::{MAX_SOLVER_FEE_BPS, SWAP_SCHEMA};
 use crate::circuit::{
     gadgets::{
-        conditional_equal::ConditionalEqualConfig,
+        add::{AddChip, AddInstructions},
+        assign_free_constant,
+        comparison::{is_greater_or_equal_64, ComparisonConfig},
+        conditional::ConditionalConfig,
         mul::{MulChip, MulInstructions},
-        poseidon_hash::poseidon_hash_gadget,
+        poseidon_hash::poseidon_hash_slice_gadget,
         sub::{SubChip, SubInstructions},
     },
     vp_circuit::BasicValidityPredicateVariables,
@@ -10,7 +14,7 @@ use crate::circuit::{
 use halo2_gadgets::poseidon::Pow5Config as PoseidonConfig;
 use halo2_proofs::{
     circuit::{AssignedCell, Layouter},
-    plonk::Error,
+    plonk::{Advice, Column, Error},
 };
 use pasta_curves::pallas;
 
@@ -19,8 +23,17 @@ pub struct PartialFulfillmentIntentDataStatic {
     pub token_vp_vk: AssignedCell<pallas::Base, pallas::Base>,
     pub sold_token: AssignedCell<pallas::Base, pallas::Base>,
     pub sold_token_value: AssignedCell<pallas::Base, pallas::Base>,
+    // The (token, value) of the padded asks
+    pub asks: Vec<(
+        AssignedCell<pallas::Base, pallas::Base>,
+        AssignedCell<pallas::Base, pallas::Base>,
+    )>,
+    // One-hot flags of the bought ask
+    pub ask_flags: Vec<AssignedCell<pallas::Base, pallas::Base>>,
+    // The bought ask
     pub bought_token: AssignedCell<pallas::Base, pallas::Base>,
     pub bought_token_value: AssignedCell<pallas::Base, pallas::Base>,
+    pub max_solver_fee_bps: AssignedCell<pallas::Base, pallas::Base>,
     pub receiver_nk_com: AssignedCell<pallas::Base, pallas::Base>,
     pub receiver_app_data_dynamic: AssignedCell<pallas::Base, pallas::Base>,
 }
@@ -29,17 +42,30 @@ impl PartialFulfillmentIntentDataStatic {
     pub fn encode(
         &self,
         config: PoseidonConfig<pallas::Base, 3, 2>,
+        advice: Column<Advice>,
         mut layouter: impl Layouter<pallas::Base>,
     ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
-        // Encode the app_data_static of intent note
-        poseidon_hash_gadget(
+        let asks: Vec<_> = self
+            .asks
+            .iter()
+            .flat_map(|(token, value)| [token.clone(), value.clone()])
+            .collect();
+        let asks_commitment = poseidon_hash_slice_gadget(
             config.clone(),
+            layouter.namespace(|| "asks commitment"),
+            &asks,
+        )?;
+
+        // Encode the app_data_static of intent note
+        SWAP_SCHEMA.encode_gadget(
+            config,
+            advice,
             layouter.namespace(|| "app_data_static encoding"),
-            [
+            &[
                 self.sold_token.clone(),
                 self.sold_token_value.clone(),
-                self.bought_token.clone(),
-                self.bought_token_value.clone(),
+                asks_commitment,
+                self.max_solver_fee_bps.clone(),
                 self.token_vp_vk.clone(),
                 self.receiver_nk_com.clone(),
                 self.receiver_app_data_dynamic.clone(),
@@ -47,75 +73,86 @@ impl PartialFulfillmentIntentDataStatic {
         )
     }
 
-    /// Checks to be enforced if `is_input_note == 1`
-    pub fn is_input_note_checks(
+    /// The bought token is one of the committed asks: exactly one flag is set, and the bought
+    /// token and value are the ones of the flagged ask.
+    pub fn ask_membership_checks(
         &self,
-        is_input_note: &AssignedCell<pallas::Base, pallas::Base>,
-        basic_variables: &BasicValidityPredicateVariables,
-        config: &ConditionalEqualConfig,
+        config: &ConditionalConfig,
+        add_chip: &AddChip<pallas::Base>,
+        advice: Column<Advice>,
         mut layouter: impl Layouter<pallas::Base>,
     ) -> Result<(), Error> {
-        layouter.assign_region(
-            || "conditional equal: check bought token vk",
-            |mut region| {
-                config.assign_region(
-                    is_input_note,
-                    &self.token_vp_vk,
-                    &basic_variables.output_note_variables[0]
-                        .note_variables
-                        .app_vk,
-                    0,
-                    &mut region,
-                )
-            },
-        )?;
+        // The conditional gadgets constrain the flags to be boolean
+        for (i, (flag, (token, value))) in self.ask_flags.iter().zip(self.asks.iter()).enumerate() {
+            config.assert_equal_if(
+                layouter.namespace(|| format!("check ask {i} token")),
+                flag,
+                &self.bought_token,
+                token,
+            )?;
+            config.assert_equal_if(
+                layouter.namespace(|| format!("check ask {i} value")),
+                flag,
+                &self.bought_token_value,
+                value,
+            )?;
+        }
 
-        layouter.assign_region(
-            || "conditional equal: check bought token vk",
-            |mut region| {
-                config.assign_region(
-                    is_input_note,
-                    &self.bought_token,
-                    &basic_variables.output_note_variables[0]
-                        .note_variables
-                        .app_data_static,
-                    0,
-                    &mut region,
-                )
-            },
+        let mut flag_sum = self.ask_flags[0].clone();
+        for flag in self.ask_flags.iter().skip(1) {
+            flag_sum = AddInstructions::add(
+                add_chip,
+                layouter.namespace(|| "sum ask flags"),
+                &flag_sum,
+                flag,
+            )?;
+        }
+        let one = assign_free_constant(
+            layouter.namespace(|| "constant one"),
+            advice,
+            pallas::Base::one(),
         )?;
-
-        // check nk_com
         layouter.assign_region(
-            || "conditional equal: check bought token nk_com",
-            |mut region| {
-                config.assign_region(
-                    is_input_note,
-                    &self.receiver_nk_com,
-                    &basic_variables.output_note_variables[0]
-                        .note_variables
-                        .nk_com,
-                    0,
-                    &mut region,
-                )
-            },
-        )?;
+            || "check one ask is bought",
+            |mut region| region.constrain_equal(flag_sum.cell(), one.cell()),
+        )
+    }
 
-        // check app_data_dynamic
-        layouter.assign_region(
-            || "conditional equal: check bought token app_data_dynamic",
-            |mut region| {
-                config.assign_region(
-                    is_input_note,
-                    &self.receiver_app_data_dynamic,
-                    &basic_variables.output_note_variables[0]
-                        .note_variables
-                        .app_data_dynamic,
-                    0,
-                    &mut region,
-                )
-            },
-        )?;
+    /// Checks to be enforced if `is_fill == 1`: the bought token goes to the creator
+    pub fn is_fill_checks(
+        &self,
+        is_fill: &AssignedCell<pallas::Base, pallas::Base>,
+        basic_variables: &BasicValidityPredicateVariables,
+        config: &ConditionalConfig,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        let bought_note = &basic_variables.output_note_variables[0].note_variables;
+        let checks = [
+            ("bought token vk", &self.token_vp_vk, &bought_note.app_vk),
+            (
+                "bought token app_data_static",
+                &self.bought_token,
+                &bought_note.app_data_static,
+            ),
+            (
+                "bought token nk_com",
+                &self.receiver_nk_com,
+                &bought_note.nk_com,
+            ),
+            (
+                "bought token app_data_dynamic",
+                &self.receiver_app_data_dynamic,
+                &bought_note.app_data_dynamic,
+            ),
+        ];
+        for (name, expected, actual) in checks {
+            config.assert_equal_if(
+                layouter.namespace(|| format!("check {name}")),
+                is_fill,
+                expected,
+                actual,
+            )?;
+        }
 
         Ok(())
     }
@@ -125,184 +162,395 @@ impl PartialFulfillmentIntentDataStatic {
         &self,
         is_output_note: &AssignedCell<pallas::Base, pallas::Base>,
         basic_variables: &BasicValidityPredicateVariables,
-        config: &ConditionalEqualConfig,
+        config: &ConditionalConfig,
         mut layouter: impl Layouter<pallas::Base>,
     ) -> Result<(), Error> {
-        layouter.assign_region(
-            || "conditional equal: check sold token vp_vk",
-            |mut region| {
-                config.assign_region(
-                    is_output_note,
-                    &self.token_vp_vk,
-                    &basic_variables.input_note_variables[0]
-                        .note_variables
-                        .app_vk,
-                    0,
-                    &mut region,
-                )
-            },
-        )?;
+        let sold_note = &basic_variables.input_note_variables[0].note_variables;
+        let checks = [
+            ("sold token vp_vk", &self.token_vp_vk, &sold_note.app_vk),
+            (
+                "sold token app_data_static",
+                &self.sold_token,
+                &sold_note.app_data_static,
+            ),
+            ("sold token value", &self.sold_token_value, &sold_note.value),
+        ];
+        for (name, expected, actual) in checks {
+            config.assert_equal_if(
+                layouter.namespace(|| format!("check {name}")),
+                is_output_note,
+                expected,
+                actual,
+            )?;
+        }
 
-        layouter.assign_region(
-            || "conditional equal: check sold token app_data_static",
-            |mut region| {
-                config.assign_region(
-                    is_output_note,
-                    &self.sold_token,
-                    &basic_variables.input_note_variables[0]
-                        .note_variables
-                        .app_data_static,
-                    0,
-                    &mut region,
-                )
-            },
-        )?;
+        Ok(())
+    }
 
-        layouter.assign_region(
-            || "conditional equal: check sold token value",
-            |mut region| {
-                config.assign_region(
-                    is_output_note,
-                    &self.sold_token_value,
-                    &basic_variables.input_note_variables[0].note_variables.value,
-                    0,
-                    &mut region,
-                )
-            },
-        )?;
+    /// Checks to be enforced if `is_cancel == 1`: the sold token goes back to the creator
+    pub fn is_cancel_checks(
+        &self,
+        is_cancel: &AssignedCell<pallas::Base, pallas::Base>,
+        basic_variables: &BasicValidityPredicateVariables,
+        config: &ConditionalConfig,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        let returned_note = &basic_variables.output_note_variables[0].note_variables;
+        let checks = [
+            (
+                "returned token vk",
+                &self.token_vp_vk,
+                &returned_note.app_vk,
+            ),
+            (
+                "returned token app_data_static",
+                &self.sold_token,
+                &returned_note.app_data_static,
+            ),
+            (
+                "returned token value",
+                &self.sold_token_value,
+                &returned_note.value,
+            ),
+            (
+                "returned token nk_com",
+                &self.receiver_nk_com,
+                &returned_note.nk_com,
+            ),
+            (
+                "returned token app_data_dynamic",
+                &self.receiver_app_data_dynamic,
+                &returned_note.app_data_dynamic,
+            ),
+        ];
+        for (name, expected, actual) in checks {
+            config.assert_equal_if(
+                layouter.namespace(|| format!("check {name}")),
+                is_cancel,
+                expected,
+                actual,
+            )?;
+        }
 
         Ok(())
     }
 
-    /// Checks to be enforced if `is_partial_fulfillment == 1`
-    pub fn is_partial_fulfillment_checks(
+    /// The full vs partial fill branch, `is_partial_fill` can only be 1 if `is_fill == 1`.
+    /// A full fill pays the expected value. A partial fill pays less and leaves `remaining_value`
+    /// of the sold token in proportion, either returned to the creator if `is_returned == 1` or
+    /// kept open in a residual intent note. `paid_value` is the bought value plus the solver fee,
+    /// and `remaining_value` must be range checked.
+    #[allow(clippy::too_many_arguments)]
+    pub fn fill_branch_checks(
         &self,
-        is_input_note: &AssignedCell<pallas::Base, pallas::Base>,
+        is_fill: &AssignedCell<pallas::Base, pallas::Base>,
+        is_partial_fill: &AssignedCell<pallas::Base, pallas::Base>,
+        is_returned: &AssignedCell<pallas::Base, pallas::Base>,
+        paid_value: &AssignedCell<pallas::Base, pallas::Base>,
+        remaining_value: &AssignedCell<pallas::Base, pallas::Base>,
         basic_variables: &BasicValidityPredicateVariables,
-        config: &ConditionalEqualConfig,
+        config: &ConditionalConfig,
+        comparison_config: &ComparisonConfig,
         sub_chip: &SubChip<pallas::Base>,
         mul_chip: &MulChip<pallas::Base>,
         mut layouter: impl Layouter<pallas::Base>,
     ) -> Result<(), Error> {
-        let is_partial_fulfillment = {
-            let is_partial_fulfillment = SubInstructions::sub(
-                sub_chip,
-                layouter.namespace(|| "expected_bought_token_value - actual_bought_token_value"),
-                &self.bought_token_value,
-                &basic_variables.output_note_variables[0]
-                    .note_variables
-                    .value,
+        let returned_note = &basic_variables.output_note_variables[1].note_variables;
+
+        // Full fill: the paid value is the expected one
+        let is_full_fill = SubInstructions::sub(
+            sub_chip,
+            layouter.namespace(|| "is_fill - is_partial_fill"),
+            is_fill,
+            is_partial_fill,
+        )?;
+        let bought_value_diff = SubInstructions::sub(
+            sub_chip,
+            layouter.namespace(|| "expected_bought_token_value - paid_value"),
+            &self.bought_token_value,
+            paid_value,
+        )?;
+        config.assert_zero_if(
+            layouter.namespace(|| "check paid value if it's fully filled"),
+            &is_full_fill,
+            &bought_value_diff,
+        )?;
+
+        // Partial fill returning the sold token: check the returned note
+        let checks = [
+            (
+                "returned token vk",
+                &self.token_vp_vk,
+                &returned_note.app_vk,
+            ),
+            (
+                "returned token app_data_static",
+                &self.sold_token,
+                &returned_note.app_data_static,
+            ),
+            (
+                "returned token nk_com",
+                &self.receiver_nk_com,
+                &returned_note.nk_com,
+            ),
+            (
+                "returned token app_data_dynamic",
+                &self.receiver_app_data_dynamic,
+                &returned_note.app_data_dynamic,
+            ),
+            (
+                "returned token value",
+                remaining_value,
+                &returned_note.value,
+            ),
+        ];
+        for (name, expected, actual) in checks {
+            config.assert_equal_if(
+                layouter.namespace(|| format!("check {name} if it's returned")),
+                is_returned,
+                expected,
+                actual,
             )?;
-            MulInstructions::mul(
-                mul_chip,
-                layouter.namespace(|| "is_input * is_partial_fulfillment"),
-                is_input_note,
-                &is_partial_fulfillment,
-            )?
-        };
+        }
 
-        // check returned token vk if it's partially fulfilled
-        layouter.assign_region(
-            || "conditional equal: check returned token vk",
-            |mut region| {
-                config.assign_region(
-                    &is_partial_fulfillment,
-                    &self.token_vp_vk,
-                    &basic_variables.output_note_variables[1]
-                        .note_variables
-                        .app_vk,
-                    0,
-                    &mut region,
-                )
-            },
+        // Partial fill: check (expected_bought_value * actual_sold_value) == (expected_sold_value * paid_value)
+        // The remaining value can't exceed the sold one, so actual_sold_value doesn't underflow
+        let is_sold_value_enough = is_greater_or_equal_64(
+            layouter.namespace(|| "expected_sold_value >= remaining_value"),
+            comparison_config,
+            &self.sold_token_value,
+            remaining_value,
         )?;
+        config.assert_equal_if(
+            layouter.namespace(|| "check the remaining value if it's partially filled"),
+            is_partial_fill,
+            &is_sold_value_enough,
+            is_partial_fill,
+        )?;
+        let actual_sold_value = SubInstructions::sub(
+            sub_chip,
+            layouter.namespace(|| "expected_sold_value - remaining_value"),
+            &self.sold_token_value,
+            remaining_value,
+        )?;
+        let expected_bought_mul_actual_sold_value = MulInstructions::mul(
+            mul_chip,
+            layouter.namespace(|| "expected_bought_value * actual_sold_value"),
+            &self.bought_token_value,
+            &actual_sold_value,
+        )?;
+        let expected_sold_mul_paid_value = MulInstructions::mul(
+            mul_chip,
+            layouter.namespace(|| "expected_sold_value * paid_value"),
+            &self.sold_token_value,
+            paid_value,
+        )?;
+        config.assert_equal_if(
+            layouter.namespace(|| "check the swap ratio if it's partially filled"),
+            is_partial_fill,
+            &expected_bought_mul_actual_sold_value,
+            &expected_sold_mul_paid_value,
+        )
+    }
 
-        // check return token app_data_static if it's partially fulfilled
-        layouter.assign_region(
-            || "conditional equal: check returned token app_data_static",
-            |mut region| {
-                config.assign_region(
-                    &is_partial_fulfillment,
-                    &self.sold_token,
-                    &basic_variables.output_note_variables[1]
-                        .note_variables
-                        .app_data_static,
-                    0,
-                    &mut region,
-                )
-            },
+    /// Checks to be enforced if `is_fill == 1`: the solver fee is at most `max_solver_fee_bps` of
+    /// the paid value, i.e. the pro-rata value of the fill, rounded down. `allowed_fee` and
+    /// `fee_remainder` are the quotient and the remainder of
+    /// `paid_value * max_solver_fee_bps / MAX_SOLVER_FEE_BPS`. The fee, `allowed_fee` and
+    /// `fee_remainder` must be range checked.
+    #[allow(clippy::too_many_arguments)]
+    pub fn solver_fee_checks(
+        &self,
+        is_fill: &AssignedCell<pallas::Base, pallas::Base>,
+        paid_value: &AssignedCell<pallas::Base, pallas::Base>,
+        solver_fee: &AssignedCell<pallas::Base, pallas::Base>,
+        allowed_fee: &AssignedCell<pallas::Base, pallas::Base>,
+        fee_remainder: &AssignedCell<pallas::Base, pallas::Base>,
+        config: &ConditionalConfig,
+        comparison_config: &ComparisonConfig,
+        add_chip: &AddChip<pallas::Base>,
+        mul_chip: &MulChip<pallas::Base>,
+        advice: Column<Advice>,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        // paid_value * max_solver_fee_bps == allowed_fee * MAX_SOLVER_FEE_BPS + fee_remainder,
+        // the values are u64 so nothing wraps in the field
+        let max_bps = assign_free_constant(
+            layouter.namespace(|| "constant MAX_SOLVER_FEE_BPS"),
+            advice,
+            pallas::Base::from(MAX_SOLVER_FEE_BPS),
+        )?;
+        let lhs = MulInstructions::mul(
+            mul_chip,
+            layouter.namespace(|| "paid_value * max_solver_fee_bps"),
+            paid_value,
+            &self.max_solver_fee_bps,
+        )?;
+        let allowed_fee_mul_max_bps = MulInstructions::mul(
+            mul_chip,
+            layouter.namespace(|| "allowed_fee * MAX_SOLVER_FEE_BPS"),
+            allowed_fee,
+            &max_bps,
+        )?;
+        let rhs = AddInstructions::add(
+            add_chip,
+            layouter.namespace(|| "allowed_fee * MAX_SOLVER_FEE_BPS + fee_remainder"),
+            &allowed_fee_mul_max_bps,
+            fee_remainder,
+        )?;
+        config.assert_equal_if(
+            layouter.namespace(|| "check the allowed fee"),
+            is_fill,
+            &lhs,
+            &rhs,
         )?;
 
-        layouter.assign_region(
-            || "conditional equal: check returned token nk_com",
-            |mut region| {
-                config.assign_region(
-                    &is_partial_fulfillment,
-                    &self.receiver_nk_com,
-                    &basic_variables.output_note_variables[1]
-                        .note_variables
-                        .nk_com,
-                    0,
-                    &mut region,
-                )
-            },
+        // fee_remainder < MAX_SOLVER_FEE_BPS
+        let max_remainder = assign_free_constant(
+            layouter.namespace(|| "constant MAX_SOLVER_FEE_BPS - 1"),
+            advice,
+            pallas::Base::from(MAX_SOLVER_FEE_BPS - 1),
+        )?;
+        let is_remainder_valid = is_greater_or_equal_64(
+            layouter.namespace(|| "MAX_SOLVER_FEE_BPS - 1 >= fee_remainder"),
+            comparison_config,
+            &max_remainder,
+            fee_remainder,
+        )?;
+        config.assert_equal_if(
+            layouter.namespace(|| "check the fee remainder"),
+            is_fill,
+            &is_remainder_valid,
+            is_fill,
         )?;
 
-        layouter.assign_region(
-            || "conditional equal: check returned token app_data_dynamic",
-            |mut region| {
-                config.assign_region(
-                    &is_partial_fulfillment,
-                    &self.receiver_app_data_dynamic,
-                    &basic_variables.output_note_variables[1]
-                        .note_variables
-                        .app_data_dynamic,
-                    0,
-                    &mut region,
-                )
-            },
+        // solver_fee <= allowed_fee
+        let is_fee_allowed = is_greater_or_equal_64(
+            layouter.namespace(|| "allowed_fee >= solver_fee"),
+            comparison_config,
+            allowed_fee,
+            solver_fee,
         )?;
+        config.assert_equal_if(
+            layouter.namespace(|| "check the solver fee"),
+            is_fill,
+            &is_fee_allowed,
+            is_fill,
+        )
+    }
 
-        // value check
-        {
-            let actual_sold_value = SubInstructions::sub(
-                sub_chip,
-                layouter.namespace(|| "expected_sold_value - returned_value"),
-                &self.sold_token_value,
-                &basic_variables.output_note_variables[1]
-                    .note_variables
-                    .value,
+    /// Checks to be enforced if `is_residual == 1`: `self` is the swap of the first input intent
+    /// note, partially filled, and `residual` the swap of the residual intent note in the second
+    /// output. The residual sells the same token to the same creator, for the same asks at the
+    /// same ratios and with the same solver fee.
+    #[allow(clippy::too_many_arguments)]
+    pub fn residual_checks(
+        &self,
+        residual: &Self,
+        is_residual: &AssignedCell<pallas::Base, pallas::Base>,
+        basic_variables: &BasicValidityPredicateVariables,
+        config: &ConditionalConfig,
+        poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
+        mul_chip: &MulChip<pallas::Base>,
+        advice: Column<Advice>,
+        mut layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        let parent_note = &basic_variables.input_note_variables[0].note_variables;
+        let residual_note = &basic_variables.output_note_variables[1].note_variables;
+
+        // Both intent notes are the ones of the swaps, with the same intent vp
+        let encoded_parent = self.encode(
+            poseidon_config.clone(),
+            advice,
+            layouter.namespace(|| "encode parent app_data_static"),
+        )?;
+        let encoded_residual = residual.encode(
+            poseidon_config,
+            advice,
+            layouter.namespace(|| "encode residual app_data_static"),
+        )?;
+        let mut checks = vec![
+            (
+                "parent app_data_static",
+                &encoded_parent,
+                &parent_note.app_data_static,
+            ),
+            (
+                "residual app_data_static",
+                &encoded_residual,
+                &residual_note.app_data_static,
+            ),
+            ("residual vk", &parent_note.app_vk, &residual_note.app_vk),
+            (
+                "residual nk_com",
+                &self.receiver_nk_com,
+                &residual_note.nk_com,
+            ),
+            (
+                "residual app_data_dynamic",
+                &self.receiver_app_data_dynamic,
+                &residual_note.app_data_dynamic,
+            ),
+            (
+                "residual sold token",
+                &self.sold_token,
+                &residual.sold_token,
+            ),
+            (
+                "residual token vk",
+                &self.token_vp_vk,
+                &residual.token_vp_vk,
+            ),
+            (
+                "residual receiver nk_com",
+                &self.receiver_nk_com,
+                &residual.receiver_nk_com,
+            ),
+            (
+                "residual receiver app_data_dynamic",
+                &self.receiver_app_data_dynamic,
+                &residual.receiver_app_data_dynamic,
+            ),
+            (
+                "residual max solver fee",
+                &self.max_solver_fee_bps,
+                &residual.max_solver_fee_bps,
+            ),
+        ];
+        for ((token, _), (residual_token, _)) in self.asks.iter().zip(residual.asks.iter()) {
+            checks.push(("residual ask token", token, residual_token));
+        }
+        for (name, expected, actual) in checks {
+            config.assert_equal_if(
+                layouter.namespace(|| format!("check {name}")),
+                is_residual,
+                expected,
+                actual,
             )?;
+        }
 
-            // check (expected_bought_value * actual_sold_value) == (expected_sold_value * actual_bought_value)
-            // if it's partially fulfilled
-            let expected_bought_mul_actual_sold_value = MulInstructions::mul(
+        // The ratios are kept: residual_ask_value * sold_value == ask_value * residual_sold_value
+        for (i, ((_, value), (_, residual_value))) in
+            self.asks.iter().zip(residual.asks.iter()).enumerate()
+        {
+            let lhs = MulInstructions::mul(
                 mul_chip,
-                layouter.namespace(|| "expected_bought_value * actual_sold_value"),
-                &self.bought_token_value,
-                &actual_sold_value,
+                layouter.namespace(|| format!("residual ask {i} value * sold_value")),
+                residual_value,
+                &self.sold_token_value,
             )?;
-            let expected_sold_mul_actual_bought_value = MulInstructions::mul(
+            let rhs = MulInstructions::mul(
                 mul_chip,
-                layouter.namespace(|| "expected_sold_value * actual_bought_value"),
-                &self.sold_token_value,
-                &basic_variables.output_note_variables[0]
-                    .note_variables
-                    .value,
+                layouter.namespace(|| format!("ask {i} value * residual sold_value")),
+                value,
+                &residual.sold_token_value,
+            )?;
+            config.assert_equal_if(
+                layouter.namespace(|| format!("check residual ask {i} ratio")),
+                is_residual,
+                &lhs,
+                &rhs,
             )?;
-
-            layouter.assign_region(
-                    || "conditional equal: expected_bought_value * actual_sold_value == expected_sold_value * actual_bought_value",
-                    |mut region| {
-                        config.assign_region(
-                            &is_partial_fulfillment,
-                            &expected_bought_mul_actual_sold_value,
-                            &expected_sold_mul_actual_bought_value,
-                            0,
-                            &mut region,
-                        )
-                    },
-                )?;
         }
 
         Ok(())
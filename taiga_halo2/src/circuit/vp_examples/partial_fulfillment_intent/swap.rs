@@ -1,60 +1,460 @@
-use super::{PartialFulfillmentIntentDataStatic, COMPRESSED_PARTIAL_FULFILLMENT_INTENT_VK};
+use super::{
+    PartialFulfillmentIntentDataStatic, PartialFulfillmentIntentValidityPredicateCircuit,
+    COMPRESSED_PARTIAL_FULFILLMENT_INTENT_VK,
+};
 use crate::{
     circuit::{
         gadgets::assign_free_advice,
-        vp_examples::token::{Token, TokenAuthorization, TokenNote, TOKEN_VK},
+        vp_examples::{
+            signature_verification::{
+                off_chain_signing_message, signing_message, SchnorrSignature,
+                SCHNORR_SIGNATURE_SIZE,
+            },
+            token::{Token, TokenAuthorization, TokenName, TokenNote, TOKEN_VK},
+        },
+    },
+    constant::{INTENT_ADVERT_DOMAIN, NOTE_ENCRYPTION_PLAINTEXT_NUM, NUM_NOTE, SWAP_INTENT_DOMAIN},
+    error::{NoteError, TransactionError},
+    keys::{IncomingViewingKey, SigningKey},
+    merkle_tree::{Anchor, MerklePath},
+    note::{
+        encoding::{StaticDataField, StaticDataSchema, StaticDataValue},
+        InputNoteProvingInfo, Note, NoteCommitment, OutputNoteProvingInfo, RandomSeed,
     },
-    constant::NUM_NOTE,
-    note::{Note, RandomSeed},
+    note_encryption::{EncryptedNote, NoteCiphertext, NotePlaintext, SecretKey},
     nullifier::{Nullifier, NullifierKeyContainer},
-    utils::poseidon_hash_n,
+    shielded_ptx::ShieldedPartialTransaction,
+    utils::{
+        mod_r_p, non_identity_point_from_bytes, point_to_bytes, poseidon_hash_n,
+        poseidon_hash_slice,
+    },
 };
 use halo2_proofs::{
+    arithmetic::Field,
     circuit::{Layouter, Value},
     plonk::{Advice, Column, Error},
 };
-use pasta_curves::pallas;
+use lazy_static::lazy_static;
+use pasta_curves::{
+    group::{ff::PrimeField, Group},
+    pallas,
+};
 use rand::RngCore;
+use std::fmt;
+use std::sync::Arc;
+
+/// The max number of ask options of a swap, the intent vp always witnesses this many.
+pub const MAX_SWAP_ASKS: usize = 4;
+
+/// The solver fee is in basis points of the pro-rata value of a fill, it's at most all of it.
+pub const MAX_SOLVER_FEE_BPS: u64 = 10_000;
+
+lazy_static! {
+    // The app_data_static of intent notes: (sold token name, sold value, asks commitment,
+    // max solver fee bps, token vp vk, receiver nk_com, receiver app_data_dynamic)
+    pub static ref SWAP_SCHEMA: StaticDataSchema = StaticDataSchema::new(
+        SWAP_INTENT_DOMAIN,
+        vec![
+            StaticDataField::Base,
+            StaticDataField::U64,
+            StaticDataField::Base,
+            StaticDataField::U64,
+            StaticDataField::Base,
+            StaticDataField::Base,
+            StaticDataField::Base,
+            StaticDataField::Base,
+        ]
+    );
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapError {
+    /// The note is not created by the partial fulfillment intent vp.
+    NotIntentNote,
+    /// The swap doesn't match the intent note.
+    InconsistentIntentNote,
+    /// The sold note is not a token note of the authorization, or its nk is unknown.
+    InvalidSellNote,
+    /// The bought value is not a multiple of the sold value.
+    InvalidRatio,
+    /// The key is not the authorization key of the swap.
+    InvalidAuthKey,
+    /// The swap has no ask or more than `MAX_SWAP_ASKS`.
+    InvalidAsks,
+    /// The sold value or the value of an ask is zero.
+    ZeroValue,
+    /// An ask buys the sold token.
+    SameToken,
+    /// The max solver fee is more than `MAX_SOLVER_FEE_BPS`, or a fill takes more than it.
+    InvalidSolverFee,
+    /// The decrypted swap terms can't be decoded.
+    MalformedTerms,
+}
+
+impl fmt::Display for SwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SwapError::*;
+        match self {
+            NotIntentNote => f.write_str("The note is not an intent note"),
+            InconsistentIntentNote => f.write_str("The swap doesn't match the intent note"),
+            InvalidSellNote => f.write_str("The sold note can't be used in the swap"),
+            InvalidRatio => f.write_str("The bought value is not a multiple of the sold value"),
+            InvalidAuthKey => f.write_str("The key doesn't match the swap authorization"),
+            InvalidAsks => write!(f, "The swap must have 1 to {MAX_SWAP_ASKS} asks"),
+            ZeroValue => f.write_str("The sold and bought values must be non-zero"),
+            SameToken => f.write_str("The swap can't buy the sold token"),
+            MalformedTerms => f.write_str("The swap terms are malformed"),
+            InvalidSolverFee => f.write_str("The solver fee is more than the swap allows"),
+        }
+    }
+}
+
+/// The sold note is exchanged for any one of the asks, e.g. 2 btc for either 10 eth or 500 xan.
+/// A fill buys a single ask, fully or partially.
+/// The solver of a fill can keep up to `max_solver_fee_bps` of the pro-rata value of the bought
+/// ask, the creator gets the rest.
 #[derive(Clone, Debug, Default)]
 pub struct Swap {
     pub sell: TokenNote,
-    pub buy: Token,
+    pub asks: Vec<Token>,
     pub auth: TokenAuthorization,
+    pub max_solver_fee_bps: u64,
+}
+
+/// A partial fill keeping the rest of the swap open: the fill outputs a residual intent note
+/// instead of returning the unsold token. The intent vps of both intent notes check the residual
+/// terms.
+#[derive(Clone, Debug, Default)]
+pub struct ResidualFill {
+    pub parent: Swap,
+    pub residual: Swap,
 }
 
 impl Swap {
-    pub fn random(
+    /// Panics if the swap can't be filled, see `validate`.
+    pub fn random(rng: impl RngCore, sell: Token, buy: Token, auth: TokenAuthorization) -> Self {
+        Self::random_with_asks(rng, sell, vec![buy], auth)
+    }
+
+    pub fn random_with_asks(
         mut rng: impl RngCore,
         sell: Token,
-        buy: Token,
+        asks: Vec<Token>,
         auth: TokenAuthorization,
     ) -> Self {
-        assert_eq!(buy.value() % sell.value(), 0);
-
         let sell = {
             let rho = Nullifier::random(&mut rng);
             let nk = NullifierKeyContainer::random_key(&mut rng);
             sell.create_random_token_note(&mut rng, rho, nk, &auth)
         };
 
-        Swap { sell, buy, auth }
+        let swap = Swap {
+            sell,
+            asks,
+            auth,
+            max_solver_fee_bps: 0,
+        };
+        swap.validate().expect("the swap can't be filled");
+        swap
+    }
+
+    /// Checks the swap can be filled, the intent vp of a swap failing the checks can't be proven.
+    /// The ratios are integral: the asks are multiples of the sold value, so a fill of any sold
+    /// value has a whole bought value. The values are u64 and the ratio checks of the vp multiply
+    /// two of them, so the products don't wrap in the field.
+    pub fn validate(&self) -> Result<(), SwapError> {
+        if self.asks.is_empty() || self.asks.len() > MAX_SWAP_ASKS {
+            return Err(SwapError::InvalidAsks);
+        }
+        let sell = self.sell.note();
+        if sell.value == 0 || self.asks.iter().any(|ask| ask.value() == 0) {
+            return Err(SwapError::ZeroValue);
+        }
+        // The token notes share the token vp, the value bases only differ if the names do
+        if self
+            .asks
+            .iter()
+            .any(|ask| ask.note_type() == sell.note_type)
+        {
+            return Err(SwapError::SameToken);
+        }
+        if self.asks.iter().any(|ask| ask.value() % sell.value != 0) {
+            return Err(SwapError::InvalidRatio);
+        }
+        if self.max_solver_fee_bps > MAX_SOLVER_FEE_BPS {
+            return Err(SwapError::InvalidSolverFee);
+        }
+        Ok(())
+    }
+
+    pub fn sell(&self) -> &TokenNote {
+        &self.sell
+    }
+
+    pub fn asks(&self) -> &[Token] {
+        &self.asks
+    }
+
+    /// The first ask of the token, if any.
+    pub fn ask(&self, name: &TokenName) -> Option<&Token> {
+        self.asks.iter().find(|ask| ask.name() == name)
+    }
+
+    pub fn auth(&self) -> &TokenAuthorization {
+        &self.auth
+    }
+
+    /// The bought value of the ask per sold unit.
+    pub fn ratio(&self, ask: &Token) -> u64 {
+        ask.value() / self.sell.value
+    }
+
+    /// The max fee a solver can keep out of the pro-rata value of a fill, rounded down.
+    pub fn max_solver_fee(&self, pro_rata_value: u64) -> u64 {
+        (pro_rata_value as u128 * self.max_solver_fee_bps as u128 / MAX_SOLVER_FEE_BPS as u128)
+            as u64
+    }
+
+    /// The asks padded to `MAX_SWAP_ASKS` by repeating the last one, so the padding doesn't add
+    /// any option.
+    pub fn padded_asks(&self) -> [Token; MAX_SWAP_ASKS] {
+        let last = self.asks.last().cloned().unwrap_or_default();
+        std::array::from_fn(|i| self.asks.get(i).cloned().unwrap_or_else(|| last.clone()))
+    }
+
+    /// The poseidon hash of the padded (token name, value) pairs, committed in app_data_static.
+    pub fn encode_asks(&self) -> pallas::Base {
+        let elements: Vec<_> = self
+            .padded_asks()
+            .iter()
+            .flat_map(|ask| [ask.encode_name(), ask.encode_value()])
+            .collect();
+        poseidon_hash_slice(&elements)
+    }
+
+    /// Whether the intent note is created by the swap, from its sold note.
+    pub fn opens_intent_note(&self, note: &Note) -> Result<(), SwapError> {
+        self.validate()?;
+        self.check_intent_note(note)?;
+        if note.rho != self.sell.note().get_nf().unwrap() {
+            return Err(SwapError::InconsistentIntentNote);
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the swap of a decrypted intent note from its decrypted terms, see
+    /// `EncryptedSwapTerms`. The terms are checked against the commitments of the note.
+    /// The sold note is spent by the intent, it's rebuilt with the token, value and authorization
+    /// of the terms and the nk of the intent note, its rho, psi and rcm are not recovered.
+    pub fn from_decrypted_intent_note(note: &Note, terms: &[u8]) -> Result<Swap, SwapError> {
+        let (sell, asks, max_solver_fee_bps, auth) =
+            Self::terms_from_bytes(terms).ok_or(SwapError::MalformedTerms)?;
+        let sell_type = sell.note_type();
+        let sell = TokenNote {
+            token_name: sell.name().clone(),
+            note: Note::from_full(
+                sell_type.app_vk,
+                sell_type.app_data_static,
+                auth.to_app_data_dynamic(),
+                sell.value(),
+                note.nk_container,
+                Nullifier::from(pallas::Base::zero()),
+                true,
+                pallas::Base::zero(),
+                pallas::Base::zero(),
+            ),
+        };
+        let swap = Swap {
+            sell,
+            asks,
+            auth,
+            max_solver_fee_bps,
+        };
+        swap.validate()?;
+        swap.check_intent_note(note)?;
+
+        Ok(swap)
+    }
+
+    /// Encrypts the terms of the swap to the rcv pk of the authorization, they're published
+    /// along with the intent note, see `EncryptedSwapTerms`.
+    pub fn encrypt_terms(&self, mut rng: impl RngCore) -> EncryptedSwapTerms {
+        // The terms are prefixed with their length and packed 31 bytes per element
+        let terms = self.terms_to_bytes();
+        let mut bytes = (terms.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(terms);
+        let mut elements: Vec<pallas::Base> = bytes
+            .chunks(31)
+            .map(|chunk| {
+                let mut repr = [0u8; 32];
+                repr[..chunk.len()].copy_from_slice(chunk);
+                pallas::Base::from_repr(repr).unwrap()
+            })
+            .collect();
+        let padded_len = (elements.len() + NOTE_ENCRYPTION_PLAINTEXT_NUM - 1)
+            / NOTE_ENCRYPTION_PLAINTEXT_NUM
+            * NOTE_ENCRYPTION_PLAINTEXT_NUM;
+        elements.resize(padded_len, pallas::Base::zero());
+
+        // Every plaintext is encrypted with a fresh esk, as the receiver vp does
+        let ciphertexts = elements
+            .chunks(NOTE_ENCRYPTION_PLAINTEXT_NUM)
+            .map(|chunk| {
+                let esk = pallas::Base::random(&mut rng);
+                let key = SecretKey::from_dh_exchange(&self.auth.rcv_pk, &mod_r_p(esk));
+                let nonce = pallas::Base::random(&mut rng);
+                EncryptedNote {
+                    ciphertext: NoteCiphertext::encrypt(
+                        &NotePlaintext::from(chunk.to_vec()),
+                        &key,
+                        &nonce,
+                    ),
+                    sender_pk: pallas::Point::generator() * mod_r_p(esk),
+                }
+            })
+            .collect();
+        EncryptedSwapTerms(ciphertexts)
+    }
+
+    // sold token || sold value || asks number || (ask token || ask value)* || max solver fee bps
+    // || authorization, a token name is its length || name || issuer flag || issuer
+    fn terms_to_bytes(&self) -> Vec<u8> {
+        let write_name = |bytes: &mut Vec<u8>, name: &TokenName| {
+            let inner = name.inner();
+            bytes.push(inner.len() as u8);
+            bytes.extend(inner.as_bytes());
+            match name.issuer() {
+                None => bytes.push(0),
+                Some(issuer) => {
+                    bytes.push(1);
+                    bytes.extend(point_to_bytes(&issuer));
+                }
+            }
+        };
+        let mut bytes = vec![];
+        write_name(&mut bytes, self.sell.token_name());
+        bytes.extend(self.sell.note().value.to_le_bytes());
+        bytes.push(self.asks.len() as u8);
+        for ask in &self.asks {
+            write_name(&mut bytes, ask.name());
+            bytes.extend(ask.value().to_le_bytes());
+        }
+        bytes.extend(self.max_solver_fee_bps.to_le_bytes());
+        bytes.extend(self.auth.to_bytes());
+        bytes
+    }
+
+    // None if the bytes are not the terms of a swap, see `terms_to_bytes`
+    fn terms_from_bytes(mut bytes: &[u8]) -> Option<(Token, Vec<Token>, u64, TokenAuthorization)> {
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if bytes.len() < len {
+                return None;
+            }
+            let (head, tail) = bytes.split_at(len);
+            *bytes = tail;
+            Some(head)
+        }
+        fn take_u64(bytes: &mut &[u8]) -> Option<u64> {
+            Some(u64::from_le_bytes(take(bytes, 8)?.try_into().unwrap()))
+        }
+        fn take_token(bytes: &mut &[u8]) -> Option<Token> {
+            let len = take(bytes, 1)?[0] as usize;
+            let name = std::str::from_utf8(take(bytes, len)?).ok()?;
+            let name = match take(bytes, 1)?[0] {
+                0 => TokenName::new(name).ok()?,
+                1 => {
+                    let issuer =
+                        non_identity_point_from_bytes(take(bytes, 32)?.try_into().unwrap()).ok()?;
+                    TokenName::new_issuable(name, issuer).ok()?
+                }
+                _ => return None,
+            };
+            Some(Token::new(name, take_u64(bytes)?))
+        }
+
+        let sell = take_token(&mut bytes)?;
+        let asks_num = take(&mut bytes, 1)?[0] as usize;
+        let asks = (0..asks_num)
+            .map(|_| take_token(&mut bytes))
+            .collect::<Option<Vec<_>>>()?;
+        let max_solver_fee_bps = take_u64(&mut bytes)?;
+        let auth = TokenAuthorization::from_bytes(bytes.try_into().ok()?).ok()?;
+        Some((sell, asks, max_solver_fee_bps, auth))
+    }
+
+    // The intent note commits to the swap terms. The rho of a residual intent note is not the
+    // nullifier of the sold note, it's only checked for the intents created from a sold note.
+    fn check_intent_note(&self, note: &Note) -> Result<(), SwapError> {
+        if note.get_app_vk() != *COMPRESSED_PARTIAL_FULFILLMENT_INTENT_VK {
+            return Err(SwapError::NotIntentNote);
+        }
+        if note.get_nk_commitment() != self.sell.note().get_nk_commitment()
+            || note.get_app_data_static() != self.encode_app_data_static()
+            || note.app_data_dynamic != self.auth.to_app_data_dynamic()
+        {
+            return Err(SwapError::InconsistentIntentNote);
+        }
+
+        Ok(())
     }
 
-    /// Either:
-    /// - completely fills the swap using a single `TokenNote`, or
-    /// - partially fills the swap, producing a `TokenNote` and a
+    /// The swap left open after selling `fill_amount` of the sold token: the same token, ratios,
+    /// authorization and solver fee, with the sold value and the asks reduced in proportion.
+    pub fn residual_after(&self, fill_amount: u64) -> Swap {
+        assert!(fill_amount < self.sell.value);
+        let residual_value = self.sell.value - fill_amount;
+        let asks = self
+            .asks
+            .iter()
+            .map(|ask| Token::new(ask.name().clone(), self.ratio(ask) * residual_value))
+            .collect();
+        let sell = TokenNote {
+            token_name: self.sell.token_name().clone(),
+            note: Note {
+                value: residual_value,
+                ..*self.sell.note()
+            },
+        };
+
+        Swap {
+            sell,
+            asks,
+            auth: self.auth,
+            max_solver_fee_bps: self.max_solver_fee_bps,
+        }
+    }
+
+    /// Fills the ask of the offered token. Either:
+    /// - completely fills the ask using a single `TokenNote`, or
+    /// - partially fills the ask, producing a `TokenNote` and a
     ///   returned note.
     pub fn fill(
         &self,
-        mut rng: impl RngCore,
+        rng: impl RngCore,
         intent_note: Note,
         offer: Token,
     ) -> ([Note; NUM_NOTE], [Note; NUM_NOTE]) {
-        assert_eq!(offer.name(), self.buy.name());
+        self.fill_with_solver_fee(rng, intent_note, offer, 0)
+            .expect("the fill takes no fee")
+    }
+
+    /// Fills the ask of the offered token like `fill`, and the solver keeps `solver_fee` of the
+    /// offer: the creator gets the offered value less the fee. The offered value is the pro-rata
+    /// value of the fill, the fee is left in the ptx for the solver to claim.
+    pub fn fill_with_solver_fee(
+        &self,
+        mut rng: impl RngCore,
+        intent_note: Note,
+        offer: Token,
+        solver_fee: u64,
+    ) -> Result<([Note; NUM_NOTE], [Note; NUM_NOTE]), SwapError> {
+        let ask = self.ask(offer.name()).expect("the offer matches no ask");
 
-        let ratio = self.buy.value() / self.sell.value;
+        let ratio = self.ratio(ask);
         assert_eq!(offer.value() % ratio, 0);
+        if solver_fee > self.max_solver_fee(offer.value()) {
+            return Err(SwapError::InvalidSolverFee);
+        }
 
         let offer_note = offer.create_random_token_note(
             &mut rng,
@@ -62,14 +462,18 @@ impl Swap {
             self.sell.note().nk_container,
             &self.auth,
         );
+        // The creator gets the offer less the solver fee
+        let bought_note = Note {
+            value: offer.value() - solver_fee,
+            ..*offer_note.note()
+        };
 
         let input_padding_note = Note::random_padding_input_note(&mut rng);
 
-        let returned_note = if offer.value() < self.buy.value() {
+        let returned_note = if offer.value() < ask.value() {
             let filled_value = offer.value() / ratio;
             let returned_value = self.sell.value - filled_value;
-            let returned_token =
-                Token::new(self.sell.token_name().inner().to_string(), returned_value);
+            let returned_token = Token::new(self.sell.token_name().clone(), returned_value);
             *returned_token
                 .create_random_token_note(
                     &mut rng,
@@ -83,43 +487,227 @@ impl Swap {
         };
 
         let input_notes = [intent_note, input_padding_note];
-        let output_notes = [*offer_note.note(), returned_note];
+        let output_notes = [bought_note, returned_note];
+
+        Ok((input_notes, output_notes))
+    }
+
+    /// Partially fills the ask of the offered token, and keeps the rest of the swap open in a
+    /// residual intent note instead of returning it to the creator. Returns the residual swap
+    /// along with the notes, the residual intent note is the second output note.
+    pub fn fill_with_residual(
+        &self,
+        mut rng: impl RngCore,
+        intent_note: Note,
+        offer: Token,
+    ) -> (ResidualFill, [Note; NUM_NOTE], [Note; NUM_NOTE]) {
+        let ask = self.ask(offer.name()).expect("the offer matches no ask");
+
+        let ratio = self.ratio(ask);
+        assert_eq!(offer.value() % ratio, 0);
+        assert!(offer.value() < ask.value());
+
+        let offer_note = offer.create_random_token_note(
+            &mut rng,
+            intent_note.get_nf().unwrap(),
+            self.sell.note().nk_container,
+            &self.auth,
+        );
+
+        let input_padding_note = Note::random_padding_input_note(&mut rng);
+
+        let residual = self.residual_after(offer.value() / ratio);
+        let residual_intent_note = residual.intent_note(
+            input_padding_note.get_nf().unwrap(),
+            RandomSeed::random(&mut rng),
+        );
+
+        let input_notes = [intent_note, input_padding_note];
+        let output_notes = [*offer_note.note(), residual_intent_note];
+        let residual_fill = ResidualFill {
+            parent: self.clone(),
+            residual,
+        };
+
+        (residual_fill, input_notes, output_notes)
+    }
+
+    /// Cancels the swap, returning the sold token to the creator.
+    pub fn cancel(
+        &self,
+        mut rng: impl RngCore,
+        intent_note: Note,
+    ) -> ([Note; NUM_NOTE], [Note; NUM_NOTE]) {
+        let returned_token = Token::new(self.sell.token_name().clone(), self.sell.value);
+        let returned_note = returned_token.create_random_token_note(
+            &mut rng,
+            intent_note.get_nf().unwrap(),
+            self.sell.note().nk_container,
+            &self.auth,
+        );
+
+        let input_padding_note = Note::random_padding_input_note(&mut rng);
+        let output_padding_note =
+            Note::random_padding_output_note(&mut rng, input_padding_note.get_nf().unwrap());
+
+        let input_notes = [intent_note, input_padding_note];
+        let output_notes = [*returned_note.note(), output_padding_note];
 
         (input_notes, output_notes)
     }
 
+    /// Cancels the swap: consumes the intent note and returns the sold token to the creator.
+    /// Only the creator can sign the cancellation.
+    pub fn create_cancel_ptx<R: RngCore>(
+        &self,
+        mut rng: R,
+        intent_note: Note,
+        merkle_path: MerklePath,
+        anchor: Anchor,
+        auth_sk: &SigningKey,
+    ) -> Result<ShieldedPartialTransaction, TransactionError> {
+        let (input_notes, output_notes) = self.cancel(&mut rng, intent_note);
+        let [_, padding_input_note] = input_notes;
+        let [returned_note, padding_output_note] = output_notes;
+
+        // The creator signs the cancellation
+        let owned_note_pub_id = intent_note.get_nf()?.inner();
+        let anchors = [anchor; NUM_NOTE];
+        let message = signing_message(&anchors, owned_note_pub_id, &input_notes, &output_notes);
+        let cancel_signature = SchnorrSignature::sign(&mut rng, auth_sk, message);
+
+        // Create the intent note proving info
+        let intent_note_proving_info = {
+            let intent_vp = PartialFulfillmentIntentValidityPredicateCircuit {
+                owned_note_pub_id,
+                input_notes: Arc::new(input_notes),
+                output_notes: Arc::new(output_notes),
+                swap: self.clone(),
+                cancel_signature: Some(cancel_signature),
+                anchors,
+                residual_fill: None,
+            };
+
+            InputNoteProvingInfo::new(
+                intent_note,
+                merkle_path.clone(),
+                Some(anchor),
+                Box::new(intent_vp),
+                vec![],
+            )
+        };
+
+        // Create the padding input note proving info
+        let padding_input_note_proving_info =
+            InputNoteProvingInfo::create_padding_note_proving_info(
+                padding_input_note,
+                merkle_path,
+                anchor,
+                input_notes,
+                output_notes,
+            );
+
+        // Create the returned note proving info
+        let returned_note_proving_info = TokenNote {
+            token_name: self.sell.token_name().clone(),
+            note: returned_note,
+        }
+        .generate_output_token_note_proving_info(
+            &mut rng,
+            self.auth,
+            input_notes,
+            output_notes,
+        );
+
+        // Create the padding output note proving info
+        let padding_output_note_proving_info =
+            OutputNoteProvingInfo::create_padding_note_proving_info(
+                padding_output_note,
+                input_notes,
+                output_notes,
+            );
+
+        ShieldedPartialTransaction::build(
+            [intent_note_proving_info, padding_input_note_proving_info],
+            [returned_note_proving_info, padding_output_note_proving_info],
+            vec![],
+            &mut rng,
+        )
+    }
+
+    /// Advertises the intent note to the solvers, signed with the auth key of the swap. The
+    /// advert only carries the swap terms and the commitment of the intent note. Only the first
+    /// ask is advertised.
+    pub fn public_advert<R: RngCore>(
+        &self,
+        rng: R,
+        intent_note: &Note,
+        expiry: u64,
+        auth_sk: &SigningKey,
+    ) -> Result<IntentAdvert, SwapError> {
+        self.check_intent_note(intent_note)?;
+        if auth_sk.public_key() != self.auth.pk {
+            return Err(SwapError::InvalidAuthKey);
+        }
+
+        let mut advert = IntentAdvert {
+            sell_token: self.sell.encode_name(),
+            sell_value: self.sell.note().value,
+            buy_token: self.asks[0].encode_name(),
+            buy_value: self.asks[0].value(),
+            intent_note_cm: intent_note.commitment(),
+            expiry,
+            signature: SchnorrSignature::default(),
+        };
+        advert.signature = SchnorrSignature::sign(rng, auth_sk, advert.signing_message());
+        Ok(advert)
+    }
+
     pub fn encode_app_data_static(&self) -> pallas::Base {
-        poseidon_hash_n([
-            self.sell.encode_name(),
-            self.sell.encode_value(),
-            self.buy.encode_name(),
-            self.buy.encode_value(),
+        SWAP_SCHEMA.encode(&self.to_static_data_values()).unwrap()
+    }
+
+    pub fn to_static_data_values(&self) -> Vec<StaticDataValue> {
+        vec![
+            StaticDataValue::Base(self.sell.encode_name()),
+            StaticDataValue::U64(self.sell.note().value),
+            StaticDataValue::Base(self.encode_asks()),
+            StaticDataValue::U64(self.max_solver_fee_bps),
             // Assuming the sold_token and bought_token have the same TOKEN_VK
-            TOKEN_VK.get_compressed(),
-            self.sell.note().get_nk_commitment(),
-            self.sell.note().app_data_dynamic,
-        ])
+            StaticDataValue::Base(TOKEN_VK.get_compressed()),
+            StaticDataValue::Base(self.sell.note().get_nk_commitment()),
+            StaticDataValue::Base(self.sell.note().app_data_dynamic),
+        ]
     }
 
     pub fn create_intent_note<R: RngCore>(&self, mut rng: R) -> Note {
-        let rseed = RandomSeed::random(&mut rng);
+        self.create_intent_note_with_rseed(RandomSeed::random(&mut rng))
+    }
+
+    pub fn create_intent_note_with_rseed(&self, rseed: RandomSeed) -> Note {
+        self.intent_note(self.sell.note().get_nf().unwrap(), rseed)
+    }
 
+    fn intent_note(&self, rho: Nullifier, rseed: RandomSeed) -> Note {
         Note::new(
             *COMPRESSED_PARTIAL_FULFILLMENT_INTENT_VK,
             self.encode_app_data_static(),
-            pallas::Base::zero(),
+            // The intent note is encrypted to the creator like the token notes
+            self.auth.to_app_data_dynamic(),
             1u64,
             self.sell.note().nk_container,
-            self.sell.note().get_nf().unwrap(),
+            rho,
             false,
             rseed,
         )
     }
 
-    /// Assign variables encoded in app_static_data
+    /// Assign variables encoded in app_static_data, along with the ask at `ask_index` of the
+    /// padded asks as the bought token
     pub fn assign_app_data_static(
         &self,
         column: Column<Advice>,
+        ask_index: usize,
         mut layouter: impl Layouter<pallas::Base>,
     ) -> Result<PartialFulfillmentIntentDataStatic, Error> {
         let token_vp_vk = assign_free_advice(
@@ -140,16 +728,44 @@ impl Swap {
             Value::known(self.sell.encode_value()),
         )?;
 
+        let padded_asks = self.padded_asks();
+        let mut asks = vec![];
+        let mut ask_flags = vec![];
+        for (i, ask) in padded_asks.iter().enumerate() {
+            let token = assign_free_advice(
+                layouter.namespace(|| format!("witness ask {i} token")),
+                column,
+                Value::known(ask.encode_name()),
+            )?;
+            let value = assign_free_advice(
+                layouter.namespace(|| format!("witness ask {i} value")),
+                column,
+                Value::known(ask.encode_value()),
+            )?;
+            asks.push((token, value));
+            ask_flags.push(assign_free_advice(
+                layouter.namespace(|| format!("witness ask {i} flag")),
+                column,
+                Value::known(pallas::Base::from((i == ask_index) as u64)),
+            )?);
+        }
+
         let bought_token = assign_free_advice(
             layouter.namespace(|| "witness bought_token"),
             column,
-            Value::known(self.buy.encode_name()),
+            Value::known(padded_asks[ask_index].encode_name()),
         )?;
 
         let bought_token_value = assign_free_advice(
             layouter.namespace(|| "witness bought_token_value"),
             column,
-            Value::known(self.buy.encode_value()),
+            Value::known(padded_asks[ask_index].encode_value()),
+        )?;
+
+        let max_solver_fee_bps = assign_free_advice(
+            layouter.namespace(|| "witness max_solver_fee_bps"),
+            column,
+            Value::known(pallas::Base::from(self.max_solver_fee_bps)),
         )?;
 
         let receiver_nk_com = assign_free_advice(
@@ -168,10 +784,613 @@ impl Swap {
             token_vp_vk,
             sold_token,
             sold_token_value,
+            asks,
+            ask_flags,
             bought_token,
             bought_token_value,
+            max_solver_fee_bps,
             receiver_nk_com,
             receiver_app_data_dynamic,
         })
     }
 }
+
+/// The size of the encoded advert
+pub const INTENT_ADVERT_SIZE: usize = 3 * 32 + 3 * 8 + SCHNORR_SIGNATURE_SIZE;
+
+/// The public terms of an intent, posted by its creator so that the solvers can find it. The
+/// intent note and the sold note stay private: no nullifier key, rseed or receiver data, only the
+/// commitment of the intent note.
+#[derive(Clone, Debug)]
+pub struct IntentAdvert {
+    // The encoded token names
+    pub sell_token: pallas::Base,
+    pub sell_value: u64,
+    pub buy_token: pallas::Base,
+    pub buy_value: u64,
+    pub intent_note_cm: NoteCommitment,
+    // A block height or a timestamp, it's only compared by the solvers
+    pub expiry: u64,
+    signature: SchnorrSignature,
+}
+
+impl IntentAdvert {
+    fn signing_message(&self) -> Vec<pallas::Base> {
+        let terms = poseidon_hash_n([
+            self.sell_token,
+            pallas::Base::from(self.sell_value),
+            self.buy_token,
+            pallas::Base::from(self.buy_value),
+            self.intent_note_cm.inner(),
+            pallas::Base::from(self.expiry),
+        ]);
+        off_chain_signing_message(INTENT_ADVERT_DOMAIN, terms)
+    }
+
+    /// Whether the advert is signed by the auth key in the signature.
+    pub fn verify(&self) -> bool {
+        self.signature.verify(&self.signing_message())
+    }
+
+    /// The auth key of the creator
+    pub fn pk(&self) -> pallas::Point {
+        self.signature.pk()
+    }
+
+    pub fn to_bytes(&self) -> [u8; INTENT_ADVERT_SIZE] {
+        let mut bytes = [0u8; INTENT_ADVERT_SIZE];
+        bytes[..32].copy_from_slice(&self.sell_token.to_repr());
+        bytes[32..40].copy_from_slice(&self.sell_value.to_le_bytes());
+        bytes[40..72].copy_from_slice(&self.buy_token.to_repr());
+        bytes[72..80].copy_from_slice(&self.buy_value.to_le_bytes());
+        bytes[80..112].copy_from_slice(&self.intent_note_cm.to_bytes());
+        bytes[112..120].copy_from_slice(&self.expiry.to_le_bytes());
+        bytes[120..].copy_from_slice(&self.signature.to_bytes());
+        bytes
+    }
+
+    /// None if a field element or the signature is not canonical. The signature is not verified.
+    pub fn from_bytes(bytes: &[u8; INTENT_ADVERT_SIZE]) -> Option<Self> {
+        let base = |bytes: &[u8]| -> Option<pallas::Base> {
+            pallas::Base::from_repr(bytes.try_into().unwrap()).into()
+        };
+        let u64_at = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+        Some(Self {
+            sell_token: base(&bytes[..32])?,
+            sell_value: u64_at(&bytes[32..40]),
+            buy_token: base(&bytes[40..72])?,
+            buy_value: u64_at(&bytes[72..80]),
+            intent_note_cm: Option::from(NoteCommitment::from_bytes(
+                bytes[80..112].try_into().unwrap(),
+            ))?,
+            expiry: u64_at(&bytes[112..120]),
+            signature: SchnorrSignature::from_bytes(bytes[120..].try_into().unwrap())?,
+        })
+    }
+}
+
+/// The terms of a swap encrypted to the rcv pk of its authorization. The intent note only
+/// commits to the terms, the creator publishes them in the extra data of the intent ptx so that
+/// a pending intent can be rebuilt from the chain, see `Swap::from_decrypted_intent_note`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedSwapTerms(Vec<EncryptedNote>);
+
+impl EncryptedSwapTerms {
+    // The note ciphertexts of the terms, one after the other
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .flat_map(|encrypted_note| encrypted_note.to_bytes())
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NoteError> {
+        if bytes.is_empty() || bytes.len() % EncryptedNote::BYTES_LEN != 0 {
+            return Err(NoteError::MalformedCiphertext);
+        }
+        bytes
+            .chunks(EncryptedNote::BYTES_LEN)
+            .map(EncryptedNote::from_bytes)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+
+    /// The serialized terms, if they're encrypted to the key.
+    pub fn decrypt(&self, ivk: &IncomingViewingKey) -> Result<Vec<u8>, NoteError> {
+        let mut bytes = vec![];
+        for encrypted_note in &self.0 {
+            for element in ivk.decrypt_plaintext(encrypted_note)? {
+                let repr = element.to_repr();
+                if repr[31] != 0 {
+                    return Err(NoteError::MalformedCiphertext);
+                }
+                bytes.extend_from_slice(&repr[..31]);
+            }
+        }
+        let len = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        bytes
+            .get(4..4 + len)
+            .map(<[u8]>::to_vec)
+            .ok_or(NoteError::MalformedCiphertext)
+    }
+}
+
+/// Builds the swap of an existing token note, e.g. a note retrieved from the chain.
+#[derive(Clone, Debug)]
+pub struct SwapBuilder {
+    sell: TokenNote,
+    asks: Vec<Token>,
+    auth: TokenAuthorization,
+    max_solver_fee_bps: u64,
+}
+
+impl SwapBuilder {
+    /// The sold note must be authorized by `auth` and carry the nullifier key.
+    pub fn new(sell: TokenNote, buy: Token, auth: TokenAuthorization) -> Self {
+        Self {
+            sell,
+            asks: vec![buy],
+            auth,
+            max_solver_fee_bps: 0,
+        }
+    }
+
+    /// Adds another ask option, the swap can be filled with any one of the asks.
+    pub fn or_buy(mut self, buy: Token) -> Self {
+        self.asks.push(buy);
+        self
+    }
+
+    /// Lets the solvers keep up to `bps` basis points of the pro-rata value of a fill, no fee by
+    /// default.
+    pub fn with_max_solver_fee_bps(mut self, bps: u64) -> Self {
+        self.max_solver_fee_bps = bps;
+        self
+    }
+
+    /// Returns the swap and its intent note. The intent note only depends on the swap and the
+    /// rseed.
+    pub fn build(self, rseed: RandomSeed) -> Result<(Swap, Note), SwapError> {
+        let note = self.sell.note();
+        if note.get_app_vk() != TOKEN_VK.get_compressed()
+            || note.get_app_data_static() != self.sell.encode_name()
+            || note.app_data_dynamic != self.auth.to_app_data_dynamic()
+            || note.nk_container.get_nk().is_none()
+        {
+            return Err(SwapError::InvalidSellNote);
+        }
+
+        let swap = Swap {
+            sell: self.sell,
+            asks: self.asks,
+            auth: self.auth,
+            max_solver_fee_bps: self.max_solver_fee_bps,
+        };
+        swap.validate()?;
+        let intent_note = swap.create_intent_note_with_rseed(rseed);
+        Ok((swap, intent_note))
+    }
+}
+
+#[test]
+fn test_swap_builder() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let auth = TokenAuthorization::random(&mut rng);
+    let rho = Nullifier::random(&mut rng);
+    let nk = NullifierKeyContainer::random_key(&mut rng);
+    let sell = Token::new(TokenName::new("btc").unwrap(), 2u64)
+        .create_random_token_note(&mut rng, rho, nk, &auth);
+    let buy = Token::new(TokenName::new("eth").unwrap(), 10u64);
+    let rseed = RandomSeed::random(&mut rng);
+
+    let (swap, intent_note) = SwapBuilder::new(sell.clone(), buy.clone(), auth)
+        .build(rseed)
+        .unwrap();
+    assert_eq!(swap.sell().note(), sell.note());
+    assert_eq!(swap.asks().len(), 1);
+    assert_eq!(swap.asks()[0].name(), buy.name());
+    assert_eq!(swap.asks()[0].value(), buy.value());
+    assert_eq!(swap.auth().pk, auth.pk);
+    assert_eq!(swap.ratio(&buy), 5);
+
+    // The intent note is deterministic and can be recovered from
+    let (_, same_intent_note) = SwapBuilder::new(sell.clone(), buy.clone(), auth)
+        .build(rseed)
+        .unwrap();
+    assert_eq!(intent_note, same_intent_note);
+    let opens = |note: &Note, asks: Vec<Token>, max_solver_fee_bps: u64| {
+        Swap {
+            sell: sell.clone(),
+            asks,
+            auth,
+            max_solver_fee_bps,
+        }
+        .opens_intent_note(note)
+    };
+    assert!(opens(&intent_note, vec![buy.clone()], 0).is_ok());
+
+    // The sold note must belong to the authorization
+    let other_auth = TokenAuthorization::random(&mut rng);
+    assert_eq!(
+        SwapBuilder::new(sell.clone(), buy.clone(), other_auth)
+            .build(rseed)
+            .unwrap_err(),
+        SwapError::InvalidSellNote
+    );
+
+    // The nullifier key is needed to derive the rho of the intent note
+    let sell_with_nk_com = TokenNote {
+        note: Note {
+            nk_container: NullifierKeyContainer::from_commitment(sell.note().get_nk_commitment()),
+            ..*sell.note()
+        },
+        ..sell.clone()
+    };
+    assert_eq!(
+        SwapBuilder::new(sell_with_nk_com, buy, auth)
+            .build(rseed)
+            .unwrap_err(),
+        SwapError::InvalidSellNote
+    );
+
+    // The bought value must be a multiple of the sold value
+    assert_eq!(
+        SwapBuilder::new(
+            sell.clone(),
+            Token::new(TokenName::new("eth").unwrap(), 11u64),
+            auth
+        )
+        .build(rseed)
+        .unwrap_err(),
+        SwapError::InvalidRatio
+    );
+
+    // Any of the asks can be bought, they are all committed in the intent note
+    let xan = Token::new(TokenName::new("xan").unwrap(), 500u64);
+    let (multi_swap, multi_intent_note) = SwapBuilder::new(sell.clone(), buy.clone(), auth)
+        .or_buy(xan.clone())
+        .build(rseed)
+        .unwrap();
+    assert_eq!(multi_swap.ask(xan.name()).unwrap().value(), 500);
+    assert_eq!(multi_swap.ratio(&xan), 250);
+    assert_ne!(multi_intent_note, intent_note);
+    assert_eq!(
+        opens(&multi_intent_note, vec![buy.clone()], 0).unwrap_err(),
+        SwapError::InconsistentIntentNote
+    );
+    assert!(opens(&multi_intent_note, vec![buy.clone(), xan.clone()], 0).is_ok());
+    assert_eq!(
+        SwapBuilder::new(sell.clone(), buy.clone(), auth)
+            .or_buy(Token::new(TokenName::new("xan").unwrap(), 501u64))
+            .build(rseed)
+            .unwrap_err(),
+        SwapError::InvalidRatio
+    );
+    let too_many_asks = (0..MAX_SWAP_ASKS)
+        .fold(SwapBuilder::new(sell.clone(), buy, auth), |builder, _| {
+            builder.or_buy(xan.clone())
+        });
+    assert_eq!(
+        too_many_asks.build(rseed).unwrap_err(),
+        SwapError::InvalidAsks
+    );
+
+    // The max solver fee is committed in the intent note
+    let (fee_swap, fee_intent_note) = SwapBuilder::new(sell.clone(), buy.clone(), auth)
+        .with_max_solver_fee_bps(25)
+        .build(rseed)
+        .unwrap();
+    assert_eq!(fee_swap.max_solver_fee_bps, 25);
+    assert_ne!(fee_intent_note, intent_note);
+    assert_eq!(
+        opens(&fee_intent_note, vec![buy.clone()], 0).unwrap_err(),
+        SwapError::InconsistentIntentNote
+    );
+    assert!(opens(&fee_intent_note, vec![buy.clone()], 25).is_ok());
+
+    // The swap must be fillable
+    assert_eq!(
+        SwapBuilder::new(
+            sell.clone(),
+            Token::new(TokenName::new("btc").unwrap(), 4u64),
+            auth
+        )
+        .build(rseed)
+        .unwrap_err(),
+        SwapError::SameToken
+    );
+    assert_eq!(
+        SwapBuilder::new(sell, Token::new(TokenName::new("eth").unwrap(), 0u64), auth)
+            .build(rseed)
+            .unwrap_err(),
+        SwapError::ZeroValue
+    );
+}
+
+#[test]
+fn test_swap_validate() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let auth = TokenAuthorization::random(&mut rng);
+    let swap = Swap::random(
+        &mut rng,
+        Token::new(TokenName::new("btc").unwrap(), 2u64),
+        Token::new(TokenName::new("eth").unwrap(), 10u64),
+        auth,
+    );
+    assert_eq!(swap.validate(), Ok(()));
+
+    let with_asks = |asks: Vec<Token>| Swap {
+        asks,
+        ..swap.clone()
+    };
+    assert_eq!(with_asks(vec![]).validate(), Err(SwapError::InvalidAsks));
+    assert_eq!(
+        with_asks(vec![
+            Token::new(TokenName::new("eth").unwrap(), 10u64);
+            MAX_SWAP_ASKS + 1
+        ])
+        .validate(),
+        Err(SwapError::InvalidAsks)
+    );
+    assert_eq!(
+        with_asks(vec![Token::new(TokenName::new("eth").unwrap(), 0u64)]).validate(),
+        Err(SwapError::ZeroValue)
+    );
+    assert_eq!(
+        with_asks(vec![
+            Token::new(TokenName::new("eth").unwrap(), 10u64),
+            Token::new(TokenName::new("btc").unwrap(), 4u64)
+        ])
+        .validate(),
+        Err(SwapError::SameToken)
+    );
+    assert_eq!(
+        with_asks(vec![Token::new(TokenName::new("eth").unwrap(), 11u64)]).validate(),
+        Err(SwapError::InvalidRatio)
+    );
+
+    // Nothing to sell
+    let zero_sell = Swap {
+        sell: TokenNote {
+            note: Note {
+                value: 0,
+                ..*swap.sell.note()
+            },
+            ..swap.sell.clone()
+        },
+        ..swap.clone()
+    };
+    assert_eq!(zero_sell.validate(), Err(SwapError::ZeroValue));
+
+    // The solver can keep up to all of the fill
+    let with_fee = |max_solver_fee_bps: u64| Swap {
+        max_solver_fee_bps,
+        ..swap.clone()
+    };
+    assert_eq!(with_fee(MAX_SOLVER_FEE_BPS).validate(), Ok(()));
+    assert_eq!(
+        with_fee(MAX_SOLVER_FEE_BPS + 1).validate(),
+        Err(SwapError::InvalidSolverFee)
+    );
+
+    // The issuable token of the same name is another token
+    let issuable_btc = Token::new(
+        TokenName::new_issuable("btc", TokenAuthorization::random(&mut rng).pk).unwrap(),
+        4u64,
+    );
+    assert_eq!(with_asks(vec![issuable_btc]).validate(), Ok(()));
+}
+
+#[test]
+fn test_fill_with_solver_fee() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let auth = TokenAuthorization::random(&mut rng);
+    let swap = Swap {
+        max_solver_fee_bps: 25,
+        ..Swap::random(
+            &mut rng,
+            Token::new(TokenName::new("btc").unwrap(), 2u64),
+            Token::new(TokenName::new("xan").unwrap(), 8000u64),
+            auth,
+        )
+    };
+    let intent_note = swap.create_intent_note(&mut rng);
+    assert_eq!(swap.max_solver_fee(4000), 10);
+    assert_eq!(swap.max_solver_fee(399), 0);
+
+    // The solver keeps 10 of the 4000 xan paid for 1 btc
+    let offer = Token::new(TokenName::new("xan").unwrap(), 4000u64);
+    let (_, [bought_note, returned_note]) = swap
+        .fill_with_solver_fee(&mut rng, intent_note, offer.clone(), 10)
+        .unwrap();
+    assert_eq!(bought_note.value, 3990);
+    assert_eq!(bought_note.get_app_data_static(), offer.encode_name());
+    assert_eq!(returned_note.value, 1);
+
+    // 11 is more than 25 bps
+    assert_eq!(
+        swap.fill_with_solver_fee(&mut rng, intent_note, offer, 11)
+            .unwrap_err(),
+        SwapError::InvalidSolverFee
+    );
+}
+
+#[test]
+#[should_panic(expected = "the swap can't be filled")]
+fn test_random_swap_of_same_token() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let auth = TokenAuthorization::random(&mut rng);
+    Swap::random(
+        &mut rng,
+        Token::new(TokenName::new("btc").unwrap(), 2u64),
+        Token::new(TokenName::new("btc").unwrap(), 4u64),
+        auth,
+    );
+}
+
+#[test]
+fn test_intent_advert() {
+    use crate::circuit::vp_examples::signature_verification::COMPRESSED_TOKEN_AUTH_VK;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let auth_sk = SigningKey::random(&mut rng);
+    let auth = TokenAuthorization::from_sk_vk(auth_sk.inner(), &COMPRESSED_TOKEN_AUTH_VK);
+    let swap = Swap::random(
+        &mut rng,
+        Token::new(TokenName::new("btc").unwrap(), 2u64),
+        Token::new(TokenName::new("eth").unwrap(), 10u64),
+        auth,
+    );
+    let intent_note = swap.create_intent_note(&mut rng);
+    let advert = swap
+        .public_advert(&mut rng, &intent_note, 100, &auth_sk)
+        .unwrap();
+    assert!(advert.verify());
+    assert_eq!(advert.pk(), auth.pk);
+    assert_eq!(advert.intent_note_cm, intent_note.commitment());
+
+    // Round trip
+    let bytes = advert.to_bytes();
+    let decoded = IntentAdvert::from_bytes(&bytes).unwrap();
+    assert!(decoded.verify());
+    assert_eq!(decoded.to_bytes(), bytes);
+
+    // The terms are signed
+    let mut tampered = advert.clone();
+    tampered.buy_value = 5;
+    assert!(!tampered.verify());
+    let mut tampered = advert;
+    tampered.expiry = 200;
+    assert!(!tampered.verify());
+
+    // Only the creator can advertise the intent, and only the intent note of the swap
+    assert_eq!(
+        swap.public_advert(&mut rng, &intent_note, 100, &SigningKey::random(&mut rng))
+            .unwrap_err(),
+        SwapError::InvalidAuthKey
+    );
+    let other_intent_note = Swap::random(
+        &mut rng,
+        Token::new(TokenName::new("btc").unwrap(), 2u64),
+        Token::new(TokenName::new("eth").unwrap(), 10u64),
+        auth,
+    )
+    .create_intent_note(&mut rng);
+    assert_eq!(
+        swap.public_advert(&mut rng, &other_intent_note, 100, &auth_sk)
+            .unwrap_err(),
+        SwapError::InconsistentIntentNote
+    );
+}
+
+#[test]
+fn test_encrypted_swap_terms() {
+    use crate::circuit::vp_examples::receiver_vp::COMPRESSED_RECEIVER_VK;
+    use crate::keys::SpendingKey;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let sk = SpendingKey::random(&mut rng);
+    let ivk = sk.to_incoming_viewing_key();
+    let auth = TokenAuthorization::from_address(&ivk.to_payment_address(*COMPRESSED_RECEIVER_VK));
+    let issuer = pallas::Point::random(&mut rng);
+    let sell = Token::new(TokenName::new("btc").unwrap(), 2u64).create_random_token_note(
+        &mut rng,
+        Nullifier::random(&mut rng),
+        sk.get_nk(),
+        &auth,
+    );
+    let (swap, intent_note) = SwapBuilder::new(
+        sell,
+        Token::new(TokenName::new("eth").unwrap(), 10u64),
+        auth,
+    )
+    .or_buy(Token::new(
+        TokenName::new_issuable("xan", issuer).unwrap(),
+        500u64,
+    ))
+    .with_max_solver_fee_bps(25)
+    .build(RandomSeed::random(&mut rng))
+    .unwrap();
+
+    // The creator decrypts the terms and rebuilds the swap from the intent note alone
+    let encrypted_terms =
+        EncryptedSwapTerms::from_bytes(&swap.encrypt_terms(&mut rng).to_bytes()).unwrap();
+    let terms = encrypted_terms.decrypt(&ivk).unwrap();
+    let recovered = Swap::from_decrypted_intent_note(&intent_note, &terms).unwrap();
+    assert_eq!(recovered.sell().token_name(), swap.sell().token_name());
+    assert_eq!(recovered.sell().note().value, 2);
+    assert_eq!(
+        recovered.sell().note().nk_container,
+        intent_note.nk_container
+    );
+    assert_eq!(recovered.asks().len(), 2);
+    assert_eq!(recovered.asks()[1].name().issuer(), Some(issuer));
+    assert_eq!(recovered.auth().to_bytes(), auth.to_bytes());
+    assert_eq!(recovered.max_solver_fee_bps, 25);
+    assert_eq!(
+        recovered.encode_app_data_static(),
+        intent_note.get_app_data_static()
+    );
+
+    // Another key can't decrypt the terms
+    assert_eq!(
+        encrypted_terms
+            .decrypt(&SpendingKey::random(&mut rng).to_incoming_viewing_key())
+            .unwrap_err(),
+        NoteError::WrongDecryptionKey
+    );
+
+    // The terms must be the ones committed in the intent note
+    let other_terms = Swap {
+        max_solver_fee_bps: 0,
+        ..swap.clone()
+    }
+    .encrypt_terms(&mut rng)
+    .decrypt(&ivk)
+    .unwrap();
+    assert_eq!(
+        Swap::from_decrypted_intent_note(&intent_note, &other_terms).unwrap_err(),
+        SwapError::InconsistentIntentNote
+    );
+    assert_eq!(
+        Swap::from_decrypted_intent_note(&intent_note, &terms[..terms.len() - 1]).unwrap_err(),
+        SwapError::MalformedTerms
+    );
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use super::Swap;
+    use crate::circuit::vp_examples::token::{testing::arb_token_authorization, Token, TokenName};
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    prop_compose! {
+        // The bought value is a multiple of the sold value, of another token
+        pub fn arb_swap()(sell_name in "[a-z]{1,16}")(
+            buy_name in "[a-z]{1,16}".prop_filter("the bought token is the sold one", {
+                let sell_name = sell_name.clone();
+                move |buy_name| *buy_name != sell_name
+            }),
+            sell_name in Just(sell_name),
+            sell_value in 1u64..1000,
+            ratio in 1u64..1000,
+            auth in arb_token_authorization(),
+            seed in any::<[u8; 32]>(),
+        ) -> Swap {
+            let sell = Token::new(TokenName::new(&sell_name).unwrap(), sell_value);
+            let buy = Token::new(TokenName::new(&buy_name).unwrap(), sell_value * ratio);
+            Swap::random(StdRng::from_seed(seed), sell, buy, auth)
+        }
+    }
+}
@@ -2,36 +2,40 @@ use crate::{
     circuit::{
         blake2s::publicize_default_dynamic_vp_commitments,
         gadgets::{
-            add::AddChip, assign_free_advice, poseidon_hash::poseidon_hash_gadget,
-            target_note_variable::get_owned_note_variable,
+            add::AddChip,
+            assign_free_advice, assign_free_constant,
+            mul::{MulChip, MulInstructions},
+            poseidon_hash::poseidon_hash_gadget,
+            sub::{SubChip, SubInstructions},
+            target_note_variable::get_owned_note_variables,
         },
         note_encryption_circuit::note_encryption_gadget,
         vp_circuit::{
-            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
-            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+            BasicValidityPredicateVariables, NoteVariables, VPVerifyingInfo,
+            ValidityPredicateCircuit, ValidityPredicateConfig, ValidityPredicatePublicInputs,
+            ValidityPredicateVerifyingInfo,
         },
         vp_examples::signature_verification::COMPRESSED_TOKEN_AUTH_VK,
     },
-    constant::{GENERATOR, NUM_NOTE, SETUP_PARAMS_MAP},
+    constant::{TaigaFixedBases, APP_DATA_DYNAMIC_DOMAIN, NUM_NOTE},
     note::{Note, RandomSeed},
-    note_encryption::{NoteCiphertext, NotePlaintext, SecretKey},
+    note_encryption::{NoteEncryption, NoteKind},
     proof::Proof,
-    utils::mod_r_p,
     vp_commitment::ValidityPredicateCommitment,
     vp_vk::ValidityPredicateVerifyingKey,
 };
-use group::Group;
-use group::{cofactor::CofactorCurveAffine, Curve};
+use group::{Curve, Group};
 use halo2_gadgets::ecc::{chip::EccChip, NonIdentityPoint};
 use halo2_proofs::{
     arithmetic::CurveAffine,
-    circuit::{floor_planner, Layouter, Value},
+    circuit::{floor_planner, AssignedCell, Layouter, Value},
     plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
 };
 use lazy_static::lazy_static;
 use pasta_curves::pallas;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use std::sync::Arc;
 const CIPHER_LEN: usize = 9;
 
 lazy_static! {
@@ -44,26 +48,179 @@ lazy_static! {
 #[derive(Clone, Debug)]
 pub struct ReceiverValidityPredicateCircuit {
     pub owned_note_pub_id: pallas::Base,
-    pub input_notes: [Note; NUM_NOTE],
-    pub output_notes: [Note; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
     pub vp_vk: pallas::Base,
     pub nonce: pallas::Base,
-    pub sk: pallas::Base,
+    // The ephemeral secret key of the note encryption, fresh for every note
+    pub esk: pallas::Base,
     pub rcv_pk: pallas::Point,
+    // The auth pk of the note, it's only bound to the app_data_dynamic
+    pub auth_pk: pallas::Point,
     pub auth_vp_vk: pallas::Base,
+    // The NoteKind tag in the plaintext, see output_note_kind
+    pub note_kind: pallas::Base,
+}
+
+/// The kind of the output note for the receiver wallet. The owner of the notes of the same
+/// application is compared by app_data_dynamic, which encodes the auth pk(and the vps) of the
+/// token-like notes.
+pub fn output_note_kind(
+    note: &Note,
+    input_notes: &[Note; NUM_NOTE],
+    output_notes: &[Note; NUM_NOTE],
+) -> NoteKind {
+    let is_spender = |note: &Note| {
+        input_notes.iter().any(|input| {
+            input.note_type.app_vk == note.note_type.app_vk
+                && input.app_data_dynamic == note.app_data_dynamic
+        })
+    };
+    if !is_spender(note) {
+        NoteKind::External
+    } else if output_notes
+        .iter()
+        .any(|output| output.note_type.app_vk == note.note_type.app_vk && !is_spender(output))
+    {
+        NoteKind::Change
+    } else {
+        NoteKind::SelfTransfer
+    }
+}
+
+/// Encrypts the owned note to rcv_pk and publicizes the ciphertext and epk. The vps encrypting
+/// their owned note share it, the plaintext is the note fields and the note kind.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn owned_note_encryption_gadget(
+    config: &ValidityPredicateConfig,
+    mut layouter: impl Layouter<pallas::Base>,
+    ecc_chip: EccChip<TaigaFixedBases>,
+    note_variables: NoteVariables,
+    note_kind: pallas::Base,
+    nonce: AssignedCell<pallas::Base, pallas::Base>,
+    esk: AssignedCell<pallas::Base, pallas::Base>,
+    rcv_pk: NonIdentityPoint<pallas::Affine, EccChip<TaigaFixedBases>>,
+) -> Result<(), Error> {
+    let mut message = vec![
+        note_variables.app_vk,
+        note_variables.app_data_static,
+        note_variables.app_data_dynamic,
+        note_variables.value,
+        note_variables.rho,
+        note_variables.nk_com,
+        note_variables.psi,
+        note_variables.rcm,
+    ];
+
+    // The note kind is one of NoteKind: note_kind * (note_kind - 1) * (note_kind - 2) = 0
+    let note_kind = assign_free_advice(
+        layouter.namespace(|| "witness note kind"),
+        config.advices[0],
+        Value::known(note_kind),
+    )?;
+    let constant_zero = assign_free_constant(
+        layouter.namespace(|| "zero"),
+        config.advices[0],
+        pallas::Base::zero(),
+    )?;
+    let constant_one = assign_free_constant(
+        layouter.namespace(|| "one"),
+        config.advices[0],
+        pallas::Base::one(),
+    )?;
+    let constant_two = assign_free_constant(
+        layouter.namespace(|| "two"),
+        config.advices[0],
+        pallas::Base::from(2),
+    )?;
+    let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+    let mul_chip = MulChip::construct(config.mul_config.clone());
+    let kind_minus_one = SubInstructions::sub(
+        &sub_chip,
+        layouter.namespace(|| "note_kind - 1"),
+        &note_kind,
+        &constant_one,
+    )?;
+    let kind_minus_two = SubInstructions::sub(
+        &sub_chip,
+        layouter.namespace(|| "note_kind - 2"),
+        &note_kind,
+        &constant_two,
+    )?;
+    let kind_check = MulInstructions::mul(
+        &mul_chip,
+        layouter.namespace(|| "note_kind * (note_kind - 1)"),
+        &note_kind,
+        &kind_minus_one,
+    )?;
+    let kind_check = MulInstructions::mul(
+        &mul_chip,
+        layouter.namespace(|| "note_kind * (note_kind - 1) * (note_kind - 2)"),
+        &kind_check,
+        &kind_minus_two,
+    )?;
+    layouter.assign_region(
+        || "check note kind",
+        |mut region| region.constrain_equal(kind_check.cell(), constant_zero.cell()),
+    )?;
+    message.push(note_kind);
+
+    let add_chip = AddChip::<pallas::Base>::construct(config.add_config.clone(), ());
+
+    note_encryption_gadget(
+        layouter.namespace(|| "note encryption"),
+        config.advices[0],
+        config.instances,
+        config.poseidon_config.clone(),
+        add_chip,
+        ecc_chip,
+        nonce,
+        esk,
+        rcv_pk,
+        &mut message,
+    )
+}
+
+/// The public inputs of owned_note_encryption_gadget: the ciphertext and the epk
+pub(crate) fn owned_note_encryption_public_inputs(
+    note_encryption: &NoteEncryption,
+    owned_note: &Note,
+) -> Vec<pallas::Base> {
+    let cipher = note_encryption.encrypt(owned_note);
+    let epk_coord = note_encryption.epk().to_affine().coordinates().unwrap();
+    cipher
+        .inner()
+        .iter()
+        .copied()
+        .chain([*epk_coord.x(), *epk_coord.y()])
+        .collect()
+}
+
+impl ReceiverValidityPredicateCircuit {
+    // The encryption of the owned note, the action of the note commits to the same ciphertext
+    pub fn note_encryption(&self) -> NoteEncryption {
+        NoteEncryption {
+            nonce: self.nonce,
+            esk: self.esk,
+            rcv_pk: self.rcv_pk,
+            note_kind: self.note_kind,
+        }
+    }
 }
 
 impl Default for ReceiverValidityPredicateCircuit {
     fn default() -> Self {
         Self {
             owned_note_pub_id: pallas::Base::zero(),
-            input_notes: [(); NUM_NOTE].map(|_| Note::default()),
-            output_notes: [(); NUM_NOTE].map(|_| Note::default()),
+            input_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            output_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
             vp_vk: pallas::Base::zero(),
             nonce: pallas::Base::zero(),
-            sk: pallas::Base::zero(),
+            esk: pallas::Base::zero(),
             rcv_pk: pallas::Point::generator(),
+            auth_pk: pallas::Point::generator(),
             auth_vp_vk: pallas::Base::zero(),
+            note_kind: NoteKind::default().to_base(),
         }
     }
 }
@@ -82,29 +239,36 @@ impl ValidityPredicateCircuit for ReceiverValidityPredicateCircuit {
             Value::known(self.nonce),
         )?;
 
-        let sk = assign_free_advice(
-            layouter.namespace(|| "witness sk"),
+        let esk = assign_free_advice(
+            layouter.namespace(|| "witness esk"),
             config.advices[0],
-            Value::known(self.sk),
+            Value::known(self.esk),
         )?;
 
         // Construct an ECC chip
-        let ecc_chip = EccChip::construct(config.ecc_config);
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
 
+        // The receiver pk comes from the app_data_dynamic, witnessing it as a NonIdentityPoint
+        // constrains it to be on the curve and rejects the identity.
         let rcv_pk = NonIdentityPoint::new(
             ecc_chip.clone(),
             layouter.namespace(|| "witness rcv_pk"),
             Value::known(self.rcv_pk.to_affine()),
         )?;
 
-        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
-        let app_data_dynamic = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note app_data_dynamic"),
-            &owned_note_pub_id,
-            &basic_variables.get_app_data_dynamic_searchable_pairs(),
+        let auth_pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness auth_pk"),
+            Value::known(self.auth_pk.to_affine()),
         )?;
 
+        let owned_note = get_owned_note_variables(
+            &config,
+            layouter.namespace(|| "get owned note variables"),
+            &basic_variables,
+        )?;
+        let app_data_dynamic = owned_note.note_variables.app_data_dynamic.clone();
+
         let auth_vp_vk = assign_free_advice(
             layouter.namespace(|| "witness auth vp vk"),
             config.advices[0],
@@ -116,15 +280,24 @@ impl ValidityPredicateCircuit for ReceiverValidityPredicateCircuit {
             Value::known(self.vp_vk),
         )?;
 
+        let app_data_dynamic_domain = assign_free_constant(
+            layouter.namespace(|| "app_data_dynamic domain"),
+            config.advices[0],
+            APP_DATA_DYNAMIC_DOMAIN,
+        )?;
+
         // Decode the app_data_dynamic, and check the app_data_dynamic encoding
         let encoded_app_data_dynamic = poseidon_hash_gadget(
             config.poseidon_config.clone(),
             layouter.namespace(|| "app_data_dynamic encoding"),
             [
-                rcv_pk.inner().x(),
-                rcv_pk.inner().y(),
+                app_data_dynamic_domain,
+                auth_pk.inner().x(),
+                auth_pk.inner().y(),
                 auth_vp_vk,
                 receiver_vp_vk,
+                rcv_pk.inner().x(),
+                rcv_pk.inner().y(),
             ],
         )?;
 
@@ -135,83 +308,16 @@ impl ValidityPredicateCircuit for ReceiverValidityPredicateCircuit {
             },
         )?;
 
-        // search target note and get the app_static_data
-        let app_data_static = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note app_data_static"),
-            &owned_note_pub_id,
-            &basic_variables.get_app_data_static_searchable_pairs(),
-        )?;
-
-        // search target note and get the app_vk
-        let app_vk = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note app_vk"),
-            &owned_note_pub_id,
-            &basic_variables.get_app_vk_searchable_pairs(),
-        )?;
-
-        // search target note and get the value
-        let value = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note value"),
-            &owned_note_pub_id,
-            &basic_variables.get_value_searchable_pairs(),
-        )?;
-
-        let rho = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note rho"),
-            &owned_note_pub_id,
-            &basic_variables.get_rho_searchable_pairs(),
-        )?;
-
-        let nk_com = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note nk_com"),
-            &owned_note_pub_id,
-            &basic_variables.get_nk_com_searchable_pairs(),
-        )?;
-
-        let psi = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note psi"),
-            &owned_note_pub_id,
-            &basic_variables.get_psi_searchable_pairs(),
-        )?;
-
-        let rcm = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note psi"),
-            &owned_note_pub_id,
-            &basic_variables.get_rcm_searchable_pairs(),
-        )?;
-
-        let mut message = vec![
-            app_vk,
-            app_data_static,
-            app_data_dynamic,
-            value,
-            rho,
-            nk_com,
-            psi,
-            rcm,
-        ];
-
-        let add_chip = AddChip::<pallas::Base>::construct(config.add_config.clone(), ());
-
         // Encryption
-        note_encryption_gadget(
-            layouter.namespace(|| "note encryption"),
-            config.advices[0],
-            config.instances,
-            config.poseidon_config,
-            add_chip,
+        owned_note_encryption_gadget(
+            &config,
+            layouter.namespace(|| "owned note encryption"),
             ecc_chip,
+            owned_note.note_variables,
+            self.note_kind,
             nonce,
-            sk,
+            esk,
             rcv_pk,
-            &mut message,
         )?;
 
         // Publicize the dynamic vp commitments with default value
@@ -251,26 +357,10 @@ impl ValidityPredicateCircuit for ReceiverValidityPredicateCircuit {
             } else {
                 self.get_output_notes()[1]
             };
-        let message = vec![
-            target_note.note_type.app_vk,
-            target_note.note_type.app_data_static,
-            target_note.app_data_dynamic,
-            pallas::Base::from(target_note.value),
-            target_note.rho.inner(),
-            target_note.get_nk_commitment(),
-            target_note.psi,
-            target_note.rcm,
-        ];
-        let plaintext = NotePlaintext::padding(&message);
-        let key = SecretKey::from_dh_exchange(&self.rcv_pk, &mod_r_p(self.sk));
-        let cipher = NoteCiphertext::encrypt(&plaintext, &key, &self.nonce);
-        cipher.inner().iter().for_each(|&c| public_inputs.push(c));
-
-        let generator = GENERATOR.to_curve();
-        let pk = generator * mod_r_p(self.sk);
-        let pk_coord = pk.to_affine().coordinates().unwrap();
-        public_inputs.push(*pk_coord.x());
-        public_inputs.push(*pk_coord.y());
+        public_inputs.extend(owned_note_encryption_public_inputs(
+            &self.note_encryption(),
+            &target_note,
+        ));
         public_inputs.into()
     }
 
@@ -284,13 +374,13 @@ vp_verifying_info_impl!(ReceiverValidityPredicateCircuit);
 
 #[test]
 fn test_halo2_receiver_vp_circuit() {
-    use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
     use crate::{
+        constant::GENERATOR,
         note::tests::{random_input_note, random_output_note},
-        utils::poseidon_hash_n,
+        utils::{mod_r_p, poseidon_hash_n},
     };
     use ff::{Field, PrimeField};
-    use halo2_proofs::dev::MockProver;
+    use group::cofactor::CofactorCurveAffine;
     use rand::rngs::OsRng;
 
     let mut rng = OsRng;
@@ -301,41 +391,105 @@ fn test_halo2_receiver_vp_circuit() {
             .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()))
             .collect::<Vec<_>>();
         let nonce = pallas::Base::from_u128(23333u128);
-        let sk = pallas::Base::random(&mut rng);
+        let esk = pallas::Base::random(&mut rng);
         let rcv_sk = pallas::Base::random(&mut rng);
         let generator = GENERATOR.to_curve();
         let rcv_pk = generator * mod_r_p(rcv_sk);
         let rcv_pk_coord = rcv_pk.to_affine().coordinates().unwrap();
+        // The auth key is not the decryption key
+        let auth_pk = pallas::Point::random(&mut rng);
+        let auth_pk_coord = auth_pk.to_affine().coordinates().unwrap();
         output_notes[0].app_data_dynamic = poseidon_hash_n([
-            *rcv_pk_coord.x(),
-            *rcv_pk_coord.y(),
+            APP_DATA_DYNAMIC_DOMAIN,
+            *auth_pk_coord.x(),
+            *auth_pk_coord.y(),
             *COMPRESSED_TOKEN_AUTH_VK,
             *COMPRESSED_RECEIVER_VK,
+            *rcv_pk_coord.x(),
+            *rcv_pk_coord.y(),
         ]);
         let owned_note_pub_id = output_notes[0].commitment().inner();
         (
             ReceiverValidityPredicateCircuit {
                 owned_note_pub_id,
-                input_notes,
-                output_notes: output_notes.try_into().unwrap(),
+                input_notes: Arc::new(input_notes),
+                output_notes: Arc::new(output_notes.try_into().unwrap()),
                 vp_vk: *COMPRESSED_RECEIVER_VK,
                 nonce,
-                sk,
+                esk,
                 rcv_pk,
+                auth_pk,
                 auth_vp_vk: *COMPRESSED_TOKEN_AUTH_VK,
+                note_kind: NoteKind::Change.to_base(),
             },
             rcv_sk,
         )
     };
     let public_inputs = circuit.get_public_inputs(&mut rng);
+    assert_eq!(
+        circuit.dev_prove_and_check_with_public_inputs(&public_inputs),
+        Ok(())
+    );
 
-    let prover = MockProver::<pallas::Base>::run(
-        VP_CIRCUIT_PARAMS_SIZE,
-        &circuit,
-        vec![public_inputs.to_vec()],
-    )
-    .unwrap();
-    assert_eq!(prover.verify(), Ok(()));
+    // The ciphertext and epk in the native public inputs match the circuit for other esks, i.e.
+    // the in-circuit symmetric key is the native one
+    for _ in 0..2 {
+        let other_circuit = ReceiverValidityPredicateCircuit {
+            esk: pallas::Base::random(&mut rng),
+            ..circuit.clone()
+        };
+        let other_public_inputs = other_circuit.get_public_inputs(&mut rng);
+        assert_eq!(
+            other_circuit.dev_prove_and_check_with_public_inputs(&other_public_inputs),
+            Ok(())
+        );
+        assert_eq!(
+            other_public_inputs.decrypt(rcv_sk).unwrap(),
+            public_inputs.decrypt(rcv_sk).unwrap()
+        );
+    }
+
+    // The note kind must be one of NoteKind
+    {
+        let bad_circuit = ReceiverValidityPredicateCircuit {
+            note_kind: pallas::Base::from(3),
+            ..circuit.clone()
+        };
+        let bad_public_inputs = bad_circuit.get_public_inputs(&mut rng);
+        assert!(bad_circuit
+            .dev_prove_and_check_with_public_inputs(&bad_public_inputs)
+            .is_err());
+    }
+
+    // The auth pk must be the one in the app_data_dynamic
+    {
+        let bad_circuit = ReceiverValidityPredicateCircuit {
+            auth_pk: pallas::Point::random(&mut rng),
+            ..circuit.clone()
+        };
+        let bad_public_inputs = bad_circuit.get_public_inputs(&mut rng);
+        assert!(bad_circuit
+            .dev_prove_and_check_with_public_inputs(&bad_public_inputs)
+            .is_err());
+    }
+
+    // Encrypting to the identity fails
+    {
+        use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
+        use halo2_proofs::dev::MockProver;
+
+        let bad_circuit = ReceiverValidityPredicateCircuit {
+            rcv_pk: pallas::Point::identity(),
+            ..circuit.clone()
+        };
+        let prover = MockProver::<pallas::Base>::run(
+            VP_CIRCUIT_PARAMS_SIZE,
+            &bad_circuit,
+            vec![public_inputs.to_instance()],
+        );
+        // The identity can't be witnessed, or the proof doesn't verify
+        assert!(prover.map_or(true, |prover| prover.verify().is_err()));
+    }
 
     let de_cipher = public_inputs.decrypt(rcv_sk).unwrap();
     assert_eq!(de_cipher[0], circuit.output_notes[0].get_app_vk());
@@ -349,4 +503,44 @@ fn test_halo2_receiver_vp_circuit() {
     assert_eq!(de_cipher[5], circuit.output_notes[0].get_nk_commitment());
     assert_eq!(de_cipher[6], circuit.output_notes[0].get_psi());
     assert_eq!(de_cipher[7], circuit.output_notes[0].get_rcm());
+    assert_eq!(NoteKind::try_from(de_cipher[8]), Ok(NoteKind::Change));
+}
+
+#[test]
+fn test_output_note_kind() {
+    use crate::note::tests::{random_input_note, random_output_note};
+    use ff::Field;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+    let mut output_notes = input_notes.map(|input| random_output_note(&mut rng, input.rho));
+    for output in output_notes.iter_mut() {
+        output.note_type.app_vk = input_notes[0].note_type.app_vk;
+    }
+    assert_eq!(
+        output_note_kind(&output_notes[0], &input_notes, &output_notes),
+        NoteKind::External
+    );
+
+    // The first output goes back to the spender of the first input
+    output_notes[0].app_data_dynamic = input_notes[0].app_data_dynamic;
+    assert_eq!(
+        output_note_kind(&output_notes[0], &input_notes, &output_notes),
+        NoteKind::Change
+    );
+
+    // Both outputs go back to the spender
+    output_notes[1].app_data_dynamic = input_notes[0].app_data_dynamic;
+    assert_eq!(
+        output_note_kind(&output_notes[1], &input_notes, &output_notes),
+        NoteKind::SelfTransfer
+    );
+
+    // The notes of other applications are external
+    output_notes[1].note_type.app_vk = pallas::Base::random(&mut rng);
+    assert_eq!(
+        output_note_kind(&output_notes[1], &input_notes, &output_notes),
+        NoteKind::External
+    );
 }
@@ -2,52 +2,83 @@ use crate::{
     circuit::{
         blake2s::publicize_default_dynamic_vp_commitments,
         gadgets::{
-            assign_free_advice, poseidon_hash::poseidon_hash_gadget,
-            target_note_variable::get_owned_note_variable,
+            assign_free_advice, assign_free_constant, poseidon_hash::poseidon_hash_gadget,
+            target_note_variable::get_owned_note_variables,
         },
         vp_circuit::{
             BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
             ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
         },
     },
-    constant::{TaigaFixedBasesFull, NUM_NOTE, SETUP_PARAMS_MAP},
+    constant::{
+        TaigaFixedBases, TaigaFixedBasesFull, APP_DATA_DYNAMIC_DOMAIN, NUM_NOTE,
+        SCHNORR_SIGHASH_DOMAIN, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX,
+    },
+    keys::SigningKey,
+    merkle_tree::Anchor,
     note::{Note, RandomSeed},
     proof::Proof,
-    utils::{mod_r_p, poseidon_hash_n},
+    utils::{
+        mod_r_p, non_identity_point_from_bytes, point_to_bytes, poseidon_hash_slice, zeroize_field,
+    },
     vp_commitment::ValidityPredicateCommitment,
     vp_vk::ValidityPredicateVerifyingKey,
 };
 use halo2_gadgets::ecc::{chip::EccChip, FixedPoint, NonIdentityPoint, ScalarFixed, ScalarVar};
 use halo2_proofs::{
     arithmetic::Field,
-    circuit::{floor_planner, Layouter, Value},
+    circuit::{floor_planner, AssignedCell, Layouter, Value},
     plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
 };
 use lazy_static::lazy_static;
 use pasta_curves::{
     arithmetic::CurveAffine,
-    group::{Curve, Group},
+    group::{ff::PrimeField, Curve, Group},
     pallas,
 };
 use rand::rngs::OsRng;
 use rand::RngCore;
+use std::sync::Arc;
 
-// The message contains the input note nullifiers and output note commitments
-const MESSAGE_LEN: usize = NUM_NOTE * 2;
-const POSEIDON_HASH_LEN: usize = MESSAGE_LEN + 4;
 lazy_static! {
     pub static ref TOKEN_AUTH_VK: ValidityPredicateVerifyingKey =
         SignatureVerificationValidityPredicateCircuit::default().get_vp_vk();
     pub static ref COMPRESSED_TOKEN_AUTH_VK: pallas::Base = TOKEN_AUTH_VK.get_compressed();
+    pub static ref LEGACY_TOKEN_AUTH_VK: ValidityPredicateVerifyingKey =
+        LegacySignatureVerificationValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_LEGACY_TOKEN_AUTH_VK: pallas::Base =
+        LEGACY_TOKEN_AUTH_VK.get_compressed();
 }
 
+/// Where the vps signing with signing_message_gadget publicize the signed anchors, they declare
+/// it with ValidityPredicateCircuit::get_signed_anchors_public_input_idx.
+pub const SIGNED_ANCHORS_PUBLIC_INPUT_IDX: usize = VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX;
+
+/// The version of the message signed by the schnorr signatures.
+/// v1: SCHNORR_SIGHASH_DOMAIN || anchors || owned_note_pub_id || (nf, cm) pairs
+pub const SCHNORR_SIGHASH_VERSION: u8 = 1;
+/// v0: (nf, cm) pairs. It has no domain separation and can be replayed wherever the nfs and cms
+/// coincide, it's only verified by `LegacySignatureVerificationValidityPredicateCircuit` to
+/// accept the old signatures during the migration.
+pub const LEGACY_SCHNORR_SIGHASH_VERSION: u8 = 0;
+
+// The length of the signed message
+const SIGHASH_MESSAGE_LEN: usize = 2 + 3 * NUM_NOTE;
+const LEGACY_SIGHASH_MESSAGE_LEN: usize = 2 * NUM_NOTE;
+// The signed message followed by the issuance fields, see
+// `TokenIssuanceValidityPredicateCircuit::signing_message`
+pub(crate) const ISSUANCE_SIGHASH_MESSAGE_LEN: usize = SIGHASH_MESSAGE_LEN + 4;
+
+/// The size of the encoded signature: pk(32) || r(32) || s(32)
+pub const SCHNORR_SIGNATURE_SIZE: usize = 96;
+
 #[derive(Clone, Debug)]
 pub struct SchnorrSignature {
     // public key
-    pk: pallas::Point,
+    pub(crate) pk: pallas::Point,
     // signature (r,s)
-    r: pallas::Point,
-    s: pallas::Scalar,
+    pub(crate) r: pallas::Point,
+    pub(crate) s: pallas::Scalar,
 }
 
 impl Default for SchnorrSignature {
@@ -61,105 +92,313 @@ impl Default for SchnorrSignature {
 }
 
 impl SchnorrSignature {
-    pub fn sign<R: RngCore>(mut rng: R, sk: pallas::Scalar, message: Vec<pallas::Base>) -> Self {
+    pub fn sign<R: RngCore>(mut rng: R, sk: &SigningKey, message: Vec<pallas::Base>) -> Self {
         // TDOD: figure out whether the generator is applicable.
         let generator = pallas::Point::generator();
-        let pk = generator * sk;
-        let pk_coord = pk.to_affine().coordinates().unwrap();
+        let pk = sk.public_key();
         // Generate a random number: z
-        let z = pallas::Scalar::random(&mut rng);
+        let mut z = pallas::Scalar::random(&mut rng);
         // Compute: R = z*G
         let r = generator * z;
-        let r_coord = r.to_affine().coordinates().unwrap();
         // Compute: s = z + Hash(r||P||m)*sk
-        assert_eq!(message.len(), MESSAGE_LEN);
-        let h = mod_r_p(poseidon_hash_n::<POSEIDON_HASH_LEN>([
-            *r_coord.x(),
-            *r_coord.y(),
-            *pk_coord.x(),
-            *pk_coord.y(),
-            message[0],
-            message[1],
-            message[2],
-            message[3],
-        ]));
-        let s = z + h * sk;
+        let h = schnorr_challenge(&r, &pk, &message).unwrap();
+        let mut h_sk = h * sk.inner();
+        let s = z + h_sk;
+        // Wipe the nonce, leaking it reveals the sk
+        zeroize_field(&mut z);
+        zeroize_field(&mut h_sk);
         Self { pk, r, s }
     }
+
+    pub fn pk(&self) -> pallas::Point {
+        self.pk
+    }
+
+    pub fn r(&self) -> pallas::Point {
+        self.r
+    }
+
+    pub fn s(&self) -> pallas::Scalar {
+        self.s
+    }
+
+    pub fn to_bytes(&self) -> [u8; SCHNORR_SIGNATURE_SIZE] {
+        let mut bytes = [0u8; SCHNORR_SIGNATURE_SIZE];
+        bytes[..32].copy_from_slice(&point_to_bytes(&self.pk));
+        bytes[32..64].copy_from_slice(&point_to_bytes(&self.r));
+        bytes[64..].copy_from_slice(&self.s.to_repr());
+        bytes
+    }
+
+    /// Parses the canonical encoding. None if the points are not valid or are the identity, or
+    /// s is not in the field.
+    pub fn from_bytes(bytes: &[u8; SCHNORR_SIGNATURE_SIZE]) -> Option<Self> {
+        let point = |bytes: &[u8]| non_identity_point_from_bytes(bytes.try_into().unwrap()).ok();
+        let pk = point(&bytes[..32])?;
+        let r = point(&bytes[32..64])?;
+        let s = Option::from(pallas::Scalar::from_repr(bytes[64..].try_into().unwrap()))?;
+        Some(Self { pk, r, s })
+    }
+
+    /// Checks s*G == R + Hash(r||P||m)*P natively, the same check as the verifying circuit.
+    pub fn verify(&self, message: &[pallas::Base]) -> bool {
+        // The circuits only hash messages of the sighash lengths
+        if message.len() != SIGHASH_MESSAGE_LEN && message.len() != LEGACY_SIGHASH_MESSAGE_LEN {
+            return false;
+        }
+        match schnorr_challenge(&self.r, &self.pk, message) {
+            Some(h) => pallas::Point::generator() * self.s == self.r + self.pk * h,
+            None => false,
+        }
+    }
+}
+
+// Hash(r||P||m), the verifying circuit hashes the same r||P||m with the poseidon gadget.
+// None if r or P is the identity, they have no affine coordinates.
+fn schnorr_challenge(
+    r: &pallas::Point,
+    pk: &pallas::Point,
+    message: &[pallas::Base],
+) -> Option<pallas::Scalar> {
+    let r_coord = r.to_affine().coordinates();
+    let pk_coord = pk.to_affine().coordinates();
+    if bool::from(r_coord.is_none() | pk_coord.is_none()) {
+        return None;
+    }
+    let (r_coord, pk_coord) = (r_coord.unwrap(), pk_coord.unwrap());
+    Some(mod_r_p(poseidon_hash_slice(
+        &[
+            &[*r_coord.x(), *r_coord.y(), *pk_coord.x(), *pk_coord.y()],
+            message,
+        ]
+        .concat(),
+    )))
+}
+
+// The message signed: the sighash domain, the anchors of the input notes, the owned note and
+// the input note nullifiers and output note commitments. See SCHNORR_SIGHASH_VERSION.
+pub fn signing_message(
+    anchors: &[Anchor; NUM_NOTE],
+    owned_note_pub_id: pallas::Base,
+    input_notes: &[Note; NUM_NOTE],
+    output_notes: &[Note; NUM_NOTE],
+) -> Vec<pallas::Base> {
+    let mut message = vec![SCHNORR_SIGHASH_DOMAIN];
+    message.extend(anchors.iter().map(|anchor| anchor.inner()));
+    message.push(owned_note_pub_id);
+    message.extend(legacy_signing_message(input_notes, output_notes));
+    assert_eq!(message.len(), SIGHASH_MESSAGE_LEN);
+    message
+}
+
+// The v0 message: the input note nullifiers and output note commitments. See
+// LEGACY_SCHNORR_SIGHASH_VERSION.
+pub fn legacy_signing_message(
+    input_notes: &[Note; NUM_NOTE],
+    output_notes: &[Note; NUM_NOTE],
+) -> Vec<pallas::Base> {
+    input_notes
+        .iter()
+        .zip(output_notes.iter())
+        .flat_map(|(input_note, output_note)| {
+            [
+                input_note.get_nf().unwrap().inner(),
+                output_note.commitment().inner(),
+            ]
+        })
+        .collect()
+}
+
+// A message signed outside of the transactions, e.g. an intent advert. It has the sighash
+// length so the native verify accepts it, and the domain in place of SCHNORR_SIGHASH_DOMAIN keeps
+// it from being replayed as a transaction signature.
+pub(crate) fn off_chain_signing_message(
+    domain: pallas::Base,
+    payload: pallas::Base,
+) -> Vec<pallas::Base> {
+    let mut message = vec![pallas::Base::zero(); SIGHASH_MESSAGE_LEN];
+    message[0] = domain;
+    message[1] = payload;
+    message
+}
+
+// The in-circuit `signing_message`. The anchors are witnessed and publicized at the signed
+// anchors index the vp declares, the ptx checks them against the action anchors. It fails if the
+// vp declares no index, the anchors it signs would be unchecked.
+pub(crate) fn signing_message_gadget(
+    config: &ValidityPredicateConfig,
+    mut layouter: impl Layouter<pallas::Base>,
+    basic_variables: &BasicValidityPredicateVariables,
+    anchors: &[Anchor; NUM_NOTE],
+) -> Result<Vec<AssignedCell<pallas::Base, pallas::Base>>, Error> {
+    let signed_anchors_idx = basic_variables
+        .signed_anchors_public_input_idx
+        .ok_or(Error::Synthesis)?;
+    let mut anchor_cells = vec![];
+    for (i, anchor) in anchors.iter().enumerate() {
+        let anchor = assign_free_advice(
+            layouter.namespace(|| "witness anchor"),
+            config.advices[0],
+            Value::known(anchor.inner()),
+        )?;
+        layouter.constrain_instance(anchor.cell(), config.instances, signed_anchors_idx + i)?;
+        anchor_cells.push(anchor);
+    }
+
+    let mut message = vec![assign_free_constant(
+        layouter.namespace(|| "sighash domain"),
+        config.advices[0],
+        SCHNORR_SIGHASH_DOMAIN,
+    )?];
+    message.extend(anchor_cells);
+    message.push(basic_variables.owned_note_pub_id.clone());
+    message.extend(legacy_signing_message_gadget(basic_variables));
+    Ok(message)
+}
+
+// The in-circuit `legacy_signing_message`
+pub(crate) fn legacy_signing_message_gadget(
+    basic_variables: &BasicValidityPredicateVariables,
+) -> Vec<AssignedCell<pallas::Base, pallas::Base>> {
+    let nfs = basic_variables.get_input_note_nfs();
+    let cms = basic_variables.get_output_note_cms();
+    nfs.into_iter()
+        .zip(cms)
+        .flat_map(|(nf, cm)| [nf, cm])
+        .collect()
+}
+
+// Hash(r||P||m) as the scalar to multiply P
+pub(crate) fn schnorr_challenge_gadget(
+    config: &ValidityPredicateConfig,
+    mut layouter: impl Layouter<pallas::Base>,
+    r: &NonIdentityPoint<pallas::Affine, EccChip<TaigaFixedBases>>,
+    pk: &NonIdentityPoint<pallas::Affine, EccChip<TaigaFixedBases>>,
+    message: &[AssignedCell<pallas::Base, pallas::Base>],
+) -> Result<ScalarVar<pallas::Affine, EccChip<TaigaFixedBases>>, Error> {
+    let input = [
+        &[r.inner().x(), r.inner().y(), pk.inner().x(), pk.inner().y()],
+        message,
+    ]
+    .concat();
+    // The poseidon gadget hashes inputs of a constant length, one per sighash version
+    let h = match message.len() {
+        SIGHASH_MESSAGE_LEN => poseidon_hash_gadget::<{ 4 + SIGHASH_MESSAGE_LEN }>(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "Poseidon_hash(r, P, m)"),
+            input.try_into().unwrap(),
+        ),
+        LEGACY_SIGHASH_MESSAGE_LEN => poseidon_hash_gadget::<{ 4 + LEGACY_SIGHASH_MESSAGE_LEN }>(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "Poseidon_hash(r, P, m)"),
+            input.try_into().unwrap(),
+        ),
+        ISSUANCE_SIGHASH_MESSAGE_LEN => {
+            poseidon_hash_gadget::<{ 4 + ISSUANCE_SIGHASH_MESSAGE_LEN }>(
+                config.poseidon_config.clone(),
+                layouter.namespace(|| "Poseidon_hash(r, P, m)"),
+                input.try_into().unwrap(),
+            )
+        }
+        _ => Err(Error::Synthesis),
+    }?;
+    let ecc_chip = EccChip::construct(config.ecc_config.clone());
+    ScalarVar::from_base(ecc_chip, layouter.namespace(|| "ScalarVar from_base"), &h)
 }
 
 // SignatureVerificationValidityPredicateCircuit uses the schnorr signature.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct SignatureVerificationValidityPredicateCircuit {
     pub owned_note_pub_id: pallas::Base,
-    pub input_notes: [Note; NUM_NOTE],
-    pub output_notes: [Note; NUM_NOTE],
+    // The anchors of the input notes, they're signed and publicized as the custom public inputs
+    pub anchors: [Anchor; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
     pub vp_vk: pallas::Base,
     pub signature: SchnorrSignature,
     pub receiver_vp_vk: pallas::Base,
+    // The rcv pk of the note, it's only bound to the app_data_dynamic
+    pub rcv_pk: pallas::Point,
+}
+
+impl Default for SignatureVerificationValidityPredicateCircuit {
+    fn default() -> Self {
+        Self {
+            owned_note_pub_id: pallas::Base::zero(),
+            anchors: [Anchor::default(); NUM_NOTE],
+            input_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            output_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            vp_vk: pallas::Base::zero(),
+            signature: SchnorrSignature::default(),
+            receiver_vp_vk: pallas::Base::zero(),
+            rcv_pk: pallas::Point::generator(),
+        }
+    }
 }
 
 impl SignatureVerificationValidityPredicateCircuit {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         owned_note_pub_id: pallas::Base,
+        anchors: [Anchor; NUM_NOTE],
         input_notes: [Note; NUM_NOTE],
         output_notes: [Note; NUM_NOTE],
         vp_vk: pallas::Base,
         signature: SchnorrSignature,
         receiver_vp_vk: pallas::Base,
+        rcv_pk: pallas::Point,
     ) -> Self {
         Self {
             owned_note_pub_id,
-            input_notes,
-            output_notes,
+            anchors,
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
             vp_vk,
             signature,
             receiver_vp_vk,
+            rcv_pk,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn from_sk_and_sign<R: RngCore>(
         mut rng: R,
         owned_note_pub_id: pallas::Base,
-        input_notes: [Note; NUM_NOTE],
-        output_notes: [Note; NUM_NOTE],
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        output_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
         vp_vk: pallas::Base,
-        sk: pallas::Scalar,
+        sk: &SigningKey,
         receiver_vp_vk: pallas::Base,
+        rcv_pk: pallas::Point,
     ) -> Self {
-        assert_eq!(NUM_NOTE, 2);
-        let mut message = vec![];
-        input_notes
-            .iter()
-            .zip(output_notes.iter())
-            .for_each(|(input_note, output_note)| {
-                let nf = input_note.get_nf().unwrap().inner();
-                message.push(nf);
-                let cm = output_note.commitment();
-                message.push(cm.inner());
-            });
+        let input_notes: Arc<[Note; NUM_NOTE]> = input_notes.into();
+        let output_notes: Arc<[Note; NUM_NOTE]> = output_notes.into();
+        let message = signing_message(&anchors, owned_note_pub_id, &input_notes, &output_notes);
         let signature = SchnorrSignature::sign(&mut rng, sk, message);
         Self {
             owned_note_pub_id,
+            anchors,
             input_notes,
             output_notes,
             vp_vk,
             signature,
             receiver_vp_vk,
+            rcv_pk,
         }
     }
-}
 
-impl ValidityPredicateCircuit for SignatureVerificationValidityPredicateCircuit {
-    // Add custom constraints
-    fn custom_constraints(
+    // The custom constraints of the token auth vps, they verify the signature of the message of
+    // the sighash version
+    fn signature_constraints(
         &self,
-        config: Self::Config,
+        config: ValidityPredicateConfig,
         mut layouter: impl Layouter<pallas::Base>,
         basic_variables: BasicValidityPredicateVariables,
+        sighash_version: u8,
     ) -> Result<(), Error> {
         // Construct an ECC chip
-        let ecc_chip = EccChip::construct(config.ecc_config);
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
 
         let pk = NonIdentityPoint::new(
             ecc_chip.clone(),
@@ -167,14 +406,19 @@ impl ValidityPredicateCircuit for SignatureVerificationValidityPredicateCircuit
             Value::known(self.signature.pk.to_affine()),
         )?;
 
+        let rcv_pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness rcv_pk"),
+            Value::known(self.rcv_pk.to_affine()),
+        )?;
+
         // search target note and get the app_data_dynamic
-        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
-        let app_data_dynamic = get_owned_note_variable(
-            config.get_owned_note_variable_config,
-            layouter.namespace(|| "get owned note app_data_dynamic"),
-            &owned_note_pub_id,
-            &basic_variables.get_app_data_dynamic_searchable_pairs(),
+        let owned_note = get_owned_note_variables(
+            &config,
+            layouter.namespace(|| "get owned note variables"),
+            &basic_variables,
         )?;
+        let app_data_dynamic = owned_note.note_variables.app_data_dynamic;
 
         let auth_vp_vk = assign_free_advice(
             layouter.namespace(|| "witness auth vp vk"),
@@ -187,11 +431,25 @@ impl ValidityPredicateCircuit for SignatureVerificationValidityPredicateCircuit
             Value::known(self.receiver_vp_vk),
         )?;
 
+        let app_data_dynamic_domain = assign_free_constant(
+            layouter.namespace(|| "app_data_dynamic domain"),
+            config.advices[0],
+            APP_DATA_DYNAMIC_DOMAIN,
+        )?;
+
         // Decode the app_data_dynamic, and check the app_data_dynamic encoding
         let encoded_app_data_dynamic = poseidon_hash_gadget(
             config.poseidon_config.clone(),
             layouter.namespace(|| "app_data_dynamic encoding"),
-            [pk.inner().x(), pk.inner().y(), auth_vp_vk, receiver_vp_vk],
+            [
+                app_data_dynamic_domain,
+                pk.inner().x(),
+                pk.inner().y(),
+                auth_vp_vk,
+                receiver_vp_vk,
+                rcv_pk.inner().x(),
+                rcv_pk.inner().y(),
+            ],
         )?;
 
         layouter.assign_region(
@@ -219,27 +477,23 @@ impl ValidityPredicateCircuit for SignatureVerificationValidityPredicateCircuit
         let (s_g, _) = generator.mul(layouter.namespace(|| "s_scalar * generator"), &s_scalar)?;
 
         // Hash(r||P||m)
-        let h_scalar = {
-            let nfs = basic_variables.get_input_note_nfs();
-            let cms = basic_variables.get_output_note_cms();
-            assert_eq!(NUM_NOTE, 2);
-            let h = poseidon_hash_gadget(
-                config.poseidon_config,
-                layouter.namespace(|| "Poseidon_hash(r, P, m)"),
-                [
-                    r.inner().x(),
-                    r.inner().y(),
-                    pk.inner().x(),
-                    pk.inner().y(),
-                    nfs[0].clone(),
-                    cms[0].clone(),
-                    nfs[1].clone(),
-                    cms[1].clone(),
-                ],
-            )?;
-
-            ScalarVar::from_base(ecc_chip, layouter.namespace(|| "ScalarVar from_base"), &h)?
+        let message = if sighash_version == LEGACY_SCHNORR_SIGHASH_VERSION {
+            legacy_signing_message_gadget(&basic_variables)
+        } else {
+            signing_message_gadget(
+                &config,
+                layouter.namespace(|| "signing message"),
+                &basic_variables,
+                &self.anchors,
+            )?
         };
+        let h_scalar = schnorr_challenge_gadget(
+            &config,
+            layouter.namespace(|| "schnorr challenge"),
+            &r,
+            &pk,
+            &message,
+        )?;
 
         // Hash(r||P||m)*P
         let (h_p, _) = pk.mul(layouter.namespace(|| "hP"), h_scalar)?;
@@ -258,6 +512,18 @@ impl ValidityPredicateCircuit for SignatureVerificationValidityPredicateCircuit
 
         Ok(())
     }
+}
+
+impl ValidityPredicateCircuit for SignatureVerificationValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        self.signature_constraints(config, layouter, basic_variables, SCHNORR_SIGHASH_VERSION)
+    }
 
     fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
         &self.input_notes
@@ -273,6 +539,7 @@ impl ValidityPredicateCircuit for SignatureVerificationValidityPredicateCircuit
             ValidityPredicateCommitment::default().to_public_inputs();
         public_inputs.extend(default_vp_cm);
         public_inputs.extend(default_vp_cm);
+        public_inputs.extend(self.anchors.map(|anchor| anchor.inner()));
         let padding = ValidityPredicatePublicInputs::get_public_input_padding(
             public_inputs.len(),
             &RandomSeed::random(&mut rng),
@@ -284,19 +551,250 @@ impl ValidityPredicateCircuit for SignatureVerificationValidityPredicateCircuit
     fn get_owned_note_pub_id(&self) -> pallas::Base {
         self.owned_note_pub_id
     }
+
+    fn get_signed_anchors_public_input_idx(&self) -> Option<usize> {
+        Some(SIGNED_ANCHORS_PUBLIC_INPUT_IDX)
+    }
 }
 
 vp_circuit_impl!(SignatureVerificationValidityPredicateCircuit);
 vp_verifying_info_impl!(SignatureVerificationValidityPredicateCircuit);
 
+// The token auth vp of the v0 signatures, see LEGACY_SCHNORR_SIGHASH_VERSION. The notes select
+// it with COMPRESSED_LEGACY_TOKEN_AUTH_VK as the auth vp vk of their app_data_dynamic. The v0
+// message has no anchors, so the vp signs none and ignores the anchors of the inner circuit.
+#[derive(Clone, Debug, Default)]
+pub struct LegacySignatureVerificationValidityPredicateCircuit(
+    pub SignatureVerificationValidityPredicateCircuit,
+);
+
+impl LegacySignatureVerificationValidityPredicateCircuit {
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_sk_and_sign<R: RngCore>(
+        mut rng: R,
+        owned_note_pub_id: pallas::Base,
+        input_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        output_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        vp_vk: pallas::Base,
+        sk: &SigningKey,
+        receiver_vp_vk: pallas::Base,
+        rcv_pk: pallas::Point,
+    ) -> Self {
+        let input_notes: Arc<[Note; NUM_NOTE]> = input_notes.into();
+        let output_notes: Arc<[Note; NUM_NOTE]> = output_notes.into();
+        let message = legacy_signing_message(&input_notes, &output_notes);
+        let signature = SchnorrSignature::sign(&mut rng, sk, message);
+        Self(SignatureVerificationValidityPredicateCircuit {
+            owned_note_pub_id,
+            anchors: [Anchor::default(); NUM_NOTE],
+            input_notes,
+            output_notes,
+            vp_vk,
+            signature,
+            receiver_vp_vk,
+            rcv_pk,
+        })
+    }
+}
+
+impl ValidityPredicateCircuit for LegacySignatureVerificationValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        self.0.signature_constraints(
+            config,
+            layouter,
+            basic_variables,
+            LEGACY_SCHNORR_SIGHASH_VERSION,
+        )
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.0.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.0.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.0.owned_note_pub_id
+    }
+}
+
+vp_circuit_impl!(LegacySignatureVerificationValidityPredicateCircuit);
+vp_verifying_info_impl!(LegacySignatureVerificationValidityPredicateCircuit);
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use super::{SchnorrSignature, SIGHASH_MESSAGE_LEN};
+    use crate::{
+        keys::SigningKey,
+        utils::testing::{arb_base, arb_scalar},
+    };
+    use halo2_proofs::arithmetic::Field;
+    use pasta_curves::pallas;
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    pub fn arb_secret_key() -> impl Strategy<Value = SigningKey> {
+        arb_scalar()
+            .prop_filter("the pk is the identity", |sk| !bool::from(sk.is_zero()))
+            .prop_map(SigningKey::new)
+    }
+
+    pub fn arb_message() -> impl Strategy<Value = Vec<pallas::Base>> {
+        prop::collection::vec(arb_base(), SIGHASH_MESSAGE_LEN)
+    }
+
+    prop_compose! {
+        // The signature of an arbitrary message with an arbitrary key
+        pub fn arb_signed_message()(
+            sk in arb_secret_key(),
+            message in arb_message(),
+            seed in any::<[u8; 32]>(),
+        ) -> (Vec<pallas::Base>, SchnorrSignature) {
+            let signature = SchnorrSignature::sign(StdRng::from_seed(seed), &sk, message.clone());
+            (message, signature)
+        }
+    }
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_schnorr_sign_verify(
+        (message, signature) in testing::arb_signed_message(),
+        other_message in testing::arb_message(),
+    ) {
+        proptest::prop_assert!(signature.verify(&message));
+        proptest::prop_assert!(!signature.verify(&message[1..]));
+        if other_message != message {
+            proptest::prop_assert!(!signature.verify(&other_message));
+        }
+        let tampered = SchnorrSignature {
+            s: signature.s + pallas::Scalar::one(),
+            ..signature
+        };
+        proptest::prop_assert!(!tampered.verify(&message));
+    }
+}
+
+#[test]
+fn test_schnorr_signature_encoding() {
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let sk = SigningKey::random(&mut rng);
+    let message = vec![pallas::Base::random(&mut rng); SIGHASH_MESSAGE_LEN];
+    let signature = SchnorrSignature::sign(&mut rng, &sk, message.clone());
+    assert_eq!(signature.pk(), sk.public_key());
+
+    let bytes = signature.to_bytes();
+    let decoded = SchnorrSignature::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.pk(), signature.pk());
+    assert_eq!(decoded.r(), signature.r());
+    assert_eq!(decoded.s(), signature.s());
+    assert!(decoded.verify(&message));
+
+    // The identity pk
+    let mut identity_pk = bytes;
+    identity_pk[..32].copy_from_slice(&point_to_bytes(&pallas::Point::identity()));
+    assert!(SchnorrSignature::from_bytes(&identity_pk).is_none());
+    // An invalid r encoding
+    let mut invalid_r = bytes;
+    invalid_r[32..64].copy_from_slice(&[0xff; 32]);
+    assert!(SchnorrSignature::from_bytes(&invalid_r).is_none());
+    // s is not canonical
+    let mut invalid_s = bytes;
+    invalid_s[64..].copy_from_slice(&[0xff; 32]);
+    assert!(SchnorrSignature::from_bytes(&invalid_s).is_none());
+    // r with the other sign is the negated point, it doesn't verify
+    let mut negated_r = bytes;
+    negated_r[63] ^= 0x80;
+    let negated = SchnorrSignature::from_bytes(&negated_r).unwrap();
+    assert_eq!(negated.r(), -signature.r());
+    assert!(!negated.verify(&message));
+    // The identity r with the sign bit set
+    let mut signed_identity_r = bytes;
+    signed_identity_r[32..64].copy_from_slice(&[0u8; 32]);
+    signed_identity_r[63] = 0x80;
+    assert!(SchnorrSignature::from_bytes(&signed_identity_r).is_none());
+}
+
+// The native verifier and the circuit accept the same signatures
+#[test]
+fn test_schnorr_native_and_circuit_verification() {
+    use crate::circuit::vp_examples::{
+        receiver_vp::COMPRESSED_RECEIVER_VK, token::TokenAuthorization,
+    };
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let mut input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+    let sk = SigningKey::random(&mut rng);
+    let auth_vk = pallas::Base::random(&mut rng);
+    let auth = TokenAuthorization::from_sk_vk(sk.inner(), &auth_vk);
+    input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
+    let output_notes =
+        input_notes.map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+    let owned_note_pub_id = input_notes[0].get_nf().unwrap().inner();
+    let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+    let message = signing_message(&anchors, owned_note_pub_id, &input_notes, &output_notes);
+    let circuit = |signature: SchnorrSignature| {
+        SignatureVerificationValidityPredicateCircuit::new(
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            auth_vk,
+            signature,
+            *COMPRESSED_RECEIVER_VK,
+            auth.rcv_pk,
+        )
+    };
+
+    let signature = SchnorrSignature::sign(&mut rng, &sk, message.clone());
+    assert!(signature.verify(&message));
+    assert_eq!(
+        circuit(signature.clone()).dev_prove_and_check(&mut rng),
+        Ok(())
+    );
+
+    let tampered = SchnorrSignature {
+        s: signature.s + pallas::Scalar::one(),
+        ..signature
+    };
+    assert!(!tampered.verify(&message));
+    assert!(circuit(tampered).dev_prove_and_check(&mut rng).is_err());
+}
+
 #[test]
 fn test_halo2_sig_verification_vp_circuit() {
     use crate::circuit::vp_examples::{
         receiver_vp::COMPRESSED_RECEIVER_VK, token::TokenAuthorization,
     };
-    use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
+    use crate::constant::VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX;
     use crate::note::tests::{random_input_note, random_output_note};
-    use halo2_proofs::dev::MockProver;
     use rand::rngs::OsRng;
 
     let mut rng = OsRng;
@@ -306,28 +804,278 @@ fn test_halo2_sig_verification_vp_circuit() {
             .iter()
             .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()))
             .collect::<Vec<_>>();
-        let sk = pallas::Scalar::random(&mut rng);
+        let sk = SigningKey::random(&mut rng);
         let auth_vk = pallas::Base::random(&mut rng);
-        let auth = TokenAuthorization::from_sk_vk(&sk, &auth_vk);
+        // The notes are encrypted to another key than the signing key
+        let auth = TokenAuthorization::from_sk_vk(sk.inner(), &auth_vk)
+            .with_rcv_pk(pallas::Point::random(&mut rng))
+            .unwrap();
         input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
         let owned_note_pub_id = input_notes[0].get_nf().unwrap().inner();
+        let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
         SignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
             &mut rng,
             owned_note_pub_id,
+            anchors,
             input_notes,
             output_notes.try_into().unwrap(),
             auth_vk,
-            sk,
+            &sk,
+            *COMPRESSED_RECEIVER_VK,
+            auth.rcv_pk,
+        )
+    };
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+    // The vp declares where it publicizes the signed anchors, it can't claim to sign none
+    let mut public_inputs = circuit.get_public_inputs(&mut rng).to_vec();
+    assert_eq!(
+        public_inputs[VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX],
+        pallas::Base::from(SIGNED_ANCHORS_PUBLIC_INPUT_IDX as u64)
+    );
+    public_inputs[VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX] = pallas::Base::zero();
+    assert!(circuit
+        .dev_prove_and_check_with_public_inputs(&ValidityPredicatePublicInputs::from(public_inputs))
+        .is_err());
+}
+
+#[test]
+fn test_sig_verification_vp_public_input_accessors() {
+    use crate::circuit::vp_examples::{
+        receiver_vp::COMPRESSED_RECEIVER_VK, token::TokenAuthorization,
+    };
+    use crate::error::TransactionError;
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let mut input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+    let output_notes =
+        input_notes.map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+    let sk = SigningKey::random(&mut rng);
+    let auth_vk = pallas::Base::random(&mut rng);
+    let auth = TokenAuthorization::from_sk_vk(sk.inner(), &auth_vk);
+    input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
+    let owned_note_pub_id = input_notes[0].get_nf().unwrap().inner();
+    let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+    let circuit = SignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
+        &mut rng,
+        owned_note_pub_id,
+        anchors,
+        input_notes,
+        output_notes,
+        auth_vk,
+        &sk,
+        *COMPRESSED_RECEIVER_VK,
+        auth.rcv_pk,
+    );
+
+    let public_inputs = circuit.get_public_inputs(&mut rng);
+    assert_eq!(
+        public_inputs.input_nullifiers(),
+        input_notes.map(|note| note.get_nf().unwrap().inner())
+    );
+    assert_eq!(
+        public_inputs.output_commitments(),
+        output_notes.map(|note| note.commitment())
+    );
+    assert_eq!(public_inputs.owned_note_pub_id(), owned_note_pub_id);
+    assert_eq!(
+        public_inputs.dynamic_vp_commitments(),
+        [
+            ValidityPredicateCommitment::default(),
+            ValidityPredicateCommitment::default()
+        ]
+    );
+
+    // The vector round trips, a shortened one is rejected
+    let public_input_vec = public_inputs.to_vec();
+    assert_eq!(
+        ValidityPredicatePublicInputs::try_from_vec(public_input_vec.clone())
+            .unwrap()
+            .inner(),
+        public_inputs.inner()
+    );
+    assert!(matches!(
+        ValidityPredicatePublicInputs::try_from_vec(
+            public_input_vec[..public_input_vec.len() - 1].to_vec()
+        ),
+        Err(TransactionError::PublicInputMalformed)
+    ));
+
+    // A non-canonical encoding of the padding is rejected
+    let mut reprs = public_input_vec
+        .iter()
+        .map(|x| x.to_repr())
+        .collect::<Vec<_>>();
+    assert!(ValidityPredicatePublicInputs::try_from_reprs(&reprs).is_ok());
+    *reprs.last_mut().unwrap() = [0xff; 32];
+    assert!(matches!(
+        ValidityPredicatePublicInputs::try_from_reprs(&reprs),
+        Err(TransactionError::PublicInputMalformed)
+    ));
+}
+
+#[test]
+fn test_halo2_sig_verification_vp_circuit_owned_note() {
+    use crate::circuit::vp_examples::{
+        receiver_vp::COMPRESSED_RECEIVER_VK, token::TokenAuthorization,
+    };
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+    let mut output_notes =
+        input_notes.map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+    let sk = SigningKey::random(&mut rng);
+    let auth_vk = pallas::Base::random(&mut rng);
+    let auth = TokenAuthorization::from_sk_vk(sk.inner(), &auth_vk);
+    output_notes[1].app_data_dynamic = auth.to_app_data_dynamic();
+    let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+    let circuit = |owned_note_pub_id: pallas::Base| {
+        SignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
+            OsRng,
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            auth_vk,
+            &sk,
             *COMPRESSED_RECEIVER_VK,
+            auth.rcv_pk,
         )
     };
+
+    // The owned note is an output note
+    let output_circuit = circuit(output_notes[1].commitment().inner());
+    assert_eq!(output_circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+    // The owned note doesn't carry the signer's app_data_dynamic
+    let input_circuit = circuit(input_notes[1].get_nf().unwrap().inner());
+    assert!(input_circuit.dev_prove_and_check(&mut rng).is_err());
+
+    // The owned_note_pub_id matches neither an input nf nor an output cm
+    let unknown_circuit = circuit(pallas::Base::random(&mut rng));
+    assert!(unknown_circuit.dev_prove_and_check(&mut rng).is_err());
+}
+
+#[test]
+fn test_halo2_sig_verification_replay() {
+    use crate::circuit::vp_examples::{
+        receiver_vp::COMPRESSED_RECEIVER_VK, token::TokenAuthorization,
+    };
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let sk = SigningKey::random(&mut rng);
+    let auth_vk = pallas::Base::random(&mut rng);
+    let auth = TokenAuthorization::from_sk_vk(sk.inner(), &auth_vk);
+    // Both input notes are owned by the signer
+    let input_notes = [(); NUM_NOTE].map(|_| {
+        let mut note = random_input_note(&mut rng);
+        note.app_data_dynamic = auth.to_app_data_dynamic();
+        note
+    });
+    let output_notes =
+        input_notes.map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+    let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+    let circuit = SignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
+        &mut rng,
+        input_notes[0].get_nf().unwrap().inner(),
+        anchors,
+        input_notes,
+        output_notes,
+        auth_vk,
+        &sk,
+        *COMPRESSED_RECEIVER_VK,
+        auth.rcv_pk,
+    );
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+    // The message is domain separated
+    let message = signing_message(
+        &anchors,
+        input_notes[0].get_nf().unwrap().inner(),
+        &input_notes,
+        &output_notes,
+    );
+    assert_eq!(message[0], SCHNORR_SIGHASH_DOMAIN);
+    assert_eq!(message.len(), SIGHASH_MESSAGE_LEN);
+
+    // Replay the signature for the other owned note, it was valid in v0
+    let replayed_circuit = SignatureVerificationValidityPredicateCircuit {
+        owned_note_pub_id: input_notes[1].get_nf().unwrap().inner(),
+        ..circuit.clone()
+    };
+    assert!(replayed_circuit.dev_prove_and_check(&mut rng).is_err());
+
+    // Replay the signature with the same nfs and cms under other anchors. It only fails if the
+    // published anchors are the new ones, see test_ptx_signed_anchor_replay for the ptx check.
+    let replayed_circuit = SignatureVerificationValidityPredicateCircuit {
+        anchors: [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng))),
+        ..circuit
+    };
+    assert!(replayed_circuit.dev_prove_and_check(&mut rng).is_err());
+}
+
+#[test]
+fn test_halo2_legacy_sig_verification_vp_circuit() {
+    use crate::circuit::vp_examples::{
+        receiver_vp::COMPRESSED_RECEIVER_VK, token::TokenAuthorization,
+    };
+    use crate::constant::VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX;
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let sk = SigningKey::random(&mut rng);
+    let auth = TokenAuthorization::from_sk_vk(sk.inner(), &COMPRESSED_LEGACY_TOKEN_AUTH_VK);
+    let mut input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+    input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
+    let output_notes =
+        input_notes.map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+    let owned_note_pub_id = input_notes[0].get_nf().unwrap().inner();
+    let circuit = LegacySignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
+        &mut rng,
+        owned_note_pub_id,
+        input_notes,
+        output_notes,
+        *COMPRESSED_LEGACY_TOKEN_AUTH_VK,
+        &sk,
+        *COMPRESSED_RECEIVER_VK,
+        auth.rcv_pk,
+    );
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+    let message = legacy_signing_message(&input_notes, &output_notes);
+    assert_eq!(message.len(), LEGACY_SIGHASH_MESSAGE_LEN);
+    assert!(circuit.0.signature.verify(&message));
+
+    // The legacy vp signs no anchors
     let public_inputs = circuit.get_public_inputs(&mut rng);
+    assert_eq!(
+        public_inputs.inner()[VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX],
+        pallas::Base::zero()
+    );
+
+    // Both versions are verified in the same build, by two vps
+    assert_ne!(*COMPRESSED_LEGACY_TOKEN_AUTH_VK, *COMPRESSED_TOKEN_AUTH_VK);
+    let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+    let circuit = SignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
+        &mut rng,
+        owned_note_pub_id,
+        anchors,
+        input_notes,
+        output_notes,
+        *COMPRESSED_LEGACY_TOKEN_AUTH_VK,
+        &sk,
+        *COMPRESSED_RECEIVER_VK,
+        auth.rcv_pk,
+    );
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
 
-    let prover = MockProver::<pallas::Base>::run(
-        VP_CIRCUIT_PARAMS_SIZE,
-        &circuit,
-        vec![public_inputs.to_vec()],
-    )
-    .unwrap();
-    assert_eq!(prover.verify(), Ok(()));
+    // A v1 signature is not a v0 signature
+    let legacy_circuit = LegacySignatureVerificationValidityPredicateCircuit(circuit);
+    assert!(legacy_circuit.dev_prove_and_check(&mut rng).is_err());
 }
@@ -0,0 +1,815 @@
+/// The spending cap application limits how much of a token can be spent per epoch.
+/// The token notes carry the spending cap VP as the auth dynamic VP, and each spend also evolves a
+/// budget note of the account. A budget note has value 1, the app_data_static encodes the account
+/// (spend pk, master pk, cap) and the app_data_dynamic encodes the budget state
+/// (remaining, epoch). The same circuit is the application VP of the budget notes.
+/// The notes are in fixed positions: the first input is the spent token note, the second input
+/// and output are the budget notes.
+/// - A spend is signed by the spend key, it stays in the epoch of the budget:
+///   remaining' = remaining - spent.
+/// - A refill is signed by the master key, it moves to a later epoch and resets the budget:
+///   remaining' = cap - spent.
+/// The epoch is a custom public input, the validator checks it against the current epoch.
+/// A budget is opened by refilling an ephemeral genesis note.
+///
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            assign_free_advice, assign_free_constant,
+            poseidon_hash::poseidon_hash_gadget,
+            sub::{SubChip, SubInstructions},
+        },
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::{
+            receiver_vp::COMPRESSED_RECEIVER_VK,
+            signature_verification::{
+                schnorr_challenge_gadget, signing_message, signing_message_gadget,
+                SchnorrSignature, SIGNED_ANCHORS_PUBLIC_INPUT_IDX,
+            },
+            token::{TokenAuthorization, TOKEN_AUTHORIZATION_SCHEMA},
+        },
+    },
+    constant::{
+        TaigaFixedBasesFull, NUM_NOTE, SPENDING_CAP_DOMAIN,
+        VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX,
+    },
+    keys::SigningKey,
+    merkle_tree::Anchor,
+    note::{
+        encoding::{StaticDataField, StaticDataSchema, StaticDataValue},
+        Note, RandomSeed,
+    },
+    nullifier::{Nullifier, NullifierKeyContainer},
+    proof::Proof,
+    utils::poseidon_hash_n,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_gadgets::ecc::{chip::EccChip, FixedPoint, NonIdentityPoint, ScalarFixed};
+use halo2_proofs::{
+    arithmetic::Field,
+    circuit::{floor_planner, AssignedCell, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::{
+    group::{Curve, Group},
+    pallas,
+};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+// The remaining budget and the epoch gap are range checked in 7 words of 10 bits, an underflow
+// wraps around the field and fails the check.
+const RANGE_CHECK_WORDS: usize = 7;
+
+/// The epoch follows the anchors in the custom public inputs.
+pub const SPENDING_CAP_EPOCH_PUBLIC_INPUT_IDX: usize =
+    VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX + NUM_NOTE;
+
+lazy_static! {
+    pub static ref SPENDING_CAP_VK: ValidityPredicateVerifyingKey =
+        SpendingCapValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_SPENDING_CAP_VK: pallas::Base = SPENDING_CAP_VK.get_compressed();
+    // The app_data_static of budget notes: (spend pk, master pk, cap)
+    pub static ref SPENDING_CAP_SCHEMA: StaticDataSchema = StaticDataSchema::new(
+        SPENDING_CAP_DOMAIN,
+        vec![
+            StaticDataField::Point,
+            StaticDataField::Point,
+            StaticDataField::U64,
+        ]
+    );
+}
+
+// The account of a spending cap, encoded in the app_data_static of the budget notes
+#[derive(Clone, Copy, Debug)]
+pub struct SpendingCap {
+    // Signs the spends within the budget
+    pub spend_pk: pallas::Point,
+    // Signs the refills, the token notes are also encrypted to it
+    pub master_pk: pallas::Point,
+    // The budget per epoch
+    pub cap: u64,
+}
+
+impl Default for SpendingCap {
+    fn default() -> Self {
+        Self {
+            spend_pk: pallas::Point::generator(),
+            master_pk: pallas::Point::generator(),
+            cap: 0,
+        }
+    }
+}
+
+impl SpendingCap {
+    pub fn new(spend_sk: &SigningKey, master_sk: &SigningKey, cap: u64) -> Self {
+        Self {
+            spend_pk: spend_sk.public_key(),
+            master_pk: master_sk.public_key(),
+            cap,
+        }
+    }
+
+    pub fn to_static_data_values(&self) -> Vec<StaticDataValue> {
+        vec![
+            StaticDataValue::Point(self.spend_pk),
+            StaticDataValue::Point(self.master_pk),
+            StaticDataValue::U64(self.cap),
+        ]
+    }
+
+    pub fn encode_app_data_static(&self) -> pallas::Base {
+        SPENDING_CAP_SCHEMA
+            .encode(&self.to_static_data_values())
+            .unwrap()
+    }
+
+    // The authorization of the token notes under the cap
+    pub fn token_authorization(&self) -> TokenAuthorization {
+        TokenAuthorization {
+            pk: self.master_pk,
+            vk: *COMPRESSED_SPENDING_CAP_VK,
+            rcv_pk: self.master_pk,
+        }
+    }
+
+    // Create the ephemeral genesis budget note, it must be refilled before the first spend
+    pub fn create_genesis_budget_note<R: RngCore>(
+        &self,
+        mut rng: R,
+        rho: Nullifier,
+        nk: NullifierKeyContainer,
+    ) -> Note {
+        let rseed = RandomSeed::random(&mut rng);
+        Note::new(
+            *COMPRESSED_SPENDING_CAP_VK,
+            self.encode_app_data_static(),
+            BudgetState::default().encode(),
+            1u64,
+            nk,
+            rho,
+            false,
+            rseed,
+        )
+    }
+
+    pub fn create_budget_note<R: RngCore>(
+        &self,
+        mut rng: R,
+        state: BudgetState,
+        rho: Nullifier,
+        nk: NullifierKeyContainer,
+    ) -> Note {
+        let rseed = RandomSeed::random(&mut rng);
+        Note::new(
+            *COMPRESSED_SPENDING_CAP_VK,
+            self.encode_app_data_static(),
+            state.encode(),
+            1u64,
+            nk,
+            rho,
+            true,
+            rseed,
+        )
+    }
+}
+
+// The state encoded in the app_data_dynamic of budget notes. The genesis state is (0, 0), so the
+// epochs start from 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BudgetState {
+    pub remaining: u64,
+    pub epoch: u64,
+}
+
+impl BudgetState {
+    pub fn new(remaining: u64, epoch: u64) -> Self {
+        Self { remaining, epoch }
+    }
+
+    // The state after spending in the epoch, None if it exceeds the budget or the epoch is past
+    pub fn next(&self, cap: u64, epoch: u64, spent: u64) -> Option<Self> {
+        let budget = if epoch == self.epoch {
+            self.remaining
+        } else if epoch > self.epoch {
+            cap
+        } else {
+            return None;
+        };
+        Some(Self::new(budget.checked_sub(spent)?, epoch))
+    }
+
+    // app_data_dynamic = poseidon_hash(remaining || epoch)
+    pub fn encode(&self) -> pallas::Base {
+        poseidon_hash_n([
+            pallas::Base::from(self.remaining),
+            pallas::Base::from(self.epoch),
+        ])
+    }
+}
+
+// SpendingCapValidityPredicateCircuit
+#[derive(Clone, Debug, Default)]
+pub struct SpendingCapValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    // The anchors of the input notes, they're signed and publicized as the custom public inputs
+    pub anchors: [Anchor; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    pub account: SpendingCap,
+    // The state of the budget note in the second input
+    pub input_state: BudgetState,
+    // The current epoch
+    pub epoch: u64,
+    // Signed by the spend key, or by the master key for a refill
+    pub signature: SchnorrSignature,
+}
+
+impl SpendingCapValidityPredicateCircuit {
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_sk_and_sign<R: RngCore>(
+        mut rng: R,
+        owned_note_pub_id: pallas::Base,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        output_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        account: SpendingCap,
+        input_state: BudgetState,
+        epoch: u64,
+        sk: &SigningKey,
+    ) -> Self {
+        let input_notes: Arc<[Note; NUM_NOTE]> = input_notes.into();
+        let output_notes: Arc<[Note; NUM_NOTE]> = output_notes.into();
+        let message = signing_message(&anchors, owned_note_pub_id, &input_notes, &output_notes);
+        let signature = SchnorrSignature::sign(&mut rng, sk, message);
+        Self {
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            account,
+            input_state,
+            epoch,
+            signature,
+        }
+    }
+
+    pub fn is_refill(&self) -> bool {
+        self.epoch != self.input_state.epoch
+    }
+
+    fn range_check(
+        config: &ValidityPredicateConfig,
+        layouter: impl Layouter<pallas::Base>,
+        element: &AssignedCell<pallas::Base, pallas::Base>,
+    ) -> Result<(), Error> {
+        config
+            .range_check
+            .copy_check(layouter, element.clone(), RANGE_CHECK_WORDS, true)?;
+        Ok(())
+    }
+}
+
+impl ValidityPredicateCircuit for SpendingCapValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
+
+        let token_note = &basic_variables.input_note_variables[0].note_variables;
+        let input_budget = &basic_variables.input_note_variables[1].note_variables;
+        let output_budget = &basic_variables.output_note_variables[1].note_variables;
+
+        let constant_zero = assign_free_constant(
+            layouter.namespace(|| "zero"),
+            config.advices[0],
+            pallas::Base::zero(),
+        )?;
+        let constant_one = assign_free_constant(
+            layouter.namespace(|| "one"),
+            config.advices[0],
+            pallas::Base::one(),
+        )?;
+
+        // The budget notes are of the same account
+        layouter.assign_region(
+            || "check the budget notes",
+            |mut region| {
+                region.constrain_equal(input_budget.app_vk.cell(), output_budget.app_vk.cell())?;
+                region.constrain_equal(
+                    input_budget.app_data_static.cell(),
+                    output_budget.app_data_static.cell(),
+                )?;
+                region.constrain_equal(input_budget.value.cell(), constant_one.cell())?;
+                region.constrain_equal(output_budget.value.cell(), constant_one.cell())
+            },
+        )?;
+
+        // Decode the account: (spend_x, spend_y, master_x, master_y, cap)
+        let account = SPENDING_CAP_SCHEMA
+            .to_field_elements(&self.account.to_static_data_values())
+            .unwrap()
+            .into_iter()
+            .map(|element| {
+                assign_free_advice(
+                    layouter.namespace(|| "witness account element"),
+                    config.advices[0],
+                    Value::known(element),
+                )
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let encoded_account = SPENDING_CAP_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "account encoding"),
+            &account,
+        )?;
+        let (spend_x, spend_y, master_x, master_y, cap) = (
+            &account[0],
+            &account[1],
+            &account[2],
+            &account[3],
+            &account[4],
+        );
+
+        // The spent token is authorized by this vp under the master pk of the account, it's also
+        // the rcv pk
+        let receiver_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness receiver vp vk"),
+            config.advices[0],
+            Value::known(*COMPRESSED_RECEIVER_VK),
+        )?;
+        let encoded_token_auth = TOKEN_AUTHORIZATION_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "token authorization encoding"),
+            &[
+                master_x.clone(),
+                master_y.clone(),
+                input_budget.app_vk.clone(),
+                receiver_vp_vk,
+                master_x.clone(),
+                master_y.clone(),
+            ],
+        )?;
+
+        // Decode the input budget state
+        let input_remaining = assign_free_advice(
+            layouter.namespace(|| "witness input remaining"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.input_state.remaining)),
+        )?;
+        let input_epoch = assign_free_advice(
+            layouter.namespace(|| "witness input epoch"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.input_state.epoch)),
+        )?;
+        let encoded_input_state = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "input state encoding"),
+            [input_remaining.clone(), input_epoch.clone()],
+        )?;
+
+        layouter.assign_region(
+            || "check the account and the input state",
+            |mut region| {
+                region
+                    .constrain_equal(encoded_account.cell(), input_budget.app_data_static.cell())?;
+                region.constrain_equal(
+                    encoded_token_auth.cell(),
+                    token_note.app_data_dynamic.cell(),
+                )?;
+                region.constrain_equal(
+                    encoded_input_state.cell(),
+                    input_budget.app_data_dynamic.cell(),
+                )
+            },
+        )?;
+
+        // Publicize the epoch
+        let epoch = assign_free_advice(
+            layouter.namespace(|| "witness epoch"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.epoch)),
+        )?;
+        layouter.constrain_instance(
+            epoch.cell(),
+            config.instances,
+            SPENDING_CAP_EPOCH_PUBLIC_INPUT_IDX,
+        )?;
+
+        // The flags are boolean checked in the conditional gates
+        let is_refill = assign_free_advice(
+            layouter.namespace(|| "witness is_refill"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.is_refill() as u64)),
+        )?;
+        let is_spend = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "1 - is_refill"),
+            &constant_one,
+            &is_refill,
+        )?;
+
+        // A spend stays in the epoch and spends a budget note from the tree
+        config.conditional_config.assert_equal_if(
+            layouter.namespace(|| "spend epoch"),
+            &is_spend,
+            &epoch,
+            &input_epoch,
+        )?;
+        config.conditional_config.assert_equal_if(
+            layouter.namespace(|| "spend budget is merkle checked"),
+            &is_spend,
+            &input_budget.is_merkle_checked,
+            &constant_one,
+        )?;
+
+        // A refill moves to a later epoch: epoch - input_epoch - 1 >= 0
+        let epoch_gap = {
+            let diff = SubInstructions::sub(
+                &sub_chip,
+                layouter.namespace(|| "epoch - input_epoch"),
+                &epoch,
+                &input_epoch,
+            )?;
+            let gap = SubInstructions::sub(
+                &sub_chip,
+                layouter.namespace(|| "epoch - input_epoch - 1"),
+                &diff,
+                &constant_one,
+            )?;
+            config.conditional_config.select(
+                layouter.namespace(|| "select epoch gap"),
+                &is_refill,
+                &gap,
+                &constant_zero,
+            )?
+        };
+        Self::range_check(
+            &config,
+            layouter.namespace(|| "range check epoch gap"),
+            &epoch_gap,
+        )?;
+
+        // remaining' = (cap if refill else remaining) - spent
+        let budget = config.conditional_config.select(
+            layouter.namespace(|| "select budget"),
+            &is_refill,
+            cap,
+            &input_remaining,
+        )?;
+        let output_remaining = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "budget - spent"),
+            &budget,
+            &token_note.value,
+        )?;
+        Self::range_check(
+            &config,
+            layouter.namespace(|| "range check output remaining"),
+            &output_remaining,
+        )?;
+        let encoded_output_state = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "output state encoding"),
+            [output_remaining, epoch],
+        )?;
+        layouter.assign_region(
+            || "check the output state",
+            |mut region| {
+                region.constrain_equal(
+                    encoded_output_state.cell(),
+                    output_budget.app_data_dynamic.cell(),
+                )
+            },
+        )?;
+
+        // The signer is the master key for a refill and the spend key otherwise
+        let signer_x = config.conditional_config.select(
+            layouter.namespace(|| "select signer x"),
+            &is_refill,
+            master_x,
+            spend_x,
+        )?;
+        let signer_y = config.conditional_config.select(
+            layouter.namespace(|| "select signer y"),
+            &is_refill,
+            master_y,
+            spend_y,
+        )?;
+        let pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness pk"),
+            Value::known(self.signature.pk.to_affine()),
+        )?;
+        layouter.assign_region(
+            || "check the signer",
+            |mut region| {
+                region.constrain_equal(pk.inner().x().cell(), signer_x.cell())?;
+                region.constrain_equal(pk.inner().y().cell(), signer_y.cell())
+            },
+        )?;
+
+        let r = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness r"),
+            Value::known(self.signature.r.to_affine()),
+        )?;
+        let s_scalar = ScalarFixed::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness s"),
+            Value::known(self.signature.s),
+        )?;
+
+        // Verify: s*G = R + Hash(r||P||m)*P
+        let generator = FixedPoint::from_inner(ecc_chip, TaigaFixedBasesFull::BaseGenerator);
+        let (s_g, _) = generator.mul(layouter.namespace(|| "s_scalar * generator"), &s_scalar)?;
+        let message = signing_message_gadget(
+            &config,
+            layouter.namespace(|| "signing message"),
+            &basic_variables,
+            &self.anchors,
+        )?;
+        let h_scalar = schnorr_challenge_gadget(
+            &config,
+            layouter.namespace(|| "schnorr challenge"),
+            &r,
+            &pk,
+            &message,
+        )?;
+        let (h_p, _) = pk.mul(layouter.namespace(|| "hP"), h_scalar)?;
+        let rhs = r.add(layouter.namespace(|| "R + Hash(r||P||m)*P"), &h_p)?;
+        s_g.constrain_equal(layouter.namespace(|| "s*G = R + Hash(r||P||m)*P"), &rhs)?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(self.anchors.map(|anchor| anchor.inner()));
+        public_inputs.push(pallas::Base::from(self.epoch));
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+
+    fn get_signed_anchors_public_input_idx(&self) -> Option<usize> {
+        Some(SIGNED_ANCHORS_PUBLIC_INPUT_IDX)
+    }
+}
+
+vp_circuit_impl!(SpendingCapValidityPredicateCircuit);
+vp_verifying_info_impl!(SpendingCapValidityPredicateCircuit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::vp_examples::token::{Token, TokenName};
+    use crate::note::tests::random_output_note;
+
+    struct Keys {
+        spend_sk: SigningKey,
+        master_sk: SigningKey,
+        account: SpendingCap,
+    }
+
+    fn keys(cap: u64) -> Keys {
+        let spend_sk = SigningKey::random(OsRng);
+        let master_sk = SigningKey::random(OsRng);
+        let account = SpendingCap::new(&spend_sk, &master_sk, cap);
+        Keys {
+            spend_sk,
+            master_sk,
+            account,
+        }
+    }
+
+    // Spend `spent` of the token with the budget, the output budget has `output_state`
+    fn spending_cap_circuit(
+        account: SpendingCap,
+        input_state: Option<BudgetState>,
+        epoch: u64,
+        spent: u64,
+        output_state: BudgetState,
+        sk: &SigningKey,
+    ) -> SpendingCapValidityPredicateCircuit {
+        let mut rng = OsRng;
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let token_note = Token::new(TokenName::new("btc").unwrap(), spent)
+            .create_random_token_note(
+                &mut rng,
+                Nullifier::random(&mut rng),
+                nk,
+                &account.token_authorization(),
+            );
+        let rho = Nullifier::random(&mut rng);
+        // The genesis note if there's no input state
+        let input_budget = match input_state {
+            Some(state) => account.create_budget_note(&mut rng, state, rho, nk),
+            None => account.create_genesis_budget_note(&mut rng, rho, nk),
+        };
+        let output_budget =
+            account.create_budget_note(&mut rng, output_state, input_budget.get_nf().unwrap(), nk);
+        let input_notes = [*token_note.note(), input_budget];
+        let output_notes = [
+            random_output_note(&mut rng, token_note.get_nf().unwrap()),
+            output_budget,
+        ];
+        let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+        SpendingCapValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            token_note.get_nf().unwrap().inner(),
+            anchors,
+            input_notes,
+            output_notes,
+            account,
+            input_state.unwrap_or_default(),
+            epoch,
+            sk,
+        )
+    }
+
+    fn check(circuit: &SpendingCapValidityPredicateCircuit) -> bool {
+        circuit.dev_prove_and_check(OsRng).is_ok()
+    }
+
+    #[test]
+    fn test_budget_state_next() {
+        let state = BudgetState::new(20, 1);
+        assert_eq!(state.next(50, 1, 15), Some(BudgetState::new(5, 1)));
+        assert_eq!(state.next(50, 1, 30), None);
+        assert_eq!(state.next(50, 3, 30), Some(BudgetState::new(20, 3)));
+        assert_eq!(state.next(50, 0, 0), None);
+        assert_eq!(
+            BudgetState::default().next(50, 1, 0),
+            Some(BudgetState::new(50, 1))
+        );
+    }
+
+    #[test]
+    fn test_halo2_spending_cap_vp_circuit_spend() {
+        let Keys {
+            spend_sk, account, ..
+        } = keys(50);
+        let state = BudgetState::new(20, 1);
+        let mut circuit = spending_cap_circuit(
+            account,
+            Some(state),
+            1,
+            15,
+            BudgetState::new(5, 1),
+            &spend_sk,
+        );
+        assert!(check(&circuit));
+
+        // The budget output owns the same vp
+        let owned_note_pub_id = circuit.output_notes[1].commitment().inner();
+        circuit = SpendingCapValidityPredicateCircuit::from_sk_and_sign(
+            OsRng,
+            owned_note_pub_id,
+            circuit.anchors,
+            circuit.input_notes,
+            circuit.output_notes,
+            account,
+            state,
+            1,
+            &spend_sk,
+        );
+        assert!(check(&circuit));
+
+        // The output budget doesn't deduct the spent value
+        let circuit = spending_cap_circuit(
+            account,
+            Some(state),
+            1,
+            15,
+            BudgetState::new(20, 1),
+            &spend_sk,
+        );
+        assert!(!check(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_spending_cap_vp_circuit_overspend() {
+        let Keys {
+            spend_sk, account, ..
+        } = keys(50);
+        let state = BudgetState::new(20, 1);
+        assert_eq!(state.next(account.cap, 1, 30), None);
+        // remaining' = 20 - 30 wraps around and fails the range check
+        let circuit = spending_cap_circuit(
+            account,
+            Some(state),
+            1,
+            30,
+            BudgetState::new(0, 1),
+            &spend_sk,
+        );
+        assert!(!check(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_spending_cap_vp_circuit_refill() {
+        let Keys {
+            spend_sk,
+            master_sk,
+            account,
+        } = keys(50);
+        let state = BudgetState::new(20, 1);
+        let output_state = state.next(account.cap, 2, 30).unwrap();
+        let circuit = spending_cap_circuit(account, Some(state), 2, 30, output_state, &master_sk);
+        assert!(check(&circuit));
+
+        // The spend key can't refill
+        let circuit = spending_cap_circuit(account, Some(state), 2, 30, output_state, &spend_sk);
+        assert!(!check(&circuit));
+
+        // The refill can't go back to an earlier epoch
+        let state = BudgetState::new(20, 3);
+        let circuit = spending_cap_circuit(
+            account,
+            Some(state),
+            2,
+            30,
+            BudgetState::new(20, 2),
+            &master_sk,
+        );
+        assert!(!check(&circuit));
+
+        // Open the budget from the genesis note
+        let output_state = BudgetState::default().next(account.cap, 1, 10).unwrap();
+        let circuit = spending_cap_circuit(account, None, 1, 10, output_state, &master_sk);
+        assert!(check(&circuit));
+    }
+
+    #[test]
+    fn test_halo2_spending_cap_vp_circuit_wrong_account() {
+        let Keys {
+            spend_sk, account, ..
+        } = keys(50);
+        let state = BudgetState::new(20, 1);
+        let circuit = spending_cap_circuit(
+            account,
+            Some(state),
+            1,
+            15,
+            BudgetState::new(5, 1),
+            &spend_sk,
+        );
+        // The token is authorized by another master key
+        let mut input_notes = *circuit.input_notes;
+        input_notes[0].app_data_dynamic =
+            keys(50).account.token_authorization().to_app_data_dynamic();
+        let circuit = SpendingCapValidityPredicateCircuit::from_sk_and_sign(
+            OsRng,
+            input_notes[0].get_nf().unwrap().inner(),
+            circuit.anchors,
+            input_notes,
+            circuit.output_notes,
+            account,
+            state,
+            1,
+            &spend_sk,
+        );
+        assert!(!check(&circuit));
+    }
+}
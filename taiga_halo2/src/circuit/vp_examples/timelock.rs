@@ -0,0 +1,295 @@
+/// The timelock application, a timelocked note can't be spent before its unlock height.
+/// The app_data_static encodes the unlock height, so the notes locked until different heights are
+/// of different note types.
+/// - When the owned note is spent, the height must be at least the unlock height.
+/// - When the owned note is created, only the encoding is checked.
+/// The height is a custom public input, the verifier checks it against the height of the block,
+/// see `ExecutionContext`.
+///
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            assign_free_advice, comparison::assert_greater_or_equal_64,
+            target_note_variable::get_owned_note_variables,
+        },
+        integrity::check_value_range,
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+    },
+    constant::{NUM_NOTE, TIMELOCK_DOMAIN, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX},
+    note::{
+        encoding::{StaticDataField, StaticDataSchema, StaticDataValue},
+        Note, RandomSeed,
+    },
+    nullifier::{Nullifier, NullifierKeyContainer},
+    proof::Proof,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_proofs::{
+    circuit::{floor_planner, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::pallas;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// The height is the first custom public input.
+pub const TIMELOCK_HEIGHT_PUBLIC_INPUT_IDX: usize = VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX;
+
+lazy_static! {
+    pub static ref TIMELOCK_VK: ValidityPredicateVerifyingKey =
+        TimelockValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_TIMELOCK_VK: pallas::Base = TIMELOCK_VK.get_compressed();
+    // The app_data_static of timelocked notes: (unlock height)
+    pub static ref TIMELOCK_SCHEMA: StaticDataSchema =
+        StaticDataSchema::new(TIMELOCK_DOMAIN, vec![StaticDataField::U64]);
+}
+
+// TimelockValidityPredicateCircuit
+#[derive(Clone, Debug, Default)]
+pub struct TimelockValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    pub unlock_height: u64,
+    // The height of the transaction
+    pub height: u64,
+}
+
+impl TimelockValidityPredicateCircuit {
+    pub fn encode_app_data_static(unlock_height: u64) -> pallas::Base {
+        TIMELOCK_SCHEMA
+            .encode(&[StaticDataValue::U64(unlock_height)])
+            .unwrap()
+    }
+
+    // Whether a note locked until unlock_height can be spent at the height
+    pub fn is_unlocked(unlock_height: u64, height: u64) -> bool {
+        height >= unlock_height
+    }
+}
+
+impl ValidityPredicateCircuit for TimelockValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        let owned_note = get_owned_note_variables(
+            &config,
+            layouter.namespace(|| "get owned note variables"),
+            &basic_variables,
+        )?;
+
+        // Decode the unlock height, it's compared as a u64
+        let unlock_height = assign_free_advice(
+            layouter.namespace(|| "witness unlock height"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.unlock_height)),
+        )?;
+        check_value_range(
+            layouter.namespace(|| "range check unlock height"),
+            &config.range_check,
+            &unlock_height,
+        )?;
+        let encoded_app_data_static = TIMELOCK_SCHEMA.encode_gadget(
+            config.poseidon_config.clone(),
+            config.advices[0],
+            layouter.namespace(|| "app_data_static encoding"),
+            &[unlock_height.clone()],
+        )?;
+        layouter.assign_region(
+            || "check app_data_static",
+            |mut region| {
+                region.constrain_equal(
+                    encoded_app_data_static.cell(),
+                    owned_note.note_variables.app_data_static.cell(),
+                )
+            },
+        )?;
+
+        // Publicize the height
+        let height = assign_free_advice(
+            layouter.namespace(|| "witness height"),
+            config.advices[0],
+            Value::known(pallas::Base::from(self.height)),
+        )?;
+        layouter.constrain_instance(
+            height.cell(),
+            config.instances,
+            TIMELOCK_HEIGHT_PUBLIC_INPUT_IDX,
+        )?;
+
+        // The lock only applies to the spend: when the note is created, the unlock height is
+        // compared with itself.
+        let height = config.conditional_config.select(
+            layouter.namespace(|| "select height"),
+            &owned_note.is_input_note,
+            &height,
+            &unlock_height,
+        )?;
+        assert_greater_or_equal_64(
+            layouter.namespace(|| "check height >= unlock height"),
+            &config.comparison_config,
+            &height,
+            &unlock_height,
+        )?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        public_inputs.push(pallas::Base::from(self.height));
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+}
+
+vp_circuit_impl!(TimelockValidityPredicateCircuit);
+vp_verifying_info_impl!(TimelockValidityPredicateCircuit);
+
+pub fn create_timelocked_note<R: RngCore>(
+    mut rng: R,
+    unlock_height: u64,
+    value: u64,
+    rho: Nullifier,
+    nk: NullifierKeyContainer,
+) -> Note {
+    let app_data_static = TimelockValidityPredicateCircuit::encode_app_data_static(unlock_height);
+    let rseed = RandomSeed::random(&mut rng);
+    Note::new(
+        *COMPRESSED_TIMELOCK_VK,
+        app_data_static,
+        pallas::Base::zero(),
+        value,
+        nk,
+        rho,
+        true,
+        rseed,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    // Spend the note locked until unlock_height at the height
+    fn spend_circuit(
+        mut rng: impl RngCore,
+        unlock_height: u64,
+        height: u64,
+    ) -> TimelockValidityPredicateCircuit {
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let locked_note =
+            create_timelocked_note(&mut rng, unlock_height, 5, Nullifier::random(&mut rng), nk);
+        let input_notes = [locked_note, random_input_note(&mut rng)];
+        let output_notes = input_notes
+            .iter()
+            .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()))
+            .collect::<Vec<_>>();
+        TimelockValidityPredicateCircuit {
+            owned_note_pub_id: locked_note.get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes.try_into().unwrap()),
+            unlock_height,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_is_unlocked() {
+        assert!(!TimelockValidityPredicateCircuit::is_unlocked(100, 99));
+        assert!(TimelockValidityPredicateCircuit::is_unlocked(100, 100));
+        assert!(TimelockValidityPredicateCircuit::is_unlocked(100, 150));
+    }
+
+    #[test]
+    fn test_halo2_timelock_vp_circuit_spend() {
+        let mut rng = OsRng;
+
+        let circuit = spend_circuit(&mut rng, 100, 150);
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+        // Exactly at the unlock height
+        let circuit = spend_circuit(&mut rng, 100, 100);
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+
+        // Before the unlock height
+        let circuit = spend_circuit(&mut rng, 100, 50);
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+
+        // The published height is not the one compared with the unlock height
+        let circuit = spend_circuit(&mut rng, 100, 150);
+        let mut public_inputs = circuit.get_public_inputs(&mut rng).to_vec();
+        public_inputs[TIMELOCK_HEIGHT_PUBLIC_INPUT_IDX] = pallas::Base::from(50u64);
+        assert!(circuit
+            .dev_prove_and_check_with_public_inputs(&public_inputs.into())
+            .is_err());
+
+        // The app_data_static doesn't encode the unlock height
+        let mut circuit = spend_circuit(&mut rng, 100, 150);
+        circuit.unlock_height = 10;
+        assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+    }
+
+    #[test]
+    fn test_halo2_timelock_vp_circuit_create() {
+        let mut rng = OsRng;
+        let input_notes = [random_input_note(&mut rng), random_input_note(&mut rng)];
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let locked_note =
+            create_timelocked_note(&mut rng, 100, 5, input_notes[0].get_nf().unwrap(), nk);
+        let output_notes = [
+            locked_note,
+            random_output_note(&mut rng, input_notes[1].get_nf().unwrap()),
+        ];
+
+        // The note can be created before the unlock height
+        let circuit = TimelockValidityPredicateCircuit {
+            owned_note_pub_id: locked_note.commitment().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes),
+            unlock_height: 100,
+            height: 50,
+        };
+        assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+    }
+}
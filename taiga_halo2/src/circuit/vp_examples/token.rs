@@ -1,33 +1,46 @@
 use crate::{
+    address::PaymentAddress,
     circuit::{
-        blake2s::{vp_commitment_gadget, Blake2sChip},
+        blake2s::dynamic_vp_commitment_gadget,
         gadgets::{
             assign_free_advice, assign_free_constant,
-            poseidon_hash::poseidon_hash_gadget,
+            mul::{MulChip, MulInstructions},
+            sub::{SubChip, SubInstructions},
             target_note_variable::{get_is_input_note_flag, get_owned_note_variable},
         },
         vp_circuit::{
             BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
             ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
         },
-        vp_examples::receiver_vp::{ReceiverValidityPredicateCircuit, COMPRESSED_RECEIVER_VK},
+        vp_examples::receiver_vp::{
+            output_note_kind, ReceiverValidityPredicateCircuit, COMPRESSED_RECEIVER_VK,
+        },
         vp_examples::signature_verification::{
             SignatureVerificationValidityPredicateCircuit, COMPRESSED_TOKEN_AUTH_VK,
         },
+        vp_examples::token_issuance::{
+            TokenIssuanceValidityPredicateCircuit, COMPRESSED_TOKEN_ISSUANCE_VK,
+        },
     },
     constant::{
-        NUM_NOTE, PRF_EXPAND_DYNAMIC_VP_1_CM_R, SETUP_PARAMS_MAP, VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1,
+        APP_DATA_DYNAMIC_DOMAIN, NUM_NOTE, PRF_EXPAND_DYNAMIC_VP_1_CM_R, TOKEN_ISSUANCE_DOMAIN,
+        TOKEN_NAME_PERSONALIZATION, VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1,
         VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2, VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_1,
         VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_2,
     },
-    merkle_tree::MerklePath,
-    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+    keys::SigningKey,
+    merkle_tree::{Anchor, MerklePath},
+    note::{
+        encoding::{EncodingError, StaticDataField, StaticDataSchema, StaticDataValue},
+        InputNoteProvingInfo, Note, NoteType, OutputNoteProvingInfo, RandomSeed,
+    },
     nullifier::{Nullifier, NullifierKeyContainer},
     proof::Proof,
-    utils::poseidon_hash_n,
+    utils::{non_identity_point_from_bytes, point_to_bytes, poseidon_hash_n},
     vp_commitment::ValidityPredicateCommitment,
     vp_vk::ValidityPredicateVerifyingKey,
 };
+use blake2s_simd::Params as Blake2sParams;
 use ff::Field;
 use group::{Curve, Group};
 use halo2_gadgets::ecc::{chip::EccChip, NonIdentityPoint};
@@ -39,26 +52,139 @@ use lazy_static::lazy_static;
 use pasta_curves::arithmetic::CurveAffine;
 use pasta_curves::{group::ff::PrimeField, pallas};
 use rand::{rngs::OsRng, Rng, RngCore};
+use std::fmt;
+use std::sync::Arc;
 
 lazy_static! {
     pub static ref TOKEN_VK: ValidityPredicateVerifyingKey =
         TokenValidityPredicateCircuit::default().get_vp_vk();
     pub static ref COMPRESSED_TOKEN_VK: pallas::Base = TOKEN_VK.get_compressed();
+    // The app_data_dynamic of token notes: (auth pk, auth vp vk, receiver vp vk, rcv pk)
+    pub static ref TOKEN_AUTHORIZATION_SCHEMA: StaticDataSchema = StaticDataSchema::new(
+        APP_DATA_DYNAMIC_DOMAIN,
+        vec![
+            StaticDataField::Point,
+            StaticDataField::Base,
+            StaticDataField::Base,
+            StaticDataField::Point
+        ]
+    );
+}
+
+/// The max length of a token name in bytes
+pub const TOKEN_NAME_MAX_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenNameError {
+    /// The name is empty.
+    Empty,
+    /// The name is longer than `TOKEN_NAME_MAX_LEN` bytes.
+    TooLong(usize),
+    /// The name has a character out of the token name charset.
+    InvalidCharacter(char),
 }
 
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct TokenName(String);
+impl fmt::Display for TokenNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TokenNameError::*;
+        match self {
+            Empty => f.write_str("The token name is empty"),
+            TooLong(len) => write!(
+                f,
+                "The token name has {len} bytes, the max is {TOKEN_NAME_MAX_LEN}"
+            ),
+            InvalidCharacter(c) => write!(f, "The token name has an invalid character {c:?}"),
+        }
+    }
+}
+
+impl std::error::Error for TokenNameError {}
+
+/// The name of a token. Names are 1 to `TOKEN_NAME_MAX_LEN` ASCII letters, digits, '_', '-' or
+/// '.', and uppercase letters are folded to lowercase, i.e. "BTC" and "btc" are the same token.
+/// Non-ASCII names are rejected, so there is no unicode normalization to agree on: an ASCII
+/// string is already in NFC.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenName {
+    name: String,
+    // The issuer can mint and burn the token, it's committed to the encoding of issuable tokens.
+    issuer: Option<pallas::Point>,
+}
 
 impl TokenName {
+    pub fn new(name: &str) -> Result<Self, TokenNameError> {
+        Ok(Self {
+            name: Self::normalize(name)?,
+            issuer: None,
+        })
+    }
+
+    pub fn new_issuable(name: &str, issuer: pallas::Point) -> Result<Self, TokenNameError> {
+        Ok(Self {
+            name: Self::normalize(name)?,
+            issuer: Some(issuer),
+        })
+    }
+
+    fn normalize(name: &str) -> Result<String, TokenNameError> {
+        if name.is_empty() {
+            return Err(TokenNameError::Empty);
+        }
+        if name.len() > TOKEN_NAME_MAX_LEN {
+            return Err(TokenNameError::TooLong(name.len()));
+        }
+        if let Some(c) = name
+            .chars()
+            .find(|&c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')))
+        {
+            return Err(TokenNameError::InvalidCharacter(c));
+        }
+        Ok(name.to_ascii_lowercase())
+    }
+
+    // The encoding goes to app_data_static.
+    // encode = name_element if the token is not issuable, otherwise
+    // poseidon_hash(TOKEN_ISSUANCE_DOMAIN || name_element || issuer)
     pub fn encode(&self) -> pallas::Base {
-        assert!(self.0.len() < 32);
-        let mut bytes: [u8; 32] = [0; 32];
-        bytes[..self.0.len()].copy_from_slice(self.0.as_bytes());
+        let name = self.name_element();
+        match self.issuer {
+            None => name,
+            Some(issuer) => {
+                let issuer_coord = issuer.to_affine().coordinates().unwrap();
+                poseidon_hash_n([
+                    TOKEN_ISSUANCE_DOMAIN,
+                    name,
+                    *issuer_coord.x(),
+                    *issuer_coord.y(),
+                ])
+            }
+        }
+    }
+
+    // The Blake2s hash of the normalized name, with the top two bits cleared to fit in the field
+    pub fn name_element(&self) -> pallas::Base {
+        let h = Blake2sParams::new()
+            .hash_length(32)
+            .personal(TOKEN_NAME_PERSONALIZATION)
+            .hash(self.name.as_bytes());
+        let mut bytes: [u8; 32] = h.as_bytes().try_into().unwrap();
+        bytes[31] &= 0x3f;
         pallas::Base::from_repr(bytes).unwrap()
     }
 
     pub fn inner(&self) -> String {
-        self.0.clone()
+        self.name.clone()
+    }
+
+    pub fn issuer(&self) -> Option<pallas::Point> {
+        self.issuer
+    }
+}
+
+// A placeholder name for the default circuits, it must pass the validation as well.
+impl Default for TokenName {
+    fn default() -> Self {
+        Self::new("token_name").unwrap()
     }
 }
 
@@ -69,11 +195,8 @@ pub struct Token {
 }
 
 impl Token {
-    pub fn new(name: String, value: u64) -> Self {
-        Self {
-            name: TokenName(name),
-            value,
-        }
+    pub fn new(name: TokenName, value: u64) -> Self {
+        Self { name, value }
     }
 
     pub fn name(&self) -> &TokenName {
@@ -92,6 +215,14 @@ impl Token {
         pallas::Base::from(self.value)
     }
 
+    pub fn note_type(&self) -> NoteType {
+        NoteType::new(*COMPRESSED_TOKEN_VK, self.encode_name())
+    }
+
+    pub fn value_base(&self) -> pallas::Point {
+        self.note_type().derive_note_type()
+    }
+
     pub fn create_random_token_note<R: RngCore>(
         &self,
         mut rng: R,
@@ -99,12 +230,12 @@ impl Token {
         nk_container: NullifierKeyContainer,
         auth: &TokenAuthorization,
     ) -> TokenNote {
-        let app_data_static = self.encode_name();
+        let note_type = self.note_type();
         let app_data_dynamic = auth.to_app_data_dynamic();
         let rseed = RandomSeed::random(&mut rng);
         let note = Note::new(
-            *COMPRESSED_TOKEN_VK,
-            app_data_static,
+            note_type.app_vk,
+            note_type.app_data_static,
             app_data_dynamic,
             self.value(),
             nk_container,
@@ -118,6 +249,72 @@ impl Token {
             note,
         }
     }
+
+    // Create the token note sent to the payment address. The address auth pk authorizes the note
+    // with the token auth vp, and the note is encrypted to the address rcv pk by the receiver vp
+    // of the address.
+    pub fn create_note_to_address<R: RngCore>(
+        &self,
+        mut rng: R,
+        addr: &PaymentAddress,
+        rho: Nullifier,
+    ) -> TokenNote {
+        let note_type = self.note_type();
+        let app_data_dynamic = TOKEN_AUTHORIZATION_SCHEMA
+            .encode(&[
+                StaticDataValue::Point(addr.get_auth_pk()),
+                StaticDataValue::Base(*COMPRESSED_TOKEN_AUTH_VK),
+                StaticDataValue::Base(addr.get_receiver_vp_vk()),
+                StaticDataValue::Point(addr.get_rcv_pk()),
+            ])
+            .expect("the address pks are the identity");
+        let rseed = RandomSeed::random(&mut rng);
+        let note = Note::new(
+            note_type.app_vk,
+            note_type.app_data_static,
+            app_data_dynamic,
+            self.value(),
+            addr.get_nk_container(),
+            rho,
+            true,
+            rseed,
+        );
+
+        TokenNote {
+            token_name: self.name().clone(),
+            note,
+        }
+    }
+
+    // Create an ephemeral note of the issuable token. Spending it mints the token and creating it
+    // burns the token, both are authorized by the issuer signature in the token issuance VP.
+    pub fn create_issuance_note<R: RngCore>(
+        &self,
+        mut rng: R,
+        rho: Nullifier,
+        nk_container: NullifierKeyContainer,
+    ) -> TokenNote {
+        let issuer = self.name().issuer().expect("the token is not issuable");
+        let auth = TokenAuthorization::new(issuer, *COMPRESSED_TOKEN_ISSUANCE_VK)
+            .expect("the issuer is not a valid key");
+        let note_type = self.note_type();
+        let rseed = RandomSeed::random(&mut rng);
+        let note = Note::new(
+            note_type.app_vk,
+            note_type.app_data_static,
+            auth.to_app_data_dynamic(),
+            self.value(),
+            nk_container,
+            rho,
+            false,
+            rseed,
+        );
+
+        TokenNote {
+            token_name: self.name().clone(),
+            note,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -151,23 +348,50 @@ impl TokenNote {
         &self.note
     }
 
+    // The note authorized under new_auth, the value, the token and the nk commitment are kept.
+    // Spending this note and creating the rotated one in a ptx rotates the auth key: the
+    // signature of the old key covers the commitment of the rotated note.
+    pub fn rotate_auth<R: RngCore>(&self, mut rng: R, new_auth: &TokenAuthorization) -> TokenNote {
+        let note = Note::new(
+            self.note.get_app_vk(),
+            self.note.get_app_data_static(),
+            new_auth.to_app_data_dynamic(),
+            self.note.value,
+            self.note.nk_container,
+            self.note.get_nf().unwrap(),
+            true,
+            RandomSeed::random(&mut rng),
+        );
+
+        TokenNote {
+            token_name: self.token_name.clone(),
+            note,
+        }
+    }
+
+    // The anchors of the input notes are signed by the auth_sk, the anchor of the token note must
+    // be the root of the merkle_path.
     #[allow(clippy::too_many_arguments)]
     pub fn generate_input_token_note_proving_info<R: RngCore>(
         &self,
         mut rng: R,
         auth: TokenAuthorization,
-        auth_sk: pallas::Scalar,
+        auth_sk: &SigningKey,
         merkle_path: MerklePath,
+        anchors: [Anchor; NUM_NOTE],
         input_notes: [Note; NUM_NOTE],
         output_notes: [Note; NUM_NOTE],
     ) -> InputNoteProvingInfo {
         let TokenNote { token_name, note } = self;
+        // The vps share the notes
+        let input_notes = Arc::new(input_notes);
+        let output_notes = Arc::new(output_notes);
         // token VP
         let nf = note.get_nf().unwrap().inner();
         let token_vp = TokenValidityPredicateCircuit {
             owned_note_pub_id: nf,
-            input_notes,
-            output_notes,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
             token_name: token_name.clone(),
             auth,
             receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
@@ -178,11 +402,13 @@ impl TokenNote {
         let token_auth_vp = SignatureVerificationValidityPredicateCircuit::from_sk_and_sign(
             &mut rng,
             nf,
+            anchors,
             input_notes,
             output_notes,
             auth.vk,
             auth_sk,
             *COMPRESSED_RECEIVER_VK,
+            auth.rcv_pk,
         );
 
         // input note proving info
@@ -205,11 +431,16 @@ impl TokenNote {
         let TokenNote { token_name, note } = self;
 
         let owned_note_pub_id = note.commitment().inner();
+        // Change or self if the receiver is a spender, i.e. the auth pks are the same
+        let note_kind = output_note_kind(note, &input_notes, &output_notes).to_base();
+        // The vps share the notes
+        let input_notes = Arc::new(input_notes);
+        let output_notes = Arc::new(output_notes);
         // token VP
         let token_vp = TokenValidityPredicateCircuit {
             owned_note_pub_id,
-            input_notes,
-            output_notes,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
             token_name: token_name.clone(),
             auth,
             receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
@@ -223,12 +454,113 @@ impl TokenNote {
             output_notes,
             vp_vk: *COMPRESSED_RECEIVER_VK,
             nonce: pallas::Base::from_u128(rng.gen()),
-            sk: pallas::Base::random(&mut rng),
-            rcv_pk: auth.pk,
+            esk: pallas::Base::random(&mut rng),
+            rcv_pk: auth.rcv_pk,
+            auth_pk: auth.pk,
             auth_vp_vk: *COMPRESSED_TOKEN_AUTH_VK,
+            note_kind,
         };
 
-        OutputNoteProvingInfo::new(*note, Box::new(token_vp), vec![Box::new(receiver_vp)])
+        let note_encryption = receiver_vp.note_encryption();
+
+        let mut proving_info =
+            OutputNoteProvingInfo::new(*note, Box::new(token_vp), vec![Box::new(receiver_vp)]);
+        // The receiver vp publishes the note ciphertext
+        proving_info.set_note_encryption(note_encryption);
+        proving_info
+    }
+
+    // The output note proving info of a note created by `Token::create_note_to_address`.
+    // Only the receiver vp of this crate can be proven, panics if the address has another one.
+    pub fn generate_output_token_note_proving_info_to_address<R: RngCore>(
+        &self,
+        rng: R,
+        addr: &PaymentAddress,
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> OutputNoteProvingInfo {
+        assert_eq!(
+            addr.get_receiver_vp_vk(),
+            *COMPRESSED_RECEIVER_VK,
+            "the receiver vp of the address is not supported"
+        );
+        let auth = TokenAuthorization::from_address(addr);
+        self.generate_output_token_note_proving_info(rng, auth, input_notes, output_notes)
+    }
+
+    // Spend the issuance note to mint the token, the issuer signs the anchors of the input notes
+    pub fn generate_input_issuance_proving_info<R: RngCore>(
+        &self,
+        mut rng: R,
+        issuer_sk: &SigningKey,
+        merkle_path: MerklePath,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> InputNoteProvingInfo {
+        let nf = self.note.get_nf().unwrap().inner();
+        let (token_vp, issuance_vp) =
+            self.issuance_vps(&mut rng, nf, issuer_sk, anchors, input_notes, output_notes);
+        InputNoteProvingInfo::new(
+            self.note,
+            merkle_path,
+            None,
+            Box::new(token_vp),
+            vec![Box::new(issuance_vp)],
+        )
+    }
+
+    // Create the issuance note to burn the token, the issuer signs the anchors of the input notes
+    pub fn generate_output_issuance_proving_info<R: RngCore>(
+        &self,
+        mut rng: R,
+        issuer_sk: &SigningKey,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> OutputNoteProvingInfo {
+        let cm = self.note.commitment().inner();
+        let (token_vp, issuance_vp) =
+            self.issuance_vps(&mut rng, cm, issuer_sk, anchors, input_notes, output_notes);
+        OutputNoteProvingInfo::new(self.note, Box::new(token_vp), vec![Box::new(issuance_vp)])
+    }
+
+    fn issuance_vps<R: RngCore>(
+        &self,
+        mut rng: R,
+        owned_note_pub_id: pallas::Base,
+        issuer_sk: &SigningKey,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: [Note; NUM_NOTE],
+        output_notes: [Note; NUM_NOTE],
+    ) -> (
+        TokenValidityPredicateCircuit,
+        TokenIssuanceValidityPredicateCircuit,
+    ) {
+        let issuer = self.token_name.issuer().expect("the token is not issuable");
+        // The vps share the notes
+        let input_notes = Arc::new(input_notes);
+        let output_notes = Arc::new(output_notes);
+        let token_vp = TokenValidityPredicateCircuit {
+            owned_note_pub_id,
+            input_notes: input_notes.clone(),
+            output_notes: output_notes.clone(),
+            token_name: self.token_name.clone(),
+            auth: TokenAuthorization::new(issuer, *COMPRESSED_TOKEN_ISSUANCE_VK)
+                .expect("the issuer is not a valid key"),
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+            rseed: RandomSeed::random(&mut rng),
+        };
+        let issuance_vp = TokenIssuanceValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            self.token_name.clone(),
+            issuer_sk,
+        );
+        (token_vp, issuance_vp)
     }
 }
 
@@ -236,8 +568,8 @@ impl TokenNote {
 #[derive(Clone, Debug)]
 pub struct TokenValidityPredicateCircuit {
     pub owned_note_pub_id: pallas::Base,
-    pub input_notes: [Note; NUM_NOTE],
-    pub output_notes: [Note; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
     // The token_name goes to app_data_static. It can be extended to a list and embedded to app_data_static.
     pub token_name: TokenName,
     // The auth goes to app_data_dynamic and defines how to consume and create the note.
@@ -251,6 +583,9 @@ pub struct TokenValidityPredicateCircuit {
 pub struct TokenAuthorization {
     pub pk: pallas::Point,
     pub vk: pallas::Base,
+    // The receiver vp encrypts the note to the rcv_pk. It's the pk unless it's set apart with
+    // `with_rcv_pk`, e.g. the notes sent to a payment address.
+    pub rcv_pk: pallas::Point,
 }
 
 impl Default for TokenAuthorization {
@@ -258,6 +593,7 @@ impl Default for TokenAuthorization {
         Self {
             pk: pallas::Point::generator(),
             vk: pallas::Base::one(),
+            rcv_pk: pallas::Point::generator(),
         }
     }
 }
@@ -266,9 +602,9 @@ impl Default for TokenValidityPredicateCircuit {
     fn default() -> Self {
         Self {
             owned_note_pub_id: pallas::Base::zero(),
-            input_notes: [(); NUM_NOTE].map(|_| Note::default()),
-            output_notes: [(); NUM_NOTE].map(|_| Note::default()),
-            token_name: TokenName("Token_name".to_string()),
+            input_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            output_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            token_name: TokenName::new("Token_name").unwrap(),
             auth: TokenAuthorization::default(),
             receiver_vp_vk: pallas::Base::zero(),
             rseed: RandomSeed::default(),
@@ -286,6 +622,7 @@ impl ValidityPredicateCircuit for TokenValidityPredicateCircuit {
     ) -> Result<(), Error> {
         let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
 
+        // The token name element is derived natively, see TokenName::name_element
         let token_property = assign_free_advice(
             layouter.namespace(|| "witness token_property"),
             config.advices[0],
@@ -302,7 +639,7 @@ impl ValidityPredicateCircuit for TokenValidityPredicateCircuit {
             &basic_variables.get_app_data_static_searchable_pairs(),
         )?;
 
-        // check app_data_static
+        // check app_data_static, the action circuit derives the value base of the note from it
         layouter.assign_region(
             || "check app_data_static",
             |mut region| region.constrain_equal(token_property.cell(), app_data_static.cell()),
@@ -312,11 +649,17 @@ impl ValidityPredicateCircuit for TokenValidityPredicateCircuit {
         let ecc_chip = EccChip::construct(config.ecc_config);
 
         let pk = NonIdentityPoint::new(
-            ecc_chip,
+            ecc_chip.clone(),
             layouter.namespace(|| "witness pk"),
             Value::known(self.auth.pk.to_affine()),
         )?;
 
+        let rcv_pk = NonIdentityPoint::new(
+            ecc_chip,
+            layouter.namespace(|| "witness rcv_pk"),
+            Value::known(self.auth.rcv_pk.to_affine()),
+        )?;
+
         let auth_vp_vk = assign_free_advice(
             layouter.namespace(|| "witness auth vp vk"),
             config.advices[0],
@@ -338,14 +681,17 @@ impl ValidityPredicateCircuit for TokenValidityPredicateCircuit {
         )?;
 
         // Decode the app_data_dynamic, and check the app_data_dynamic encoding
-        let encoded_app_data_dynamic = poseidon_hash_gadget(
+        let encoded_app_data_dynamic = TOKEN_AUTHORIZATION_SCHEMA.encode_gadget(
             config.poseidon_config,
+            config.advices[0],
             layouter.namespace(|| "app_data_dynamic encoding"),
-            [
+            &[
                 pk.inner().x(),
                 pk.inner().y(),
                 auth_vp_vk.clone(),
                 receiver_vp_vk.clone(),
+                rcv_pk.inner().x(),
+                rcv_pk.inner().y(),
             ],
         )?;
 
@@ -357,56 +703,143 @@ impl ValidityPredicateCircuit for TokenValidityPredicateCircuit {
         )?;
 
         // check the is_merkle_checked flag
+        // Only the issuance notes(authorized by the token issuance vp) can be ephemeral:
+        // (1 - is_merkle_checked) * (auth_vp_vk - issuance_vp_vk) = 0
         let is_merkle_checked = get_owned_note_variable(
             config.get_owned_note_variable_config,
             layouter.namespace(|| "get is_merkle_checked"),
             &owned_note_pub_id,
             &basic_variables.get_is_merkle_checked_searchable_pairs(),
         )?;
+        let constant_zero = assign_free_constant(
+            layouter.namespace(|| "zero"),
+            config.advices[0],
+            pallas::Base::zero(),
+        )?;
         let constant_one = assign_free_constant(
             layouter.namespace(|| "one"),
             config.advices[0],
             pallas::Base::one(),
         )?;
+        let issuance_vp_vk = assign_free_constant(
+            layouter.namespace(|| "issuance vp vk"),
+            config.advices[0],
+            *COMPRESSED_TOKEN_ISSUANCE_VK,
+        )?;
+        let sub_chip = SubChip::construct(config.sub_config.clone(), ());
+        let mul_chip = MulChip::construct(config.mul_config.clone());
+        let is_ephemeral = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "1 - is_merkle_checked"),
+            &constant_one,
+            &is_merkle_checked,
+        )?;
+        let auth_vk_diff = SubInstructions::sub(
+            &sub_chip,
+            layouter.namespace(|| "auth_vp_vk - issuance_vp_vk"),
+            &auth_vp_vk,
+            &issuance_vp_vk,
+        )?;
+        let ephemeral_check = MulInstructions::mul(
+            &mul_chip,
+            layouter.namespace(|| "is_ephemeral * auth_vk_diff"),
+            &is_ephemeral,
+            &auth_vk_diff,
+        )?;
         layouter.assign_region(
             || "check is_merkle_checked",
-            |mut region| region.constrain_equal(is_merkle_checked.cell(), constant_one.cell()),
+            |mut region| region.constrain_equal(ephemeral_check.cell(), constant_zero.cell()),
         )?;
 
         // VP Commitment
         // Commt the sender(authorization method included) vp if it's an input note;
-        // Commit the receiver(note encryption constraints included) vp if it's an output note.
+        // Commit the receiver(note encryption constraints included) vp if it's an output note;
+        // Commit the issuance vp(the auth vp) if it's an issuance note.
+        let is_input_note = get_is_input_note_flag(
+            config.get_is_input_note_flag_config,
+            layouter.namespace(|| "get is_input_note_flag"),
+            &owned_note_pub_id,
+            &basic_variables.get_input_note_nfs(),
+            &basic_variables.get_output_note_cms(),
+        )?;
         let first_dynamic_vp = {
-            let is_input_note = get_is_input_note_flag(
-                config.get_is_input_note_flag_config,
-                layouter.namespace(|| "get is_input_note_flag"),
-                &owned_note_pub_id,
-                &basic_variables.get_input_note_nfs(),
-                &basic_variables.get_output_note_cms(),
+            let transfer_vp = layouter.assign_region(
+                || "conditional select: ",
+                |mut region| {
+                    config.conditional_select_config.assign_region(
+                        &is_input_note,
+                        &auth_vp_vk,
+                        &receiver_vp_vk,
+                        0,
+                        &mut region,
+                    )
+                },
             )?;
             layouter.assign_region(
                 || "conditional select: ",
                 |mut region| {
                     config.conditional_select_config.assign_region(
-                        &is_input_note,
+                        &is_merkle_checked,
+                        &transfer_vp,
                         &auth_vp_vk,
+                        0,
+                        &mut region,
+                    )
+                },
+            )?
+        };
+
+        // The receiver vp encrypts the owned note if it's a transferred output note, the
+        // input notes and the ephemeral notes are not encrypted
+        let encryption_vp_vk = {
+            let output_vp = layouter.assign_region(
+                || "conditional select: ",
+                |mut region| {
+                    config.conditional_select_config.assign_region(
+                        &is_input_note,
+                        &constant_zero,
                         &receiver_vp_vk,
                         0,
                         &mut region,
                     )
                 },
+            )?;
+            layouter.assign_region(
+                || "conditional select: ",
+                |mut region| {
+                    config.conditional_select_config.assign_region(
+                        &is_merkle_checked,
+                        &output_vp,
+                        &constant_zero,
+                        0,
+                        &mut region,
+                    )
+                },
             )?
         };
+        layouter.assign_region(
+            || "check encryption vp vk",
+            |mut region| {
+                region.constrain_equal(
+                    encryption_vp_vk.cell(),
+                    basic_variables.encryption_vp_vk.cell(),
+                )
+            },
+        )?;
 
-        // Construct a blake2s chip
-        let blake2s_chip = Blake2sChip::construct(config.blake2s_config);
+        // The first dynamic vp commitment opens to the vp selected above, the verifier of the
+        // dynamic vp proof checks the opening
         let vp_cm_r = assign_free_advice(
             layouter.namespace(|| "vp_cm_r"),
             config.advices[0],
             Value::known(self.rseed.get_vp_cm_r(PRF_EXPAND_DYNAMIC_VP_1_CM_R)),
         )?;
-        let first_dynamic_vp_cm =
-            vp_commitment_gadget(&mut layouter, &blake2s_chip, first_dynamic_vp, vp_cm_r)?;
+        let first_dynamic_vp_cm = dynamic_vp_commitment_gadget(
+            &mut layouter,
+            config.blake2s_config,
+            first_dynamic_vp,
+            vp_cm_r,
+        )?;
 
         layouter.constrain_instance(
             first_dynamic_vp_cm[0].cell(),
@@ -457,12 +890,13 @@ impl ValidityPredicateCircuit for TokenValidityPredicateCircuit {
 
     fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
         let mut public_inputs = self.get_mandatory_public_inputs();
-        let dynamic_vp = if self.owned_note_pub_id == self.output_notes[0].commitment().inner()
-            || self.owned_note_pub_id == self.output_notes[1].commitment().inner()
-        {
-            self.receiver_vp_vk
-        } else {
-            self.auth.vk
+        let owned_output_note = self
+            .output_notes
+            .iter()
+            .find(|note| note.commitment().inner() == self.owned_note_pub_id);
+        let dynamic_vp = match owned_output_note {
+            Some(note) if note.is_merkle_checked => self.receiver_vp_vk,
+            _ => self.auth.vk,
         };
 
         let vp_com_r = self.rseed.get_vp_cm_r(PRF_EXPAND_DYNAMIC_VP_1_CM_R);
@@ -484,45 +918,109 @@ impl ValidityPredicateCircuit for TokenValidityPredicateCircuit {
     fn get_owned_note_pub_id(&self) -> pallas::Base {
         self.owned_note_pub_id
     }
+
+    fn get_encryption_vp_vk(&self) -> Option<pallas::Base> {
+        let is_transferred_output = self.output_notes.iter().any(|note| {
+            note.commitment().inner() == self.owned_note_pub_id && note.is_merkle_checked
+        });
+        Some(if is_transferred_output {
+            self.receiver_vp_vk
+        } else {
+            pallas::Base::zero()
+        })
+    }
 }
 
 vp_circuit_impl!(TokenValidityPredicateCircuit);
 vp_verifying_info_impl!(TokenValidityPredicateCircuit);
 
 impl TokenAuthorization {
-    pub fn new(pk: pallas::Point, vk: pallas::Base) -> Self {
-        Self { pk, vk }
+    // The notes are encrypted to the pk, so the identity is rejected
+    pub fn new(pk: pallas::Point, vk: pallas::Base) -> Result<Self, EncodingError> {
+        if bool::from(pk.is_identity()) {
+            return Err(EncodingError::InvalidElement(0));
+        }
+        Ok(Self { pk, vk, rcv_pk: pk })
+    }
+
+    // Encrypt the notes to the rcv_pk instead of the pk, so the decryption key is not the auth key
+    pub fn with_rcv_pk(self, rcv_pk: pallas::Point) -> Result<Self, EncodingError> {
+        if bool::from(rcv_pk.is_identity()) {
+            return Err(EncodingError::InvalidElement(2));
+        }
+        Ok(Self { rcv_pk, ..self })
+    }
+
+    // The authorization of the notes sent to the address, see `Token::create_note_to_address`
+    pub fn from_address(addr: &PaymentAddress) -> Self {
+        Self {
+            pk: addr.get_auth_pk(),
+            vk: *COMPRESSED_TOKEN_AUTH_VK,
+            rcv_pk: addr.get_rcv_pk(),
+        }
     }
 
     pub fn random<R: RngCore>(mut rng: R) -> Self {
         Self {
             pk: pallas::Point::random(&mut rng),
             vk: *COMPRESSED_TOKEN_AUTH_VK,
+            rcv_pk: pallas::Point::random(&mut rng),
         }
     }
 
+    // Panics if the pk is the identity, see `try_to_app_data_dynamic`
     pub fn to_app_data_dynamic(&self) -> pallas::Base {
-        let pk_coord = self.pk.to_affine().coordinates().unwrap();
-        poseidon_hash_n::<4>([
-            *pk_coord.x(),
-            *pk_coord.y(),
-            self.vk,
-            *COMPRESSED_RECEIVER_VK,
-        ])
+        self.try_to_app_data_dynamic()
+            .expect("the auth pk is the identity")
+    }
+
+    pub fn try_to_app_data_dynamic(&self) -> Result<pallas::Base, EncodingError> {
+        TOKEN_AUTHORIZATION_SCHEMA.encode(&self.to_static_data_values())
+    }
+
+    pub fn to_static_data_values(&self) -> Vec<StaticDataValue> {
+        vec![
+            StaticDataValue::Point(self.pk),
+            StaticDataValue::Base(self.vk),
+            StaticDataValue::Base(*COMPRESSED_RECEIVER_VK),
+            StaticDataValue::Point(self.rcv_pk),
+        ]
     }
 
     pub fn from_sk_vk(sk: &pallas::Scalar, vk: &pallas::Base) -> Self {
         let generator = pallas::Point::generator().to_affine();
         let pk = generator * sk;
-        Self { pk, vk: *vk }
+        Self {
+            pk,
+            vk: *vk,
+            rcv_pk: pk,
+        }
+    }
+
+    // pk || vk || rcv_pk
+    pub fn to_bytes(&self) -> [u8; 96] {
+        let mut bytes = [0u8; 96];
+        bytes[..32].copy_from_slice(&point_to_bytes(&self.pk));
+        bytes[32..64].copy_from_slice(&self.vk.to_repr());
+        bytes[64..].copy_from_slice(&point_to_bytes(&self.rcv_pk));
+        bytes
+    }
+
+    // The pks must be canonical non-identity points and the vk a canonical field element
+    pub fn from_bytes(bytes: &[u8; 96]) -> Result<Self, EncodingError> {
+        let pk = non_identity_point_from_bytes(bytes[..32].try_into().unwrap())
+            .map_err(|_| EncodingError::InvalidElement(0))?;
+        let vk = Option::from(pallas::Base::from_repr(bytes[32..64].try_into().unwrap()))
+            .ok_or(EncodingError::InvalidElement(1))?;
+        let rcv_pk = non_identity_point_from_bytes(bytes[64..].try_into().unwrap())
+            .map_err(|_| EncodingError::InvalidElement(2))?;
+        Ok(Self { pk, vk, rcv_pk })
     }
 }
 
 #[test]
 fn test_halo2_token_vp_circuit() {
-    use crate::constant::VP_CIRCUIT_PARAMS_SIZE;
     use crate::note::tests::{random_input_note, random_output_note};
-    use halo2_proofs::dev::MockProver;
     use rand::rngs::OsRng;
 
     let mut rng = OsRng;
@@ -532,14 +1030,14 @@ fn test_halo2_token_vp_circuit() {
             .iter()
             .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()))
             .collect::<Vec<_>>();
-        let token_name = TokenName("Token_name".to_string());
+        let token_name = TokenName::new("Token_name").unwrap();
         let auth = TokenAuthorization::random(&mut rng);
-        input_notes[0].note_type.app_data_static = token_name.encode();
+        input_notes[0].note_type = NoteType::new(input_notes[0].get_app_vk(), token_name.encode());
         input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
         TokenValidityPredicateCircuit {
             owned_note_pub_id: input_notes[0].get_nf().unwrap().inner(),
-            input_notes,
-            output_notes: output_notes.try_into().unwrap(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes.try_into().unwrap()),
             token_name,
             auth,
             receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
@@ -547,13 +1045,431 @@ fn test_halo2_token_vp_circuit() {
         }
     };
 
-    let public_inputs = circuit.get_public_inputs(&mut rng);
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+}
+
+#[test]
+fn test_halo2_token_vp_circuit_mismatched_name() {
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let circuit = {
+        let mut input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+        let output_notes = input_notes
+            .iter()
+            .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()))
+            .collect::<Vec<_>>();
+        let token_name = TokenName::new("Token_name").unwrap();
+        let auth = TokenAuthorization::random(&mut rng);
+        input_notes[0].note_type = NoteType::new(input_notes[0].get_app_vk(), token_name.encode());
+        input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
+        TokenValidityPredicateCircuit {
+            owned_note_pub_id: input_notes[0].get_nf().unwrap().inner(),
+            input_notes: Arc::new(input_notes),
+            output_notes: Arc::new(output_notes.try_into().unwrap()),
+            token_name,
+            auth,
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+            rseed: RandomSeed::random(&mut rng),
+        }
+    };
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
 
-    let prover = MockProver::<pallas::Base>::run(
-        VP_CIRCUIT_PARAMS_SIZE,
-        &circuit,
-        vec![public_inputs.to_vec()],
-    )
-    .unwrap();
+    // The note looks like a valid token note, but of another token
+    let circuit = TokenValidityPredicateCircuit {
+        token_name: TokenName::new("Other_token_name").unwrap(),
+        ..circuit
+    };
+    assert!(circuit.dev_prove_and_check(&mut rng).is_err());
+}
+
+#[test]
+fn test_halo2_token_vp_commitment_opening() {
+    use crate::circuit::blake2s::{open_vp_commitment_gadget, Blake2sChip, Blake2sConfig};
+    use crate::note::tests::{random_input_note, random_output_note};
+    use halo2_proofs::{
+        dev::MockProver,
+        plonk::{Column, Instance},
+    };
+    use rand::rngs::OsRng;
+
+    // Prove the dynamic vp commitment published in another ptx opens to the vp
+    #[derive(Default)]
+    struct VPCommitmentOpeningCircuit {
+        vp: pallas::Base,
+        rcm: pallas::Base,
+    }
+
+    impl Circuit<pallas::Base> for VPCommitmentOpeningCircuit {
+        type Config = (Blake2sConfig<pallas::Base>, Column<Instance>);
+        type FloorPlanner = floor_planner::V1;
+
+        fn without_witnesses(&self) -> Self {
+            Self::default()
+        }
+
+        fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+            let advices = [(); 10].map(|_| meta.advice_column());
+            for advice in advices.iter() {
+                meta.enable_equality(*advice);
+            }
+            let instances = meta.instance_column();
+            meta.enable_equality(instances);
+            let constants = meta.fixed_column();
+            meta.enable_constant(constants);
+            (Blake2sConfig::configure(meta, advices), instances)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instances): Self::Config,
+            mut layouter: impl Layouter<pallas::Base>,
+        ) -> Result<(), Error> {
+            let vp = assign_free_advice(
+                layouter.namespace(|| "witness vp"),
+                config.advices[0],
+                Value::known(self.vp),
+            )?;
+            let rcm = assign_free_advice(
+                layouter.namespace(|| "witness rcm"),
+                config.advices[0],
+                Value::known(self.rcm),
+            )?;
+            let vp_cm = layouter.assign_region(
+                || "load vp commitment",
+                |mut region| {
+                    let vp_cm_1 = region.assign_advice_from_instance(
+                        || "vp_cm 1",
+                        instances,
+                        0,
+                        config.advices[0],
+                        0,
+                    )?;
+                    let vp_cm_2 = region.assign_advice_from_instance(
+                        || "vp_cm 2",
+                        instances,
+                        1,
+                        config.advices[0],
+                        1,
+                    )?;
+                    Ok([vp_cm_1, vp_cm_2])
+                },
+            )?;
+            let blake2s_chip = Blake2sChip::construct(config);
+            open_vp_commitment_gadget(&mut layouter, &blake2s_chip, vp, rcm, &vp_cm)
+        }
+    }
+
+    let mut rng = OsRng;
+    // The first ptx spends a token note, the auth vp is hidden behind the dynamic vp commitment
+    let mut input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+    let output_notes = input_notes
+        .iter()
+        .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()))
+        .collect::<Vec<_>>();
+    let token_name = TokenName::new("Token_name").unwrap();
+    let auth = TokenAuthorization::random(&mut rng);
+    input_notes[0].note_type = NoteType::new(input_notes[0].get_app_vk(), token_name.encode());
+    input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
+    let rseed = RandomSeed::random(&mut rng);
+    let circuit = TokenValidityPredicateCircuit {
+        owned_note_pub_id: input_notes[0].get_nf().unwrap().inner(),
+        input_notes: Arc::new(input_notes),
+        output_notes: Arc::new(output_notes.try_into().unwrap()),
+        token_name,
+        auth,
+        receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+        rseed,
+    };
+    assert_eq!(circuit.dev_prove_and_check(&mut rng), Ok(()));
+    let public_inputs = circuit.get_public_inputs(&mut rng).to_vec();
+    let vp_cm = ValidityPredicateCommitment::from_public_inputs(&[
+        public_inputs[VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1],
+        public_inputs[VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2],
+    ]);
+    assert!(!vp_cm.is_default());
+
+    // The second ptx reveals the auth vp behind the commitment
+    let rcm = rseed.get_vp_cm_r(PRF_EXPAND_DYNAMIC_VP_1_CM_R);
+    assert!(vp_cm.open(&auth.vk, &rcm));
+    let opening = VPCommitmentOpeningCircuit { vp: auth.vk, rcm };
+    let instances: [pallas::Base; 2] = vp_cm.to_public_inputs();
+    let prover = MockProver::run(14, &opening, vec![instances.to_vec()]).unwrap();
     assert_eq!(prover.verify(), Ok(()));
+
+    // The opening fails with wrong randomness
+    let wrong_rcm = pallas::Base::random(&mut rng);
+    assert!(!vp_cm.open(&auth.vk, &wrong_rcm));
+    let opening = VPCommitmentOpeningCircuit {
+        vp: auth.vk,
+        rcm: wrong_rcm,
+    };
+    let prover = MockProver::run(14, &opening, vec![instances.to_vec()]).unwrap();
+    assert!(prover.verify().is_err());
+}
+
+#[test]
+fn test_halo2_token_vp_dynamic_vp_commitment() {
+    use crate::note::tests::{random_input_note, random_output_note};
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let mut input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+    let output_notes = input_notes
+        .iter()
+        .map(|input| random_output_note(&mut rng, input.get_nf().unwrap()))
+        .collect::<Vec<_>>();
+    let token_name = TokenName::new("Token_name").unwrap();
+    let auth = TokenAuthorization::from_sk_vk(
+        &pallas::Scalar::random(&mut rng),
+        &COMPRESSED_TOKEN_AUTH_VK,
+    );
+    input_notes[0].note_type = NoteType::new(input_notes[0].get_app_vk(), token_name.encode());
+    input_notes[0].app_data_dynamic = auth.to_app_data_dynamic();
+    let rseed = RandomSeed::random(&mut rng);
+    let circuit = TokenValidityPredicateCircuit {
+        owned_note_pub_id: input_notes[0].get_nf().unwrap().inner(),
+        input_notes: Arc::new(input_notes),
+        output_notes: Arc::new(output_notes.try_into().unwrap()),
+        token_name,
+        auth,
+        receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+        rseed,
+    };
+    let public_inputs = circuit.get_public_inputs(&mut rng);
+    let rcm = rseed.get_vp_cm_r(PRF_EXPAND_DYNAMIC_VP_1_CM_R);
+
+    // Publish the first dynamic vp commitment of vk and rcm
+    let check_with_vp_cm = |vk: pallas::Base, rcm: pallas::Base| {
+        let vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::commit(&vk, &rcm).to_public_inputs();
+        let mut inputs = public_inputs.to_vec();
+        inputs[VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1] = vp_cm[0];
+        inputs[VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2] = vp_cm[1];
+        let inputs: ValidityPredicatePublicInputs = inputs.into();
+        circuit.dev_prove_and_check_with_public_inputs(&inputs)
+    };
+
+    // The spent note commits to the token auth vp
+    assert_eq!(check_with_vp_cm(*COMPRESSED_TOKEN_AUTH_VK, rcm), Ok(()));
+    // Another vk
+    assert!(check_with_vp_cm(*COMPRESSED_RECEIVER_VK, rcm).is_err());
+    // Wrong randomness
+    assert!(check_with_vp_cm(*COMPRESSED_TOKEN_AUTH_VK, pallas::Base::random(&mut rng)).is_err());
+}
+
+#[test]
+fn test_token_authorization_schema() {
+    use rand::rngs::OsRng;
+
+    let auth = TokenAuthorization::random(OsRng);
+    // The rcv pk follows the fields of the previous encoding
+    let pk_coord = auth.pk.to_affine().coordinates().unwrap();
+    let rcv_pk_coord = auth.rcv_pk.to_affine().coordinates().unwrap();
+    let expected = poseidon_hash_n::<7>([
+        APP_DATA_DYNAMIC_DOMAIN,
+        *pk_coord.x(),
+        *pk_coord.y(),
+        auth.vk,
+        *COMPRESSED_RECEIVER_VK,
+        *rcv_pk_coord.x(),
+        *rcv_pk_coord.y(),
+    ]);
+    assert_eq!(auth.to_app_data_dynamic(), expected);
+
+    let values = auth.to_static_data_values();
+    let elements = TOKEN_AUTHORIZATION_SCHEMA
+        .to_field_elements(&values)
+        .unwrap();
+    assert_eq!(
+        TOKEN_AUTHORIZATION_SCHEMA.from_field_elements(&elements),
+        Ok(values)
+    );
+}
+
+#[test]
+fn test_token_authorization_rejects_identity() {
+    let identity = pallas::Point::identity();
+    assert_eq!(
+        TokenAuthorization::new(identity, *COMPRESSED_TOKEN_AUTH_VK).unwrap_err(),
+        EncodingError::InvalidElement(0)
+    );
+    let auth = TokenAuthorization {
+        pk: identity,
+        vk: *COMPRESSED_TOKEN_AUTH_VK,
+        rcv_pk: pallas::Point::generator(),
+    };
+    assert!(auth.try_to_app_data_dynamic().is_err());
+
+    let pk = pallas::Point::generator();
+    let auth = TokenAuthorization::new(pk, *COMPRESSED_TOKEN_AUTH_VK).unwrap();
+    assert_eq!(
+        auth.with_rcv_pk(identity).unwrap_err(),
+        EncodingError::InvalidElement(2)
+    );
+    assert_eq!(
+        auth.try_to_app_data_dynamic(),
+        Ok(auth.to_app_data_dynamic())
+    );
+}
+
+#[test]
+fn test_token_authorization_encoding() {
+    use rand::rngs::OsRng;
+
+    let auth = TokenAuthorization::random(OsRng);
+    let bytes = auth.to_bytes();
+    let decoded = TokenAuthorization::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.pk, auth.pk);
+    assert_eq!(decoded.vk, auth.vk);
+    assert_eq!(decoded.rcv_pk, auth.rcv_pk);
+
+    // The identity pk, with and without the sign bit
+    let mut identity_pk = bytes;
+    identity_pk[..32].copy_from_slice(&[0u8; 32]);
+    assert_eq!(
+        TokenAuthorization::from_bytes(&identity_pk).unwrap_err(),
+        EncodingError::InvalidElement(0)
+    );
+    identity_pk[31] = 0x80;
+    assert_eq!(
+        TokenAuthorization::from_bytes(&identity_pk).unwrap_err(),
+        EncodingError::InvalidElement(0)
+    );
+    // The pk x-coordinate is not reduced
+    let mut invalid_pk = bytes;
+    invalid_pk[..32].copy_from_slice(&[0xff; 32]);
+    assert_eq!(
+        TokenAuthorization::from_bytes(&invalid_pk).unwrap_err(),
+        EncodingError::InvalidElement(0)
+    );
+    // The vk is not reduced
+    let mut invalid_vk = bytes;
+    invalid_vk[32..64].copy_from_slice(&[0xff; 32]);
+    assert_eq!(
+        TokenAuthorization::from_bytes(&invalid_vk).unwrap_err(),
+        EncodingError::InvalidElement(1)
+    );
+    // The identity rcv pk
+    let mut identity_rcv_pk = bytes;
+    identity_rcv_pk[64..].copy_from_slice(&[0u8; 32]);
+    assert_eq!(
+        TokenAuthorization::from_bytes(&identity_rcv_pk).unwrap_err(),
+        EncodingError::InvalidElement(2)
+    );
+}
+
+#[test]
+fn test_token_note_rotate_auth() {
+    use rand::rngs::OsRng;
+
+    let token = Token::new(TokenName::new("xan").unwrap(), 10);
+    let old_auth = TokenAuthorization::random(OsRng);
+    let new_auth = TokenAuthorization::random(OsRng);
+    let rho = Nullifier::from(pallas::Base::random(OsRng));
+    let nk = NullifierKeyContainer::random_key(OsRng);
+    let token_note = token.create_random_token_note(OsRng, rho, nk, &old_auth);
+
+    let rotated = token_note.rotate_auth(OsRng, &new_auth);
+    assert_eq!(rotated.token_name(), token_note.token_name());
+    assert_eq!(rotated.note_type, token_note.note_type);
+    assert_eq!(rotated.value, token_note.value);
+    assert_eq!(rotated.get_nk_commitment(), token_note.get_nk_commitment());
+    assert_eq!(rotated.rho, token_note.get_nf().unwrap());
+    assert_eq!(rotated.app_data_dynamic, new_auth.to_app_data_dynamic());
+    assert_ne!(rotated.commitment(), token_note.commitment());
+}
+
+#[test]
+fn test_token_name_case_folding() {
+    let btc = TokenName::new("btc").unwrap();
+    let upper_btc = TokenName::new("BTC").unwrap();
+    assert_eq!(upper_btc, btc);
+    assert_eq!(upper_btc.inner(), "btc");
+    assert_eq!(
+        Token::new(upper_btc, 1).value_base(),
+        Token::new(btc, 1).value_base()
+    );
+}
+
+#[test]
+fn test_token_name_charset() {
+    assert!(TokenName::new("usdc.e_v-2").is_ok());
+    assert_eq!(TokenName::new(""), Err(TokenNameError::Empty));
+    // The default name is valid too
+    let default_name = TokenName::default();
+    assert_eq!(TokenName::new(&default_name.inner()), Ok(default_name));
+    assert!(TokenName::new(&"a".repeat(TOKEN_NAME_MAX_LEN)).is_ok());
+    assert_eq!(
+        TokenName::new(&"a".repeat(TOKEN_NAME_MAX_LEN + 1)),
+        Err(TokenNameError::TooLong(TOKEN_NAME_MAX_LEN + 1))
+    );
+    assert_eq!(
+        TokenName::new("bt c"),
+        Err(TokenNameError::InvalidCharacter(' '))
+    );
+    // No unicode, even the ones that look like ASCII
+    assert_eq!(
+        TokenName::new("bt\u{0441}"),
+        Err(TokenNameError::InvalidCharacter('\u{0441}'))
+    );
+    assert_eq!(
+        TokenName::new("\u{FF22}tc"),
+        Err(TokenNameError::InvalidCharacter('\u{FF22}'))
+    );
+}
+
+#[test]
+fn test_token_name_element_vectors() {
+    // The little-endian repr of the name elements
+    let vectors = [
+        (
+            "btc",
+            "ad669975f46482a264b124a16c852b1b6a5b2d4a5e5946e26fa3616eb8e0de2d",
+        ),
+        (
+            "XAN",
+            "fd11672daceaf5f0f96a8786e1b639d5809eba7f247ab6df3a4cd95904db6c0b",
+        ),
+        (
+            "token_1.v2",
+            "620a976e9f16568d6a5b6da1d30c20a94c7a0d6ba9bafe7bf8d982acbbc1532f",
+        ),
+    ];
+    for (name, expected) in vectors {
+        let token_name = TokenName::new(name).unwrap();
+        let repr: String = token_name
+            .name_element()
+            .to_repr()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(repr, expected);
+        // Not issuable, the name element is the encoding
+        assert_eq!(token_name.encode(), token_name.name_element());
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use super::{Token, TokenAuthorization, TokenName};
+    use crate::utils::testing::{arb_base, arb_scalar};
+    use halo2_proofs::arithmetic::Field;
+    use proptest::prelude::*;
+
+    pub fn arb_token() -> impl Strategy<Value = Token> {
+        ("[a-z]{1,16}", any::<u64>())
+            .prop_map(|(name, value)| Token::new(TokenName::new(&name).unwrap(), value))
+    }
+
+    pub fn arb_token_authorization() -> impl Strategy<Value = TokenAuthorization> {
+        (
+            arb_scalar().prop_filter("the auth pk is the identity", |sk| {
+                !bool::from(sk.is_zero())
+            }),
+            arb_base(),
+        )
+            .prop_map(|(sk, vk)| TokenAuthorization::from_sk_vk(&sk, &vk))
+    }
 }
@@ -0,0 +1,463 @@
+use crate::{
+    circuit::{
+        blake2s::publicize_default_dynamic_vp_commitments,
+        gadgets::{
+            assign_free_advice, assign_free_constant, poseidon_hash::poseidon_hash_gadget,
+            target_note_variable::get_owned_note_variable,
+        },
+        vp_circuit::{
+            BasicValidityPredicateVariables, VPVerifyingInfo, ValidityPredicateCircuit,
+            ValidityPredicateConfig, ValidityPredicatePublicInputs, ValidityPredicateVerifyingInfo,
+        },
+        vp_examples::{
+            receiver_vp::COMPRESSED_RECEIVER_VK,
+            signature_verification::{
+                schnorr_challenge_gadget, signing_message, signing_message_gadget,
+                SchnorrSignature, ISSUANCE_SIGHASH_MESSAGE_LEN, SIGNED_ANCHORS_PUBLIC_INPUT_IDX,
+            },
+            token::TokenName,
+        },
+    },
+    constant::{TaigaFixedBasesFull, APP_DATA_DYNAMIC_DOMAIN, NUM_NOTE, TOKEN_ISSUANCE_DOMAIN},
+    keys::SigningKey,
+    merkle_tree::Anchor,
+    note::{Note, RandomSeed},
+    proof::Proof,
+    vp_commitment::ValidityPredicateCommitment,
+    vp_vk::ValidityPredicateVerifyingKey,
+};
+use halo2_gadgets::ecc::{chip::EccChip, FixedPoint, NonIdentityPoint, ScalarFixed};
+use halo2_proofs::{
+    arithmetic::Field,
+    circuit::{floor_planner, Layouter, Value},
+    plonk::{keygen_pk, keygen_vk, Circuit, ConstraintSystem, Error},
+};
+use lazy_static::lazy_static;
+use pasta_curves::{
+    arithmetic::CurveAffine,
+    group::{Curve, Group},
+    pallas,
+};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::sync::Arc;
+
+lazy_static! {
+    pub static ref TOKEN_ISSUANCE_VK: ValidityPredicateVerifyingKey =
+        TokenIssuanceValidityPredicateCircuit::default().get_vp_vk();
+    pub static ref COMPRESSED_TOKEN_ISSUANCE_VK: pallas::Base = TOKEN_ISSUANCE_VK.get_compressed();
+}
+
+// TokenIssuanceValidityPredicateCircuit authorizes the ephemeral issuance notes of an issuable token.
+// Spending an issuance note mints the token and creating one burns the token. The issuer signs
+// the sighash of the ptx(see `signing_message`) followed by the issuance vp, the token and the
+// amount.
+#[derive(Clone, Debug)]
+pub struct TokenIssuanceValidityPredicateCircuit {
+    pub owned_note_pub_id: pallas::Base,
+    // The anchors of the input notes, they're signed and publicized as the custom public inputs
+    pub anchors: [Anchor; NUM_NOTE],
+    pub input_notes: Arc<[Note; NUM_NOTE]>,
+    pub output_notes: Arc<[Note; NUM_NOTE]>,
+    pub vp_vk: pallas::Base,
+    pub token_name: TokenName,
+    pub signature: SchnorrSignature,
+    pub receiver_vp_vk: pallas::Base,
+}
+
+impl Default for TokenIssuanceValidityPredicateCircuit {
+    fn default() -> Self {
+        Self {
+            owned_note_pub_id: pallas::Base::zero(),
+            anchors: [Anchor::default(); NUM_NOTE],
+            input_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            output_notes: Arc::new([(); NUM_NOTE].map(|_| Note::default())),
+            vp_vk: pallas::Base::zero(),
+            token_name: TokenName::new_issuable("Token_name", pallas::Point::generator()).unwrap(),
+            signature: SchnorrSignature::default(),
+            receiver_vp_vk: pallas::Base::zero(),
+        }
+    }
+}
+
+impl TokenIssuanceValidityPredicateCircuit {
+    pub fn from_sk_and_sign<R: RngCore>(
+        mut rng: R,
+        owned_note_pub_id: pallas::Base,
+        anchors: [Anchor; NUM_NOTE],
+        input_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        output_notes: impl Into<Arc<[Note; NUM_NOTE]>>,
+        token_name: TokenName,
+        issuer_sk: &SigningKey,
+    ) -> Self {
+        let input_notes: Arc<[Note; NUM_NOTE]> = input_notes.into();
+        let output_notes: Arc<[Note; NUM_NOTE]> = output_notes.into();
+        let message = Self::signing_message(
+            *COMPRESSED_TOKEN_ISSUANCE_VK,
+            &anchors,
+            owned_note_pub_id,
+            &input_notes,
+            &output_notes,
+        );
+        let signature = SchnorrSignature::sign(&mut rng, issuer_sk, message);
+        Self {
+            owned_note_pub_id,
+            anchors,
+            input_notes,
+            output_notes,
+            vp_vk: *COMPRESSED_TOKEN_ISSUANCE_VK,
+            token_name,
+            signature,
+            receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+        }
+    }
+
+    // The message signed: the sighash of the transfer signatures, then TOKEN_ISSUANCE_DOMAIN, the
+    // issuance vp vk, the token(app_data_static) and the amount
+    pub fn signing_message(
+        vp_vk: pallas::Base,
+        anchors: &[Anchor; NUM_NOTE],
+        owned_note_pub_id: pallas::Base,
+        input_notes: &[Note; NUM_NOTE],
+        output_notes: &[Note; NUM_NOTE],
+    ) -> Vec<pallas::Base> {
+        let owned_note = input_notes
+            .iter()
+            .find(|note| note.get_nf().unwrap().inner() == owned_note_pub_id)
+            .or_else(|| {
+                output_notes
+                    .iter()
+                    .find(|note| note.commitment().inner() == owned_note_pub_id)
+            })
+            .expect("the owned note is not found");
+        let mut message = signing_message(anchors, owned_note_pub_id, input_notes, output_notes);
+        message.extend([
+            TOKEN_ISSUANCE_DOMAIN,
+            vp_vk,
+            owned_note.note_type.app_data_static,
+            pallas::Base::from(owned_note.value),
+        ]);
+        assert_eq!(message.len(), ISSUANCE_SIGHASH_MESSAGE_LEN);
+        message
+    }
+}
+
+impl ValidityPredicateCircuit for TokenIssuanceValidityPredicateCircuit {
+    // Add custom constraints
+    fn custom_constraints(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<pallas::Base>,
+        basic_variables: BasicValidityPredicateVariables,
+    ) -> Result<(), Error> {
+        // Construct an ECC chip
+        let ecc_chip = EccChip::construct(config.ecc_config.clone());
+
+        let issuer_pk = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness issuer pk"),
+            Value::known(self.signature.pk.to_affine()),
+        )?;
+
+        let owned_note_pub_id = basic_variables.get_owned_note_pub_id();
+
+        // Check the issuer is committed to the app_data_static(token name)
+        let app_data_static = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get owned note app_data_static"),
+            &owned_note_pub_id,
+            &basic_variables.get_app_data_static_searchable_pairs(),
+        )?;
+        let name_element = assign_free_advice(
+            layouter.namespace(|| "witness token name element"),
+            config.advices[0],
+            Value::known(self.token_name.name_element()),
+        )?;
+        let issuance_domain = assign_free_constant(
+            layouter.namespace(|| "token issuance domain"),
+            config.advices[0],
+            TOKEN_ISSUANCE_DOMAIN,
+        )?;
+        let encoded_app_data_static = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "app_data_static encoding"),
+            [
+                issuance_domain.clone(),
+                name_element,
+                issuer_pk.inner().x(),
+                issuer_pk.inner().y(),
+            ],
+        )?;
+        layouter.assign_region(
+            || "check app_data_static encoding",
+            |mut region| {
+                region.constrain_equal(encoded_app_data_static.cell(), app_data_static.cell())
+            },
+        )?;
+
+        // Check the issuer is the authorization key in app_data_dynamic
+        let app_data_dynamic = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get owned note app_data_dynamic"),
+            &owned_note_pub_id,
+            &basic_variables.get_app_data_dynamic_searchable_pairs(),
+        )?;
+        let issuance_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness issuance vp vk"),
+            config.advices[0],
+            Value::known(self.vp_vk),
+        )?;
+        let receiver_vp_vk = assign_free_advice(
+            layouter.namespace(|| "witness receiver vp vk"),
+            config.advices[0],
+            Value::known(self.receiver_vp_vk),
+        )?;
+        let app_data_dynamic_domain = assign_free_constant(
+            layouter.namespace(|| "app_data_dynamic domain"),
+            config.advices[0],
+            APP_DATA_DYNAMIC_DOMAIN,
+        )?;
+        let encoded_app_data_dynamic = poseidon_hash_gadget(
+            config.poseidon_config.clone(),
+            layouter.namespace(|| "app_data_dynamic encoding"),
+            [
+                app_data_dynamic_domain,
+                issuer_pk.inner().x(),
+                issuer_pk.inner().y(),
+                issuance_vp_vk.clone(),
+                receiver_vp_vk,
+                issuer_pk.inner().x(),
+                issuer_pk.inner().y(),
+            ],
+        )?;
+        layouter.assign_region(
+            || "check app_data_dynamic encoding",
+            |mut region| {
+                region.constrain_equal(encoded_app_data_dynamic.cell(), app_data_dynamic.cell())
+            },
+        )?;
+
+        // Get the minted or burned amount
+        let value = get_owned_note_variable(
+            config.get_owned_note_variable_config,
+            layouter.namespace(|| "get owned note value"),
+            &owned_note_pub_id,
+            &basic_variables.get_value_searchable_pairs(),
+        )?;
+
+        let r = NonIdentityPoint::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness r"),
+            Value::known(self.signature.r.to_affine()),
+        )?;
+        let s_scalar = ScalarFixed::new(
+            ecc_chip.clone(),
+            layouter.namespace(|| "witness s"),
+            Value::known(self.signature.s),
+        )?;
+
+        // Verify: s*G = R + Hash(r||P||m)*P
+        // s*G
+        let generator =
+            FixedPoint::from_inner(ecc_chip.clone(), TaigaFixedBasesFull::BaseGenerator);
+        let (s_g, _) = generator.mul(layouter.namespace(|| "s_scalar * generator"), &s_scalar)?;
+
+        // Hash(r||P||m)
+        let mut message = signing_message_gadget(
+            &config,
+            layouter.namespace(|| "signing message"),
+            &basic_variables,
+            &self.anchors,
+        )?;
+        message.extend([issuance_domain, issuance_vp_vk, app_data_static, value]);
+        let h_scalar = schnorr_challenge_gadget(
+            &config,
+            layouter.namespace(|| "schnorr challenge"),
+            &r,
+            &issuer_pk,
+            &message,
+        )?;
+
+        // Hash(r||P||m)*P
+        let (h_p, _) = issuer_pk.mul(layouter.namespace(|| "hP"), h_scalar)?;
+
+        // R + Hash(r||P||m)*P
+        let rhs = r.add(layouter.namespace(|| "R + Hash(r||P||m)*P"), &h_p)?;
+
+        s_g.constrain_equal(layouter.namespace(|| "s*G = R + Hash(r||P||m)*P"), &rhs)?;
+
+        // Publicize the dynamic vp commitments with default value
+        publicize_default_dynamic_vp_commitments(
+            &mut layouter,
+            config.advices[0],
+            config.instances,
+        )?;
+
+        Ok(())
+    }
+
+    fn get_input_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.input_notes
+    }
+
+    fn get_output_notes(&self) -> &[Note; NUM_NOTE] {
+        &self.output_notes
+    }
+
+    fn get_public_inputs(&self, mut rng: impl RngCore) -> ValidityPredicatePublicInputs {
+        let mut public_inputs = self.get_mandatory_public_inputs();
+        let default_vp_cm: [pallas::Base; 2] =
+            ValidityPredicateCommitment::default().to_public_inputs();
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(default_vp_cm);
+        public_inputs.extend(self.anchors.map(|anchor| anchor.inner()));
+        let padding = ValidityPredicatePublicInputs::get_public_input_padding(
+            public_inputs.len(),
+            &RandomSeed::random(&mut rng),
+        );
+        public_inputs.extend(padding);
+        public_inputs.into()
+    }
+
+    fn get_owned_note_pub_id(&self) -> pallas::Base {
+        self.owned_note_pub_id
+    }
+
+    fn get_signed_anchors_public_input_idx(&self) -> Option<usize> {
+        Some(SIGNED_ANCHORS_PUBLIC_INPUT_IDX)
+    }
+}
+
+vp_circuit_impl!(TokenIssuanceValidityPredicateCircuit);
+vp_verifying_info_impl!(TokenIssuanceValidityPredicateCircuit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::vp_examples::token::Token;
+    use crate::note::tests::{random_input_note, random_output_note};
+    use crate::nullifier::{Nullifier, NullifierKeyContainer};
+    use rand::rngs::OsRng;
+
+    // Mint: spend an issuance note of the token
+    fn mint_circuit(
+        sign_sk: &SigningKey,
+        issuer_pk: pallas::Point,
+    ) -> TokenIssuanceValidityPredicateCircuit {
+        let mut rng = OsRng;
+        let token = Token::new(TokenName::new_issuable("btc", issuer_pk).unwrap(), 100);
+        let rho = Nullifier::from(pallas::Base::random(&mut rng));
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let issuance_note = token.create_issuance_note(&mut rng, rho, nk);
+        let input_notes = [*issuance_note.note(), random_input_note(&mut rng)];
+        let output_notes =
+            input_notes.map(|input| random_output_note(&mut rng, input.get_nf().unwrap()));
+        let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+        TokenIssuanceValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            input_notes[0].get_nf().unwrap().inner(),
+            anchors,
+            input_notes,
+            output_notes,
+            token.name().clone(),
+            sign_sk,
+        )
+    }
+
+    fn check(circuit: &TokenIssuanceValidityPredicateCircuit) -> bool {
+        circuit.dev_prove_and_check(OsRng).is_ok()
+    }
+
+    #[test]
+    fn test_token_issuance_vp_mint() {
+        let issuer_sk = SigningKey::random(OsRng);
+        let issuer_pk = issuer_sk.public_key();
+        assert!(check(&mint_circuit(&issuer_sk, issuer_pk)));
+    }
+
+    #[test]
+    fn test_token_issuance_vp_mint_with_wrong_key() {
+        let issuer_sk = SigningKey::random(OsRng);
+        let issuer_pk = issuer_sk.public_key();
+        let wrong_sk = SigningKey::random(OsRng);
+        assert!(!check(&mint_circuit(&wrong_sk, issuer_pk)));
+    }
+
+    #[test]
+    fn test_token_issuance_vp_signed_anchors() {
+        let issuer_sk = SigningKey::random(OsRng);
+        let issuer_pk = issuer_sk.public_key();
+        let mut circuit = mint_circuit(&issuer_sk, issuer_pk);
+        assert_eq!(
+            circuit.get_public_inputs(OsRng).inner()[SIGNED_ANCHORS_PUBLIC_INPUT_IDX..][..NUM_NOTE],
+            circuit.anchors.map(|anchor| anchor.inner())
+        );
+
+        // The signature doesn't cover other anchors
+        circuit.anchors[1] = Anchor::from(pallas::Base::random(OsRng));
+        assert!(!check(&circuit));
+    }
+
+    #[test]
+    fn test_token_issuance_sighash_domain() {
+        let mut rng = OsRng;
+        let issuer_sk = SigningKey::random(&mut rng);
+        let circuit = mint_circuit(&issuer_sk, issuer_sk.public_key());
+        // The issuance message extends the transfer sighash, a transfer signature of the issuer
+        // over the same notes doesn't verify
+        let transfer_message = signing_message(
+            &circuit.anchors,
+            circuit.owned_note_pub_id,
+            &circuit.input_notes,
+            &circuit.output_notes,
+        );
+        let issuance_message = TokenIssuanceValidityPredicateCircuit::signing_message(
+            *COMPRESSED_TOKEN_ISSUANCE_VK,
+            &circuit.anchors,
+            circuit.owned_note_pub_id,
+            &circuit.input_notes,
+            &circuit.output_notes,
+        );
+        assert_eq!(issuance_message[..transfer_message.len()], transfer_message);
+        assert_eq!(
+            issuance_message[transfer_message.len()],
+            TOKEN_ISSUANCE_DOMAIN
+        );
+
+        let circuit = TokenIssuanceValidityPredicateCircuit {
+            signature: SchnorrSignature::sign(&mut rng, &issuer_sk, transfer_message),
+            ..circuit
+        };
+        assert!(!check(&circuit));
+    }
+
+    #[test]
+    fn test_token_issuance_vp_burn() {
+        let mut rng = OsRng;
+        let issuer_sk = SigningKey::random(&mut rng);
+        let issuer_pk = issuer_sk.public_key();
+        let token = Token::new(TokenName::new_issuable("btc", issuer_pk).unwrap(), 100);
+        let input_notes = [(); NUM_NOTE].map(|_| random_input_note(&mut rng));
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let burn_note = token.create_issuance_note(
+            &mut rng,
+            input_notes[0].get_nf().unwrap(),
+            nk.to_commitment(),
+        );
+        let output_notes = [
+            *burn_note.note(),
+            random_output_note(&mut rng, input_notes[1].get_nf().unwrap()),
+        ];
+        let anchors = [(); NUM_NOTE].map(|_| Anchor::from(pallas::Base::random(&mut rng)));
+        let circuit = TokenIssuanceValidityPredicateCircuit::from_sk_and_sign(
+            &mut rng,
+            burn_note.note().commitment().inner(),
+            anchors,
+            input_notes,
+            output_notes,
+            token.name().clone(),
+            &issuer_sk,
+        );
+        assert!(check(&circuit));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::circuit::action_circuit::ActionCircuit;
+use crate::circuit::action_circuit::{ActionCircuit, AggregatedActionCircuit};
 use crate::utils::to_field_elements;
 use group::Group;
 use halo2_gadgets::{
@@ -9,23 +9,47 @@ use halo2_gadgets::{
         },
         FixedPoints,
     },
-    sinsemilla::{primitives::CommitDomain, CommitDomains, HashDomains},
+    sinsemilla::{
+        primitives::{CommitDomain, HashDomain},
+        CommitDomains, HashDomains,
+    },
 };
+#[cfg(feature = "prover")]
+use halo2_proofs::plonk::{keygen_pk, ProvingKey};
 use halo2_proofs::{
-    plonk::{keygen_pk, keygen_vk, ProvingKey, VerifyingKey},
+    plonk::{keygen_vk, VerifyingKey},
     poly::commitment::Params,
 };
 use lazy_static::lazy_static;
 use pasta_curves::{group::Curve, pallas, vesta};
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 /// SWU hash-to-curve personalization for the note commitment generator
 pub const NOTE_COMMITMENT_PERSONALIZATION: &str = "Taiga-NoteCommit";
 
+/// Sinsemilla personalization of the commitment tree hash, see SinsemillaMerkleHash
+pub const MERKLE_CRH_PERSONALIZATION: &str = "Taiga-MerkleCRH";
+
 pub const TRANSACTION_BINDING_HASH_PERSONALIZATION: &[u8; 16] = b"TxBindingSigHash";
 
+pub const TRANSACTION_ID_PERSONALIZATION: &[u8; 8] = b"TaigaTxI";
+
+pub const PTX_CONTENT_HASH_PERSONALIZATION: &[u8; 16] = b"TaigaPtxContents";
+
 pub const VP_COMMITMENT_PERSONALIZATION: &[u8; 8] = b"VPCommit";
 
+pub const VP_SOURCE_COMMITMENT_PERSONALIZATION: &[u8; 8] = b"VPSource";
+
+pub const VP_ATTESTATION_PERSONALIZATION: &[u8; 8] = b"VPAttest";
+
+pub const PADDING_SEED_COMMITMENT_PERSONALIZATION: &[u8; 8] = b"PadSdCom";
+
+pub const PADDING_SEED_PERSONALIZATION: &[u8; 8] = b"PadSeed_";
+
+/// Blake2s personalization of the token name element, see `TokenName::name_element`
+pub const TOKEN_NAME_PERSONALIZATION: &[u8; 8] = b"TaigaTkN";
+
 pub const PRF_EXPAND_PERSONALIZATION: &[u8; 16] = b"Taiga_ExpandSeed";
 pub const PRF_EXPAND_PSI: u8 = 0;
 pub const PRF_EXPAND_RCM: u8 = 1;
@@ -35,6 +59,71 @@ pub const PRF_EXPAND_INPUT_VP_CM_R: u8 = 4;
 pub const PRF_EXPAND_OUTPUT_VP_CM_R: u8 = 5;
 pub const PRF_EXPAND_DYNAMIC_VP_1_CM_R: u8 = 6;
 pub const PRF_EXPAND_DYNAMIC_VP_2_CM_R: u8 = 7;
+pub const PRF_EXPAND_PADDING_INPUT_NOTE: u8 = 8;
+pub const PRF_EXPAND_PADDING_OUTPUT_NOTE: u8 = 9;
+pub const PRF_EXPAND_VP_PADDING_SEED: u8 = 10;
+pub const PRF_EXPAND_AUTH_SK: u8 = 11;
+pub const PRF_EXPAND_NK: u8 = 12;
+pub const PRF_EXPAND_RCV_SK: u8 = 13;
+
+/// Poseidon personalizations. The derived domain separator is prepended to the
+/// message of the corresponding poseidon hash, so that the different uses of
+/// poseidon in the protocol can't collide with each other.
+pub const NK_COMMITMENT_POSEIDON_PERSONALIZATION: &[u8; 14] = b"Taiga-NkCommit";
+pub const NOTE_COMMITMENT_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-NoteCommit";
+pub const APP_DATA_DYNAMIC_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-AppDataDyn";
+pub const NOTE_CIPHERTEXT_COMMITMENT_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-CipherComm";
+pub const SCHNORR_SIGHASH_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-SigHash-V1";
+pub const SWAP_INTENT_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-SwapIntent";
+pub const DUTCH_AUCTION_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-DutchAuctn";
+pub const NOTE_ENCRYPTION_KEY_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-NoteEncKey";
+pub const INTENT_ADVERT_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-IntentAdvt";
+pub const SPENDING_CAP_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-SpendngCap";
+pub const TOKEN_BRIDGE_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-TokenBrdge";
+pub const TIMELOCK_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-TimeLocked";
+pub const ESCROW_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-EscrowTerm";
+pub const MULTISIG_KEYS_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-MultisigKs";
+pub const TOKEN_ISSUANCE_POSEIDON_PERSONALIZATION: &[u8; 16] = b"Taiga-TokenIssue";
+
+pub const NK_COMMITMENT_DOMAIN: pallas::Base =
+    poseidon_domain(NK_COMMITMENT_POSEIDON_PERSONALIZATION);
+pub const NOTE_COMMITMENT_POSEIDON_DOMAIN: pallas::Base =
+    poseidon_domain(NOTE_COMMITMENT_POSEIDON_PERSONALIZATION);
+pub const APP_DATA_DYNAMIC_DOMAIN: pallas::Base =
+    poseidon_domain(APP_DATA_DYNAMIC_POSEIDON_PERSONALIZATION);
+pub const NOTE_CIPHERTEXT_COMMITMENT_DOMAIN: pallas::Base =
+    poseidon_domain(NOTE_CIPHERTEXT_COMMITMENT_POSEIDON_PERSONALIZATION);
+pub const SCHNORR_SIGHASH_DOMAIN: pallas::Base =
+    poseidon_domain(SCHNORR_SIGHASH_POSEIDON_PERSONALIZATION);
+pub const SWAP_INTENT_DOMAIN: pallas::Base = poseidon_domain(SWAP_INTENT_POSEIDON_PERSONALIZATION);
+pub const DUTCH_AUCTION_DOMAIN: pallas::Base =
+    poseidon_domain(DUTCH_AUCTION_POSEIDON_PERSONALIZATION);
+pub const NOTE_ENCRYPTION_KEY_DOMAIN: pallas::Base =
+    poseidon_domain(NOTE_ENCRYPTION_KEY_POSEIDON_PERSONALIZATION);
+pub const INTENT_ADVERT_DOMAIN: pallas::Base =
+    poseidon_domain(INTENT_ADVERT_POSEIDON_PERSONALIZATION);
+pub const SPENDING_CAP_DOMAIN: pallas::Base =
+    poseidon_domain(SPENDING_CAP_POSEIDON_PERSONALIZATION);
+pub const TOKEN_BRIDGE_DOMAIN: pallas::Base =
+    poseidon_domain(TOKEN_BRIDGE_POSEIDON_PERSONALIZATION);
+pub const TIMELOCK_DOMAIN: pallas::Base = poseidon_domain(TIMELOCK_POSEIDON_PERSONALIZATION);
+pub const ESCROW_DOMAIN: pallas::Base = poseidon_domain(ESCROW_POSEIDON_PERSONALIZATION);
+pub const MULTISIG_KEYS_DOMAIN: pallas::Base =
+    poseidon_domain(MULTISIG_KEYS_POSEIDON_PERSONALIZATION);
+pub const TOKEN_ISSUANCE_DOMAIN: pallas::Base =
+    poseidon_domain(TOKEN_ISSUANCE_POSEIDON_PERSONALIZATION);
+
+// Packs a personalization(at most 31 bytes) into a field element in little-endian.
+const fn poseidon_domain(personalization: &[u8]) -> pallas::Base {
+    assert!(personalization.len() < 32);
+    let mut limbs = [0u64; 4];
+    let mut i = 0;
+    while i < personalization.len() {
+        limbs[i / 8] |= (personalization[i] as u64) << ((i % 8) * 8);
+        i += 1;
+    }
+    pallas::Base::from_raw(limbs)
+}
 
 /// Commitment merkle tree depth
 pub const TAIGA_COMMITMENT_TREE_DEPTH: usize = 32;
@@ -44,6 +133,14 @@ pub const BASE_BITS_NUM: usize = 255;
 /// The number of notes in a (partial)tx.
 pub const NUM_NOTE: usize = 2;
 
+/// The maximum total value of a note type on either side of a ptx or a transaction. The action
+/// circuit checks the note values are u64, so the totals stay far below the field modulus.
+pub const MAX_TOTAL_VALUE: u128 = 1 << 70;
+
+/// The maximum number of ptxs in a bundle. Each ptx has at most NUM_NOTE u64 values on either
+/// side, so the totals of a bundle don't exceed MAX_TOTAL_VALUE.
+pub const MAX_PTX_NUM: usize = (MAX_TOTAL_VALUE >> 64) as usize / NUM_NOTE;
+
 pub const ACTION_NF_PUBLIC_INPUT_ROW_IDX: usize = 0;
 pub const ACTION_ANCHOR_PUBLIC_INPUT_ROW_IDX: usize = 1;
 pub const ACTION_OUTPUT_CM_PUBLIC_INPUT_ROW_IDX: usize = 2;
@@ -53,6 +150,17 @@ pub const ACTION_INPUT_VP_CM_1_ROW_IDX: usize = 5;
 pub const ACTION_INPUT_VP_CM_2_ROW_IDX: usize = 6;
 pub const ACTION_OUTPUT_VP_CM_1_ROW_IDX: usize = 7;
 pub const ACTION_OUTPUT_VP_CM_2_ROW_IDX: usize = 8;
+pub const ACTION_CIPHER_COMMITMENT_ROW_IDX: usize = 9;
+pub const ACTION_INPUT_APP_VK_ROW_IDX: usize = 10;
+pub const ACTION_OUTPUT_APP_VK_ROW_IDX: usize = 11;
+pub const ACTION_PUBLIC_INPUT_NUM: usize = 12;
+// The aggregated action circuit has the public inputs of the actions one after the other
+pub const AGGREGATED_ACTION_PUBLIC_INPUT_NUM: usize = NUM_NOTE * ACTION_PUBLIC_INPUT_NUM;
+// Bump it when the action public input rows change, see ActionPublicInputs::from_vec
+pub const ACTION_PUBLIC_INPUT_LAYOUT_VERSION: u8 = 1;
+
+/// The cipher commitment of the output notes that are not sent with an encrypted payload.
+pub const NOTE_CIPHERTEXT_COMMITMENT_SENTINEL: pallas::Base = pallas::Base::from_raw([0, 0, 0, 0]);
 
 pub const POSEIDON_TO_CURVE_INPUT_LEN: usize = 3;
 pub const CURVE_ID: &str = "pallas";
@@ -61,7 +169,14 @@ pub const VALUE_BASE_DOMAIN_POSTFIX: &str = "Taiga-NoteType";
 pub const VP_CIRCUIT_PUBLIC_INPUT_NUM: usize = VP_CIRCUIT_MANDATORY_PUBLIC_INPUT_NUM
     + VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_NUM
     + VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_NUM;
-pub const VP_CIRCUIT_MANDATORY_PUBLIC_INPUT_NUM: usize = 9;
+// The length of the vp instance column, the public inputs are compressed into one element with
+// compressed_vp_instance
+pub const VP_CIRCUIT_INSTANCE_NUM: usize = if cfg!(feature = "compressed_vp_instance") {
+    1
+} else {
+    VP_CIRCUIT_PUBLIC_INPUT_NUM
+};
+pub const VP_CIRCUIT_MANDATORY_PUBLIC_INPUT_NUM: usize = 11;
 pub const VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_NUM: usize = 2;
 pub const VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_NUM: usize = NOTE_ENCRYPTION_CIPHERTEXT_NUM + 2; // ciphertext(12) + public_key(2)
 
@@ -70,25 +185,44 @@ pub const VP_CIRCUIT_OUTPUT_CM_ONE_PUBLIC_INPUT_IDX: usize = 1;
 pub const VP_CIRCUIT_NULLIFIER_TWO_PUBLIC_INPUT_IDX: usize = 2;
 pub const VP_CIRCUIT_OUTPUT_CM_TWO_PUBLIC_INPUT_IDX: usize = 3;
 pub const VP_CIRCUIT_OWNED_NOTE_PUB_ID_PUBLIC_INPUT_IDX: usize = 4;
-pub const VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1: usize = 5;
-pub const VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2: usize = 6;
-pub const VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_1: usize = 7;
-pub const VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_2: usize = 8;
+// The nullifiers, the output cms and the owned note pub id come first, they are the same for all
+// the vps of a note and the ptx rebuilds them from the actions
+pub const VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM: usize = 5;
+// The only public input of the vp circuit with the compressed_vp_instance feature
+pub const VP_CIRCUIT_COMPRESSED_INSTANCE_IDX: usize = 0;
+// The index of the public inputs the vp signs as the action anchors, 0 if it signs none. It's a
+// constant of the vp circuit, the ptx checks the public inputs at the index against the anchors.
+pub const VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX: usize = 5;
+// The compressed vk of the vp publishing the ciphertext of the owned note, 0 if the vp binds none.
+// The ptx only takes the ciphertext of an output note from the vp declared by its application vp.
+pub const VP_CIRCUIT_ENCRYPTION_VP_PUBLIC_INPUT_IDX: usize = 6;
+pub const VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_1: usize = 7;
+pub const VP_CIRCUIT_FIRST_DYNAMIC_VP_CM_2: usize = 8;
+pub const VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_1: usize = 9;
+pub const VP_CIRCUIT_SECOND_DYNAMIC_VP_CM_2: usize = 10;
 pub const VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX: usize = VP_CIRCUIT_MANDATORY_PUBLIC_INPUT_NUM;
 pub const VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX: usize =
     VP_CIRCUIT_MANDATORY_PUBLIC_INPUT_NUM + VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_NUM;
-pub const VP_CIRCUIT_NOTE_ENCRYPTION_NONCE_IDX: usize = 21;
-pub const VP_CIRCUIT_NOTE_ENCRYPTION_MAC_IDX: usize = 22;
-pub const VP_CIRCUIT_NOTE_ENCRYPTION_PK_X_IDX: usize = 23;
-pub const VP_CIRCUIT_NOTE_ENCRYPTION_PK_Y_IDX: usize = 24;
+pub const VP_CIRCUIT_NOTE_ENCRYPTION_NONCE_IDX: usize = 23;
+pub const VP_CIRCUIT_NOTE_ENCRYPTION_MAC_IDX: usize = 24;
+pub const VP_CIRCUIT_NOTE_ENCRYPTION_PK_X_IDX: usize = 25;
+pub const VP_CIRCUIT_NOTE_ENCRYPTION_PK_Y_IDX: usize = 26;
 
 // Note encryption
 pub const NOTE_ENCRYPTION_PLAINTEXT_NUM: usize = 10;
 pub const NOTE_ENCRYPTION_CIPHERTEXT_NUM: usize = NOTE_ENCRYPTION_PLAINTEXT_NUM + 2; // msg(10) + MAC(1) + NOUNCE(1)
 
+// The scans trial-decrypt more ciphertexts than this in a batch, see `IncomingViewingKey::scan`
+pub const BATCH_DECRYPTION_THRESHOLD: usize = 32;
+
+// The human readable part of the bech32m payment addresses, see `address::PaymentAddress`
+pub const PAYMENT_ADDRESS_HRP: &str = "taiga";
+
 // Poseidon parameters
 pub const POSEIDON_RATE: usize = 2;
 pub const POSEIDON_WIDTH: usize = 3;
+// The max message length of the runtime-length poseidon hash
+pub const POSEIDON_HASH_MAX_LEN: usize = 16;
 
 lazy_static! {
     pub static ref POSEIDON_TO_FIELD_U_0_POSTFIX: Vec<pallas::Base> = {
@@ -113,40 +247,140 @@ lazy_static! {
 
 pub const PARAMS_SIZE: u32 = 15;
 pub const ACTION_CIRCUIT_PARAMS_SIZE: u32 = PARAMS_SIZE;
+// The NUM_NOTE actions take twice the rows of one action
+pub const AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE: u32 = ACTION_CIRCUIT_PARAMS_SIZE + 1;
 pub const VP_CIRCUIT_PARAMS_SIZE: u32 = PARAMS_SIZE;
 
-// Setup params map
+// The largest params size that can be requested from get_params
+pub const MAX_PARAMS_SIZE: u32 = 18;
+
+// Setup params map, the missing sizes are generated on demand
 lazy_static! {
-    pub static ref SETUP_PARAMS_MAP: HashMap<u32, Params<vesta::Affine>> = {
+    static ref SETUP_PARAMS_MAP: RwLock<HashMap<u32, Arc<Params<vesta::Affine>>>> = {
         let mut m = HashMap::new();
         let params_15 = {
             let bytes = include_bytes!("../params/params_15");
             Params::<vesta::Affine>::read(&mut &bytes[..]).unwrap()
         };
 
-        m.insert(PARAMS_SIZE, params_15);
-        m
+        m.insert(PARAMS_SIZE, Arc::new(params_15));
+        RwLock::new(m)
     };
 }
 
-// Action proving key and verifying key
+/// Returns the setup params of size k, generating them on first use if they are not shipped.
+pub fn get_params(k: u32) -> Arc<Params<vesta::Affine>> {
+    assert!(
+        k <= MAX_PARAMS_SIZE,
+        "params size {k} exceeds the maximum params size {MAX_PARAMS_SIZE}"
+    );
+    if let Some(params) = SETUP_PARAMS_MAP.read().unwrap().get(&k) {
+        return params.clone();
+    }
+    SETUP_PARAMS_MAP
+        .write()
+        .unwrap()
+        .entry(k)
+        .or_insert_with(|| Arc::new(Params::new(k)))
+        .clone()
+}
+
+// Action verifying key, the verifiers only run keygen_vk
+lazy_static! {
+    pub static ref ACTION_VERIFYING_KEY: VerifyingKey<vesta::Affine> = {
+        let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+        let empty_circuit: ActionCircuit = Default::default();
+        keygen_vk(params, &empty_circuit).expect("keygen_vk should not fail")
+    };
+    // The action verifying keys of the other tree depths
+    static ref ACTION_VERIFYING_KEY_MAP: RwLock<HashMap<usize, Arc<VerifyingKey<vesta::Affine>>>> =
+        RwLock::new(HashMap::new());
+}
+
+// Action proving key
+#[cfg(feature = "prover")]
 lazy_static! {
-    pub static ref ACTION_VERIFYING_KEY: VerifyingKey<vesta::Affine> =
-        ACTION_PROVING_KEY.get_vk().clone();
     pub static ref ACTION_PROVING_KEY: ProvingKey<vesta::Affine> = {
-        let params = SETUP_PARAMS_MAP.get(&ACTION_CIRCUIT_PARAMS_SIZE).unwrap();
+        let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
         let empty_circuit: ActionCircuit = Default::default();
-        let vk = keygen_vk(params, &empty_circuit).expect("keygen_vk should not fail");
-        keygen_pk(params, vk, &empty_circuit).expect("keygen_pk should not fail")
+        keygen_pk(params, ACTION_VERIFYING_KEY.clone(), &empty_circuit)
+            .expect("keygen_pk should not fail")
+    };
+    // The action proving keys of the other tree depths
+    static ref ACTION_PROVING_KEY_MAP: RwLock<HashMap<usize, Arc<ProvingKey<vesta::Affine>>>> =
+        RwLock::new(HashMap::new());
+}
+
+// Aggregated action verifying key
+lazy_static! {
+    pub static ref AGGREGATED_ACTION_VERIFYING_KEY: VerifyingKey<vesta::Affine> = {
+        let params = &get_params(AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE);
+        let empty_circuit: AggregatedActionCircuit = Default::default();
+        keygen_vk(params, &empty_circuit).expect("keygen_vk should not fail")
     };
 }
 
+// Aggregated action proving key
+#[cfg(feature = "prover")]
+lazy_static! {
+    pub static ref AGGREGATED_ACTION_PROVING_KEY: ProvingKey<vesta::Affine> = {
+        let params = &get_params(AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE);
+        let empty_circuit: AggregatedActionCircuit = Default::default();
+        keygen_pk(
+            params,
+            AGGREGATED_ACTION_VERIFYING_KEY.clone(),
+            &empty_circuit,
+        )
+        .expect("keygen_pk should not fail")
+    };
+}
+
+/// Returns the verifying key of the action circuit of tree depth DEPTH, generating it on first
+/// use. ACTION_VERIFYING_KEY is the one of TAIGA_COMMITMENT_TREE_DEPTH.
+pub fn get_action_verifying_key<const DEPTH: usize>() -> Arc<VerifyingKey<vesta::Affine>> {
+    if let Some(vk) = ACTION_VERIFYING_KEY_MAP.read().unwrap().get(&DEPTH) {
+        return vk.clone();
+    }
+    ACTION_VERIFYING_KEY_MAP
+        .write()
+        .unwrap()
+        .entry(DEPTH)
+        .or_insert_with(|| {
+            let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+            let empty_circuit = ActionCircuit::<DEPTH>::default();
+            Arc::new(keygen_vk(params, &empty_circuit).expect("keygen_vk should not fail"))
+        })
+        .clone()
+}
+
+/// Returns the proving key of the action circuit of tree depth DEPTH, generating it on first use.
+/// ACTION_PROVING_KEY is the one of TAIGA_COMMITMENT_TREE_DEPTH.
+#[cfg(feature = "prover")]
+pub fn get_action_proving_key<const DEPTH: usize>() -> Arc<ProvingKey<vesta::Affine>> {
+    if let Some(pk) = ACTION_PROVING_KEY_MAP.read().unwrap().get(&DEPTH) {
+        return pk.clone();
+    }
+    ACTION_PROVING_KEY_MAP
+        .write()
+        .unwrap()
+        .entry(DEPTH)
+        .or_insert_with(|| {
+            let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+            let empty_circuit = ActionCircuit::<DEPTH>::default();
+            let vk = (*get_action_verifying_key::<DEPTH>()).clone();
+            Arc::new(keygen_pk(params, vk, &empty_circuit).expect("keygen_pk should not fail"))
+        })
+        .clone()
+}
+
 // SinsemillaCommit parameters
 lazy_static! {
     pub static ref NOTE_COMMIT_DOMAIN: CommitDomain =
         CommitDomain::new(NOTE_COMMITMENT_PERSONALIZATION);
     pub static ref NOTE_COMMITMENT_GENERATOR: pallas::Affine = NOTE_COMMIT_DOMAIN.Q().to_affine();
     pub static ref NOTE_COMMITMENT_R_GENERATOR: pallas::Affine = NOTE_COMMIT_DOMAIN.R().to_affine();
+    pub static ref MERKLE_CRH_DOMAIN: HashDomain = HashDomain::new(MERKLE_CRH_PERSONALIZATION);
+    pub static ref MERKLE_CRH_Q: pallas::Affine = MERKLE_CRH_DOMAIN.Q().to_affine();
     // Generator used in NullifierK and VP
     pub static ref GENERATOR: pallas::Affine = pallas::Point::generator().to_affine();
     // pub static ref R_ZS_AND_US: Vec<(u64, [pallas::Base; H])> =
@@ -6129,6 +6363,142 @@ fn export_params() {
     file.write_all(&bytes).unwrap();
 }
 
+#[test]
+fn test_poseidon_domains() {
+    use crate::circuit::vp_examples::token::TOKEN_AUTHORIZATION_SCHEMA;
+    use crate::note::{encoding::StaticDataValue, NoteCommitmentPreimage};
+    use crate::utils::{poseidon_hash, poseidon_hash_n, testing::base_from_hex};
+    use pasta_curves::arithmetic::CurveAffine;
+
+    // Fixed vectors: the personalizations packed in little-endian
+    assert_eq!(
+        NK_COMMITMENT_DOMAIN,
+        pallas::Base::from_raw([0x6b4e2d6167696154, 0x74696d6d6f43, 0, 0])
+    );
+    assert_eq!(
+        NOTE_COMMITMENT_POSEIDON_DOMAIN,
+        pallas::Base::from_raw([0x6f4e2d6167696154, 0x74696d6d6f436574, 0, 0])
+    );
+    assert_eq!(
+        APP_DATA_DYNAMIC_DOMAIN,
+        pallas::Base::from_raw([0x70412d6167696154, 0x6e79446174614470, 0, 0])
+    );
+    assert_eq!(
+        SCHNORR_SIGHASH_DOMAIN,
+        pallas::Base::from_raw([0x69532d6167696154, 0x31562d6873614867, 0, 0])
+    );
+    assert_eq!(
+        SWAP_INTENT_DOMAIN,
+        pallas::Base::from_raw([0x77532d6167696154, 0x746e65746e497061, 0, 0])
+    );
+    assert_eq!(
+        DUTCH_AUCTION_DOMAIN,
+        pallas::Base::from_raw([0x75442d6167696154, 0x6e74637541686374, 0, 0])
+    );
+    assert_eq!(
+        NOTE_ENCRYPTION_KEY_DOMAIN,
+        pallas::Base::from_raw([0x6f4e2d6167696154, 0x79654b636e456574, 0, 0])
+    );
+    assert_eq!(
+        INTENT_ADVERT_DOMAIN,
+        pallas::Base::from_raw([0x6e492d6167696154, 0x74766441746e6574, 0, 0])
+    );
+    assert_eq!(
+        SPENDING_CAP_DOMAIN,
+        pallas::Base::from_raw([0x70532d6167696154, 0x706143676e646e65, 0, 0])
+    );
+    assert_eq!(
+        TOKEN_BRIDGE_DOMAIN,
+        pallas::Base::from_raw([0x6f542d6167696154, 0x65676472426e656b, 0, 0])
+    );
+    assert_eq!(
+        TIMELOCK_DOMAIN,
+        pallas::Base::from_raw([0x69542d6167696154, 0x64656b636f4c656d, 0, 0])
+    );
+    assert_eq!(
+        ESCROW_DOMAIN,
+        pallas::Base::from_raw([0x73452d6167696154, 0x6d726554776f7263, 0, 0])
+    );
+    assert_eq!(
+        MULTISIG_KEYS_DOMAIN,
+        pallas::Base::from_raw([0x754d2d6167696154, 0x734b67697369746c, 0, 0])
+    );
+    assert_eq!(
+        TOKEN_ISSUANCE_DOMAIN,
+        pallas::Base::from_raw([0x6f542d6167696154, 0x65757373496e656b, 0, 0])
+    );
+
+    // The same inputs under different domains produce different outputs
+    let domains = [
+        NK_COMMITMENT_DOMAIN,
+        NOTE_COMMITMENT_POSEIDON_DOMAIN,
+        APP_DATA_DYNAMIC_DOMAIN,
+    ];
+    let input = pallas::Base::from(1u64);
+    let outputs = domains.map(|domain| poseidon_hash(domain, input));
+    assert_ne!(outputs[0], outputs[1]);
+    assert_ne!(outputs[0], outputs[2]);
+    assert_ne!(outputs[1], outputs[2]);
+    assert_ne!(outputs[0], poseidon_hash(pallas::Base::zero(), input));
+
+    let inputs = [input; 4];
+    let outputs =
+        domains.map(|domain| poseidon_hash_n([domain, inputs[0], inputs[1], inputs[2], inputs[3]]));
+    assert_ne!(outputs[0], outputs[1]);
+    assert_ne!(outputs[0], outputs[2]);
+    assert_ne!(outputs[1], outputs[2]);
+
+    // Golden vectors: the canonical little-endian encodings of fixed inputs and the outputs
+    let preimage = NoteCommitmentPreimage {
+        app_vk: base_from_hex("0101010101010101010101010101010101010101010101010101010101010101"),
+        app_data_static: base_from_hex(
+            "0202020202020202020202020202020202020202020202020202020202020202",
+        ),
+        app_data_dynamic: base_from_hex(
+            "0303030303030303030303030303030303030303030303030303030303030303",
+        ),
+        nk_commitment: base_from_hex(
+            "0404040404040404040404040404040404040404040404040404040404040404",
+        ),
+        rho: base_from_hex("0505050505050505050505050505050505050505050505050505050505050505"),
+        psi: base_from_hex("0606060606060606060606060606060606060606060606060606060606060606"),
+        is_merkle_checked: pallas::Base::one(),
+        value: pallas::Base::from(1000u64),
+        rcm: base_from_hex("0707070707070707070707070707070707070707070707070707070707070707"),
+    };
+    assert_eq!(
+        preimage.commitment().inner(),
+        base_from_hex("b961d5e2e24e3c677a7f359c930bd1c8f39ab9760aa5354de291fd20ab85701c")
+    );
+
+    // The token authorization app_data_dynamic: (auth pk, auth vp vk, receiver vp vk, rcv pk)
+    let point = |x: &str, y: &str| {
+        pallas::Point::from(pallas::Affine::from_xy(base_from_hex(x), base_from_hex(y)).unwrap())
+    };
+    let app_data_dynamic = TOKEN_AUTHORIZATION_SCHEMA
+        .encode(&[
+            StaticDataValue::Point(point(
+                "00000000ed302d991bf94c09fc98462200000000000000000000000000000040",
+                "0200000000000000000000000000000000000000000000000000000000000000",
+            )),
+            StaticDataValue::Base(base_from_hex(
+                "0808080808080808080808080808080808080808080808080808080808080808",
+            )),
+            StaticDataValue::Base(base_from_hex(
+                "0909090909090909090909090909090909090909090909090909090909090909",
+            )),
+            StaticDataValue::Point(point(
+                "030000b067c50313fcac1144eee2fe0e0000000000000000000000000000001c",
+                "fcffff3bdf5cea8a5eb73f56c96e07170000000000000000000000000000002b",
+            )),
+        ])
+        .unwrap();
+    assert_eq!(
+        app_data_dynamic,
+        base_from_hex("21d1127ef3aea352c4f89adb259e7d64de7f5c2a3c50527248f53a615435671e")
+    );
+}
+
 // It takes 4 seconds to generate one proving key.
 // It may be fine to generate the key once when compiling.
 // Consider loading the key from file when the keys are stablized.
@@ -6137,7 +6507,7 @@ fn export_params() {
 // fn export_action_proving_key() {
 //     use std::io::Write;
 
-//     let params = SETUP_PARAMS_MAP.get(&ACTION_CIRCUIT_PARAMS_SIZE).unwrap();
+//     let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
 //     let empty_circuit: ActionCircuit = Default::default();
 //     let vk = keygen_vk(params, &empty_circuit).expect("keygen_vk should not fail");
 //     let pk = keygen_pk(params, vk, &empty_circuit).expect("keygen_pk should not fail");
@@ -6154,7 +6524,7 @@ fn export_params() {
 //     use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
 //     use std::io::Write;
 
-//     let params = SETUP_PARAMS_MAP.get(&VP_CIRCUIT_PARAMS_SIZE).unwrap();
+//     let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
 //     let empty_circuit = TrivialValidityPredicateCircuit::default();
 //     let vk = keygen_vk(params, &empty_circuit).expect("keygen_vk should not fail");
 //     let pk = keygen_pk(params, vk, &empty_circuit).expect("keygen_pk should not fail");
@@ -6164,3 +6534,56 @@ fn export_params() {
 //         .unwrap_or_else(|err| panic!("cannot create trivial_vp_proving_key with {}", err));
 //     file.write_all(&bytes).unwrap();
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The verifiers don't derive the action vk from the proving key
+    #[cfg(feature = "prover")]
+    #[test]
+    fn test_action_keys() {
+        use crate::vp_vk::ValidityPredicateVerifyingKey;
+
+        let compress = ValidityPredicateVerifyingKey::compress;
+        assert_eq!(
+            compress(&ACTION_VERIFYING_KEY),
+            compress(ACTION_PROVING_KEY.get_vk())
+        );
+        assert_eq!(
+            compress(&get_action_verifying_key::<TAIGA_COMMITMENT_TREE_DEPTH>()),
+            compress(&ACTION_VERIFYING_KEY)
+        );
+    }
+
+    // Writes the action vk, to embed it in the verifier builds once the circuit is stable
+    #[ignore]
+    #[test]
+    fn export_action_verifying_key() {
+        use std::io::Write;
+
+        let mut bytes = vec![];
+        ACTION_VERIFYING_KEY.write(&mut bytes).unwrap();
+        let mut file = std::fs::File::create("./params/action_verifying_key")
+            .unwrap_or_else(|err| panic!("cannot create action_verifying_key with {}", err));
+        file.write_all(&bytes).unwrap();
+    }
+
+    #[test]
+    fn test_get_params() {
+        let params = get_params(15);
+        assert_eq!(params.k(), 15);
+        assert!(Arc::ptr_eq(&params, &get_params(15)));
+
+        // Not shipped, generated on the first request
+        let params_12 = get_params(12);
+        assert_eq!(params_12.k(), 12);
+        assert!(Arc::ptr_eq(&params_12, &get_params(12)));
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds the maximum params size")]
+    fn test_get_params_too_large() {
+        get_params(MAX_PARAMS_SIZE + 1);
+    }
+}
@@ -0,0 +1,113 @@
+/// Transaction size and verification cost estimates, to budget the block space.
+/// The sizes are the lengths of the borsh encoding. The verification cost is measured in
+/// scalar multiplications: every halo2 proof is checked with a multi-scalar multiplication of
+/// about 2^k generators, plus the instance commitment.
+use halo2_proofs::arithmetic::best_multiexp;
+use pasta_curves::{
+    group::{ff::Field, Curve, Group},
+    vesta,
+};
+use rand::rngs::OsRng;
+use std::time::{Duration, Instant};
+
+/// The default time of one scalar multiplication in the verifier msm, in nanoseconds.
+/// Run `TxCostReport::calibrate` to measure it on the target machine.
+pub const DEFAULT_SCALAR_MUL_NANOS: f64 = 1_000.0;
+
+// The msm size used to calibrate
+const CALIBRATION_MSM_SIZE: usize = 1 << 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PtxCostReport {
+    // The proof sizes in bytes
+    pub action_proof_sizes: Vec<usize>,
+    pub vp_proof_sizes: Vec<usize>,
+    // The instance counts of the action proofs followed by the vp proofs
+    pub instance_counts: Vec<usize>,
+    // The serialized public inputs of all the proofs
+    pub instance_bytes: usize,
+    // The serialized vp vks
+    pub vk_bytes: usize,
+    // The length prefixes, binding_sig_r, hints and extra_data
+    pub framing_bytes: usize,
+    pub scalar_muls: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxCostReport {
+    pub total_size: usize,
+    pub shielded_ptxs: Vec<PtxCostReport>,
+    pub transparent_bytes: usize,
+    // The bundle length prefixes and the binding signature
+    pub framing_bytes: usize,
+    pub scalar_muls: usize,
+    // Estimated with DEFAULT_SCALAR_MUL_NANOS
+    pub estimated_verification_time: Duration,
+}
+
+impl PtxCostReport {
+    pub fn size(&self) -> usize {
+        self.action_proof_sizes.iter().sum::<usize>()
+            + self.vp_proof_sizes.iter().sum::<usize>()
+            + self.instance_bytes
+            + self.vk_bytes
+            + self.framing_bytes
+    }
+}
+
+impl TxCostReport {
+    pub fn new(
+        shielded_ptxs: Vec<PtxCostReport>,
+        transparent_bytes: usize,
+        framing_bytes: usize,
+    ) -> Self {
+        let total_size = shielded_ptxs.iter().map(|ptx| ptx.size()).sum::<usize>()
+            + transparent_bytes
+            + framing_bytes;
+        let scalar_muls = shielded_ptxs.iter().map(|ptx| ptx.scalar_muls).sum();
+        let mut report = Self {
+            total_size,
+            shielded_ptxs,
+            transparent_bytes,
+            framing_bytes,
+            scalar_muls,
+            estimated_verification_time: Duration::ZERO,
+        };
+        report.estimated_verification_time =
+            report.estimate_verification_time(DEFAULT_SCALAR_MUL_NANOS);
+        report
+    }
+
+    pub fn estimate_verification_time(&self, scalar_mul_nanos: f64) -> Duration {
+        Duration::from_nanos((self.scalar_muls as f64 * scalar_mul_nanos) as u64)
+    }
+
+    /// Measures the time of one scalar multiplication in an msm on this machine, in nanoseconds.
+    pub fn calibrate() -> f64 {
+        let mut rng = OsRng;
+        let bases: Vec<vesta::Affine> = (0..CALIBRATION_MSM_SIZE)
+            .map(|_| vesta::Point::random(&mut rng).to_affine())
+            .collect();
+        let scalars: Vec<vesta::Scalar> = (0..CALIBRATION_MSM_SIZE)
+            .map(|_| vesta::Scalar::random(&mut rng))
+            .collect();
+        let start = Instant::now();
+        let _ = best_multiexp(&scalars, &bases);
+        start.elapsed().as_nanos() as f64 / CALIBRATION_MSM_SIZE as f64
+    }
+}
+
+// The verifier msm size of a proof
+pub(crate) fn proof_scalar_muls(params_size: u32, instance_count: usize) -> usize {
+    (1 << params_size) + instance_count
+}
+
+#[test]
+fn test_calibrate() {
+    let scalar_mul_nanos = TxCostReport::calibrate();
+    assert!(scalar_mul_nanos > 0.0);
+
+    let report = TxCostReport::new(vec![], 10, 64);
+    assert_eq!(report.total_size, 74);
+    assert_eq!(report.estimated_verification_time, Duration::ZERO);
+}
@@ -1,21 +1,203 @@
+use crate::note::{NoteCommitment, NoteType};
+use crate::state::StateError;
 use core::fmt;
 use halo2_proofs::plonk::Error as PlonkError;
 use std::fmt::Display;
 
+/// Errors from the note level APIs: nullifiers, note decryption and merkle paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteError {
+    /// The nullifier can't be derived from the nk commitment.
+    MissingNullifierKey,
+    /// The ciphertext is not sent to the key, or the note is not owned by it.
+    WrongDecryptionKey,
+    /// The ciphertext or the sender pk is malformed, or the plaintext is not a note.
+    MalformedCiphertext,
+    /// The merkle path doesn't match the depth of the commitment tree.
+    MerklePathDepthMismatch { expected: usize, actual: usize },
+}
+
+impl Display for NoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use NoteError::*;
+        match self {
+            MissingNullifierKey => {
+                f.write_str("The nullifier can't be derived from the nk commitment")
+            }
+            WrongDecryptionKey => f.write_str("The note is not sent to the key"),
+            MalformedCiphertext => f.write_str("The note ciphertext is malformed"),
+            MerklePathDepthMismatch { expected, actual } => f.write_str(&format!(
+                "The merkle path depth is {actual}, expected {expected}"
+            )),
+        }
+    }
+}
+
+/// Errors from decoding a curve point, see `utils::point_from_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointEncodingError {
+    /// The x-coordinate is not reduced, or the encoding is not the one of its point.
+    NonCanonical,
+    /// No curve point has the x-coordinate.
+    NotOnCurve,
+    /// The identity where a non-identity point is expected.
+    Identity,
+}
+
+impl Display for PointEncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use PointEncodingError::*;
+        match self {
+            NonCanonical => f.write_str("The point encoding is not canonical"),
+            NotOnCurve => f.write_str("The point is not on the curve"),
+            Identity => f.write_str("The point is the identity"),
+        }
+    }
+}
+
+/// Errors from parsing a payment address, see `address::PaymentAddress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentAddressError {
+    /// The string mixes upper and lower case characters.
+    MixedCase,
+    /// The string has no '1' separator between the hrp and the data.
+    MissingSeparator,
+    /// The hrp is not the taiga one.
+    InvalidHrp,
+    /// The data contains a character out of the bech32 charset.
+    InvalidCharacter(char),
+    /// The bech32m checksum doesn't match, e.g. a typo in the address.
+    InvalidChecksum,
+    /// The data doesn't decode to the address bytes.
+    InvalidLength,
+    /// The auth pk or the rcv pk is not a valid non-identity point.
+    InvalidPk(PointEncodingError),
+    /// The nk commitment or the receiver vp vk is not a canonical field element.
+    NonCanonicalField,
+}
+
+impl Display for PaymentAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use PaymentAddressError::*;
+        match self {
+            MixedCase => f.write_str("The address mixes upper and lower case"),
+            MissingSeparator => f.write_str("The address has no separator"),
+            InvalidHrp => f.write_str("The address is not a taiga address"),
+            InvalidCharacter(c) => {
+                f.write_str(&format!("The address contains an invalid character {c:?}"))
+            }
+            InvalidChecksum => f.write_str("The address checksum is invalid"),
+            InvalidLength => f.write_str("The address length is invalid"),
+            InvalidPk(e) => f.write_str(&format!("The address pk is invalid: {e}")),
+            NonCanonicalField => f.write_str("The address contains a non-canonical field element"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum TransactionError {
     /// An error occurred when creating halo2 proof.
     Proof(PlonkError),
-    /// Binding signature is not valid.
-    InvalidBindingSignature,
+    /// The action proof is not valid.
+    ActionProofVerificationFailure {
+        ptx_index: usize,
+        action_index: usize,
+    },
+    /// The vp proof is not valid. The input notes come first in the note index, then the output notes.
+    /// The vp index is 0 for the application vp and i + 1 for the i-th dynamic vp.
+    VPProofVerificationFailure {
+        ptx_index: usize,
+        note_index: usize,
+        vp_index: usize,
+    },
+    /// The vp constraints are not satisfied in the dry run, the indexes are the same as in
+    /// VPProofVerificationFailure.
+    VPConstraintFailure {
+        ptx_index: usize,
+        note_index: usize,
+        vp_index: usize,
+    },
+    /// The value commitments don't match the binding signature.
+    ValueCommitmentImbalance,
+    /// The input and output values of the note type don't match in the dry run.
+    UnbalancedNoteType {
+        note_type: NoteType,
+        input_value: u128,
+        output_value: u128,
+    },
+    /// Binding signature is malformed.
+    BindingSignatureInvalid,
     /// Binding signature is missing.
     MissingBindingSignatures,
+    /// The same nullifier is revealed more than once in the transaction.
+    DuplicateNullifierWithinTx,
+    /// The same output note commitment is created more than once in the transaction, the indexes
+    /// are in `Transaction::output_commitments`.
+    DuplicateOutputNote {
+        commitment: NoteCommitment,
+        first_index: usize,
+        second_index: usize,
+    },
+    /// The public inputs don't fit the circuit.
+    PublicInputMalformed,
+    /// The public inputs are in another layout version of the circuit.
+    UnsupportedPublicInputLayout,
     /// Nullifier is not consistent between the action and the vp.
     InconsistentNullifier,
+    /// The rho of the output note is not the nullifier of the input note of the action.
+    InconsistentOutputNoteRho,
+    /// The root of the merkle path from the input note is not the anchor.
+    InconsistentAnchor,
+    /// The anchors signed by a vp are not the anchors of the actions.
+    InconsistentSignedAnchor,
     /// Output note commitment is not consistent between the action and the vp.
     InconsistentOutputNoteCommitment,
     /// Owned note public id is not consistent between the action and the vp.
     InconsistentOwnedNotePubID,
+    /// Output note ciphertext is not consistent with the cipher commitment in the action.
+    InconsistentNoteCiphertext,
+    /// An output note with a note encryption vp is not bound to its ciphertext.
+    MissingNoteCiphertext,
+    /// The value base of a note is not derived from its application vp.
+    InconsistentValueBase,
+    /// The application vp proof is not created under the app_vk of the note.
+    InconsistentApplicationVK,
+    /// The vp vk is not in the vk registry.
+    UnregisteredValidityPredicate,
+    /// The nullifier or the nk witness from the nk holder doesn't match the input note.
+    InvalidNullifierWitness,
+    /// The opening doesn't match the padding seed commitment.
+    InvalidPaddingSeedOpening,
+    /// The proving session was cancelled before all the proofs were created.
+    ProvingCancelled,
+    /// The notes in the vp bytecode don't match the notes of the partial transaction.
+    InconsistentBytecodeNotes,
+    /// The vk derived from the vp bytecode is not the attested vk.
+    InconsistentBytecodeVK,
+    /// The dynamic vp commitment of the application vp doesn't open to the expected vp.
+    InconsistentDynamicVPCommitment,
+    /// The dynamic vp committed by the application vp is not attached to the note.
+    MissingDynamicVP,
+    /// The total value of a note type exceeds MAX_TOTAL_VALUE.
+    ValueOverflow,
+    /// The anchor is not a known root of the commitment tree.
+    UnknownAnchor,
+    /// The nullifier is already in the state, the note is spent.
+    NullifierAlreadySpent,
+    /// The transaction has neither shielded nor transparent ptxs.
+    EmptyTransaction,
+    /// A vp publishes the height, but the execution context has none.
+    MissingHeight,
+    /// The height published by a vp is not the height of the execution context.
+    HeightMismatch,
+    /// A vp publishes the time, but the execution context has no timestamp.
+    MissingTimestamp,
+    /// The time published by a vp is not the timestamp of the execution context.
+    TimestampMismatch,
+    /// The note data is invalid.
+    Note(NoteError),
+    /// The state update failed.
+    State(StateError),
     /// IO error
     IoError(std::io::Error),
 }
@@ -25,28 +207,171 @@ impl Display for TransactionError {
         use TransactionError::*;
         match self {
             Proof(e) => f.write_str(&format!("Proof error: {e}")),
-            InvalidBindingSignature => f.write_str("Binding signature was invalid"),
+            ActionProofVerificationFailure {
+                ptx_index,
+                action_index,
+            } => f.write_str(&format!(
+                "Action proof {action_index} in partial transaction {ptx_index} is invalid"
+            )),
+            VPProofVerificationFailure {
+                ptx_index,
+                note_index,
+                vp_index,
+            } => f.write_str(&format!(
+                "VP proof {vp_index} of note {note_index} in partial transaction {ptx_index} is invalid"
+            )),
+            VPConstraintFailure {
+                ptx_index,
+                note_index,
+                vp_index,
+            } => f.write_str(&format!(
+                "VP {vp_index} of note {note_index} in partial transaction {ptx_index} is not satisfied"
+            )),
+            ValueCommitmentImbalance => {
+                f.write_str("Value commitments are not balanced by the binding signature")
+            }
+            UnbalancedNoteType {
+                note_type,
+                input_value,
+                output_value,
+            } => f.write_str(&format!(
+                "The note type (app_vk: {:?}, app_data_static: {:?}) has input value {input_value} and output value {output_value}",
+                note_type.app_vk, note_type.app_data_static
+            )),
+            BindingSignatureInvalid => f.write_str("Binding signature was invalid"),
             MissingBindingSignatures => f.write_str("Binding signature is missing"),
+            DuplicateNullifierWithinTx => {
+                f.write_str("Nullifier is revealed more than once in the transaction")
+            }
+            DuplicateOutputNote {
+                commitment,
+                first_index,
+                second_index,
+            } => f.write_str(&format!(
+                "Output note commitment {:?} is created at {first_index} and {second_index}",
+                commitment.inner()
+            )),
+            PublicInputMalformed => f.write_str("Public inputs are malformed"),
+            UnsupportedPublicInputLayout => {
+                f.write_str("Public inputs are in an unsupported layout version")
+            }
             InconsistentNullifier => {
                 f.write_str("Nullifier is not consistent between the action and the vp")
             }
+            InconsistentOutputNoteRho => {
+                f.write_str("The rho of the output note is not the nullifier of the input note")
+            }
+            InconsistentAnchor => {
+                f.write_str("The merkle path of the input note doesn't lead to the anchor")
+            }
+            InconsistentSignedAnchor => {
+                f.write_str("The anchors signed by a vp are not the anchors of the actions")
+            }
             InconsistentOutputNoteCommitment => f.write_str(
                 "Output note commitment is not consistent between the action and the vp",
             ),
             InconsistentOwnedNotePubID => {
                 f.write_str("Owned note public id is not consistent between the action and the vp")
             }
+            InconsistentNoteCiphertext => f.write_str(
+                "Output note ciphertext is not consistent with the cipher commitment in the action",
+            ),
+            MissingNoteCiphertext => f.write_str(
+                "Output note with a note encryption vp is not bound to its ciphertext",
+            ),
+            InconsistentValueBase => {
+                f.write_str("The net value commitment is not consistent with the application vps")
+            }
+            InconsistentApplicationVK => {
+                f.write_str("The application vp vk is not consistent with the note app_vk")
+            }
+            UnregisteredValidityPredicate => f.write_str("The vp vk is not registered"),
+            InvalidNullifierWitness => {
+                f.write_str("The nullifier or the nk witness doesn't match the input note")
+            }
+            InvalidPaddingSeedOpening => {
+                f.write_str("The opening doesn't match the padding seed commitment")
+            }
+            ProvingCancelled => f.write_str("The proving session was cancelled"),
+            InconsistentBytecodeNotes => {
+                f.write_str("The vp bytecode notes don't match the partial transaction notes")
+            }
+            InconsistentBytecodeVK => {
+                f.write_str("The vk derived from the vp bytecode doesn't match the attested vk")
+            }
+            InconsistentDynamicVPCommitment => {
+                f.write_str("The dynamic vp commitment doesn't open to the expected vp")
+            }
+            MissingDynamicVP => f.write_str("The committed dynamic vp is missing"),
+            ValueOverflow => f.write_str("The total value exceeds the maximum total value"),
+            UnknownAnchor => f.write_str("The anchor is not a known commitment tree root"),
+            NullifierAlreadySpent => f.write_str("The nullifier is already spent"),
+            EmptyTransaction => f.write_str("The transaction has no partial transactions"),
+            MissingHeight => f.write_str("The execution context has no height"),
+            HeightMismatch => {
+                f.write_str("The height of the vp is not the height of the execution context")
+            }
+            MissingTimestamp => f.write_str("The execution context has no timestamp"),
+            TimestampMismatch => {
+                f.write_str("The time of the vp is not the timestamp of the execution context")
+            }
+            Note(e) => f.write_str(&format!("Note error: {e}")),
+            State(e) => f.write_str(&format!("State error: {e}")),
             IoError(e) => f.write_str(&format!("IoError error: {e}")),
         }
     }
 }
 
+impl TransactionError {
+    // Set the index of the partial transaction in the bundle
+    pub(crate) fn with_ptx_index(self, index: usize) -> Self {
+        use TransactionError::*;
+        match self {
+            ActionProofVerificationFailure { action_index, .. } => ActionProofVerificationFailure {
+                ptx_index: index,
+                action_index,
+            },
+            VPProofVerificationFailure {
+                note_index,
+                vp_index,
+                ..
+            } => VPProofVerificationFailure {
+                ptx_index: index,
+                note_index,
+                vp_index,
+            },
+            VPConstraintFailure {
+                note_index,
+                vp_index,
+                ..
+            } => VPConstraintFailure {
+                ptx_index: index,
+                note_index,
+                vp_index,
+            },
+            e => e,
+        }
+    }
+}
+
 impl From<PlonkError> for TransactionError {
     fn from(e: PlonkError) -> Self {
         TransactionError::Proof(e)
     }
 }
 
+impl From<NoteError> for TransactionError {
+    fn from(e: NoteError) -> Self {
+        TransactionError::Note(e)
+    }
+}
+
+impl From<StateError> for TransactionError {
+    fn from(e: StateError) -> Self {
+        TransactionError::State(e)
+    }
+}
+
 impl From<std::io::Error> for TransactionError {
     fn from(e: std::io::Error) -> Self {
         TransactionError::IoError(e)
@@ -0,0 +1,124 @@
+/// The public content of a transaction, for block explorers. The view is built from the public
+/// inputs of the action and vp proofs, the proofs are not verified and nothing secret(e.g. the
+/// binding signature randomness or the hints) is included.
+use crate::transaction::TransactionId;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TxPublicView {
+    pub id: TransactionId,
+    // The transparent ptxs are not rendered yet
+    pub shielded_ptxs: Vec<PtxPublicView>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PtxPublicView {
+    pub actions: Vec<ActionPublicView>,
+    pub extra_data: Vec<u8>,
+}
+
+/// The field elements and points are in their canonical 32-byte encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ActionPublicView {
+    pub anchor: [u8; 32],
+    pub nullifier: [u8; 32],
+    pub output_cm: [u8; 32],
+    // The affine coordinates of the net value commitment, zero for the identity
+    pub cv_net_x: [u8; 32],
+    pub cv_net_y: [u8; 32],
+    // The compressed application vp vks of the input and output notes
+    pub input_app_vk: [u8; 32],
+    pub output_app_vk: [u8; 32],
+    // The ciphertext of the output note, if it's sent with an encrypted payload
+    pub note_ciphertext: Option<Vec<[u8; 32]>>,
+    // The compressed ephemeral pk of the sender, to decrypt the ciphertext
+    pub note_sender_pk: Option<[u8; 32]>,
+}
+
+impl TxPublicView {
+    pub fn nullifiers(&self) -> Vec<[u8; 32]> {
+        self.actions().map(|action| action.nullifier).collect()
+    }
+
+    pub fn output_cms(&self) -> Vec<[u8; 32]> {
+        self.actions().map(|action| action.output_cm).collect()
+    }
+
+    fn actions(&self) -> impl Iterator<Item = &ActionPublicView> {
+        self.shielded_ptxs.iter().flat_map(|ptx| ptx.actions.iter())
+    }
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+}
+
+impl fmt::Display for TxPublicView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "transaction {}", self.id)?;
+        for (i, ptx) in self.shielded_ptxs.iter().enumerate() {
+            writeln!(f, "shielded ptx {}", i)?;
+            write!(f, "{}", ptx)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PtxPublicView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, action) in self.actions.iter().enumerate() {
+            writeln!(f, "  action {}", i)?;
+            write!(f, "{}", action)?;
+        }
+        write!(f, "  extra_data: ")?;
+        write_hex(f, &self.extra_data)?;
+        writeln!(f)
+    }
+}
+
+impl fmt::Display for ActionPublicView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, bytes) in [
+            ("anchor", &self.anchor),
+            ("nullifier", &self.nullifier),
+            ("output_cm", &self.output_cm),
+            ("cv_net_x", &self.cv_net_x),
+            ("cv_net_y", &self.cv_net_y),
+            ("input_app_vk", &self.input_app_vk),
+            ("output_app_vk", &self.output_app_vk),
+        ] {
+            write!(f, "    {}: ", name)?;
+            write_hex(f, bytes)?;
+            writeln!(f)?;
+        }
+        write!(f, "    note_ciphertext:")?;
+        match &self.note_ciphertext {
+            Some(ciphertext) => {
+                for element in ciphertext.iter() {
+                    write!(f, " ")?;
+                    write_hex(f, element)?;
+                }
+                writeln!(f)
+            }
+            None => writeln!(f, " none"),
+        }?;
+        write!(f, "    note_sender_pk: ")?;
+        match &self.note_sender_pk {
+            Some(pk) => write_hex(f, pk)?,
+            None => write!(f, "none")?,
+        }
+        writeln!(f)
+    }
+}
@@ -0,0 +1,648 @@
+use crate::{
+    address::PaymentAddress,
+    circuit::vp_circuit::ValidityPredicatePublicInputs,
+    constant::{
+        BATCH_DECRYPTION_THRESHOLD, NOTE_ENCRYPTION_PLAINTEXT_NUM, PRF_EXPAND_AUTH_SK,
+        PRF_EXPAND_NK, PRF_EXPAND_PERSONALIZATION, PRF_EXPAND_RCV_SK,
+    },
+    error::NoteError,
+    note::{Note, NoteCommitment},
+    note_encryption::{batch_symmetric_keys, EncryptedNote, NoteKind},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    utils::{mod_r_p, zeroize_field},
+};
+use blake2b_simd::Params as Blake2bParams;
+use ff::{Field, FromUniformBytes, PrimeField};
+use group::Group;
+use pasta_curves::pallas;
+use rand::RngCore;
+use std::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// The signing key. It's wiped on drop and redacted in Debug.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SigningKey(pallas::Scalar);
+
+/// The full key material of a user.
+/// auth_sk signs the spends, nk derives the nullifiers and rcv_sk decrypts the received notes.
+/// As the viewing keys, it's wiped on drop and redacted in Debug.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SpendingKey {
+    auth_sk: pallas::Scalar,
+    nk: pallas::Base,
+    rcv_sk: pallas::Base,
+}
+
+/// A note decrypted by the incoming viewing key, with the kind tagged by the sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecryptedNote {
+    pub note: Note,
+    pub kind: NoteKind,
+}
+
+/// The full viewing key detects both the received notes and the spends, but can't sign.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FullViewingKey {
+    nk: pallas::Base,
+    ivk: IncomingViewingKey,
+}
+
+/// The incoming viewing key detects the received notes, but can't derive nullifiers.
+/// It carries the auth pk to build the payment address.
+#[derive(Clone, PartialEq, Eq)]
+pub struct IncomingViewingKey {
+    rcv_sk: pallas::Base,
+    nk_com: pallas::Base,
+    auth_pk: pallas::Point,
+}
+
+impl SigningKey {
+    pub fn new(sk: pallas::Scalar) -> Self {
+        Self(sk)
+    }
+
+    pub fn random<R: RngCore>(mut rng: R) -> Self {
+        Self(pallas::Scalar::random(&mut rng))
+    }
+
+    pub(crate) fn inner(&self) -> &pallas::Scalar {
+        &self.0
+    }
+
+    pub fn public_key(&self) -> pallas::Point {
+        pallas::Point::generator() * self.0
+    }
+}
+
+impl From<pallas::Scalar> for SigningKey {
+    fn from(sk: pallas::Scalar) -> Self {
+        Self(sk)
+    }
+}
+
+impl Zeroize for SigningKey {
+    fn zeroize(&mut self) {
+        zeroize_field(&mut self.0);
+    }
+}
+
+impl Drop for SigningKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SigningKey {}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SigningKey(<redacted>)")
+    }
+}
+
+impl SpendingKey {
+    pub fn new(auth_sk: pallas::Scalar, nk: pallas::Base, rcv_sk: pallas::Base) -> Self {
+        Self {
+            auth_sk,
+            nk,
+            rcv_sk,
+        }
+    }
+
+    pub fn random<R: RngCore>(mut rng: R) -> Self {
+        Self {
+            auth_sk: pallas::Scalar::random(&mut rng),
+            nk: pallas::Base::random(&mut rng),
+            rcv_sk: pallas::Base::random(&mut rng),
+        }
+    }
+
+    // Each key is derived from the root with its own PRF^expand tag, so the auth_sk signing the
+    // spends is independent of the rcv_sk decrypting the notes.
+    pub fn from_root(root: &[u8; 32]) -> Self {
+        Self {
+            auth_sk: prf_expand(root, PRF_EXPAND_AUTH_SK),
+            nk: prf_expand(root, PRF_EXPAND_NK),
+            rcv_sk: prf_expand(root, PRF_EXPAND_RCV_SK),
+        }
+    }
+
+    pub fn get_auth_sk(&self) -> SigningKey {
+        SigningKey::new(self.auth_sk)
+    }
+
+    pub fn get_auth_pk(&self) -> pallas::Point {
+        pallas::Point::generator() * self.auth_sk
+    }
+
+    pub fn get_nk(&self) -> NullifierKeyContainer {
+        NullifierKeyContainer::from_key(self.nk)
+    }
+
+    pub fn to_full_viewing_key(&self) -> FullViewingKey {
+        FullViewingKey {
+            nk: self.nk,
+            ivk: IncomingViewingKey {
+                rcv_sk: self.rcv_sk,
+                nk_com: self.get_nk().get_commitment(),
+                auth_pk: self.get_auth_pk(),
+            },
+        }
+    }
+
+    pub fn to_incoming_viewing_key(&self) -> IncomingViewingKey {
+        self.to_full_viewing_key().to_incoming_viewing_key()
+    }
+}
+
+// PRF^expand(root, tag), the bytes are wiped once reduced to the key
+fn prf_expand<F: FromUniformBytes<64>>(root: &[u8; 32], tag: u8) -> F {
+    let mut h = Blake2bParams::new()
+        .hash_length(64)
+        .personal(PRF_EXPAND_PERSONALIZATION)
+        .to_state();
+    h.update(&[tag]);
+    h.update(root);
+    let mut bytes = *h.finalize().as_array();
+    let key = F::from_uniform_bytes(&bytes);
+    bytes.zeroize();
+    key
+}
+
+impl Zeroize for SpendingKey {
+    fn zeroize(&mut self) {
+        zeroize_field(&mut self.auth_sk);
+        zeroize_field(&mut self.nk);
+        zeroize_field(&mut self.rcv_sk);
+    }
+}
+
+impl Drop for SpendingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SpendingKey {}
+
+impl fmt::Debug for SpendingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SpendingKey(<redacted>)")
+    }
+}
+
+impl FullViewingKey {
+    pub fn to_incoming_viewing_key(&self) -> IncomingViewingKey {
+        self.ivk.clone()
+    }
+
+    pub fn get_nk(&self) -> NullifierKeyContainer {
+        NullifierKeyContainer::from_key(self.nk)
+    }
+
+    // Returns None if the note is not owned by the key
+    pub fn derive_nullifier(&self, note: &Note) -> Option<Nullifier> {
+        if note.get_nk_commitment() != self.ivk.nk_com {
+            return None;
+        }
+        let mut nk = self.get_nk();
+        let nf = Nullifier::derive(&nk, &note.rho.inner(), &note.psi, &note.commitment());
+        nk.zeroize();
+        nf.ok()
+    }
+
+    // Check if the note is spent, i.e. its nullifier is revealed
+    pub fn is_spent(&self, note: &Note, nfs: &[Nullifier]) -> bool {
+        match self.derive_nullifier(note) {
+            Some(nf) => nfs.contains(&nf),
+            None => false,
+        }
+    }
+}
+
+impl Zeroize for FullViewingKey {
+    fn zeroize(&mut self) {
+        zeroize_field(&mut self.nk);
+        self.ivk.zeroize();
+    }
+}
+
+impl Drop for FullViewingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for FullViewingKey {}
+
+impl fmt::Debug for FullViewingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FullViewingKey(<redacted>)")
+    }
+}
+
+impl IncomingViewingKey {
+    pub fn get_rcv_pk(&self) -> pallas::Point {
+        pallas::Point::generator() * mod_r_p(self.rcv_sk)
+    }
+
+    pub fn get_nk_commitment(&self) -> pallas::Base {
+        self.nk_com
+    }
+
+    pub fn get_auth_pk(&self) -> pallas::Point {
+        self.auth_pk
+    }
+
+    // The notes sent to the address are encrypted to the rcv pk and authorized by the auth pk
+    pub fn to_payment_address(&self, receiver_vp_vk: pallas::Base) -> PaymentAddress {
+        PaymentAddress::new(self.auth_pk, self.get_rcv_pk(), self.nk_com, receiver_vp_vk)
+            .expect("the auth pk or the rcv pk is the identity")
+    }
+
+    // Trial-decrypt the note ciphertext published in the vp public inputs.
+    // Returns the note if it's sent to the key. The decrypted note only contains the nk commitment.
+    // The ciphertexts with an identity or invalid pk are rejected.
+    pub fn decrypt_note(
+        &self,
+        public_inputs: &ValidityPredicatePublicInputs,
+    ) -> Result<DecryptedNote, NoteError> {
+        self.decrypt(&public_inputs.get_encrypted_note()?)
+    }
+
+    // Trial-decrypt a ciphertext published in a transaction, see `Transaction::ciphertexts`
+    pub fn decrypt(&self, encrypted_note: &EncryptedNote) -> Result<DecryptedNote, NoteError> {
+        let plaintext = self.decrypt_plaintext(encrypted_note)?;
+        self.decode_plaintext(&plaintext)
+    }
+
+    // Decrypt a ciphertext sent to the key without decoding the plaintext as a note, e.g. the
+    // terms of a swap, see `EncryptedSwapTerms`
+    pub fn decrypt_plaintext(
+        &self,
+        encrypted_note: &EncryptedNote,
+    ) -> Result<Vec<pallas::Base>, NoteError> {
+        encrypted_note.decrypt(self.rcv_sk)
+    }
+
+    // Trial-decrypt the ciphertexts as `decrypt`, None for the ones not sent to the key.
+    // The DH exchanges are batched, see `batch_symmetric_keys`, and the plaintext buffer is reused.
+    pub fn batch_decrypt(&self, encrypted_notes: &[EncryptedNote]) -> Vec<Option<DecryptedNote>> {
+        let sender_pks: Vec<pallas::Point> = encrypted_notes
+            .iter()
+            .map(|encrypted_note| encrypted_note.sender_pk)
+            .collect();
+        let mut plaintext = [pallas::Base::zero(); NOTE_ENCRYPTION_PLAINTEXT_NUM];
+        batch_symmetric_keys(&sender_pks, &mod_r_p(self.rcv_sk))
+            .into_iter()
+            .zip(encrypted_notes)
+            .map(|(key, encrypted_note)| {
+                encrypted_note
+                    .ciphertext
+                    .decrypt_with_symmetric_key(key?, &mut plaintext)
+                    .ok()?;
+                self.decode_plaintext(&plaintext).ok()
+            })
+            .collect()
+    }
+
+    // Trial-decrypt the pending ciphertexts of a scan, the batched path is taken above
+    // BATCH_DECRYPTION_THRESHOLD ciphertexts.
+    pub fn scan(&self, encrypted_notes: &[EncryptedNote]) -> Vec<Option<DecryptedNote>> {
+        if encrypted_notes.len() > BATCH_DECRYPTION_THRESHOLD {
+            self.batch_decrypt(encrypted_notes)
+        } else {
+            encrypted_notes
+                .iter()
+                .map(|encrypted_note| self.decrypt(encrypted_note).ok())
+                .collect()
+        }
+    }
+
+    fn decode_plaintext(&self, plaintext: &[pallas::Base]) -> Result<DecryptedNote, NoteError> {
+        // The plaintext is
+        // [app_vk, app_data_static, app_data_dynamic, value, rho, nk_com, psi, rcm, note_kind]
+        let value_repr = plaintext[3].to_repr();
+        if value_repr[8..].iter().any(|&b| b != 0) {
+            return Err(NoteError::MalformedCiphertext);
+        }
+        let value = u64::from_le_bytes(value_repr[..8].try_into().unwrap());
+        if plaintext[5] != self.nk_com {
+            return Err(NoteError::WrongDecryptionKey);
+        }
+        // Notes sent to the receiver are always normal notes
+        let note = Note::from_full(
+            plaintext[0],
+            plaintext[1],
+            plaintext[2],
+            value,
+            NullifierKeyContainer::from_commitment(plaintext[5]),
+            Nullifier::from(plaintext[4]),
+            true,
+            plaintext[6],
+            plaintext[7],
+        );
+        let kind = NoteKind::try_from(plaintext[8])?;
+        Ok(DecryptedNote { note, kind })
+    }
+
+    // Check the decrypted note is in the output note commitments
+    pub fn is_note_created(&self, note: &Note, cms: &[NoteCommitment]) -> bool {
+        note.get_nk_commitment() == self.nk_com && cms.contains(&note.commitment())
+    }
+}
+
+impl Zeroize for IncomingViewingKey {
+    fn zeroize(&mut self) {
+        zeroize_field(&mut self.rcv_sk);
+    }
+}
+
+impl Drop for IncomingViewingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for IncomingViewingKey {}
+
+// The nk commitment and the auth pk are public, only the rcv_sk is redacted
+impl fmt::Debug for IncomingViewingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncomingViewingKey")
+            .field("rcv_sk", &"<redacted>")
+            .field("nk_com", &self.nk_com)
+            .field("auth_pk", &self.auth_pk)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        constant::{
+            NOTE_ENCRYPTION_CIPHERTEXT_NUM, VP_CIRCUIT_NOTE_ENCRYPTION_PK_X_IDX,
+            VP_CIRCUIT_NOTE_ENCRYPTION_PK_Y_IDX, VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX,
+            VP_CIRCUIT_PUBLIC_INPUT_NUM,
+        },
+        note::RandomSeed,
+        note_encryption::{NoteCiphertext, NotePlaintext, SecretKey},
+    };
+    use group::Curve;
+    use halo2_proofs::arithmetic::CurveAffine;
+    use rand::rngs::OsRng;
+
+    // Encrypt the note to the receiver as the receiver vp does
+    pub fn encrypt_note<R: RngCore>(
+        mut rng: R,
+        note: &Note,
+        rcv_pk: &pallas::Point,
+    ) -> ValidityPredicatePublicInputs {
+        let message = vec![
+            note.note_type.app_vk,
+            note.note_type.app_data_static,
+            note.app_data_dynamic,
+            pallas::Base::from(note.value),
+            note.rho.inner(),
+            note.get_nk_commitment(),
+            note.psi,
+            note.rcm,
+        ];
+        let sender_sk = pallas::Base::random(&mut rng);
+        let key = SecretKey::from_dh_exchange(rcv_pk, &mod_r_p(sender_sk));
+        let nonce = pallas::Base::random(&mut rng);
+        let cipher = NoteCiphertext::encrypt(&NotePlaintext::padding(&message), &key, &nonce);
+
+        let mut public_inputs = vec![pallas::Base::zero(); VP_CIRCUIT_PUBLIC_INPUT_NUM];
+        let begin = VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX;
+        public_inputs[begin..begin + cipher.inner().len()].copy_from_slice(cipher.inner());
+        let sender_pk = (pallas::Point::generator() * mod_r_p(sender_sk)).to_affine();
+        let sender_pk_coord = sender_pk.coordinates().unwrap();
+        public_inputs[VP_CIRCUIT_NOTE_ENCRYPTION_PK_X_IDX] = *sender_pk_coord.x();
+        public_inputs[VP_CIRCUIT_NOTE_ENCRYPTION_PK_Y_IDX] = *sender_pk_coord.y();
+        public_inputs.into()
+    }
+
+    #[test]
+    fn test_signing_key() {
+        let mut rng = OsRng;
+        let sk = pallas::Scalar::random(&mut rng);
+        let mut signing_key = SigningKey::new(sk);
+        assert_eq!(format!("{:?}", signing_key), "SigningKey(<redacted>)");
+        assert!(!format!("{:?}", signing_key).contains(&format!("{:?}", sk)));
+        assert_eq!(signing_key.public_key(), pallas::Point::generator() * sk);
+
+        signing_key.zeroize();
+        assert_eq!(*signing_key.inner(), pallas::Scalar::zero());
+    }
+
+    #[test]
+    fn test_spending_key_redaction() {
+        let mut rng = OsRng;
+        let mut sk = SpendingKey::random(&mut rng);
+        let fvk = sk.to_full_viewing_key();
+        let ivk = sk.to_incoming_viewing_key();
+        assert_eq!(format!("{:?}", sk), "SpendingKey(<redacted>)");
+        assert_eq!(format!("{:?}", fvk), "FullViewingKey(<redacted>)");
+        assert!(!format!("{:?}", ivk).contains(&format!("{:?}", sk.rcv_sk)));
+
+        sk.zeroize();
+        assert_eq!(sk.auth_sk, pallas::Scalar::zero());
+        assert_eq!(sk.nk, pallas::Base::zero());
+        assert_eq!(sk.rcv_sk, pallas::Base::zero());
+    }
+
+    #[test]
+    fn test_payment_address() {
+        let mut rng = OsRng;
+        let mut root = [0u8; 32];
+        rng.fill_bytes(&mut root);
+        let sk = SpendingKey::from_root(&root);
+        assert_eq!(SpendingKey::from_root(&root), sk);
+        let ivk = sk.to_incoming_viewing_key();
+        let receiver_vp_vk = pallas::Base::random(&mut rng);
+        let addr = ivk.to_payment_address(receiver_vp_vk);
+        // The address pks receive and authorize the notes, the auth key is not the decryption key
+        assert_eq!(addr.get_rcv_pk(), ivk.get_rcv_pk());
+        assert_eq!(addr.get_auth_pk(), sk.get_auth_pk());
+        assert_ne!(addr.get_auth_pk(), addr.get_rcv_pk());
+        assert_eq!(addr.get_nk_container(), sk.get_nk().to_commitment());
+        assert_eq!(addr.get_receiver_vp_vk(), receiver_vp_vk);
+        assert_eq!(addr.to_string().parse(), Ok(addr));
+    }
+
+    #[test]
+    fn test_viewing_keys() {
+        let mut rng = OsRng;
+        let sk = SpendingKey::random(&mut rng);
+        let fvk = sk.to_full_viewing_key();
+        let ivk = sk.to_incoming_viewing_key();
+        assert_eq!(fvk.to_incoming_viewing_key(), ivk);
+
+        // The note sent to the receiver
+        let rho = Nullifier::random(&mut rng);
+        let note = Note::new(
+            pallas::Base::random(&mut rng),
+            pallas::Base::random(&mut rng),
+            pallas::Base::random(&mut rng),
+            100,
+            sk.get_nk().to_commitment(),
+            rho,
+            true,
+            RandomSeed::random(&mut rng),
+        );
+        let public_inputs = encrypt_note(&mut rng, &note, &ivk.get_rcv_pk());
+
+        // The ivk decrypts the note and confirms the commitment
+        let DecryptedNote {
+            note: decrypted_note,
+            kind,
+        } = ivk.decrypt_note(&public_inputs).unwrap();
+        assert_eq!(decrypted_note, note);
+        // The note kind is not in the plaintext
+        assert_eq!(kind, NoteKind::External);
+        assert!(ivk.is_note_created(&decrypted_note, &[note.commitment()]));
+        // The same as the ciphertext published in a transaction
+        let encrypted_note = public_inputs.get_encrypted_note().unwrap();
+        assert_eq!(ivk.decrypt(&encrypted_note).unwrap().note, note);
+        // But it can't produce the nullifier
+        assert_eq!(decrypted_note.get_nf(), Err(NoteError::MissingNullifierKey));
+
+        // The fvk derives the same nullifier as the spending key
+        let spendable_note = Note {
+            nk_container: sk.get_nk(),
+            ..note
+        };
+        let nf = fvk.derive_nullifier(&decrypted_note).unwrap();
+        assert_eq!(Ok(nf), spendable_note.get_nf());
+        assert!(fvk.is_spent(&decrypted_note, &[nf]));
+        assert!(!fvk.is_spent(&decrypted_note, &[Nullifier::random(&mut rng)]));
+
+        // Other keys can't decrypt the note
+        let other_ivk = SpendingKey::random(&mut rng).to_incoming_viewing_key();
+        assert_eq!(
+            other_ivk.decrypt_note(&public_inputs),
+            Err(NoteError::WrongDecryptionKey)
+        );
+
+        // The sender pk is (0, 0), i.e. the identity
+        let mut instances = public_inputs.inner().to_vec();
+        instances[VP_CIRCUIT_NOTE_ENCRYPTION_PK_X_IDX] = pallas::Base::zero();
+        instances[VP_CIRCUIT_NOTE_ENCRYPTION_PK_Y_IDX] = pallas::Base::zero();
+        assert_eq!(
+            ivk.decrypt_note(&instances.into()),
+            Err(NoteError::MalformedCiphertext)
+        );
+    }
+
+    // A note sent to the key, or a random note sent to another key
+    fn random_encrypted_note<R: RngCore>(
+        mut rng: R,
+        sk: &SpendingKey,
+        to_key: bool,
+    ) -> (Note, EncryptedNote) {
+        let note = Note::new(
+            pallas::Base::random(&mut rng),
+            pallas::Base::random(&mut rng),
+            pallas::Base::random(&mut rng),
+            rng.next_u64(),
+            sk.get_nk().to_commitment(),
+            Nullifier::random(&mut rng),
+            true,
+            RandomSeed::random(&mut rng),
+        );
+        let rcv_pk = if to_key {
+            sk.to_incoming_viewing_key().get_rcv_pk()
+        } else {
+            pallas::Point::random(&mut rng)
+        };
+        let encrypted_note = encrypt_note(&mut rng, &note, &rcv_pk)
+            .get_encrypted_note()
+            .unwrap();
+        (note, encrypted_note)
+    }
+
+    #[test]
+    fn test_batch_decrypt() {
+        let mut rng = OsRng;
+        let sk = SpendingKey::random(&mut rng);
+        let ivk = sk.to_incoming_viewing_key();
+        let mut encrypted_notes: Vec<EncryptedNote> = (0..BATCH_DECRYPTION_THRESHOLD + 8)
+            .map(|i| random_encrypted_note(&mut rng, &sk, i % 3 == 0).1)
+            .collect();
+        // A ciphertext with random elements
+        encrypted_notes.push(EncryptedNote {
+            ciphertext: vec![pallas::Base::random(&mut rng); NOTE_ENCRYPTION_CIPHERTEXT_NUM].into(),
+            sender_pk: pallas::Point::random(&mut rng),
+        });
+
+        // The same as the single note path
+        let expected: Vec<Option<DecryptedNote>> = encrypted_notes
+            .iter()
+            .map(|encrypted_note| ivk.decrypt(encrypted_note).ok())
+            .collect();
+        assert_eq!(
+            expected.iter().filter(|note| note.is_some()).count(),
+            (0..BATCH_DECRYPTION_THRESHOLD + 8)
+                .filter(|i| i % 3 == 0)
+                .count()
+        );
+        assert_eq!(ivk.batch_decrypt(&encrypted_notes), expected);
+        assert_eq!(ivk.scan(&encrypted_notes), expected);
+        // Below the threshold
+        assert_eq!(ivk.scan(&encrypted_notes[..4]), expected[..4].to_vec());
+        assert!(ivk.batch_decrypt(&[]).is_empty());
+
+        let (note, encrypted_note) = random_encrypted_note(&mut rng, &sk, true);
+        assert_eq!(
+            ivk.batch_decrypt(&[encrypted_note]),
+            vec![Some(DecryptedNote {
+                note,
+                kind: NoteKind::External,
+            })]
+        );
+    }
+
+    // cargo test --release -- --ignored bench_batch_decrypt --nocapture
+    #[test]
+    #[ignore]
+    fn bench_batch_decrypt() {
+        use std::time::Instant;
+
+        let mut rng = OsRng;
+        let sk = SpendingKey::random(&mut rng);
+        let ivk = sk.to_incoming_viewing_key();
+        // Most of the ciphertexts of a scan are not sent to the key
+        let (_, encrypted_note) = random_encrypted_note(&mut rng, &sk, false);
+        let mut encrypted_notes: Vec<EncryptedNote> = (0..10_000)
+            .map(|_| EncryptedNote {
+                sender_pk: pallas::Point::random(&mut rng),
+                ..encrypted_note.clone()
+            })
+            .collect();
+        encrypted_notes[0] = random_encrypted_note(&mut rng, &sk, true).1;
+
+        let start = Instant::now();
+        let single: Vec<Option<DecryptedNote>> = encrypted_notes
+            .iter()
+            .map(|encrypted_note| ivk.decrypt(encrypted_note).ok())
+            .collect();
+        let single_time = start.elapsed();
+
+        let start = Instant::now();
+        let batched = ivk.batch_decrypt(&encrypted_notes);
+        let batched_time = start.elapsed();
+
+        assert_eq!(single, batched);
+        assert!(batched[0].is_some());
+        assert!(batched[1..].iter().all(Option::is_none));
+        // The timings are only reported, they depend on the machine
+        println!(
+            "10k ciphertexts: single {:?}, batched {:?}, speedup {:.2}x",
+            single_time,
+            batched_time,
+            single_time.as_secs_f64() / batched_time.as_secs_f64()
+        );
+    }
+}
@@ -2,21 +2,45 @@
 #![allow(clippy::large_enum_variant)]
 
 pub mod action;
+pub mod address;
+pub mod beacon;
 pub mod binding_signature;
+pub mod block;
 pub mod circuit;
 pub mod constant;
+pub mod cost;
 pub mod error;
 mod executable;
+pub mod explorer;
+pub mod keys;
+pub mod mempool;
 pub mod merkle_tree;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod note;
 pub mod note_encryption;
+pub mod note_selection;
 pub mod nullifier;
 pub mod proof;
+#[cfg(feature = "prover")]
+pub mod proving_session;
+#[cfg(feature = "examples")]
+pub mod receipt;
 pub mod shielded_ptx;
+pub mod simulation;
+pub mod solver;
+pub mod state;
 pub mod taiga_api;
+#[cfg(feature = "test_vectors")]
+pub mod test_vectors;
 pub mod transaction;
 pub mod transparent_ptx;
 pub mod utils;
 pub mod value_commitment;
+#[cfg(feature = "transcript")]
+pub mod verification_transcript;
+pub mod vk_cache;
 pub mod vp_commitment;
+#[cfg(feature = "borsh")]
+pub mod vp_registry;
 pub mod vp_vk;
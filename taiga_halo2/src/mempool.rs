@@ -0,0 +1,169 @@
+//! The pending partial transactions and transactions of a node.
+//!
+//! The proofs are verified when a ptx or a transaction is submitted, so the block builder only
+//! checks the state, see `BlockBuilder::build_block`. The ptxs wait for a solver to match them,
+//! they are removed once a transaction including them is submitted.
+use crate::error::TransactionError;
+use crate::executable::Executable;
+use crate::shielded_ptx::ShieldedPartialTransaction;
+use crate::state::TaigaState;
+use crate::transaction::{Transaction, TransactionId};
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum MempoolError {
+    /// The proofs or the binding signature are invalid.
+    Invalid(TransactionError),
+    /// The ptx or the transaction is already in the mempool.
+    Duplicate,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Invalid(e) => f.write_str(&format!("Invalid submission: {e}")),
+            MempoolError::Duplicate => f.write_str("Already in the mempool"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    // The ptxs waiting for a solver, e.g. the intents
+    ptxs: Vec<ShieldedPartialTransaction>,
+    // The transactions waiting for a block, in the submission order
+    txs: Vec<Transaction>,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ptxs(&self) -> &[ShieldedPartialTransaction] {
+        &self.ptxs
+    }
+
+    pub fn txs(&self) -> &[Transaction] {
+        &self.txs
+    }
+
+    /// Verifies the proofs of the ptx and shares it with the solvers.
+    pub fn submit_ptx(&mut self, ptx: ShieldedPartialTransaction) -> Result<(), MempoolError> {
+        let hash = ptx.content_hash();
+        if self
+            .ptxs
+            .iter()
+            .any(|existing| existing.content_hash() == hash)
+        {
+            return Err(MempoolError::Duplicate);
+        }
+        ptx.execute().map_err(MempoolError::Invalid)?;
+        self.ptxs.push(ptx);
+        Ok(())
+    }
+
+    /// Verifies the proofs and the binding signature of the transaction. The pending ptxs
+    /// included in the transaction are removed.
+    pub fn submit_tx(&mut self, tx: Transaction) -> Result<TransactionId, MempoolError> {
+        let id = tx.id();
+        if self.txs.iter().any(|existing| existing.id() == id) {
+            return Err(MempoolError::Duplicate);
+        }
+        tx.verify_proofs().map_err(MempoolError::Invalid)?;
+
+        let included: HashSet<_> = tx
+            .get_shielded_ptx_bundle()
+            .partial_txs()
+            .iter()
+            .map(|ptx| ptx.content_hash())
+            .collect();
+        self.ptxs
+            .retain(|ptx| !included.contains(&ptx.content_hash()));
+        self.txs.push(tx);
+        Ok(id)
+    }
+
+    /// Drops the ptxs and the transactions that can't be applied to the state anymore: a
+    /// nullifier is spent or an anchor is unknown, e.g. too old.
+    pub fn prune(&mut self, state: &impl TaigaState) {
+        let is_valid = |nfs: &[_], anchors: &[_]| {
+            nfs.iter().all(|nf| !state.contains_nullifier(nf))
+                && anchors.iter().all(|anchor| state.is_known_anchor(anchor))
+        };
+        self.ptxs
+            .retain(|ptx| is_valid(&ptx.get_nullifiers(), &ptx.get_anchors()));
+        self.txs
+            .retain(|tx| is_valid(&tx.get_nullifiers(), &tx.get_anchors()));
+    }
+
+    // Hands the transactions to the block builder, the deferred ones come back with return_txs
+    pub(crate) fn take_txs(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.txs)
+    }
+
+    // The returned transactions go before the ones submitted in between
+    pub(crate) fn return_txs(&mut self, mut txs: Vec<Transaction>) {
+        txs.append(&mut self.txs);
+        self.txs = txs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shielded_ptx::testing::{corrupt_action_proof, create_shielded_ptx};
+    use crate::state::CheckpointedState;
+    use crate::transaction::{ShieldedPartialTxBundle, TransparentPartialTxBundle};
+    use rand::rngs::OsRng;
+
+    fn build_tx(ptxs: Vec<ShieldedPartialTransaction>) -> Transaction {
+        Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(ptxs),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mempool_submit() {
+        let mut mempool = Mempool::new();
+        let ptx = create_shielded_ptx();
+        mempool.submit_ptx(ptx.clone()).unwrap();
+        assert!(matches!(
+            mempool.submit_ptx(ptx.clone()),
+            Err(MempoolError::Duplicate)
+        ));
+
+        let mut corrupted_ptx = create_shielded_ptx();
+        corrupt_action_proof(&mut corrupted_ptx, 0);
+        assert!(matches!(
+            mempool.submit_ptx(corrupted_ptx.clone()),
+            Err(MempoolError::Invalid(_))
+        ));
+        assert!(matches!(
+            mempool.submit_tx(build_tx(vec![corrupted_ptx])),
+            Err(MempoolError::Invalid(_))
+        ));
+
+        // The matched ptx leaves the pending ptxs
+        let other_ptx = create_shielded_ptx();
+        mempool.submit_ptx(other_ptx).unwrap();
+        let tx = build_tx(vec![ptx]);
+        let id = mempool.submit_tx(tx.clone()).unwrap();
+        assert_eq!(id, tx.id());
+        assert_eq!(mempool.ptxs().len(), 1);
+        assert_eq!(mempool.txs().len(), 1);
+        assert!(matches!(
+            mempool.submit_tx(tx),
+            Err(MempoolError::Duplicate)
+        ));
+
+        // The anchors of the test ptxs are random, they are unknown to the state
+        mempool.prune(&CheckpointedState::default());
+        assert!(mempool.ptxs().is_empty());
+        assert!(mempool.txs().is_empty());
+    }
+}
@@ -1,10 +1,17 @@
+use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
 
 use crate::merkle_tree::LR::{L, R};
 use crate::note::NoteCommitment;
 use crate::utils::poseidon_hash;
-use crate::{constant::TAIGA_COMMITMENT_TREE_DEPTH, note::Note};
-use ff::PrimeField;
+use crate::{
+    constant::{MERKLE_CRH_DOMAIN, TAIGA_COMMITMENT_TREE_DEPTH},
+    error::NoteError,
+    note::Note,
+};
+use ff::{PrimeField, PrimeFieldBits};
+use halo2_gadgets::{sinsemilla::primitives as sinsemilla, utilities::i2lebsp};
 use halo2_proofs::arithmetic::Field;
 use pasta_curves::pallas;
 use rand::distributions::{Distribution, Standard};
@@ -20,7 +27,7 @@ use serde;
 use borsh::{BorshDeserialize, BorshSerialize};
 
 /// The root of the note commitment Merkletree.
-#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default)]
 #[cfg_attr(feature = "nif", derive(NifTuple))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Anchor(pallas::Base);
@@ -45,8 +52,8 @@ impl From<pallas::Base> for Anchor {
     }
 }
 
-impl From<Node> for Anchor {
-    fn from(node: Node) -> Anchor {
+impl<H: MerkleHash> From<Node<H>> for Anchor {
+    fn from(node: Node<H>) -> Anchor {
         Anchor(node.0)
     }
 }
@@ -110,33 +117,120 @@ impl Distribution<LR> for Standard {
     }
 }
 
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for super::PoseidonMerkleHash {}
+    impl Sealed for super::SinsemillaMerkleHash {}
+}
+
+/// The hash of the commitment tree, `level` is the height of the children(the leaves are at level
+/// 0). The trait is sealed, the hash is picked with one of the marker types below. The nodes, the
+/// paths and the action circuit are all typed by the marker, so a path of one hash can't be
+/// checked against a circuit of the other.
+pub trait MerkleHash:
+    sealed::Sealed + Copy + Clone + Debug + Default + PartialEq + Eq + Hash + Send + Sync + 'static
+{
+    fn combine(level: usize, left: &Node<Self>, right: &Node<Self>) -> Node<Self>;
+}
+
+/// poseidon_hash(left, right), the level is not hashed. It's the default and the one used by the
+/// partial transactions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PoseidonMerkleHash;
+
+impl MerkleHash for PoseidonMerkleHash {
+    fn combine(_level: usize, left: &Node<Self>, right: &Node<Self>) -> Node<Self> {
+        Node::from(poseidon_hash(left.inner(), right.inner()))
+    }
+}
+
+/// The Orchard MerkleCRH: sinsemilla(l || left || right) with the level l in 10 bits and the
+/// 255-bit nodes, under the MERKLE_CRH_PERSONALIZATION.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SinsemillaMerkleHash;
+
+impl MerkleHash for SinsemillaMerkleHash {
+    fn combine(level: usize, left: &Node<Self>, right: &Node<Self>) -> Node<Self> {
+        let node_bits = pallas::Base::NUM_BITS as usize;
+        let (left_bits, right_bits) = (left.inner().to_le_bits(), right.inner().to_le_bits());
+        let message = i2lebsp::<{ sinsemilla::K }>(level as u64)
+            .into_iter()
+            .chain(left_bits.iter().by_vals().take(node_bits))
+            .chain(right_bits.iter().by_vals().take(node_bits));
+        // The hash fails with negligible probability, Orchard maps it to zero as well
+        Node::from(
+            MERKLE_CRH_DOMAIN
+                .hash(message)
+                .unwrap_or(pallas::Base::zero()),
+        )
+    }
+}
+
 /// A path from a position in a particular commitment tree to the root of that tree.
-/// In Orchard merkle tree, they are using MerkleCRH(layer, left, right), where MerkleCRH is a sinsemilla. We are using poseidon_hash(left, right).
+/// The nodes are combined with the MerkleHash H, poseidon_hash(left, right) by default.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct MerklePath {
-    merkle_path: Vec<(Node, LR)>,
+pub struct MerklePath<H: MerkleHash = PoseidonMerkleHash> {
+    merkle_path: Vec<(Node<H>, LR)>,
 }
 
-impl MerklePath {
+impl<H: MerkleHash> MerklePath<H> {
     /// Constructs a random dummy merkle path with depth. Only used in tests.
     pub fn random(rng: &mut impl RngCore, depth: usize) -> Self {
         let merkle_path = (0..depth).map(|_| (Node::rand(rng), rng.gen())).collect();
         Self::from_path(merkle_path)
     }
     /// Constructs a Merkle path directly from a path.
-    pub fn from_path(merkle_path: Vec<(Node, LR)>) -> Self {
+    pub fn from_path(merkle_path: Vec<(Node<H>, LR)>) -> Self {
         MerklePath { merkle_path }
     }
 
+    /// Same as from_path, but rejects the paths whose depth is not TAIGA_COMMITMENT_TREE_DEPTH,
+    /// which the action circuit can't take.
+    pub fn try_from_path(merkle_path: Vec<(Node<H>, LR)>) -> Result<Self, NoteError> {
+        Self::try_from_path_with_depth(merkle_path, TAIGA_COMMITMENT_TREE_DEPTH)
+    }
+
+    /// Same as try_from_path for a tree of another depth.
+    pub fn try_from_path_with_depth(
+        merkle_path: Vec<(Node<H>, LR)>,
+        depth: usize,
+    ) -> Result<Self, NoteError> {
+        if merkle_path.len() != depth {
+            return Err(NoteError::MerklePathDepthMismatch {
+                expected: depth,
+                actual: merkle_path.len(),
+            });
+        }
+        Ok(Self::from_path(merkle_path))
+    }
+
+    pub fn depth(&self) -> usize {
+        self.merkle_path.len()
+    }
+
+    /// The leaf index of the path: the bit l is set when the node at level l is a right child,
+    /// i.e. its sibling is on the left.
+    pub fn position(&self) -> u64 {
+        self.merkle_path
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, lr))| is_left(*lr))
+            .fold(0, |position, (level, _)| position | (1 << level))
+    }
+
     /// Returns the root of the tree corresponding to this path applied to `leaf`.
-    pub fn root(&self, leaf: Node) -> Anchor {
+    pub fn root(&self, leaf: Node<H>) -> Anchor {
         let mut root = leaf;
-        for val in self.merkle_path.iter() {
+        for (level, val) in self.merkle_path.iter().enumerate() {
             root = match val.1 {
-                R => Node::combine(&root, &val.0),
-                L => Node::combine(&val.0, &root),
+                R => H::combine(level, &root, &val.0),
+                L => H::combine(level, &val.0, &root),
             }
         }
         root.into()
@@ -149,10 +243,20 @@ impl MerklePath {
             .map(|(node, b)| (node.inner(), *b))
             .collect()
     }
+
+    /// The path witnessed by the action circuit of tree depth DEPTH.
+    pub fn to_array<const DEPTH: usize>(&self) -> Result<[(Node<H>, LR); DEPTH], NoteError> {
+        self.merkle_path.clone().try_into().map_err(|path: Vec<_>| {
+            NoteError::MerklePathDepthMismatch {
+                expected: DEPTH,
+                actual: path.len(),
+            }
+        })
+    }
 }
 
-impl Default for MerklePath {
-    fn default() -> MerklePath {
+impl<H: MerkleHash> Default for MerklePath<H> {
+    fn default() -> MerklePath<H> {
         let merkle_path = (0..TAIGA_COMMITMENT_TREE_DEPTH)
             .map(|_| (Node::from(pallas::Base::one()), L))
             .collect();
@@ -160,45 +264,52 @@ impl Default for MerklePath {
     }
 }
 
-/// A node within the Sapling commitment tree.
+/// A node within the commitment tree of the MerkleHash H.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct Node(pallas::Base);
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Node<H: MerkleHash = PoseidonMerkleHash>(
+    pallas::Base,
+    #[cfg_attr(feature = "serde", serde(skip))] PhantomData<H>,
+);
 
-impl Node {
+impl<H: MerkleHash> Node<H> {
     pub fn rand(rng: &mut impl RngCore) -> Self {
-        Self(pallas::Base::random(rng))
+        Self::from(pallas::Base::random(rng))
     }
 
     pub fn inner(&self) -> pallas::Base {
         self.0
     }
+}
 
+impl Node {
+    // The poseidon hash doesn't take the level
     pub fn combine(left: &Node, right: &Node) -> Node {
-        Self(poseidon_hash(left.inner(), right.inner()))
+        PoseidonMerkleHash::combine(0, left, right)
     }
 }
 
-impl From<pallas::Base> for Node {
-    fn from(node: pallas::Base) -> Node {
-        Node(node)
+impl<H: MerkleHash> From<pallas::Base> for Node<H> {
+    fn from(node: pallas::Base) -> Node<H> {
+        Node(node, PhantomData)
     }
 }
 
-impl From<&Note> for Node {
-    fn from(note: &Note) -> Node {
-        Node(note.commitment().inner())
+impl<H: MerkleHash> From<&Note> for Node<H> {
+    fn from(note: &Note) -> Node<H> {
+        Node::from(note.commitment().inner())
     }
 }
 
-impl From<NoteCommitment> for Node {
-    fn from(cm: NoteCommitment) -> Node {
-        Node(cm.inner())
+impl<H: MerkleHash> From<NoteCommitment> for Node<H> {
+    fn from(cm: NoteCommitment) -> Node<H> {
+        Node::from(cm.inner())
     }
 }
 
 #[cfg(feature = "borsh")]
-impl BorshSerialize for Node {
+impl<H: MerkleHash> BorshSerialize for Node<H> {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
         writer.write_all(&self.0.to_repr())?;
         Ok(())
@@ -206,19 +317,173 @@ impl BorshSerialize for Node {
 }
 
 #[cfg(feature = "borsh")]
-impl BorshDeserialize for Node {
+impl<H: MerkleHash> BorshDeserialize for Node<H> {
     fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
         let mut repr = [0u8; 32];
         reader.read_exact(&mut repr)?;
         let value = Option::from(pallas::Base::from_repr(repr)).ok_or_else(|| {
             std::io::Error::new(std::io::ErrorKind::InvalidData, "Node value not in field")
         })?;
-        Ok(Self(value))
+        Ok(Self::from(value))
     }
 }
 
-impl Hash for Node {
-    fn hash<H: Hasher>(&self, state: &mut H) {
+impl<H: MerkleHash> Hash for Node<H> {
+    fn hash<S: Hasher>(&self, state: &mut S) {
         self.0.to_repr().hash(state);
     }
 }
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use super::{MerklePath, Node, LR};
+    use crate::utils::testing::arb_base;
+    use proptest::prelude::*;
+
+    pub fn arb_node() -> impl Strategy<Value = Node> {
+        arb_base().prop_map(Node::from)
+    }
+
+    pub fn arb_lr() -> impl Strategy<Value = LR> {
+        any::<bool>().prop_map(|is_left| if is_left { LR::L } else { LR::R })
+    }
+
+    pub fn arb_merkle_path(depth: usize) -> impl Strategy<Value = MerklePath> {
+        prop::collection::vec((arb_node(), arb_lr()), depth).prop_map(MerklePath::from_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        testing::arb_node, MerkleHash, MerklePath, Node, PoseidonMerkleHash, SinsemillaMerkleHash,
+        L, R,
+    };
+    use crate::{constant::TAIGA_COMMITMENT_TREE_DEPTH, error::NoteError};
+    use halo2_proofs::arithmetic::Field;
+    use pasta_curves::pallas;
+    use proptest::prelude::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_merkle_path_depth() {
+        let path: MerklePath = MerklePath::random(&mut OsRng, TAIGA_COMMITMENT_TREE_DEPTH);
+        assert_eq!(
+            MerklePath::try_from_path(path.merkle_path.clone()),
+            Ok(path)
+        );
+
+        let short_path: MerklePath =
+            MerklePath::random(&mut OsRng, TAIGA_COMMITMENT_TREE_DEPTH - 1);
+        assert_eq!(
+            MerklePath::try_from_path(short_path.merkle_path),
+            Err(NoteError::MerklePathDepthMismatch {
+                expected: TAIGA_COMMITMENT_TREE_DEPTH,
+                actual: TAIGA_COMMITMENT_TREE_DEPTH - 1,
+            })
+        );
+
+        // Other depths
+        let path: MerklePath = MerklePath::random(&mut OsRng, 8);
+        assert_eq!(path.depth(), 8);
+        assert_eq!(
+            MerklePath::try_from_path_with_depth(path.merkle_path.clone(), 8),
+            Ok(path.clone())
+        );
+        assert_eq!(path.to_array::<8>().unwrap().to_vec(), path.merkle_path);
+        assert_eq!(
+            path.to_array::<TAIGA_COMMITMENT_TREE_DEPTH>(),
+            Err(NoteError::MerklePathDepthMismatch {
+                expected: TAIGA_COMMITMENT_TREE_DEPTH,
+                actual: 8,
+            })
+        );
+    }
+
+    // The path of the leaf at the position in the complete tree of the leaves
+    fn rebuild_root_and_path<H: MerkleHash>(
+        leaves: &[Node<H>],
+        position: usize,
+    ) -> (Node<H>, MerklePath<H>) {
+        let mut level = leaves.to_vec();
+        let mut height = 0;
+        let mut index = position;
+        let mut path = vec![];
+        while level.len() > 1 {
+            // The sibling is on the right(R) of the left nodes
+            if index % 2 == 0 {
+                path.push((level[index + 1], R));
+            } else {
+                path.push((level[index - 1], L));
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| H::combine(height, &pair[0], &pair[1]))
+                .collect();
+            index /= 2;
+            height += 1;
+        }
+        (level[0], MerklePath::from_path(path))
+    }
+
+    proptest! {
+        #[test]
+        fn merkle_path_root_consistency(
+            (leaves, position) in (1usize..5)
+                .prop_flat_map(|depth| prop::collection::vec(arb_node(), 1 << depth))
+                .prop_flat_map(|leaves| {
+                    let len = leaves.len();
+                    (Just(leaves), 0..len)
+                })
+        ) {
+            let (root, path) = rebuild_root_and_path(&leaves, position);
+            prop_assert_eq!(path.root(leaves[position]).inner(), root.inner());
+            // A different leaf doesn't open to the root
+            let other = (position + 1) % leaves.len();
+            if leaves[other] != leaves[position] {
+                prop_assert_ne!(path.root(leaves[other]).inner(), root.inner());
+            }
+        }
+    }
+
+    #[test]
+    fn test_merkle_hash_backends() {
+        let leaves: Vec<pallas::Base> = (0..8).map(|_| pallas::Base::random(OsRng)).collect();
+        let poseidon_leaves: Vec<Node<PoseidonMerkleHash>> =
+            leaves.iter().map(|leaf| Node::from(*leaf)).collect();
+        let sinsemilla_leaves: Vec<Node<SinsemillaMerkleHash>> =
+            leaves.iter().map(|leaf| Node::from(*leaf)).collect();
+
+        let (poseidon_root, poseidon_path) = rebuild_root_and_path(&poseidon_leaves, 5);
+        let (sinsemilla_root, sinsemilla_path) = rebuild_root_and_path(&sinsemilla_leaves, 5);
+        assert_eq!(
+            poseidon_path.root(poseidon_leaves[5]).inner(),
+            poseidon_root.inner()
+        );
+        assert_eq!(
+            sinsemilla_path.root(sinsemilla_leaves[5]).inner(),
+            sinsemilla_root.inner()
+        );
+        assert_ne!(poseidon_root.inner(), sinsemilla_root.inner());
+
+        // The sinsemilla hash takes the level, the same children give another node one level up
+        let (left, right) = (&sinsemilla_leaves[0], &sinsemilla_leaves[1]);
+        assert_ne!(
+            SinsemillaMerkleHash::combine(0, left, right),
+            SinsemillaMerkleHash::combine(1, left, right)
+        );
+
+        // The siblings of a path rehashed with the other backend don't open to the root
+        let rehashed: MerklePath<SinsemillaMerkleHash> = MerklePath::from_path(
+            poseidon_path
+                .merkle_path
+                .iter()
+                .map(|(node, lr)| (Node::from(node.inner()), *lr))
+                .collect(),
+        );
+        assert_ne!(
+            rehashed.root(sinsemilla_leaves[5]).inner(),
+            poseidon_root.inner()
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Timing instrumentation for keygen, witness synthesis and proving.
+//!
+//! The events are recorded into a thread-local sink. Nothing is recorded unless a sink is
+//! set with `set_metrics_sink`, and the whole module is compiled out without the `metrics` feature.
+use halo2_proofs::poly::commitment::Params;
+#[cfg(feature = "prover")]
+use halo2_proofs::{
+    circuit::Layouter,
+    plonk::{Circuit, ConstraintSystem, Error},
+};
+#[cfg(feature = "prover")]
+use pasta_curves::pallas;
+use pasta_curves::vesta;
+#[cfg(feature = "prover")]
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProvingStage {
+    Keygen,
+    // The synthesis of the circuit witnesses, it's part of the proving
+    WitnessSynthesis,
+    Proving,
+    PartialTransactionBuild,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProvingEvent {
+    pub stage: ProvingStage,
+    // The circuit(or partial transaction) label
+    pub circuit: String,
+    pub k: Option<u32>,
+    pub duration: Duration,
+    pub proof_size: Option<usize>,
+}
+
+pub trait MetricsSink {
+    fn record(&self, event: ProvingEvent);
+}
+
+/// ProvingMetrics collects all the recorded events.
+#[derive(Debug, Default)]
+pub struct ProvingMetrics {
+    events: RefCell<Vec<ProvingEvent>>,
+}
+
+impl ProvingMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<ProvingEvent> {
+        self.events.borrow().clone()
+    }
+
+    pub fn events_of(&self, stage: ProvingStage) -> Vec<ProvingEvent> {
+        self.events
+            .borrow()
+            .iter()
+            .filter(|event| event.stage == stage)
+            .cloned()
+            .collect()
+    }
+
+    pub fn total_duration(&self, stage: ProvingStage) -> Duration {
+        self.events_of(stage)
+            .iter()
+            .map(|event| event.duration)
+            .sum()
+    }
+
+    pub fn clear(&self) {
+        self.events.borrow_mut().clear();
+    }
+}
+
+impl MetricsSink for ProvingMetrics {
+    fn record(&self, event: ProvingEvent) {
+        self.events.borrow_mut().push(event);
+    }
+}
+
+thread_local! {
+    static METRICS_SINK: RefCell<Option<Rc<dyn MetricsSink>>> = RefCell::new(None);
+}
+
+/// Sets the sink of the current thread.
+pub fn set_metrics_sink(sink: Rc<dyn MetricsSink>) {
+    METRICS_SINK.with(|s| *s.borrow_mut() = Some(sink));
+}
+
+/// Removes the sink of the current thread.
+pub fn clear_metrics_sink() {
+    METRICS_SINK.with(|s| *s.borrow_mut() = None);
+}
+
+pub fn record(event: ProvingEvent) {
+    METRICS_SINK.with(|s| {
+        if let Some(sink) = s.borrow().as_ref() {
+            sink.record(event);
+        }
+    });
+}
+
+pub fn record_span(
+    stage: ProvingStage,
+    circuit: &str,
+    k: Option<u32>,
+    start: Instant,
+    proof_size: Option<usize>,
+) {
+    record(ProvingEvent {
+        stage,
+        circuit: circuit.to_string(),
+        k,
+        duration: start.elapsed(),
+        proof_size,
+    });
+}
+
+// The params size k, where n = 2^k is the number of generators.
+pub fn params_k(params: &Params<vesta::Affine>) -> u32 {
+    params.get_g().len().trailing_zeros()
+}
+
+// Wraps a circuit to time its synthesis in the prover. The floor planner may synthesize the
+// circuit more than once, e.g. to measure the regions first, so the durations add up.
+#[cfg(feature = "prover")]
+pub(crate) struct TimedCircuit<C> {
+    circuit: C,
+    synthesis_duration: Cell<Duration>,
+}
+
+#[cfg(feature = "prover")]
+impl<C> TimedCircuit<C> {
+    pub(crate) fn new(circuit: C) -> Self {
+        Self {
+            circuit,
+            synthesis_duration: Cell::new(Duration::ZERO),
+        }
+    }
+
+    pub(crate) fn synthesis_duration(&self) -> Duration {
+        self.synthesis_duration.get()
+    }
+}
+
+#[cfg(feature = "prover")]
+impl<C: Circuit<pallas::Base>> Circuit<pallas::Base> for TimedCircuit<C> {
+    type Config = C::Config;
+    type FloorPlanner = C::FloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::new(self.circuit.without_witnesses())
+    }
+
+    fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+        C::configure(meta)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        layouter: impl Layouter<pallas::Base>,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+        let result = self.circuit.synthesize(config, layouter);
+        self.synthesis_duration
+            .set(self.synthesis_duration.get() + start.elapsed());
+        result
+    }
+}
+
+// Get the circuit label from the type name, e.g. "TokenValidityPredicateCircuit".
+pub(crate) fn circuit_label<C>() -> &'static str {
+    let name = std::any::type_name::<C>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+#[test]
+fn test_metrics_sink() {
+    let metrics = Rc::new(ProvingMetrics::new());
+    // Nothing is recorded without a sink
+    record_span(
+        ProvingStage::Keygen,
+        "circuit",
+        Some(15),
+        Instant::now(),
+        None,
+    );
+
+    set_metrics_sink(metrics.clone());
+    record_span(
+        ProvingStage::Keygen,
+        "circuit",
+        Some(15),
+        Instant::now(),
+        None,
+    );
+    record_span(
+        ProvingStage::Proving,
+        circuit_label::<ProvingMetrics>(),
+        Some(15),
+        Instant::now(),
+        Some(100),
+    );
+    clear_metrics_sink();
+    record_span(
+        ProvingStage::Keygen,
+        "circuit",
+        Some(15),
+        Instant::now(),
+        None,
+    );
+
+    let events = metrics.events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(metrics.events_of(ProvingStage::Proving).len(), 1);
+    assert_eq!(events[1].circuit, "ProvingMetrics");
+    assert_eq!(events[1].proof_size, Some(100));
+}
@@ -1,17 +1,24 @@
 use crate::{
+    beacon::commit_padding_seed,
     circuit::{
         vp_circuit::ValidityPredicate,
         vp_examples::{TrivialValidityPredicateCircuit, COMPRESSED_TRIVIAL_VP_VK},
     },
     constant::{
-        NUM_NOTE, POSEIDON_TO_CURVE_INPUT_LEN, PRF_EXPAND_PERSONALIZATION, PRF_EXPAND_PSI,
-        PRF_EXPAND_PUBLIC_INPUT_PADDING, PRF_EXPAND_RCM, PRF_EXPAND_VCM_R,
+        NOTE_COMMITMENT_POSEIDON_DOMAIN, NUM_NOTE, PADDING_SEED_PERSONALIZATION,
+        POSEIDON_TO_CURVE_INPUT_LEN, PRF_EXPAND_PADDING_INPUT_NOTE, PRF_EXPAND_PADDING_OUTPUT_NOTE,
+        PRF_EXPAND_PERSONALIZATION, PRF_EXPAND_PSI, PRF_EXPAND_PUBLIC_INPUT_PADDING,
+        PRF_EXPAND_RCM, PRF_EXPAND_VCM_R, PRF_EXPAND_VP_PADDING_SEED,
     },
+    error::{NoteError, TransactionError},
     merkle_tree::{Anchor, MerklePath, Node},
-    nullifier::{Nullifier, NullifierKeyContainer},
+    note_encryption::NoteEncryption,
+    nullifier::{Nullifier, NullifierKeyContainer, NullifierKeyWitness},
+    shielded_ptx::NoteVPVerifyingInfoSet,
     utils::{poseidon_hash_n, poseidon_to_curve},
 };
 use blake2b_simd::Params as Blake2bParams;
+use blake2s_simd::Params as Blake2sParams;
 use ff::{FromUniformBytes, PrimeField};
 use halo2_proofs::arithmetic::Field;
 use pasta_curves::pallas;
@@ -19,6 +26,11 @@ use rand::RngCore;
 use std::hash::{Hash, Hasher};
 use subtle::CtOption;
 
+pub mod encoding;
+
+#[cfg(feature = "prover")]
+use crate::proving_session::ProvingHook;
+
 #[cfg(feature = "nif")]
 use rustler::{NifStruct, NifTuple};
 
@@ -83,6 +95,69 @@ impl Hash for NoteCommitment {
     }
 }
 
+pub const NOTE_COMMITMENT_PREIMAGE_LEN: usize = 10;
+
+/// The canonical order of the note commitment preimage, shared by the native and circuit paths.
+pub const NOTE_COMMITMENT_PREIMAGE_LABELS: [&str; NOTE_COMMITMENT_PREIMAGE_LEN] = [
+    "domain",
+    "app_vk",
+    "app_data_static",
+    "app_data_dynamic",
+    "nk_commitment",
+    "rho",
+    "psi",
+    "is_merkle_checked",
+    "value",
+    "rcm",
+];
+
+/// The preimage of a note commitment, generic over native field elements and circuit cells.
+/// Every field is exactly one field element (value and is_merkle_checked are embedded as
+/// integers, nk is replaced by its commitment), and the hash has a constant length, so two
+/// different preimages can't be encoded to the same message. The NOTE_COMMITMENT_POSEIDON_DOMAIN
+/// goes first to separate note commitments from the other poseidon hashes of the same length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteCommitmentPreimage<F> {
+    pub app_vk: F,
+    pub app_data_static: F,
+    pub app_data_dynamic: F,
+    pub nk_commitment: F,
+    pub rho: F,
+    pub psi: F,
+    pub is_merkle_checked: F,
+    pub value: F,
+    pub rcm: F,
+}
+
+impl<F> NoteCommitmentPreimage<F> {
+    // Lays out the domain and the preimage in the order of NOTE_COMMITMENT_PREIMAGE_LABELS
+    pub fn to_field_elements_with_domain(self, domain: F) -> [F; NOTE_COMMITMENT_PREIMAGE_LEN] {
+        [
+            domain,
+            self.app_vk,
+            self.app_data_static,
+            self.app_data_dynamic,
+            self.nk_commitment,
+            self.rho,
+            self.psi,
+            self.is_merkle_checked,
+            self.value,
+            self.rcm,
+        ]
+    }
+}
+
+impl NoteCommitmentPreimage<pallas::Base> {
+    pub fn to_field_elements(self) -> [pallas::Base; NOTE_COMMITMENT_PREIMAGE_LEN] {
+        self.to_field_elements_with_domain(NOTE_COMMITMENT_POSEIDON_DOMAIN)
+    }
+
+    // note_commitment = poseidon_hash(NOTE_COMMITMENT_POSEIDON_DOMAIN || app_vk || app_data_static || app_data_dynamic || nk_commitment || rho || psi || is_merkle_checked || value || rcm)
+    pub fn commitment(self) -> NoteCommitment {
+        NoteCommitment(poseidon_hash_n(self.to_field_elements()))
+    }
+}
+
 /// A note
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 #[cfg_attr(feature = "nif", derive(NifStruct))]
@@ -97,7 +172,8 @@ pub struct Note {
     pub value: u64,
     /// NullifierKeyContainer contains the nullifier_key or the nullifier_key commitment.
     pub nk_container: NullifierKeyContainer,
-    /// old nullifier. Nonce which is a deterministically computed, unique nonce
+    /// old nullifier. Nonce which is a deterministically computed, unique nonce. It's the nf of
+    /// the input note of the action, so notes with the same content get distinct nullifiers.
     pub rho: Nullifier,
     /// psi is to derive the nullifier
     pub psi: pallas::Base,
@@ -119,7 +195,8 @@ pub struct NoteType {
     pub app_data_static: pallas::Base,
 }
 
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RandomSeed([u8; 32]);
 
 #[derive(Clone)]
@@ -131,11 +208,29 @@ pub struct InputNoteProvingInfo {
     dynamic_vps: Vec<Box<ValidityPredicate>>,
 }
 
+/// An input note waiting for the nullifier from the device holding its nk. It only knows the nk
+/// commitment of the note, see `InputNoteProvingInfo::prepare`.
+#[derive(Clone)]
+pub struct PendingInput {
+    info: InputNoteProvingInfo,
+}
+
+/// The data the nk holder needs to derive the nullifier of a pending input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NullifierChallenge {
+    pub nk_commitment: pallas::Base,
+    pub rho: pallas::Base,
+    pub psi: pallas::Base,
+    pub cm: NoteCommitment,
+}
+
 #[derive(Clone)]
 pub struct OutputNoteProvingInfo {
     pub note: Note,
     application_vp: Box<ValidityPredicate>,
     dynamic_vps: Vec<Box<ValidityPredicate>>,
+    // The encryption of the note, the encryption vp publishes the ciphertext. None if the note is not sent with an encrypted payload.
+    note_encryption: Option<NoteEncryption>,
 }
 
 impl Note {
@@ -189,37 +284,53 @@ impl Note {
     }
 
     pub fn random_padding_input_note<R: RngCore>(mut rng: R) -> Self {
-        let app_vk = *COMPRESSED_TRIVIAL_VP_VK;
         let app_data_static = pallas::Base::random(&mut rng);
-        let note_type = NoteType::new(app_vk, app_data_static);
         let app_data_dynamic = pallas::Base::random(&mut rng);
         let rho = Nullifier::from(pallas::Base::random(&mut rng));
         let nk = NullifierKeyContainer::from_key(pallas::Base::random(&mut rng));
         let rseed = RandomSeed::random(&mut rng);
-        Note {
-            note_type,
-            app_data_dynamic,
-            value: 0,
-            nk_container: nk,
-            rho,
-            psi: rseed.get_psi(&rho),
-            rcm: rseed.get_rcm(&rho),
-            is_merkle_checked: false,
-        }
+        Self::padding_note(app_data_static, app_data_dynamic, nk, rho, rseed)
     }
 
     pub fn random_padding_output_note<R: RngCore>(mut rng: R, rho: Nullifier) -> Self {
-        let app_vk = *COMPRESSED_TRIVIAL_VP_VK;
         let app_data_static = pallas::Base::random(&mut rng);
-        let note_type = NoteType::new(app_vk, app_data_static);
         let app_data_dynamic = pallas::Base::random(&mut rng);
         let nk_com = NullifierKeyContainer::from_commitment(pallas::Base::random(&mut rng));
         let rseed = RandomSeed::random(&mut rng);
+        Self::padding_note(app_data_static, app_data_dynamic, nk_com, rho, rseed)
+    }
+
+    // The index-th padding input note of the ptx, all the fields are derived from the ptx seed so
+    // the ptx can be rebuilt byte for byte. They are pseudorandom and look like the random ones.
+    pub fn padding_input_from_seed(seed: &RandomSeed, index: usize) -> Self {
+        let rseed = seed.get_padding_note_rseed(PRF_EXPAND_PADDING_INPUT_NOTE, index);
+        let fields = rseed.get_random_padding(4);
+        let rho = Nullifier::from(fields[2]);
+        let nk = NullifierKeyContainer::from_key(fields[3]);
+        Self::padding_note(fields[0], fields[1], nk, rho, rseed)
+    }
+
+    // The output analog of padding_input_from_seed, rho is the nf of the paired input note
+    pub fn padding_output_from_seed(seed: &RandomSeed, index: usize, rho: Nullifier) -> Self {
+        let rseed = seed.get_padding_note_rseed(PRF_EXPAND_PADDING_OUTPUT_NOTE, index);
+        let fields = rseed.get_random_padding(3);
+        let nk_com = NullifierKeyContainer::from_commitment(fields[2]);
+        Self::padding_note(fields[0], fields[1], nk_com, rho, rseed)
+    }
+
+    fn padding_note(
+        app_data_static: pallas::Base,
+        app_data_dynamic: pallas::Base,
+        nk_container: NullifierKeyContainer,
+        rho: Nullifier,
+        rseed: RandomSeed,
+    ) -> Self {
+        let note_type = NoteType::new(*COMPRESSED_TRIVIAL_VP_VK, app_data_static);
         Note {
             note_type,
             app_data_dynamic,
             value: 0,
-            nk_container: nk_com,
+            nk_container,
             rho,
             psi: rseed.get_psi(&rho),
             rcm: rseed.get_rcm(&rho),
@@ -227,23 +338,25 @@ impl Note {
         }
     }
 
-    // note_commitment = poseidon_hash(app_vk || app_data_static || app_data_dynamic || nk_commitment || rho || psi || is_merkle_checked || value || rcm)
     pub fn commitment(&self) -> NoteCommitment {
-        let ret = poseidon_hash_n([
-            self.get_app_vk(),
-            self.get_app_data_static(),
-            self.app_data_dynamic,
-            self.get_nk_commitment(),
-            self.rho.inner(),
-            self.psi,
-            pallas::Base::from(self.is_merkle_checked as u64),
-            pallas::Base::from(self.value),
-            self.rcm,
-        ]);
-        NoteCommitment(ret)
+        self.commitment_preimage().commitment()
+    }
+
+    pub fn commitment_preimage(&self) -> NoteCommitmentPreimage<pallas::Base> {
+        NoteCommitmentPreimage {
+            app_vk: self.get_app_vk(),
+            app_data_static: self.get_app_data_static(),
+            app_data_dynamic: self.app_data_dynamic,
+            nk_commitment: self.get_nk_commitment(),
+            rho: self.rho.inner(),
+            psi: self.psi,
+            is_merkle_checked: pallas::Base::from(self.is_merkle_checked as u64),
+            value: pallas::Base::from(self.value),
+            rcm: self.rcm,
+        }
     }
 
-    pub fn get_nf(&self) -> Option<Nullifier> {
+    pub fn get_nf(&self) -> Result<Nullifier, NoteError> {
         Nullifier::derive(
             &self.nk_container,
             &self.rho.inner(),
@@ -261,6 +374,12 @@ impl Note {
     }
 
     pub fn get_note_type(&self) -> pallas::Point {
+        self.value_base()
+    }
+
+    // The value base of the note, derived from NoteType::new(app_vk, app_data_static).
+    // Notes with the same value base are fungible in the balance check.
+    pub fn value_base(&self) -> pallas::Point {
         self.note_type.derive_note_type()
     }
 
@@ -290,25 +409,14 @@ impl Note {
 impl BorshSerialize for Note {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
         use byteorder::{LittleEndian, WriteBytesExt};
-        // Write app_vk
-        writer.write_all(&self.note_type.app_vk.to_repr())?;
-        // Write app_data_static
-        writer.write_all(&self.note_type.app_data_static.to_repr())?;
+        // Write note_type
+        self.note_type.serialize(writer)?;
         // Write app_data_dynamic
         writer.write_all(&self.app_data_dynamic.to_repr())?;
         // Write note value
         writer.write_u64::<LittleEndian>(self.value)?;
         // Write nk_container
-        match self.nk_container {
-            NullifierKeyContainer::Commitment(nk) => {
-                writer.write_u8(1)?;
-                writer.write_all(&nk.to_repr())
-            }
-            NullifierKeyContainer::Key(nk) => {
-                writer.write_u8(2)?;
-                writer.write_all(&nk.to_repr())
-            }
-        }?;
+        self.nk_container.serialize(writer)?;
         // Write rho
         writer.write_all(&self.rho.to_bytes())?;
         // Write psi
@@ -327,18 +435,8 @@ impl BorshDeserialize for Note {
     fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
         use byteorder::{LittleEndian, ReadBytesExt};
         use std::io;
-        // Read app_vk
-        let mut app_vk_bytes = [0u8; 32];
-        reader.read_exact(&mut app_vk_bytes)?;
-        let app_vk = Option::from(pallas::Base::from_repr(app_vk_bytes))
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "app_vk not in field"))?;
-        // Read app_data_static
-        let mut app_data_static_bytes = [0u8; 32];
-        reader.read_exact(&mut app_data_static_bytes)?;
-        let app_data_static = Option::from(pallas::Base::from_repr(app_data_static_bytes))
-            .ok_or_else(|| {
-                io::Error::new(io::ErrorKind::InvalidData, "app_data_static not in field")
-            })?;
+        // Read note_type
+        let note_type = NoteType::deserialize_reader(reader)?;
         // Read app_data_dynamic
         let mut app_data_dynamic_bytes = [0u8; 32];
         reader.read_exact(&mut app_data_dynamic_bytes)?;
@@ -349,18 +447,7 @@ impl BorshDeserialize for Note {
         // Read note value
         let value = reader.read_u64::<LittleEndian>()?;
         // Read nk_container
-        let mut nk_container_type = [0u8; 1];
-        reader.read_exact(&mut nk_container_type)?;
-        let nk_container_type = nk_container_type[0];
-        let mut nk_container_bytes = [0u8; 32];
-        reader.read_exact(&mut nk_container_bytes)?;
-        let nk = Option::from(pallas::Base::from_repr(nk_container_bytes))
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "nk not in field"))?;
-        let nk_container = if nk_container_type == 0x01 {
-            NullifierKeyContainer::from_commitment(nk)
-        } else {
-            NullifierKeyContainer::from_key(nk)
-        };
+        let nk_container = NullifierKeyContainer::deserialize_reader(reader)?;
         // Read rho
         let mut rho_bytes = [0u8; 32];
         reader.read_exact(&mut rho_bytes)?;
@@ -379,28 +466,87 @@ impl BorshDeserialize for Note {
         // Read is_merkle_checked
         let mut is_merkle_checked_byte = [0u8; 1];
         reader.read_exact(&mut is_merkle_checked_byte)?;
-        let is_merkle_checked_byte = is_merkle_checked_byte[0];
-        let is_merkle_checked = is_merkle_checked_byte == 0x01;
+        let is_merkle_checked = match is_merkle_checked_byte[0] {
+            0x00 => false,
+            0x01 => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "is_merkle_checked is not a bool",
+                ))
+            }
+        };
         // Construct note
-        Ok(Note::from_full(
-            app_vk,
-            app_data_static,
+        Ok(Note {
+            note_type,
             app_data_dynamic,
             value,
             nk_container,
             rho,
-            is_merkle_checked,
             psi,
             rcm,
-        ))
+            is_merkle_checked,
+        })
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshSerialize for NoteType {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        // Write app_vk
+        writer.write_all(&self.app_vk.to_repr())?;
+        // Write app_data_static
+        writer.write_all(&self.app_data_static.to_repr())?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshDeserialize for NoteType {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        use std::io;
+        // Read app_vk
+        let mut app_vk_bytes = [0u8; 32];
+        reader.read_exact(&mut app_vk_bytes)?;
+        let app_vk = Option::from(pallas::Base::from_repr(app_vk_bytes))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "app_vk not in field"))?;
+        // Read app_data_static
+        let mut app_data_static_bytes = [0u8; 32];
+        reader.read_exact(&mut app_data_static_bytes)?;
+        let app_data_static = Option::from(pallas::Base::from_repr(app_data_static_bytes))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "app_data_static not in field")
+            })?;
+        Ok(NoteType::new(app_vk, app_data_static))
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshSerialize for RandomSeed {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.0)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshDeserialize for RandomSeed {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        // Any 32 bytes are a valid random seed
+        let mut rseed = [0u8; 32];
+        reader.read_exact(&mut rseed)?;
+        Ok(Self(rseed))
     }
 }
 
 impl NoteType {
-    pub fn new(vk: pallas::Base, data: pallas::Base) -> Self {
+    // Create the NoteType of an application.
+    // app_vk is the compressed vk of the application vp, NOT the app_data_static.
+    // The value base is derived as poseidon_to_curve(app_vk || app_data_static).
+    pub fn new(app_vk: pallas::Base, app_data_static: pallas::Base) -> Self {
         Self {
-            app_vk: vk,
-            app_data_static: data,
+            app_vk,
+            app_data_static,
         }
     }
 
@@ -478,6 +624,50 @@ impl RandomSeed {
         pallas::Scalar::from_uniform_bytes(&bytes)
     }
 
+    // The seed of the index-th padding note, the tag separates the input and output padding notes
+    pub fn get_padding_note_rseed(&self, tag: u8, index: usize) -> RandomSeed {
+        let mut h = Blake2bParams::new()
+            .hash_length(32)
+            .personal(PRF_EXPAND_PERSONALIZATION)
+            .to_state();
+        h.update(&[tag]);
+        h.update(&(index as u64).to_le_bytes());
+        h.update(&self.0);
+        let mut rseed = [0; 32];
+        rseed.copy_from_slice(h.finalize().as_bytes());
+        RandomSeed(rseed)
+    }
+
+    /// The seed opened by `opening`, or None if the opening doesn't match the Blake2s commitment,
+    /// see `beacon::commit_padding_seed`.
+    pub fn from_commitment(seed_commitment: [u8; 32], opening: &[u8]) -> Option<Self> {
+        if commit_padding_seed(opening) != seed_commitment {
+            return None;
+        }
+        let h = Blake2sParams::new()
+            .hash_length(32)
+            .personal(PADDING_SEED_PERSONALIZATION)
+            .hash(opening);
+        Some(Self(h.as_bytes().try_into().unwrap()))
+    }
+
+    // The seed of the public input padding rng of the vp_index-th vp of the owned note
+    pub fn get_vp_padding_seed(
+        &self,
+        owned_note_pub_id: &pallas::Base,
+        vp_index: usize,
+    ) -> [u8; 32] {
+        let mut h = Blake2bParams::new()
+            .hash_length(32)
+            .personal(PRF_EXPAND_PERSONALIZATION)
+            .to_state();
+        h.update(&[PRF_EXPAND_VP_PADDING_SEED]);
+        h.update(&owned_note_pub_id.to_repr());
+        h.update(&(vp_index as u64).to_le_bytes());
+        h.update(&self.0);
+        h.finalize().as_bytes().try_into().unwrap()
+    }
+
     pub fn get_vp_cm_r(&self, tag: u8) -> pallas::Base {
         let mut h = Blake2bParams::new()
             .hash_length(64)
@@ -520,6 +710,43 @@ impl InputNoteProvingInfo {
         self.dynamic_vps.clone()
     }
 
+    // The leaf index of the note in the commitment tree, as witnessed by the merkle path
+    pub fn position(&self) -> u64 {
+        self.merkle_path.position()
+    }
+
+    // The action circuit checks the anchor is the root of the merkle path from the note, unless
+    // the note is ephemeral. It's checked natively before proving.
+    pub fn check_anchor(&self) -> Result<(), TransactionError> {
+        if self.note.is_merkle_checked && self.note.calculate_root(&self.merkle_path) != self.anchor
+        {
+            return Err(TransactionError::InconsistentAnchor);
+        }
+        Ok(())
+    }
+
+    // Creates the vp proofs, consuming the vp circuits. The nf of the note must be derivable.
+    #[cfg(feature = "prover")]
+    pub(crate) fn prove_vps(
+        self,
+        padding_seed: Option<&RandomSeed>,
+        hook: &mut dyn ProvingHook,
+    ) -> Result<(Note, MerklePath, Anchor, NoteVPVerifyingInfoSet), TransactionError> {
+        let owned_note_pub_id = self.note.get_nf().unwrap().inner();
+        let vp_info = NoteVPVerifyingInfoSet::prove_with_hook(
+            self.application_vp,
+            self.dynamic_vps,
+            padding_seed.map(|seed| (seed, owned_note_pub_id)),
+            hook,
+        )?;
+        Ok((self.note, self.merkle_path, self.anchor, vp_info))
+    }
+
+    #[cfg(feature = "prover")]
+    pub(crate) fn dynamic_vp_num(&self) -> usize {
+        self.dynamic_vps.len()
+    }
+
     pub fn create_padding_note_proving_info(
         padding_note: Note,
         merkle_path: MerklePath,
@@ -527,13 +754,82 @@ impl InputNoteProvingInfo {
         input_notes: [Note; NUM_NOTE],
         output_notes: [Note; NUM_NOTE],
     ) -> Self {
-        let trivail_vp = Box::new(TrivialValidityPredicateCircuit {
-            owned_note_pub_id: padding_note.get_nf().unwrap().inner(),
+        let trivail_vp = Box::new(TrivialValidityPredicateCircuit::new(
+            padding_note.get_nf().unwrap().inner(),
             input_notes,
             output_notes,
-        });
+        ));
         InputNoteProvingInfo::new(padding_note, merkle_path, Some(anchor), trivail_vp, vec![])
     }
+
+    // The first step of proving a note whose nk is held by a separate device, the nk is dropped
+    // from the note if present. Note the vps open the input notes and witness nk as well.
+    pub fn prepare(
+        note: Note,
+        merkle_path: MerklePath,
+        custom_anchor: Option<Anchor>,
+        application_vp: Box<ValidityPredicate>,
+        dynamic_vps: Vec<Box<ValidityPredicate>>,
+    ) -> PendingInput {
+        let note = Note {
+            nk_container: note.nk_container.to_commitment(),
+            ..note
+        };
+        PendingInput {
+            info: InputNoteProvingInfo::new(
+                note,
+                merkle_path,
+                custom_anchor,
+                application_vp,
+                dynamic_vps,
+            ),
+        }
+    }
+}
+
+impl PendingInput {
+    pub fn note(&self) -> &Note {
+        &self.info.note
+    }
+
+    pub fn challenge(&self) -> NullifierChallenge {
+        let note = &self.info.note;
+        NullifierChallenge {
+            nk_commitment: note.get_nk_commitment(),
+            rho: note.rho.inner(),
+            psi: note.psi,
+            cm: note.commitment(),
+        }
+    }
+
+    // Checks the nullifier and the nk witness from the device before attaching the witness.
+    pub fn finalize(
+        self,
+        nf: Nullifier,
+        nk_witness: NullifierKeyWitness,
+    ) -> Result<InputNoteProvingInfo, TransactionError> {
+        let mut info = self.info;
+        let note = Note {
+            nk_container: nk_witness.to_container(),
+            ..info.note
+        };
+        if note.get_nk_commitment() != info.note.get_nk_commitment() || note.get_nf() != Ok(nf) {
+            return Err(TransactionError::InvalidNullifierWitness);
+        }
+        info.note = note;
+        Ok(info)
+    }
+}
+
+impl NullifierChallenge {
+    // Run by the nk holder, returns None if nk doesn't open the nk commitment of the note.
+    pub fn derive_nullifier(&self, nk: pallas::Base) -> Option<Nullifier> {
+        let nk = NullifierKeyContainer::from_key(nk);
+        if nk.get_commitment() != self.nk_commitment {
+            return None;
+        }
+        Nullifier::derive(&nk, &self.rho, &self.psi, &self.cm).ok()
+    }
 }
 
 impl OutputNoteProvingInfo {
@@ -546,6 +842,7 @@ impl OutputNoteProvingInfo {
             note,
             application_vp,
             dynamic_vps,
+            note_encryption: None,
         }
     }
 
@@ -557,20 +854,101 @@ impl OutputNoteProvingInfo {
         self.dynamic_vps.clone()
     }
 
+    // Creates the vp proofs, consuming the vp circuits
+    #[cfg(feature = "prover")]
+    pub(crate) fn prove_vps(
+        self,
+        padding_seed: Option<&RandomSeed>,
+        hook: &mut dyn ProvingHook,
+    ) -> Result<(Note, Option<NoteEncryption>, NoteVPVerifyingInfoSet), TransactionError> {
+        let owned_note_pub_id = self.note.commitment().inner();
+        let vp_info = NoteVPVerifyingInfoSet::prove_with_hook(
+            self.application_vp,
+            self.dynamic_vps,
+            padding_seed.map(|seed| (seed, owned_note_pub_id)),
+            hook,
+        )?;
+        Ok((self.note, self.note_encryption, vp_info))
+    }
+
+    #[cfg(feature = "prover")]
+    pub(crate) fn dynamic_vp_num(&self) -> usize {
+        self.dynamic_vps.len()
+    }
+
+    // Bind the ciphertext published by the encryption vp to the action of the note, the
+    // encryption must be the one of the vp
+    pub fn set_note_encryption(&mut self, note_encryption: NoteEncryption) {
+        self.note_encryption = Some(note_encryption);
+    }
+
+    pub fn get_note_encryption(&self) -> Option<NoteEncryption> {
+        self.note_encryption
+    }
+
     pub fn create_padding_note_proving_info(
         padding_note: Note,
         input_notes: [Note; NUM_NOTE],
         output_notes: [Note; NUM_NOTE],
     ) -> Self {
-        let trivail_vp = Box::new(TrivialValidityPredicateCircuit {
-            owned_note_pub_id: padding_note.commitment().inner(),
+        let trivail_vp = Box::new(TrivialValidityPredicateCircuit::new(
+            padding_note.commitment().inner(),
             input_notes,
             output_notes,
-        });
+        ));
         OutputNoteProvingInfo::new(padding_note, trivail_vp, vec![])
     }
 }
 
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use super::{Note, RandomSeed};
+    use crate::{
+        nullifier::{Nullifier, NullifierKeyContainer},
+        utils::testing::arb_base,
+    };
+    use proptest::prelude::*;
+
+    pub fn arb_random_seed() -> impl Strategy<Value = RandomSeed> {
+        any::<[u8; 32]>().prop_map(RandomSeed::from_bytes)
+    }
+
+    // The input notes have the nk, the output notes only have the nk commitment
+    pub fn arb_nullifier_key_container() -> impl Strategy<Value = NullifierKeyContainer> {
+        (arb_base(), any::<bool>()).prop_map(|(nk, is_key)| {
+            if is_key {
+                NullifierKeyContainer::from_key(nk)
+            } else {
+                NullifierKeyContainer::from_commitment(nk)
+            }
+        })
+    }
+
+    prop_compose! {
+        pub fn arb_note()(
+            app_vk in arb_base(),
+            app_data_static in arb_base(),
+            app_data_dynamic in arb_base(),
+            value in any::<u64>(),
+            nk_container in arb_nullifier_key_container(),
+            rho in arb_base(),
+            is_merkle_checked in any::<bool>(),
+            rseed in arb_random_seed(),
+        ) -> Note {
+            Note::new(
+                app_vk,
+                app_data_static,
+                app_data_dynamic,
+                value,
+                nk_container,
+                Nullifier::from(rho),
+                is_merkle_checked,
+                rseed,
+            )
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::{InputNoteProvingInfo, Note, NoteType, OutputNoteProvingInfo, RandomSeed};
@@ -641,9 +1019,139 @@ pub mod tests {
             note,
             application_vp,
             dynamic_vps,
+            note_encryption: None,
         }
     }
 
+    #[test]
+    fn test_two_step_input_proving_info() {
+        use crate::action::ActionInfo;
+        use crate::constant::ACTION_CIRCUIT_PARAMS_SIZE;
+        use crate::error::TransactionError;
+        use crate::note::NullifierChallenge;
+        use crate::nullifier::NullifierKeyWitness;
+        use halo2_proofs::dev::MockProver;
+        use rand::rngs::OsRng;
+
+        // The nk never leaves the device, except as the proving witness
+        struct Device {
+            nk: pallas::Base,
+        }
+
+        impl Device {
+            fn nk_commitment(&self) -> pallas::Base {
+                NullifierKeyContainer::from_key(self.nk).get_commitment()
+            }
+
+            fn respond(
+                &self,
+                challenge: &NullifierChallenge,
+            ) -> Option<(Nullifier, NullifierKeyWitness)> {
+                let nf = challenge.derive_nullifier(self.nk)?;
+                Some((nf, NullifierKeyWitness::new(self.nk)))
+            }
+        }
+
+        let mut rng = OsRng;
+        let device = Device {
+            nk: pallas::Base::random(&mut rng),
+        };
+
+        // The host only knows the nk commitment
+        let mut note = random_input_note(&mut rng);
+        note.nk_container = NullifierKeyContainer::from_commitment(device.nk_commitment());
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let app_vp = Box::new(random_trivial_vp_circuit(&mut rng));
+        let pending = InputNoteProvingInfo::prepare(note, merkle_path, None, app_vp, vec![]);
+        let challenge = pending.challenge();
+        assert_eq!(challenge.cm, note.commitment());
+
+        // A nk that doesn't open the nk commitment is refused by the device
+        assert!(challenge
+            .derive_nullifier(pallas::Base::random(&mut rng))
+            .is_none());
+
+        // A wrong nullifier or nk witness is rejected
+        let (nf, _) = device.respond(&challenge).unwrap();
+        assert!(matches!(
+            pending.clone().finalize(
+                Nullifier::from(pallas::Base::random(&mut rng)),
+                device.respond(&challenge).unwrap().1,
+            ),
+            Err(TransactionError::InvalidNullifierWitness)
+        ));
+        assert!(matches!(
+            pending
+                .clone()
+                .finalize(nf, NullifierKeyWitness::new(pallas::Base::random(&mut rng))),
+            Err(TransactionError::InvalidNullifierWitness)
+        ));
+
+        let (nf, nk_witness) = device.respond(&challenge).unwrap();
+        let input_info = pending.finalize(nf, nk_witness).unwrap();
+        assert_eq!(input_info.note.commitment(), note.commitment());
+        assert_eq!(input_info.note.get_nf(), Ok(nf));
+
+        // The action circuit is satisfied by the finalized input
+        let output_note = random_output_note(&mut rng, nf);
+        let output_info = OutputNoteProvingInfo::new(
+            output_note,
+            Box::new(random_trivial_vp_circuit(&mut rng)),
+            vec![],
+        );
+        let action_info = ActionInfo::from_proving_info(input_info, output_info, &mut rng);
+        let (action, action_circuit) = action_info.build();
+        let prover = MockProver::<pallas::Base>::run(
+            ACTION_CIRCUIT_PARAMS_SIZE,
+            &action_circuit,
+            vec![action.to_vec()],
+        )
+        .unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    // Fixed vectors, the note commitment must not change across refactors
+    #[test]
+    fn note_commitment_test_vectors() {
+        use crate::note::NoteCommitment;
+
+        let note_from_fields = |fields: [pallas::Base; 7], value, is_merkle_checked| Note {
+            note_type: NoteType {
+                app_vk: fields[0],
+                app_data_static: fields[1],
+            },
+            app_data_dynamic: fields[2],
+            value,
+            nk_container: NullifierKeyContainer::from_commitment(fields[3]),
+            rho: Nullifier::from(fields[4]),
+            psi: fields[5],
+            rcm: fields[6],
+            is_merkle_checked,
+        };
+
+        let small = [1u64, 2, 3, 4, 5, 6, 8].map(pallas::Base::from);
+        assert_eq!(
+            note_from_fields(small, 7, true).commitment(),
+            NoteCommitment::from(pallas::Base::from_raw([
+                0xdc3fc2444ab85db8,
+                0xd1db8bc1e8795b6d,
+                0x4d69d8eff63064ba,
+                0x048aae760c0dab59,
+            ]))
+        );
+
+        let large = [1u64, 2, 3, 4, 5, 6, 7].map(|x| -pallas::Base::from(x));
+        assert_eq!(
+            note_from_fields(large, u64::MAX, false).commitment(),
+            NoteCommitment::from(pallas::Base::from_raw([
+                0xc81564a8fd369b5f,
+                0xf0c5dec2f832b1ea,
+                0x589a337cf66fb7e1,
+                0x0b97ab63acb2b6c9,
+            ]))
+        );
+    }
+
     #[cfg(feature = "borsh")]
     #[test]
     fn note_borsh_serialization_test() {
@@ -690,5 +1198,188 @@ pub mod tests {
                 BorshDeserialize::deserialize(&mut borsh.as_ref()).unwrap();
             assert_eq!(ocm, de_ocm);
         }
+
+        // The deserialized note recomputes the same commitment and nullifier
+        {
+            let borsh = borsh::to_vec(&input_note).unwrap();
+            let de_note: Note = BorshDeserialize::deserialize(&mut borsh.as_ref()).unwrap();
+            assert_eq!(de_note.commitment(), icm);
+            assert_eq!(de_note.get_nf(), input_note.get_nf());
+        }
+
+        let rseed = RandomSeed::random(&mut rng);
+        {
+            let borsh = borsh::to_vec(&rseed).unwrap();
+            let de_rseed: RandomSeed = BorshDeserialize::deserialize(&mut borsh.as_ref()).unwrap();
+            assert_eq!(rseed, de_rseed);
+        }
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn note_borsh_rejection_test() {
+        use borsh::BorshDeserialize;
+        use ff::PrimeField;
+        use rand::rngs::OsRng;
+
+        let mut rng = OsRng;
+        let note = random_input_note(&mut rng);
+        let borsh = borsh::to_vec(&note).unwrap();
+        // app_vk(32) || app_data_static(32) || app_data_dynamic(32) || value(8) || nk tag(1) || nk(32) || rho(32) || psi(32) || rcm(32) || is_merkle_checked(1)
+        assert_eq!(borsh.len(), 234);
+
+        // The field elements are encoded in the canonical little-endian form
+        assert_eq!(borsh[..32], note.get_app_vk().to_repr());
+
+        // Out-of-range field element
+        let mut out_of_range = borsh.clone();
+        out_of_range[64..96].copy_from_slice(&[0xff; 32]);
+        assert!(Note::deserialize(&mut out_of_range.as_ref()).is_err());
+
+        // The field modulus itself is not canonical
+        let mut modulus = (-pallas::Base::one()).to_repr();
+        modulus[0] += 1;
+        let mut non_canonical = borsh.clone();
+        non_canonical[201..233].copy_from_slice(&modulus);
+        assert!(Note::deserialize(&mut non_canonical.as_ref()).is_err());
+
+        // Invalid nk container tag
+        let mut invalid_tag = borsh.clone();
+        invalid_tag[104] = 0;
+        assert!(Note::deserialize(&mut invalid_tag.as_ref()).is_err());
+
+        // Invalid is_merkle_checked byte
+        let mut invalid_flag = borsh;
+        invalid_flag[233] = 2;
+        assert!(Note::deserialize(&mut invalid_flag.as_ref()).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn note_serde_serialization_test() {
+        use rand::rngs::OsRng;
+
+        let mut rng = OsRng;
+        let input_note = random_input_note(&mut rng);
+        let output_note = random_output_note(&mut rng, input_note.get_nf().unwrap());
+        for note in [input_note, output_note] {
+            let json = serde_json::to_string(&note).unwrap();
+            let de_note: Note = serde_json::from_str(&json).unwrap();
+            assert_eq!(note, de_note);
+            assert_eq!(note.commitment(), de_note.commitment());
+        }
+
+        let rseed = RandomSeed::random(&mut rng);
+        let json = serde_json::to_string(&rseed).unwrap();
+        let de_rseed: RandomSeed = serde_json::from_str(&json).unwrap();
+        assert_eq!(rseed, de_rseed);
+    }
+
+    #[test]
+    fn test_faerie_gold_notes() {
+        use rand::rngs::OsRng;
+
+        let mut rng = OsRng;
+        // Two payments of the same content to the same receiver, from two input notes
+        let input_notes = [random_input_note(&mut rng), random_input_note(&mut rng)];
+        let template = random_output_note(&mut rng, random_nullifier(&mut rng));
+        let rseed = RandomSeed::random(&mut rng);
+        let nk = random_nullifier_key(&mut rng);
+        let [note_1, note_2] = input_notes.map(|input_note| {
+            let rho = input_note.get_nf().unwrap();
+            Note {
+                nk_container: nk,
+                rho,
+                psi: rseed.get_psi(&rho),
+                rcm: rseed.get_rcm(&rho),
+                ..template
+            }
+        });
+
+        // The rho chaining makes the notes and their nullifiers distinct, so both are spendable
+        assert_ne!(note_1.commitment(), note_2.commitment());
+        assert_ne!(note_1.get_nf().unwrap(), note_2.get_nf().unwrap());
+
+        // A copy of note_1 needs the nf of the same input note as rho, so the action creating
+        // it reveals that nf again and is rejected as a double spend
+        assert_eq!(note_1.rho, input_notes[0].get_nf().unwrap());
+    }
+
+    #[test]
+    fn test_padding_notes_from_seed() {
+        use crate::circuit::vp_examples::COMPRESSED_TRIVIAL_VP_VK;
+        use rand::rngs::OsRng;
+
+        let seed = RandomSeed::random(OsRng);
+        let input = Note::padding_input_from_seed(&seed, 0);
+        let output = Note::padding_output_from_seed(&seed, 0, input.get_nf().unwrap());
+
+        // The same seed gives the same padding notes
+        let same_input = Note::padding_input_from_seed(&seed, 0);
+        let same_output = Note::padding_output_from_seed(&seed, 0, same_input.get_nf().unwrap());
+        assert_eq!(input, same_input);
+        assert_eq!(input.commitment(), same_input.commitment());
+        assert_eq!(output.commitment(), same_output.commitment());
+
+        // Another index or seed gives other notes
+        let other_index = Note::padding_input_from_seed(&seed, 1);
+        assert_ne!(input.commitment(), other_index.commitment());
+        assert_ne!(input.get_nf().unwrap(), other_index.get_nf().unwrap());
+        let other_seed = Note::padding_input_from_seed(&RandomSeed::random(OsRng), 0);
+        assert_ne!(input.commitment(), other_seed.commitment());
+
+        // The input and output padding notes are domain separated
+        let output_with_same_rho = Note::padding_output_from_seed(&seed, 0, input.rho);
+        assert_ne!(input.get_rcm(), output_with_same_rho.get_rcm());
+        assert_ne!(input.get_app_data_static(), output.get_app_data_static());
+
+        // The padding notes carry no value and skip the merkle check, like the random ones
+        for note in [input, output] {
+            assert_eq!(note.value, 0);
+            assert!(!note.is_merkle_checked);
+            assert_eq!(note.get_app_vk(), *COMPRESSED_TRIVIAL_VP_VK);
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn note_commitment_stability(note in super::testing::arb_note()) {
+            // The commitment only depends on the note fields
+            let rebuilt = Note::from_full(
+                note.get_app_vk(),
+                note.get_app_data_static(),
+                note.app_data_dynamic,
+                note.value,
+                note.nk_container,
+                note.rho,
+                note.is_merkle_checked,
+                note.psi,
+                note.rcm,
+            );
+            proptest::prop_assert_eq!(rebuilt.commitment(), note.commitment());
+        }
+
+        #[cfg(feature = "borsh")]
+        #[test]
+        fn note_borsh_round_trip(note in super::testing::arb_note()) {
+            use borsh::BorshDeserialize;
+
+            let bytes = borsh::to_vec(&note).unwrap();
+            proptest::prop_assert_eq!(bytes.len(), crate::taiga_api::NOTE_SIZE);
+            let de_note: Note = BorshDeserialize::deserialize(&mut bytes.as_ref()).unwrap();
+            proptest::prop_assert_eq!(de_note, note);
+            proptest::prop_assert_eq!(de_note.commitment(), note.commitment());
+            proptest::prop_assert_eq!(de_note.get_nf(), note.get_nf());
+        }
+
+        #[cfg(feature = "borsh")]
+        #[test]
+        fn random_seed_borsh_round_trip(rseed in super::testing::arb_random_seed()) {
+            use borsh::BorshDeserialize;
+
+            let bytes = borsh::to_vec(&rseed).unwrap();
+            let de_rseed: RandomSeed = BorshDeserialize::deserialize(&mut bytes.as_ref()).unwrap();
+            proptest::prop_assert_eq!(de_rseed, rseed);
+        }
     }
 }
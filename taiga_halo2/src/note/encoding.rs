@@ -0,0 +1,400 @@
+/// Typed schemas for the application data packed into a field element, e.g. the app_data_static
+/// and app_data_dynamic of notes.
+/// The values are flattened to a canonical sequence of field elements, and the encoding is the
+/// poseidon hash of the schema id followed by the sequence. The schema id is the domain tag, so
+/// the same values under different schemas are encoded differently.
+use crate::{
+    circuit::gadgets::{assign_free_constant, poseidon_hash::poseidon_hash_slice_gadget},
+    constant::POSEIDON_HASH_MAX_LEN,
+    utils::poseidon_hash_slice,
+};
+use ff::PrimeField;
+use group::{Curve, Group};
+use halo2_gadgets::poseidon::Pow5Config as PoseidonConfig;
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    circuit::{AssignedCell, Layouter},
+    plonk::{Advice, Column, Error},
+};
+use pasta_curves::pallas;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaticDataField {
+    Base,
+    U64,
+    // Split into two 128-bit halves, the lower half first
+    Bytes32,
+    // The affine coordinates (x, y), the identity is not encodable
+    Point,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaticDataValue {
+    Base(pallas::Base),
+    U64(u64),
+    Bytes32([u8; 32]),
+    Point(pallas::Point),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingError {
+    /// The number of values or field elements doesn't match the schema.
+    LengthMismatch,
+    /// The value at the index doesn't have the type of the schema field.
+    TypeMismatch(usize),
+    /// The field element at the index is not a valid encoding of the schema field.
+    InvalidElement(usize),
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use EncodingError::*;
+        match self {
+            LengthMismatch => f.write_str("The data length doesn't match the schema"),
+            TypeMismatch(i) => write!(f, "The value {i} doesn't match the schema field type"),
+            InvalidElement(i) => write!(f, "The field element {i} is not a valid encoding"),
+        }
+    }
+}
+
+impl StaticDataField {
+    // The number of field elements in the canonical sequence
+    pub fn element_len(&self) -> usize {
+        match self {
+            StaticDataField::Base | StaticDataField::U64 => 1,
+            StaticDataField::Bytes32 | StaticDataField::Point => 2,
+        }
+    }
+}
+
+impl StaticDataValue {
+    pub fn field(&self) -> StaticDataField {
+        match self {
+            StaticDataValue::Base(_) => StaticDataField::Base,
+            StaticDataValue::U64(_) => StaticDataField::U64,
+            StaticDataValue::Bytes32(_) => StaticDataField::Bytes32,
+            StaticDataValue::Point(_) => StaticDataField::Point,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticDataSchema {
+    id: pallas::Base,
+    fields: Vec<StaticDataField>,
+}
+
+impl StaticDataSchema {
+    // Panics if the encoding doesn't fit in one poseidon hash
+    pub fn new(id: pallas::Base, fields: Vec<StaticDataField>) -> Self {
+        let schema = Self { id, fields };
+        assert!(schema.field_elements_len() < POSEIDON_HASH_MAX_LEN);
+        schema
+    }
+
+    pub fn id(&self) -> pallas::Base {
+        self.id
+    }
+
+    pub fn fields(&self) -> &[StaticDataField] {
+        &self.fields
+    }
+
+    // The length of the canonical field element sequence, without the schema id
+    pub fn field_elements_len(&self) -> usize {
+        self.fields.iter().map(|field| field.element_len()).sum()
+    }
+
+    pub fn to_field_elements(
+        &self,
+        values: &[StaticDataValue],
+    ) -> Result<Vec<pallas::Base>, EncodingError> {
+        if values.len() != self.fields.len() {
+            return Err(EncodingError::LengthMismatch);
+        }
+        let mut elements = vec![];
+        for (i, (field, value)) in self.fields.iter().zip(values.iter()).enumerate() {
+            if value.field() != *field {
+                return Err(EncodingError::TypeMismatch(i));
+            }
+            match value {
+                StaticDataValue::Base(v) => elements.push(*v),
+                StaticDataValue::U64(v) => elements.push(pallas::Base::from(*v)),
+                StaticDataValue::Bytes32(bytes) => {
+                    let (lo, hi) = bytes.split_at(16);
+                    elements.push(pallas::Base::from_u128(u128::from_le_bytes(
+                        lo.try_into().unwrap(),
+                    )));
+                    elements.push(pallas::Base::from_u128(u128::from_le_bytes(
+                        hi.try_into().unwrap(),
+                    )));
+                }
+                StaticDataValue::Point(point) => {
+                    if bool::from(point.is_identity()) {
+                        return Err(EncodingError::InvalidElement(elements.len()));
+                    }
+                    let coord = point.to_affine().coordinates().unwrap();
+                    elements.push(*coord.x());
+                    elements.push(*coord.y());
+                }
+            }
+        }
+        Ok(elements)
+    }
+
+    pub fn from_field_elements(
+        &self,
+        elements: &[pallas::Base],
+    ) -> Result<Vec<StaticDataValue>, EncodingError> {
+        if elements.len() != self.field_elements_len() {
+            return Err(EncodingError::LengthMismatch);
+        }
+        // Returns the value if the top bytes of the element are zero
+        let to_bytes = |i: usize, len: usize| {
+            let repr = elements[i].to_repr();
+            if repr[len..].iter().any(|&b| b != 0) {
+                return Err(EncodingError::InvalidElement(i));
+            }
+            Ok(repr)
+        };
+        let mut values = vec![];
+        let mut i = 0;
+        for field in self.fields.iter() {
+            let value = match field {
+                StaticDataField::Base => StaticDataValue::Base(elements[i]),
+                StaticDataField::U64 => {
+                    let repr = to_bytes(i, 8)?;
+                    StaticDataValue::U64(u64::from_le_bytes(repr[..8].try_into().unwrap()))
+                }
+                StaticDataField::Bytes32 => {
+                    let mut bytes = [0u8; 32];
+                    bytes[..16].copy_from_slice(&to_bytes(i, 16)?[..16]);
+                    bytes[16..].copy_from_slice(&to_bytes(i + 1, 16)?[..16]);
+                    StaticDataValue::Bytes32(bytes)
+                }
+                StaticDataField::Point => {
+                    let point: pallas::Point = Option::<pallas::Affine>::from(
+                        pallas::Affine::from_xy(elements[i], elements[i + 1]),
+                    )
+                    .map(pallas::Point::from)
+                    .ok_or(EncodingError::InvalidElement(i))?;
+                    // (0, 0) is decoded as the identity
+                    if bool::from(point.is_identity()) {
+                        return Err(EncodingError::InvalidElement(i));
+                    }
+                    StaticDataValue::Point(point)
+                }
+            };
+            i += field.element_len();
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    pub fn encode(&self, values: &[StaticDataValue]) -> Result<pallas::Base, EncodingError> {
+        let elements = self.to_field_elements(values)?;
+        Ok(poseidon_hash_slice(
+            &[&[self.id], elements.as_slice()].concat(),
+        ))
+    }
+
+    // Encode the canonical field element cells in circuit, the schema id is loaded as a constant.
+    // It only checks the encoding, the u64 and bytes32 cells are not range checked here.
+    pub fn encode_gadget(
+        &self,
+        config: PoseidonConfig<pallas::Base, 3, 2>,
+        advice: Column<Advice>,
+        mut layouter: impl Layouter<pallas::Base>,
+        elements: &[AssignedCell<pallas::Base, pallas::Base>],
+    ) -> Result<AssignedCell<pallas::Base, pallas::Base>, Error> {
+        if elements.len() != self.field_elements_len() {
+            return Err(Error::Synthesis);
+        }
+        let id = assign_free_constant(layouter.namespace(|| "schema id"), advice, self.id)?;
+        poseidon_hash_slice_gadget(
+            config,
+            layouter.namespace(|| "schema encoding"),
+            &[&[id], elements].concat(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::arithmetic::Field;
+    use rand::rngs::OsRng;
+    use rand::RngCore;
+
+    fn schema(id: u64) -> StaticDataSchema {
+        StaticDataSchema::new(
+            pallas::Base::from(id),
+            vec![
+                StaticDataField::Base,
+                StaticDataField::U64,
+                StaticDataField::Bytes32,
+                StaticDataField::Point,
+            ],
+        )
+    }
+
+    fn random_values() -> Vec<StaticDataValue> {
+        let mut rng = OsRng;
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        vec![
+            StaticDataValue::Base(pallas::Base::random(&mut rng)),
+            StaticDataValue::U64(rng.next_u64()),
+            StaticDataValue::Bytes32(bytes),
+            StaticDataValue::Point(pallas::Point::random(&mut rng)),
+        ]
+    }
+
+    #[test]
+    fn test_schema_round_trip() {
+        let schema = schema(1);
+        let values = random_values();
+        let elements = schema.to_field_elements(&values).unwrap();
+        assert_eq!(elements.len(), schema.field_elements_len());
+        assert_eq!(schema.from_field_elements(&elements), Ok(values.clone()));
+
+        // The values don't match the schema
+        assert_eq!(
+            schema.to_field_elements(&values[1..]),
+            Err(EncodingError::LengthMismatch)
+        );
+        let mut wrong_values = values.clone();
+        wrong_values[1] = StaticDataValue::Base(pallas::Base::one());
+        assert_eq!(
+            schema.encode(&wrong_values),
+            Err(EncodingError::TypeMismatch(1))
+        );
+        wrong_values[1] = values[1];
+        wrong_values[3] = StaticDataValue::Point(pallas::Point::identity());
+        assert!(schema.encode(&wrong_values).is_err());
+
+        // The u64 element is out of range
+        let mut wrong_elements = elements.clone();
+        wrong_elements[1] = pallas::Base::from_u128(1 << 64);
+        assert_eq!(
+            schema.from_field_elements(&wrong_elements),
+            Err(EncodingError::InvalidElement(1))
+        );
+        // The point is not on the curve
+        let mut wrong_elements = elements;
+        wrong_elements[5] += pallas::Base::one();
+        assert_eq!(
+            schema.from_field_elements(&wrong_elements),
+            Err(EncodingError::InvalidElement(4))
+        );
+    }
+
+    #[test]
+    fn test_schema_id() {
+        let values = random_values();
+        let encoded = schema(1).encode(&values).unwrap();
+        assert_eq!(schema(1).encode(&values), Ok(encoded));
+        assert_ne!(schema(2).encode(&values), Ok(encoded));
+    }
+
+    #[test]
+    fn test_schema_encode_gadget() {
+        use crate::circuit::gadgets::assign_free_advice;
+        use halo2_gadgets::poseidon::{primitives as poseidon, Pow5Chip as PoseidonChip};
+        use halo2_proofs::{
+            circuit::{SimpleFloorPlanner, Value},
+            dev::MockProver,
+            plonk::{Circuit, ConstraintSystem, Instance},
+        };
+
+        #[derive(Clone, Debug)]
+        struct MyConfig {
+            advice: Column<Advice>,
+            instance: Column<Instance>,
+            poseidon_config: PoseidonConfig<pallas::Base, 3, 2>,
+        }
+
+        struct MyCircuit {
+            schema: StaticDataSchema,
+            elements: Vec<pallas::Base>,
+        }
+
+        impl Circuit<pallas::Base> for MyCircuit {
+            type Config = MyConfig;
+            type FloorPlanner = SimpleFloorPlanner;
+
+            fn without_witnesses(&self) -> Self {
+                Self {
+                    schema: self.schema.clone(),
+                    elements: vec![pallas::Base::zero(); self.elements.len()],
+                }
+            }
+
+            fn configure(meta: &mut ConstraintSystem<pallas::Base>) -> Self::Config {
+                let advice = meta.advice_column();
+                meta.enable_equality(advice);
+                let instance = meta.instance_column();
+                meta.enable_equality(instance);
+
+                let state = (0..3).map(|_| meta.advice_column()).collect::<Vec<_>>();
+                let partial_sbox = meta.advice_column();
+                let rc_a = (0..3).map(|_| meta.fixed_column()).collect::<Vec<_>>();
+                let rc_b = (0..3).map(|_| meta.fixed_column()).collect::<Vec<_>>();
+                meta.enable_constant(rc_b[0]);
+                let poseidon_config = PoseidonChip::configure::<poseidon::P128Pow5T3>(
+                    meta,
+                    state.try_into().unwrap(),
+                    partial_sbox,
+                    rc_a.try_into().unwrap(),
+                    rc_b.try_into().unwrap(),
+                );
+
+                MyConfig {
+                    advice,
+                    instance,
+                    poseidon_config,
+                }
+            }
+
+            fn synthesize(
+                &self,
+                config: Self::Config,
+                mut layouter: impl Layouter<pallas::Base>,
+            ) -> Result<(), Error> {
+                let elements = self
+                    .elements
+                    .iter()
+                    .map(|element| {
+                        assign_free_advice(
+                            layouter.namespace(|| "witness element"),
+                            config.advice,
+                            Value::known(*element),
+                        )
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                let encoded = self.schema.encode_gadget(
+                    config.poseidon_config,
+                    config.advice,
+                    layouter.namespace(|| "encode"),
+                    &elements,
+                )?;
+                layouter.constrain_instance(encoded.cell(), config.instance, 0)
+            }
+        }
+
+        let values = random_values();
+        let circuit = MyCircuit {
+            schema: schema(1),
+            elements: schema(1).to_field_elements(&values).unwrap(),
+        };
+        let encoded = schema(1).encode(&values).unwrap();
+        let prover = MockProver::run(10, &circuit, vec![vec![encoded]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // The circuit encodes under the schema id
+        let other_encoded = schema(2).encode(&values).unwrap();
+        let prover = MockProver::run(10, &circuit, vec![vec![other_encoded]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
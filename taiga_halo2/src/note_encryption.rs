@@ -1,30 +1,80 @@
 use crate::constant::{
-    NOTE_ENCRYPTION_CIPHERTEXT_NUM, NOTE_ENCRYPTION_PLAINTEXT_NUM, POSEIDON_RATE, POSEIDON_WIDTH,
+    GENERATOR, NOTE_CIPHERTEXT_COMMITMENT_DOMAIN, NOTE_ENCRYPTION_CIPHERTEXT_NUM,
+    NOTE_ENCRYPTION_KEY_DOMAIN, NOTE_ENCRYPTION_PLAINTEXT_NUM, POSEIDON_RATE, POSEIDON_WIDTH,
 };
+use crate::error::NoteError;
+use crate::note::Note;
+use crate::utils::{mod_r_p, non_identity_point_from_bytes, point_to_bytes, poseidon_hash_n};
 use ff::PrimeField;
-use group::Curve;
+use group::{cofactor::CofactorCurveAffine, Curve, Group};
 use halo2_gadgets::poseidon::primitives as poseidon;
 use halo2_proofs::arithmetic::CurveAffine;
 use pasta_curves::pallas;
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+use zeroize::Zeroize;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NoteCiphertext([pallas::Base; NOTE_ENCRYPTION_CIPHERTEXT_NUM]);
 
+/// A note ciphertext published by an output note vp, along with the ephemeral pk of the sender.
+/// The receiver decrypts it with its key alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedNote {
+    pub ciphertext: NoteCiphertext,
+    pub sender_pk: pallas::Point,
+}
+
 #[derive(Debug, Clone)]
 pub struct NotePlaintext([pallas::Base; NOTE_ENCRYPTION_PLAINTEXT_NUM]);
 
+/// The relation of an encrypted output note to the spender of the ptx, set by the sender. It's
+/// the last field of the note plaintext, the ciphertexts without it decode as external.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoteKind {
+    /// A payment to another owner
+    #[default]
+    External,
+    /// The note goes back to the spender, who pays another owner in the same ptx
+    Change,
+    /// All the notes go back to the spender
+    SelfTransfer,
+}
+
+/// The DH shared point of the sender's ephemeral key and the receiver key: esk * rcv_pk on the
+/// sender side, rcv_sk * epk on the receiver side. The sender picks a fresh esk for every note and
+/// publishes epk = esk * G with the ciphertext.
 #[derive(Debug, Clone)]
 pub struct SecretKey(pallas::Point);
 
+/// The encryption of an output note to the receiver pk. The encryption vp publishes the
+/// ciphertext, and the action circuit derives the cipher commitment of the note from the same
+/// witnesses.
+#[derive(Debug, Clone, Copy)]
+pub struct NoteEncryption {
+    pub nonce: pallas::Base,
+    // The ephemeral secret key, fresh for every note
+    pub esk: pallas::Base,
+    pub rcv_pk: pallas::Point,
+    // The NoteKind tag in the plaintext
+    pub note_kind: pallas::Base,
+}
+
 impl NoteCiphertext {
     pub fn inner(&self) -> &[pallas::Base; NOTE_ENCRYPTION_CIPHERTEXT_NUM] {
         &self.0
     }
 
+    // cipher_commitment = poseidon_hash(NOTE_CIPHERTEXT_COMMITMENT_DOMAIN || ciphertext)
+    pub fn commitment(&self) -> pallas::Base {
+        let mut inputs = [NOTE_CIPHERTEXT_COMMITMENT_DOMAIN; NOTE_ENCRYPTION_CIPHERTEXT_NUM + 1];
+        inputs[1..].copy_from_slice(&self.0);
+        poseidon_hash_n(inputs)
+    }
+
     pub fn encrypt(message: &NotePlaintext, secret_key: &SecretKey, nonce: &pallas::Base) -> Self {
         // Init poseidon sponge state
         let mut poseidon_sponge =
-            Self::poseidon_sponge_init(message.inner().len(), secret_key, nonce);
+            Self::poseidon_sponge_init(message.inner().len(), secret_key.symmetric_key(), nonce);
 
         // Encrypt
         let mut cipher = vec![];
@@ -53,24 +103,36 @@ impl NoteCiphertext {
         cipher.into()
     }
 
-    pub fn decrypt(&self, secret_key: &SecretKey) -> Option<Vec<pallas::Base>> {
-        let cipher_len = self.0.len();
-        let mac = self.0[cipher_len - 1];
-        let nonce = self.0[cipher_len - 2];
+    pub fn decrypt(&self, secret_key: &SecretKey) -> Result<Vec<pallas::Base>, NoteError> {
+        let mut msg = [pallas::Base::zero(); NOTE_ENCRYPTION_PLAINTEXT_NUM];
+        self.decrypt_with_symmetric_key(secret_key.symmetric_key(), &mut msg)?;
+        Ok(msg.to_vec())
+    }
+
+    // Decrypt into the msg buffer, so that the trial decryptions of a scan can reuse it
+    pub(crate) fn decrypt_with_symmetric_key(
+        &self,
+        symmetric_key: pallas::Base,
+        msg: &mut [pallas::Base; NOTE_ENCRYPTION_PLAINTEXT_NUM],
+    ) -> Result<(), NoteError> {
+        let mac = self.0[NOTE_ENCRYPTION_CIPHERTEXT_NUM - 1];
+        let nonce = self.0[NOTE_ENCRYPTION_CIPHERTEXT_NUM - 2];
         // Init poseidon sponge state
-        let mut poseidon_sponge = Self::poseidon_sponge_init(cipher_len - 2, secret_key, &nonce);
+        let mut poseidon_sponge =
+            Self::poseidon_sponge_init(NOTE_ENCRYPTION_PLAINTEXT_NUM, symmetric_key, &nonce);
 
         // Decrypt
-        let mut msg = vec![];
-        for chunk in self.0[0..cipher_len - 2].chunks(POSEIDON_RATE) {
+        for (msg_chunk, chunk) in msg
+            .chunks_mut(POSEIDON_RATE)
+            .zip(self.0[..NOTE_ENCRYPTION_PLAINTEXT_NUM].chunks(POSEIDON_RATE))
+        {
             poseidon::permute::<_, poseidon::P128Pow5T3, POSEIDON_WIDTH, POSEIDON_RATE>(
                 &mut poseidon_sponge.state,
                 &poseidon_sponge.mds_matrix,
                 &poseidon_sponge.round_constants,
             );
             for (idx, cipher_element) in chunk.iter().enumerate() {
-                let msg_element = *cipher_element - poseidon_sponge.state[idx];
-                msg.push(msg_element);
+                msg_chunk[idx] = *cipher_element - poseidon_sponge.state[idx];
                 poseidon_sponge.state[idx] = *cipher_element;
             }
         }
@@ -82,15 +144,15 @@ impl NoteCiphertext {
             &poseidon_sponge.round_constants,
         );
         if mac != poseidon_sponge.state[0] {
-            return None;
+            return Err(NoteError::WrongDecryptionKey);
         }
 
-        Some(msg)
+        Ok(())
     }
 
     fn poseidon_sponge_init(
         message_len: usize,
-        secret_key: &SecretKey,
+        symmetric_key: pallas::Base,
         nonce: &pallas::Base,
     ) -> poseidon::Sponge<
         pallas::Base,
@@ -99,14 +161,93 @@ impl NoteCiphertext {
         POSEIDON_WIDTH,
         POSEIDON_RATE,
     > {
-        let key_coord = secret_key.get_coordinates();
         let length_nonce = nonce
             + pallas::Base::from(message_len as u64) * pallas::Base::from_u128(1 << 64).square();
-        let state = [key_coord.0, key_coord.1, length_nonce];
+        let state = [symmetric_key, pallas::Base::zero(), length_nonce];
         poseidon::Sponge::<_, poseidon::P128Pow5T3, _, POSEIDON_WIDTH, POSEIDON_RATE>::init(state)
     }
 }
 
+impl NoteEncryption {
+    // app_vk || app_data_static || app_data_dynamic || value || rho || nk_com || psi || rcm || note_kind
+    pub fn plaintext(&self, note: &Note) -> NotePlaintext {
+        let message = vec![
+            note.note_type.app_vk,
+            note.note_type.app_data_static,
+            note.app_data_dynamic,
+            pallas::Base::from(note.value),
+            note.rho.inner(),
+            note.get_nk_commitment(),
+            note.psi,
+            note.rcm,
+            self.note_kind,
+        ];
+        NotePlaintext::padding(&message)
+    }
+
+    pub fn encrypt(&self, note: &Note) -> NoteCiphertext {
+        let key = SecretKey::from_dh_exchange(&self.rcv_pk, &mod_r_p(self.esk));
+        NoteCiphertext::encrypt(&self.plaintext(note), &key, &self.nonce)
+    }
+
+    // epk = esk * G, published with the ciphertext
+    pub fn epk(&self) -> pallas::Point {
+        GENERATOR.to_curve() * mod_r_p(self.esk)
+    }
+}
+
+// The witnesses of an output note without an encrypted payload in the action circuit
+impl Default for NoteEncryption {
+    fn default() -> Self {
+        Self {
+            nonce: pallas::Base::zero(),
+            esk: pallas::Base::one(),
+            rcv_pk: pallas::Point::generator(),
+            note_kind: NoteKind::default().to_base(),
+        }
+    }
+}
+
+impl EncryptedNote {
+    pub const BYTES_LEN: usize = (NOTE_ENCRYPTION_CIPHERTEXT_NUM + 1) * 32;
+
+    pub fn decrypt(&self, rcv_sk: pallas::Base) -> Result<Vec<pallas::Base>, NoteError> {
+        let key = SecretKey::from_dh_exchange(&self.sender_pk, &mod_r_p(rcv_sk));
+        self.ciphertext.decrypt(&key)
+    }
+
+    // ciphertext || sender_pk
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self
+            .ciphertext
+            .inner()
+            .iter()
+            .flat_map(|element| element.to_repr())
+            .collect();
+        bytes.extend(point_to_bytes(&self.sender_pk));
+        bytes
+    }
+
+    // The elements must be canonical and the epk a non-identity point
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NoteError> {
+        if bytes.len() != Self::BYTES_LEN {
+            return Err(NoteError::MalformedCiphertext);
+        }
+        let (ciphertext_bytes, pk_bytes) = bytes.split_at(NOTE_ENCRYPTION_CIPHERTEXT_NUM * 32);
+        let ciphertext = ciphertext_bytes
+            .chunks(32)
+            .map(|chunk| Option::from(pallas::Base::from_repr(chunk.try_into().unwrap())))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(NoteError::MalformedCiphertext)?;
+        let sender_pk = non_identity_point_from_bytes(pk_bytes.try_into().unwrap())
+            .map_err(|_| NoteError::MalformedCiphertext)?;
+        Ok(Self {
+            ciphertext: ciphertext.into(),
+            sender_pk,
+        })
+    }
+}
+
 impl From<Vec<pallas::Base>> for NoteCiphertext {
     fn from(input_vec: Vec<pallas::Base>) -> Self {
         NoteCiphertext(
@@ -145,6 +286,23 @@ impl From<Vec<pallas::Base>> for NotePlaintext {
     }
 }
 
+impl NoteKind {
+    pub fn to_base(self) -> pallas::Base {
+        pallas::Base::from(self as u64)
+    }
+}
+
+impl TryFrom<pallas::Base> for NoteKind {
+    type Error = NoteError;
+
+    fn try_from(tag: pallas::Base) -> Result<Self, Self::Error> {
+        [NoteKind::External, NoteKind::Change, NoteKind::SelfTransfer]
+            .into_iter()
+            .find(|kind| kind.to_base() == tag)
+            .ok_or(NoteError::MalformedCiphertext)
+    }
+}
+
 impl SecretKey {
     pub fn from_dh_exchange(pk: &pallas::Point, sk: &pallas::Scalar) -> Self {
         Self(pk * sk)
@@ -158,6 +316,74 @@ impl SecretKey {
         let coordinates = self.0.to_affine().coordinates().unwrap();
         (*coordinates.x(), *coordinates.y())
     }
+
+    // symmetric_key = poseidon_hash(NOTE_ENCRYPTION_KEY_DOMAIN || shared_x || shared_y)
+    pub fn symmetric_key(&self) -> pallas::Base {
+        let (x, y) = self.get_coordinates();
+        poseidon_hash_n([NOTE_ENCRYPTION_KEY_DOMAIN, x, y])
+    }
+}
+
+/// The symmetric keys of a batch of ciphertexts received by sk, one for each sender pk, i.e. the
+/// `SecretKey::symmetric_key` of the DH exchange on the receiver side. None for an identity pk.
+/// sk is secret, so there's no vartime multiscalar multiplication: sk is split in 4-bit windows
+/// once for the batch, and each pk is multiplied with constant-time table lookups, i.e. 79
+/// additions instead of 255. The shared points are normalized with a single inversion.
+pub fn batch_symmetric_keys(
+    pks: &[pallas::Point],
+    sk: &pallas::Scalar,
+) -> Vec<Option<pallas::Base>> {
+    let mut windows = scalar_windows(sk);
+    let shared_points: Vec<pallas::Point> =
+        pks.iter().map(|pk| windowed_mul(pk, &windows)).collect();
+    windows.zeroize();
+
+    let mut shared_affines = vec![pallas::Affine::default(); shared_points.len()];
+    pallas::Point::batch_normalize(&shared_points, &mut shared_affines);
+    shared_affines
+        .iter()
+        .map(|shared| {
+            let coord = shared.coordinates();
+            bool::from(coord.is_some()).then(|| {
+                let coord = coord.unwrap();
+                poseidon_hash_n([NOTE_ENCRYPTION_KEY_DOMAIN, *coord.x(), *coord.y()])
+            })
+        })
+        .collect()
+}
+
+// The 4-bit windows of the scalar, the most significant first
+fn scalar_windows(sk: &pallas::Scalar) -> [u8; 64] {
+    let mut windows = [0u8; 64];
+    for (i, byte) in sk.to_repr().iter().enumerate() {
+        windows[63 - 2 * i] = byte & 0x0f;
+        windows[62 - 2 * i] = byte >> 4;
+    }
+    windows
+}
+
+// sk * pk with the windows of sk, the table lookups don't depend on the windows
+fn windowed_mul(pk: &pallas::Point, windows: &[u8; 64]) -> pallas::Point {
+    // table[i] = i * pk
+    let mut table = [pallas::Point::identity(); 16];
+    let mut multiple = pallas::Point::identity();
+    for entry in table.iter_mut().skip(1) {
+        multiple += pk;
+        *entry = multiple;
+    }
+
+    let mut acc = pallas::Point::identity();
+    for window in windows.iter() {
+        for _ in 0..4 {
+            acc = acc.double();
+        }
+        let mut term = pallas::Point::identity();
+        for (i, entry) in table.iter().enumerate() {
+            term.conditional_assign(entry, (i as u8).ct_eq(window));
+        }
+        acc += term;
+    }
+    acc
 }
 
 #[test]
@@ -187,3 +413,136 @@ fn test_halo2_note_encryption() {
     let decryption = cipher.decrypt(&key).unwrap();
     assert_eq!(plaintext.to_vec(), decryption);
 }
+
+#[test]
+fn test_note_encryption_ephemeral_key() {
+    use crate::utils::mod_r_p;
+    use ff::Field;
+    use group::Group;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let rcv_sk = pallas::Base::random(&mut rng);
+    let rcv_pk = pallas::Point::generator() * mod_r_p(rcv_sk);
+    let plaintext = NotePlaintext::padding(&vec![pallas::Base::random(&mut rng); 8]);
+    let nonce = pallas::Base::random(&mut rng);
+
+    // The sender encrypts under a fresh esk and publishes epk
+    let esk = pallas::Base::random(&mut rng);
+    let epk = pallas::Point::generator() * mod_r_p(esk);
+    let cipher = NoteCiphertext::encrypt(
+        &plaintext,
+        &SecretKey::from_dh_exchange(&rcv_pk, &mod_r_p(esk)),
+        &nonce,
+    );
+
+    // The receiver derives the same key from epk
+    let key = SecretKey::from_dh_exchange(&epk, &mod_r_p(rcv_sk));
+    assert_eq!(cipher.decrypt(&key).unwrap(), plaintext.to_vec());
+
+    // Another receiver can't decrypt
+    let wrong_sk = pallas::Base::random(&mut rng);
+    let wrong_key = SecretKey::from_dh_exchange(&epk, &mod_r_p(wrong_sk));
+    assert_eq!(
+        cipher.decrypt(&wrong_key),
+        Err(NoteError::WrongDecryptionKey)
+    );
+
+    // A fresh esk gives another ciphertext of the same note
+    let other_esk = pallas::Base::random(&mut rng);
+    let other_cipher = NoteCiphertext::encrypt(
+        &plaintext,
+        &SecretKey::from_dh_exchange(&rcv_pk, &mod_r_p(other_esk)),
+        &nonce,
+    );
+    assert_ne!(other_cipher.inner(), cipher.inner());
+}
+
+#[test]
+fn test_encrypted_note_encoding() {
+    use ff::Field;
+    use group::Group;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let encrypted_note = EncryptedNote {
+        ciphertext: vec![pallas::Base::random(&mut rng); NOTE_ENCRYPTION_CIPHERTEXT_NUM].into(),
+        sender_pk: pallas::Point::random(&mut rng),
+    };
+    let bytes = encrypted_note.to_bytes();
+    assert_eq!(bytes.len(), EncryptedNote::BYTES_LEN);
+    assert_eq!(EncryptedNote::from_bytes(&bytes), Ok(encrypted_note));
+
+    let pk_start = EncryptedNote::BYTES_LEN - 32;
+    // The identity epk, with and without the sign bit
+    let mut identity_epk = bytes.clone();
+    identity_epk[pk_start..].copy_from_slice(&[0u8; 32]);
+    assert_eq!(
+        EncryptedNote::from_bytes(&identity_epk),
+        Err(NoteError::MalformedCiphertext)
+    );
+    identity_epk[EncryptedNote::BYTES_LEN - 1] = 0x80;
+    assert_eq!(
+        EncryptedNote::from_bytes(&identity_epk),
+        Err(NoteError::MalformedCiphertext)
+    );
+    // The epk x-coordinate is not reduced
+    let mut invalid_epk = bytes.clone();
+    invalid_epk[pk_start..].copy_from_slice(&[0xff; 32]);
+    assert_eq!(
+        EncryptedNote::from_bytes(&invalid_epk),
+        Err(NoteError::MalformedCiphertext)
+    );
+    // A ciphertext element is not reduced
+    let mut invalid_element = bytes.clone();
+    invalid_element[..32].copy_from_slice(&[0xff; 32]);
+    assert_eq!(
+        EncryptedNote::from_bytes(&invalid_element),
+        Err(NoteError::MalformedCiphertext)
+    );
+    assert_eq!(
+        EncryptedNote::from_bytes(&bytes[1..]),
+        Err(NoteError::MalformedCiphertext)
+    );
+}
+
+#[test]
+fn test_batch_symmetric_keys() {
+    use ff::Field;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let sk = pallas::Scalar::random(&mut rng);
+    let mut pks: Vec<pallas::Point> = (0..40).map(|_| pallas::Point::random(&mut rng)).collect();
+    pks.push(pallas::Point::generator());
+    let keys = batch_symmetric_keys(&pks, &sk);
+    assert_eq!(keys.len(), pks.len());
+    for (pk, key) in pks.iter().zip(keys) {
+        assert_eq!(
+            key,
+            Some(SecretKey::from_dh_exchange(pk, &sk).symmetric_key())
+        );
+    }
+
+    // The edge scalars
+    for sk in [
+        pallas::Scalar::zero(),
+        pallas::Scalar::one(),
+        -pallas::Scalar::one(),
+    ] {
+        let pk = pallas::Point::random(&mut rng);
+        let expected = if sk == pallas::Scalar::zero() {
+            None
+        } else {
+            Some(SecretKey::from_dh_exchange(&pk, &sk).symmetric_key())
+        };
+        assert_eq!(batch_symmetric_keys(&[pk], &sk), vec![expected]);
+    }
+
+    // The identity pk has no key
+    assert_eq!(
+        batch_symmetric_keys(&[pallas::Point::identity()], &sk),
+        vec![None]
+    );
+    assert!(batch_symmetric_keys(&[], &sk).is_empty());
+}
@@ -0,0 +1,304 @@
+//! Selection of the notes to spend for a payment.
+//!
+//! A ptx spends at most NUM_NOTE notes, the selected notes are split into the ptxs of a plan. The
+//! change goes back to the spender in a note created by the last ptx.
+use crate::constant::NUM_NOTE;
+use crate::merkle_tree::{Anchor, MerklePath};
+use crate::note::Note;
+use std::fmt;
+
+// The number of nodes visited by the branch-and-bound search before giving up
+const BRANCH_AND_BOUND_MAX_TRIES: usize = 100_000;
+
+/// An owned note and its path in the commitment tree.
+#[derive(Debug, Clone)]
+pub struct NoteInTree {
+    pub note: Note,
+    pub merkle_path: MerklePath,
+}
+
+impl NoteInTree {
+    pub fn new(note: Note, merkle_path: MerklePath) -> Self {
+        Self { note, merkle_path }
+    }
+
+    pub fn anchor(&self) -> Anchor {
+        self.note.calculate_root(&self.merkle_path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionStrategy {
+    /// Spends the smallest notes first, it consolidates the dust but may need several ptxs.
+    SmallestFirst,
+    /// Spends the largest notes first, it uses as few notes as possible.
+    LargestFirst,
+    /// Searches for notes adding up to the target exactly, so that there's no change note.
+    BranchAndBound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectionError {
+    /// The notes add up to less than the target.
+    InsufficientFunds { available: u128, target: u64 },
+    /// The notes are not all of the same note type.
+    MixedNoteTypes,
+    /// No subset of the notes adds up to the target, only for BranchAndBound.
+    NoExactMatch,
+}
+
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SelectionError::*;
+        match self {
+            InsufficientFunds { available, target } => {
+                write!(
+                    f,
+                    "Insufficient funds: {} available, {} needed",
+                    available, target
+                )
+            }
+            MixedNoteTypes => f.write_str("The notes are not of the same note type"),
+            NoExactMatch => f.write_str("No set of notes adds up to the target"),
+        }
+    }
+}
+
+impl std::error::Error for SelectionError {}
+
+/// The notes to spend, grouped by ptx, and the value of the change note.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// Each ptx of the plan spends at most NUM_NOTE notes.
+    pub ptxs: Vec<Vec<NoteInTree>>,
+    pub change: u64,
+}
+
+impl Selection {
+    pub fn notes(&self) -> impl Iterator<Item = &NoteInTree> {
+        self.ptxs.iter().flatten()
+    }
+
+    pub fn total_value(&self) -> u128 {
+        self.notes().map(|n| n.note.value as u128).sum()
+    }
+}
+
+/// Selects the notes to spend `target` of the notes' type. The zero-value notes are never
+/// selected.
+pub fn select_notes(
+    notes: &[NoteInTree],
+    target: u64,
+    strategy: SelectionStrategy,
+) -> Result<Selection, SelectionError> {
+    if let Some(first) = notes.first() {
+        if notes
+            .iter()
+            .any(|n| n.note.note_type != first.note.note_type)
+        {
+            return Err(SelectionError::MixedNoteTypes);
+        }
+    }
+
+    let mut candidates: Vec<&NoteInTree> = notes.iter().filter(|n| n.note.value > 0).collect();
+    let available: u128 = candidates.iter().map(|n| n.note.value as u128).sum();
+    if available < target as u128 {
+        return Err(SelectionError::InsufficientFunds { available, target });
+    }
+
+    let selected = match strategy {
+        SelectionStrategy::SmallestFirst => {
+            candidates.sort_by_key(|n| n.note.value);
+            take_until(candidates, target)
+        }
+        SelectionStrategy::LargestFirst => {
+            candidates.sort_by_key(|n| std::cmp::Reverse(n.note.value));
+            take_until(candidates, target)
+        }
+        SelectionStrategy::BranchAndBound => {
+            candidates.sort_by_key(|n| std::cmp::Reverse(n.note.value));
+            branch_and_bound(&candidates, target).ok_or(SelectionError::NoExactMatch)?
+        }
+    };
+
+    let total: u128 = selected.iter().map(|n| n.note.value as u128).sum();
+    // The last selected note tops the sum up to the target, the change is less than its value
+    let change = (total - target as u128) as u64;
+    let ptxs = selected
+        .chunks(NUM_NOTE)
+        .map(|chunk| chunk.iter().map(|&n| n.clone()).collect())
+        .collect();
+    Ok(Selection { ptxs, change })
+}
+
+fn take_until(candidates: Vec<&NoteInTree>, target: u64) -> Vec<&NoteInTree> {
+    let mut total: u128 = 0;
+    candidates
+        .into_iter()
+        .take_while(|n| {
+            let more = total < target as u128;
+            total += n.note.value as u128;
+            more
+        })
+        .collect()
+}
+
+// Depth-first search over the candidates sorted by decreasing value. A branch is cut when it
+// overshoots the target or when the remaining notes can't reach it.
+fn branch_and_bound<'a>(candidates: &[&'a NoteInTree], target: u64) -> Option<Vec<&'a NoteInTree>> {
+    // remaining[i] is the sum of the values from i on
+    let mut remaining = vec![0u128; candidates.len() + 1];
+    for i in (0..candidates.len()).rev() {
+        remaining[i] = remaining[i + 1] + candidates[i].note.value as u128;
+    }
+
+    fn search(
+        values: &[u128],
+        remaining: &[u128],
+        index: usize,
+        needed: u128,
+        picked: &mut Vec<usize>,
+        tries: &mut usize,
+    ) -> bool {
+        if needed == 0 {
+            return true;
+        }
+        *tries += 1;
+        if index == values.len() || remaining[index] < needed || *tries > BRANCH_AND_BOUND_MAX_TRIES
+        {
+            return false;
+        }
+        if values[index] <= needed {
+            picked.push(index);
+            if search(
+                values,
+                remaining,
+                index + 1,
+                needed - values[index],
+                picked,
+                tries,
+            ) {
+                return true;
+            }
+            picked.pop();
+        }
+        search(values, remaining, index + 1, needed, picked, tries)
+    }
+
+    let values: Vec<u128> = candidates.iter().map(|n| n.note.value as u128).collect();
+    let mut picked = vec![];
+    let mut tries = 0;
+    search(
+        &values,
+        &remaining,
+        0,
+        target as u128,
+        &mut picked,
+        &mut tries,
+    )
+    .then(|| picked.into_iter().map(|i| candidates[i]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant::TAIGA_COMMITMENT_TREE_DEPTH;
+    use crate::note::tests::random_input_note;
+    use rand::rngs::OsRng;
+
+    fn wallet(values: &[u64]) -> Vec<NoteInTree> {
+        let mut rng = OsRng;
+        let base = random_input_note(&mut rng);
+        values
+            .iter()
+            .map(|&value| {
+                let note = Note {
+                    value,
+                    ..random_input_note(&mut rng)
+                };
+                let note = Note {
+                    note_type: base.note_type,
+                    ..note
+                };
+                NoteInTree::new(
+                    note,
+                    MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH),
+                )
+            })
+            .collect()
+    }
+
+    fn values(selection: &Selection) -> Vec<Vec<u64>> {
+        selection
+            .ptxs
+            .iter()
+            .map(|ptx| ptx.iter().map(|n| n.note.value).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_select_notes_exact_match() {
+        let notes = wallet(&[1, 10, 3, 5]);
+        let selection = select_notes(&notes, 8, SelectionStrategy::BranchAndBound).unwrap();
+        assert_eq!(selection.change, 0);
+        assert_eq!(values(&selection), vec![vec![5, 3]]);
+
+        // 2 can't be made of 1, 3, 5 and 10
+        assert_eq!(
+            select_notes(&notes, 2, SelectionStrategy::BranchAndBound).unwrap_err(),
+            SelectionError::NoExactMatch
+        );
+    }
+
+    #[test]
+    fn test_select_notes_with_change() {
+        let notes = wallet(&[10, 3, 0, 5]);
+        let selection = select_notes(&notes, 7, SelectionStrategy::LargestFirst).unwrap();
+        assert_eq!(values(&selection), vec![vec![10]]);
+        assert_eq!(selection.change, 3);
+
+        let selection = select_notes(&notes, 4, SelectionStrategy::SmallestFirst).unwrap();
+        assert_eq!(values(&selection), vec![vec![3, 5]]);
+        assert_eq!(selection.change, 4);
+    }
+
+    #[test]
+    fn test_select_notes_insufficient_funds() {
+        let notes = wallet(&[1, 2, 3]);
+        for strategy in [
+            SelectionStrategy::SmallestFirst,
+            SelectionStrategy::LargestFirst,
+            SelectionStrategy::BranchAndBound,
+        ] {
+            assert_eq!(
+                select_notes(&notes, 7, strategy).unwrap_err(),
+                SelectionError::InsufficientFunds {
+                    available: 6,
+                    target: 7
+                }
+            );
+        }
+
+        let mut mixed = wallet(&[1, 2]);
+        mixed.extend(wallet(&[3]));
+        assert_eq!(
+            select_notes(&mixed, 1, SelectionStrategy::SmallestFirst).unwrap_err(),
+            SelectionError::MixedNoteTypes
+        );
+    }
+
+    #[test]
+    fn test_select_notes_two_ptxs() {
+        assert_eq!(NUM_NOTE, 2);
+        // The dust is consolidated in two ptxs
+        let notes = wallet(&[1, 2, 1, 20, 3]);
+        let selection = select_notes(&notes, 6, SelectionStrategy::SmallestFirst).unwrap();
+        assert_eq!(values(&selection), vec![vec![1, 1], vec![2, 3]]);
+        assert_eq!(selection.change, 1);
+        assert_eq!(selection.total_value(), 7);
+
+        let selection = select_notes(&notes, 7, SelectionStrategy::BranchAndBound).unwrap();
+        assert_eq!(values(&selection), vec![vec![3, 2], vec![1, 1]]);
+        assert_eq!(selection.change, 0);
+    }
+}
@@ -1,8 +1,10 @@
 use std::hash::Hash;
 
 use crate::{
+    constant::NK_COMMITMENT_DOMAIN,
+    error::NoteError,
     note::NoteCommitment,
-    utils::{poseidon_hash_n, prf_nf},
+    utils::{poseidon_hash, poseidon_hash_n, zeroize_field},
 };
 use halo2_proofs::arithmetic::Field;
 use pasta_curves::group::ff::PrimeField;
@@ -11,6 +13,7 @@ use rand::RngCore;
 #[cfg(feature = "nif")]
 use rustler::{NifTaggedEnum, NifTuple};
 use subtle::CtOption;
+use zeroize::Zeroize;
 
 #[cfg(feature = "serde")]
 use serde;
@@ -29,24 +32,30 @@ pub struct Nullifier(pallas::Base);
 #[cfg_attr(feature = "nif", derive(NifTaggedEnum))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NullifierKeyContainer {
-    // The NullifierKeyContainer::Commitment is the commitment of NullifierKeyContainer::Key `nk_com = Commitment(nk, 0)`
+    // The NullifierKeyContainer::Commitment is the commitment of NullifierKeyContainer::Key `nk_com = poseidon_hash(NK_COMMITMENT_DOMAIN || nk)`
     Commitment(pallas::Base),
     Key(pallas::Base),
 }
 
 impl Nullifier {
     // nf = poseidon_hash(nk || \rho || \psi || note_cm), in which note_cm is a field element
+    //
+    // The position of the note is not bound. Instead the rho of an output note is the nf of the
+    // input note of its action, the action circuit takes it from the nf cell. A nf is revealed
+    // only once, so every created note has a fresh rho and two notes can't share a commitment
+    // or a nf. This is what stops the faerie gold attack: sending the same note twice needs the
+    // same input nf twice, which the ledger rejects.
     pub fn derive(
         nk: &NullifierKeyContainer,
         rho: &pallas::Base,
         psi: &pallas::Base,
         cm: &NoteCommitment,
-    ) -> Option<Self> {
+    ) -> Result<Self, NoteError> {
         match nk {
-            NullifierKeyContainer::Commitment(_) => None,
+            NullifierKeyContainer::Commitment(_) => Err(NoteError::MissingNullifierKey),
             NullifierKeyContainer::Key(key) => {
                 let nf = Nullifier(poseidon_hash_n([*key, *rho, *psi, cm.inner()]));
-                Some(nf)
+                Ok(nf)
             }
         }
     }
@@ -136,8 +145,8 @@ impl NullifierKeyContainer {
         match self {
             NullifierKeyContainer::Commitment(v) => *v,
             NullifierKeyContainer::Key(key) => {
-                // Commitment(nk, zero), use poseidon hash as Commitment.
-                prf_nf(*key, pallas::Base::zero())
+                // Domain-separated poseidon hash as the commitment.
+                poseidon_hash(NK_COMMITMENT_DOMAIN, *key)
             }
         }
     }
@@ -152,6 +161,76 @@ impl NullifierKeyContainer {
     }
 }
 
+// Wipes the nullifier key. Copies of the container are not affected.
+impl Zeroize for NullifierKeyContainer {
+    fn zeroize(&mut self) {
+        match self {
+            NullifierKeyContainer::Commitment(v) => zeroize_field(v),
+            NullifierKeyContainer::Key(key) => zeroize_field(key),
+        }
+    }
+}
+
+/// The nullifier key released by the device holding it, only to be used as a proving witness.
+/// The action and vp circuits derive the nullifier and the nk commitment from nk in-circuit, so
+/// the prover needs it. Unlike NullifierKeyContainer, it can't be copied, printed or serialized,
+/// and is wiped on drop.
+pub struct NullifierKeyWitness(pallas::Base);
+
+impl NullifierKeyWitness {
+    pub fn new(nk: pallas::Base) -> Self {
+        Self(nk)
+    }
+
+    pub(crate) fn to_container(&self) -> NullifierKeyContainer {
+        NullifierKeyContainer::from_key(self.0)
+    }
+}
+
+impl Drop for NullifierKeyWitness {
+    fn drop(&mut self) {
+        zeroize_field(&mut self.0);
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshSerialize for NullifierKeyContainer {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        // The tag byte indicates whether it is a key or a commitment
+        match self {
+            NullifierKeyContainer::Commitment(nk_com) => {
+                writer.write_all(&[1u8])?;
+                writer.write_all(&nk_com.to_repr())
+            }
+            NullifierKeyContainer::Key(nk) => {
+                writer.write_all(&[2u8])?;
+                writer.write_all(&nk.to_repr())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshDeserialize for NullifierKeyContainer {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        use std::io;
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        let mut repr = [0u8; 32];
+        reader.read_exact(&mut repr)?;
+        let value = Option::from(pallas::Base::from_repr(repr))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "nk not in field"))?;
+        match tag[0] {
+            0x01 => Ok(NullifierKeyContainer::from_commitment(value)),
+            0x02 => Ok(NullifierKeyContainer::from_key(value)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid NullifierKeyContainer tag",
+            )),
+        }
+    }
+}
+
 impl Default for NullifierKeyContainer {
     fn default() -> NullifierKeyContainer {
         let key = pallas::Base::default();
@@ -166,6 +245,8 @@ pub mod tests {
     use rand::RngCore;
 
     use super::{Nullifier, NullifierKeyContainer};
+    use crate::constant::NK_COMMITMENT_DOMAIN;
+    use crate::utils::{poseidon_hash, testing::base_from_hex};
 
     pub fn random_nullifier<R: RngCore>(mut rng: R) -> Nullifier {
         Nullifier::from(pallas::Base::random(&mut rng))
@@ -178,4 +259,58 @@ pub mod tests {
     pub fn random_nullifier_key_commitment<R: RngCore>(mut rng: R) -> NullifierKeyContainer {
         NullifierKeyContainer::from_commitment(pallas::Base::random(&mut rng))
     }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn nk_container_borsh_serialization_test() {
+        use borsh::BorshDeserialize;
+        use rand::rngs::OsRng;
+
+        let mut rng = OsRng;
+        for nk in [
+            random_nullifier_key(&mut rng),
+            random_nullifier_key_commitment(&mut rng),
+        ] {
+            let borsh = borsh::to_vec(&nk).unwrap();
+            assert_eq!(borsh.len(), 33);
+            let de_nk: NullifierKeyContainer =
+                BorshDeserialize::deserialize(&mut borsh.as_ref()).unwrap();
+            // The key or commitment variant is preserved
+            assert_eq!(nk, de_nk);
+        }
+
+        // Unknown tag
+        let mut borsh = borsh::to_vec(&random_nullifier_key(&mut rng)).unwrap();
+        borsh[0] = 3;
+        assert!(NullifierKeyContainer::deserialize(&mut borsh.as_ref()).is_err());
+
+        // Non-canonical field element
+        let mut borsh = vec![2u8];
+        borsh.extend([0xffu8; 32]);
+        assert!(NullifierKeyContainer::deserialize(&mut borsh.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_nk_commitment_domain_separation() {
+        let key = pallas::Base::from(42u64);
+        let nk = NullifierKeyContainer::from_key(key);
+        // Fixed vector: the commitment is the poseidon hash of the domain and the key
+        assert_eq!(
+            nk.get_commitment(),
+            poseidon_hash(NK_COMMITMENT_DOMAIN, key)
+        );
+        // The old un-separated commitment must not be reproduced
+        assert_ne!(
+            nk.get_commitment(),
+            poseidon_hash(key, pallas::Base::zero())
+        );
+        assert_eq!(nk.to_commitment().get_commitment(), nk.get_commitment());
+
+        // Golden vector: the canonical little-endian encodings of the key and the commitment
+        let key = base_from_hex("2a00000000000000000000000000000000000000000000000000000000000000");
+        assert_eq!(
+            NullifierKeyContainer::from_key(key).get_commitment(),
+            base_from_hex("a4ee0a703935d48bc4c68cc05b068ced5ed538697c826692239430032731e906")
+        );
+    }
 }
@@ -1,12 +1,19 @@
 use halo2_proofs::{
-    plonk::{self, Circuit, ProvingKey, SingleVerifier, VerifyingKey},
+    plonk::{self, SingleVerifier, VerifyingKey},
     poly::commitment::Params,
-    transcript::{Blake2bRead, Blake2bWrite},
+    transcript::Blake2bRead,
+};
+#[cfg(feature = "prover")]
+use halo2_proofs::{
+    plonk::{Circuit, ProvingKey},
+    transcript::Blake2bWrite,
 };
 use pasta_curves::{pallas, vesta};
+#[cfg(feature = "prover")]
 use rand::RngCore;
 #[cfg(feature = "nif")]
 use rustler::NifTuple;
+use std::io;
 
 #[cfg(feature = "serde")]
 use serde;
@@ -16,12 +23,13 @@ use borsh::{BorshDeserialize, BorshSerialize};
 
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "nif", derive(NifTuple))]
-#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Proof(Vec<u8>);
 
 impl Proof {
     /// Creates a proof for the given circuits and instances.
+    #[cfg(feature = "prover")]
     pub fn create<C: Circuit<pallas::Base>>(
         pk: &ProvingKey<vesta::Affine>,
         params: &Params<vesta::Affine>,
@@ -29,16 +37,38 @@ impl Proof {
         instance: &[&[pallas::Base]],
         mut rng: impl RngCore,
     ) -> Result<Self, plonk::Error> {
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
+        #[cfg(feature = "metrics")]
+        let circuit = crate::metrics::TimedCircuit::new(circuit);
+        let circuits = [circuit];
         let mut transcript = Blake2bWrite::<_, vesta::Affine, _>::init(vec![]);
         plonk::create_proof(
             params,
             pk,
-            &[circuit],
+            &circuits,
             &[instance],
             &mut rng,
             &mut transcript,
         )?;
-        Ok(Proof(transcript.finalize()))
+        let proof = transcript.finalize();
+        #[cfg(feature = "metrics")]
+        crate::metrics::record(crate::metrics::ProvingEvent {
+            stage: crate::metrics::ProvingStage::WitnessSynthesis,
+            circuit: crate::metrics::circuit_label::<C>().to_string(),
+            k: Some(crate::metrics::params_k(params)),
+            duration: circuits[0].synthesis_duration(),
+            proof_size: None,
+        });
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_span(
+            crate::metrics::ProvingStage::Proving,
+            crate::metrics::circuit_label::<C>(),
+            Some(crate::metrics::params_k(params)),
+            start,
+            Some(proof.len()),
+        );
+        Ok(Proof(proof))
     }
 
     /// Verifies this proof with the given instances.
@@ -48,11 +78,33 @@ impl Proof {
         params: &Params<vesta::Affine>,
         instance: &[&[pallas::Base]],
     ) -> Result<(), plonk::Error> {
+        if self.0.is_empty() {
+            return Err(plonk::Error::Transcript(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "empty proof",
+            )));
+        }
         let strategy = SingleVerifier::new(params);
         let mut transcript = Blake2bRead::init(&self.0[..]);
         plonk::verify_proof(params, vk, strategy, &[instance], &mut transcript)
     }
 
+    /// Verifies this proof of a circuit with a single instance column of `instance_len` elements.
+    /// An instance of another length is rejected with InvalidInstances before the halo2 verifier
+    /// runs.
+    pub fn verify_single_instance(
+        &self,
+        vk: &VerifyingKey<vesta::Affine>,
+        params: &Params<vesta::Affine>,
+        instance: &[pallas::Base],
+        instance_len: usize,
+    ) -> Result<(), plonk::Error> {
+        if instance.len() != instance_len {
+            return Err(plonk::Error::InvalidInstances);
+        }
+        self.verify(vk, params, &[instance])
+    }
+
     /// Constructs a new Proof value.
     pub fn new(bytes: Vec<u8>) -> Self {
         Proof(bytes)
@@ -61,4 +113,21 @@ impl Proof {
     pub fn inner(&self) -> Vec<u8> {
         self.0.clone()
     }
+
+    /// The proof length in bytes.
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+// An empty proof is rejected, the halo2 transcript can't be read from it
+#[cfg(feature = "borsh")]
+impl BorshDeserialize for Proof {
+    fn deserialize_reader<R: io::Read>(reader: &mut R) -> io::Result<Self> {
+        let bytes = Vec::<u8>::deserialize_reader(reader)?;
+        if bytes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty proof"));
+        }
+        Ok(Proof(bytes))
+    }
 }
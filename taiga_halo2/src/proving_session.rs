@@ -0,0 +1,294 @@
+/// Runs the proving of a transaction with progress reporting and cancellation, e.g. off the UI
+/// thread of a wallet. The session is runtime agnostic: `run` blocks, the subscribers are called
+/// on the proving thread and the session is cancelled from any thread with a `CancelHandle`.
+/// The cancellation is checked before each proof, the proofs already created are dropped.
+use crate::constant::NUM_NOTE;
+use crate::error::TransactionError;
+use crate::note::{InputNoteProvingInfo, OutputNoteProvingInfo};
+use crate::shielded_ptx::ShieldedPartialTransaction;
+use crate::transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle};
+use core::fmt;
+use rand::{CryptoRng, RngCore};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofStage {
+    VPProof,
+    ActionProof,
+}
+
+/// Reported after each proof of the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvingProgress {
+    pub stage: ProofStage,
+    pub circuit_name: String,
+    // The number of proofs created so far, this one included
+    pub completed: usize,
+    // The number of proofs of the session
+    pub total: usize,
+}
+
+#[derive(Debug)]
+pub enum ProvingError {
+    /// The session was cancelled before all the proofs were created.
+    Cancelled,
+    Transaction(TransactionError),
+}
+
+impl Display for ProvingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvingError::Cancelled => f.write_str("The proving session was cancelled"),
+            ProvingError::Transaction(e) => f.write_str(&format!("Transaction error: {e}")),
+        }
+    }
+}
+
+impl From<TransactionError> for ProvingError {
+    fn from(e: TransactionError) -> Self {
+        match e {
+            TransactionError::ProvingCancelled => ProvingError::Cancelled,
+            e => ProvingError::Transaction(e),
+        }
+    }
+}
+
+/// The proving infos of a shielded ptx, see `ShieldedPartialTransaction::build`.
+pub struct PtxProvingRequest {
+    pub input_info: [InputNoteProvingInfo; NUM_NOTE],
+    pub output_info: [OutputNoteProvingInfo; NUM_NOTE],
+    pub hints: Vec<u8>,
+}
+
+impl PtxProvingRequest {
+    pub fn new(
+        input_info: [InputNoteProvingInfo; NUM_NOTE],
+        output_info: [OutputNoteProvingInfo; NUM_NOTE],
+        hints: Vec<u8>,
+    ) -> Self {
+        Self {
+            input_info,
+            output_info,
+            hints,
+        }
+    }
+
+    // The vp proofs of all the notes and the action proofs
+    fn proof_num(&self) -> usize {
+        let vp_num: usize = self
+            .input_info
+            .iter()
+            .map(|input| 1 + input.dynamic_vp_num())
+            .chain(
+                self.output_info
+                    .iter()
+                    .map(|output| 1 + output.dynamic_vp_num()),
+            )
+            .sum();
+        vp_num + NUM_NOTE
+    }
+}
+
+/// Cancels the session it's taken from, it can be sent to another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+type ProgressCallback = Box<dyn FnMut(ProvingProgress) + Send>;
+
+pub struct ProvingSession {
+    ptx_requests: Vec<PtxProvingRequest>,
+    subscribers: Vec<ProgressCallback>,
+    cancel_handle: CancelHandle,
+}
+
+impl ProvingSession {
+    pub fn new(ptx_requests: Vec<PtxProvingRequest>) -> Self {
+        Self {
+            ptx_requests,
+            subscribers: vec![],
+            cancel_handle: CancelHandle::default(),
+        }
+    }
+
+    pub fn subscribe(&mut self, callback: impl FnMut(ProvingProgress) + Send + 'static) {
+        self.subscribers.push(Box::new(callback));
+    }
+
+    pub fn cancel(&self) {
+        self.cancel_handle.cancel();
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel_handle.clone()
+    }
+
+    // The number of proofs of the session
+    pub fn total(&self) -> usize {
+        self.ptx_requests
+            .iter()
+            .map(|request| request.proof_num())
+            .sum()
+    }
+
+    /// Builds the ptxs one by one, then the transaction. Returns `ProvingError::Cancelled` if the
+    /// session is cancelled before the last proof.
+    pub fn run<R: RngCore + CryptoRng>(self, mut rng: R) -> Result<Transaction, ProvingError> {
+        let mut progress = SessionProgress {
+            total: self.total(),
+            completed: 0,
+            subscribers: self.subscribers,
+            cancel_handle: self.cancel_handle,
+        };
+        let mut ptxs = vec![];
+        for request in self.ptx_requests {
+            let ptx = ShieldedPartialTransaction::build_with_hook(
+                request.input_info,
+                request.output_info,
+                request.hints,
+                &mut progress,
+                &mut rng,
+            )?;
+            ptxs.push(ptx);
+        }
+        progress.before_proof()?;
+
+        let tx = Transaction::build(
+            rng,
+            ShieldedPartialTxBundle::new(ptxs),
+            TransparentPartialTxBundle::default(),
+        )?;
+        Ok(tx)
+    }
+}
+
+/// Called around each proof of the ptx building. An error from before_proof aborts the building.
+pub(crate) trait ProvingHook {
+    fn before_proof(&mut self) -> Result<(), TransactionError>;
+    fn after_proof(&mut self, stage: ProofStage, circuit_name: &str);
+}
+
+// The hook of the plain builders
+impl ProvingHook for () {
+    fn before_proof(&mut self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+
+    fn after_proof(&mut self, _stage: ProofStage, _circuit_name: &str) {}
+}
+
+struct SessionProgress {
+    total: usize,
+    completed: usize,
+    subscribers: Vec<ProgressCallback>,
+    cancel_handle: CancelHandle,
+}
+
+impl ProvingHook for SessionProgress {
+    fn before_proof(&mut self) -> Result<(), TransactionError> {
+        if self.cancel_handle.is_cancelled() {
+            return Err(TransactionError::ProvingCancelled);
+        }
+        Ok(())
+    }
+
+    fn after_proof(&mut self, stage: ProofStage, circuit_name: &str) {
+        self.completed += 1;
+        let progress = ProvingProgress {
+            stage,
+            circuit_name: circuit_name.to_string(),
+            completed: self.completed,
+            total: self.total,
+        };
+        for subscriber in self.subscribers.iter_mut() {
+            subscriber(progress.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::vp_circuit::ValidityPredicateVerifyingInfo;
+    use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
+    use crate::note::{NoteType, RandomSeed};
+    use crate::shielded_ptx::testing::create_trivial_swap_proving_info;
+    use pasta_curves::pallas;
+    use rand::rngs::OsRng;
+    use std::sync::Mutex;
+
+    fn create_session() -> ProvingSession {
+        let compressed_trivial_vp_vk = TrivialValidityPredicateCircuit::default()
+            .get_vp_vk()
+            .get_compressed();
+        let note_type = NoteType::new(compressed_trivial_vp_vk, pallas::Base::zero());
+        let (input_info, output_info) = create_trivial_swap_proving_info(
+            (note_type, 5),
+            (note_type, 5),
+            &RandomSeed::random(OsRng),
+        );
+        ProvingSession::new(vec![PtxProvingRequest::new(
+            input_info,
+            output_info,
+            vec![],
+        )])
+    }
+
+    #[test]
+    fn test_proving_session_progress() {
+        let mut session = create_session();
+        // The trivial vps of the four notes and the two action proofs
+        assert_eq!(session.total(), 6);
+        let events = Arc::new(Mutex::new(vec![]));
+        let recorded = events.clone();
+        session.subscribe(move |progress| recorded.lock().unwrap().push(progress));
+
+        let tx = session.run(OsRng).unwrap();
+        tx.execute().unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 6);
+        for (i, progress) in events.iter().enumerate() {
+            assert_eq!(progress.completed, i + 1);
+            assert_eq!(progress.total, 6);
+        }
+        assert!(events[..4]
+            .iter()
+            .all(|progress| progress.stage == ProofStage::VPProof
+                && progress.circuit_name == "TrivialValidityPredicateCircuit"));
+        assert!(events[4..]
+            .iter()
+            .all(|progress| progress.stage == ProofStage::ActionProof));
+    }
+
+    #[test]
+    fn test_proving_session_cancel() {
+        let mut session = create_session();
+        let cancel_handle = session.cancel_handle();
+        let stages = Arc::new(Mutex::new(0));
+        let counted = stages.clone();
+        session.subscribe(move |_| {
+            *counted.lock().unwrap() += 1;
+            cancel_handle.cancel();
+        });
+
+        assert!(matches!(session.run(OsRng), Err(ProvingError::Cancelled)));
+        assert_eq!(*stages.lock().unwrap(), 1);
+
+        // Cancelled before running
+        let session = create_session();
+        session.cancel();
+        assert!(matches!(session.run(OsRng), Err(ProvingError::Cancelled)));
+    }
+}
@@ -0,0 +1,157 @@
+//! Payment receipts of token notes.
+//!
+//! A receipt shows that a transaction created a token note of some value to a receiver. It opens
+//! one output note commitment of the transaction and carries the public data of the transaction
+//! id, so the merchant only needs the id. The nullifier key is not included, the receipt doesn't
+//! allow to compute the nullifier of the note or to link the other notes of the sender.
+use crate::circuit::vp_examples::token::{Token, TOKEN_AUTHORIZATION_SCHEMA};
+use crate::note::encoding::StaticDataValue;
+use crate::note::{Note, NoteCommitmentPreimage};
+use crate::transaction::{Transaction, TransactionId, TransactionIdPreimage};
+use pasta_curves::pallas;
+
+/// The receiver binding in the app_data_dynamic of a token note, see TOKEN_AUTHORIZATION_SCHEMA.
+/// The receiver auth pk is provided by the verifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiverOpening {
+    pub auth_vk: pallas::Base,
+    pub receiver_vp_vk: pallas::Base,
+    pub rcv_pk: pallas::Point,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentReceipt {
+    tx: TransactionIdPreimage,
+    // The index of the note commitment in the shielded output commitments of the tx
+    output_index: usize,
+    value: u64,
+    receiver: ReceiverOpening,
+    // The rest of the commitment preimage
+    nk_commitment: pallas::Base,
+    rho: pallas::Base,
+    psi: pallas::Base,
+    is_merkle_checked: pallas::Base,
+    rcm: pallas::Base,
+}
+
+impl PaymentReceipt {
+    /// Panics if the note is not the output note at `output_index` of the tx.
+    pub fn create(
+        tx: &Transaction,
+        output_index: usize,
+        note: &Note,
+        receiver: ReceiverOpening,
+    ) -> Self {
+        let tx = tx.id_preimage();
+        assert_eq!(
+            tx.shielded_output_cms().get(output_index),
+            Some(&note.commitment().to_bytes()),
+            "the note is not created by the transaction"
+        );
+        let preimage = note.commitment_preimage();
+        Self {
+            tx,
+            output_index,
+            value: note.value,
+            receiver,
+            nk_commitment: preimage.nk_commitment,
+            rho: preimage.rho,
+            psi: preimage.psi,
+            is_merkle_checked: preimage.is_merkle_checked,
+            rcm: preimage.rcm,
+        }
+    }
+
+    /// Whether the transaction `tx_id` created a note of `expected_value` of `expected_token` to
+    /// `receiver_pk`.
+    pub fn verify(
+        &self,
+        tx_id: &TransactionId,
+        expected_value: u64,
+        expected_token: &Token,
+        receiver_pk: pallas::Point,
+    ) -> bool {
+        if self.tx.id() != *tx_id || self.value != expected_value {
+            return false;
+        }
+        let app_data_dynamic = match TOKEN_AUTHORIZATION_SCHEMA.encode(&[
+            StaticDataValue::Point(receiver_pk),
+            StaticDataValue::Base(self.receiver.auth_vk),
+            StaticDataValue::Base(self.receiver.receiver_vp_vk),
+            StaticDataValue::Point(self.receiver.rcv_pk),
+        ]) {
+            Ok(encoded) => encoded,
+            Err(_) => return false,
+        };
+        let note_type = expected_token.note_type();
+        let cm = NoteCommitmentPreimage {
+            app_vk: note_type.app_vk,
+            app_data_static: note_type.app_data_static,
+            app_data_dynamic,
+            nk_commitment: self.nk_commitment,
+            rho: self.rho,
+            psi: self.psi,
+            is_merkle_checked: self.is_merkle_checked,
+            value: pallas::Base::from(self.value),
+            rcm: self.rcm,
+        }
+        .commitment();
+        self.tx.shielded_output_cms().get(self.output_index) == Some(&cm.to_bytes())
+    }
+}
+
+#[test]
+fn test_payment_receipt() {
+    use crate::circuit::vp_examples::receiver_vp::COMPRESSED_RECEIVER_VK;
+    use crate::circuit::vp_examples::token::TokenName;
+    use crate::shielded_ptx::testing::create_token_ptx_with_output;
+    use crate::transaction::{ShieldedPartialTxBundle, TransparentPartialTxBundle};
+    use group::Group;
+    use rand::rngs::OsRng;
+
+    let (ptx, output_note, auth) = create_token_ptx_with_output();
+    let tx = Transaction::build(
+        OsRng,
+        ShieldedPartialTxBundle::new(vec![ptx]),
+        TransparentPartialTxBundle::default(),
+    )
+    .unwrap();
+    let output_index = tx
+        .id_preimage()
+        .shielded_output_cms()
+        .iter()
+        .position(|cm| *cm == output_note.commitment().to_bytes())
+        .unwrap();
+    let receiver = ReceiverOpening {
+        auth_vk: auth.vk,
+        receiver_vp_vk: *COMPRESSED_RECEIVER_VK,
+        rcv_pk: auth.rcv_pk,
+    };
+    let receipt = PaymentReceipt::create(&tx, output_index, output_note.note(), receiver);
+    let token = Token::new(TokenName::new("btc").unwrap(), 5);
+    let tx_id = tx.id();
+
+    // The valid receipt
+    assert!(receipt.verify(&tx_id, 5, &token, auth.pk));
+
+    // Wrong expected value, token, tx id or receiver
+    assert!(!receipt.verify(&tx_id, 6, &token, auth.pk));
+    assert!(!receipt.verify(
+        &tx_id,
+        5,
+        &Token::new(TokenName::new("eth").unwrap(), 5),
+        auth.pk
+    ));
+    assert!(!receipt.verify(&TransactionId::from_bytes([0; 32]), 5, &token, auth.pk));
+    assert!(!receipt.verify(&tx_id, 5, &token, pallas::Point::random(OsRng)));
+
+    // The value in the receipt is altered
+    let mut altered = receipt.clone();
+    altered.value = 6;
+    assert!(!altered.verify(&tx_id, 6, &token, auth.pk));
+
+    // The receipt points at another output note
+    let mut altered = receipt;
+    altered.output_index = 1 - output_index;
+    assert!(!altered.verify(&tx_id, 5, &token, auth.pk));
+}
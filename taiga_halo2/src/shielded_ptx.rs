@@ -1,18 +1,47 @@
 use crate::action::{ActionInfo, ActionPublicInputs};
-use crate::circuit::vp_circuit::{VPVerifyingInfo, ValidityPredicate};
+use crate::beacon::{check_vp_padding, vp_padding_rng};
+use crate::block::BlockProofBatch;
+#[cfg(feature = "prover")]
+use crate::circuit::action_circuit::AggregatedActionCircuit;
+use crate::circuit::vp_circuit::{
+    SharedPublicInputs, VPVerifyingInfo, ValidityPredicate, ValidityPredicatePublicInputs,
+};
+#[cfg(feature = "prover")]
+use crate::constant::{get_action_proving_key, ACTION_PROVING_KEY, AGGREGATED_ACTION_PROVING_KEY};
 use crate::constant::{
-    ACTION_CIRCUIT_PARAMS_SIZE, ACTION_PROVING_KEY, ACTION_VERIFYING_KEY, MAX_DYNAMIC_VP_NUM,
-    NUM_NOTE, SETUP_PARAMS_MAP,
+    get_action_verifying_key, get_params, ACTION_CIRCUIT_PARAMS_SIZE, ACTION_PUBLIC_INPUT_NUM,
+    ACTION_VERIFYING_KEY, AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE,
+    AGGREGATED_ACTION_PUBLIC_INPUT_NUM, AGGREGATED_ACTION_VERIFYING_KEY, MAX_DYNAMIC_VP_NUM,
+    MAX_TOTAL_VALUE, NOTE_CIPHERTEXT_COMMITMENT_SENTINEL, NUM_NOTE,
+    PTX_CONTENT_HASH_PERSONALIZATION, VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX,
+    VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX, VP_CIRCUIT_PARAMS_SIZE,
+    VP_CIRCUIT_PUBLIC_INPUT_NUM, VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX,
 };
+use crate::cost::{proof_scalar_muls, PtxCostReport};
 use crate::error::TransactionError;
 use crate::executable::Executable;
+use crate::explorer::{ActionPublicView, PtxPublicView};
 use crate::merkle_tree::Anchor;
-use crate::note::{InputNoteProvingInfo, NoteCommitment, OutputNoteProvingInfo};
+use crate::note::{
+    InputNoteProvingInfo, Note, NoteCommitment, NoteType, OutputNoteProvingInfo, RandomSeed,
+};
+use crate::note_encryption::EncryptedNote;
 use crate::nullifier::Nullifier;
 use crate::proof::Proof;
+#[cfg(feature = "prover")]
+use crate::proving_session::{ProofStage, ProvingHook};
+use crate::simulation::{ActionPlan, PtxPlan};
+use crate::utils::point_to_bytes;
 use crate::value_commitment::ValueCommitment;
-use halo2_proofs::plonk::Error;
-use pasta_curves::pallas;
+use crate::vk_cache::VerifierKeyCache;
+use crate::vp_vk::ValidityPredicateVerifyingKey;
+use blake2b_simd::Params as Blake2bParams;
+use ff::PrimeField;
+use halo2_proofs::{arithmetic::CurveAffine, plonk::Error};
+use pasta_curves::{
+    group::{Curve, Group},
+    pallas,
+};
 use rand::RngCore;
 
 #[cfg(feature = "nif")]
@@ -25,17 +54,29 @@ use serde;
 use crate::circuit::vp_bytecode::ApplicationByteCode;
 #[cfg(feature = "borsh")]
 use borsh::{BorshDeserialize, BorshSerialize};
-#[cfg(feature = "borsh")]
-use ff::PrimeField;
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ShieldedPartialTransaction {
-    actions: [ActionVerifyingInfo; NUM_NOTE],
+    actions: [ActionPublicInputs; NUM_NOTE],
+    action_proofs: ActionProofs,
     inputs: [NoteVPVerifyingInfoSet; NUM_NOTE],
     outputs: [NoteVPVerifyingInfoSet; NUM_NOTE],
     binding_sig_r: pallas::Scalar,
     hints: Vec<u8>,
+    // The application payload, it's not checked by any circuit but signed by the binding signature
+    extra_data: Vec<u8>,
+}
+
+/// The action proofs of a ptx, either one proof per action or one aggregated proof of all the
+/// actions, see `ShieldedPartialTransaction::build_aggregated`. The public inputs are the same.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ActionProofs {
+    Separate([Proof; NUM_NOTE]),
+    // The proof of the AggregatedActionCircuit
+    Aggregated(Proof),
 }
 
 #[derive(Debug, Clone)]
@@ -65,15 +106,19 @@ pub struct NoteVPVerifyingInfoSet {
 #[cfg_attr(feature = "nif", derive(NifStruct))]
 #[cfg_attr(feature = "nif", module = "Taiga.Shielded.PTX")]
 struct ShieldedPartialTransactionProxy {
-    actions: Vec<ActionVerifyingInfo>,
+    actions: Vec<ActionPublicInputs>,
+    // NUM_NOTE separate proofs, or the aggregated one
+    action_proofs: Vec<Proof>,
     inputs: Vec<NoteVPVerifyingInfoSet>,
     outputs: Vec<NoteVPVerifyingInfoSet>,
     binding_sig_r: pallas::Scalar,
     hints: Vec<u8>,
+    // The application payload, it's not checked by any circuit but signed by the binding signature
+    extra_data: Vec<u8>,
 }
 
 impl ShieldedPartialTransaction {
-    #[cfg(feature = "borsh")]
+    #[cfg(all(feature = "borsh", feature = "prover"))]
     pub fn from_bytecode<R: RngCore>(
         actions: Vec<ActionInfo>,
         input_note_app: Vec<ApplicationByteCode>,
@@ -97,79 +142,449 @@ impl ShieldedPartialTransaction {
                 ActionVerifyingInfo::create(action_info, &mut rng).unwrap()
             })
             .collect();
+        let (actions, action_proofs) = ActionVerifyingInfo::split(actions);
 
         Self {
-            actions: actions.try_into().unwrap(),
+            actions,
+            action_proofs,
             inputs: inputs.try_into().unwrap(),
             outputs: outputs.try_into().unwrap(),
             binding_sig_r: rcv_sum,
             hints,
+            extra_data: vec![],
         }
     }
 
+    #[cfg(feature = "prover")]
     pub fn build<R: RngCore>(
         input_info: [InputNoteProvingInfo; NUM_NOTE],
         output_info: [OutputNoteProvingInfo; NUM_NOTE],
         hints: Vec<u8>,
+        rng: R,
+    ) -> Result<Self, TransactionError> {
+        Self::build_inner(
+            input_info,
+            output_info,
+            hints,
+            false,
+            false,
+            None,
+            &mut (),
+            rng,
+        )
+    }
+
+    /// Same as build, with the actions proved in one aggregated action proof instead of one proof
+    /// per action. The proof is about half the size of the separate ones, it's created and
+    /// verified with the params of AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE.
+    #[cfg(feature = "prover")]
+    pub fn build_aggregated<R: RngCore>(
+        input_info: [InputNoteProvingInfo; NUM_NOTE],
+        output_info: [OutputNoteProvingInfo; NUM_NOTE],
+        hints: Vec<u8>,
+        rng: R,
+    ) -> Result<Self, TransactionError> {
+        Self::build_inner(
+            input_info,
+            output_info,
+            hints,
+            false,
+            true,
+            None,
+            &mut (),
+            rng,
+        )
+    }
+
+    // Same as build, with the hook called around each vp and action proof, see `ProvingSession`
+    #[cfg(feature = "prover")]
+    pub(crate) fn build_with_hook<R: RngCore>(
+        input_info: [InputNoteProvingInfo; NUM_NOTE],
+        output_info: [OutputNoteProvingInfo; NUM_NOTE],
+        hints: Vec<u8>,
+        hook: &mut dyn ProvingHook,
+        rng: R,
+    ) -> Result<Self, TransactionError> {
+        Self::build_inner(
+            input_info,
+            output_info,
+            hints,
+            false,
+            false,
+            None,
+            hook,
+            rng,
+        )
+    }
+
+    // Same as build, but also checks the value bases of the notes are consistent with the
+    // application vps, and returns an error if not.
+    #[cfg(feature = "prover")]
+    pub fn build_strict<R: RngCore>(
+        input_info: [InputNoteProvingInfo; NUM_NOTE],
+        output_info: [OutputNoteProvingInfo; NUM_NOTE],
+        hints: Vec<u8>,
+        rng: R,
+    ) -> Result<Self, TransactionError> {
+        Self::build_inner(
+            input_info,
+            output_info,
+            hints,
+            true,
+            false,
+            None,
+            &mut (),
+            rng,
+        )
+    }
+
+    /// Same as build, with the public input padding of the vp proofs derived from the seed opened
+    /// by `opening`. The seed commitment is recorded in the extra data, so the padding can be
+    /// checked with `beacon::audit_padding` once the opening is revealed.
+    #[cfg(feature = "prover")]
+    pub fn build_with_padding_seed<R: RngCore>(
+        input_info: [InputNoteProvingInfo; NUM_NOTE],
+        output_info: [OutputNoteProvingInfo; NUM_NOTE],
+        hints: Vec<u8>,
+        seed_commitment: [u8; 32],
+        opening: &[u8],
+        rng: R,
+    ) -> Result<Self, TransactionError> {
+        let padding_seed = RandomSeed::from_commitment(seed_commitment, opening)
+            .ok_or(TransactionError::InvalidPaddingSeedOpening)?;
+        let mut ptx = Self::build_inner(
+            input_info,
+            output_info,
+            hints,
+            false,
+            false,
+            Some(&padding_seed),
+            &mut (),
+            rng,
+        )?;
+        ptx.extra_data = seed_commitment.to_vec();
+        Ok(ptx)
+    }
+
+    #[cfg(feature = "prover")]
+    #[allow(clippy::too_many_arguments)]
+    fn build_inner<R: RngCore>(
+        input_info: [InputNoteProvingInfo; NUM_NOTE],
+        output_info: [OutputNoteProvingInfo; NUM_NOTE],
+        hints: Vec<u8>,
+        strict: bool,
+        aggregated: bool,
+        padding_seed: Option<&RandomSeed>,
+        hook: &mut dyn ProvingHook,
         mut rng: R,
-    ) -> Self {
-        let inputs: Vec<NoteVPVerifyingInfoSet> = input_info
-            .iter()
-            .map(|input_note| {
-                NoteVPVerifyingInfoSet::build(
-                    input_note.get_application_vp(),
-                    input_note.get_dynamic_vps(),
-                )
-            })
-            .collect();
-        let outputs: Vec<NoteVPVerifyingInfoSet> = output_info
-            .iter()
-            .map(|output_note| {
-                NoteVPVerifyingInfoSet::build(
-                    output_note.get_application_vp(),
-                    output_note.get_dynamic_vps(),
-                )
-            })
-            .collect();
+    ) -> Result<Self, TransactionError> {
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
+        check_value_totals(input_info.iter().map(|input| &input.note))?;
+        check_value_totals(output_info.iter().map(|output| &output.note))?;
+        // The input notes must be spendable, i.e. carry the nk, and open to the anchors
+        for input in input_info.iter() {
+            input.note.get_nf()?;
+            input.check_anchor()?;
+        }
+        // The vp circuits are consumed one by one, each of them is dropped once its proof is
+        // created. Only the notes are kept for the action circuits.
+        let mut input_parts = vec![];
+        let mut inputs = vec![];
+        for input in input_info {
+            let (note, merkle_path, anchor, vp_info) = input.prove_vps(padding_seed, hook)?;
+            input_parts.push((note, merkle_path, anchor));
+            inputs.push(vp_info);
+        }
+        let mut output_parts = vec![];
+        let mut outputs = vec![];
+        for output in output_info {
+            let (note, note_encryption, vp_info) = output.prove_vps(padding_seed, hook)?;
+            output_parts.push((note, note_encryption));
+            outputs.push(vp_info);
+        }
         let mut rcv_sum = pallas::Scalar::zero();
-        let actions: Vec<ActionVerifyingInfo> = input_info
+        let mut expected_cv_net = pallas::Point::identity();
+        let action_infos: Vec<ActionInfo> = input_parts
             .into_iter()
-            .zip(output_info)
-            .map(|(input, output)| {
-                let action_info = ActionInfo::from_proving_info(input, output, &mut rng);
-                rcv_sum += action_info.get_rcv();
-                ActionVerifyingInfo::create(action_info, &mut rng).unwrap()
-            })
+            .zip(output_parts)
+            .zip(inputs.iter().zip(outputs.iter()))
+            .map(
+                |(
+                    ((input_note, merkle_path, anchor), (output_note, note_encryption)),
+                    (input_vps, output_vps),
+                )| {
+                    let mut action_info = ActionInfo::new(
+                        input_note,
+                        merkle_path,
+                        anchor,
+                        output_note,
+                        RandomSeed::random(&mut rng),
+                    );
+                    if let Some(note_encryption) = note_encryption {
+                        action_info.set_note_encryption(note_encryption);
+                    }
+
+                    // Derive the value bases from the application vps natively
+                    let mut input_note = input_note;
+                    input_note.note_type =
+                        NoteType::new(input_vps.get_app_vk(), input_note.get_app_data_static());
+                    let mut output_note = output_note;
+                    output_note.note_type =
+                        NoteType::new(output_vps.get_app_vk(), output_note.get_app_data_static());
+
+                    let rcv = action_info.get_rcv();
+                    rcv_sum += rcv;
+                    expected_cv_net +=
+                        ValueCommitment::new(&input_note, &output_note, &rcv).inner();
+                    action_info
+                },
+            )
             .collect();
 
-        Self {
-            actions: actions.try_into().unwrap(),
+        // Check the net value commitment derived from the application vps matches the one in the
+        // action circuits before creating the action proofs.
+        if strict {
+            let cv_net = action_infos
+                .iter()
+                .fold(pallas::Point::identity(), |acc, action_info| {
+                    acc + action_info.build().0.cv_net.inner()
+                });
+            if cv_net != expected_cv_net {
+                return Err(TransactionError::InconsistentValueBase);
+            }
+        }
+
+        let (actions, action_proofs) = if aggregated {
+            hook.before_proof()?;
+            let aggregated_proof = ActionVerifyingInfo::create_aggregated(action_infos, &mut rng)?;
+            hook.after_proof(ProofStage::ActionProof, "AggregatedActionCircuit");
+            aggregated_proof
+        } else {
+            let mut actions = vec![];
+            for action_info in action_infos {
+                hook.before_proof()?;
+                actions.push(ActionVerifyingInfo::create(action_info, &mut rng)?);
+                hook.after_proof(ProofStage::ActionProof, "ActionCircuit");
+            }
+            ActionVerifyingInfo::split(actions)
+        };
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_span(
+            crate::metrics::ProvingStage::PartialTransactionBuild,
+            "ShieldedPartialTransaction",
+            None,
+            start,
+            None,
+        );
+
+        Ok(Self {
+            actions,
+            action_proofs,
             inputs: inputs.try_into().unwrap(),
             outputs: outputs.try_into().unwrap(),
             binding_sig_r: rcv_sum,
             hints,
+            extra_data: vec![],
+        })
+    }
+
+    /// The native checks of `build_strict` without the proofs: the values, the anchors, the
+    /// nullifiers, the commitments and the rho of the output notes. If check_vps is set, the value bases are
+    /// checked against the application vps and the vp constraints are run in the MockProver.
+    /// The ptx_index in the returned errors is always 0.
+    pub fn dry_run(
+        input_info: &[InputNoteProvingInfo; NUM_NOTE],
+        output_info: &[OutputNoteProvingInfo; NUM_NOTE],
+        check_vps: bool,
+    ) -> Result<PtxPlan, TransactionError> {
+        check_value_totals(input_info.iter().map(|input| &input.note))?;
+        check_value_totals(output_info.iter().map(|output| &output.note))?;
+        for input in input_info.iter() {
+            input.check_anchor()?;
+        }
+
+        if check_vps {
+            let vps = input_info
+                .iter()
+                .map(|input| {
+                    (
+                        input.note,
+                        input.get_application_vp(),
+                        input.get_dynamic_vps(),
+                    )
+                })
+                .chain(output_info.iter().map(|output| {
+                    (
+                        output.note,
+                        output.get_application_vp(),
+                        output.get_dynamic_vps(),
+                    )
+                }));
+            for (note_index, (note, app_vp, dynamic_vps)) in vps.enumerate() {
+                let note_type = NoteType::new(
+                    app_vp.get_vp_vk().get_compressed(),
+                    note.get_app_data_static(),
+                );
+                if note_type != note.note_type {
+                    return Err(TransactionError::InconsistentValueBase);
+                }
+                for (vp_index, vp) in std::iter::once(app_vp).chain(dynamic_vps).enumerate() {
+                    if vp.dev_check().is_err() {
+                        return Err(TransactionError::VPConstraintFailure {
+                            ptx_index: 0,
+                            note_index,
+                            vp_index,
+                        });
+                    }
+                }
+            }
         }
+
+        let actions = input_info
+            .iter()
+            .zip(output_info.iter())
+            .map(|(input, output)| {
+                let nf = input.note.get_nf()?;
+                if output.note.rho != nf {
+                    return Err(TransactionError::InconsistentOutputNoteRho);
+                }
+                Ok(ActionPlan {
+                    nf,
+                    anchor: input.anchor,
+                    input_note_type: input.note.note_type,
+                    input_value: input.note.value,
+                    cm: output.note.commitment(),
+                    output_note_type: output.note.note_type,
+                    output_value: output.note.value,
+                })
+            })
+            .collect::<Result<Vec<_>, TransactionError>>()?;
+        Ok(PtxPlan::new(actions))
     }
 
     // verify zk proof
+    // The ptx_index in the returned errors is always 0, the bundle sets the real index.
     pub fn verify_proof(&self) -> Result<(), TransactionError> {
-        // Verify action proofs
-        for verifying_info in self.actions.iter() {
-            verifying_info.verify()?;
+        self.verify_action_proofs()?;
+
+        // Verify vp proofs from input notes and output notes
+        for (note_index, verifying_info) in
+            self.inputs.iter().chain(self.outputs.iter()).enumerate()
+        {
+            for (vp_index, vp_info) in verifying_info.get_vp_verifying_infos().enumerate() {
+                vp_info.verify().map_err(|e| {
+                    verify_error(
+                        e,
+                        TransactionError::VPProofVerificationFailure {
+                            ptx_index: 0,
+                            note_index,
+                            vp_index,
+                        },
+                    )
+                })?;
+            }
         }
 
-        // Verify vp proofs from input notes
-        for verifying_info in self.inputs.iter() {
-            verifying_info.verify()?;
+        Ok(())
+    }
+
+    // Same as verify_proof followed by check_app_vks, with the application vp proofs verified under
+    // the cached vks of the note app_vks
+    fn verify_proof_with_cached_vks(&self) -> Result<(), TransactionError> {
+        self.verify_action_proofs()?;
+
+        let app_vks = self
+            .actions
+            .iter()
+            .map(|action| action.input_app_vk)
+            .chain(self.actions.iter().map(|action| action.output_app_vk));
+        for (note_index, (verifying_info, app_vk)) in self
+            .inputs
+            .iter()
+            .chain(self.outputs.iter())
+            .zip(app_vks)
+            .enumerate()
+        {
+            verifying_info.verify_with_cached_app_vk(app_vk, |vp_index, e| {
+                verify_error(
+                    e,
+                    TransactionError::VPProofVerificationFailure {
+                        ptx_index: 0,
+                        note_index,
+                        vp_index,
+                    },
+                )
+            })?;
         }
-        // Verify vp proofs from output notes
-        for verifying_info in self.outputs.iter() {
-            verifying_info.verify()?;
+
+        Ok(())
+    }
+
+    // Either layout of the action proofs is accepted. The aggregated proof is reported as the
+    // action proof 0.
+    fn verify_action_proofs(&self) -> Result<(), TransactionError> {
+        match &self.action_proofs {
+            ActionProofs::Separate(proofs) => {
+                let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+                for (action_index, (proof, action)) in
+                    proofs.iter().zip(self.actions.iter()).enumerate()
+                {
+                    proof
+                        .verify_single_instance(
+                            &ACTION_VERIFYING_KEY,
+                            params,
+                            &action.to_vec(),
+                            ACTION_PUBLIC_INPUT_NUM,
+                        )
+                        .map_err(|e| {
+                            verify_error(
+                                e,
+                                TransactionError::ActionProofVerificationFailure {
+                                    ptx_index: 0,
+                                    action_index,
+                                },
+                            )
+                        })?;
+                }
+            }
+            ActionProofs::Aggregated(proof) => {
+                let params = &get_params(AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE);
+                proof
+                    .verify_single_instance(
+                        &AGGREGATED_ACTION_VERIFYING_KEY,
+                        params,
+                        &self.aggregated_action_instance(),
+                        AGGREGATED_ACTION_PUBLIC_INPUT_NUM,
+                    )
+                    .map_err(|e| {
+                        verify_error(
+                            e,
+                            TransactionError::ActionProofVerificationFailure {
+                                ptx_index: 0,
+                                action_index: 0,
+                            },
+                        )
+                    })?;
+            }
         }
 
         Ok(())
     }
 
+    // The public inputs of the aggregated action proof, the actions one after the other
+    fn aggregated_action_instance(&self) -> Vec<pallas::Base> {
+        self.actions
+            .iter()
+            .flat_map(|action| action.to_vec())
+            .collect()
+    }
+
+    pub fn get_action_proofs(&self) -> &ActionProofs {
+        &self.action_proofs
+    }
+
     // check the nullifiers are from action proofs
     fn check_nullifiers(&self) -> Result<(), TransactionError> {
         assert_eq!(NUM_NOTE, 2);
@@ -234,15 +649,227 @@ impl ShieldedPartialTransaction {
         Ok(())
     }
 
+    // check the application vps are the ones committed in the notes of the action proofs
+    fn check_app_vks(&self) -> Result<(), TransactionError> {
+        for (action, (input, output)) in self
+            .actions
+            .iter()
+            .zip(self.inputs.iter().zip(self.outputs.iter()))
+        {
+            input.check_app_vk(action.input_app_vk)?;
+            output.check_app_vk(action.output_app_vk)?;
+        }
+        Ok(())
+    }
+
+    // check the anchors signed by the vps are the action anchors, otherwise a signature could be
+    // replayed in a ptx under other anchors. A vp publicizes the index of the anchors it signs,
+    // the index is a constant of the circuit and 0 if it signs none, see `signing_message_gadget`
+    fn check_signed_anchors(&self) -> Result<(), TransactionError> {
+        let anchors = self.get_anchors();
+        for vp_info in self.get_vp_verifying_infos() {
+            let public_inputs = vp_info.public_inputs.inner();
+            let signed_anchors_idx = public_inputs[VP_CIRCUIT_SIGNED_ANCHORS_IDX_PUBLIC_INPUT_IDX];
+            if signed_anchors_idx == pallas::Base::zero() {
+                continue;
+            }
+            let signed_anchors_idx = (VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX
+                ..=public_inputs.len() - NUM_NOTE)
+                .find(|idx| pallas::Base::from(*idx as u64) == signed_anchors_idx)
+                .ok_or(TransactionError::InconsistentSignedAnchor)?;
+            let signed_anchors = &public_inputs[signed_anchors_idx..][..NUM_NOTE];
+            if anchors
+                .iter()
+                .zip(signed_anchors)
+                .any(|(anchor, signed_anchor)| anchor.inner() != *signed_anchor)
+            {
+                return Err(TransactionError::InconsistentSignedAnchor);
+            }
+        }
+        Ok(())
+    }
+
+    // check the output note ciphertexts are consistent with the cipher commitments from action proofs.
+    // The action circuit derives the cipher commitment from the output note, so the committed
+    // ciphertext decrypts to the note.
+    fn check_note_ciphertexts(&self) -> Result<(), TransactionError> {
+        for (action, vp_info) in self.actions.iter().zip(self.outputs.iter()) {
+            let cipher_commitment = action.cipher_commitment;
+            // The note is not sent with an encrypted payload, unless its application vp binds an
+            // encryption vp
+            if cipher_commitment == NOTE_CIPHERTEXT_COMMITMENT_SENTINEL {
+                if vp_info.declared_encryption_vp_vk() != pallas::Base::zero() {
+                    return Err(TransactionError::MissingNoteCiphertext);
+                }
+                continue;
+            }
+
+            // Only the vp bound to the note can publish the committed ciphertext
+            let is_published = vp_info.encryption_vp().map_or(false, |vp| {
+                vp.public_inputs.owned_note_pub_id() == action.cm.inner()
+                    && vp.public_inputs.get_ciphertext().commitment() == cipher_commitment
+            });
+            if !is_published {
+                return Err(TransactionError::InconsistentNoteCiphertext);
+            }
+        }
+        Ok(())
+    }
+
+    // The public content of the actions and the payload, see `Transaction::public_view`
+    pub(crate) fn public_view(&self) -> PtxPublicView {
+        let actions = self
+            .actions
+            .iter()
+            .zip(self.outputs.iter())
+            .map(|(instance, vp_info)| {
+                let (cv_net_x, cv_net_y) = instance
+                    .cv_net
+                    .inner()
+                    .to_affine()
+                    .coordinates()
+                    .map(|c| (*c.x(), *c.y()))
+                    .unwrap_or((pallas::Base::zero(), pallas::Base::zero()));
+                let encrypted_note = Self::committed_ciphertext(instance, vp_info);
+                let note_ciphertext = encrypted_note.as_ref().map(|encrypted_note| {
+                    encrypted_note
+                        .ciphertext
+                        .inner()
+                        .iter()
+                        .map(|e| e.to_repr())
+                        .collect()
+                });
+                let note_sender_pk =
+                    encrypted_note.map(|encrypted_note| point_to_bytes(&encrypted_note.sender_pk));
+                ActionPublicView {
+                    anchor: instance.anchor.to_bytes(),
+                    nullifier: instance.nf.to_bytes(),
+                    output_cm: instance.cm.to_bytes(),
+                    cv_net_x: cv_net_x.to_repr(),
+                    cv_net_y: cv_net_y.to_repr(),
+                    input_app_vk: instance.input_app_vk.to_repr(),
+                    output_app_vk: instance.output_app_vk.to_repr(),
+                    note_ciphertext,
+                    note_sender_pk,
+                }
+            })
+            .collect();
+        PtxPublicView {
+            actions,
+            extra_data: self.extra_data.clone(),
+        }
+    }
+
     // Conversion to the generic length proxy
     fn to_proxy(&self) -> ShieldedPartialTransactionProxy {
         ShieldedPartialTransactionProxy {
             actions: self.actions.to_vec(),
+            action_proofs: self.action_proofs.proofs().to_vec(),
             inputs: self.inputs.to_vec(),
             outputs: self.outputs.to_vec(),
             binding_sig_r: self.binding_sig_r,
             hints: self.hints.clone(),
+            extra_data: self.extra_data.clone(),
+        }
+    }
+
+    // The vp proofs of the input notes, then the output notes
+    pub fn get_vp_verifying_infos(&self) -> impl Iterator<Item = &VPVerifyingInfo> {
+        self.inputs
+            .iter()
+            .chain(self.outputs.iter())
+            .flat_map(|vp_info| vp_info.get_vp_verifying_infos())
+    }
+
+    // The checks of execute except the proofs, the block verifier checks the proofs in a batch
+    pub(crate) fn check_public_data(&self) -> Result<(), TransactionError> {
+        self.check_app_vks()?;
+        self.check_note_data()
+    }
+
+    fn check_note_data(&self) -> Result<(), TransactionError> {
+        self.check_nullifiers()?;
+        self.check_note_commitments()?;
+        self.check_note_ciphertexts()?;
+        self.check_signed_anchors()?;
+        Ok(())
+    }
+
+    pub(crate) fn add_proofs_to_batch(&self, batch: &mut BlockProofBatch) {
+        match &self.action_proofs {
+            ActionProofs::Separate(proofs) => {
+                for (proof, action) in proofs.iter().zip(self.actions.iter()) {
+                    batch.add_action_proof(proof, action.to_vec());
+                }
+            }
+            ActionProofs::Aggregated(proof) => {
+                batch.add_aggregated_action_proof(proof, self.aggregated_action_instance())
+            }
         }
+        for vp_info in self.get_vp_verifying_infos() {
+            batch.add_vp_proof(
+                &vp_info.vk,
+                &vp_info.proof,
+                vp_info.public_inputs.to_instance(),
+            );
+        }
+    }
+
+    // The action proofs and their instances
+    #[cfg(feature = "transcript")]
+    pub(crate) fn proof_checks(&self) -> Vec<crate::verification_transcript::ProofCheck> {
+        use crate::verification_transcript::{ProofCheck, ProofKind};
+        let actions: Vec<ProofCheck> = match &self.action_proofs {
+            ActionProofs::Separate(proofs) => proofs
+                .iter()
+                .zip(self.actions.iter())
+                .map(|(proof, action)| {
+                    ProofCheck::new(
+                        ProofKind::Action,
+                        &ACTION_VERIFYING_KEY,
+                        &action.to_vec(),
+                        proof,
+                    )
+                })
+                .collect(),
+            ActionProofs::Aggregated(proof) => vec![ProofCheck::new(
+                ProofKind::AggregatedAction,
+                &AGGREGATED_ACTION_VERIFYING_KEY,
+                &self.aggregated_action_instance(),
+                proof,
+            )],
+        };
+        let vps = self
+            .inputs
+            .iter()
+            .chain(self.outputs.iter())
+            .flat_map(|vp_info| {
+                std::iter::once((
+                    ProofKind::ApplicationValidityPredicate,
+                    &vp_info.app_vp_verifying_info,
+                ))
+                .chain(
+                    vp_info
+                        .app_dynamic_vp_verifying_info
+                        .iter()
+                        .map(|info| (ProofKind::DynamicValidityPredicate, info)),
+                )
+                .map(|(kind, info)| {
+                    ProofCheck::new(
+                        kind,
+                        &info.vk,
+                        &info.public_inputs.to_instance(),
+                        &info.proof,
+                    )
+                })
+            });
+        actions.into_iter().chain(vps).collect()
+    }
+
+    // The sum of the action value commitments, it binds to zero with binding_sig_r if the ptx is
+    // balanced by itself
+    pub fn get_net_value_commitment(&self) -> ValueCommitment {
+        self.actions.iter().map(|action| action.cv_net).sum()
     }
 
     pub fn get_binding_sig_r(&self) -> pallas::Scalar {
@@ -252,95 +879,401 @@ impl ShieldedPartialTransaction {
     pub fn get_hints(&self) -> Vec<u8> {
         self.hints.clone()
     }
+
+    pub fn get_extra_data(&self) -> Vec<u8> {
+        self.extra_data.clone()
+    }
+
+    /// The hash of the whole ptx: the proofs, their public inputs and vks, binding_sig_r, hints
+    /// and extra_data. The proofs are randomized, two ptxs have the same hash only if one is a
+    /// copy of the other.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut h = Blake2bParams::new()
+            .hash_length(32)
+            .personal(PTX_CONTENT_HASH_PERSONALIZATION)
+            .to_state();
+        // Variable length data is prefixed with its length
+        let mut update_bytes = |bytes: &[u8]| {
+            h.update(&(bytes.len() as u64).to_le_bytes());
+            h.update(bytes);
+        };
+        // The number of action proofs tells the layout
+        let action_proofs = self.action_proofs.proofs();
+        update_bytes(&(action_proofs.len() as u64).to_le_bytes());
+        for proof in action_proofs {
+            update_bytes(&proof.inner());
+        }
+        for action in self.actions.iter() {
+            action
+                .to_vec()
+                .iter()
+                .for_each(|x| update_bytes(&x.to_repr()));
+        }
+        for note_vps in self.inputs.iter().chain(self.outputs.iter()) {
+            update_bytes(&(note_vps.get_vp_verifying_infos().count() as u64).to_le_bytes());
+            for vp_info in note_vps.get_vp_verifying_infos() {
+                let vk = ValidityPredicateVerifyingKey::compress(&vp_info.vk);
+                update_bytes(&vk.to_repr());
+                update_bytes(&vp_info.proof.inner());
+                vp_info
+                    .public_inputs
+                    .to_instance()
+                    .iter()
+                    .for_each(|x| update_bytes(&x.to_repr()));
+            }
+        }
+        update_bytes(&self.binding_sig_r.to_repr());
+        update_bytes(&self.hints);
+        update_bytes(&self.extra_data);
+        h.finalize().as_bytes().try_into().unwrap()
+    }
+
+    // Attach the application payload, it must be set before the ptx goes into a transaction
+    pub fn set_extra_data(&mut self, extra_data: Vec<u8>) {
+        self.extra_data = extra_data;
+    }
+
+    // The ciphertext committed by the action is published by the encryption vp of the output
+    // note, see check_note_ciphertexts
+    fn committed_ciphertext(
+        action: &ActionPublicInputs,
+        vp_info: &NoteVPVerifyingInfoSet,
+    ) -> Option<EncryptedNote> {
+        let cipher_commitment = action.cipher_commitment;
+        if cipher_commitment == NOTE_CIPHERTEXT_COMMITMENT_SENTINEL {
+            return None;
+        }
+        vp_info
+            .encryption_vp()
+            .filter(|vp| vp.public_inputs.get_ciphertext().commitment() == cipher_commitment)
+            .and_then(|vp| vp.public_inputs.get_encrypted_note().ok())
+    }
+
+    /// The ciphertexts of the output notes sent with an encrypted payload, along with the output
+    /// note commitments. A wallet finds its notes by trial decryption, see
+    /// `IncomingViewingKey::decrypt`.
+    pub fn ciphertexts(&self) -> Vec<(NoteCommitment, EncryptedNote)> {
+        self.actions
+            .iter()
+            .zip(self.outputs.iter())
+            .filter_map(|(action, vp_info)| {
+                Self::committed_ciphertext(action, vp_info)
+                    .map(|encrypted_note| (action.cm, encrypted_note))
+            })
+            .collect()
+    }
+
+    // Whether the public input padding of every vp is derived from the seed, see
+    // `beacon::audit_padding`
+    pub(crate) fn check_padding(&self, padding_seed: &RandomSeed) -> bool {
+        let inputs = self.inputs.iter().map(|vp_info| (vp_info, None));
+        let outputs = self
+            .actions
+            .iter()
+            .zip(self.outputs.iter())
+            .map(|(action, vp_info)| {
+                // The vp publishing the note ciphertext
+                let encryption_vp_index =
+                    if action.cipher_commitment == NOTE_CIPHERTEXT_COMMITMENT_SENTINEL {
+                        None
+                    } else {
+                        vp_info.encryption_vp_index()
+                    };
+                (vp_info, encryption_vp_index)
+            });
+        inputs.chain(outputs).all(|(vp_info, encryption_vp_index)| {
+            vp_info
+                .get_vp_verifying_infos()
+                .enumerate()
+                .all(|(vp_index, vp)| {
+                    // The padding of the vp publishing the note ciphertext ends before the ciphertext
+                    let is_encryption_vp = encryption_vp_index == Some(vp_index);
+                    let padding_end = if is_encryption_vp {
+                        VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX
+                    } else {
+                        VP_CIRCUIT_PUBLIC_INPUT_NUM
+                    };
+                    let owned_note_pub_id = vp.public_inputs.owned_note_pub_id();
+                    check_vp_padding(
+                        &vp.public_inputs,
+                        padding_end,
+                        vp_padding_rng(padding_seed, &owned_note_pub_id, vp_index),
+                    )
+                })
+        })
+    }
+
+    // The public inputs of the output note vps, the note ciphertexts are published there
+    pub fn get_output_vp_public_inputs(&self) -> Vec<ValidityPredicatePublicInputs> {
+        self.outputs
+            .iter()
+            .flat_map(|vp_info| vp_info.get_vp_verifying_infos())
+            .map(|vp_info| vp_info.public_inputs.clone())
+            .collect()
+    }
+
+    // Check the dynamic vp committed by the application vp of an output note is attached. The
+    // commitment is hiding, so the prover hands the opening to the checker out of band.
+    pub fn check_output_dynamic_vp(
+        &self,
+        note_index: usize,
+        vp_vk: pallas::Base,
+        vp_cm_r: pallas::Base,
+    ) -> Result<(), TransactionError> {
+        self.outputs[note_index].check_dynamic_vp(vp_vk, vp_cm_r)
+    }
+
+    // The serialized size and verification cost, the sizes follow the borsh encoding
+    pub fn cost_report(&self) -> PtxCostReport {
+        let vp_infos: Vec<&VPVerifyingInfo> = self
+            .inputs
+            .iter()
+            .chain(self.outputs.iter())
+            .flat_map(|vp_info| vp_info.get_vp_verifying_infos())
+            .collect();
+        let action_proofs = self.action_proofs.proofs();
+        let (action_params_size, action_instance_count) = match self.action_proofs {
+            ActionProofs::Separate(_) => (ACTION_CIRCUIT_PARAMS_SIZE, ACTION_PUBLIC_INPUT_NUM),
+            ActionProofs::Aggregated(_) => (
+                AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE,
+                AGGREGATED_ACTION_PUBLIC_INPUT_NUM,
+            ),
+        };
+        let action_proof_sizes: Vec<usize> =
+            action_proofs.iter().map(|proof| proof.size()).collect();
+        let vp_proof_sizes: Vec<usize> = vp_infos.iter().map(|info| info.proof_size()).collect();
+        let instance_counts: Vec<usize> = action_proofs
+            .iter()
+            .map(|_| action_instance_count)
+            .chain(vp_infos.iter().map(|info| info.instance_count()))
+            .collect();
+        let scalar_muls = instance_counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let params_size = if i < action_proofs.len() {
+                    action_params_size
+                } else {
+                    VP_CIRCUIT_PARAMS_SIZE
+                };
+                proof_scalar_muls(params_size, *count)
+            })
+            .sum();
+        // The vps skip the public inputs shared with the actions
+        let [input_prefixes, output_prefixes] = shared_vp_public_inputs(&self.actions);
+        let stored_public_input_num: usize = self
+            .inputs
+            .iter()
+            .zip(input_prefixes.iter())
+            .chain(self.outputs.iter().zip(output_prefixes.iter()))
+            .flat_map(|(note, prefix)| {
+                note.get_vp_verifying_infos()
+                    .map(move |info| info.public_inputs.stored(prefix).len())
+            })
+            .sum();
+        let instance_bytes =
+            self.actions.len() * ACTION_PUBLIC_INPUTS_BYTES + stored_public_input_num * 32;
+        let vk_bytes = vp_infos.iter().map(|info| info.vk_size()).sum();
+        // The version, the action proof layout, a length prefix for every proof and dynamic vp
+        // list, a length tag for the public inputs of every vp, binding_sig_r, hints and
+        // extra_data if any
+        let framing_bytes = 1
+            + 1
+            + 4 * (action_proofs.len() + vp_infos.len())
+            + vp_infos.len()
+            + 4 * (self.inputs.len() + self.outputs.len())
+            + 32
+            + 4
+            + self.hints.len()
+            + if self.extra_data.is_empty() {
+                0
+            } else {
+                4 + self.extra_data.len()
+            };
+
+        PtxCostReport {
+            action_proof_sizes,
+            vp_proof_sizes,
+            instance_counts,
+            instance_bytes,
+            vk_bytes,
+            framing_bytes,
+            scalar_muls,
+        }
+    }
 }
 
 impl ShieldedPartialTransactionProxy {
     fn to_concrete(&self) -> Option<ShieldedPartialTransaction> {
         let actions = self.actions.clone().try_into().ok()?;
+        let action_proofs = match self.action_proofs.as_slice() {
+            [proof] => ActionProofs::Aggregated(proof.clone()),
+            proofs => ActionProofs::Separate(proofs.to_vec().try_into().ok()?),
+        };
         let inputs = self.inputs.clone().try_into().ok()?;
         let outputs = self.outputs.clone().try_into().ok()?;
         Some(ShieldedPartialTransaction {
             actions,
+            action_proofs,
             inputs,
             outputs,
             binding_sig_r: self.binding_sig_r,
             hints: self.hints.clone(),
+            extra_data: self.extra_data.clone(),
         })
     }
 }
 
 impl Executable for ShieldedPartialTransaction {
     fn execute(&self) -> Result<(), TransactionError> {
-        self.verify_proof()?;
-        self.check_nullifiers()?;
-        self.check_note_commitments()?;
-        Ok(())
+        self.verify_proof_with_cached_vks()?;
+        self.check_note_data()
     }
 
     fn get_nullifiers(&self) -> Vec<Nullifier> {
-        self.actions
-            .iter()
-            .map(|action| action.action_instance.nf)
-            .collect()
+        self.actions.iter().map(|action| action.nf).collect()
     }
 
     fn get_output_cms(&self) -> Vec<NoteCommitment> {
-        self.actions
-            .iter()
-            .map(|action| action.action_instance.cm)
-            .collect()
+        self.actions.iter().map(|action| action.cm).collect()
     }
 
     fn get_value_commitments(&self) -> Vec<ValueCommitment> {
-        self.actions
-            .iter()
-            .map(|action| action.action_instance.cv_net)
-            .collect()
+        self.actions.iter().map(|action| action.cv_net).collect()
     }
 
     fn get_anchors(&self) -> Vec<Anchor> {
-        self.actions
-            .iter()
-            .map(|action| action.action_instance.anchor)
-            .collect()
+        self.actions.iter().map(|action| action.anchor).collect()
     }
 }
 
+// The shared public inputs of the vps of the input notes and of the output notes, rebuilt from the
+// actions
+fn shared_vp_public_inputs(
+    actions: &[ActionPublicInputs; NUM_NOTE],
+) -> [[SharedPublicInputs; NUM_NOTE]; 2] {
+    let nfs = std::array::from_fn(|i| actions[i].nf.inner());
+    let cms = std::array::from_fn(|i| actions[i].cm);
+    [
+        std::array::from_fn(|i| {
+            ValidityPredicatePublicInputs::shared_prefix_of(nfs, cms, actions[i].nf.inner())
+        }),
+        std::array::from_fn(|i| {
+            ValidityPredicatePublicInputs::shared_prefix_of(nfs, cms, actions[i].cm.inner())
+        }),
+    ]
+}
+
+// The borsh encoding of the ptx starts with it, the vps skip the public inputs shared with the
+// actions. The legacy encoding has no version and starts with the ActionProofs tag, 0 or 1.
 #[cfg(feature = "borsh")]
-impl BorshSerialize for ShieldedPartialTransaction {
-    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        for action in self.actions.iter() {
-            action.serialize(writer)?;
+const SHARED_VP_INSTANCE_VERSION: u8 = 2;
+// The SHARED_VP_INSTANCE_VERSION encoding followed by the extra data. A ptx without extra data
+// keeps the previous version, so its encoding is unchanged.
+#[cfg(feature = "borsh")]
+const EXTRA_DATA_VERSION: u8 = 3;
+
+#[cfg(feature = "borsh")]
+impl ShieldedPartialTransaction {
+    /// The encoding before SHARED_VP_INSTANCE_VERSION, with all the vp public inputs, for the
+    /// readers not upgraded yet. Both encodings are deserialized. It has no room for the extra
+    /// data, returns an error if the ptx has any.
+    pub fn serialize_legacy<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.serialize_inner(writer, false)
+    }
+
+    fn serialize_inner<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        shared_vp_instance: bool,
+    ) -> std::io::Result<()> {
+        let has_extra_data = !self.extra_data.is_empty();
+        if shared_vp_instance {
+            let version = if has_extra_data {
+                EXTRA_DATA_VERSION
+            } else {
+                SHARED_VP_INSTANCE_VERSION
+            };
+            writer.write_all(&[version])?;
+        } else if has_extra_data {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the legacy encoding has no extra data",
+            ));
         }
+        self.action_proofs.serialize(writer)?;
 
-        for input in self.inputs.iter() {
-            input.serialize(writer)?;
+        for action in self.actions.iter() {
+            action.serialize(writer)?;
         }
 
-        for output in self.outputs.iter() {
-            output.serialize(writer)?;
+        let [input_prefixes, output_prefixes] = shared_vp_public_inputs(&self.actions);
+        for (notes, prefixes) in [
+            (&self.inputs, input_prefixes),
+            (&self.outputs, output_prefixes),
+        ] {
+            for (note, prefix) in notes.iter().zip(prefixes.iter()) {
+                if shared_vp_instance {
+                    note.serialize_with_shared_prefix(prefix, writer)?;
+                } else {
+                    note.serialize(writer)?;
+                }
+            }
         }
 
         writer.write_all(&self.binding_sig_r.to_repr())?;
 
         self.hints.serialize(writer)?;
 
+        if has_extra_data {
+            self.extra_data.serialize(writer)?;
+        }
+
         Ok(())
     }
 }
 
+#[cfg(feature = "borsh")]
+impl BorshSerialize for ShieldedPartialTransaction {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.serialize_inner(writer, true)
+    }
+}
+
 #[cfg(feature = "borsh")]
 impl BorshDeserialize for ShieldedPartialTransaction {
     fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
-        let actions: Vec<_> = (0..NUM_NOTE)
-            .map(|_| ActionVerifyingInfo::deserialize_reader(reader))
-            .collect::<Result<_, _>>()?;
-        let inputs: Vec<_> = (0..NUM_NOTE)
-            .map(|_| NoteVPVerifyingInfoSet::deserialize_reader(reader))
-            .collect::<Result<_, _>>()?;
-        let outputs: Vec<_> = (0..NUM_NOTE)
-            .map(|_| NoteVPVerifyingInfoSet::deserialize_reader(reader))
-            .collect::<Result<_, _>>()?;
+        use std::io::Read;
+
+        let version = u8::deserialize_reader(reader)?;
+        let has_extra_data = version == EXTRA_DATA_VERSION;
+        let shared_vp_instance = version == SHARED_VP_INSTANCE_VERSION || has_extra_data;
+        let action_proofs = if shared_vp_instance {
+            ActionProofs::deserialize_reader(reader)?
+        } else {
+            // The legacy encoding, the byte was the ActionProofs tag
+            ActionProofs::deserialize_reader(&mut [version].as_slice().chain(&mut *reader))?
+        };
+        let actions: [ActionPublicInputs; NUM_NOTE] = (0..NUM_NOTE)
+            .map(|_| ActionPublicInputs::deserialize_reader(reader))
+            .collect::<Result<Vec<_>, _>>()?
+            .try_into()
+            .unwrap();
+        let [input_prefixes, output_prefixes] = shared_vp_public_inputs(&actions);
+        let mut read_notes = |prefixes: [SharedPublicInputs; NUM_NOTE]| {
+            prefixes
+                .iter()
+                .map(|prefix| {
+                    if shared_vp_instance {
+                        NoteVPVerifyingInfoSet::deserialize_with_shared_prefix(reader, prefix)
+                    } else {
+                        NoteVPVerifyingInfoSet::deserialize_reader(reader)
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let inputs = read_notes(input_prefixes)?;
+        let outputs = read_notes(output_prefixes)?;
         let binding_sig_r_bytes = <[u8; 32]>::deserialize_reader(reader)?;
         let binding_sig_r = Option::from(pallas::Scalar::from_repr(binding_sig_r_bytes))
             .ok_or_else(|| {
@@ -350,12 +1283,53 @@ impl BorshDeserialize for ShieldedPartialTransaction {
                 )
             })?;
         let hints = Vec::<u8>::deserialize_reader(reader)?;
+        let extra_data = if has_extra_data {
+            Vec::<u8>::deserialize_reader(reader)?
+        } else {
+            vec![]
+        };
         Ok(ShieldedPartialTransaction {
-            actions: actions.try_into().unwrap(),
+            actions,
+            action_proofs,
             inputs: inputs.try_into().unwrap(),
             outputs: outputs.try_into().unwrap(),
             binding_sig_r,
             hints,
+            extra_data,
+        })
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl NoteVPVerifyingInfoSet {
+    // The derived encoding with the vps written by serialize_with_shared_prefix
+    fn serialize_with_shared_prefix<W: std::io::Write>(
+        &self,
+        shared_prefix: &SharedPublicInputs,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        self.app_vp_verifying_info
+            .serialize_with_shared_prefix(shared_prefix, writer)?;
+        (self.app_dynamic_vp_verifying_info.len() as u32).serialize(writer)?;
+        for vp_info in self.app_dynamic_vp_verifying_info.iter() {
+            vp_info.serialize_with_shared_prefix(shared_prefix, writer)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize_with_shared_prefix<R: std::io::Read>(
+        reader: &mut R,
+        shared_prefix: &SharedPublicInputs,
+    ) -> std::io::Result<Self> {
+        let app_vp_verifying_info =
+            VPVerifyingInfo::deserialize_with_shared_prefix(reader, shared_prefix)?;
+        let dynamic_vp_num = u32::deserialize_reader(reader)?;
+        let app_dynamic_vp_verifying_info = (0..dynamic_vp_num)
+            .map(|_| VPVerifyingInfo::deserialize_with_shared_prefix(reader, shared_prefix))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            app_vp_verifying_info,
+            app_dynamic_vp_verifying_info,
         })
     }
 }
@@ -376,18 +1350,30 @@ impl<'a> Decoder<'a> for ShieldedPartialTransaction {
     }
 }
 
+// The borsh encoding of ActionPublicInputs: anchor, nf, cm, cv_net, the two vp commitments,
+// cipher_commitment and the two app vks
+const ACTION_PUBLIC_INPUTS_BYTES: usize = 9 * 32;
+
+// Map a proof verification error to the precise transaction error
+fn verify_error(e: Error, failure: TransactionError) -> TransactionError {
+    match e {
+        Error::InvalidInstances | Error::InstanceTooLarge => TransactionError::PublicInputMalformed,
+        _ => failure,
+    }
+}
+
 impl ActionVerifyingInfo {
+    #[cfg(feature = "prover")]
     pub fn create<R: RngCore>(action_info: ActionInfo, mut rng: R) -> Result<Self, Error> {
         let (action_instance, circuit) = action_info.build();
-        let params = SETUP_PARAMS_MAP.get(&ACTION_CIRCUIT_PARAMS_SIZE).unwrap();
+        let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
         let action_proof = Proof::create(
             &ACTION_PROVING_KEY,
             params,
             circuit,
-            &[&action_instance.to_instance()],
+            &[&action_instance.to_vec()],
             &mut rng,
-        )
-        .unwrap();
+        )?;
         Ok(Self {
             action_proof,
             action_instance,
@@ -395,15 +1381,114 @@ impl ActionVerifyingInfo {
     }
 
     pub fn verify(&self) -> Result<(), Error> {
-        let params = SETUP_PARAMS_MAP.get(&ACTION_CIRCUIT_PARAMS_SIZE).unwrap();
-        self.action_proof.verify(
+        let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+        self.action_proof.verify_single_instance(
             &ACTION_VERIFYING_KEY,
             params,
-            &[&self.action_instance.to_instance()],
+            &self.action_instance.to_vec(),
+            ACTION_PUBLIC_INPUT_NUM,
+        )
+    }
+
+    /// Same as create for the action circuit of tree depth DEPTH. The merkle path of the action
+    /// info must have the depth.
+    #[cfg(feature = "prover")]
+    pub fn create_with_depth<const DEPTH: usize, R: RngCore>(
+        action_info: ActionInfo,
+        mut rng: R,
+    ) -> Result<Self, TransactionError> {
+        let (action_instance, circuit) = action_info.build_with_depth::<DEPTH>()?;
+        let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+        let action_proof = Proof::create(
+            &get_action_proving_key::<DEPTH>(),
+            params,
+            circuit,
+            &[&action_instance.to_vec()],
+            &mut rng,
+        )?;
+        Ok(Self {
+            action_proof,
+            action_instance,
+        })
+    }
+
+    pub fn verify_with_depth<const DEPTH: usize>(&self) -> Result<(), Error> {
+        let params = &get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+        self.action_proof.verify_single_instance(
+            &get_action_verifying_key::<DEPTH>(),
+            params,
+            &self.action_instance.to_vec(),
+            ACTION_PUBLIC_INPUT_NUM,
+        )
+    }
+
+    pub fn proof_size(&self) -> usize {
+        self.action_proof.size()
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.action_instance.to_vec().len()
+    }
+
+    /// Proves the NUM_NOTE actions in one AggregatedActionCircuit proof.
+    #[cfg(feature = "prover")]
+    pub fn create_aggregated<R: RngCore>(
+        action_infos: Vec<ActionInfo>,
+        mut rng: R,
+    ) -> Result<([ActionPublicInputs; NUM_NOTE], ActionProofs), Error> {
+        let (action_instances, circuits): (Vec<_>, Vec<_>) = action_infos
+            .iter()
+            .map(|action_info| action_info.build())
+            .unzip();
+        let circuit = AggregatedActionCircuit {
+            actions: circuits.try_into().unwrap(),
+        };
+        let instance: Vec<pallas::Base> = action_instances
+            .iter()
+            .flat_map(|action_instance| action_instance.to_vec())
+            .collect();
+        let params = &get_params(AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE);
+        let action_proof = Proof::create(
+            &AGGREGATED_ACTION_PROVING_KEY,
+            params,
+            circuit,
+            &[&instance],
+            &mut rng,
+        )?;
+        Ok((
+            action_instances.try_into().unwrap(),
+            ActionProofs::Aggregated(action_proof),
+        ))
+    }
+
+    // The public inputs and the separate proofs of the actions of a ptx
+    #[cfg(feature = "prover")]
+    fn split(actions: Vec<ActionVerifyingInfo>) -> ([ActionPublicInputs; NUM_NOTE], ActionProofs) {
+        let (proofs, action_instances): (Vec<_>, Vec<_>) = actions
+            .into_iter()
+            .map(|action| (action.action_proof, action.action_instance))
+            .unzip();
+        (
+            action_instances.try_into().unwrap(),
+            ActionProofs::Separate(proofs.try_into().unwrap()),
         )
     }
 }
 
+impl ActionProofs {
+    // The separate proofs in the action order, or the aggregated one
+    pub fn proofs(&self) -> &[Proof] {
+        match self {
+            ActionProofs::Separate(proofs) => proofs,
+            ActionProofs::Aggregated(proof) => std::slice::from_ref(proof),
+        }
+    }
+
+    pub fn is_aggregated(&self) -> bool {
+        matches!(self, ActionProofs::Aggregated(_))
+    }
+}
+
 impl NoteVPVerifyingInfoSet {
     pub fn new(
         app_vp_verifying_info: VPVerifyingInfo,
@@ -417,23 +1502,65 @@ impl NoteVPVerifyingInfoSet {
         }
     }
 
+    #[cfg(feature = "prover")]
     pub fn build(
         application_vp: Box<ValidityPredicate>,
         dynamic_vps: Vec<Box<ValidityPredicate>>,
     ) -> Self {
-        assert!(dynamic_vps.len() <= MAX_DYNAMIC_VP_NUM);
+        Self::prove_with_hook(application_vp, dynamic_vps, None, &mut ()).unwrap()
+    }
 
-        let app_vp_verifying_info = application_vp.get_verifying_info();
+    // Same as build, with the public input padding of the vps derived from the seed, see
+    // `beacon::vp_padding_rng`
+    #[cfg(feature = "prover")]
+    pub fn build_with_padding_seed(
+        application_vp: Box<ValidityPredicate>,
+        dynamic_vps: Vec<Box<ValidityPredicate>>,
+        padding_seed: &RandomSeed,
+        owned_note_pub_id: pallas::Base,
+    ) -> Self {
+        Self::prove_with_hook(
+            application_vp,
+            dynamic_vps,
+            Some((padding_seed, owned_note_pub_id)),
+            &mut (),
+        )
+        .unwrap()
+    }
 
-        let app_dynamic_vp_verifying_info = dynamic_vps
-            .into_iter()
-            .map(|verifying_info| verifying_info.get_verifying_info())
-            .collect();
+    // Creates the vp proofs one by one, the hook is called around each of them. The padding
+    // seed comes with the owned note pub id.
+    #[cfg(feature = "prover")]
+    pub(crate) fn prove_with_hook(
+        application_vp: Box<ValidityPredicate>,
+        dynamic_vps: Vec<Box<ValidityPredicate>>,
+        padding_seed: Option<(&RandomSeed, pallas::Base)>,
+        hook: &mut dyn ProvingHook,
+    ) -> Result<Self, TransactionError> {
+        assert!(dynamic_vps.len() <= MAX_DYNAMIC_VP_NUM);
 
-        Self {
-            app_vp_verifying_info,
-            app_dynamic_vp_verifying_info,
+        let mut vp_infos = vec![];
+        for (vp_index, vp) in std::iter::once(application_vp)
+            .chain(dynamic_vps)
+            .enumerate()
+        {
+            hook.before_proof()?;
+            let vp_info = match padding_seed {
+                Some((seed, owned_note_pub_id)) => {
+                    let mut padding_rng = vp_padding_rng(seed, &owned_note_pub_id, vp_index);
+                    vp.get_verifying_info_with_padding_rng(&mut padding_rng)
+                }
+                None => vp.get_verifying_info(),
+            };
+            hook.after_proof(ProofStage::VPProof, vp.circuit_name());
+            vp_infos.push(vp_info);
         }
+        let mut vp_infos = vp_infos.into_iter();
+
+        Ok(Self {
+            app_vp_verifying_info: vp_infos.next().unwrap(),
+            app_dynamic_vp_verifying_info: vp_infos.collect(),
+        })
     }
 
     pub fn verify(&self) -> Result<(), Error> {
@@ -450,6 +1577,106 @@ impl NoteVPVerifyingInfoSet {
         Ok(())
     }
 
+    // Check the application vp proof is created under the app_vk of the note
+    pub fn check_app_vk(&self, app_vk: pallas::Base) -> Result<(), TransactionError> {
+        if self.get_app_vk() != app_vk {
+            return Err(TransactionError::InconsistentApplicationVK);
+        }
+        Ok(())
+    }
+
+    // Check one of the dynamic vp commitments of the application vp opens to vp_vk and a dynamic
+    // vp proof under vp_vk is attached
+    pub fn check_dynamic_vp(
+        &self,
+        vp_vk: pallas::Base,
+        vp_cm_r: pallas::Base,
+    ) -> Result<(), TransactionError> {
+        let committed = self
+            .app_vp_verifying_info
+            .public_inputs
+            .dynamic_vp_commitments()
+            .iter()
+            .any(|vp_cm| vp_cm.open(&vp_vk, &vp_cm_r));
+        if !committed {
+            return Err(TransactionError::InconsistentDynamicVPCommitment);
+        }
+
+        if !self
+            .app_dynamic_vp_verifying_info
+            .iter()
+            .any(|vp_info| ValidityPredicateVerifyingKey::compress(&vp_info.vk) == vp_vk)
+        {
+            return Err(TransactionError::MissingDynamicVP);
+        }
+        Ok(())
+    }
+
+    // The vk of the encryption vp the application vp binds to the owned note, zero if none
+    fn declared_encryption_vp_vk(&self) -> pallas::Base {
+        self.app_vp_verifying_info.public_inputs.encryption_vp_vk()
+    }
+
+    // The index in get_vp_verifying_infos of the vp allowed to publish the note ciphertext: the
+    // application vp if it binds no encryption vp, otherwise the vp under the bound vk
+    fn encryption_vp_index(&self) -> Option<usize> {
+        let encryption_vp_vk = self.declared_encryption_vp_vk();
+        if encryption_vp_vk == pallas::Base::zero() {
+            return Some(0);
+        }
+        self.get_vp_verifying_infos()
+            .position(|vp| ValidityPredicateVerifyingKey::compress(&vp.vk) == encryption_vp_vk)
+    }
+
+    fn encryption_vp(&self) -> Option<&VPVerifyingInfo> {
+        self.encryption_vp_index()
+            .and_then(|vp_index| self.get_vp_verifying_infos().nth(vp_index))
+    }
+
+    // Verify the vp proofs and the binding of the application vp to the note app_vk
+    pub fn verify_all(&self, app_vk: pallas::Base) -> Result<(), TransactionError> {
+        self.verify_with_cached_app_vk(app_vk, |_, e| e.into())
+    }
+
+    // The application vp proof is verified under the cached vk of app_vk, which binds it to the
+    // app_vk. The attached vk is only compressed on a cache miss, or to report a failure.
+    // on_proof_error maps the error of the vp at the index, the application vp first.
+    pub(crate) fn verify_with_cached_app_vk(
+        &self,
+        app_vk: pallas::Base,
+        on_proof_error: impl Fn(usize, Error) -> TransactionError,
+    ) -> Result<(), TransactionError> {
+        let vk = match VerifierKeyCache::get(&app_vk) {
+            Some(vk) => vk,
+            None => {
+                self.check_app_vk(app_vk)?;
+                VerifierKeyCache::get_or_insert(app_vk, || self.app_vp_verifying_info.vk.clone())
+            }
+        };
+        if let Err(e) = self.app_vp_verifying_info.verify_with_vk(&vk) {
+            // The proof may be valid under another vp
+            self.check_app_vk(app_vk)?;
+            return Err(on_proof_error(0, e));
+        }
+
+        for (i, vp_info) in self.app_dynamic_vp_verifying_info.iter().enumerate() {
+            vp_info.verify().map_err(|e| on_proof_error(i + 1, e))?;
+        }
+
+        Ok(())
+    }
+
+    // The application vp comes first, then the dynamic vps
+    pub fn get_vp_verifying_infos(&self) -> impl Iterator<Item = &VPVerifyingInfo> {
+        std::iter::once(&self.app_vp_verifying_info)
+            .chain(self.app_dynamic_vp_verifying_info.iter())
+    }
+
+    // Get the compressed vk of the application vp
+    pub fn get_app_vk(&self) -> pallas::Base {
+        ValidityPredicateVerifyingKey::compress(&self.app_vp_verifying_info.vk)
+    }
+
     pub fn get_nullifiers(&self) -> Vec<[pallas::Base; NUM_NOTE]> {
         let mut nfs = vec![self.app_vp_verifying_info.get_nullifiers()];
         self.app_dynamic_vp_verifying_info
@@ -467,17 +1694,71 @@ impl NoteVPVerifyingInfoSet {
     }
 }
 
+// The total value of each note type must not exceed MAX_TOTAL_VALUE
+pub(crate) fn check_value_totals<'a>(
+    notes: impl Iterator<Item = &'a Note>,
+) -> Result<(), TransactionError> {
+    let mut totals: Vec<(NoteType, u128)> = vec![];
+    for note in notes {
+        match totals
+            .iter_mut()
+            .find(|(note_type, _)| *note_type == note.note_type)
+        {
+            Some((_, total)) => *total += note.value as u128,
+            None => totals.push((note.note_type, note.value as u128)),
+        }
+    }
+    if totals.iter().any(|(_, total)| *total > MAX_TOTAL_VALUE) {
+        return Err(TransactionError::ValueOverflow);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_value_totals() {
+    use crate::note::tests::random_input_note;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let note = Note {
+        value: u64::MAX,
+        ..random_input_note(&mut rng)
+    };
+    let other_note = Note {
+        value: u64::MAX,
+        ..random_input_note(&mut rng)
+    };
+
+    // 64 max values fit in 70 bits
+    let notes = vec![note; 64];
+    assert!(check_value_totals(notes.iter()).is_ok());
+
+    // 65 don't
+    let notes = vec![note; 65];
+    assert_eq!(
+        check_value_totals(notes.iter()).unwrap_err().to_string(),
+        TransactionError::ValueOverflow.to_string()
+    );
+
+    // The totals are per note type
+    let notes: Vec<Note> = [note, other_note].repeat(64);
+    assert!(check_value_totals(notes.iter()).is_ok());
+}
+
 #[cfg(test)]
 pub mod testing {
     use crate::{
         circuit::vp_circuit::{ValidityPredicate, ValidityPredicateVerifyingInfo},
         circuit::vp_examples::TrivialValidityPredicateCircuit,
-        constant::TAIGA_COMMITMENT_TREE_DEPTH,
+        constant::{APP_DATA_DYNAMIC_DOMAIN, NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH},
         merkle_tree::MerklePath,
-        note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo, RandomSeed},
+        note::{
+            InputNoteProvingInfo, Note, NoteCommitment, NoteType, OutputNoteProvingInfo, RandomSeed,
+        },
         nullifier::{Nullifier, NullifierKeyContainer},
-        shielded_ptx::ShieldedPartialTransaction,
-        utils::poseidon_hash,
+        proof::Proof,
+        shielded_ptx::{ActionProofs, ShieldedPartialTransaction},
+        utils::poseidon_hash_n,
     };
     use halo2_proofs::arithmetic::Field;
     use pasta_curves::pallas;
@@ -499,7 +1780,11 @@ pub mod testing {
             // Encode the app_dynamic_vp_vk into app_data_dynamic
             // The encoding method is flexible and defined in the application vp.
             // Use poseidon hash to encode the two dynamic VPs here
-            let app_data_dynamic = poseidon_hash(app_dynamic_vp_vk[0], app_dynamic_vp_vk[1]);
+            let app_data_dynamic = poseidon_hash_n([
+                APP_DATA_DYNAMIC_DOMAIN,
+                app_dynamic_vp_vk[0],
+                app_dynamic_vp_vk[1],
+            ]);
             let rho = Nullifier::from(pallas::Base::random(&mut rng));
             let value = 5000u64;
             let nk = NullifierKeyContainer::random_key(&mut rng);
@@ -580,11 +1865,11 @@ pub mod testing {
         // Generate note info
         let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
         // Create vp circuit and fill the note info
-        let mut trivial_vp_circuit = TrivialValidityPredicateCircuit {
-            owned_note_pub_id: input_note_1.get_nf().unwrap().inner(),
-            input_notes: [input_note_1, input_note_2],
-            output_notes: [output_note_1, output_note_2],
-        };
+        let mut trivial_vp_circuit = TrivialValidityPredicateCircuit::new(
+            input_note_1.get_nf().unwrap().inner(),
+            [input_note_1, input_note_2],
+            [output_note_1, output_note_2],
+        );
         let input_application_vp_1 = Box::new(trivial_vp_circuit.clone());
         let trivial_app_logic_1: Box<ValidityPredicate> = Box::new(trivial_vp_circuit.clone());
         let trivial_app_logic_2 = Box::new(trivial_vp_circuit.clone());
@@ -619,11 +1904,1136 @@ pub mod testing {
             OutputNoteProvingInfo::new(output_note_2, output_application_vp_2, dynamic_vps);
 
         // Create shielded partial tx
-        ShieldedPartialTransaction::build(
+        ShieldedPartialTransaction::build_strict(
             [input_note_proving_info_1, input_note_proving_info_2],
             [output_note_proving_info_1, output_note_proving_info_2],
             vec![],
             &mut rng,
         )
+        .unwrap()
+    }
+
+    // Replace the nullifier and the output commitment of an action, the proofs are not updated
+    pub fn set_action_public_data(
+        ptx: &mut ShieldedPartialTransaction,
+        action_index: usize,
+        nf: Nullifier,
+        cm: NoteCommitment,
+    ) {
+        ptx.actions[action_index].nf = nf;
+        ptx.actions[action_index].cm = cm;
+    }
+
+    // Replace a public input of a vp, the proof is not updated. The input notes come first in the
+    // note index, the vp index is 0 for the application vp and i + 1 for the i-th dynamic vp.
+    pub fn set_vp_public_input(
+        ptx: &mut ShieldedPartialTransaction,
+        note_index: usize,
+        vp_index: usize,
+        public_input_index: usize,
+        value: pallas::Base,
+    ) {
+        let vp_infos = if note_index < NUM_NOTE {
+            &mut ptx.inputs[note_index]
+        } else {
+            &mut ptx.outputs[note_index - NUM_NOTE]
+        };
+        let vp_info = match vp_index {
+            0 => &mut vp_infos.app_vp_verifying_info,
+            i => &mut vp_infos.app_dynamic_vp_verifying_info[i - 1],
+        };
+        let mut public_inputs = vp_info.public_inputs.to_vec();
+        public_inputs[public_input_index] = value;
+        vp_info.public_inputs = public_inputs.into();
+    }
+
+    // Flips a byte of the action proof, the public data is untouched. The aggregated proof is
+    // corrupted whatever the action index.
+    pub fn corrupt_action_proof(ptx: &mut ShieldedPartialTransaction, action_index: usize) {
+        let proof = match &mut ptx.action_proofs {
+            ActionProofs::Separate(proofs) => &mut proofs[action_index],
+            ActionProofs::Aggregated(proof) => proof,
+        };
+        let mut bytes = proof.inner();
+        bytes[bytes.len() / 2] ^= 1;
+        *proof = Proof::new(bytes);
+    }
+
+    // The proofs are valid but the input value is larger than the output value
+    pub fn create_unbalanced_shielded_ptx() -> ShieldedPartialTransaction {
+        let compressed_trivial_vp_vk = TrivialValidityPredicateCircuit::default()
+            .get_vp_vk()
+            .get_compressed();
+        let note_type = NoteType::new(compressed_trivial_vp_vk, pallas::Base::zero());
+        create_trivial_swap_ptx((note_type, 100), (note_type, 90))
+    }
+
+    // Spends the sold note and creates the bought note, under trivial vps
+    pub fn create_trivial_swap_ptx(
+        sell: (NoteType, u64),
+        buy: (NoteType, u64),
+    ) -> ShieldedPartialTransaction {
+        create_trivial_swap_ptx_with_padding_seed(sell, buy, &RandomSeed::random(OsRng))
+    }
+
+    // Same as create_trivial_swap_ptx, with the padding notes derived from the seed
+    pub fn create_trivial_swap_ptx_with_padding_seed(
+        sell: (NoteType, u64),
+        buy: (NoteType, u64),
+        padding_seed: &RandomSeed,
+    ) -> ShieldedPartialTransaction {
+        let (input_info, output_info) = create_trivial_swap_proving_info(sell, buy, padding_seed);
+        ShieldedPartialTransaction::build(input_info, output_info, vec![], OsRng).unwrap()
+    }
+
+    // The proving infos of create_trivial_swap_ptx_with_padding_seed
+    pub fn create_trivial_swap_proving_info(
+        sell: (NoteType, u64),
+        buy: (NoteType, u64),
+        padding_seed: &RandomSeed,
+    ) -> (
+        [InputNoteProvingInfo; NUM_NOTE],
+        [OutputNoteProvingInfo; NUM_NOTE],
+    ) {
+        let mut rng = OsRng;
+        let input_note = {
+            let mut note = Note::random_padding_input_note(&mut rng);
+            note.note_type = sell.0;
+            note.value = sell.1;
+            note
+        };
+        let output_note = {
+            let mut note = Note::random_padding_output_note(&mut rng, input_note.get_nf().unwrap());
+            note.note_type = buy.0;
+            note.value = buy.1;
+            note
+        };
+        let padding_input_note = Note::padding_input_from_seed(padding_seed, 0);
+        let padding_output_note =
+            Note::padding_output_from_seed(padding_seed, 0, padding_input_note.get_nf().unwrap());
+
+        let input_notes = [input_note, padding_input_note];
+        let output_notes = [output_note, padding_output_note];
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let anchor = input_note.calculate_root(&merkle_path);
+        let input_info = input_notes.map(|note| {
+            InputNoteProvingInfo::create_padding_note_proving_info(
+                note,
+                merkle_path.clone(),
+                anchor,
+                input_notes,
+                output_notes,
+            )
+        });
+        let output_info = output_notes.map(|note| {
+            OutputNoteProvingInfo::create_padding_note_proving_info(note, input_notes, output_notes)
+        });
+        (input_info, output_info)
+    }
+
+    #[cfg(feature = "examples")]
+    pub fn create_token_ptx() -> ShieldedPartialTransaction {
+        create_token_ptx_with_output().0
+    }
+
+    // Also returns the output token note and its authorization
+    #[cfg(feature = "examples")]
+    pub fn create_token_ptx_with_output() -> (
+        ShieldedPartialTransaction,
+        crate::circuit::vp_examples::token::TokenNote,
+        crate::circuit::vp_examples::token::TokenAuthorization,
+    ) {
+        use crate::circuit::vp_examples::{
+            signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+            token::{Token, TokenAuthorization, TokenName},
+        };
+        use crate::keys::SigningKey;
+        use crate::merkle_tree::Anchor;
+
+        let mut rng = OsRng;
+        let token = Token::new(TokenName::new("btc").unwrap(), 5);
+        let auth_sk = SigningKey::random(&mut rng);
+        let auth = TokenAuthorization::from_sk_vk(auth_sk.inner(), &COMPRESSED_TOKEN_AUTH_VK);
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+
+        let rho = Nullifier::from(pallas::Base::random(&mut rng));
+        let input_note = token.create_random_token_note(&mut rng, rho, nk, &auth);
+        let output_note = token.create_random_token_note(
+            &mut rng,
+            input_note.get_nf().unwrap(),
+            nk.to_commitment(),
+            &auth,
+        );
+        let padding_input_note = Note::random_padding_input_note(&mut rng);
+        let padding_output_note =
+            Note::random_padding_output_note(&mut rng, padding_input_note.get_nf().unwrap());
+
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let padding_anchor = Anchor::from(pallas::Base::random(&mut rng));
+        let ptx = build_token_ptx(
+            &input_note,
+            &output_note,
+            [padding_input_note, padding_output_note],
+            (auth, &auth_sk),
+            merkle_path,
+            padding_anchor,
+        );
+        (ptx, output_note, auth)
+    }
+
+    // The ptx spending the token input note to the output note, the padding input note is under
+    // padding_anchor. The token auth vp signs the action anchors.
+    #[cfg(feature = "examples")]
+    pub fn build_token_ptx(
+        input_note: &crate::circuit::vp_examples::token::TokenNote,
+        output_note: &crate::circuit::vp_examples::token::TokenNote,
+        [padding_input_note, padding_output_note]: [Note; NUM_NOTE],
+        (auth, auth_sk): (
+            crate::circuit::vp_examples::token::TokenAuthorization,
+            &crate::keys::SigningKey,
+        ),
+        merkle_path: MerklePath,
+        padding_anchor: crate::merkle_tree::Anchor,
+    ) -> ShieldedPartialTransaction {
+        let mut rng = OsRng;
+        let input_notes = [*input_note.note(), padding_input_note];
+        let output_notes = [*output_note.note(), padding_output_note];
+
+        let input_note_proving_info = input_note.generate_input_token_note_proving_info(
+            &mut rng,
+            auth,
+            auth_sk,
+            merkle_path.clone(),
+            [input_note.calculate_root(&merkle_path), padding_anchor],
+            input_notes,
+            output_notes,
+        );
+        let output_note_proving_info = output_note.generate_output_token_note_proving_info(
+            &mut rng,
+            auth,
+            input_notes,
+            output_notes,
+        );
+        let padding_input_note_proving_info =
+            InputNoteProvingInfo::create_padding_note_proving_info(
+                padding_input_note,
+                merkle_path,
+                padding_anchor,
+                input_notes,
+                output_notes,
+            );
+        let padding_output_note_proving_info =
+            OutputNoteProvingInfo::create_padding_note_proving_info(
+                padding_output_note,
+                input_notes,
+                output_notes,
+            );
+
+        ShieldedPartialTransaction::build(
+            [input_note_proving_info, padding_input_note_proving_info],
+            [output_note_proving_info, padding_output_note_proving_info],
+            vec![],
+            &mut rng,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ptx_without_encrypted_payload() {
+        use crate::constant::NOTE_CIPHERTEXT_COMMITMENT_SENTINEL;
+        use crate::executable::Executable;
+
+        let ptx = create_shielded_ptx();
+        assert!(ptx
+            .actions
+            .iter()
+            .all(|action| action.cipher_commitment == NOTE_CIPHERTEXT_COMMITMENT_SENTINEL));
+        ptx.execute().unwrap();
+    }
+
+    #[test]
+    fn test_ptx_deterministic_padding() {
+        use crate::executable::Executable;
+
+        let compressed_trivial_vp_vk = TrivialValidityPredicateCircuit::default()
+            .get_vp_vk()
+            .get_compressed();
+        let note_type = NoteType::new(compressed_trivial_vp_vk, pallas::Base::zero());
+        let seed = RandomSeed::random(OsRng);
+        let ptx =
+            create_trivial_swap_ptx_with_padding_seed((note_type, 100), (note_type, 100), &seed);
+        ptx.execute().unwrap();
+
+        // The padding notes are the second note pair, rebuilding with the seed gives them back
+        let padding_input_note = Note::padding_input_from_seed(&seed, 0);
+        let padding_nf = padding_input_note.get_nf().unwrap();
+        let padding_output_note = Note::padding_output_from_seed(&seed, 0, padding_nf);
+        assert_eq!(ptx.get_nullifiers()[1], padding_nf);
+        assert_eq!(ptx.get_output_cms()[1], padding_output_note.commitment());
+
+        let other =
+            create_trivial_swap_ptx_with_padding_seed((note_type, 100), (note_type, 100), &seed);
+        assert_eq!(other.get_nullifiers()[1], ptx.get_nullifiers()[1]);
+        assert_eq!(other.get_output_cms()[1], ptx.get_output_cms()[1]);
+    }
+
+    #[test]
+    fn test_ptx_aggregated_action_proof() {
+        use crate::error::TransactionError;
+        use crate::executable::Executable;
+        use crate::merkle_tree::Anchor;
+        use crate::transaction::{
+            ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle,
+        };
+
+        let compressed_trivial_vp_vk = TrivialValidityPredicateCircuit::default()
+            .get_vp_vk()
+            .get_compressed();
+        let note_type = NoteType::new(compressed_trivial_vp_vk, pallas::Base::zero());
+        let (input_info, output_info) = create_trivial_swap_proving_info(
+            (note_type, 100),
+            (note_type, 100),
+            &RandomSeed::random(OsRng),
+        );
+        let separate_ptx = ShieldedPartialTransaction::build(
+            input_info.clone(),
+            output_info.clone(),
+            vec![],
+            OsRng,
+        )
+        .unwrap();
+        let ptx =
+            ShieldedPartialTransaction::build_aggregated(input_info, output_info, vec![], OsRng)
+                .unwrap();
+        assert!(ptx.get_action_proofs().is_aggregated());
+        assert!(!separate_ptx.get_action_proofs().is_aggregated());
+        ptx.execute().unwrap();
+        assert_eq!(ptx.get_nullifiers(), separate_ptx.get_nullifiers());
+        assert_eq!(ptx.get_output_cms(), separate_ptx.get_output_cms());
+
+        // One proof of about half the size of the two separate ones
+        let report = ptx.cost_report();
+        let separate_report = separate_ptx.cost_report();
+        assert_eq!(report.action_proof_sizes.len(), 1);
+        assert_eq!(separate_report.action_proof_sizes.len(), NUM_NOTE);
+        let aggregated_size: usize = report.action_proof_sizes.iter().sum();
+        let separate_size: usize = separate_report.action_proof_sizes.iter().sum();
+        assert!(aggregated_size * 3 < separate_size * 2);
+        assert!(report.size() < separate_report.size());
+
+        // The layout goes through the serialization
+        #[cfg(feature = "borsh")]
+        {
+            use borsh::BorshDeserialize;
+
+            let bytes = borsh::to_vec(&ptx).unwrap();
+            assert_eq!(bytes.len(), report.size());
+            let decoded = ShieldedPartialTransaction::try_from_slice(&bytes).unwrap();
+            assert!(decoded.get_action_proofs().is_aggregated());
+            assert_eq!(decoded.content_hash(), ptx.content_hash());
+            decoded.verify_proof().unwrap();
+            let separate_decoded =
+                ShieldedPartialTransaction::try_from_slice(&borsh::to_vec(&separate_ptx).unwrap())
+                    .unwrap();
+            assert!(!separate_decoded.get_action_proofs().is_aggregated());
+        }
+        assert_ne!(ptx.content_hash(), separate_ptx.content_hash());
+
+        // The public data of the second action is bound by the aggregated proof
+        let mut tampered_ptx = ptx.clone();
+        tampered_ptx.actions[1].anchor = Anchor::from(pallas::Base::random(OsRng));
+        assert!(matches!(
+            tampered_ptx.verify_proof(),
+            Err(TransactionError::ActionProofVerificationFailure {
+                ptx_index: 0,
+                action_index: 0
+            })
+        ));
+        let mut tampered_ptx = ptx.clone();
+        corrupt_action_proof(&mut tampered_ptx, 1);
+        assert!(tampered_ptx.execute().is_err());
+
+        // The layouts can be mixed in a transaction
+        let bundle = ShieldedPartialTxBundle::new(vec![ptx, separate_ptx]);
+        let tx = Transaction::build(OsRng, bundle, TransparentPartialTxBundle::default()).unwrap();
+        tx.execute().unwrap();
+    }
+
+    #[test]
+    fn test_ptx_dry_run() {
+        use crate::error::TransactionError;
+        use crate::executable::Executable;
+
+        let compressed_trivial_vp_vk = TrivialValidityPredicateCircuit::default()
+            .get_vp_vk()
+            .get_compressed();
+        let note_type = NoteType::new(compressed_trivial_vp_vk, pallas::Base::zero());
+        let (input_info, output_info) = create_trivial_swap_proving_info(
+            (note_type, 100),
+            (note_type, 90),
+            &RandomSeed::random(OsRng),
+        );
+
+        // The plan has the public data of the built ptx
+        let plan = ShieldedPartialTransaction::dry_run(&input_info, &output_info, true).unwrap();
+        let ptx = ShieldedPartialTransaction::build_strict(
+            input_info.clone(),
+            output_info.clone(),
+            vec![],
+            OsRng,
+        )
+        .unwrap();
+        assert_eq!(plan.nullifiers(), ptx.get_nullifiers());
+        assert_eq!(plan.output_cms(), ptx.get_output_cms());
+        assert_eq!(plan.anchors(), ptx.get_anchors());
+        assert!(!plan.is_balanced());
+        assert_eq!(plan.balances.len(), 1);
+        assert_eq!(plan.balances[0].input_value, 100);
+        assert_eq!(plan.balances[0].output_value, 90);
+
+        // The rho of the output note is not the nf of the input note
+        let mut bad_output_info = output_info.clone();
+        bad_output_info[0].note.rho = Nullifier::random(OsRng);
+        assert!(matches!(
+            ShieldedPartialTransaction::dry_run(&input_info, &bad_output_info, false),
+            Err(TransactionError::InconsistentOutputNoteRho)
+        ));
+
+        // The note type is not derived from the application vp
+        let mut bad_input_info = input_info;
+        bad_input_info[0].note.note_type =
+            NoteType::new(pallas::Base::random(OsRng), pallas::Base::zero());
+        assert!(matches!(
+            ShieldedPartialTransaction::dry_run(&bad_input_info, &output_info, true),
+            Err(TransactionError::InconsistentValueBase)
+        ));
+    }
+
+    #[test]
+    fn test_ptx_app_vk_binding() {
+        use crate::error::TransactionError;
+        use crate::executable::Executable;
+
+        let mut rng = OsRng;
+        // The application vps match the notes
+        let ptx = create_shielded_ptx();
+        ptx.check_app_vks().unwrap();
+        ptx.inputs[0]
+            .verify_all(ptx.actions[0].input_app_vk)
+            .unwrap();
+
+        // The input note belongs to another application, but the trivial vp is attached
+        let input_note = {
+            let mut note = Note::random_padding_input_note(&mut rng);
+            note.note_type = NoteType::new(pallas::Base::random(&mut rng), pallas::Base::zero());
+            note
+        };
+        let output_note = Note::random_padding_output_note(&mut rng, input_note.get_nf().unwrap());
+        let padding_input_note = Note::random_padding_input_note(&mut rng);
+        let padding_output_note =
+            Note::random_padding_output_note(&mut rng, padding_input_note.get_nf().unwrap());
+
+        let input_notes = [input_note, padding_input_note];
+        let output_notes = [output_note, padding_output_note];
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let anchor = input_note.calculate_root(&merkle_path);
+        let input_info = input_notes.map(|note| {
+            InputNoteProvingInfo::create_padding_note_proving_info(
+                note,
+                merkle_path.clone(),
+                anchor,
+                input_notes,
+                output_notes,
+            )
+        });
+        let output_info = output_notes.map(|note| {
+            OutputNoteProvingInfo::create_padding_note_proving_info(note, input_notes, output_notes)
+        });
+        let ptx =
+            ShieldedPartialTransaction::build(input_info, output_info, vec![], &mut rng).unwrap();
+
+        // The proofs are valid, but the application vp is not the one of the note
+        ptx.verify_proof().unwrap();
+        assert!(matches!(
+            ptx.execute(),
+            Err(TransactionError::InconsistentApplicationVK)
+        ));
+        assert!(matches!(
+            ptx.inputs[0].verify_all(input_note.get_app_vk()),
+            Err(TransactionError::InconsistentApplicationVK)
+        ));
+    }
+
+    #[test]
+    fn test_ptx_build_missing_nk() {
+        use crate::constant::NUM_NOTE;
+        use crate::error::{NoteError, TransactionError};
+
+        let mut rng = OsRng;
+        let padding_notes = [(); NUM_NOTE].map(|_| Note::random_padding_input_note(&mut rng));
+        // Only the nk commitments of the input notes are known
+        let input_notes = padding_notes.map(|note| Note {
+            nk_container: note.nk_container.to_commitment(),
+            ..note
+        });
+        assert_eq!(input_notes[0].get_nf(), Err(NoteError::MissingNullifierKey));
+        let output_notes = padding_notes
+            .map(|note| Note::random_padding_output_note(&mut rng, note.get_nf().unwrap()));
+
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let input_info = input_notes.map(|note| {
+            InputNoteProvingInfo::new(
+                note,
+                merkle_path.clone(),
+                None,
+                Box::new(TrivialValidityPredicateCircuit::default()),
+                vec![],
+            )
+        });
+        let output_info = output_notes.map(|note| {
+            OutputNoteProvingInfo::new(
+                note,
+                Box::new(TrivialValidityPredicateCircuit::default()),
+                vec![],
+            )
+        });
+        assert!(matches!(
+            ShieldedPartialTransaction::build(input_info, output_info, vec![], &mut rng),
+            Err(TransactionError::Note(NoteError::MissingNullifierKey))
+        ));
+    }
+
+    #[test]
+    fn test_ptx_build_wrong_position() {
+        use crate::constant::NUM_NOTE;
+        use crate::error::TransactionError;
+        use crate::state::CheckpointedState;
+
+        let mut rng = OsRng;
+        let input_notes = [(); NUM_NOTE].map(|_| Note {
+            is_merkle_checked: true,
+            ..Note::random_padding_input_note(&mut rng)
+        });
+        let output_notes = input_notes
+            .map(|note| Note::random_padding_output_note(&mut rng, note.get_nf().unwrap()));
+
+        // The input notes are at the positions 3 and 4 of the tree
+        let mut state = CheckpointedState::default();
+        for _ in 0..3 {
+            state
+                .append(NoteCommitment::from(pallas::Base::random(&mut rng)))
+                .unwrap();
+        }
+        let positions = input_notes.map(|note| state.append_marked(note.commitment()).unwrap());
+        assert_eq!(positions, [3, 4]);
+        let anchor = state.root();
+        let input_info = |paths: [u64; NUM_NOTE]| {
+            [0, 1].map(|i| {
+                InputNoteProvingInfo::new(
+                    input_notes[i],
+                    state.witness(paths[i]).unwrap(),
+                    Some(anchor),
+                    Box::new(TrivialValidityPredicateCircuit::default()),
+                    vec![],
+                )
+            })
+        };
+        let output_info = || {
+            output_notes.map(|note| {
+                OutputNoteProvingInfo::new(
+                    note,
+                    Box::new(TrivialValidityPredicateCircuit::default()),
+                    vec![],
+                )
+            })
+        };
+
+        let correct_info = input_info(positions);
+        for (info, position) in correct_info.iter().zip(positions) {
+            assert_eq!(info.position(), position);
+            assert!(info.check_anchor().is_ok());
+        }
+
+        // The paths of the notes are swapped, it fails before any proof is created
+        let swapped_info = input_info([4, 3]);
+        assert_eq!(swapped_info[0].position(), 4);
+        assert!(matches!(
+            swapped_info[0].check_anchor(),
+            Err(TransactionError::InconsistentAnchor)
+        ));
+        assert!(matches!(
+            ShieldedPartialTransaction::dry_run(&swapped_info, &output_info(), false),
+            Err(TransactionError::InconsistentAnchor)
+        ));
+        assert!(matches!(
+            ShieldedPartialTransaction::build(swapped_info, output_info(), vec![], &mut rng),
+            Err(TransactionError::InconsistentAnchor)
+        ));
+    }
+
+    #[test]
+    fn test_ptx_build_strict_value_base() {
+        use crate::error::TransactionError;
+
+        let mut rng = OsRng;
+        let compressed_trivial_vp_vk = TrivialValidityPredicateCircuit::default()
+            .get_vp_vk()
+            .get_compressed();
+        let app_data_static = pallas::Base::from(2u64);
+
+        // The app_vk and app_data_static are swapped when creating the input note
+        let swapped_note_type = NoteType::new(app_data_static, compressed_trivial_vp_vk);
+        let input_note = {
+            let mut note = Note::random_padding_input_note(&mut rng);
+            note.note_type = swapped_note_type;
+            note.value = 100;
+            note
+        };
+        let output_note = {
+            let mut note = Note::random_padding_output_note(&mut rng, input_note.get_nf().unwrap());
+            note.note_type = NoteType::new(compressed_trivial_vp_vk, app_data_static);
+            note.value = 100;
+            note
+        };
+        let padding_input_note = Note::random_padding_input_note(&mut rng);
+        let padding_output_note =
+            Note::random_padding_output_note(&mut rng, padding_input_note.get_nf().unwrap());
+        assert_ne!(input_note.value_base(), output_note.value_base());
+
+        let input_notes = [input_note, padding_input_note];
+        let output_notes = [output_note, padding_output_note];
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let anchor = input_note.calculate_root(&merkle_path);
+        let input_info = input_notes.map(|note| {
+            InputNoteProvingInfo::create_padding_note_proving_info(
+                note,
+                merkle_path.clone(),
+                anchor,
+                input_notes,
+                output_notes,
+            )
+        });
+        let output_info = output_notes.map(|note| {
+            OutputNoteProvingInfo::create_padding_note_proving_info(note, input_notes, output_notes)
+        });
+
+        assert!(matches!(
+            ShieldedPartialTransaction::build_strict(input_info, output_info, vec![], &mut rng),
+            Err(TransactionError::InconsistentValueBase)
+        ));
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn test_ptx_signed_anchor_replay() {
+        use crate::circuit::vp_examples::{
+            signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+            token::{Token, TokenAuthorization, TokenName},
+        };
+        use crate::constant::VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX;
+        use crate::error::TransactionError;
+        use crate::executable::Executable;
+        use crate::keys::SigningKey;
+        use crate::merkle_tree::Anchor;
+
+        let mut rng = OsRng;
+        let token = Token::new(TokenName::new("btc").unwrap(), 5);
+        let auth_sk = SigningKey::random(&mut rng);
+        let auth = TokenAuthorization::from_sk_vk(auth_sk.inner(), &COMPRESSED_TOKEN_AUTH_VK);
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let rho = Nullifier::from(pallas::Base::random(&mut rng));
+        let input_note = token.create_random_token_note(&mut rng, rho, nk, &auth);
+        let output_note = token.create_random_token_note(
+            &mut rng,
+            input_note.get_nf().unwrap(),
+            nk.to_commitment(),
+            &auth,
+        );
+        let padding_input_note = Note::random_padding_input_note(&mut rng);
+        let padding_notes = [
+            padding_input_note,
+            Note::random_padding_output_note(&mut rng, padding_input_note.get_nf().unwrap()),
+        ];
+        let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+        let build = |padding_anchor| {
+            build_token_ptx(
+                &input_note,
+                &output_note,
+                padding_notes,
+                (auth, &auth_sk),
+                merkle_path.clone(),
+                padding_anchor,
+            )
+        };
+        let signed_ptx = build(Anchor::from(pallas::Base::random(&mut rng)));
+        let signed_anchors = signed_ptx.get_anchors();
+        signed_ptx.execute().unwrap();
+
+        // The same notes under another anchor, with the token auth vp signed under the anchors
+        // of the first ptx. The nfs and cms coincide, only the anchors tell them apart.
+        let mut replayed_ptx = build(Anchor::from(pallas::Base::random(&mut rng)));
+        assert_ne!(replayed_ptx.get_anchors(), signed_anchors);
+        replayed_ptx.inputs[0].app_dynamic_vp_verifying_info[0] =
+            signed_ptx.inputs[0].app_dynamic_vp_verifying_info[0].clone();
+        let signed_anchor_inputs = &replayed_ptx.inputs[0].app_dynamic_vp_verifying_info[0]
+            .public_inputs
+            .inner()[VP_CIRCUIT_CUSTOM_PUBLIC_INPUT_BEGIN_IDX..][..NUM_NOTE];
+        assert!(signed_anchor_inputs
+            .iter()
+            .zip(signed_anchors.iter())
+            .all(|(input, anchor)| *input == anchor.inner()));
+
+        // Every proof is valid, the signed anchors are not the action anchors
+        assert!(replayed_ptx.verify_proof().is_ok());
+        assert!(matches!(
+            replayed_ptx.execute(),
+            Err(TransactionError::InconsistentSignedAnchor)
+        ));
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn test_ptx_note_ciphertext_binding() {
+        use crate::circuit::vp_circuit::ValidityPredicatePublicInputs;
+        use crate::constant::{
+            NOTE_CIPHERTEXT_COMMITMENT_SENTINEL, VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX,
+        };
+        use crate::error::TransactionError;
+        use crate::executable::Executable;
+        use ff::PrimeField;
+
+        let ptx = create_token_ptx();
+        // The token output note is bound to the ciphertext from the receiver vp
+        let cipher_commitment = ptx.actions[0].cipher_commitment;
+        assert_ne!(cipher_commitment, NOTE_CIPHERTEXT_COMMITMENT_SENTINEL);
+        assert_eq!(
+            cipher_commitment,
+            ptx.outputs[0].app_dynamic_vp_verifying_info[0]
+                .public_inputs
+                .get_ciphertext()
+                .commitment()
+        );
+        // The padding output note has no encrypted payload
+        assert_eq!(
+            ptx.actions[1].cipher_commitment,
+            NOTE_CIPHERTEXT_COMMITMENT_SENTINEL
+        );
+        ptx.execute().unwrap();
+
+        // The explorer view shows the committed ciphertext
+        let view = ptx.public_view();
+        let ciphertext = ptx.outputs[0].app_dynamic_vp_verifying_info[0]
+            .public_inputs
+            .get_ciphertext();
+        assert_eq!(
+            view.actions[0].note_ciphertext,
+            Some(ciphertext.inner().iter().map(|e| e.to_repr()).collect())
+        );
+        assert_eq!(view.actions[1].note_ciphertext, None);
+
+        // The ciphertext is published along with the output note commitment and the sender pk
+        let encrypted_note = ptx.outputs[0].app_dynamic_vp_verifying_info[0]
+            .public_inputs
+            .get_encrypted_note()
+            .unwrap();
+        assert_eq!(encrypted_note.ciphertext, ciphertext);
+        assert_eq!(
+            ptx.ciphertexts(),
+            vec![(ptx.actions[0].cm, encrypted_note.clone())]
+        );
+        assert_eq!(
+            view.actions[0].note_sender_pk,
+            Some(crate::utils::point_to_bytes(&encrypted_note.sender_pk))
+        );
+        assert_eq!(view.actions[1].note_sender_pk, None);
+
+        // Tamper the published ciphertext
+        let mut tampered_ptx = ptx.clone();
+        let receiver_vp_info = &mut tampered_ptx.outputs[0].app_dynamic_vp_verifying_info[0];
+        let mut public_inputs = receiver_vp_info.public_inputs.to_vec();
+        public_inputs[VP_CIRCUIT_NOTE_ENCRYPTION_PUBLIC_INPUT_BEGIN_IDX] += pallas::Base::one();
+        receiver_vp_info.public_inputs = ValidityPredicatePublicInputs::from(public_inputs);
+        assert!(matches!(
+            tampered_ptx.check_note_ciphertexts(),
+            Err(TransactionError::InconsistentNoteCiphertext)
+        ));
+        assert!(tampered_ptx.execute().is_err());
+
+        // The same ciphertext from a vp the token vp didn't bind is rejected
+        let mut tampered_ptx = ptx.clone();
+        let other_vk = tampered_ptx.outputs[1].app_vp_verifying_info.vk.clone();
+        tampered_ptx.outputs[0].app_dynamic_vp_verifying_info[0].vk = other_vk;
+        assert!(matches!(
+            tampered_ptx.check_note_ciphertexts(),
+            Err(TransactionError::InconsistentNoteCiphertext)
+        ));
+        assert_eq!(tampered_ptx.ciphertexts(), vec![]);
+
+        // Tamper the cipher commitment
+        let mut tampered_ptx = ptx.clone();
+        tampered_ptx.actions[0].cipher_commitment += pallas::Base::one();
+        assert!(tampered_ptx.execute().is_err());
+
+        // The sentinel can't drop the ciphertext of the bound receiver vp
+        let mut tampered_ptx = ptx;
+        tampered_ptx.actions[0].cipher_commitment = NOTE_CIPHERTEXT_COMMITMENT_SENTINEL;
+        assert!(matches!(
+            tampered_ptx.check_note_ciphertexts(),
+            Err(TransactionError::MissingNoteCiphertext)
+        ));
+    }
+
+    #[test]
+    fn test_ptx_corrupted_proof() {
+        use crate::error::TransactionError;
+        use crate::proof::Proof;
+        use crate::transaction::{
+            ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle,
+        };
+
+        let ptx = create_shielded_ptx();
+
+        // Corrupt the second action proof
+        let mut tampered_ptx = ptx.clone();
+        let proofs = match &mut tampered_ptx.action_proofs {
+            ActionProofs::Separate(proofs) => proofs,
+            ActionProofs::Aggregated(_) => unreachable!(),
+        };
+        let mut proof_bytes = proofs[1].inner();
+        proof_bytes[0] ^= 1;
+        proofs[1] = Proof::new(proof_bytes);
+        assert!(matches!(
+            tampered_ptx.verify_proof(),
+            Err(TransactionError::ActionProofVerificationFailure {
+                ptx_index: 0,
+                action_index: 1
+            })
+        ));
+
+        // The error reports the index of the ptx in the bundle
+        let bundle = ShieldedPartialTxBundle::new(vec![ptx.clone(), tampered_ptx]);
+        let tx = Transaction::build(OsRng, bundle, TransparentPartialTxBundle::default()).unwrap();
+        assert!(matches!(
+            tx.execute(),
+            Err(TransactionError::ActionProofVerificationFailure {
+                ptx_index: 1,
+                action_index: 1
+            })
+        ));
+
+        // Corrupt the first dynamic vp proof of the first input note
+        let mut tampered_ptx = ptx;
+        let vp_info = &mut tampered_ptx.inputs[0].app_dynamic_vp_verifying_info[0];
+        let mut proof_bytes = vp_info.proof.inner();
+        proof_bytes[0] ^= 1;
+        vp_info.proof = Proof::new(proof_bytes);
+        assert!(matches!(
+            tampered_ptx.verify_proof(),
+            Err(TransactionError::VPProofVerificationFailure {
+                ptx_index: 0,
+                note_index: 0,
+                vp_index: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_ptx_malformed_instance_and_proof() {
+        use crate::constant::{
+            get_params, ACTION_CIRCUIT_PARAMS_SIZE, ACTION_PUBLIC_INPUT_NUM, ACTION_VERIFYING_KEY,
+            VP_CIRCUIT_INSTANCE_NUM, VP_CIRCUIT_PARAMS_SIZE,
+        };
+        use crate::error::TransactionError;
+        use crate::executable::Executable;
+        use crate::proof::Proof;
+        use crate::shielded_ptx::verify_error;
+        use halo2_proofs::plonk::Error;
+
+        let ptx = create_shielded_ptx();
+        let action = &ptx.actions[0];
+        let action_proof = &ptx.action_proofs.proofs()[0];
+        let vp_info = &ptx.inputs[0].app_vp_verifying_info;
+        let action_params = get_params(ACTION_CIRCUIT_PARAMS_SIZE);
+        let vp_params = get_params(VP_CIRCUIT_PARAMS_SIZE);
+
+        // Truncated and extended instances are rejected before the halo2 verifier runs
+        let action_instance = action.to_vec();
+        let vp_instance = vp_info.public_inputs.to_instance();
+        let mut extended_action_instance = action_instance.clone();
+        extended_action_instance.push(pallas::Base::one());
+        let mut extended_vp_instance = vp_instance.clone();
+        extended_vp_instance.push(pallas::Base::one());
+        for instance in [
+            &action_instance[..ACTION_PUBLIC_INPUT_NUM - 1],
+            extended_action_instance.as_slice(),
+            &[][..],
+        ] {
+            assert!(matches!(
+                action_proof.verify_single_instance(
+                    &ACTION_VERIFYING_KEY,
+                    &action_params,
+                    instance,
+                    ACTION_PUBLIC_INPUT_NUM,
+                ),
+                Err(Error::InvalidInstances)
+            ));
+            // The halo2 verifier doesn't panic on them either
+            assert!(action_proof
+                .verify(&ACTION_VERIFYING_KEY, &action_params, &[instance])
+                .is_err());
+        }
+        for instance in [
+            &vp_instance[..VP_CIRCUIT_INSTANCE_NUM - 1],
+            extended_vp_instance.as_slice(),
+        ] {
+            assert!(matches!(
+                vp_info.proof.verify_single_instance(
+                    &vp_info.vk,
+                    &vp_params,
+                    instance,
+                    VP_CIRCUIT_INSTANCE_NUM,
+                ),
+                Err(Error::InvalidInstances)
+            ));
+        }
+        assert!(matches!(
+            verify_error(
+                Error::InvalidInstances,
+                TransactionError::ActionProofVerificationFailure {
+                    ptx_index: 0,
+                    action_index: 0
+                }
+            ),
+            TransactionError::PublicInputMalformed
+        ));
+
+        // Garbage, empty and truncated proofs fail without panicking
+        let proof_bytes = action_proof.inner();
+        for bytes in [
+            vec![],
+            vec![0xff; 32],
+            vec![0u8; proof_bytes.len()],
+            proof_bytes[..proof_bytes.len() / 2].to_vec(),
+            [proof_bytes.as_slice(), &[0u8; 64][..]].concat(),
+        ] {
+            let proof = Proof::new(bytes);
+            assert!(proof
+                .verify_single_instance(
+                    &ACTION_VERIFYING_KEY,
+                    &action_params,
+                    &action_instance,
+                    ACTION_PUBLIC_INPUT_NUM,
+                )
+                .is_err());
+            assert!(proof
+                .verify_single_instance(
+                    &vp_info.vk,
+                    &vp_params,
+                    &vp_instance,
+                    VP_CIRCUIT_INSTANCE_NUM,
+                )
+                .is_err());
+        }
+
+        // A ptx carrying a garbage proof is rejected
+        let mut tampered_ptx = ptx;
+        tampered_ptx.inputs[0].app_vp_verifying_info.proof = Proof::new(vec![0xff; 32]);
+        assert!(matches!(
+            tampered_ptx.execute(),
+            Err(TransactionError::VPProofVerificationFailure {
+                ptx_index: 0,
+                note_index: 0,
+                vp_index: 0
+            })
+        ));
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn test_malformed_vp_verifying_info_deserialization() {
+        use crate::circuit::vp_circuit::VPVerifyingInfo;
+        use crate::proof::Proof;
+        use borsh::BorshDeserialize;
+
+        let ptx = create_shielded_ptx();
+        let vp_info = &ptx.inputs[0].app_vp_verifying_info;
+        let bytes = borsh::to_vec(vp_info).unwrap();
+        assert!(VPVerifyingInfo::try_from_slice(&bytes).is_ok());
+
+        // Truncated and extended encodings
+        assert!(VPVerifyingInfo::try_from_slice(&bytes[..bytes.len() - 1]).is_err());
+        assert!(
+            VPVerifyingInfo::try_from_slice(&[bytes.as_slice(), &[0u8; 32][..]].concat()).is_err()
+        );
+        assert!(VPVerifyingInfo::try_from_slice(&bytes[..bytes.len() / 2]).is_err());
+
+        // A non-canonical public input, the last one is encoded above the field modulus
+        let mut non_canonical = bytes.clone();
+        let last = non_canonical.len() - 32;
+        non_canonical[last..].copy_from_slice(&[0xffu8; 32]);
+        assert!(VPVerifyingInfo::try_from_slice(&non_canonical).is_err());
+
+        // An empty proof
+        assert!(Proof::try_from_slice(&borsh::to_vec(&Vec::<u8>::new()).unwrap()).is_err());
+        let proof = borsh::to_vec(&vp_info.proof).unwrap();
+        assert!(Proof::try_from_slice(&proof).is_ok());
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn test_shared_vp_instance_serialization() {
+        use crate::circuit::vp_circuit::{VPVerifyingInfo, ValidityPredicatePublicInputs};
+        use crate::constant::VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM;
+        use crate::error::TransactionError;
+        use crate::executable::Executable;
+        use crate::shielded_ptx::shared_vp_public_inputs;
+        use borsh::BorshDeserialize;
+
+        let ptx = create_shielded_ptx();
+        let bytes = borsh::to_vec(&ptx).unwrap();
+        let mut legacy_bytes = vec![];
+        ptx.serialize_legacy(&mut legacy_bytes).unwrap();
+
+        // Every vp skips its shared public inputs for a length tag, the ptx has a version
+        let vp_num = ptx
+            .inputs
+            .iter()
+            .chain(ptx.outputs.iter())
+            .map(|note| note.get_vp_verifying_infos().count())
+            .sum::<usize>();
+        assert_eq!(
+            legacy_bytes.len() - bytes.len(),
+            vp_num * (VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM * 32 - 1) - 1
+        );
+        assert_eq!(bytes.len(), ptx.cost_report().size());
+
+        // Both encodings decode to the same ptx, the shared public inputs are rebuilt
+        for encoding in [&bytes, &legacy_bytes] {
+            let decoded = ShieldedPartialTransaction::try_from_slice(encoding).unwrap();
+            assert_eq!(decoded.content_hash(), ptx.content_hash());
+            decoded.execute().unwrap();
+        }
+
+        // A vp proven in another ptx, relabelled with the shared public inputs of this ptx. The
+        // encoding doesn't carry them, the proof fails under the rebuilt ones.
+        let other_info = create_shielded_ptx().inputs[0]
+            .app_vp_verifying_info
+            .clone();
+        let prefix = shared_vp_public_inputs(&ptx.actions)[0][0];
+        let public_inputs = ValidityPredicatePublicInputs::try_from_vec(
+            prefix
+                .iter()
+                .chain(&other_info.public_inputs.inner()[VP_CIRCUIT_SHARED_PUBLIC_INPUT_NUM..])
+                .copied()
+                .collect(),
+        )
+        .unwrap();
+        let mut forged = ptx.clone();
+        forged.inputs[0].app_vp_verifying_info = VPVerifyingInfo {
+            public_inputs,
+            ..other_info
+        };
+        let decoded =
+            ShieldedPartialTransaction::try_from_slice(&borsh::to_vec(&forged).unwrap()).unwrap();
+        assert!(matches!(
+            decoded.verify_proof(),
+            Err(TransactionError::VPProofVerificationFailure {
+                ptx_index: 0,
+                note_index: 0,
+                vp_index: 0
+            })
+        ));
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn test_extra_data_serialization() {
+        use crate::shielded_ptx::{EXTRA_DATA_VERSION, SHARED_VP_INSTANCE_VERSION};
+        use borsh::BorshDeserialize;
+
+        // Without extra data, the ptx keeps the shared vp instance encoding
+        let ptx = create_shielded_ptx();
+        let bytes = borsh::to_vec(&ptx).unwrap();
+        assert_eq!(bytes[0], SHARED_VP_INSTANCE_VERSION);
+        assert_eq!(bytes.len(), ptx.cost_report().size());
+        let decoded = ShieldedPartialTransaction::try_from_slice(&bytes).unwrap();
+        assert!(decoded.get_extra_data().is_empty());
+        assert_eq!(borsh::to_vec(&decoded).unwrap(), bytes);
+
+        // The extra data is appended to the same encoding under another version
+        let extra_data = b"sell 2 btc for 10 eth".to_vec();
+        let mut ptx_with_data = ptx.clone();
+        ptx_with_data.set_extra_data(extra_data.clone());
+        let bytes_with_data = borsh::to_vec(&ptx_with_data).unwrap();
+        assert_eq!(bytes_with_data[0], EXTRA_DATA_VERSION);
+        assert_eq!(bytes_with_data[1..bytes.len()], bytes[1..]);
+        assert_eq!(
+            bytes_with_data[bytes.len()..],
+            borsh::to_vec(&extra_data).unwrap()
+        );
+        assert_eq!(bytes_with_data.len(), ptx_with_data.cost_report().size());
+        let decoded = ShieldedPartialTransaction::try_from_slice(&bytes_with_data).unwrap();
+        assert_eq!(decoded.get_extra_data(), extra_data);
+        assert_eq!(borsh::to_vec(&decoded).unwrap(), bytes_with_data);
+
+        // The legacy encoding can't carry it
+        assert!(ptx_with_data.serialize_legacy(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_action_with_tree_depth() {
+        use crate::action::ActionInfo;
+        use crate::error::{NoteError, TransactionError};
+        use crate::note::tests::{random_input_note, random_output_note};
+        use crate::shielded_ptx::ActionVerifyingInfo;
+        use crate::state::CheckpointedState;
+
+        const DEPTH: usize = 8;
+        let mut rng = OsRng;
+
+        // The input note is in a tree of depth 8
+        let input_note = random_input_note(&mut rng);
+        let mut tree = CheckpointedState::new(DEPTH);
+        tree.append(NoteCommitment::from(pallas::Base::random(&mut rng)))
+            .unwrap();
+        let position = tree.append_marked(input_note.commitment()).unwrap();
+        let merkle_path = tree.witness(position).unwrap();
+        assert_eq!(merkle_path.depth(), DEPTH);
+        let anchor = tree.root();
+        assert_eq!(input_note.calculate_root(&merkle_path), anchor);
+
+        let output_note = random_output_note(&mut rng, input_note.get_nf().unwrap());
+        let action_info = ActionInfo::new(
+            input_note,
+            merkle_path,
+            anchor,
+            output_note,
+            RandomSeed::random(&mut rng),
+        );
+
+        // The path doesn't fit the default depth
+        assert!(matches!(
+            action_info.build_with_depth::<TAIGA_COMMITMENT_TREE_DEPTH>(),
+            Err(NoteError::MerklePathDepthMismatch {
+                expected: TAIGA_COMMITMENT_TREE_DEPTH,
+                actual: DEPTH
+            })
+        ));
+        assert!(matches!(
+            ActionVerifyingInfo::create_with_depth::<TAIGA_COMMITMENT_TREE_DEPTH, _>(
+                action_info.clone(),
+                &mut rng
+            ),
+            Err(TransactionError::Note(
+                NoteError::MerklePathDepthMismatch { .. }
+            ))
+        ));
+
+        // Prove and verify with the keys of depth 8
+        let action =
+            ActionVerifyingInfo::create_with_depth::<DEPTH, _>(action_info, &mut rng).unwrap();
+        assert_eq!(action.verify_with_depth::<DEPTH>(), Ok(()));
+        // The proof doesn't verify under the keys of the default depth
+        assert!(action.verify().is_err());
     }
 }
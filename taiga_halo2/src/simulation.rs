@@ -0,0 +1,127 @@
+/// The plans of the dry runs, see `ShieldedPartialTransaction::dry_run` and `Transaction::dry_run`.
+/// A dry run does the native part of the building: the nullifiers, the commitments and the value
+/// balances are computed from the proving infos, but no proof is created. The plans are meant to
+/// be shown to the user before proving.
+use crate::merkle_tree::Anchor;
+use crate::note::{NoteCommitment, NoteType};
+use crate::nullifier::Nullifier;
+
+#[cfg(feature = "serde")]
+use serde;
+
+/// The public data of an action and the notes behind it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ActionPlan {
+    pub nf: Nullifier,
+    pub anchor: Anchor,
+    pub input_note_type: NoteType,
+    pub input_value: u64,
+    pub cm: NoteCommitment,
+    pub output_note_type: NoteType,
+    pub output_value: u64,
+}
+
+/// The total input and output values of a note type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValueBalance {
+    pub note_type: NoteType,
+    pub input_value: u128,
+    pub output_value: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PtxPlan {
+    pub actions: Vec<ActionPlan>,
+    // The note types with a non-zero value, in the order they first appear
+    pub balances: Vec<ValueBalance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TxPlan {
+    pub ptxs: Vec<PtxPlan>,
+    // The balances summed over the ptxs, they are all balanced
+    pub balances: Vec<ValueBalance>,
+}
+
+impl ValueBalance {
+    pub fn is_balanced(&self) -> bool {
+        self.input_value == self.output_value
+    }
+}
+
+impl PtxPlan {
+    pub fn new(actions: Vec<ActionPlan>) -> Self {
+        let balances = sum_balances(actions.iter().flat_map(|action| {
+            [
+                ValueBalance {
+                    note_type: action.input_note_type,
+                    input_value: action.input_value as u128,
+                    output_value: 0,
+                },
+                ValueBalance {
+                    note_type: action.output_note_type,
+                    input_value: 0,
+                    output_value: action.output_value as u128,
+                },
+            ]
+        }));
+        Self { actions, balances }
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.balances.iter().all(|balance| balance.is_balanced())
+    }
+
+    pub fn nullifiers(&self) -> Vec<Nullifier> {
+        self.actions.iter().map(|action| action.nf).collect()
+    }
+
+    pub fn output_cms(&self) -> Vec<NoteCommitment> {
+        self.actions.iter().map(|action| action.cm).collect()
+    }
+
+    pub fn anchors(&self) -> Vec<Anchor> {
+        self.actions.iter().map(|action| action.anchor).collect()
+    }
+}
+
+impl TxPlan {
+    pub(crate) fn new(ptxs: Vec<PtxPlan>) -> Self {
+        let balances = sum_balances(ptxs.iter().flat_map(|ptx| ptx.balances.iter().copied()));
+        Self { ptxs, balances }
+    }
+
+    pub fn nullifiers(&self) -> Vec<Nullifier> {
+        self.ptxs.iter().flat_map(|ptx| ptx.nullifiers()).collect()
+    }
+
+    pub fn output_cms(&self) -> Vec<NoteCommitment> {
+        self.ptxs.iter().flat_map(|ptx| ptx.output_cms()).collect()
+    }
+
+    pub fn anchors(&self) -> Vec<Anchor> {
+        self.ptxs.iter().flat_map(|ptx| ptx.anchors()).collect()
+    }
+}
+
+// Sums the values per note type, the zero values are dropped
+fn sum_balances(values: impl Iterator<Item = ValueBalance>) -> Vec<ValueBalance> {
+    let mut balances: Vec<ValueBalance> = vec![];
+    for value in values.filter(|value| value.input_value != 0 || value.output_value != 0) {
+        match balances
+            .iter_mut()
+            .find(|balance| balance.note_type == value.note_type)
+        {
+            Some(balance) => {
+                balance.input_value += value.input_value;
+                balance.output_value += value.output_value;
+            }
+            None => balances.push(value),
+        }
+    }
+    balances
+}
@@ -0,0 +1,55 @@
+//! Helpers for solvers matching partial transactions.
+use crate::shielded_ptx::ShieldedPartialTransaction;
+use crate::value_commitment::ValueCommitment;
+use pasta_curves::pallas;
+
+#[cfg(feature = "examples")]
+mod intent_book;
+#[cfg(feature = "examples")]
+pub use intent_book::IntentBook;
+
+/// Whether the candidate ptxs balance each other, checked with the net value commitments only.
+/// It's a cheap filter before building the transaction, the proofs are not verified.
+pub fn match_ptxs(candidates: &[ShieldedPartialTransaction]) -> bool {
+    let cv_net: ValueCommitment = candidates
+        .iter()
+        .map(|ptx| ptx.get_net_value_commitment())
+        .sum();
+    let total_blind = candidates.iter().fold(pallas::Scalar::zero(), |acc, ptx| {
+        acc + ptx.get_binding_sig_r()
+    });
+    cv_net.is_binding_to_zero(&total_blind)
+}
+
+#[test]
+fn test_match_ptxs() {
+    use crate::note::NoteType;
+    use crate::shielded_ptx::testing::{create_shielded_ptx, create_trivial_swap_ptx};
+    use halo2_proofs::arithmetic::Field;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let btc = NoteType::new(pallas::Base::random(&mut rng), pallas::Base::zero());
+    let eth = NoteType::new(pallas::Base::random(&mut rng), pallas::Base::zero());
+
+    // Alice sells 1 btc for 10 eth, Bob sells 10 eth for 1 btc
+    let alice_ptx = create_trivial_swap_ptx((btc, 1), (eth, 10));
+    let bob_ptx = create_trivial_swap_ptx((eth, 10), (btc, 1));
+    assert!(!match_ptxs(&[alice_ptx.clone()]));
+    assert!(match_ptxs(&[alice_ptx.clone(), bob_ptx.clone()]));
+
+    // Bob only sells 9 eth
+    let cheap_bob_ptx = create_trivial_swap_ptx((eth, 9), (btc, 1));
+    assert!(!match_ptxs(&[alice_ptx.clone(), cheap_bob_ptx]));
+
+    // An unrelated ptx that is not balanced by itself breaks the match
+    let unrelated_ptx = create_trivial_swap_ptx((btc, 2), (eth, 5));
+    assert!(!match_ptxs(&[
+        alice_ptx.clone(),
+        bob_ptx.clone(),
+        unrelated_ptx
+    ]));
+
+    // A ptx balanced by itself doesn't
+    assert!(match_ptxs(&[alice_ptx, bob_ptx, create_shielded_ptx()]));
+}
@@ -0,0 +1,165 @@
+use crate::circuit::vp_examples::partial_fulfillment_intent::{IntentAdvert, Swap};
+use crate::note::Note;
+use std::collections::HashMap;
+
+/// The intent adverts seen by a solver. The adverts are enough to find the matching intents, the
+/// creators then hand their swaps to the solver to build the fulfillment ptxs.
+#[derive(Clone, Debug, Default)]
+pub struct IntentBook {
+    adverts: Vec<IntentAdvert>,
+    // The swaps and intent notes from the creators, keyed by the intent note commitment
+    swaps: HashMap<[u8; 32], (Swap, Note)>,
+}
+
+impl IntentBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn adverts(&self) -> &[IntentAdvert] {
+        &self.adverts
+    }
+
+    /// Adds the advert if it's signed and not in the book yet.
+    pub fn insert(&mut self, advert: IntentAdvert) -> bool {
+        let cm = advert.intent_note_cm.to_bytes();
+        if !advert.verify()
+            || self
+                .adverts
+                .iter()
+                .any(|existing| existing.intent_note_cm.to_bytes() == cm)
+        {
+            return false;
+        }
+        self.adverts.push(advert);
+        true
+    }
+
+    /// Adds the swap of an advert in the book. It's rejected if the swap or the intent note don't
+    /// open the advert.
+    pub fn add_swap(&mut self, swap: Swap, intent_note: Note) -> bool {
+        let cm = intent_note.commitment().to_bytes();
+        let advert = match self
+            .adverts
+            .iter()
+            .find(|advert| advert.intent_note_cm.to_bytes() == cm)
+        {
+            Some(advert) => advert,
+            None => return false,
+        };
+        // The advert carries the first ask
+        let opens_advert = advert.sell_token == swap.sell().encode_name()
+            && advert.sell_value == swap.sell().note().value
+            && swap.asks().first().map_or(false, |ask| {
+                advert.buy_token == ask.encode_name() && advert.buy_value == ask.value()
+            })
+            && advert.pk() == swap.auth().pk
+            && swap.opens_intent_note(&intent_note).is_ok();
+        if opens_advert {
+            self.swaps.insert(cm, (swap, intent_note));
+        }
+        opens_advert
+    }
+
+    /// The pairs of adverts, by index, not expired at `now` and selling each other's bought
+    /// token, where both creators get at least their price.
+    pub fn matching_pairs(&self, now: u64) -> Vec<(usize, usize)> {
+        let live: Vec<_> = self
+            .adverts
+            .iter()
+            .enumerate()
+            .filter(|(_, advert)| advert.expiry >= now)
+            .collect();
+        let mut pairs = vec![];
+        for (i, (a_index, a)) in live.iter().enumerate() {
+            for (b_index, b) in live.iter().skip(i + 1) {
+                if is_match(a, b) {
+                    pairs.push((*a_index, *b_index));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// The swaps and intent notes of the matching pairs, for the fulfillment ptx builders. The
+    /// pairs missing a swap are skipped.
+    pub fn matched_swaps(&self, now: u64) -> Vec<[(Swap, Note); 2]> {
+        let swap_of = |index: usize| {
+            self.swaps
+                .get(&self.adverts[index].intent_note_cm.to_bytes())
+                .cloned()
+        };
+        self.matching_pairs(now)
+            .into_iter()
+            .filter_map(|(a, b)| Some([swap_of(a)?, swap_of(b)?]))
+            .collect()
+    }
+}
+
+// a sells x_a for y_a, b sells y_b of a's bought token for x_b of a's sold token. The prices
+// cross if y_a / x_a <= y_b / x_b, i.e. y_a * x_b <= y_b * x_a
+fn is_match(a: &IntentAdvert, b: &IntentAdvert) -> bool {
+    a.sell_token == b.buy_token
+        && a.buy_token == b.sell_token
+        && (a.buy_value as u128) * (b.buy_value as u128)
+            <= (a.sell_value as u128) * (b.sell_value as u128)
+}
+
+#[test]
+fn test_intent_book() {
+    use crate::circuit::vp_examples::{
+        signature_verification::COMPRESSED_TOKEN_AUTH_VK,
+        token::{Token, TokenAuthorization, TokenName},
+    };
+    use crate::keys::SigningKey;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let mut intent = |sell: (&str, u64), buy: (&str, u64), expiry: u64| {
+        let auth_sk = SigningKey::random(&mut rng);
+        let auth = TokenAuthorization::from_sk_vk(auth_sk.inner(), &COMPRESSED_TOKEN_AUTH_VK);
+        let swap = Swap::random(
+            &mut rng,
+            Token::new(TokenName::new(sell.0).unwrap(), sell.1),
+            Token::new(TokenName::new(buy.0).unwrap(), buy.1),
+            auth,
+        );
+        let intent_note = swap.create_intent_note(&mut rng);
+        let advert = swap
+            .public_advert(&mut rng, &intent_note, expiry, &auth_sk)
+            .unwrap();
+        (swap, intent_note, advert)
+    };
+
+    // Alice sells 2 btc for 2 eth, Bob sells 4 eth for 4 btc
+    let (alice_swap, alice_note, alice_advert) = intent(("btc", 2), ("eth", 2), 100);
+    let (bob_swap, bob_note, bob_advert) = intent(("eth", 4), ("btc", 4), 100);
+    // Carol wants 2 btc per eth, Alice wants 1 eth per btc
+    let (_, _, carol_advert) = intent(("eth", 2), ("btc", 4), 100);
+    // Dave sells at Bob's price, but the advert expires
+    let (_, _, dave_advert) = intent(("eth", 1), ("btc", 1), 10);
+
+    let mut book = IntentBook::new();
+    assert!(book.insert(alice_advert.clone()));
+    assert!(book.insert(bob_advert));
+    assert!(book.insert(carol_advert));
+    assert!(book.insert(dave_advert));
+    // The same advert again, or a forged one
+    assert!(!book.insert(alice_advert.clone()));
+    let mut forged = alice_advert;
+    forged.buy_value = 1;
+    assert!(!book.insert(forged));
+
+    assert_eq!(book.matching_pairs(0), vec![(0, 1), (0, 3)]);
+    assert_eq!(book.matching_pairs(50), vec![(0, 1)]);
+
+    // The swaps come from the creators, the swap of another intent is rejected
+    assert!(book.matched_swaps(50).is_empty());
+    assert!(!book.add_swap(bob_swap.clone(), alice_note));
+    assert!(book.add_swap(alice_swap, alice_note));
+    assert!(book.add_swap(bob_swap, bob_note));
+    let matched = book.matched_swaps(50);
+    assert_eq!(matched.len(), 1);
+    assert_eq!(matched[0][0].1, alice_note);
+    assert_eq!(matched[0][1].1, bob_note);
+}
@@ -0,0 +1,769 @@
+//! The note commitment tree and the nullifier set with checkpoints.
+//!
+//! The tree is an append-only incremental merkle tree, only the frontier and the witnesses of the
+//! marked notes are stored. A checkpoint keeps the frontier and an undo log of the operations
+//! after it, so the memory grows with the number of operations since the oldest retained
+//! checkpoint, not with the tree size.
+use crate::constant::TAIGA_COMMITMENT_TREE_DEPTH;
+use crate::merkle_tree::{Anchor, MerklePath, Node, LR};
+use crate::note::NoteCommitment;
+use crate::note_encryption::EncryptedNote;
+use crate::nullifier::Nullifier;
+use crate::transaction::TransactionId;
+use halo2_proofs::arithmetic::Field;
+use pasta_curves::pallas;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+pub type CheckpointId = u64;
+
+/// The ledger state a transaction is applied to.
+pub trait TaigaState {
+    /// Whether the anchor is a root of the commitment tree accepted by the ledger.
+    fn is_known_anchor(&self, anchor: &Anchor) -> bool;
+
+    fn contains_nullifier(&self, nf: &Nullifier) -> bool;
+
+    fn insert_nullifier(&mut self, nf: Nullifier) -> Result<(), StateError>;
+
+    /// Appends the output note commitment and returns its position.
+    fn append_commitment(&mut self, cm: NoteCommitment) -> Result<u64, StateError>;
+
+    /// The current root of the commitment tree.
+    fn root(&self) -> Anchor;
+
+    /// Called with the new root after a diff is applied, e.g. to accept it as an anchor.
+    fn record_anchor(&mut self, _anchor: Anchor) {}
+
+    /// Called with the note ciphertexts of an applied diff, they are dropped by default.
+    fn store_ciphertexts(&mut self, _ciphertexts: Vec<(NoteCommitment, EncryptedNote)>) {}
+
+    /// The height of the block the diffs are applied in, if the state knows it. It's reported to
+    /// the observers with the new anchors.
+    fn height(&self) -> Option<u64> {
+        None
+    }
+
+    /// Inserts the nullifiers and appends the commitments of the diff, then returns the new root.
+    /// The diff is not checked against the state, see `Transaction::apply`.
+    fn apply_diff(&mut self, diff: StateDiff) -> Result<Anchor, StateError> {
+        self.apply_diff_with_observer(diff, &mut ())
+    }
+
+    /// Same as apply_diff, the observer is called as the diff is applied: the spent nullifiers,
+    /// then the created notes in the order they are appended, then the new anchor. If the diff
+    /// fails halfway, the observer has seen the events before the failure.
+    fn apply_diff_with_observer(
+        &mut self,
+        diff: StateDiff,
+        observer: &mut dyn StateObserver,
+    ) -> Result<Anchor, StateError> {
+        for nf in diff.spent_nullifiers {
+            self.insert_nullifier(nf)?;
+            observer.on_nullifier_spent(nf, diff.tx_id);
+        }
+        for cm in diff.new_commitments.iter() {
+            let position = self.append_commitment(*cm)?;
+            let ciphertext = diff
+                .ciphertexts
+                .iter()
+                .find(|(ciphertext_cm, _)| ciphertext_cm == cm)
+                .map(|(_, ciphertext)| ciphertext);
+            observer.on_note_created(*cm, position, ciphertext);
+        }
+        let anchor = self.root();
+        self.record_anchor(anchor);
+        observer.on_anchor(anchor, self.height());
+        self.store_ciphertexts(diff.ciphertexts);
+        Ok(anchor)
+    }
+}
+
+/// The callbacks of the state changes, e.g. for an indexer. The events are reported while the
+/// diffs are applied, so the observer doesn't re-parse the transactions. The callbacks do nothing
+/// by default.
+pub trait StateObserver {
+    /// A note commitment is appended at the position. The ciphertext is the one published with
+    /// the note, if any.
+    fn on_note_created(
+        &mut self,
+        _cm: NoteCommitment,
+        _position: u64,
+        _ciphertext: Option<&EncryptedNote>,
+    ) {
+    }
+
+    /// The nullifier is inserted, tx_id is the id of the transaction of the diff if it's known.
+    fn on_nullifier_spent(&mut self, _nf: Nullifier, _tx_id: Option<TransactionId>) {}
+
+    /// The root after a diff, with the height of the state if it's known.
+    fn on_anchor(&mut self, _anchor: Anchor, _height: Option<u64>) {}
+}
+
+// No observer
+impl StateObserver for () {}
+
+/// A state change reported to a StateObserver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateEvent {
+    NoteCreated {
+        cm: NoteCommitment,
+        position: u64,
+        ciphertext: Option<EncryptedNote>,
+    },
+    NullifierSpent {
+        nf: Nullifier,
+        tx_id: Option<TransactionId>,
+    },
+    Anchor {
+        anchor: Anchor,
+        height: Option<u64>,
+    },
+}
+
+/// Records the events in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VecObserver {
+    pub events: Vec<StateEvent>,
+}
+
+impl StateObserver for VecObserver {
+    fn on_note_created(
+        &mut self,
+        cm: NoteCommitment,
+        position: u64,
+        ciphertext: Option<&EncryptedNote>,
+    ) {
+        self.events.push(StateEvent::NoteCreated {
+            cm,
+            position,
+            ciphertext: ciphertext.cloned(),
+        });
+    }
+
+    fn on_nullifier_spent(&mut self, nf: Nullifier, tx_id: Option<TransactionId>) {
+        self.events.push(StateEvent::NullifierSpent { nf, tx_id });
+    }
+
+    fn on_anchor(&mut self, anchor: Anchor, height: Option<u64>) {
+        self.events.push(StateEvent::Anchor { anchor, height });
+    }
+}
+
+/// The state changes of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDiff {
+    // The transaction the diff comes from, if it's known
+    pub tx_id: Option<TransactionId>,
+    // The output note commitments, in the order they are appended
+    pub new_commitments: Vec<NoteCommitment>,
+    pub spent_nullifiers: Vec<Nullifier>,
+    // The ciphertexts of the output notes sent with an encrypted payload, with the commitments of
+    // the notes
+    pub ciphertexts: Vec<(NoteCommitment, EncryptedNote)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The tree has no room for more commitments.
+    TreeFull,
+    /// The nullifier is already in the nullifier set.
+    DuplicateNullifier,
+    /// The checkpoint id is not greater than the latest checkpoint id.
+    InvalidCheckpoint,
+    /// The checkpoint doesn't exist or has been pruned.
+    UnknownCheckpoint,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use StateError::*;
+        match self {
+            TreeFull => f.write_str("The commitment tree is full"),
+            DuplicateNullifier => f.write_str("The nullifier already exists"),
+            InvalidCheckpoint => {
+                f.write_str("The checkpoint id must be greater than the latest checkpoint id")
+            }
+            UnknownCheckpoint => f.write_str("The checkpoint doesn't exist or has been pruned"),
+        }
+    }
+}
+
+// The witness of a marked note. The left siblings are known when the note is appended, the
+// right siblings are filled when the sibling subtrees are completed.
+#[derive(Debug, Clone)]
+struct TrackedWitness {
+    leaf: Node,
+    siblings: Vec<Option<Node>>,
+}
+
+// The frontier of the incremental merkle tree
+#[derive(Debug, Clone)]
+struct Frontier {
+    // The number of leaves
+    size: u64,
+    // ommers[l] is the root of the last complete subtree at level l if the bit l of size is set,
+    // ommers[depth] is the root of the full tree
+    ommers: Vec<Option<Node>>,
+}
+
+#[derive(Debug, Clone)]
+enum Operation {
+    // The witness siblings (position, level) filled by the append
+    Append(Vec<(u64, usize)>),
+    Mark(u64),
+    InsertNullifier(Nullifier),
+}
+
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    id: CheckpointId,
+    frontier: Frontier,
+    // The operations after the checkpoint and before the next checkpoint
+    operations: Vec<Operation>,
+}
+
+/// CheckpointedState wraps the note commitment tree and the nullifier set.
+#[derive(Debug, Clone)]
+pub struct CheckpointedState {
+    depth: usize,
+    frontier: Frontier,
+    // empty_roots[l] is the root of an empty subtree at level l
+    empty_roots: Vec<Node>,
+    witnesses: BTreeMap<u64, TrackedWitness>,
+    nullifiers: HashSet<Nullifier>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Default for CheckpointedState {
+    fn default() -> Self {
+        Self::new(TAIGA_COMMITMENT_TREE_DEPTH)
+    }
+}
+
+impl CheckpointedState {
+    pub fn new(depth: usize) -> Self {
+        assert!(depth > 0 && depth < 64);
+        let mut empty_roots = vec![Node::from(pallas::Base::zero())];
+        for l in 0..depth {
+            empty_roots.push(Node::combine(&empty_roots[l], &empty_roots[l]));
+        }
+        Self {
+            depth,
+            frontier: Frontier {
+                size: 0,
+                ommers: vec![None; depth + 1],
+            },
+            empty_roots,
+            witnesses: BTreeMap::new(),
+            nullifiers: HashSet::new(),
+            checkpoints: vec![],
+        }
+    }
+
+    /// The number of the appended commitments
+    pub fn size(&self) -> u64 {
+        self.frontier.size
+    }
+
+    pub fn root(&self) -> Anchor {
+        self.root_of(&self.frontier)
+    }
+
+    /// The current root and the roots at the retained checkpoints
+    pub fn recent_roots(&self) -> Vec<Anchor> {
+        let mut roots: Vec<Anchor> = self
+            .checkpoints
+            .iter()
+            .map(|checkpoint| self.root_of(&checkpoint.frontier))
+            .collect();
+        roots.push(self.root());
+        roots
+    }
+
+    /// Appends the note commitment and returns its position.
+    pub fn append(&mut self, cm: NoteCommitment) -> Result<u64, StateError> {
+        self.append_inner(cm, false)
+    }
+
+    /// Appends the note commitment and tracks its witness.
+    pub fn append_marked(&mut self, cm: NoteCommitment) -> Result<u64, StateError> {
+        self.append_inner(cm, true)
+    }
+
+    /// Returns the merkle path of the marked note to the current root.
+    pub fn witness(&self, position: u64) -> Option<MerklePath> {
+        let witness = self.witnesses.get(&position)?;
+        let partial_subtrees = self.partial_subtrees(&self.frontier);
+        let path = (0..self.depth)
+            .map(|l| {
+                let index = position >> l;
+                if index & 1 == 1 {
+                    // The left sibling
+                    (witness.siblings[l].unwrap(), LR::L)
+                } else {
+                    // The right sibling is complete, partially filled or empty
+                    let sibling = witness.siblings[l]
+                        .or(if index + 1 == self.frontier.size >> l {
+                            partial_subtrees[l]
+                        } else {
+                            None
+                        })
+                        .unwrap_or(self.empty_roots[l]);
+                    (sibling, LR::R)
+                }
+            })
+            .collect();
+        Some(MerklePath::from_path(path))
+    }
+
+    /// Returns the marked note commitment
+    pub fn get_marked_leaf(&self, position: u64) -> Option<NoteCommitment> {
+        self.witnesses
+            .get(&position)
+            .map(|witness| NoteCommitment::from(witness.leaf.inner()))
+    }
+
+    pub fn insert_nullifier(&mut self, nf: Nullifier) -> Result<(), StateError> {
+        if !self.nullifiers.insert(nf) {
+            return Err(StateError::DuplicateNullifier);
+        }
+        self.log(Operation::InsertNullifier(nf));
+        Ok(())
+    }
+
+    pub fn contains_nullifier(&self, nf: &Nullifier) -> bool {
+        self.nullifiers.contains(nf)
+    }
+
+    /// Creates a checkpoint of the current state, the id must be increasing.
+    pub fn checkpoint(&mut self, id: CheckpointId) -> Result<(), StateError> {
+        if let Some(latest) = self.checkpoints.last() {
+            if id <= latest.id {
+                return Err(StateError::InvalidCheckpoint);
+            }
+        }
+        self.checkpoints.push(Checkpoint {
+            id,
+            frontier: self.frontier.clone(),
+            operations: vec![],
+        });
+        Ok(())
+    }
+
+    /// Undoes the operations after the checkpoint. The checkpoint is retained and the later
+    /// checkpoints are removed.
+    pub fn rollback_to(&mut self, id: CheckpointId) -> Result<(), StateError> {
+        let index = self
+            .checkpoints
+            .iter()
+            .position(|checkpoint| checkpoint.id == id)
+            .ok_or(StateError::UnknownCheckpoint)?;
+
+        for checkpoint in self.checkpoints.drain(index + 1..).rev() {
+            Self::undo(
+                &mut self.witnesses,
+                &mut self.nullifiers,
+                checkpoint.operations,
+            );
+        }
+        let checkpoint = &mut self.checkpoints[index];
+        Self::undo(
+            &mut self.witnesses,
+            &mut self.nullifiers,
+            std::mem::take(&mut checkpoint.operations),
+        );
+        self.frontier = checkpoint.frontier.clone();
+        Ok(())
+    }
+
+    /// Removes the checkpoints older than the checkpoint id, they can't be rolled back to anymore.
+    pub fn prune_before(&mut self, id: CheckpointId) {
+        self.checkpoints.retain(|checkpoint| checkpoint.id >= id);
+    }
+
+    pub fn checkpoint_ids(&self) -> Vec<CheckpointId> {
+        self.checkpoints
+            .iter()
+            .map(|checkpoint| checkpoint.id)
+            .collect()
+    }
+
+    fn append_inner(&mut self, cm: NoteCommitment, mark: bool) -> Result<u64, StateError> {
+        let position = self.frontier.size;
+        if position >> self.depth != 0 {
+            return Err(StateError::TreeFull);
+        }
+
+        // Carry the new leaf up and collect the completed subtrees
+        let leaf = Node::from(cm);
+        let mut node = leaf;
+        let mut completed = vec![(0, node)];
+        let mut left_siblings = vec![None; self.depth];
+        let mut level = 0;
+        while (position >> level) & 1 == 1 {
+            let ommer = self.frontier.ommers[level].take().unwrap();
+            left_siblings[level] = Some(ommer);
+            node = Node::combine(&ommer, &node);
+            level += 1;
+            completed.push((level, node));
+        }
+        self.frontier.ommers[level] = Some(node);
+        for (l, left_sibling) in left_siblings.iter_mut().enumerate().skip(level + 1) {
+            if (position >> l) & 1 == 1 {
+                *left_sibling = self.frontier.ommers[l];
+            }
+        }
+        self.frontier.size += 1;
+
+        // Fill the right siblings of the tracked witnesses
+        let mut filled = vec![];
+        for (level, node) in completed.into_iter().filter(|(l, _)| *l < self.depth) {
+            let index = position >> level;
+            // The completed subtree is the right sibling of the subtrees under index - 1
+            if index & 1 == 1 {
+                let start = (index - 1) << level;
+                let end = index << level;
+                for (p, witness) in self.witnesses.range_mut(start..end) {
+                    witness.siblings[level] = Some(node);
+                    filled.push((*p, level));
+                }
+            }
+        }
+        self.log(Operation::Append(filled));
+
+        if mark {
+            self.witnesses.insert(
+                position,
+                TrackedWitness {
+                    leaf,
+                    siblings: left_siblings,
+                },
+            );
+            self.log(Operation::Mark(position));
+        }
+
+        Ok(position)
+    }
+
+    fn root_of(&self, frontier: &Frontier) -> Anchor {
+        self.partial_subtrees(frontier)[self.depth]
+            .unwrap_or(self.empty_roots[self.depth])
+            .into()
+    }
+
+    // partial_subtrees[l] is the root of the subtree at level l containing the next position,
+    // None if the subtree is empty. partial_subtrees[depth] is the root.
+    fn partial_subtrees(&self, frontier: &Frontier) -> Vec<Option<Node>> {
+        let size = frontier.size;
+        let mut partial_subtrees = vec![None];
+        let mut acc: Option<Node> = None;
+        for l in 0..self.depth {
+            acc = if (size >> l) & 1 == 1 {
+                let right = acc.unwrap_or(self.empty_roots[l]);
+                Some(Node::combine(&frontier.ommers[l].unwrap(), &right))
+            } else {
+                acc.map(|left| Node::combine(&left, &self.empty_roots[l]))
+            };
+            partial_subtrees.push(acc);
+        }
+        // The tree is full
+        if size >> self.depth != 0 {
+            partial_subtrees[self.depth] = frontier.ommers[self.depth];
+        }
+        partial_subtrees
+    }
+
+    fn log(&mut self, operation: Operation) {
+        if let Some(checkpoint) = self.checkpoints.last_mut() {
+            checkpoint.operations.push(operation);
+        }
+    }
+
+    fn undo(
+        witnesses: &mut BTreeMap<u64, TrackedWitness>,
+        nullifiers: &mut HashSet<Nullifier>,
+        operations: Vec<Operation>,
+    ) {
+        for operation in operations.into_iter().rev() {
+            match operation {
+                Operation::Append(filled) => {
+                    for (position, level) in filled {
+                        if let Some(witness) = witnesses.get_mut(&position) {
+                            witness.siblings[level] = None;
+                        }
+                    }
+                }
+                Operation::Mark(position) => {
+                    witnesses.remove(&position);
+                }
+                Operation::InsertNullifier(nf) => {
+                    nullifiers.remove(&nf);
+                }
+            }
+        }
+    }
+}
+
+// The anchors of the current root and the retained checkpoints are accepted
+impl TaigaState for CheckpointedState {
+    fn is_known_anchor(&self, anchor: &Anchor) -> bool {
+        self.recent_roots().contains(anchor)
+    }
+
+    fn contains_nullifier(&self, nf: &Nullifier) -> bool {
+        CheckpointedState::contains_nullifier(self, nf)
+    }
+
+    fn insert_nullifier(&mut self, nf: Nullifier) -> Result<(), StateError> {
+        CheckpointedState::insert_nullifier(self, nf)
+    }
+
+    fn append_commitment(&mut self, cm: NoteCommitment) -> Result<u64, StateError> {
+        self.append(cm)
+    }
+
+    fn root(&self) -> Anchor {
+        CheckpointedState::root(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    const DEPTH: usize = 4;
+
+    fn random_cm() -> NoteCommitment {
+        NoteCommitment::from(pallas::Base::random(OsRng))
+    }
+
+    // Computes the root from all the leaves
+    fn naive_root(leaves: &[NoteCommitment]) -> Anchor {
+        let mut nodes: Vec<Node> = (0..1 << DEPTH)
+            .map(|i| {
+                leaves
+                    .get(i)
+                    .map(|cm| Node::from(*cm))
+                    .unwrap_or(Node::from(pallas::Base::zero()))
+            })
+            .collect();
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| Node::combine(&pair[0], &pair[1]))
+                .collect();
+        }
+        nodes[0].into()
+    }
+
+    #[test]
+    fn test_incremental_root() {
+        let mut state = CheckpointedState::new(DEPTH);
+        let mut leaves = vec![];
+        assert_eq!(state.root(), naive_root(&leaves));
+        for _ in 0..1 << DEPTH {
+            let cm = random_cm();
+            leaves.push(cm);
+            state.append(cm).unwrap();
+            assert_eq!(state.root(), naive_root(&leaves));
+        }
+        assert_eq!(state.append(random_cm()), Err(StateError::TreeFull));
+    }
+
+    #[test]
+    fn test_rollback_root() {
+        let mut state = CheckpointedState::new(DEPTH);
+        for _ in 0..3 {
+            state.append(random_cm()).unwrap();
+        }
+        let root = state.root();
+        state.checkpoint(1).unwrap();
+        for _ in 0..5 {
+            state.append(random_cm()).unwrap();
+        }
+        assert_ne!(state.root(), root);
+        state.rollback_to(1).unwrap();
+        assert_eq!(state.root(), root);
+        assert_eq!(state.size(), 3);
+
+        // The checkpoint is retained after rollback
+        state.append(random_cm()).unwrap();
+        state.rollback_to(1).unwrap();
+        assert_eq!(state.root(), root);
+    }
+
+    #[test]
+    fn test_witness_across_rollbacks() {
+        let mut state = CheckpointedState::new(DEPTH);
+        let mut leaves = vec![];
+        for i in 0..5 {
+            let cm = random_cm();
+            leaves.push(cm);
+            if i == 2 {
+                state.append_marked(cm).unwrap();
+            } else {
+                state.append(cm).unwrap();
+            }
+        }
+        let check_witness = |state: &CheckpointedState, position: u64| {
+            let cm = state.get_marked_leaf(position).unwrap();
+            let path = state.witness(position).unwrap();
+            assert_eq!(path.root(Node::from(cm)), state.root());
+            assert_eq!(path.position(), position);
+        };
+        check_witness(&state, 2);
+
+        state.checkpoint(1).unwrap();
+        let marked_after_checkpoint = {
+            state.append(random_cm()).unwrap();
+            let position = state.append_marked(random_cm()).unwrap();
+            for _ in 0..4 {
+                state.append(random_cm()).unwrap();
+            }
+            position
+        };
+        check_witness(&state, 2);
+        check_witness(&state, marked_after_checkpoint);
+
+        state.rollback_to(1).unwrap();
+        assert_eq!(state.root(), naive_root(&leaves));
+        check_witness(&state, 2);
+        assert!(state.witness(marked_after_checkpoint).is_none());
+
+        // Keep appending after the rollback
+        for _ in 0..8 {
+            let cm = random_cm();
+            leaves.push(cm);
+            state.append(cm).unwrap();
+            assert_eq!(state.root(), naive_root(&leaves));
+            check_witness(&state, 2);
+        }
+    }
+
+    #[test]
+    fn test_nullifier_rollback() {
+        let mut state = CheckpointedState::new(DEPTH);
+        let nf_1 = Nullifier::from(pallas::Base::random(OsRng));
+        let nf_2 = Nullifier::from(pallas::Base::random(OsRng));
+        state.insert_nullifier(nf_1).unwrap();
+        state.checkpoint(1).unwrap();
+        state.insert_nullifier(nf_2).unwrap();
+        assert_eq!(
+            state.insert_nullifier(nf_2),
+            Err(StateError::DuplicateNullifier)
+        );
+        state.rollback_to(1).unwrap();
+        assert!(state.contains_nullifier(&nf_1));
+        assert!(!state.contains_nullifier(&nf_2));
+        state.insert_nullifier(nf_2).unwrap();
+    }
+
+    #[test]
+    fn test_observer_events() {
+        use crate::constant::NOTE_ENCRYPTION_CIPHERTEXT_NUM;
+        use group::Group;
+
+        let random_nf = || Nullifier::from(pallas::Base::random(OsRng));
+        let ciphertext = EncryptedNote {
+            ciphertext: vec![pallas::Base::random(OsRng); NOTE_ENCRYPTION_CIPHERTEXT_NUM].into(),
+            sender_pk: pallas::Point::random(OsRng),
+        };
+
+        // The first transaction creates two notes, one with a ciphertext, the second one spends
+        // the first note and creates another
+        let (cm_1, cm_2, cm_3) = (random_cm(), random_cm(), random_cm());
+        let (nf_1, nf_2) = (random_nf(), random_nf());
+        let (tx_id_1, tx_id_2) = (
+            TransactionId::from_bytes([1; 32]),
+            TransactionId::from_bytes([2; 32]),
+        );
+        let diff_1 = StateDiff {
+            tx_id: Some(tx_id_1),
+            new_commitments: vec![cm_1, cm_2],
+            spent_nullifiers: vec![nf_1],
+            ciphertexts: vec![(cm_2, ciphertext.clone())],
+        };
+        let diff_2 = StateDiff {
+            tx_id: Some(tx_id_2),
+            new_commitments: vec![cm_3],
+            spent_nullifiers: vec![nf_2],
+            ciphertexts: vec![],
+        };
+
+        let mut state = CheckpointedState::new(DEPTH);
+        let mut observer = VecObserver::default();
+        let anchor_1 = state
+            .apply_diff_with_observer(diff_1, &mut observer)
+            .unwrap();
+        let anchor_2 = state
+            .apply_diff_with_observer(diff_2.clone(), &mut observer)
+            .unwrap();
+        assert_eq!(
+            observer.events,
+            vec![
+                StateEvent::NullifierSpent {
+                    nf: nf_1,
+                    tx_id: Some(tx_id_1)
+                },
+                StateEvent::NoteCreated {
+                    cm: cm_1,
+                    position: 0,
+                    ciphertext: None
+                },
+                StateEvent::NoteCreated {
+                    cm: cm_2,
+                    position: 1,
+                    ciphertext: Some(ciphertext)
+                },
+                StateEvent::Anchor {
+                    anchor: anchor_1,
+                    height: None
+                },
+                StateEvent::NullifierSpent {
+                    nf: nf_2,
+                    tx_id: Some(tx_id_2)
+                },
+                StateEvent::NoteCreated {
+                    cm: cm_3,
+                    position: 2,
+                    ciphertext: None
+                },
+                StateEvent::Anchor {
+                    anchor: anchor_2,
+                    height: None
+                },
+            ]
+        );
+
+        // A failed diff reports the events before the failure
+        let mut observer = VecObserver::default();
+        assert_eq!(
+            state.apply_diff_with_observer(diff_2, &mut observer),
+            Err(StateError::DuplicateNullifier)
+        );
+        assert!(observer.events.is_empty());
+    }
+
+    #[test]
+    fn test_prune_checkpoints() {
+        let mut state = CheckpointedState::new(DEPTH);
+        state.checkpoint(1).unwrap();
+        state.append(random_cm()).unwrap();
+        state.checkpoint(2).unwrap();
+        let root = state.root();
+        state.append(random_cm()).unwrap();
+        state.checkpoint(3).unwrap();
+        state.append(random_cm()).unwrap();
+        assert_eq!(state.checkpoint(3), Err(StateError::InvalidCheckpoint));
+
+        state.prune_before(2);
+        assert_eq!(state.checkpoint_ids(), vec![2, 3]);
+        assert_eq!(state.rollback_to(1), Err(StateError::UnknownCheckpoint));
+        state.rollback_to(2).unwrap();
+        assert_eq!(state.root(), root);
+        // The later checkpoints are removed by the rollback
+        assert_eq!(state.rollback_to(3), Err(StateError::UnknownCheckpoint));
+    }
+}
@@ -1,16 +1,22 @@
-#[cfg(feature = "borsh")]
 use crate::{
     action::ActionInfo,
-    circuit::vp_bytecode::ApplicationByteCode,
-    error::TransactionError,
-    transaction::{ShieldedResult, TransparentResult},
-};
-use crate::{
-    note::{Note, RandomSeed},
+    keys::{DecryptedNote, FullViewingKey, IncomingViewingKey},
+    merkle_tree::{Anchor, MerklePath},
+    note::{Note, NoteCommitment, RandomSeed},
+    note_encryption::EncryptedNote,
+    note_selection::{select_notes, NoteInTree, Selection, SelectionError, SelectionStrategy},
     nullifier::{Nullifier, NullifierKeyContainer},
     shielded_ptx::ShieldedPartialTransaction,
+    state::{CheckpointedState, StateError, TaigaState},
     transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
 };
+#[cfg(feature = "borsh")]
+use crate::{
+    circuit::vp_bytecode::ApplicationByteCode,
+    error::TransactionError,
+    transaction::{ShieldedResult, TransparentResult},
+    vp_registry::{RegisteredVp, VkRegistry},
+};
 use pasta_curves::pallas;
 use rand::rngs::OsRng;
 
@@ -122,7 +128,10 @@ pub fn note_deserialize(bytes: Vec<u8>) -> std::io::Result<Note> {
 /// Shielded Partial Transaction layout:
 /// | Parameters                        | type                  | size(bytes)   |
 /// |       -                           |       -               |   -           |
-/// | 2 action proofs                   | ActionVerifyingInfo   | 4676 * 2      |
+/// | action proof layout(by borsh)     | u8                    | 1             |
+/// | 2 separate action proofs          | Proof                 | 4388 * 2      |
+/// | or 1 aggregated action proof      | Proof                 | -             |
+/// | 2 action public inputs            | ActionPublicInputs    | 288 * 2       |
 /// | input1 static vp proof            | VPVerifyingInfo       | 158216        |
 /// | input1 dynamic vp num(by borsh)   | u32                   | 4             |
 /// | input1 dynamic vp proof           | VPVerifyingInfo       | 158216 * num  |
@@ -137,6 +146,9 @@ pub fn note_deserialize(bytes: Vec<u8>) -> std::io::Result<Note> {
 /// | output2 dynamic vp proofs         | VPVerifyingInfo       | 158216 * num  |
 /// | binding_sig_r                     | pallas::Scalar        | 32            |
 /// | hints                             | Vec<u8>               | -             |
+/// | extra_data(if not empty)          | Vec<u8>               | -             |
+///
+/// The extra data is only written under its own version, a ptx without it is encoded as before.
 ///
 /// Note: Ultimately, vp proofs won't go to the ptx. It's verifier proofs instead.
 /// The verifier proof may have a much smaller size since the verifier verifying-key
@@ -175,8 +187,46 @@ pub fn transaction_deserialize(bytes: Vec<u8>) -> std::io::Result<Transaction> {
     BorshDeserialize::deserialize(&mut bytes.as_ref())
 }
 
+/// Select the owned notes to spend for a payment of `target`
+///
+/// The notes must be of the same note type. The selected notes are split into ptxs of at most
+/// NUM_NOTE input notes, and the change value is returned to the spender by the last ptx.
+pub fn select_input_notes(
+    owned_notes: &[NoteInTree],
+    target: u64,
+    strategy: SelectionStrategy,
+) -> Result<Selection, SelectionError> {
+    select_notes(owned_notes, target, strategy)
+}
+
+/// Create the actions of a ptx in a selection plan
+///
+/// The output notes are paired with the selected input notes in order, their rho must be the
+/// nullifier of the paired input note.
+pub fn create_ptx_actions(inputs: &[NoteInTree], output_notes: Vec<Note>) -> Vec<ActionInfo> {
+    assert_eq!(
+        inputs.len(),
+        output_notes.len(),
+        "every selected note needs an output note"
+    );
+    let mut rng = OsRng;
+    inputs
+        .iter()
+        .zip(output_notes)
+        .map(|(input, output_note)| {
+            ActionInfo::new(
+                input.note,
+                input.merkle_path.clone(),
+                input.anchor(),
+                output_note,
+                RandomSeed::random(&mut rng),
+            )
+        })
+        .collect()
+}
+
 /// Create a shielded partial transaction from vp bytecode
-#[cfg(feature = "borsh")]
+#[cfg(all(feature = "borsh", feature = "prover"))]
 pub fn create_shielded_partial_transaction(
     actions: Vec<ActionInfo>,
     input_note_app: Vec<ApplicationByteCode>,
@@ -193,7 +243,7 @@ pub fn create_transaction(
     shielded_ptxs: Vec<ShieldedPartialTransaction>,
     // TODO: add transparent_ptxs
     // transparent_ptxs: Vec<TransparentPartialTransaction>,
-) -> Transaction {
+) -> Result<Transaction, TransactionError> {
     let rng = OsRng;
     let shielded_ptx_bundle = ShieldedPartialTxBundle::new(shielded_ptxs);
     // empty transparent_ptx_bundle
@@ -201,6 +251,40 @@ pub fn create_transaction(
     Transaction::build(rng, shielded_ptx_bundle, transparent_ptx_bundle)
 }
 
+/// Retrieve the notes sent to the incoming viewing key in the transaction
+///
+/// The ciphertexts published by the output note vps are trial-decrypted, and the decrypted notes
+/// are checked against the output note commitments. The returned notes only contain the nk
+/// commitment, use the full viewing key to derive the nullifiers.
+pub fn retrieve_owned_notes(ivk: &IncomingViewingKey, tx: &Transaction) -> Vec<Note> {
+    let bundle = tx.get_shielded_ptx_bundle();
+    let output_cms = bundle.get_output_cms();
+    let encrypted_notes: Vec<EncryptedNote> = bundle
+        .get_output_vp_public_inputs()
+        .iter()
+        .filter_map(|public_inputs| public_inputs.get_encrypted_note().ok())
+        .collect();
+    let mut notes: Vec<Note> = vec![];
+    for DecryptedNote { note, .. } in ivk.scan(&encrypted_notes).into_iter().flatten() {
+        // The app vp and the dynamic vps may publish the same ciphertext
+        if ivk.is_note_created(&note, &output_cms) && !notes.contains(&note) {
+            notes.push(note);
+        }
+    }
+    notes
+}
+
+/// Check if the note is spent in the transaction
+pub fn check_spent(fvk: &FullViewingKey, note: &Note, tx: &Transaction) -> bool {
+    fvk.is_spent(note, &tx.get_shielded_ptx_bundle().get_nullifiers())
+}
+
+/// Resolve the application vp of the note by its app_vk
+#[cfg(feature = "borsh")]
+pub fn retrieve_note_app<'a>(registry: &'a VkRegistry, note: &Note) -> Option<&'a RegisteredVp> {
+    registry.resolve(&note.note_type.app_vk)
+}
+
 /// Verify a transaction and return the results
 ///
 /// ShieldedResult layout:
@@ -237,6 +321,104 @@ pub fn verify_shielded_partial_transaction(ptx_bytes: Vec<u8>) -> Result<(), Tra
     ptx.verify_proof()
 }
 
+/// Verify a shielded transaction whose vp proofs are all created under registered vks
+#[cfg(feature = "borsh")]
+pub fn verify_shielded_partial_transaction_with_registry(
+    ptx_bytes: Vec<u8>,
+    registry: &VkRegistry,
+) -> Result<(), TransactionError> {
+    // Decode the ptx
+    let ptx = partial_transaction_deserialize(ptx_bytes)?;
+
+    // Check the vps are known
+    if !ptx
+        .get_vp_verifying_infos()
+        .all(|vp_info| registry.contains_vk(&vp_info.vk))
+    {
+        return Err(TransactionError::UnregisteredValidityPredicate);
+    }
+
+    // Verify the ptx
+    ptx.verify_proof()
+}
+
+/// An in-memory ledger state for tests and examples
+///
+/// Every appended commitment is marked, so the merkle path of any output note can be retrieved
+/// to spend it in a later transaction. All the roots after applied diffs are accepted as anchors.
+#[derive(Debug, Clone)]
+pub struct TestContext {
+    state: CheckpointedState,
+    anchors: Vec<Anchor>,
+    ciphertexts: Vec<(NoteCommitment, EncryptedNote)>,
+}
+
+impl TestContext {
+    pub fn new() -> Self {
+        let state = CheckpointedState::default();
+        let anchors = vec![state.root()];
+        Self {
+            state,
+            anchors,
+            ciphertexts: vec![],
+        }
+    }
+
+    /// The merkle path of the commitment at the position to the current root
+    pub fn witness(&self, position: u64) -> Option<MerklePath> {
+        self.state.witness(position)
+    }
+
+    /// The position of the commitment in the tree
+    pub fn position(&self, cm: &NoteCommitment) -> Option<u64> {
+        (0..self.state.size()).find(|position| self.state.get_marked_leaf(*position) == Some(*cm))
+    }
+
+    pub fn anchors(&self) -> &[Anchor] {
+        &self.anchors
+    }
+
+    pub fn ciphertexts(&self) -> &[(NoteCommitment, EncryptedNote)] {
+        &self.ciphertexts
+    }
+}
+
+impl Default for TestContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaigaState for TestContext {
+    fn is_known_anchor(&self, anchor: &Anchor) -> bool {
+        self.anchors.contains(anchor)
+    }
+
+    fn contains_nullifier(&self, nf: &Nullifier) -> bool {
+        self.state.contains_nullifier(nf)
+    }
+
+    fn insert_nullifier(&mut self, nf: Nullifier) -> Result<(), StateError> {
+        self.state.insert_nullifier(nf)
+    }
+
+    fn append_commitment(&mut self, cm: NoteCommitment) -> Result<u64, StateError> {
+        self.state.append_marked(cm)
+    }
+
+    fn root(&self) -> Anchor {
+        self.state.root()
+    }
+
+    fn record_anchor(&mut self, anchor: Anchor) {
+        self.anchors.push(anchor);
+    }
+
+    fn store_ciphertexts(&mut self, ciphertexts: Vec<(NoteCommitment, EncryptedNote)>) {
+        self.ciphertexts.extend(ciphertexts);
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "borsh")]
 pub mod tests {
@@ -264,6 +446,34 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn vk_registry_api_test() {
+        use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
+        use crate::error::TransactionError;
+        use crate::shielded_ptx::testing::create_shielded_ptx;
+        use crate::vp_registry::VkRegistry;
+
+        let mut rng = OsRng;
+        let ptx_bytes = partial_transaction_serialize(&create_shielded_ptx()).unwrap();
+
+        // The trivial vp is not registered yet
+        let mut registry = VkRegistry::new();
+        assert!(matches!(
+            verify_shielded_partial_transaction_with_registry(ptx_bytes.clone(), &registry),
+            Err(TransactionError::UnregisteredValidityPredicate)
+        ));
+
+        let trivial_vp = TrivialValidityPredicateCircuit::default();
+        let app_vk = registry.register(trivial_vp.to_bytecode()).unwrap();
+        verify_shielded_partial_transaction_with_registry(ptx_bytes, &registry).unwrap();
+
+        // Resolve the application of the notes
+        let mut note = random_input_note(&mut rng);
+        assert!(retrieve_note_app(&registry, &note).is_none());
+        note.note_type.app_vk = app_vk;
+        assert!(retrieve_note_app(&registry, &note).is_some());
+    }
+
     #[ignore]
     #[test]
     fn ptx_example_test() {
@@ -359,4 +569,88 @@ pub mod tests {
         let ptx_bytes = partial_transaction_serialize(&ptx).unwrap();
         verify_shielded_partial_transaction(ptx_bytes).unwrap();
     }
+
+    #[test]
+    fn chained_transactions_test() {
+        use crate::circuit::vp_examples::{
+            TrivialValidityPredicateCircuit, COMPRESSED_TRIVIAL_VP_VK,
+        };
+        use crate::constant::TAIGA_COMMITMENT_TREE_DEPTH;
+        use crate::note::{InputNoteProvingInfo, OutputNoteProvingInfo};
+        use halo2_proofs::arithmetic::Field;
+
+        let mut rng = OsRng;
+        let nk = NullifierKeyContainer::random_key(&mut rng);
+        let create_note = |rho: Nullifier, value: u64| {
+            Note::new(
+                *COMPRESSED_TRIVIAL_VP_VK,
+                pallas::Base::zero(),
+                pallas::Base::zero(),
+                value,
+                nk,
+                rho,
+                true,
+                RandomSeed::random(OsRng),
+            )
+        };
+        // Spends the input notes to the output notes of the same values
+        let create_tx = |inputs: [(Note, MerklePath); 2], anchor: Option<Anchor>| {
+            let input_notes = inputs.clone().map(|(note, _)| note);
+            let output_notes =
+                input_notes.map(|note| create_note(note.get_nf().unwrap(), note.value));
+            let input_info = inputs.map(|(note, merkle_path)| {
+                let vp = TrivialValidityPredicateCircuit::new(
+                    note.get_nf().unwrap().inner(),
+                    input_notes,
+                    output_notes,
+                );
+                InputNoteProvingInfo::new(note, merkle_path, anchor, Box::new(vp), vec![])
+            });
+            let output_info = output_notes.map(|note| {
+                let vp = TrivialValidityPredicateCircuit::new(
+                    note.commitment().inner(),
+                    input_notes,
+                    output_notes,
+                );
+                OutputNoteProvingInfo::new(note, Box::new(vp), vec![])
+            });
+            let ptx =
+                ShieldedPartialTransaction::build(input_info, output_info, vec![], OsRng).unwrap();
+            (create_transaction(vec![ptx]).unwrap(), output_notes)
+        };
+
+        // The first tx spends notes with random paths, only its diff is applied
+        let mut ctx = TestContext::new();
+        let inputs = [5u64, 10u64].map(|value| {
+            let note = create_note(Nullifier::random(&mut rng), value);
+            (
+                note,
+                MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH),
+            )
+        });
+        let (tx1, output_notes) = create_tx(inputs, None);
+        tx1.execute().unwrap();
+        let anchor = ctx.apply_diff(tx1.state_diff()).unwrap();
+        assert_eq!(anchor, ctx.root());
+
+        // The second tx spends the outputs of the first one with the real paths
+        let inputs = output_notes.map(|note| {
+            let position = ctx.position(&note.commitment()).unwrap();
+            let merkle_path = ctx.witness(position).unwrap();
+            assert_eq!(note.calculate_root(&merkle_path), anchor);
+            (note, merkle_path)
+        });
+        let (tx2, _) = create_tx(inputs, Some(anchor));
+        tx2.execute().unwrap();
+        let diff = tx2.apply(&mut ctx).unwrap();
+        assert_eq!(
+            diff.spent_nullifiers,
+            output_notes.map(|note| note.get_nf().unwrap())
+        );
+        assert_eq!(ctx.anchors().len(), 3);
+        assert!(ctx.ciphertexts().is_empty());
+
+        // The outputs of the first tx can't be spent again
+        assert!(tx2.apply(&mut ctx).is_err());
+    }
 }
@@ -0,0 +1,482 @@
+//! Conformance test vectors for the other implementations of Taiga.
+//!
+//! Every vector is generated from a fixed seed and records its inputs along with the expected
+//! outputs, so the consumers don't need to reproduce the rng. The field elements are encoded in
+//! their canonical 32-byte little-endian form and the points in the compressed form, both as hex.
+//!
+//! The golden files in `test_vectors/` are checked by `test_golden_vectors`, any change of them is
+//! a consensus change. Regenerate them with `TAIGA_UPDATE_TEST_VECTORS=1` after an intended one.
+use crate::{
+    circuit::vp_examples::{
+        signature_verification::SchnorrSignature, TrivialValidityPredicateCircuit,
+        COMPRESSED_TRIVIAL_VP_VK,
+    },
+    constant::{
+        APP_DATA_DYNAMIC_DOMAIN, APP_DATA_DYNAMIC_POSEIDON_PERSONALIZATION, DUTCH_AUCTION_DOMAIN,
+        DUTCH_AUCTION_POSEIDON_PERSONALIZATION, INTENT_ADVERT_DOMAIN,
+        INTENT_ADVERT_POSEIDON_PERSONALIZATION, NK_COMMITMENT_DOMAIN,
+        NK_COMMITMENT_POSEIDON_PERSONALIZATION, NOTE_CIPHERTEXT_COMMITMENT_DOMAIN,
+        NOTE_CIPHERTEXT_COMMITMENT_POSEIDON_PERSONALIZATION, NOTE_COMMITMENT_POSEIDON_DOMAIN,
+        NOTE_COMMITMENT_POSEIDON_PERSONALIZATION, NOTE_ENCRYPTION_KEY_DOMAIN,
+        NOTE_ENCRYPTION_KEY_POSEIDON_PERSONALIZATION, NUM_NOTE, SCHNORR_SIGHASH_DOMAIN,
+        SCHNORR_SIGHASH_POSEIDON_PERSONALIZATION, SPENDING_CAP_DOMAIN,
+        SPENDING_CAP_POSEIDON_PERSONALIZATION, SWAP_INTENT_DOMAIN,
+        SWAP_INTENT_POSEIDON_PERSONALIZATION, TAIGA_COMMITMENT_TREE_DEPTH, TOKEN_BRIDGE_DOMAIN,
+        TOKEN_BRIDGE_POSEIDON_PERSONALIZATION,
+    },
+    error::TransactionError,
+    explorer::ActionPublicView,
+    keys::SigningKey,
+    merkle_tree::MerklePath,
+    note::{InputNoteProvingInfo, Note, NoteType, OutputNoteProvingInfo, RandomSeed},
+    nullifier::{Nullifier, NullifierKeyContainer},
+    shielded_ptx::ShieldedPartialTransaction,
+    utils::point_to_bytes,
+};
+use ff::{Field, PrimeField};
+use pasta_curves::pallas;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const NOTE_COMMITMENT_VECTOR_NUM: u64 = 20;
+pub const NULLIFIER_VECTOR_NUM: u64 = 10;
+pub const VALUE_BASE_VECTOR_NUM: u64 = 10;
+pub const SCHNORR_VECTOR_NUM: u64 = 10;
+pub const PTX_VECTOR_NUM: u64 = 5;
+
+// The seeds of the vectors are offset per kind, so the kinds don't share the rng streams
+const NOTE_COMMITMENT_SEED: u64 = 0;
+const NULLIFIER_SEED: u64 = 100;
+const VALUE_BASE_SEED: u64 = 200;
+const SCHNORR_SEED: u64 = 300;
+const PTX_SEED: u64 = 400;
+// The length of the signed messages, the sighash layout is covered by the ptx vectors
+const SCHNORR_MESSAGE_LEN: usize = 4;
+
+const VECTORS_FILE: &str = "vectors.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    pub note_commitments: Vec<NoteCommitmentVector>,
+    pub nullifiers: Vec<NullifierVector>,
+    pub value_bases: Vec<ValueBaseVector>,
+    pub poseidon_domains: Vec<PoseidonDomainVector>,
+    pub schnorr_signatures: Vec<SchnorrVector>,
+    pub ptxs: Vec<PtxVector>,
+}
+
+/// cm = poseidon_hash(NOTE_COMMITMENT_POSEIDON_DOMAIN || app_vk || app_data_static ||
+/// app_data_dynamic || nk_commitment || rho || psi || is_merkle_checked || value || rcm)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteCommitmentVector {
+    pub seed: u64,
+    pub app_vk: String,
+    pub app_data_static: String,
+    pub app_data_dynamic: String,
+    pub nk_commitment: String,
+    pub rho: String,
+    pub psi: String,
+    pub is_merkle_checked: bool,
+    pub value: u64,
+    pub rcm: String,
+    pub cm: String,
+}
+
+/// nf = poseidon_hash(nk || rho || psi || cm)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NullifierVector {
+    pub seed: u64,
+    pub nk: String,
+    pub rho: String,
+    pub psi: String,
+    pub cm: String,
+    pub nf: String,
+}
+
+/// value_base = poseidon_to_curve(app_vk || app_data_static)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueBaseVector {
+    pub seed: u64,
+    pub app_vk: String,
+    pub app_data_static: String,
+    pub value_base: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoseidonDomainVector {
+    pub personalization: String,
+    pub domain: String,
+}
+
+/// s*G == R + Hash(R || P || message)*P, signature = pk || r || s
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchnorrVector {
+    pub seed: u64,
+    pub sk: String,
+    pub message: Vec<String>,
+    pub pk: String,
+    pub r: String,
+    pub s: String,
+    pub signature: String,
+}
+
+/// The public content of a ptx of trivial vps. The proofs are randomized, so the serialized ptx
+/// is exported next to the vectors and checked by verifying it instead.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtxVector {
+    pub seed: u64,
+    pub actions: Vec<ActionVector>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionVector {
+    pub anchor: String,
+    pub nullifier: String,
+    pub output_cm: String,
+    pub cv_net_x: String,
+    pub cv_net_y: String,
+    pub input_app_vk: String,
+    pub output_app_vk: String,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_base(base: &pallas::Base) -> String {
+    hex(&base.to_repr())
+}
+
+fn hex_point(point: &pallas::Point) -> String {
+    hex(&point_to_bytes(point))
+}
+
+fn random_note(rng: &mut StdRng) -> Note {
+    Note::new(
+        pallas::Base::random(&mut *rng),
+        pallas::Base::random(&mut *rng),
+        pallas::Base::random(&mut *rng),
+        rng.gen(),
+        NullifierKeyContainer::random_key(&mut *rng),
+        Nullifier::random(&mut *rng),
+        rng.gen(),
+        RandomSeed::random(&mut *rng),
+    )
+}
+
+impl NoteCommitmentVector {
+    fn generate(seed: u64) -> Self {
+        let note = random_note(&mut StdRng::seed_from_u64(seed));
+        let preimage = note.commitment_preimage();
+        Self {
+            seed,
+            app_vk: hex_base(&preimage.app_vk),
+            app_data_static: hex_base(&preimage.app_data_static),
+            app_data_dynamic: hex_base(&preimage.app_data_dynamic),
+            nk_commitment: hex_base(&preimage.nk_commitment),
+            rho: hex_base(&preimage.rho),
+            psi: hex_base(&preimage.psi),
+            is_merkle_checked: note.is_merkle_checked,
+            value: note.value,
+            rcm: hex_base(&preimage.rcm),
+            cm: hex(&note.commitment().to_bytes()),
+        }
+    }
+}
+
+impl NullifierVector {
+    fn generate(seed: u64) -> Self {
+        let note = random_note(&mut StdRng::seed_from_u64(seed));
+        Self {
+            seed,
+            nk: hex_base(&note.nk_container.get_nk().unwrap()),
+            rho: hex_base(&note.rho.inner()),
+            psi: hex_base(&note.psi),
+            cm: hex(&note.commitment().to_bytes()),
+            nf: hex(&note.get_nf().unwrap().to_bytes()),
+        }
+    }
+}
+
+impl ValueBaseVector {
+    fn generate(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let note_type = NoteType::new(
+            pallas::Base::random(&mut rng),
+            pallas::Base::random(&mut rng),
+        );
+        Self {
+            seed,
+            app_vk: hex_base(&note_type.app_vk),
+            app_data_static: hex_base(&note_type.app_data_static),
+            value_base: hex_point(&note_type.derive_note_type()),
+        }
+    }
+}
+
+impl PoseidonDomainVector {
+    fn all() -> Vec<Self> {
+        [
+            (
+                &NK_COMMITMENT_POSEIDON_PERSONALIZATION[..],
+                NK_COMMITMENT_DOMAIN,
+            ),
+            (
+                &NOTE_COMMITMENT_POSEIDON_PERSONALIZATION[..],
+                NOTE_COMMITMENT_POSEIDON_DOMAIN,
+            ),
+            (
+                &APP_DATA_DYNAMIC_POSEIDON_PERSONALIZATION[..],
+                APP_DATA_DYNAMIC_DOMAIN,
+            ),
+            (
+                &NOTE_CIPHERTEXT_COMMITMENT_POSEIDON_PERSONALIZATION[..],
+                NOTE_CIPHERTEXT_COMMITMENT_DOMAIN,
+            ),
+            (
+                &SCHNORR_SIGHASH_POSEIDON_PERSONALIZATION[..],
+                SCHNORR_SIGHASH_DOMAIN,
+            ),
+            (
+                &SWAP_INTENT_POSEIDON_PERSONALIZATION[..],
+                SWAP_INTENT_DOMAIN,
+            ),
+            (
+                &DUTCH_AUCTION_POSEIDON_PERSONALIZATION[..],
+                DUTCH_AUCTION_DOMAIN,
+            ),
+            (
+                &NOTE_ENCRYPTION_KEY_POSEIDON_PERSONALIZATION[..],
+                NOTE_ENCRYPTION_KEY_DOMAIN,
+            ),
+            (
+                &INTENT_ADVERT_POSEIDON_PERSONALIZATION[..],
+                INTENT_ADVERT_DOMAIN,
+            ),
+            (
+                &SPENDING_CAP_POSEIDON_PERSONALIZATION[..],
+                SPENDING_CAP_DOMAIN,
+            ),
+            (
+                &TOKEN_BRIDGE_POSEIDON_PERSONALIZATION[..],
+                TOKEN_BRIDGE_DOMAIN,
+            ),
+        ]
+        .into_iter()
+        .map(|(personalization, domain)| Self {
+            personalization: String::from_utf8_lossy(personalization).into_owned(),
+            domain: hex_base(&domain),
+        })
+        .collect()
+    }
+}
+
+impl SchnorrVector {
+    fn generate(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let sk = pallas::Scalar::random(&mut rng);
+        let message: Vec<pallas::Base> = (0..SCHNORR_MESSAGE_LEN)
+            .map(|_| pallas::Base::random(&mut rng))
+            .collect();
+        let signature = SchnorrSignature::sign(&mut rng, &SigningKey::new(sk), message.clone());
+        Self {
+            seed,
+            sk: hex(&sk.to_repr()),
+            message: message.iter().map(hex_base).collect(),
+            pk: hex_point(&signature.pk()),
+            r: hex_point(&signature.r()),
+            s: hex(&signature.s().to_repr()),
+            signature: hex(&signature.to_bytes()),
+        }
+    }
+}
+
+impl PtxVector {
+    // Spends two random notes of the trivial vp to the notes of the same values
+    fn generate_ptx(seed: u64) -> Result<ShieldedPartialTransaction, TransactionError> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let create_note = |app_data_static, rho, value, rng: &mut StdRng| {
+            Note::new(
+                *COMPRESSED_TRIVIAL_VP_VK,
+                app_data_static,
+                pallas::Base::zero(),
+                value,
+                NullifierKeyContainer::random_key(&mut *rng),
+                rho,
+                true,
+                RandomSeed::random(&mut *rng),
+            )
+        };
+        let input_notes = [(); NUM_NOTE].map(|_| {
+            let app_data_static = pallas::Base::random(&mut rng);
+            let rho = Nullifier::random(&mut rng);
+            let value = rng.gen::<u32>() as u64;
+            create_note(app_data_static, rho, value, &mut rng)
+        });
+        // The output notes have the same note types and values, the ptx is balanced
+        let output_notes = input_notes.map(|input| {
+            let rho = input.get_nf().unwrap();
+            create_note(input.note_type.app_data_static, rho, input.value, &mut rng)
+        });
+
+        let input_info = input_notes.map(|note| {
+            let vp = TrivialValidityPredicateCircuit::new(
+                note.get_nf().unwrap().inner(),
+                input_notes,
+                output_notes,
+            );
+            let merkle_path = MerklePath::random(&mut rng, TAIGA_COMMITMENT_TREE_DEPTH);
+            InputNoteProvingInfo::new(note, merkle_path, None, Box::new(vp), vec![])
+        });
+        let output_info = output_notes.map(|note| {
+            let vp = TrivialValidityPredicateCircuit::new(
+                note.commitment().inner(),
+                input_notes,
+                output_notes,
+            );
+            OutputNoteProvingInfo::new(note, Box::new(vp), vec![])
+        });
+        ShieldedPartialTransaction::build(input_info, output_info, vec![], &mut rng)
+    }
+
+    fn from_ptx(seed: u64, ptx: &ShieldedPartialTransaction) -> Self {
+        let actions = ptx
+            .public_view()
+            .actions
+            .iter()
+            .map(|action| {
+                let ActionPublicView {
+                    anchor,
+                    nullifier,
+                    output_cm,
+                    cv_net_x,
+                    cv_net_y,
+                    input_app_vk,
+                    output_app_vk,
+                    ..
+                } = action;
+                ActionVector {
+                    anchor: hex(anchor),
+                    nullifier: hex(nullifier),
+                    output_cm: hex(output_cm),
+                    cv_net_x: hex(cv_net_x),
+                    cv_net_y: hex(cv_net_y),
+                    input_app_vk: hex(input_app_vk),
+                    output_app_vk: hex(output_app_vk),
+                }
+            })
+            .collect();
+        Self { seed, actions }
+    }
+}
+
+impl TestVectors {
+    /// Generates the vectors, the ptxs are proved so it takes a while.
+    pub fn generate() -> Self {
+        Self::generate_with_ptxs().0
+    }
+
+    fn generate_with_ptxs() -> (Self, Vec<ShieldedPartialTransaction>) {
+        let seeds = |begin: u64, num: u64| begin..begin + num;
+        let ptxs: Vec<_> = seeds(PTX_SEED, PTX_VECTOR_NUM)
+            .map(|seed| PtxVector::generate_ptx(seed).expect("the ptx vectors are valid"))
+            .collect();
+        let vectors = Self {
+            note_commitments: seeds(NOTE_COMMITMENT_SEED, NOTE_COMMITMENT_VECTOR_NUM)
+                .map(NoteCommitmentVector::generate)
+                .collect(),
+            nullifiers: seeds(NULLIFIER_SEED, NULLIFIER_VECTOR_NUM)
+                .map(NullifierVector::generate)
+                .collect(),
+            value_bases: seeds(VALUE_BASE_SEED, VALUE_BASE_VECTOR_NUM)
+                .map(ValueBaseVector::generate)
+                .collect(),
+            poseidon_domains: PoseidonDomainVector::all(),
+            schnorr_signatures: seeds(SCHNORR_SEED, SCHNORR_VECTOR_NUM)
+                .map(SchnorrVector::generate)
+                .collect(),
+            ptxs: seeds(PTX_SEED, PTX_VECTOR_NUM)
+                .zip(ptxs.iter())
+                .map(|(seed, ptx)| PtxVector::from_ptx(seed, ptx))
+                .collect(),
+        };
+        (vectors, ptxs)
+    }
+}
+
+fn ptx_file_name(seed: u64) -> String {
+    format!("ptx_{}.bin", seed)
+}
+
+/// Writes the vectors to `vectors.json` and the borsh encoded ptxs to `ptx_<seed>.bin` in the
+/// directory.
+pub fn generate_test_vectors(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let (vectors, ptxs) = TestVectors::generate_with_ptxs();
+    write_test_vectors(path.as_ref(), &vectors, &ptxs)
+}
+
+fn write_test_vectors(
+    path: &Path,
+    vectors: &TestVectors,
+    ptxs: &[ShieldedPartialTransaction],
+) -> std::io::Result<()> {
+    std::fs::create_dir_all(path)?;
+    let json = serde_json::to_string_pretty(vectors)?;
+    std::fs::write(path.join(VECTORS_FILE), json + "\n")?;
+    for (vector, ptx) in vectors.ptxs.iter().zip(ptxs) {
+        std::fs::write(path.join(ptx_file_name(vector.seed)), borsh::to_vec(ptx)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshDeserialize;
+
+    const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_vectors");
+
+    #[test]
+    fn test_golden_vectors() {
+        let dir = Path::new(GOLDEN_DIR);
+        let (vectors, ptxs) = TestVectors::generate_with_ptxs();
+        if std::env::var_os("TAIGA_UPDATE_TEST_VECTORS").is_some()
+            || !dir.join(VECTORS_FILE).exists()
+        {
+            write_test_vectors(dir, &vectors, &ptxs).unwrap();
+        }
+
+        let golden: TestVectors =
+            serde_json::from_slice(&std::fs::read(dir.join(VECTORS_FILE)).unwrap()).unwrap();
+        assert_eq!(vectors.note_commitments, golden.note_commitments);
+        assert_eq!(vectors.nullifiers, golden.nullifiers);
+        assert_eq!(vectors.value_bases, golden.value_bases);
+        assert_eq!(vectors.poseidon_domains, golden.poseidon_domains);
+        assert_eq!(vectors.schnorr_signatures, golden.schnorr_signatures);
+        assert_eq!(vectors.ptxs, golden.ptxs);
+
+        // The golden ptxs still decode and verify
+        for vector in golden.ptxs.iter() {
+            let bytes = std::fs::read(dir.join(ptx_file_name(vector.seed))).unwrap();
+            let ptx = ShieldedPartialTransaction::deserialize(&mut bytes.as_slice()).unwrap();
+            ptx.verify_proof().unwrap();
+            assert_eq!(PtxVector::from_ptx(vector.seed, &ptx), *vector);
+        }
+    }
+
+    #[test]
+    fn test_vectors_are_deterministic() {
+        for seed in 0..3 {
+            assert_eq!(
+                NoteCommitmentVector::generate(seed),
+                NoteCommitmentVector::generate(seed)
+            );
+            assert_eq!(SchnorrVector::generate(seed), SchnorrVector::generate(seed));
+        }
+        assert_ne!(
+            NullifierVector::generate(0).nf,
+            NullifierVector::generate(1).nf
+        );
+    }
+}
@@ -1,16 +1,34 @@
 use crate::binding_signature::{BindingSignature, BindingSigningKey, BindingVerificationKey};
-use crate::constant::TRANSACTION_BINDING_HASH_PERSONALIZATION;
+use crate::block::BlockProofBatch;
+use crate::circuit::vp_circuit::{VPVerifyingInfo, ValidityPredicatePublicInputs};
+use crate::constant::{
+    MAX_PTX_NUM, TRANSACTION_BINDING_HASH_PERSONALIZATION, TRANSACTION_ID_PERSONALIZATION,
+};
+use crate::cost::TxCostReport;
 use crate::error::TransactionError;
 use crate::executable::Executable;
+use crate::explorer::TxPublicView;
 use crate::merkle_tree::Anchor;
 use crate::note::NoteCommitment;
+use crate::note_encryption::EncryptedNote;
 use crate::nullifier::Nullifier;
 use crate::shielded_ptx::ShieldedPartialTransaction;
+use crate::simulation::{PtxPlan, TxPlan};
+use crate::state::{StateDiff, TaigaState};
 use crate::transparent_ptx::{OutputResource, TransparentPartialTransaction};
+use crate::utils::point_from_bytes;
 use crate::value_commitment::ValueCommitment;
+#[cfg(feature = "transcript")]
+use crate::verification_transcript::{BindingSignatureCheck, VerificationTranscript};
+use crate::vp_vk::ValidityPredicateVerifyingKey;
 use blake2b_simd::Params as Blake2bParams;
+use blake2s_simd::Params as Blake2sParams;
+use ff::PrimeField;
 use pasta_curves::{group::Group, pallas};
 use rand::{CryptoRng, RngCore};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 
 #[cfg(feature = "nif")]
 use rustler::{atoms, types::atom, Decoder, Env, NifRecord, NifResult, NifStruct, Term};
@@ -62,29 +80,159 @@ pub struct TransparentResult {
     pub outputs: Vec<OutputResource>,
 }
 
+/// The canonical transaction id, used for deduplication and replay detection.
+/// It commits to the anchors, nullifiers, output commitments and value commitments of the
+/// transaction. The proofs and the binding signature are excluded: they are randomized, so
+/// re-proving the same transfer must not change the id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransactionId([u8; 32]);
+
+/// The encoded anchors, nullifiers, output commitments and value commitments of the shielded
+/// bundle, then of the transparent bundle. Anyone holding it can recompute the transaction id
+/// without the proofs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionIdPreimage {
+    lists: [Vec<[u8; 32]>; 8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionIdError {
+    /// The hex string is not 64 characters long.
+    InvalidLength,
+    /// The string contains a non-hex character.
+    InvalidHex,
+}
+
+/// The nullifier is already revealed by a ptx of the bundle, or twice by the inserted ptx.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundleConflict {
+    pub nullifier: Nullifier,
+}
+
+/// The ledger data a transaction is executed against, the default context has no height and no
+/// timestamp.
+/// The vps of the height bound vks publish the height in a custom public input, e.g. the timelock
+/// vp, the executor checks it's the height of the context. Likewise the vps of the timestamp bound
+/// vks publish the time, e.g. the dutch auction intent vp, and all of them in the transaction must
+/// publish the timestamp of the context.
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionContext {
+    pub height: Option<u64>,
+    pub timestamp: Option<u64>,
+    // (compressed vk, index of the height in the public inputs)
+    height_bound_vks: Vec<(pallas::Base, usize)>,
+    // (compressed vk, index of the timestamp in the public inputs)
+    timestamp_bound_vks: Vec<(pallas::Base, usize)>,
+}
+
+impl ExecutionContext {
+    pub fn new(height: u64) -> Self {
+        Self {
+            height: Some(height),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    // Check the height published by the vps of the compressed vk at the public input index
+    pub fn bind_height(mut self, vp_vk: pallas::Base, public_input_idx: usize) -> Self {
+        self.height_bound_vks.push((vp_vk, public_input_idx));
+        self
+    }
+
+    // Check the timestamp published by the vps of the compressed vk at the public input index
+    pub fn bind_timestamp(mut self, vp_vk: pallas::Base, public_input_idx: usize) -> Self {
+        self.timestamp_bound_vks.push((vp_vk, public_input_idx));
+        self
+    }
+
+    fn check_height(&self, vp_info: &VPVerifyingInfo) -> Result<(), TransactionError> {
+        let public_input_idx = match Self::bound_public_input(&self.height_bound_vks, vp_info) {
+            Some(idx) => idx,
+            None => return Ok(()),
+        };
+        let height = self.height.ok_or(TransactionError::MissingHeight)?;
+        if vp_info.public_inputs.get_from_index(public_input_idx) != pallas::Base::from(height) {
+            return Err(TransactionError::HeightMismatch);
+        }
+        Ok(())
+    }
+
+    fn check_timestamp(&self, vp_info: &VPVerifyingInfo) -> Result<(), TransactionError> {
+        let public_input_idx = match Self::bound_public_input(&self.timestamp_bound_vks, vp_info) {
+            Some(idx) => idx,
+            None => return Ok(()),
+        };
+        let timestamp = self.timestamp.ok_or(TransactionError::MissingTimestamp)?;
+        if vp_info.public_inputs.get_from_index(public_input_idx) != pallas::Base::from(timestamp) {
+            return Err(TransactionError::TimestampMismatch);
+        }
+        Ok(())
+    }
+
+    // The public input index bound to the vk of the vp, if any
+    fn bound_public_input(
+        bound_vks: &[(pallas::Base, usize)],
+        vp_info: &VPVerifyingInfo,
+    ) -> Option<usize> {
+        if bound_vks.is_empty() {
+            return None;
+        }
+        let vp_vk = ValidityPredicateVerifyingKey::compress(&vp_info.vk);
+        bound_vks
+            .iter()
+            .find(|(vk, _)| *vk == vp_vk)
+            .map(|(_, idx)| *idx)
+    }
+}
+
 impl Transaction {
     // Generate the transaction
     pub fn build<R: RngCore + CryptoRng>(
         rng: R,
         shielded_ptx_bundle: ShieldedPartialTxBundle,
         transparent_ptx_bundle: TransparentPartialTxBundle,
-    ) -> Self {
-        assert!(!(shielded_ptx_bundle.is_empty() && transparent_ptx_bundle.is_empty()));
+    ) -> Result<Self, TransactionError> {
+        if shielded_ptx_bundle.is_empty() && transparent_ptx_bundle.is_empty() {
+            return Err(TransactionError::EmptyTransaction);
+        }
         let shielded_sk = shielded_ptx_bundle.get_bindig_sig_r();
         let transparent_sk = transparent_ptx_bundle.get_bindig_sig_r();
         let binding_sk = BindingSigningKey::from(shielded_sk + transparent_sk);
         let sig_hash = Self::digest(&shielded_ptx_bundle, &transparent_ptx_bundle);
         let signature = binding_sk.sign(rng, &sig_hash);
 
-        Self {
+        Ok(Self {
             shielded_ptx_bundle,
             transparent_ptx_bundle,
             signature,
-        }
+        })
     }
 
+    // Verify the proofs and the binding signature, and return the public data of the transaction.
+    // It doesn't check the anchors and nullifiers against the ledger state, see `apply`.
     #[allow(clippy::type_complexity)]
     pub fn execute(&self) -> Result<(ShieldedResult, TransparentResult), TransactionError> {
+        self.execute_with_context(&ExecutionContext::default())
+    }
+
+    /// Same as execute, the heights and timestamps published by the vps are also checked against
+    /// the context.
+    #[allow(clippy::type_complexity)]
+    pub fn execute_with_context(
+        &self,
+        ctx: &ExecutionContext,
+    ) -> Result<(ShieldedResult, TransparentResult), TransactionError> {
+        self.shielded_ptx_bundle.check_context(ctx)?;
+        self.check_duplicate_nullifiers()?;
+        check_duplicate_output_cms(&self.output_commitments())?;
+
         let shielded_result = self.shielded_ptx_bundle.execute()?;
         let transparent_result = self.transparent_ptx_bundle.execute()?;
 
@@ -94,12 +242,278 @@ impl Transaction {
         Ok((shielded_result, transparent_result))
     }
 
+    /// Verifies all the action and vp proofs and the binding signature. It doesn't need any
+    /// external state, so it can run in parallel before the transactions are sequenced.
+    pub fn verify_proofs(&self) -> Result<(), TransactionError> {
+        self.execute().map(|_| ())
+    }
+
+    // The checks of execute except the proofs
+    pub(crate) fn check_public_data(&self) -> Result<(), TransactionError> {
+        self.check_duplicate_nullifiers()?;
+        check_duplicate_output_cms(&self.output_commitments())?;
+        self.shielded_ptx_bundle.check_public_data()?;
+        self.transparent_ptx_bundle.execute()?;
+        self.verify_binding_sig()
+    }
+
+    /// The nullifiers, the output commitments and the note ciphertexts of the transaction, see
+    /// `TaigaState::apply_diff`.
+    pub fn state_diff(&self) -> StateDiff {
+        StateDiff {
+            tx_id: Some(self.id()),
+            new_commitments: self.output_commitments(),
+            spent_nullifiers: self.get_nullifiers(),
+            ciphertexts: self.ciphertexts(),
+        }
+    }
+
+    /// The note ciphertexts published in the transaction with the output note commitments. The
+    /// receivers trial-decrypt them with `IncomingViewingKey::decrypt`.
+    pub fn ciphertexts(&self) -> Vec<(NoteCommitment, EncryptedNote)> {
+        self.shielded_ptx_bundle.ciphertexts()
+    }
+
+    /// Checks the anchors and nullifiers against the state, then applies the state diff. The
+    /// proofs are not verified here, call `verify_proofs` first. The state is not touched if a
+    /// check fails.
+    pub fn apply(&self, state: &mut impl TaigaState) -> Result<StateDiff, TransactionError> {
+        self.check_duplicate_nullifiers()?;
+
+        for anchor in self.get_anchors() {
+            if !state.is_known_anchor(&anchor) {
+                return Err(TransactionError::UnknownAnchor);
+            }
+        }
+
+        let diff = self.state_diff();
+        if diff
+            .spent_nullifiers
+            .iter()
+            .any(|nf| state.contains_nullifier(nf))
+        {
+            return Err(TransactionError::NullifierAlreadySpent);
+        }
+
+        state.apply_diff(diff.clone())?;
+        Ok(diff)
+    }
+
+    /// Checks the plans of the shielded ptxs as `execute` and `apply` would, without any proof:
+    /// the nullifiers are unique, the values of every note type are balanced and, if the state is
+    /// provided, the anchors are known and the nullifiers are not spent.
+    pub fn dry_run(
+        ptx_plans: Vec<PtxPlan>,
+        state: Option<&dyn TaigaState>,
+    ) -> Result<TxPlan, TransactionError> {
+        if ptx_plans.is_empty() {
+            return Err(TransactionError::EmptyTransaction);
+        }
+        let plan = TxPlan::new(ptx_plans);
+
+        let mut nfs = HashSet::new();
+        if !plan.nullifiers().iter().all(|nf| nfs.insert(nf.to_bytes())) {
+            return Err(TransactionError::DuplicateNullifierWithinTx);
+        }
+
+        if let Some(balance) = plan.balances.iter().find(|balance| !balance.is_balanced()) {
+            return Err(TransactionError::UnbalancedNoteType {
+                note_type: balance.note_type,
+                input_value: balance.input_value,
+                output_value: balance.output_value,
+            });
+        }
+
+        if let Some(state) = state {
+            if !plan
+                .anchors()
+                .iter()
+                .all(|anchor| state.is_known_anchor(anchor))
+            {
+                return Err(TransactionError::UnknownAnchor);
+            }
+            if plan
+                .nullifiers()
+                .iter()
+                .any(|nf| state.contains_nullifier(nf))
+            {
+                return Err(TransactionError::NullifierAlreadySpent);
+            }
+        }
+
+        Ok(plan)
+    }
+
+    // A nullifier can only be revealed once in a transaction
+    fn check_duplicate_nullifiers(&self) -> Result<(), TransactionError> {
+        let mut nfs = HashSet::new();
+        for nf in self
+            .shielded_ptx_bundle
+            .get_nullifiers()
+            .iter()
+            .chain(self.transparent_ptx_bundle.get_nullifiers().iter())
+        {
+            if !nfs.insert(nf.to_bytes()) {
+                return Err(TransactionError::DuplicateNullifierWithinTx);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_shielded_ptx_bundle(&self) -> &ShieldedPartialTxBundle {
+        &self.shielded_ptx_bundle
+    }
+
+    // The nullifiers of the shielded ptxs, then the transparent ptxs
+    pub(crate) fn get_nullifiers(&self) -> Vec<Nullifier> {
+        self.shielded_ptx_bundle
+            .get_nullifiers()
+            .into_iter()
+            .chain(self.transparent_ptx_bundle.get_nullifiers())
+            .collect()
+    }
+
+    /// The output note commitments of the shielded ptxs, then the transparent ptxs. They are
+    /// appended to the commitment tree in this order.
+    pub fn output_commitments(&self) -> Vec<NoteCommitment> {
+        self.shielded_ptx_bundle
+            .get_output_cms()
+            .into_iter()
+            .chain(self.transparent_ptx_bundle.get_output_cms())
+            .collect()
+    }
+
+    pub(crate) fn get_anchors(&self) -> Vec<Anchor> {
+        self.shielded_ptx_bundle
+            .get_anchors()
+            .into_iter()
+            .chain(self.transparent_ptx_bundle.get_anchors())
+            .collect()
+    }
+
+    /// The serialized size and the estimated verification cost of the transaction.
+    pub fn cost_report(&self) -> TxCostReport {
+        let shielded_ptxs = self
+            .shielded_ptx_bundle
+            .0
+            .iter()
+            .map(|ptx| ptx.cost_report())
+            .collect();
+        let transparent_bytes = self
+            .transparent_ptx_bundle
+            .0
+            .iter()
+            .map(|ptx| ptx.serialized_size())
+            .sum();
+        // The two bundle length prefixes and the binding signature
+        let framing_bytes = 4 + 4 + 64;
+        TxCostReport::new(shielded_ptxs, transparent_bytes, framing_bytes)
+    }
+
+    pub fn id(&self) -> TransactionId {
+        self.id_preimage().id()
+    }
+
+    /// The public data committed by the transaction id.
+    pub fn id_preimage(&self) -> TransactionIdPreimage {
+        let shielded_bundle = &self.shielded_ptx_bundle;
+        let transparent_bundle = &self.transparent_ptx_bundle;
+        let lists = [
+            shielded_bundle
+                .get_anchors()
+                .iter()
+                .map(Anchor::to_bytes)
+                .collect(),
+            shielded_bundle
+                .get_nullifiers()
+                .iter()
+                .map(Nullifier::to_bytes)
+                .collect(),
+            shielded_bundle
+                .get_output_cms()
+                .iter()
+                .map(NoteCommitment::to_bytes)
+                .collect(),
+            shielded_bundle
+                .get_value_commitments()
+                .iter()
+                .map(ValueCommitment::to_bytes)
+                .collect(),
+            transparent_bundle
+                .get_anchors()
+                .iter()
+                .map(Anchor::to_bytes)
+                .collect(),
+            transparent_bundle
+                .get_nullifiers()
+                .iter()
+                .map(Nullifier::to_bytes)
+                .collect(),
+            transparent_bundle
+                .get_output_cms()
+                .iter()
+                .map(NoteCommitment::to_bytes)
+                .collect(),
+            transparent_bundle
+                .get_value_commitments()
+                .iter()
+                .map(ValueCommitment::to_bytes)
+                .collect(),
+        ];
+        TransactionIdPreimage { lists }
+    }
+
+    /// The public content of the shielded ptxs, for block explorers. The proofs are not touched.
+    pub fn public_view(&self) -> TxPublicView {
+        TxPublicView {
+            id: self.id(),
+            shielded_ptxs: self
+                .shielded_ptx_bundle
+                .0
+                .iter()
+                .map(|ptx| ptx.public_view())
+                .collect(),
+        }
+    }
+
+    /// Records the proof checks and the binding signature check of `verify_proofs`, see
+    /// `verification_transcript`. The transaction checks that don't involve curve operations(e.g.
+    /// the consistency of the action and vp public inputs) are not recorded.
+    #[cfg(feature = "transcript")]
+    pub fn verification_transcript(&self) -> VerificationTranscript {
+        let proofs = self
+            .shielded_ptx_bundle
+            .0
+            .iter()
+            .flat_map(|ptx| ptx.proof_checks())
+            .collect();
+        let message = Self::digest(&self.shielded_ptx_bundle, &self.transparent_ptx_bundle);
+        let binding_signature =
+            BindingSignatureCheck::new(&self.get_binding_vk(), message, &self.signature);
+        VerificationTranscript {
+            proofs,
+            binding_signature,
+        }
+    }
+
     fn verify_binding_sig(&self) -> Result<(), TransactionError> {
+        // The signature must be a valid encoding of (R, s)
+        let sig_bytes = self.signature.to_bytes();
+        let r_bytes: [u8; 32] = sig_bytes[..32].try_into().unwrap();
+        let s_bytes: [u8; 32] = sig_bytes[32..].try_into().unwrap();
+        if point_from_bytes(&r_bytes).is_err()
+            || bool::from(pallas::Scalar::from_repr(s_bytes).is_none())
+        {
+            return Err(TransactionError::BindingSignatureInvalid);
+        }
+
+        // A well-formed signature that doesn't verify means the value commitments are not balanced,
+        // or the signed data(e.g. the ptx payloads) was modified
         let binding_vk = self.get_binding_vk();
         let sig_hash = Self::digest(&self.shielded_ptx_bundle, &self.transparent_ptx_bundle);
         binding_vk
             .verify(&sig_hash, &self.signature)
-            .map_err(|_| TransactionError::InvalidBindingSignature)
+            .map_err(|_| TransactionError::ValueCommitmentImbalance)
     }
 
     fn get_binding_vk(&self) -> BindingVerificationKey {
@@ -160,10 +574,101 @@ impl Transaction {
             h.update(&anchor.to_bytes());
         });
 
+        // The application payloads of the shielded ptxs, a ptx without payload doesn't change the
+        // digest
+        shielded_bundle
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(_, ptx)| !ptx.get_extra_data().is_empty())
+            .for_each(|(i, ptx)| {
+                let extra_data = ptx.get_extra_data();
+                h.update(&(i as u64).to_le_bytes());
+                h.update(&(extra_data.len() as u64).to_le_bytes());
+                h.update(&extra_data);
+            });
+
         h.finalize().as_bytes().try_into().unwrap()
     }
 }
 
+impl TransactionIdPreimage {
+    pub fn id(&self) -> TransactionId {
+        let mut h = Blake2sParams::new()
+            .hash_length(32)
+            .personal(TRANSACTION_ID_PERSONALIZATION)
+            .to_state();
+        // Every list is prefixed with its length so the encoding is unambiguous
+        for list in self.lists.iter() {
+            h.update(&(list.len() as u64).to_le_bytes());
+            list.iter().for_each(|item| {
+                h.update(item);
+            });
+        }
+
+        TransactionId(h.finalize().as_bytes().try_into().unwrap())
+    }
+
+    // The output note commitments of the shielded ptxs, in the order of the actions
+    pub fn shielded_output_cms(&self) -> &[[u8; 32]] {
+        &self.lists[2]
+    }
+}
+
+impl TransactionId {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl fmt::Display for TransactionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl FromStr for TransactionId {
+    type Err = TransactionIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.as_bytes();
+        if s.len() != 64 {
+            return Err(TransactionIdError::InvalidLength);
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, chunk) in bytes.iter_mut().zip(s.chunks(2)) {
+            let chunk = std::str::from_utf8(chunk).map_err(|_| TransactionIdError::InvalidHex)?;
+            *byte = u8::from_str_radix(chunk, 16).map_err(|_| TransactionIdError::InvalidHex)?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for BundleConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "nullifier {:?} is already spent in the bundle",
+            self.nullifier.inner()
+        )
+    }
+}
+
+impl fmt::Display for TransactionIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionIdError::InvalidLength => {
+                f.write_str("transaction id must be 64 hex characters")
+            }
+            TransactionIdError::InvalidHex => f.write_str("transaction id is not valid hex"),
+        }
+    }
+}
+
 #[cfg(feature = "nif")]
 atoms! { transaction }
 
@@ -210,11 +715,32 @@ impl<'a> Decoder<'a> for Transaction {
     }
 }
 
+// An output note commitment can only be created once, the padding notes are included: their
+// rseeds are random, so the commitments are unique.
+fn check_duplicate_output_cms(cms: &[NoteCommitment]) -> Result<(), TransactionError> {
+    let mut indexes = HashMap::new();
+    for (second_index, cm) in cms.iter().enumerate() {
+        if let Some(&first_index) = indexes.get(&cm.to_bytes()) {
+            return Err(TransactionError::DuplicateOutputNote {
+                commitment: *cm,
+                first_index,
+                second_index,
+            });
+        }
+        indexes.insert(cm.to_bytes(), second_index);
+    }
+    Ok(())
+}
+
 impl ShieldedPartialTxBundle {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
 
+    pub fn partial_txs(&self) -> &[ShieldedPartialTransaction] {
+        &self.0
+    }
+
     pub fn get_bindig_sig_r(&self) -> pallas::Scalar {
         self.0.iter().fold(pallas::Scalar::zero(), |acc, ptx| {
             acc + ptx.get_binding_sig_r()
@@ -225,14 +751,76 @@ impl ShieldedPartialTxBundle {
         Self(partial_txs)
     }
 
+    /// Same as new, but rejects the bundles with more than MAX_PTX_NUM ptxs, whose value totals
+    /// may exceed MAX_TOTAL_VALUE, and the bundles creating an output note twice.
+    pub fn try_new(partial_txs: Vec<ShieldedPartialTransaction>) -> Result<Self, TransactionError> {
+        let bundle = Self(partial_txs);
+        bundle.check_ptx_num()?;
+        check_duplicate_output_cms(&bundle.get_output_cms())?;
+        Ok(bundle)
+    }
+
     pub fn add_partial_tx(&mut self, ptx: ShieldedPartialTransaction) {
         self.0.push(ptx);
     }
 
+    /// Add the ptx unless it spends a nullifier of the bundle. A copy of a ptx already in the
+    /// bundle is skipped.
+    pub fn try_insert(&mut self, ptx: ShieldedPartialTransaction) -> Result<(), BundleConflict> {
+        let hash = ptx.content_hash();
+        if self
+            .0
+            .iter()
+            .any(|existing| existing.content_hash() == hash)
+        {
+            return Ok(());
+        }
+        if let Some(nullifier) = self.conflicts_with(&ptx) {
+            return Err(BundleConflict { nullifier });
+        }
+        let mut nfs = HashSet::new();
+        if let Some(nullifier) = ptx
+            .get_nullifiers()
+            .into_iter()
+            .find(|nf| !nfs.insert(nf.to_bytes()))
+        {
+            return Err(BundleConflict { nullifier });
+        }
+        self.0.push(ptx);
+        Ok(())
+    }
+
+    /// The first nullifier of the ptx already revealed by the bundle.
+    pub fn conflicts_with(&self, other: &ShieldedPartialTransaction) -> Option<Nullifier> {
+        let nfs: HashSet<_> = self
+            .get_nullifiers()
+            .iter()
+            .map(|nf| nf.to_bytes())
+            .collect();
+        other
+            .get_nullifiers()
+            .into_iter()
+            .find(|nf| nfs.contains(&nf.to_bytes()))
+    }
+
+    /// Remove the copies of a ptx, the first one is kept.
+    pub fn dedup(&mut self) {
+        let mut hashes = HashSet::new();
+        self.0.retain(|ptx| hashes.insert(ptx.content_hash()));
+    }
+
+    fn check_ptx_num(&self) -> Result<(), TransactionError> {
+        if self.0.len() > MAX_PTX_NUM {
+            return Err(TransactionError::ValueOverflow);
+        }
+        Ok(())
+    }
+
     #[allow(clippy::type_complexity)]
     pub fn execute(&self) -> Result<ShieldedResult, TransactionError> {
-        for partial_tx in self.0.iter() {
-            partial_tx.execute()?;
+        self.check_ptx_num()?;
+        for (i, partial_tx) in self.0.iter().enumerate() {
+            partial_tx.execute().map_err(|e| e.with_ptx_index(i))?;
         }
 
         // Return Nullifiers to check double-spent, NoteCommitments to store, anchors to check the root-existence
@@ -243,6 +831,30 @@ impl ShieldedPartialTxBundle {
         })
     }
 
+    pub(crate) fn check_context(&self, ctx: &ExecutionContext) -> Result<(), TransactionError> {
+        self.0
+            .iter()
+            .flat_map(|ptx| ptx.get_vp_verifying_infos())
+            .try_for_each(|vp_info| {
+                ctx.check_height(vp_info)?;
+                ctx.check_timestamp(vp_info)
+            })
+    }
+
+    pub(crate) fn check_public_data(&self) -> Result<(), TransactionError> {
+        self.check_ptx_num()?;
+        for (i, partial_tx) in self.0.iter().enumerate() {
+            partial_tx
+                .check_public_data()
+                .map_err(|e| e.with_ptx_index(i))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_proofs_to_batch(&self, batch: &mut BlockProofBatch) {
+        self.0.iter().for_each(|ptx| ptx.add_proofs_to_batch(batch));
+    }
+
     pub fn get_value_commitments(&self) -> Vec<ValueCommitment> {
         self.0
             .iter()
@@ -262,6 +874,17 @@ impl ShieldedPartialTxBundle {
         self.0.iter().flat_map(|ptx| ptx.get_anchors()).collect()
     }
 
+    pub fn get_output_vp_public_inputs(&self) -> Vec<ValidityPredicatePublicInputs> {
+        self.0
+            .iter()
+            .flat_map(|ptx| ptx.get_output_vp_public_inputs())
+            .collect()
+    }
+
+    pub fn ciphertexts(&self) -> Vec<(NoteCommitment, EncryptedNote)> {
+        self.0.iter().flat_map(|ptx| ptx.ciphertexts()).collect()
+    }
+
     fn get_binding_vk(&self) -> BindingVerificationKey {
         let vk = self
             .get_value_commitments()
@@ -343,7 +966,7 @@ pub mod testing {
         let shielded_ptx_bundle = create_shielded_ptx_bundle(1);
         // TODO: add transparent_ptx_bundle test
         let transparent_ptx_bundle = TransparentPartialTxBundle::default();
-        let tx = Transaction::build(rng, shielded_ptx_bundle, transparent_ptx_bundle);
+        let tx = Transaction::build(rng, shielded_ptx_bundle, transparent_ptx_bundle).unwrap();
         let (_shielded_ret, _) = tx.execute().unwrap();
 
         #[cfg(feature = "borsh")]
@@ -354,4 +977,584 @@ pub mod testing {
             assert_eq!(_shielded_ret, de_shielded_ret);
         }
     }
+
+    // cargo test --release --features metrics -- --ignored test_repeated_tx_verification
+    #[cfg(feature = "metrics")]
+    #[test]
+    #[ignore]
+    fn test_repeated_tx_verification() {
+        use super::*;
+        use crate::metrics::{clear_metrics_sink, set_metrics_sink, ProvingMetrics, ProvingStage};
+        use crate::vk_cache::VerifierKeyCache;
+        use rand::rngs::OsRng;
+        use std::rc::Rc;
+        use std::time::Instant;
+
+        let tx = Transaction::build(
+            OsRng,
+            create_shielded_ptx_bundle(1),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+
+        let metrics = Rc::new(ProvingMetrics::new());
+        set_metrics_sink(metrics.clone());
+        // The first verification fills the cache
+        tx.execute().unwrap();
+        let cached_vks = VerifierKeyCache::len();
+        metrics.clear();
+
+        let start = Instant::now();
+        for _ in 1..50 {
+            tx.execute().unwrap();
+        }
+        println!("49 verifications: {:?}", start.elapsed());
+        clear_metrics_sink();
+
+        assert!(metrics.events_of(ProvingStage::Keygen).is_empty());
+        assert_eq!(VerifierKeyCache::len(), cached_vks);
+    }
+
+    #[test]
+    fn test_shielded_ptx_bundle_value_overflow() {
+        use super::*;
+        use crate::constant::MAX_PTX_NUM;
+        use crate::shielded_ptx::testing::create_shielded_ptx;
+
+        // The checks don't depend on the proofs, the same ptx is repeated
+        let ptx = create_shielded_ptx();
+        let bundle = ShieldedPartialTxBundle::try_new(vec![ptx.clone(); MAX_PTX_NUM]).unwrap();
+        assert_eq!(bundle.0.len(), MAX_PTX_NUM);
+
+        let ptxs = vec![ptx; MAX_PTX_NUM + 1];
+        assert!(matches!(
+            ShieldedPartialTxBundle::try_new(ptxs.clone()),
+            Err(TransactionError::ValueOverflow)
+        ));
+        assert!(matches!(
+            ShieldedPartialTxBundle::new(ptxs).execute(),
+            Err(TransactionError::ValueOverflow)
+        ));
+    }
+
+    #[test]
+    fn test_empty_transaction() {
+        use super::*;
+        use rand::rngs::OsRng;
+
+        assert!(matches!(
+            Transaction::build(
+                OsRng,
+                ShieldedPartialTxBundle::default(),
+                TransparentPartialTxBundle::default(),
+            ),
+            Err(TransactionError::EmptyTransaction)
+        ));
+    }
+
+    #[test]
+    fn test_transaction_cost_report() {
+        use super::*;
+        use crate::constant::NUM_NOTE;
+        use rand::rngs::OsRng;
+
+        let shielded_ptx_bundle = create_shielded_ptx_bundle(2);
+        let tx = Transaction::build(
+            OsRng,
+            shielded_ptx_bundle,
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        let report = tx.cost_report();
+        assert_eq!(report.shielded_ptxs.len(), 2);
+        assert_eq!(
+            report.total_size,
+            report
+                .shielded_ptxs
+                .iter()
+                .map(|ptx| ptx.size())
+                .sum::<usize>()
+                + report.transparent_bytes
+                + report.framing_bytes
+        );
+        assert_eq!(
+            report.scalar_muls,
+            report.shielded_ptxs.iter().map(|ptx| ptx.scalar_muls).sum()
+        );
+        assert!(report.estimated_verification_time > std::time::Duration::ZERO);
+        for ptx_report in report.shielded_ptxs.iter() {
+            assert_eq!(ptx_report.action_proof_sizes.len(), NUM_NOTE);
+            assert_eq!(
+                ptx_report.instance_counts.len(),
+                NUM_NOTE + ptx_report.vp_proof_sizes.len()
+            );
+        }
+
+        #[cfg(feature = "borsh")]
+        {
+            assert_eq!(report.total_size, borsh::to_vec(&tx).unwrap().len());
+            for (ptx, ptx_report) in tx.shielded_ptx_bundle.0.iter().zip(&report.shielded_ptxs) {
+                assert_eq!(ptx_report.size(), borsh::to_vec(ptx).unwrap().len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_transaction_id() {
+        use super::*;
+        use crate::shielded_ptx::testing::{create_shielded_ptx, set_action_public_data};
+        use halo2_proofs::arithmetic::Field;
+        use rand::rngs::OsRng;
+
+        let ptx = create_shielded_ptx();
+        let tx = Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(vec![ptx.clone()]),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        let id = tx.id();
+
+        // The id doesn't depend on the binding signature
+        let resigned_tx = Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(vec![ptx.clone()]),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        assert_eq!(resigned_tx.id(), id);
+
+        // Hex round trip
+        let hex = id.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse::<TransactionId>(), Ok(id));
+        assert_eq!(
+            hex[1..].parse::<TransactionId>(),
+            Err(TransactionIdError::InvalidLength)
+        );
+        assert_eq!(
+            hex.replacen(&hex[..1], "g", 1).parse::<TransactionId>(),
+            Err(TransactionIdError::InvalidHex)
+        );
+
+        #[cfg(feature = "borsh")]
+        {
+            let borsh = borsh::to_vec(&tx).unwrap();
+            let de_tx: Transaction = BorshDeserialize::deserialize(&mut borsh.as_ref()).unwrap();
+            assert_eq!(de_tx.id(), id);
+
+            let id_borsh = borsh::to_vec(&id).unwrap();
+            let de_id: TransactionId =
+                BorshDeserialize::deserialize(&mut id_borsh.as_ref()).unwrap();
+            assert_eq!(de_id, id);
+        }
+
+        // Changing a nullifier or an output commitment changes the id
+        let nf = ptx.get_nullifiers()[0];
+        let cm = ptx.get_output_cms()[0];
+        let mut tampered_tx = tx.clone();
+        set_action_public_data(
+            &mut tampered_tx.shielded_ptx_bundle.0[0],
+            0,
+            Nullifier::random(OsRng),
+            cm,
+        );
+        assert_ne!(tampered_tx.id(), id);
+        let mut tampered_tx = tx.clone();
+        set_action_public_data(
+            &mut tampered_tx.shielded_ptx_bundle.0[0],
+            0,
+            nf,
+            NoteCommitment::from(pallas::Base::random(OsRng)),
+        );
+        assert_ne!(tampered_tx.id(), id);
+    }
+
+    #[test]
+    fn test_transaction_extra_data() {
+        use super::*;
+        use crate::shielded_ptx::testing::create_shielded_ptx;
+        use rand::rngs::OsRng;
+
+        let mut ptx = create_shielded_ptx();
+        let extra_data = b"sell 2 btc for 10 eth".to_vec();
+        ptx.set_extra_data(extra_data.clone());
+        let tx = Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(vec![create_shielded_ptx(), ptx]),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        tx.execute().unwrap();
+        assert_eq!(tx.shielded_ptx_bundle.0[1].get_extra_data(), extra_data);
+
+        #[cfg(feature = "borsh")]
+        {
+            let borsh = borsh::to_vec(&tx).unwrap();
+            let de_tx: Transaction = BorshDeserialize::deserialize(&mut borsh.as_ref()).unwrap();
+            assert_eq!(de_tx.shielded_ptx_bundle.0[1].get_extra_data(), extra_data);
+            de_tx.execute().unwrap();
+        }
+
+        // Modify one byte of the payload
+        let mut tampered_tx = tx.clone();
+        let mut tampered_data = extra_data.clone();
+        tampered_data[0] ^= 1;
+        tampered_tx.shielded_ptx_bundle.0[1].set_extra_data(tampered_data);
+        assert!(tampered_tx.execute().is_err());
+
+        // Strip the payload
+        let mut stripped_tx = tx.clone();
+        stripped_tx.shielded_ptx_bundle.0[1].set_extra_data(vec![]);
+        assert!(stripped_tx.execute().is_err());
+
+        // Move the payload to another ptx
+        let mut moved_tx = tx;
+        moved_tx.shielded_ptx_bundle.0[1].set_extra_data(vec![]);
+        moved_tx.shielded_ptx_bundle.0[0].set_extra_data(extra_data);
+        assert!(moved_tx.execute().is_err());
+    }
+
+    #[test]
+    fn test_transaction_verify_and_apply() {
+        use super::*;
+        use crate::state::{CheckpointedState, StateError};
+        use rand::rngs::OsRng;
+
+        // The state accepts the anchors of the tx
+        struct KnownAnchorState {
+            anchors: Vec<Anchor>,
+            inner: CheckpointedState,
+        }
+
+        impl TaigaState for KnownAnchorState {
+            fn is_known_anchor(&self, anchor: &Anchor) -> bool {
+                self.anchors.contains(anchor)
+            }
+
+            fn contains_nullifier(&self, nf: &Nullifier) -> bool {
+                self.inner.contains_nullifier(nf)
+            }
+
+            fn insert_nullifier(&mut self, nf: Nullifier) -> Result<(), StateError> {
+                self.inner.insert_nullifier(nf)
+            }
+
+            fn append_commitment(&mut self, cm: NoteCommitment) -> Result<u64, StateError> {
+                self.inner.append(cm)
+            }
+
+            fn root(&self) -> Anchor {
+                self.inner.root()
+            }
+        }
+
+        let tx = Transaction::build(
+            OsRng,
+            create_shielded_ptx_bundle(1),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        tx.verify_proofs().unwrap();
+
+        // The proofs are valid but the anchors are unknown to the state
+        let mut state = CheckpointedState::default();
+        assert!(matches!(
+            tx.apply(&mut state),
+            Err(TransactionError::UnknownAnchor)
+        ));
+        assert_eq!(state.size(), 0);
+
+        // The tx is valid
+        let mut state = KnownAnchorState {
+            anchors: tx.shielded_ptx_bundle.get_anchors(),
+            inner: CheckpointedState::default(),
+        };
+        let diff = tx.apply(&mut state).unwrap();
+        assert_eq!(
+            diff.spent_nullifiers,
+            tx.shielded_ptx_bundle.get_nullifiers()
+        );
+        let output_cms = tx.shielded_ptx_bundle.get_output_cms();
+        assert_eq!(diff.new_commitments, output_cms);
+        assert!(diff
+            .spent_nullifiers
+            .iter()
+            .all(|nf| state.inner.contains_nullifier(nf)));
+        assert_eq!(state.inner.size(), 2);
+
+        // The notes are spent
+        assert!(matches!(
+            tx.apply(&mut state),
+            Err(TransactionError::NullifierAlreadySpent)
+        ));
+        assert_eq!(state.inner.size(), 2);
+    }
+
+    #[test]
+    fn test_transaction_value_commitment_imbalance() {
+        use super::*;
+        use crate::shielded_ptx::testing::create_unbalanced_shielded_ptx;
+        use rand::rngs::OsRng;
+
+        let shielded_ptx_bundle =
+            ShieldedPartialTxBundle::new(vec![create_unbalanced_shielded_ptx()]);
+        let tx = Transaction::build(
+            OsRng,
+            shielded_ptx_bundle,
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            tx.execute(),
+            Err(TransactionError::ValueCommitmentImbalance)
+        ));
+    }
+
+    #[test]
+    fn test_shielded_ptx_bundle_try_insert() {
+        use super::*;
+        use crate::shielded_ptx::testing::create_shielded_ptx;
+
+        let ptx = create_shielded_ptx();
+        let other_ptx = create_shielded_ptx();
+        let mut bundle = ShieldedPartialTxBundle::default();
+        bundle.try_insert(ptx.clone()).unwrap();
+        assert_eq!(bundle.conflicts_with(&other_ptx), None);
+
+        // The same ptx again is a no-op
+        bundle.try_insert(ptx.clone()).unwrap();
+        assert_eq!(bundle.0.len(), 1);
+
+        // A different ptx spending the same notes, the extra data changes the content hash
+        let mut conflicting_ptx = ptx.clone();
+        conflicting_ptx.set_extra_data(vec![1]);
+        assert_ne!(conflicting_ptx.content_hash(), ptx.content_hash());
+        let nf = ptx.get_nullifiers()[0];
+        assert_eq!(bundle.conflicts_with(&conflicting_ptx), Some(nf));
+        assert_eq!(
+            bundle.try_insert(conflicting_ptx),
+            Err(BundleConflict { nullifier: nf })
+        );
+
+        bundle.try_insert(other_ptx.clone()).unwrap();
+        assert_eq!(bundle.0.len(), 2);
+
+        // dedup keeps one copy of each ptx, in order
+        let mut bundle = ShieldedPartialTxBundle::new(vec![
+            ptx.clone(),
+            other_ptx.clone(),
+            ptx.clone(),
+            other_ptx,
+        ]);
+        bundle.dedup();
+        assert_eq!(bundle.0.len(), 2);
+        assert_eq!(bundle.0[0].content_hash(), ptx.content_hash());
+    }
+
+    #[test]
+    fn test_transaction_public_view() {
+        use super::*;
+        use crate::note::tests::random_note_type;
+        use crate::shielded_ptx::testing::create_trivial_swap_ptx;
+        use rand::rngs::OsRng;
+
+        let btc = random_note_type(OsRng);
+        let eth = random_note_type(OsRng);
+        let mut alice_ptx = create_trivial_swap_ptx((btc, 1), (eth, 10));
+        alice_ptx.set_extra_data(b"swap".to_vec());
+        let bob_ptx = create_trivial_swap_ptx((eth, 10), (btc, 1));
+        let tx = Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(vec![alice_ptx, bob_ptx]),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+
+        let view = tx.public_view();
+        assert_eq!(view.id, tx.id());
+        assert_eq!(view.shielded_ptxs.len(), 2);
+        assert_eq!(
+            view.nullifiers(),
+            tx.get_nullifiers()
+                .iter()
+                .map(Nullifier::to_bytes)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            view.output_cms(),
+            tx.shielded_ptx_bundle
+                .get_output_cms()
+                .iter()
+                .map(NoteCommitment::to_bytes)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(view.shielded_ptxs[0].extra_data, b"swap".to_vec());
+        assert!(view.shielded_ptxs[1].extra_data.is_empty());
+        // The trivial notes are not sent with an encrypted payload
+        assert!(view
+            .shielded_ptxs
+            .iter()
+            .flat_map(|ptx| ptx.actions.iter())
+            .all(|action| action.note_ciphertext.is_none()));
+
+        let text = view.to_string();
+        assert!(text.starts_with(&format!("transaction {}", tx.id())));
+        assert!(text.contains("extra_data: 73776170"));
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&view).unwrap();
+            let de_view: TxPublicView = serde_json::from_str(&json).unwrap();
+            assert_eq!(de_view, view);
+        }
+
+        #[cfg(feature = "borsh")]
+        {
+            use borsh::BorshDeserialize;
+            let bytes = borsh::to_vec(&view).unwrap();
+            let de_view = TxPublicView::deserialize(&mut bytes.as_ref()).unwrap();
+            assert_eq!(de_view, view);
+        }
+    }
+
+    #[test]
+    fn test_transaction_duplicate_nullifier() {
+        use super::*;
+        use crate::shielded_ptx::testing::create_shielded_ptx;
+        use rand::rngs::OsRng;
+
+        // The same ptx is included twice
+        let ptx = create_shielded_ptx();
+        let shielded_ptx_bundle = ShieldedPartialTxBundle::new(vec![ptx.clone(), ptx]);
+        let tx = Transaction::build(
+            OsRng,
+            shielded_ptx_bundle,
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            tx.execute(),
+            Err(TransactionError::DuplicateNullifierWithinTx)
+        ));
+    }
+
+    #[test]
+    fn test_transaction_duplicate_output_note() {
+        use super::*;
+        use crate::constant::NUM_NOTE;
+        use crate::shielded_ptx::testing::create_shielded_ptx;
+        use rand::rngs::OsRng;
+
+        // A swap creates unique output notes
+        let tx = Transaction::build(
+            OsRng,
+            create_shielded_ptx_bundle(1),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        assert_eq!(tx.output_commitments().len(), NUM_NOTE);
+        assert!(check_duplicate_output_cms(&tx.output_commitments()).is_ok());
+        tx.verify_proofs().unwrap();
+
+        // The same ptx is included twice, every output note is created twice
+        let ptx = create_shielded_ptx();
+        let cms = ptx.get_output_cms();
+        match ShieldedPartialTxBundle::try_new(vec![ptx.clone(), ptx.clone()]) {
+            Err(TransactionError::DuplicateOutputNote {
+                commitment,
+                first_index,
+                second_index,
+            }) => {
+                assert_eq!(commitment, cms[0]);
+                assert_eq!(first_index, 0);
+                assert_eq!(second_index, NUM_NOTE);
+            }
+            _ => panic!("the output notes should be duplicated"),
+        }
+
+        // The nullifiers are checked first in the transaction
+        let tx = Transaction::build(
+            OsRng,
+            ShieldedPartialTxBundle::new(vec![ptx.clone(), ptx]),
+            TransparentPartialTxBundle::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            check_duplicate_output_cms(&tx.output_commitments()),
+            Err(TransactionError::DuplicateOutputNote {
+                first_index: 0,
+                second_index: NUM_NOTE,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tx.verify_proofs(),
+            Err(TransactionError::DuplicateNullifierWithinTx)
+        ));
+    }
+
+    #[test]
+    fn test_transaction_dry_run() {
+        use super::*;
+        use crate::note::tests::random_note_type;
+        use crate::note::RandomSeed;
+        use crate::shielded_ptx::testing::create_trivial_swap_proving_info;
+        use crate::state::CheckpointedState;
+        use rand::rngs::OsRng;
+
+        let btc = random_note_type(OsRng);
+        let eth = random_note_type(OsRng);
+        let swap_plan = |sell, buy| {
+            let (input_info, output_info) =
+                create_trivial_swap_proving_info(sell, buy, &RandomSeed::random(OsRng));
+            ShieldedPartialTransaction::dry_run(&input_info, &output_info, false).unwrap()
+        };
+        let alice_plan = swap_plan((btc, 1), (eth, 10));
+        let bob_plan = swap_plan((eth, 10), (btc, 1));
+
+        let plan = Transaction::dry_run(vec![alice_plan.clone(), bob_plan.clone()], None).unwrap();
+        assert_eq!(plan.ptxs, vec![alice_plan.clone(), bob_plan.clone()]);
+        assert!(plan.balances.iter().all(|balance| balance.is_balanced()));
+        assert_eq!(plan.balances.len(), 2);
+
+        // The anchors are unknown to the state
+        let state = CheckpointedState::default();
+        assert!(matches!(
+            Transaction::dry_run(vec![alice_plan.clone(), bob_plan], Some(&state)),
+            Err(TransactionError::UnknownAnchor)
+        ));
+
+        // Bob only sells 9 eth
+        let cheap_bob_plan = swap_plan((eth, 9), (btc, 1));
+        match Transaction::dry_run(vec![alice_plan.clone(), cheap_bob_plan], None) {
+            Err(TransactionError::UnbalancedNoteType {
+                note_type,
+                input_value,
+                output_value,
+            }) => {
+                assert_eq!(note_type, eth);
+                assert_eq!(input_value, 9);
+                assert_eq!(output_value, 10);
+            }
+            _ => panic!("the eth values should be unbalanced"),
+        }
+
+        // The same ptx is included twice
+        assert!(matches!(
+            Transaction::dry_run(vec![alice_plan.clone(), alice_plan], None),
+            Err(TransactionError::DuplicateNullifierWithinTx)
+        ));
+        assert!(matches!(
+            Transaction::dry_run(vec![], None),
+            Err(TransactionError::EmptyTransaction)
+        ));
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&plan).unwrap();
+            let de_plan: TxPlan = serde_json::from_str(&json).unwrap();
+            assert_eq!(de_plan, plan);
+        }
+    }
 }
@@ -17,6 +17,13 @@ pub struct TransparentPartialTransaction {
     pub outputs: Vec<OutputResource>,
 }
 
+impl TransparentPartialTransaction {
+    // The length of the borsh encoding, the resource fields are still empty
+    pub(crate) fn serialized_size(&self) -> usize {
+        4 + self.inputs.len() * 4 + 4
+    }
+}
+
 impl Executable for TransparentPartialTransaction {
     fn execute(&self) -> Result<(), TransactionError> {
         // TODO: figure out how transparent ptx executes
@@ -1,11 +1,16 @@
-use crate::constant::{POSEIDON_TO_FIELD_U_0_POSTFIX, POSEIDON_TO_FIELD_U_1_POSTFIX};
-use halo2_gadgets::poseidon::primitives as poseidon;
+use crate::constant::{
+    POSEIDON_HASH_MAX_LEN, POSEIDON_RATE, POSEIDON_TO_FIELD_U_0_POSTFIX,
+    POSEIDON_TO_FIELD_U_1_POSTFIX,
+};
+use crate::error::PointEncodingError;
+use halo2_gadgets::poseidon::primitives::{self as poseidon, Domain, Spec};
 use halo2_proofs::arithmetic::CurveAffine;
 use pasta_curves::{
     arithmetic::CurveExt,
-    group::{ff::PrimeField, Curve},
+    group::{ff::PrimeField, Curve, Group, GroupEncoding},
     hashtocurve, pallas,
 };
+use std::marker::PhantomData;
 
 /// Converts from pallas::Base to pallas::Scalar (aka $x \pmod{r_\mathbb{P}}$).
 ///
@@ -15,6 +20,16 @@ pub(crate) fn mod_r_p(x: pallas::Base) -> pallas::Scalar {
     pallas::Scalar::from_repr(x.to_repr()).unwrap()
 }
 
+/// Overwrites the field element with zero.
+///
+/// The pasta field elements don't implement `Zeroize`, the volatile write keeps the compiler from
+/// optimizing the wipe away.
+pub(crate) fn zeroize_field<F: PrimeField>(x: &mut F) {
+    // Safety: x is a valid and aligned reference, and zero is a valid field element.
+    unsafe { core::ptr::write_volatile(x, F::ZERO) };
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
 /// Coordinate extractor for Pallas.
 ///
 /// Defined in [Zcash Protocol Spec § 5.4.9.7: Coordinate Extractor for Pallas][concreteextractorpallas].
@@ -42,11 +57,192 @@ pub fn poseidon_hash(left: pallas::Base, right: pallas::Base) -> pallas::Base {
         .hash([left, right])
 }
 
+// The ConstantLength sponge absorbs the message RATE elements at a time, it matches the
+// `poseidon_hash_gadget` of the same length.
 pub(crate) fn poseidon_hash_n<const L: usize>(message: [pallas::Base; L]) -> pallas::Base {
     poseidon::Hash::<_, poseidon::P128Pow5T3, poseidon::ConstantLength<L>, 3, 2>::init()
         .hash(message)
 }
 
+// The same hash as `poseidon_hash_n` when the message length is only known at runtime.
+// Panics if the message is empty or longer than POSEIDON_HASH_MAX_LEN.
+pub(crate) fn poseidon_hash_slice(message: &[pallas::Base]) -> pallas::Base {
+    macro_rules! hash_with_len {
+        ($($len:literal),*) => {
+            match message.len() {
+                $($len => poseidon_hash_n::<$len>(message.try_into().unwrap()),)*
+                len => panic!(
+                    "the poseidon message length {} is not in [1, {}]",
+                    len, POSEIDON_HASH_MAX_LEN
+                ),
+            }
+        };
+    }
+    hash_with_len!(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16)
+}
+
+/// The padding of the variable-length sponge: a one, then zeros up to a multiple of the rate.
+///
+/// The padding always adds at least one element, so messages that differ only by trailing zeros
+/// or ones don't collide.
+#[derive(Clone, Copy, Debug)]
+pub struct VariableLength;
+
+impl Domain<pallas::Base, POSEIDON_RATE> for VariableLength {
+    type Padding = Vec<pallas::Base>;
+
+    fn name() -> String {
+        "VariableLength".to_string()
+    }
+
+    // Not a multiple of 2^64, so it doesn't collide with the ConstantLength domains.
+    fn initial_capacity_element() -> pallas::Base {
+        pallas::Base::from(u64::MAX)
+    }
+
+    fn padding(input_len: usize) -> Self::Padding {
+        let zeros = (POSEIDON_RATE - (input_len + 1) % POSEIDON_RATE) % POSEIDON_RATE;
+        std::iter::once(pallas::Base::one())
+            .chain(std::iter::repeat(pallas::Base::zero()).take(zeros))
+            .collect()
+    }
+}
+
+/// The native duplex sponge, it gives the same outputs as `PoseidonSpongeGadget`.
+///
+/// The elements are absorbed as a single stream, how they are grouped into `absorb` calls doesn't
+/// matter. The padding of the domain `D` is applied on the first squeeze. Absorbing after a squeeze
+/// starts a new sponge that absorbs the last output first.
+#[derive(Clone, Debug)]
+pub struct PoseidonSponge<D: Domain<pallas::Base, POSEIDON_RATE> = VariableLength> {
+    state: [pallas::Base; 3],
+    // The absorbed elements not permuted in yet
+    buffer: Vec<pallas::Base>,
+    absorbed_len: usize,
+    // The outputs left to squeeze, in reverse order. None while absorbing.
+    outputs: Option<Vec<pallas::Base>>,
+    last_output: pallas::Base,
+    _domain: PhantomData<D>,
+}
+
+impl<D: Domain<pallas::Base, POSEIDON_RATE>> Default for PoseidonSponge<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Domain<pallas::Base, POSEIDON_RATE>> PoseidonSponge<D> {
+    pub fn new() -> Self {
+        Self {
+            state: [
+                pallas::Base::zero(),
+                pallas::Base::zero(),
+                D::initial_capacity_element(),
+            ],
+            buffer: vec![],
+            absorbed_len: 0,
+            outputs: None,
+            last_output: pallas::Base::zero(),
+            _domain: PhantomData,
+        }
+    }
+
+    pub fn absorb(&mut self, elements: &[pallas::Base]) {
+        if self.outputs.is_some() {
+            let last_output = self.last_output;
+            *self = Self::new();
+            self.absorb(&[last_output]);
+        }
+        for element in elements {
+            self.absorb_element(*element);
+        }
+        self.absorbed_len += elements.len();
+    }
+
+    pub fn squeeze(&mut self) -> pallas::Base {
+        let mut outputs = match self.outputs.take() {
+            Some(outputs) => outputs,
+            None => {
+                for padding in D::padding(self.absorbed_len) {
+                    self.absorb_element(padding);
+                }
+                self.permute_buffer();
+                self.rate_outputs()
+            }
+        };
+        let output = match outputs.pop() {
+            Some(output) => output,
+            None => {
+                poseidon_permute(&mut self.state);
+                outputs = self.rate_outputs();
+                outputs.pop().unwrap()
+            }
+        };
+        self.outputs = Some(outputs);
+        self.last_output = output;
+        output
+    }
+
+    // The in-circuit sponge permutes when a full rate is followed by another element
+    fn absorb_element(&mut self, element: pallas::Base) {
+        if self.buffer.len() == POSEIDON_RATE {
+            self.permute_buffer();
+        }
+        self.buffer.push(element);
+    }
+
+    fn permute_buffer(&mut self) {
+        for (word, element) in self.state.iter_mut().zip(self.buffer.drain(..)) {
+            *word += element;
+        }
+        poseidon_permute(&mut self.state);
+    }
+
+    fn rate_outputs(&self) -> Vec<pallas::Base> {
+        self.state[..POSEIDON_RATE].iter().rev().copied().collect()
+    }
+}
+
+// The P128Pow5T3 permutation, the same rounds as the Pow5 chip
+fn poseidon_permute(state: &mut [pallas::Base; 3]) {
+    type S = poseidon::P128Pow5T3;
+    let (round_constants, mds, _) = <S as Spec<pallas::Base, 3, POSEIDON_RATE>>::constants();
+    let half_full_rounds = <S as Spec<pallas::Base, 3, POSEIDON_RATE>>::full_rounds() / 2;
+    let partial_rounds = <S as Spec<pallas::Base, 3, POSEIDON_RATE>>::partial_rounds();
+    let sbox = <S as Spec<pallas::Base, 3, POSEIDON_RATE>>::sbox;
+
+    let apply_mds = |state: &mut [pallas::Base; 3]| {
+        let mut new_state = [pallas::Base::zero(); 3];
+        for (i, word) in new_state.iter_mut().enumerate() {
+            for (j, old) in state.iter().enumerate() {
+                *word += mds[i][j] * old;
+            }
+        }
+        *state = new_state;
+    };
+
+    let mut round_constants = round_constants.iter();
+    let full_round = |state: &mut [pallas::Base; 3], rcs: &[pallas::Base; 3]| {
+        for (word, rc) in state.iter_mut().zip(rcs.iter()) {
+            *word = sbox(*word + rc);
+        }
+        apply_mds(state);
+    };
+    for rcs in round_constants.by_ref().take(half_full_rounds) {
+        full_round(state, rcs);
+    }
+    for rcs in round_constants.by_ref().take(partial_rounds) {
+        for (word, rc) in state.iter_mut().zip(rcs.iter()) {
+            *word += rc;
+        }
+        state[0] = sbox(state[0]);
+        apply_mds(state);
+    }
+    for rcs in round_constants.take(half_full_rounds) {
+        full_round(state, rcs);
+    }
+}
+
 pub fn poseidon_to_curve<const L: usize>(message: &[pallas::Base]) -> pallas::Point {
     let us = poseidon_to_field::<L>(message);
     let q0 = hashtocurve::map_to_curve_simple_swu::<pallas::Base, pallas::Point, pallas::Iso>(
@@ -96,3 +292,177 @@ pub fn to_field_elements(bytes: &[u8]) -> Vec<pallas::Base> {
         })
         .collect::<Vec<pallas::Base>>()
 }
+
+// The u64 of the element, None if it doesn't fit
+pub fn to_u64(x: pallas::Base) -> Option<u64> {
+    let repr = x.to_repr();
+    if repr[8..].iter().all(|byte| *byte == 0) {
+        Some(u64::from_le_bytes(repr[..8].try_into().unwrap()))
+    } else {
+        None
+    }
+}
+
+// The native mirror of the comparison gadgets: a >= b iff a - b is a u64, for u64 a and b
+pub fn is_greater_or_equal_64(a: pallas::Base, b: pallas::Base) -> bool {
+    to_u64(a - b).is_some()
+}
+
+/// The canonical compressed encoding of the point: the x-coordinate with the sign of y in the
+/// top bit, all zeros for the identity. Every (de)serialization of a point goes through it.
+pub fn point_to_bytes(point: &pallas::Point) -> [u8; 32] {
+    point.to_bytes()
+}
+
+/// Decodes the canonical encoding of a point, the identity included.
+///
+/// Any other encoding is rejected, so a point has exactly one encoding and a transaction can't be
+/// malleated by re-encoding its points. Pallas has a prime order, every curve point is in the
+/// subgroup.
+pub fn point_from_bytes(bytes: &[u8; 32]) -> Result<pallas::Point, PointEncodingError> {
+    let mut x_bytes = *bytes;
+    x_bytes[31] &= 0x7f;
+    let x = Option::<pallas::Base>::from(pallas::Base::from_repr(x_bytes))
+        .ok_or(PointEncodingError::NonCanonical)?;
+    let point = Option::<pallas::Point>::from(pallas::Point::from_bytes(bytes)).ok_or(
+        // x = 0 is only the identity, with the sign bit unset
+        if x == pallas::Base::zero() {
+            PointEncodingError::NonCanonical
+        } else {
+            PointEncodingError::NotOnCurve
+        },
+    )?;
+    if point_to_bytes(&point) != *bytes {
+        return Err(PointEncodingError::NonCanonical);
+    }
+    Ok(point)
+}
+
+/// Decodes the canonical encoding of a point, the identity is rejected, e.g. for the keys.
+pub fn non_identity_point_from_bytes(
+    bytes: &[u8; 32],
+) -> Result<pallas::Point, PointEncodingError> {
+    let point = point_from_bytes(bytes)?;
+    if bool::from(point.is_identity()) {
+        return Err(PointEncodingError::Identity);
+    }
+    Ok(point)
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use ff::{FromUniformBytes, PrimeField};
+    use pasta_curves::pallas;
+    use proptest::prelude::*;
+
+    // Decodes the hex of the canonical little-endian encoding, for the fixed test vectors
+    pub fn base_from_hex(hex: &str) -> pallas::Base {
+        assert_eq!(hex.len(), 64);
+        let mut repr = [0u8; 32];
+        for (byte, i) in repr.iter_mut().zip((0..64).step_by(2)) {
+            *byte = u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        }
+        pallas::Base::from_repr(repr).unwrap()
+    }
+
+    // Reduces 64 arbitrary bytes, so the elements are uniform
+    fn from_wide_bytes<F: FromUniformBytes<64>>(bytes: ([u8; 32], [u8; 32])) -> F {
+        let mut wide = [0u8; 64];
+        wide[..32].copy_from_slice(&bytes.0);
+        wide[32..].copy_from_slice(&bytes.1);
+        F::from_uniform_bytes(&wide)
+    }
+
+    pub fn arb_base() -> impl Strategy<Value = pallas::Base> {
+        (any::<[u8; 32]>(), any::<[u8; 32]>()).prop_map(from_wide_bytes)
+    }
+
+    pub fn arb_scalar() -> impl Strategy<Value = pallas::Scalar> {
+        (any::<[u8; 32]>(), any::<[u8; 32]>()).prop_map(from_wide_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    // The smallest x with a curve point, and the smallest one without
+    fn small_x(on_curve: bool) -> [u8; 32] {
+        (1u64..)
+            .map(|x| pallas::Base::from(x).to_repr())
+            .find(|bytes| bool::from(pallas::Point::from_bytes(bytes).is_some()) == on_curve)
+            .unwrap()
+    }
+
+    // The little-endian sum of the encodings, without reduction
+    fn add_bytes(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+        let mut sum = [0u8; 32];
+        let mut carry = 0u16;
+        for i in 0..32 {
+            let byte = a[i] as u16 + b[i] as u16 + carry;
+            sum[i] = byte as u8;
+            carry = byte >> 8;
+        }
+        sum
+    }
+
+    #[test]
+    fn test_point_encoding() {
+        let point = pallas::Point::random(OsRng);
+        let bytes = point_to_bytes(&point);
+        assert_eq!(point_from_bytes(&bytes), Ok(point));
+        assert_eq!(non_identity_point_from_bytes(&bytes), Ok(point));
+
+        // The identity
+        let identity = pallas::Point::identity();
+        assert_eq!(point_to_bytes(&identity), [0u8; 32]);
+        assert_eq!(point_from_bytes(&[0u8; 32]), Ok(identity));
+        assert_eq!(
+            non_identity_point_from_bytes(&[0u8; 32]),
+            Err(PointEncodingError::Identity)
+        );
+
+        // The identity with the sign bit set
+        let mut signed_identity = [0u8; 32];
+        signed_identity[31] = 0x80;
+        assert_eq!(
+            point_from_bytes(&signed_identity),
+            Err(PointEncodingError::NonCanonical)
+        );
+
+        // Off the curve
+        assert_eq!(
+            point_from_bytes(&small_x(false)),
+            Err(PointEncodingError::NotOnCurve)
+        );
+
+        // The x-coordinate is not reduced
+        assert_eq!(
+            point_from_bytes(&[0xff; 32]),
+            Err(PointEncodingError::NonCanonical)
+        );
+    }
+
+    #[test]
+    fn test_point_encoding_malleation() {
+        // x and x + p encode the same point, only x is accepted
+        let bytes = small_x(true);
+        let point = point_from_bytes(&bytes).unwrap();
+        let p_minus_one = (-pallas::Base::one()).to_repr();
+        let x_plus_p = add_bytes(
+            &add_bytes(&bytes, &p_minus_one),
+            &pallas::Base::one().to_repr(),
+        );
+        assert_ne!(x_plus_p, bytes);
+        assert_eq!(
+            point_from_bytes(&x_plus_p),
+            Err(PointEncodingError::NonCanonical)
+        );
+
+        // Both signs of y decode, to the point and its negation
+        let mut negated = bytes;
+        negated[31] ^= 0x80;
+        assert_eq!(point_from_bytes(&negated), Ok(-point));
+    }
+}
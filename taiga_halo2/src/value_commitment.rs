@@ -1,12 +1,15 @@
 use crate::constant::NOTE_COMMITMENT_R_GENERATOR;
+use crate::error::PointEncodingError;
 use crate::note::Note;
+use crate::utils::{point_from_bytes, point_to_bytes};
 use halo2_proofs::arithmetic::CurveAffine;
 use pasta_curves::group::cofactor::CofactorCurveAffine;
-use pasta_curves::group::{Curve, Group, GroupEncoding};
+use pasta_curves::group::{Curve, Group};
 use pasta_curves::pallas;
 #[cfg(feature = "nif")]
 use rustler::NifTuple;
-use subtle::CtOption;
+use std::iter::Sum;
+use std::ops::{Add, Sub};
 
 #[cfg(feature = "serde")]
 use serde;
@@ -43,15 +46,105 @@ impl ValueCommitment {
         }
     }
 
+    // The inverse of (get_x, get_y), (0, 0) is the identity
+    pub fn from_xy(x: pallas::Base, y: pallas::Base) -> Option<Self> {
+        if x == pallas::Base::zero() && y == pallas::Base::zero() {
+            return Some(ValueCommitment(pallas::Point::identity()));
+        }
+        Option::<pallas::Affine>::from(pallas::Affine::from_xy(x, y))
+            .map(|point| ValueCommitment(point.to_curve()))
+    }
+
     pub fn inner(&self) -> pallas::Point {
         self.0
     }
 
     pub fn to_bytes(&self) -> [u8; 32] {
-        self.0.to_bytes()
+        point_to_bytes(&self.0)
+    }
+
+    // The identity is the commitment of balanced values with a zero blind, it's accepted
+    pub fn from_bytes(bytes: [u8; 32]) -> Result<ValueCommitment, PointEncodingError> {
+        point_from_bytes(&bytes).map(ValueCommitment)
+    }
+
+    /// Whether the aggregated commitment only commits to the blinding, i.e. the values are
+    /// balanced.
+    pub fn is_binding_to_zero(&self, total_blind: &pallas::Scalar) -> bool {
+        self.0 == NOTE_COMMITMENT_R_GENERATOR.to_curve() * total_blind
     }
+}
+
+impl Add for ValueCommitment {
+    type Output = ValueCommitment;
 
-    pub fn from_bytes(bytes: [u8; 32]) -> CtOption<ValueCommitment> {
-        pallas::Point::from_bytes(&bytes).map(ValueCommitment)
+    fn add(self, rhs: ValueCommitment) -> ValueCommitment {
+        ValueCommitment(self.0 + rhs.0)
     }
 }
+
+impl Sub for ValueCommitment {
+    type Output = ValueCommitment;
+
+    fn sub(self, rhs: ValueCommitment) -> ValueCommitment {
+        ValueCommitment(self.0 - rhs.0)
+    }
+}
+
+impl Sum for ValueCommitment {
+    fn sum<I: Iterator<Item = ValueCommitment>>(iter: I) -> Self {
+        iter.fold(ValueCommitment(pallas::Point::identity()), |acc, cv| {
+            acc + cv
+        })
+    }
+}
+
+impl<'a> Sum<&'a ValueCommitment> for ValueCommitment {
+    fn sum<I: Iterator<Item = &'a ValueCommitment>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
+#[test]
+fn test_value_commitment_arithmetic() {
+    use crate::note::tests::random_input_note;
+    use halo2_proofs::arithmetic::Field;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let note_a = random_input_note(&mut rng);
+    let note_b = random_input_note(&mut rng);
+    let blind_1 = pallas::Scalar::random(&mut rng);
+    let blind_2 = pallas::Scalar::random(&mut rng);
+
+    // a -> b and b -> a cancel out
+    let cv_1 = ValueCommitment::new(&note_a, &note_b, &blind_1);
+    let cv_2 = ValueCommitment::new(&note_b, &note_a, &blind_2);
+    let total = [cv_1, cv_2].iter().sum::<ValueCommitment>();
+    assert!(total.is_binding_to_zero(&(blind_1 + blind_2)));
+    assert!(!total.is_binding_to_zero(&blind_1));
+    assert!(!cv_1.is_binding_to_zero(&blind_1));
+    assert_eq!((total - cv_2).inner(), cv_1.inner());
+}
+
+#[test]
+fn test_value_commitment_encoding() {
+    use rand::rngs::OsRng;
+
+    let cv = ValueCommitment(pallas::Point::random(OsRng));
+    let decoded = ValueCommitment::from_bytes(cv.to_bytes()).unwrap();
+    assert_eq!(decoded.inner(), cv.inner());
+
+    let identity = ValueCommitment::from_bytes([0u8; 32]).unwrap();
+    assert_eq!(identity.inner(), pallas::Point::identity());
+    let mut signed_identity = [0u8; 32];
+    signed_identity[31] = 0x80;
+    assert_eq!(
+        ValueCommitment::from_bytes(signed_identity).unwrap_err(),
+        PointEncodingError::NonCanonical
+    );
+    assert_eq!(
+        ValueCommitment::from_bytes([0xff; 32]).unwrap_err(),
+        PointEncodingError::NonCanonical
+    );
+}
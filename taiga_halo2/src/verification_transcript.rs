@@ -0,0 +1,294 @@
+//! A deterministic record of the checks done when verifying a transaction.
+//!
+//! A chain that can't run the pasta curve arithmetic (e.g. a BN254 chain) can hand the transcript
+//! to an external prover system, which re-verifies or wraps it. halo2 doesn't expose the final
+//! MSM of the IPA verifier, so a proof check records the vk, the instances and the proof, and the
+//! binding signature check records the verification key, the message and the signature.
+//!
+//! The action proofs are replayed under the action vks of the crate whatever vk the transcript
+//! records, and the application vp vks must be the app_vks published by the actions. The dynamic
+//! vps are only bound through the hiding vp commitments, which the transcript can't open.
+use crate::binding_signature::{BindingSignature, BindingVerificationKey};
+use crate::circuit::vp_examples::TrivialValidityPredicateCircuit;
+use crate::constant::{
+    get_params, ACTION_CIRCUIT_PARAMS_SIZE, ACTION_INPUT_APP_VK_ROW_IDX,
+    ACTION_OUTPUT_APP_VK_ROW_IDX, ACTION_PUBLIC_INPUT_NUM, ACTION_VERIFYING_KEY,
+    AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE, AGGREGATED_ACTION_VERIFYING_KEY, VP_CIRCUIT_PARAMS_SIZE,
+};
+use crate::proof::Proof;
+use crate::vp_vk::ValidityPredicateVerifyingKey;
+use borsh::{BorshDeserialize, BorshSerialize};
+use halo2_proofs::plonk::VerifyingKey;
+use lazy_static::lazy_static;
+use pasta_curves::{group::ff::PrimeField, pallas, vesta};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub enum ProofKind {
+    Action,
+    // The vp under the app_vk of the note
+    ApplicationValidityPredicate,
+    DynamicValidityPredicate,
+    // The one action proof of a ptx built with build_aggregated
+    AggregatedAction,
+}
+
+lazy_static! {
+    static ref ACTION_VK_BYTES: Vec<u8> = vk_to_bytes(&ACTION_VERIFYING_KEY);
+    static ref AGGREGATED_ACTION_VK_BYTES: Vec<u8> = vk_to_bytes(&AGGREGATED_ACTION_VERIFYING_KEY);
+}
+
+fn vk_to_bytes(vk: &VerifyingKey<vesta::Affine>) -> Vec<u8> {
+    let mut bytes = vec![];
+    vk.write(&mut bytes).unwrap();
+    bytes
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct ProofCheck {
+    pub kind: ProofKind,
+    // The serialized vk
+    pub vk: Vec<u8>,
+    // The canonical encodings of the instances
+    pub instances: Vec<[u8; 32]>,
+    pub proof: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct BindingSignatureCheck {
+    // The sum of the value commitments
+    pub vk: [u8; 32],
+    // The transaction digest
+    pub message: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+/// The proof checks in the verification order: the action proofs of a ptx, then its vp proofs
+/// note by note, the input notes first. The vps of a note are its application vp followed by its
+/// dynamic vps.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct VerificationTranscript {
+    pub proofs: Vec<ProofCheck>,
+    pub binding_signature: BindingSignatureCheck,
+}
+
+impl ProofCheck {
+    pub fn new(
+        kind: ProofKind,
+        vk: &VerifyingKey<vesta::Affine>,
+        instances: &[pallas::Base],
+        proof: &Proof,
+    ) -> Self {
+        Self {
+            kind,
+            vk: vk_to_bytes(vk),
+            instances: instances
+                .iter()
+                .map(|instance| instance.to_repr())
+                .collect(),
+            proof: proof.inner(),
+        }
+    }
+
+    fn verify(&self) -> bool {
+        match self.kind {
+            // The action vks are pinned, the recorded vk is only compared against them
+            ProofKind::Action => {
+                self.vk == *ACTION_VK_BYTES
+                    && self.verify_with(&ACTION_VERIFYING_KEY, ACTION_CIRCUIT_PARAMS_SIZE)
+            }
+            ProofKind::AggregatedAction => {
+                self.vk == *AGGREGATED_ACTION_VK_BYTES
+                    && self.verify_with(
+                        &AGGREGATED_ACTION_VERIFYING_KEY,
+                        AGGREGATED_ACTION_CIRCUIT_PARAMS_SIZE,
+                    )
+            }
+            // The halo2 vps share the config of the trivial vp
+            ProofKind::ApplicationValidityPredicate | ProofKind::DynamicValidityPredicate => self
+                .vp_vk()
+                .map_or(false, |vk| self.verify_with(&vk, VP_CIRCUIT_PARAMS_SIZE)),
+        }
+    }
+
+    fn vp_vk(&self) -> Option<VerifyingKey<vesta::Affine>> {
+        let params = &get_params(VP_CIRCUIT_PARAMS_SIZE);
+        VerifyingKey::from_bytes::<TrivialValidityPredicateCircuit>(&self.vk, params).ok()
+    }
+
+    fn verify_with(&self, vk: &VerifyingKey<vesta::Affine>, params_size: u32) -> bool {
+        let params = &get_params(params_size);
+        self.instances().map_or(false, |instances| {
+            Proof::new(self.proof.clone())
+                .verify(vk, params, &[&instances])
+                .is_ok()
+        })
+    }
+
+    fn instances(&self) -> Option<Vec<pallas::Base>> {
+        self.instances
+            .iter()
+            .map(|bytes| Option::from(pallas::Base::from_repr(*bytes)))
+            .collect()
+    }
+
+    // The app_vks published by the action proof, the ones of the input notes then the ones of the
+    // output notes
+    fn app_vks(&self) -> Option<(Vec<pallas::Base>, Vec<pallas::Base>)> {
+        let instances = self.instances()?;
+        if instances.len() % ACTION_PUBLIC_INPUT_NUM != 0 {
+            return None;
+        }
+        Some(
+            instances
+                .chunks(ACTION_PUBLIC_INPUT_NUM)
+                .map(|action| {
+                    (
+                        action[ACTION_INPUT_APP_VK_ROW_IDX],
+                        action[ACTION_OUTPUT_APP_VK_ROW_IDX],
+                    )
+                })
+                .unzip(),
+        )
+    }
+}
+
+impl BindingSignatureCheck {
+    pub fn new(
+        vk: &BindingVerificationKey,
+        message: [u8; 32],
+        signature: &BindingSignature,
+    ) -> Self {
+        Self {
+            vk: vk.to_bytes(),
+            message,
+            signature: signature.to_bytes(),
+        }
+    }
+
+    fn verify(&self) -> bool {
+        BindingVerificationKey::from_bytes(self.vk).map_or(false, |vk| {
+            vk.verify(&self.message, &BindingSignature::from_bytes(self.signature))
+                .is_ok()
+        })
+    }
+}
+
+// Checks the application vps of every ptx are under the app_vks published by its actions, in the
+// order of the notes.
+fn check_app_vks(proofs: &[ProofCheck]) -> bool {
+    let mut proofs = proofs.iter().peekable();
+    while proofs.peek().is_some() {
+        let mut input_app_vks = vec![];
+        let mut output_app_vks = vec![];
+        while let Some(action) = proofs
+            .next_if(|proof| matches!(proof.kind, ProofKind::Action | ProofKind::AggregatedAction))
+        {
+            match action.app_vks() {
+                Some((input, output)) => {
+                    input_app_vks.extend(input);
+                    output_app_vks.extend(output);
+                }
+                None => return false,
+            }
+        }
+
+        let mut app_vks = input_app_vks.into_iter().chain(output_app_vks);
+        while let Some(vp) = proofs.next_if(|proof| {
+            matches!(
+                proof.kind,
+                ProofKind::ApplicationValidityPredicate | ProofKind::DynamicValidityPredicate
+            )
+        }) {
+            if vp.kind == ProofKind::DynamicValidityPredicate {
+                continue;
+            }
+            let bound = app_vks
+                .next()
+                .zip(vp.vp_vk())
+                .map_or(false, |(app_vk, vk)| {
+                    ValidityPredicateVerifyingKey::compress(&vk) == app_vk
+                });
+            if !bound {
+                return false;
+            }
+        }
+        // Every note has its application vp
+        if app_vks.next().is_some() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Replays all the checks of the transcript.
+pub fn replay_verify(transcript: &VerificationTranscript) -> bool {
+    check_app_vks(&transcript.proofs)
+        && transcript.proofs.iter().all(|proof| proof.verify())
+        && transcript.binding_signature.verify()
+}
+
+#[test]
+fn test_verification_transcript() {
+    use crate::circuit::vp_circuit::ValidityPredicateVerifyingInfo;
+    use crate::circuit::vp_examples::{
+        tests::random_trivial_vp_circuit, TrivialValidityPredicateParams,
+    };
+    use crate::transaction::TransparentPartialTxBundle;
+    use crate::transaction::{testing::create_shielded_ptx_bundle, Transaction};
+    use rand::rngs::OsRng;
+
+    let tx = Transaction::build(
+        OsRng,
+        create_shielded_ptx_bundle(1),
+        TransparentPartialTxBundle::default(),
+    )
+    .unwrap();
+    let transcript = tx.verification_transcript();
+    assert!(replay_verify(&transcript));
+
+    // Deterministic for a fixed transaction
+    assert_eq!(transcript, tx.verification_transcript());
+    let bytes = borsh::to_vec(&transcript).unwrap();
+    let decoded: VerificationTranscript =
+        BorshDeserialize::deserialize(&mut bytes.as_ref()).unwrap();
+    assert!(replay_verify(&decoded));
+
+    // A modified instance fails the proof check
+    let mut bad_transcript = transcript.clone();
+    bad_transcript.proofs[0].instances[0] = pallas::Base::one().to_repr();
+    assert!(!replay_verify(&bad_transcript));
+
+    // A valid proof under a substituted vk is rejected, for the actions and the application vps
+    let vp_info = random_trivial_vp_circuit(OsRng)
+        .with_params(TrivialValidityPredicateParams {
+            extra_rows: 10,
+            ..Default::default()
+        })
+        .get_verifying_info();
+    let substituted = ProofCheck::new(
+        ProofKind::ApplicationValidityPredicate,
+        &vp_info.vk,
+        &vp_info.public_inputs.to_instance(),
+        &vp_info.proof,
+    );
+    assert!(substituted.verify());
+    let mut bad_transcript = transcript.clone();
+    bad_transcript.proofs[0] = ProofCheck {
+        kind: ProofKind::Action,
+        ..substituted.clone()
+    };
+    assert!(!replay_verify(&bad_transcript));
+    let app_vp_idx = transcript
+        .proofs
+        .iter()
+        .position(|proof| proof.kind == ProofKind::ApplicationValidityPredicate)
+        .unwrap();
+    let mut bad_transcript = transcript.clone();
+    bad_transcript.proofs[app_vp_idx] = substituted;
+    assert!(!replay_verify(&bad_transcript));
+
+    // A modified message fails the binding signature check
+    let mut bad_transcript = transcript;
+    bad_transcript.binding_signature.message[0] ^= 1;
+    assert!(!replay_verify(&bad_transcript));
+}
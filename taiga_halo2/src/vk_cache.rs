@@ -0,0 +1,134 @@
+/// A process-wide cache of the verifying keys, keyed by the compressed vk.
+/// Compressing a vk hashes its whole pinned description, so the verifier looks the app_vk of a
+/// note up in the cache and verifies the application vp proof under the cached vk instead of
+/// compressing the attached one. The built-in vps are cached at first use, the other vks are
+/// cached once their compression has been checked.
+use crate::circuit::vp_examples::TRIVIAL_VP_VK;
+use ff::PrimeField;
+use halo2_proofs::plonk::VerifyingKey;
+use lazy_static::lazy_static;
+use pasta_curves::{pallas, vesta};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// The cache stops growing beyond this, the vks are then compressed on every verification
+pub const MAX_CACHED_VKS: usize = 1024;
+
+type VkMap = HashMap<[u8; 32], Arc<VerifyingKey<vesta::Affine>>>;
+
+lazy_static! {
+    static ref VERIFIER_KEY_CACHE: RwLock<VkMap> = RwLock::new(builtin_vks());
+}
+
+fn builtin_vks() -> VkMap {
+    #[allow(unused_mut)]
+    let mut vks = vec![&*TRIVIAL_VP_VK];
+    #[cfg(feature = "examples")]
+    {
+        use crate::circuit::vp_examples::{
+            balance::BALANCE_VK,
+            bridge_vp::BRIDGE_VK,
+            cascade_intent::CASCADE_INTENT_VK,
+            counter::COUNTER_VK,
+            dutch_auction_intent::DUTCH_AUCTION_INTENT_VK,
+            multisig_authorization::MULTISIG_AUTH_VK,
+            nft::NFT_VK,
+            or_relation_intent::OR_RELATION_INTENT_VK,
+            partial_fulfillment_intent::PARTIAL_FULFILLMENT_INTENT_VK,
+            receiver_vp::RECEIVER_VK,
+            signature_verification::{LEGACY_TOKEN_AUTH_VK, TOKEN_AUTH_VK},
+            spending_cap::SPENDING_CAP_VK,
+            token::TOKEN_VK,
+            token_issuance::TOKEN_ISSUANCE_VK,
+        };
+        vks.extend([
+            &*BALANCE_VK,
+            &*BRIDGE_VK,
+            &*CASCADE_INTENT_VK,
+            &*COUNTER_VK,
+            &*DUTCH_AUCTION_INTENT_VK,
+            &*MULTISIG_AUTH_VK,
+            &*NFT_VK,
+            &*OR_RELATION_INTENT_VK,
+            &*PARTIAL_FULFILLMENT_INTENT_VK,
+            &*RECEIVER_VK,
+            &*TOKEN_AUTH_VK,
+            &*LEGACY_TOKEN_AUTH_VK,
+            &*SPENDING_CAP_VK,
+            &*TOKEN_VK,
+            &*TOKEN_ISSUANCE_VK,
+        ]);
+    }
+    vks.into_iter()
+        .map(|vp_vk| {
+            (
+                vp_vk.get_compressed().to_repr(),
+                vp_vk.vk().unwrap().clone(),
+            )
+        })
+        .collect()
+}
+
+pub struct VerifierKeyCache;
+
+impl VerifierKeyCache {
+    pub fn get(compressed: &pallas::Base) -> Option<Arc<VerifyingKey<vesta::Affine>>> {
+        VERIFIER_KEY_CACHE
+            .read()
+            .unwrap()
+            .get(&compressed.to_repr())
+            .cloned()
+    }
+
+    /// Returns the cached vk of `compressed`, caching the one of `vk` if it's missing. The caller
+    /// must check that the vk compresses to `compressed`.
+    pub fn get_or_insert(
+        compressed: pallas::Base,
+        vk: impl FnOnce() -> VerifyingKey<vesta::Affine>,
+    ) -> Arc<VerifyingKey<vesta::Affine>> {
+        if let Some(cached) = Self::get(&compressed) {
+            return cached;
+        }
+        let mut cache = VERIFIER_KEY_CACHE.write().unwrap();
+        if cache.len() >= MAX_CACHED_VKS {
+            return cache
+                .get(&compressed.to_repr())
+                .cloned()
+                .unwrap_or_else(|| Arc::new(vk()));
+        }
+        cache
+            .entry(compressed.to_repr())
+            .or_insert_with(|| Arc::new(vk()))
+            .clone()
+    }
+
+    pub fn len() -> usize {
+        VERIFIER_KEY_CACHE.read().unwrap().len()
+    }
+
+    pub fn is_empty() -> bool {
+        Self::len() == 0
+    }
+}
+
+#[test]
+fn test_verifier_key_cache() {
+    use crate::circuit::vp_examples::COMPRESSED_TRIVIAL_VP_VK;
+    use crate::vp_vk::ValidityPredicateVerifyingKey;
+    use halo2_proofs::arithmetic::Field;
+    use rand::rngs::OsRng;
+
+    // The built-in vks are cached
+    let trivial_vk = VerifierKeyCache::get(&COMPRESSED_TRIVIAL_VP_VK).unwrap();
+    assert_eq!(
+        ValidityPredicateVerifyingKey::compress(&trivial_vk),
+        *COMPRESSED_TRIVIAL_VP_VK
+    );
+
+    // The cached vk is returned without building a new one
+    let same_vk = VerifierKeyCache::get_or_insert(*COMPRESSED_TRIVIAL_VP_VK, || unreachable!());
+    assert!(Arc::ptr_eq(&trivial_vk, &same_vk));
+
+    let unknown = pallas::Base::random(OsRng);
+    assert!(VerifierKeyCache::get(&unknown).is_none());
+}
@@ -7,7 +7,7 @@ use rustler::NifTuple;
 #[cfg(feature = "serde")]
 use serde;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "nif", derive(NifTuple))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValidityPredicateCommitment(Vec<u8>);
@@ -24,6 +24,16 @@ impl ValidityPredicateCommitment {
         Self(hash.as_bytes().to_vec())
     }
 
+    // Check the commitment opens to the vp with the randomness
+    pub fn open<F: PrimeField>(&self, vp: &F, rcm: &F) -> bool {
+        *self == Self::commit(vp, rcm)
+    }
+
+    // The default commitment is published when the dynamic vp is not used
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
     pub fn to_bytes(&self) -> [u8; 32] {
         self.0.clone().try_into().unwrap()
     }
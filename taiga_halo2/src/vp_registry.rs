@@ -0,0 +1,157 @@
+//! The registry of the known vps, indexed by the compressed vk.
+//!
+//! Notes only carry the compressed vk of their application vp. The registry maps it back to the
+//! full vk and the bytecode, so a node can tell which application a note belongs to and check the
+//! vp proofs are created under a known circuit.
+use crate::circuit::vp_bytecode::ValidityPredicateByteCode;
+use crate::vp_vk::ValidityPredicateVerifyingKey;
+use borsh::{BorshDeserialize, BorshSerialize};
+use halo2_proofs::plonk::VerifyingKey;
+use pasta_curves::{group::ff::PrimeField, pallas, vesta};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VkRegistryError {
+    /// Another vk with the same compressed vk is already registered.
+    Collision(pallas::Base),
+}
+
+impl fmt::Display for VkRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use VkRegistryError::*;
+        match self {
+            Collision(compressed) => f.write_str(&format!(
+                "A different vk is already registered under the compressed vk {compressed:?}"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize)]
+pub struct RegisteredVp {
+    // The serialized full vk
+    pub vk_bytes: Vec<u8>,
+    // The bytecode the vk is generated from
+    pub bytecode: ValidityPredicateByteCode,
+}
+
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize)]
+pub struct VkRegistry {
+    vps: BTreeMap<[u8; 32], RegisteredVp>,
+}
+
+impl VkRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the vp and returns its compressed vk.
+    /// The bytecodes of the same circuit share the vk, registering them again is a no-op and the
+    /// first bytecode is kept.
+    pub fn register(
+        &mut self,
+        bytecode: ValidityPredicateByteCode,
+    ) -> Result<pallas::Base, VkRegistryError> {
+        let vp_vk = bytecode.get_vp_vk();
+        let vk = vp_vk.vk().unwrap();
+        let compressed = vp_vk.get_compressed();
+        self.insert(compressed, vk_to_bytes(vk), bytecode)?;
+        Ok(compressed)
+    }
+
+    // Rejects a different vk under the same compressed vk
+    fn insert(
+        &mut self,
+        compressed: pallas::Base,
+        vk_bytes: Vec<u8>,
+        bytecode: ValidityPredicateByteCode,
+    ) -> Result<(), VkRegistryError> {
+        match self.vps.get(&compressed.to_repr()) {
+            Some(registered) if registered.vk_bytes != vk_bytes => {
+                Err(VkRegistryError::Collision(compressed))
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.vps
+                    .insert(compressed.to_repr(), RegisteredVp { vk_bytes, bytecode });
+                Ok(())
+            }
+        }
+    }
+
+    pub fn resolve(&self, compressed: &pallas::Base) -> Option<&RegisteredVp> {
+        self.vps.get(&compressed.to_repr())
+    }
+
+    /// Whether the vk is the registered vk of its compressed vk.
+    pub fn contains_vk(&self, vk: &VerifyingKey<vesta::Affine>) -> bool {
+        let compressed = ValidityPredicateVerifyingKey::compress(vk);
+        self.resolve(&compressed)
+            .map_or(false, |registered| registered.vk_bytes == vk_to_bytes(vk))
+    }
+
+    pub fn len(&self) -> usize {
+        self.vps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vps.is_empty()
+    }
+}
+
+fn vk_to_bytes(vk: &VerifyingKey<vesta::Affine>) -> Vec<u8> {
+    let mut bytes = vec![];
+    vk.write(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn test_vk_registry() {
+    use crate::circuit::vp_circuit::ValidityPredicateVerifyingInfo;
+    use crate::circuit::vp_examples::tests::random_trivial_vp_circuit;
+    use rand::rngs::OsRng;
+
+    let mut rng = OsRng;
+    let vp = random_trivial_vp_circuit(&mut rng);
+    let vk = vp.get_vp_vk().get_vk().unwrap();
+
+    // Register and resolve
+    let mut registry = VkRegistry::new();
+    let compressed = registry.register(vp.to_bytecode()).unwrap();
+    assert_eq!(compressed, vp.get_vp_vk().get_compressed());
+    let registered = borsh::to_vec(registry.resolve(&compressed).unwrap()).unwrap();
+    assert_eq!(
+        registry.resolve(&compressed).unwrap().vk_bytes,
+        vk_to_bytes(&vk)
+    );
+    assert!(registry.contains_vk(&vk));
+    assert!(registry
+        .resolve(&(compressed + pallas::Base::one()))
+        .is_none());
+
+    // Another instance of the same circuit has the same vk
+    let other_vp = random_trivial_vp_circuit(&mut rng);
+    assert_eq!(registry.register(other_vp.to_bytecode()), Ok(compressed));
+    assert_eq!(registry.len(), 1);
+
+    // A different vk under the same compressed vk is rejected
+    assert_eq!(
+        registry.insert(compressed, vec![0u8; 32], other_vp.to_bytecode()),
+        Err(VkRegistryError::Collision(compressed))
+    );
+    assert_eq!(
+        registry.resolve(&compressed).unwrap().vk_bytes,
+        vk_to_bytes(&vk)
+    );
+
+    // Persist and reload
+    let bytes = borsh::to_vec(&registry).unwrap();
+    let reloaded: VkRegistry = BorshDeserialize::deserialize(&mut bytes.as_ref()).unwrap();
+    assert_eq!(reloaded.len(), 1);
+    assert!(reloaded.contains_vk(&vk));
+    assert_eq!(
+        borsh::to_vec(reloaded.resolve(&compressed).unwrap()).unwrap(),
+        registered
+    );
+}
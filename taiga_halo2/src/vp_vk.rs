@@ -1,59 +1,179 @@
+#[cfg(feature = "borsh")]
+use crate::{
+    circuit::vp_bytecode::ValidityPredicateByteCode,
+    constant::{VP_ATTESTATION_PERSONALIZATION, VP_SOURCE_COMMITMENT_PERSONALIZATION},
+    error::TransactionError,
+};
 use blake2b_simd::Params as Blake2bParams;
+#[cfg(feature = "borsh")]
+use blake2s_simd::Params as Blake2sParams;
 use halo2_proofs::plonk::VerifyingKey;
 use pasta_curves::{
     group::ff::{FromUniformBytes, PrimeField},
     pallas, vesta,
 };
-use std::hash::Hash;
+#[cfg(test)]
+use std::{cell::RefCell, collections::HashMap};
+use std::{
+    hash::Hash,
+    sync::{Arc, OnceLock},
+};
 
+/// The vk of a vp, or only its compression. The vk is shared behind an `Arc` so clones are cheap,
+/// and the compression is memoized once for the vk and all its clones.
 #[derive(Debug, Clone)]
-pub enum ValidityPredicateVerifyingKey {
-    // VK.
-    Uncompressed(VerifyingKey<vesta::Affine>),
-    // Compress vk into one element.
-    Compressed(pallas::Base),
+pub struct ValidityPredicateVerifyingKey {
+    // None when only the compressed vk is known.
+    vk: Option<Arc<VerifyingKey<vesta::Affine>>>,
+    // Compress vk into one element, on first use.
+    compressed: Arc<OnceLock<pallas::Base>>,
 }
 
 impl ValidityPredicateVerifyingKey {
     pub fn from_vk(vk: VerifyingKey<vesta::Affine>) -> Self {
-        Self::Uncompressed(vk)
+        Self::from_arc(Arc::new(vk))
+    }
+
+    pub fn from_arc(vk: Arc<VerifyingKey<vesta::Affine>>) -> Self {
+        Self {
+            vk: Some(vk),
+            compressed: Arc::new(OnceLock::new()),
+        }
     }
 
     pub fn from_compressed(vk: pallas::Base) -> Self {
-        Self::Compressed(vk)
+        Self {
+            vk: None,
+            compressed: Arc::new(OnceLock::from(vk)),
+        }
     }
 
+    /// Clones the vk out, for the callers that need it owned(e.g. keygen_pk).
     pub fn get_vk(&self) -> Option<VerifyingKey<vesta::Affine>> {
-        match self {
-            ValidityPredicateVerifyingKey::Uncompressed(vk) => Some(vk.clone()),
-            ValidityPredicateVerifyingKey::Compressed(_) => None,
-        }
+        self.vk.as_deref().cloned()
+    }
+
+    /// The shared vk, without cloning it.
+    pub fn vk(&self) -> Option<&Arc<VerifyingKey<vesta::Affine>>> {
+        self.vk.as_ref()
     }
 
     pub fn get_compressed(&self) -> pallas::Base {
-        match self {
-            ValidityPredicateVerifyingKey::Uncompressed(vk) => {
-                let mut hasher = Blake2bParams::new()
-                    .hash_length(64)
-                    .personal(b"Halo2-Verify-Key")
-                    .to_state();
-
-                let s = format!("{:?}", vk.pinned());
-
-                hasher.update(&(s.len() as u64).to_le_bytes());
-                hasher.update(s.as_bytes());
-
-                // Hash in final Blake2bState
-                pallas::Base::from_uniform_bytes(hasher.finalize().as_array())
-            }
-            ValidityPredicateVerifyingKey::Compressed(v) => *v,
+        *self.compressed.get_or_init(|| {
+            let vk = self
+                .vk
+                .as_deref()
+                .expect("the compressed vk is set when there is no vk");
+            Self::compress(vk)
+        })
+    }
+
+    /// Hashes the pinned vk into one element. It's not memoized, prefer get_compressed when the vk
+    /// is wrapped already.
+    pub fn compress(vk: &VerifyingKey<vesta::Affine>) -> pallas::Base {
+        let mut hasher = Blake2bParams::new()
+            .hash_length(64)
+            .personal(b"Halo2-Verify-Key")
+            .to_state();
+
+        let s = format!("{:?}", vk.pinned());
+
+        hasher.update(&(s.len() as u64).to_le_bytes());
+        hasher.update(s.as_bytes());
+
+        // Hash in final Blake2bState
+        let compressed = pallas::Base::from_uniform_bytes(hasher.finalize().as_array());
+        #[cfg(test)]
+        COMPRESSIONS.with(|c| *c.borrow_mut().entry(compressed.to_repr()).or_default() += 1);
+        compressed
+    }
+}
+
+// Counts the compressions of each vk in the current thread, see test_vp_vk_compressed_once.
+#[cfg(test)]
+thread_local! {
+    static COMPRESSIONS: RefCell<HashMap<[u8; 32], usize>> = RefCell::new(HashMap::new());
+}
+
+#[cfg(feature = "borsh")]
+impl ValidityPredicateVerifyingKey {
+    /// Re-derives the vk from the bytecode, and links the source commitment of the bytecode to the
+    /// compressed vk if they match.
+    pub fn attest(
+        &self,
+        bytecode: &ValidityPredicateByteCode,
+    ) -> Result<Attestation, TransactionError> {
+        if bytecode.get_vp_vk() != *self {
+            return Err(TransactionError::InconsistentBytecodeVK);
         }
+        Ok(Attestation::new(
+            commit_source(bytecode),
+            self.get_compressed(),
+        ))
+    }
+}
+
+/// The commitment to the source of a vp, see commit_source.
+#[cfg(feature = "borsh")]
+pub type SourceCommitment = [u8; 32];
+
+/// Blake2s over the borsh serialization of the bytecode. The circuit inputs are included, the
+/// params of a vp(e.g. the trivial vp params) are part of them and change the vk.
+#[cfg(feature = "borsh")]
+pub fn commit_source(bytecode: &ValidityPredicateByteCode) -> SourceCommitment {
+    let bytes = borsh::to_vec(bytecode).expect("the bytecode serialization should not fail");
+    let hash = Blake2sParams::new()
+        .hash_length(32)
+        .personal(VP_SOURCE_COMMITMENT_PERSONALIZATION)
+        .to_state()
+        .update(&bytes)
+        .finalize();
+    hash.as_bytes().try_into().unwrap()
+}
+
+/// A hash-linked record of a compressed vk and the source commitment of the bytecode it was
+/// derived from. Wallets check the link with verify and compare the vk with the app_vk of notes.
+#[cfg(feature = "borsh")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attestation {
+    pub source_commitment: SourceCommitment,
+    pub vk: pallas::Base,
+    digest: [u8; 32],
+}
+
+#[cfg(feature = "borsh")]
+impl Attestation {
+    fn new(source_commitment: SourceCommitment, vk: pallas::Base) -> Self {
+        Self {
+            source_commitment,
+            vk,
+            digest: Self::link(&source_commitment, &vk),
+        }
+    }
+
+    fn link(source_commitment: &SourceCommitment, vk: &pallas::Base) -> [u8; 32] {
+        let hash = Blake2sParams::new()
+            .hash_length(32)
+            .personal(VP_ATTESTATION_PERSONALIZATION)
+            .to_state()
+            .update(source_commitment)
+            .update(vk.to_repr().as_ref())
+            .finalize();
+        hash.as_bytes().try_into().unwrap()
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        self.digest
+    }
+
+    pub fn verify(&self) -> bool {
+        self.digest == Self::link(&self.source_commitment, &self.vk)
     }
 }
 
 impl Default for ValidityPredicateVerifyingKey {
     fn default() -> ValidityPredicateVerifyingKey {
-        ValidityPredicateVerifyingKey::Compressed(pallas::Base::one())
+        ValidityPredicateVerifyingKey::from_compressed(pallas::Base::one())
     }
 }
 
@@ -121,3 +241,94 @@ fn test_vpd_hashing() {
     assert!(!set.insert(vpd2));
     assert!(set.insert(vpd3));
 }
+
+#[cfg(feature = "borsh")]
+#[test]
+fn test_vp_source_attestation() {
+    use crate::circuit::vp_bytecode::ApplicationByteCode;
+    use crate::circuit::vp_examples::{
+        tests::random_trivial_vp_circuit, TrivialValidityPredicateParams, COMPRESSED_TRIVIAL_VP_VK,
+        TRIVIAL_VP_VK,
+    };
+    use rand::rngs::OsRng;
+
+    let bytecode = random_trivial_vp_circuit(OsRng).to_bytecode();
+    let attestation = TRIVIAL_VP_VK.attest(&bytecode).unwrap();
+    assert!(attestation.verify());
+    assert_eq!(attestation.vk, *COMPRESSED_TRIVIAL_VP_VK);
+    assert_eq!(attestation.source_commitment, commit_source(&bytecode));
+    assert_eq!(
+        ApplicationByteCode::new(bytecode, vec![]).source_commitment(),
+        attestation.source_commitment
+    );
+
+    // A record with another source doesn't verify
+    let mut forged = attestation.clone();
+    forged.source_commitment[0] ^= 1;
+    assert!(!forged.verify());
+
+    // A swapped bytecode derives another vk
+    let swapped = random_trivial_vp_circuit(OsRng)
+        .with_params(TrivialValidityPredicateParams {
+            fail: true,
+            ..Default::default()
+        })
+        .to_bytecode();
+    assert!(matches!(
+        TRIVIAL_VP_VK.attest(&swapped),
+        Err(TransactionError::InconsistentBytecodeVK)
+    ));
+}
+
+#[cfg(feature = "examples")]
+#[test]
+fn test_vp_vk_compressed_once() {
+    use crate::circuit::vp_circuit::ValidityPredicateVerifyingInfo;
+    use crate::circuit::vp_examples::{
+        partial_fulfillment_intent::{
+            swap::Swap, PartialFulfillmentIntentValidityPredicateCircuit,
+        },
+        token::{Token, TokenAuthorization, TokenName, TOKEN_VK},
+    };
+    use rand::rngs::OsRng;
+    use std::collections::HashSet;
+
+    let mut rng = OsRng;
+    COMPRESSIONS.with(|c| c.borrow_mut().clear());
+
+    // Build and fill a swap, the token vk is compressed in the intent note and the fill
+    let swap = Swap::random(
+        &mut rng,
+        Token::new(TokenName::new("btc").unwrap(), 2u64),
+        Token::new(TokenName::new("xan").unwrap(), 8000u64),
+        TokenAuthorization::random(&mut rng),
+    );
+    let intent_note = swap.create_intent_note(&mut rng);
+    let (input_notes, output_notes) = swap.fill(
+        &mut rng,
+        intent_note,
+        Token::new(TokenName::new("xan").unwrap(), 8000u64),
+    );
+    let intent_vk = PartialFulfillmentIntentValidityPredicateCircuit::new(
+        intent_note.get_nf().unwrap().inner(),
+        input_notes,
+        output_notes,
+        swap,
+    )
+    .unwrap()
+    .get_vp_vk();
+
+    // The clones share the compression
+    let vks: Vec<_> = (0..4)
+        .flat_map(|_| [intent_vk.clone(), TOKEN_VK.clone()])
+        .collect();
+    let set: HashSet<_> = vks.iter().cloned().collect();
+    assert_eq!(set.len(), 2);
+    assert!(vks.iter().all(|vk| vk.vk().is_some()));
+    let intent_compressed = intent_vk.get_compressed().to_repr();
+
+    // The token vk may have been compressed in another test thread already
+    let compressions = COMPRESSIONS.with(|c| c.borrow().clone());
+    assert!(compressions.values().all(|n| *n == 1));
+    assert_eq!(compressions.get(&intent_compressed), Some(&1));
+}
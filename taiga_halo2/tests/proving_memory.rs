@@ -0,0 +1,109 @@
+// Peak heap usage of building a transaction. It's slow, run it with
+// `cargo test --release --test proving_memory -- --ignored`
+use pasta_curves::pallas;
+use rand::rngs::OsRng;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use taiga_halo2::{
+    constant::{NUM_NOTE, TAIGA_COMMITMENT_TREE_DEPTH},
+    merkle_tree::{Anchor, MerklePath},
+    note::{InputNoteProvingInfo, Note, OutputNoteProvingInfo},
+    shielded_ptx::ShieldedPartialTransaction,
+    transaction::{ShieldedPartialTxBundle, Transaction, TransparentPartialTxBundle},
+};
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let live = LIVE_BYTES.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK_BYTES.fetch_max(live, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// The peak of live bytes above the live bytes before f
+fn peak_bytes_of<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let base = LIVE_BYTES.load(Ordering::SeqCst);
+    PEAK_BYTES.store(base, Ordering::SeqCst);
+    let output = f();
+    (output, PEAK_BYTES.load(Ordering::SeqCst) - base)
+}
+
+type ProvingInfos = (
+    [InputNoteProvingInfo; NUM_NOTE],
+    [OutputNoteProvingInfo; NUM_NOTE],
+);
+
+fn padding_proving_infos() -> ProvingInfos {
+    use halo2_proofs::arithmetic::Field;
+
+    let input_notes = [(); NUM_NOTE].map(|_| Note::random_padding_input_note(OsRng));
+    let output_notes =
+        input_notes.map(|note| Note::random_padding_output_note(OsRng, note.get_nf().unwrap()));
+    let merkle_path = MerklePath::random(&mut OsRng, TAIGA_COMMITMENT_TREE_DEPTH);
+    let anchor = Anchor::from(pallas::Base::random(OsRng));
+    let inputs = input_notes.map(|note| {
+        InputNoteProvingInfo::create_padding_note_proving_info(
+            note,
+            merkle_path.clone(),
+            anchor,
+            input_notes,
+            output_notes,
+        )
+    });
+    let outputs = output_notes.map(|note| {
+        OutputNoteProvingInfo::create_padding_note_proving_info(note, input_notes, output_notes)
+    });
+    (inputs, outputs)
+}
+
+fn build_tx(infos: Vec<ProvingInfos>, hold_vps: bool) -> Transaction {
+    let ptxs = infos
+        .into_iter()
+        .map(|(inputs, outputs)| {
+            // Keep a copy of the vp circuits alive during the proving, like the build did before
+            // it consumed the proving infos
+            let held = hold_vps.then(|| (inputs.clone(), outputs.clone()));
+            let ptx = ShieldedPartialTransaction::build(inputs, outputs, vec![], OsRng).unwrap();
+            drop(held);
+            ptx
+        })
+        .collect();
+    Transaction::build(
+        OsRng,
+        ShieldedPartialTxBundle::new(ptxs),
+        TransparentPartialTxBundle::default(),
+    )
+    .unwrap()
+}
+
+#[test]
+#[ignore]
+fn test_build_peak_memory() {
+    // Load the params and the action keys before measuring
+    build_tx(vec![padding_proving_infos()], false);
+
+    let infos: Vec<_> = (0..3).map(|_| padding_proving_infos()).collect();
+    let infos_copy = infos.clone();
+    let (_, held_peak) = peak_bytes_of(|| build_tx(infos_copy, true));
+    let (tx, peak) = peak_bytes_of(|| build_tx(infos, false));
+    println!("peak live bytes: {peak}, with the vps held: {held_peak}");
+    assert!(peak < held_peak);
+
+    tx.execute().unwrap();
+}
@@ -1,3 +1,5 @@
+//! The action of the ark-plonk prototype. It is not compatible with the halo2 implementation,
+//! the canonical `Action` API is `taiga_halo2::action`.
 use crate::app::App;
 use crate::circuit::action_circuit::ActionCircuit;
 use crate::circuit::circuit_parameters::CircuitParameters;